@@ -0,0 +1,37 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// GLSL sources in `shaders/` compiled into the binary, with the file name of their SPIR-V.
+/// Keep in sync with `components::embedded_shaders`. They are always compiled from the sources,
+/// building needs glslc (or `$GLSLC`) so the checked in SPIR-V can't be embedded stale.
+const EMBEDDED_SHADERS: [(&str, &str); 7] = [
+    ("2D_vertex_shader.vert", "2D_vertex_shader.spv"),
+    ("2D_fragment_shader.frag", "2D_fragment_shader.spv"),
+    ("2D_texture_fragment_shader.frag", "2D_texture_fragment_shader.spv"),
+    ("debug_line.vert", "debug_line.vert.spv"),
+    ("debug_line.frag", "debug_line.frag.spv"),
+    ("scene_data_mesh.vert", "scene_data_mesh.vert.spv"),
+    ("error_material.frag", "error_material.frag.spv"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=shaders");
+    println!("cargo:rerun-if-env-changed=GLSLC");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let glslc = env::var("GLSLC").unwrap_or_else(|_| "glslc".to_string());
+    let shader_dir = Path::new("shaders");
+
+    for (source, spirv) in EMBEDDED_SHADERS {
+        let output = out_dir.join(spirv);
+        let status = Command::new(&glslc)
+            .arg(shader_dir.join(source))
+            .arg("-o")
+            .arg(&output)
+            .status()
+            .unwrap_or_else(|err| panic!("Running {glslc} to compile {source} failed: {err}"));
+        assert!(status.success(), "{glslc} could not compile {source}: {status}");
+    }
+}
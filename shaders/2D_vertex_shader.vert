@@ -1,19 +1,36 @@
 #version 450
 
-layout(location = 0) in vec2 a_inPos;
-layout(location = 1) in vec2 a_texCoords;
-layout(location = 2) in vec4 a_inColor;
+#extension GL_EXT_buffer_reference : require
 
 layout(location = 0) out vec4 v_outColor;
 layout(location = 1) out vec2 v_texCoords;
 
+// egui vertices are 20 bytes, position and uv followed by an sRGB RGBA8 color
+const uint VERTEX_FLOATS = 5;
+
+layout(buffer_reference, std430) readonly buffer VertexBuffer {
+	float data[];
+};
+
 layout(push_constant) uniform PushConstant {
 	mat4 screenToClip;
+	VertexBuffer vertexBuffer;
 } pc;
 
+vec3 srgbToLinear(vec3 srgb) {
+	vec3 low = srgb / 12.92;
+	vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+	return mix(high, low, lessThanEqual(srgb, vec3(0.04045)));
+}
+
 void main() {
-	gl_Position = pc.screenToClip * vec4(a_inPos, 0.0, 1.0);
-	
-	v_texCoords = a_texCoords;
-	v_outColor = a_inColor;
+	uint base = gl_VertexIndex * VERTEX_FLOATS;
+	vec2 position = vec2(pc.vertexBuffer.data[base], pc.vertexBuffer.data[base + 1]);
+	vec2 texCoords = vec2(pc.vertexBuffer.data[base + 2], pc.vertexBuffer.data[base + 3]);
+	vec4 color = unpackUnorm4x8(floatBitsToUint(pc.vertexBuffer.data[base + 4]));
+
+	gl_Position = pc.screenToClip * vec4(position, 0.0, 1.0);
+
+	v_texCoords = texCoords;
+	v_outColor = vec4(srgbToLinear(color.rgb), color.a);
 }
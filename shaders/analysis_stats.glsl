@@ -0,0 +1,16 @@
+// Accumulators of the analysis passes, mirrors AnalysisStats in src/misc/analysis.rs.
+#define HISTOGRAM_BINS 64
+#define HISTOGRAM_MIN_EV -10.0f
+#define HISTOGRAM_MAX_EV 2.0f
+
+layout(rgba16f, set = 0, binding = 0) uniform image2D drawImage;
+layout(r16f, set = 0, binding = 1) uniform image2D overdrawImage;
+
+layout(std430, set = 0, binding = 2) buffer AnalysisStats {
+	uint histogram[HISTOGRAM_BINS];
+	uint coveredPixels;
+	uint shadedFragments;
+	uint maxOverdraw;
+	uint quadLanesUsed;
+	uint quadLanesLaunched;
+} stats;
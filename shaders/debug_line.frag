@@ -0,0 +1,9 @@
+#version 450
+
+layout(location = 0) in vec4 v_outColor;
+
+layout(location = 0) out vec4 f_outColor;
+
+void main() {
+	f_outColor = v_outColor;
+}
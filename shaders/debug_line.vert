@@ -0,0 +1,25 @@
+#version 450
+
+#extension GL_EXT_buffer_reference : require
+
+layout(location = 0) out vec4 v_outColor;
+
+struct DebugVertex {
+	vec3 position;
+	vec4 color;
+};
+
+layout(buffer_reference, std430) readonly buffer VertexBuffer {
+	DebugVertex vertices[];
+};
+
+layout(push_constant) uniform PushConstant {
+	mat4 viewproj;
+	VertexBuffer vertexBuffer;
+} pc;
+
+void main() {
+	DebugVertex v = pc.vertexBuffer.vertices[gl_VertexIndex];
+	gl_Position = pc.viewproj * vec4(v.position, 1.0);
+	v_outColor = v.color;
+}
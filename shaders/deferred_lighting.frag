@@ -0,0 +1,37 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
+#include "scene_data_input.glsl"
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+// written by gbuffer.frag
+layout (set = 2, binding = 0) uniform sampler2D albedoImage;
+layout (set = 2, binding = 1) uniform sampler2D normalImage;
+layout (set = 2, binding = 2) uniform sampler2D materialImage;
+layout (set = 2, binding = 3) uniform sampler2D emissiveImage;
+
+void main() 
+{
+	ivec2 texel = ivec2(gl_FragCoord.xy);
+	vec4 albedo = texelFetch(albedoImage, texel, 0);
+	// no deferred surface here, keeps the skybox or clear color
+	if (albedo.a == 0.0f) {
+		discard;
+	}
+	vec3 normal = normalize(texelFetch(normalImage, texel, 0).xyz);
+
+	float lightValue = max(dot(normal, sceneData.sunlightDirection.xyz), 0.1f);
+
+	float occlusion = texelFetch(materialImage, texel, 0).b;
+	if (sceneData.ambientOcclusion.y != 0) {
+		occlusion *= texelFetch(textures[sceneData.ambientOcclusion.x], texel, 0).r;
+	}
+	vec3 ambient = albedo.rgb * sceneData.ambientColor.xyz * occlusion;
+	vec3 emissive = texelFetch(emissiveImage, texel, 0).xyz;
+
+	outFragColor = vec4(albedo.rgb * lightValue * sceneData.sunlightColor.w + ambient + emissive, 1.0f);
+}
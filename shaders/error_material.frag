@@ -0,0 +1,21 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
+#include "scene_data_input.glsl"
+
+layout (location = 0) in vec3 inNormal;
+layout (location = 1) in vec3 inColor;
+layout (location = 2) in vec2 inUV;
+layout (location = 3) in vec4 inTangent;
+layout (location = 4) flat in uint inMaterialIndex;
+
+layout (location = 0) out vec4 outFragColor;
+
+// unlit magenta and black checkerboard bound as the color texture, used when a material could
+// not be built
+void main() 
+{
+	uint colorTex = materialBuffer.materials[inMaterialIndex].colorTex;
+	outFragColor = vec4(materialTexture(colorTex, inUV * 4.0f).rgb, 1.0f);
+}
@@ -0,0 +1,36 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
+#include "scene_data_input.glsl"
+
+layout (location = 0) in vec3 inNormal;
+layout (location = 1) in vec3 inColor;
+layout (location = 2) in vec2 inUV;
+layout (location = 3) in vec4 inTangent;
+layout (location = 4) flat in uint inMaterialIndex;
+
+// must match GBUFFER_FORMATS in deferred.rs
+layout (location = 0) out vec4 outAlbedo;
+layout (location = 1) out vec4 outNormal;
+layout (location = 2) out vec4 outMaterial; // metallic, roughness, occlusion
+layout (location = 3) out vec4 outEmissive;
+
+// the inputs of the lighting in scene_data_mesh.frag, shaded by deferred_lighting.frag
+void main() 
+{
+	MaterialData material = materialBuffer.materials[inMaterialIndex];
+	vec3 N = normalize(inNormal);
+	vec3 T = normalize(inTangent.xyz - N * dot(N, inTangent.xyz));
+	vec3 B = cross(N, T) * inTangent.w;
+	vec3 normal = normalize(mat3(T, B, N) * (materialTexture(material.normalTex, inUV).xyz * 2.0f - 1.0f));
+
+	vec4 metalRough = materialTexture(material.metalRoughTex, inUV);
+	float occlusion = 1.0f + material.emissiveFactors.w * (materialTexture(material.occlusionTex, inUV).r - 1.0f);
+
+	// alpha marks the pixel as covered for the lighting pass
+	outAlbedo = vec4(inColor * materialTexture(material.colorTex, inUV).xyz, 1.0f);
+	outNormal = vec4(normal, 0.0f);
+	outMaterial = vec4(material.metal_rough_factors.x * metalRough.b, material.metal_rough_factors.y * metalRough.g, occlusion, 0.0f);
+	outEmissive = vec4(materialTexture(material.emissiveTex, inUV).xyz * material.emissiveFactors.xyz, 0.0f);
+}
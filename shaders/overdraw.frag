@@ -0,0 +1,9 @@
+#version 450
+
+// additively blended, so every shaded fragment bumps the pixel by one
+layout (location = 0) out float outCount;
+
+void main() 
+{
+	outCount = 1.0f;
+}
@@ -0,0 +1,31 @@
+#version 450
+
+#extension GL_EXT_buffer_reference : require
+
+struct Vertex {
+
+	vec3 position;
+	vec2 uv;
+	vec3 normal;
+	vec4 color;
+	vec4 tangent;
+	uvec4 joints;
+	vec4 weights;
+}; 
+
+layout(buffer_reference, std430) readonly buffer VertexBuffer{ 
+	Vertex vertices[];
+};
+
+// render_matrix is view_proj * model for the overdraw pass
+layout( push_constant ) uniform constants
+{
+	mat4 render_matrix;
+	VertexBuffer vertexBuffer;
+} PushConstants;
+
+void main() 
+{
+	Vertex v = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
+	gl_Position = PushConstants.render_matrix * vec4(v.position, 1.0f);
+}
@@ -6,15 +6,28 @@ layout(set = 0, binding = 0) uniform  SceneData{
 	vec4 ambientColor;
 	vec4 sunlightDirection; //w for sun power
 	vec4 sunlightColor;
+	uvec4 ambientOcclusion; //x bindless slot of the SSAO result, y 1 when SSAO is enabled
 } sceneData;
 
-layout(set = 1, binding = 0) uniform GLTFMaterialData{   
+// must match BindlessMaterial in material.rs
+struct MaterialData {
 
 	vec4 colorFactors;
 	vec4 metal_rough_factors;
-	
-} materialData;
+	vec4 emissiveFactors; //w for occlusion strength
+	vec4 extra;
+	uint colorTex;
+	uint metalRoughTex;
+	uint normalTex;
+	uint emissiveTex;
+	uint occlusionTex;
+};
 
-layout(set = 1, binding = 1) uniform sampler2D colorTex;
-layout(set = 1, binding = 2) uniform sampler2D metalRoughTex;
+layout(set = 1, binding = 0) readonly buffer MaterialBuffer{
 
+	MaterialData materials[];
+} materialBuffer;
+
+layout(set = 1, binding = 1) uniform sampler2D textures[];
+
+#define materialTexture(index, uv) texture(textures[nonuniformEXT(index)], uv)
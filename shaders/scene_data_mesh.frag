@@ -1,21 +1,35 @@
 #version 450
 
 #extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
 #include "scene_data_input.glsl"
 
 layout (location = 0) in vec3 inNormal;
 layout (location = 1) in vec3 inColor;
 layout (location = 2) in vec2 inUV;
+layout (location = 3) in vec4 inTangent;
+layout (location = 4) flat in uint inMaterialIndex;
 
 layout (location = 0) out vec4 outFragColor;
 
 void main() 
 {
-	float lightValue = max(dot(inNormal, sceneData.sunlightDirection.xyz), 0.1f);
+	MaterialData material = materialBuffer.materials[inMaterialIndex];
+	vec3 N = normalize(inNormal);
+	vec3 T = normalize(inTangent.xyz - N * dot(N, inTangent.xyz));
+	vec3 B = cross(N, T) * inTangent.w;
+	vec3 normal = normalize(mat3(T, B, N) * (materialTexture(material.normalTex, inUV).xyz * 2.0f - 1.0f));
 
-	vec3 color = inColor * texture(colorTex,inUV).xyz;
-	vec3 ambient = color *  sceneData.ambientColor.xyz;
+	float lightValue = max(dot(normal, sceneData.sunlightDirection.xyz), 0.1f);
 
-	outFragColor = vec4(color * lightValue *  sceneData.sunlightColor.w + ambient ,1.0f);
+	vec3 color = inColor * materialTexture(material.colorTex, inUV).xyz;
+	float occlusion = 1.0f + material.emissiveFactors.w * (materialTexture(material.occlusionTex, inUV).r - 1.0f);
+	if (sceneData.ambientOcclusion.y != 0) {
+		occlusion *= texelFetch(textures[sceneData.ambientOcclusion.x], ivec2(gl_FragCoord.xy), 0).r;
+	}
+	vec3 ambient = color *  sceneData.ambientColor.xyz * occlusion;
+	vec3 emissive = materialTexture(material.emissiveTex, inUV).xyz * material.emissiveFactors.xyz;
+
+	outFragColor = vec4(color * lightValue *  sceneData.sunlightColor.w + ambient + emissive ,1.0f);
 }
 
@@ -1,6 +1,7 @@
 #version 450
 
 #extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
 #extension GL_EXT_buffer_reference : require
 
 #include "scene_data_input.glsl"
@@ -8,6 +9,8 @@
 layout (location = 0) out vec3 outNormal;
 layout (location = 1) out vec3 outColor;
 layout (location = 2) out vec2 outUV;
+layout (location = 3) out vec4 outTangent;
+layout (location = 4) flat out uint outMaterialIndex;
 
 struct Vertex {
 
@@ -15,6 +18,9 @@ struct Vertex {
 	vec2 uv;
 	vec3 normal;
 	vec4 color;
+	vec4 tangent;
+	uvec4 joints;
+	vec4 weights;
 }; 
 
 layout(buffer_reference, std430) readonly buffer VertexBuffer{ 
@@ -26,6 +32,7 @@ layout( push_constant ) uniform constants
 {
 	mat4 render_matrix;
 	VertexBuffer vertexBuffer;
+	uint materialIndex;
 } PushConstants;
 
 void main() 
@@ -37,7 +44,9 @@ void main()
 	gl_Position =  sceneData.viewproj * PushConstants.render_matrix *position;
 
 	outNormal = (PushConstants.render_matrix * vec4(v.normal, 0.f)).xyz;
-	outColor = v.color.xyz * materialData.colorFactors.xyz;	
+	outColor = v.color.xyz * materialBuffer.materials[PushConstants.materialIndex].colorFactors.xyz;
 	outUV = v.uv;
+	outTangent = vec4((PushConstants.render_matrix * vec4(v.tangent.xyz, 0.f)).xyz, v.tangent.w);
+	outMaterialIndex = PushConstants.materialIndex;
 }
 
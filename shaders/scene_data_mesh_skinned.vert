@@ -0,0 +1,62 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+
+#include "scene_data_input.glsl"
+
+layout (location = 0) out vec3 outNormal;
+layout (location = 1) out vec3 outColor;
+layout (location = 2) out vec2 outUV;
+layout (location = 3) out vec4 outTangent;
+layout (location = 4) flat out uint outMaterialIndex;
+
+struct Vertex {
+
+	vec3 position;
+	vec2 uv;
+	vec3 normal;
+	vec4 color;
+	vec4 tangent;
+	uvec4 joints;
+	vec4 weights;
+}; 
+
+layout(buffer_reference, std430) readonly buffer VertexBuffer{ 
+	Vertex vertices[];
+};
+
+layout(buffer_reference, std430) readonly buffer JointBuffer{ 
+	mat4 joints[];
+};
+
+//push constants block, scene_data_mesh.vert with the joint matrices of the mesh
+layout( push_constant ) uniform constants
+{
+	mat4 render_matrix;
+	VertexBuffer vertexBuffer;
+	uint materialIndex;
+	JointBuffer jointBuffer;
+} PushConstants;
+
+void main() 
+{
+	Vertex v = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
+
+	mat4 skin = v.weights.x * PushConstants.jointBuffer.joints[v.joints.x]
+		+ v.weights.y * PushConstants.jointBuffer.joints[v.joints.y]
+		+ v.weights.z * PushConstants.jointBuffer.joints[v.joints.z]
+		+ v.weights.w * PushConstants.jointBuffer.joints[v.joints.w];
+	mat4 model = PushConstants.render_matrix * skin;
+
+	vec4 position = vec4(v.position, 1.0f);
+
+	gl_Position =  sceneData.viewproj * model * position;
+
+	outNormal = (model * vec4(v.normal, 0.f)).xyz;
+	outColor = v.color.xyz * materialBuffer.materials[PushConstants.materialIndex].colorFactors.xyz;
+	outUV = v.uv;
+	outTangent = vec4((model * vec4(v.tangent.xyz, 0.f)).xyz, v.tangent.w);
+	outMaterialIndex = PushConstants.materialIndex;
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) in vec3 v_direction;
+
+layout(set = 0, binding = 0) uniform samplerCube environment_map;
+
+layout(location = 0) out vec4 f_outColor;
+
+void main() {
+	f_outColor = vec4(texture(environment_map, v_direction).rgb, 1.0);
+}
@@ -0,0 +1,35 @@
+#version 450
+
+layout(location = 0) out vec3 v_direction;
+
+layout(push_constant) uniform PushConstant {
+	mat4 viewproj; // view without translation
+} pc;
+
+const vec3 positions[8] = vec3[8](
+	vec3(-1.0, -1.0, -1.0),
+	vec3( 1.0, -1.0, -1.0),
+	vec3( 1.0,  1.0, -1.0),
+	vec3(-1.0,  1.0, -1.0),
+	vec3(-1.0, -1.0,  1.0),
+	vec3( 1.0, -1.0,  1.0),
+	vec3( 1.0,  1.0,  1.0),
+	vec3(-1.0,  1.0,  1.0)
+);
+
+const int indices[36] = int[36](
+	0, 1, 2, 2, 3, 0, // -Z
+	4, 6, 5, 6, 4, 7, // +Z
+	0, 3, 7, 7, 4, 0, // -X
+	1, 5, 6, 6, 2, 1, // +X
+	3, 2, 6, 6, 7, 3, // +Y
+	0, 4, 5, 5, 1, 0  // -Y
+);
+
+void main() {
+	vec3 position = positions[indices[gl_VertexIndex]];
+	v_direction = position;
+	vec4 clip = pc.viewproj * vec4(position, 1.0);
+	// keep the cube on the far plane
+	gl_Position = clip.xyww;
+}
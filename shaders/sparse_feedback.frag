@@ -0,0 +1,15 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+
+#include "sparse_feedback.glsl"
+
+// only the fragments that end up visible flag their page
+layout(early_fragment_tests) in;
+
+layout (location = 2) in vec2 inUV;
+
+void main()
+{
+	requestSparsePage(inUV);
+}
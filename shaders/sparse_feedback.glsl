@@ -0,0 +1,12 @@
+// Feedback for sparse resident textures, every sampled page is flagged in row-major order and
+// read back by SparseImage::requested_pages to bind the missing pages.
+layout(set = 2, binding = 0) buffer SparseFeedback {
+	uvec2 pageCount;
+	uint requested[];
+} sparseFeedback;
+
+void requestSparsePage(vec2 uv)
+{
+	uvec2 page = min(uvec2(clamp(uv, 0.0f, 1.0f) * vec2(sparseFeedback.pageCount)), sparseFeedback.pageCount - 1);
+	sparseFeedback.requested[page.y * sparseFeedback.pageCount.x + page.x] = 1;
+}
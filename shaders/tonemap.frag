@@ -0,0 +1,41 @@
+#version 460
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+layout (set = 0, binding = 0) uniform sampler2D hdrImage;
+
+// must match TonemapPushConstant
+layout (push_constant) uniform constants
+{
+	float exposure;
+	uint mode;
+} PushConstants;
+
+const uint MODE_NONE = 0;
+const uint MODE_REINHARD = 1;
+const uint MODE_ACES = 2;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x)
+{
+	const float a = 2.51f;
+	const float b = 0.03f;
+	const float c = 2.43f;
+	const float d = 0.59f;
+	const float e = 0.14f;
+	return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0f, 1.0f);
+}
+
+void main()
+{
+	vec4 hdr = texture(hdrImage, inUV);
+	vec3 color = max(hdr.rgb, vec3(0.0f)) * PushConstants.exposure;
+	if (PushConstants.mode == MODE_REINHARD) {
+		color = color / (1.0f + color);
+	} else if (PushConstants.mode == MODE_ACES) {
+		color = aces(color);
+	}
+	outFragColor = vec4(color, hdr.a);
+}
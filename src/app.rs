@@ -1,24 +1,235 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use log::error;
 use muda::dpi::LogicalSize;
 use winit::{
     application::ApplicationHandler,
-    window::{Window, WindowAttributes},
+    event::{ElementState, KeyEvent, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow},
+    keyboard::{ModifiersState, PhysicalKey},
+    monitor::VideoModeHandle,
+    window::{Window, WindowAttributes, WindowId},
+};
+
+use crate::{
+    misc::display_mode::{video_modes, DisplayMode, Hotkey},
+    renderer::{is_zero_sized, Renderer},
 };
 
-use crate::renderer::Renderer;
+/// How the event loop drives rendering.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Requests a redraw as soon as the last frame was rendered.
+    #[default]
+    Continuous,
+    /// Only renders after input, pending UI animations or `Renderer::invalidate`, the event
+    /// loop sleeps otherwise.
+    Reactive,
+}
+
+/// Steps the simulation at a fixed rate however fast frames are drawn, frames in between
+/// interpolate the last two steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedTimestep {
+    step: Duration,
+    /// Time passed that no step simulated yet, less than a step after `advance`.
+    accumulator: Duration,
+    last_advance: Option<Instant>,
+}
+
+impl FixedTimestep {
+    /// Steps run by a single frame at most. After a stall, e.g. a window drag or a sleeping
+    /// event loop, the simulation skips the rest instead of catching up.
+    pub const MAX_STEPS: u32 = 8;
+
+    pub fn new(step: Duration) -> Self {
+        Self {
+            step: step.max(Duration::from_micros(100)),
+            accumulator: Duration::ZERO,
+            last_advance: None,
+        }
+    }
+
+    pub fn from_hz(hz: f64) -> Self {
+        Self::new(Duration::from_secs_f64(1.0 / hz))
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds the time since the last call and returns how many steps to simulate for it.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        if let Some(last_advance) = self.last_advance.replace(now) {
+            self.accumulator += now.saturating_duration_since(last_advance);
+        }
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+            if steps == Self::MAX_STEPS {
+                self.accumulator = self.accumulator.min(self.step / 2);
+                break;
+            }
+        }
+        steps
+    }
+
+    /// How far the time not simulated yet reaches into the next step, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()).min(1.0) as f32
+    }
+}
 
-#[derive(Default)]
 pub struct App {
     window: Option<Window>,
     renderer: Option<Renderer>,
+    render_mode: RenderMode,
+    /// Switches between windowed and `fullscreen_mode`, `None` turns the binding off.
+    fullscreen_hotkey: Option<Hotkey>,
+    fullscreen_mode: DisplayMode,
+    /// Held down on the keyboard, kept up to date for the hotkeys.
+    modifiers: ModifiersState,
+    /// Steps the renderer's simulation at 60Hz by default, `None` steps it once per frame.
+    timestep: Option<FixedTimestep>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self {
+            window: None,
+            renderer: None,
+            render_mode: RenderMode::default(),
+            fullscreen_hotkey: Some(Hotkey::ALT_ENTER),
+            fullscreen_mode: DisplayMode::Borderless,
+            modifiers: ModifiersState::empty(),
+            timestep: Some(FixedTimestep::from_hz(60.0)),
+        }
+    }
+}
+
+impl App {
+    pub fn new(render_mode: RenderMode) -> Self {
+        Self {
+            render_mode,
+            ..Default::default()
+        }
+    }
+
+    /// Opens another window that shows the same frames as the main one, e.g. on a second
+    /// monitor. It is closed with its close button or `close_window`.
+    pub fn open_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        window_attributes: WindowAttributes,
+    ) -> Result<WindowId> {
+        let renderer = self
+            .renderer
+            .as_mut()
+            .ok_or_else(|| anyhow!("No renderer to draw the window"))?;
+        let window = Arc::new(event_loop.create_window(window_attributes)?);
+        let window_id = window.id();
+        renderer.add_window(window)?;
+        Ok(window_id)
+    }
+
+    pub fn close_window(&mut self, window_id: WindowId) -> Result<bool> {
+        match self.renderer.as_mut() {
+            Some(renderer) => renderer.remove_window(window_id),
+            None => Ok(false),
+        }
+    }
+
+    /// Shows the main window in `mode`, e.g. borderless or exclusive fullscreen.
+    pub fn set_display_mode(&mut self, mode: DisplayMode) -> Result<()> {
+        let (Some(window), Some(renderer)) = (self.window.as_ref(), self.renderer.as_mut()) else {
+            return Err(anyhow!("No window to change the display mode of"));
+        };
+        renderer.set_display_mode(window, mode)
+    }
+
+    pub fn display_mode(&self) -> DisplayMode {
+        self.window.as_ref().map(DisplayMode::of).unwrap_or_default()
+    }
+
+    /// Leaves fullscreen, or enters `fullscreen_mode` from a window.
+    pub fn toggle_fullscreen(&mut self) -> Result<()> {
+        let mode = if self.display_mode().is_fullscreen() {
+            DisplayMode::Windowed
+        } else {
+            self.fullscreen_mode.clone()
+        };
+        self.set_display_mode(mode)
+    }
+
+    /// Video modes of the monitor the main window is on, for `DisplayMode::Exclusive`.
+    pub fn video_modes(&self) -> Vec<VideoModeHandle> {
+        self.window.as_ref().map(video_modes).unwrap_or_default()
+    }
+
+    /// Binds `toggle_fullscreen` to `hotkey`, Alt+Enter by default.
+    pub fn set_fullscreen_hotkey(&mut self, hotkey: Option<Hotkey>) {
+        self.fullscreen_hotkey = hotkey;
+    }
+
+    /// The mode `toggle_fullscreen` enters, borderless by default.
+    pub fn set_fullscreen_mode(&mut self, mode: DisplayMode) {
+        self.fullscreen_mode = mode;
+    }
+
+    /// Runs `Renderer::update` in steps of `timestep` and draws the frames between them, or
+    /// once per frame with its duration for `None`.
+    pub fn set_fixed_timestep(&mut self, timestep: Option<FixedTimestep>) {
+        self.timestep = timestep;
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.set_interpolation(timestep.map(|timestep| timestep.alpha()));
+        }
+    }
+
+    pub fn fixed_timestep(&self) -> Option<FixedTimestep> {
+        self.timestep
+    }
+
+    fn is_fullscreen_hotkey(&self, event: &KeyEvent) -> bool {
+        match (self.fullscreen_hotkey, event.physical_key) {
+            (Some(hotkey), PhysicalKey::Code(key)) => {
+                event.state == ElementState::Pressed
+                    && !event.repeat
+                    && hotkey.matches(key, self.modifiers)
+            }
+            _ => false,
+        }
+    }
 }
 
 #[allow(warnings)]
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
         let window_attributes =
             WindowAttributes::default().with_inner_size(LogicalSize::new(3840, 2160));
-        self.window = event_loop.create_window(window_attributes).ok();
-        self.renderer = Renderer::init(&self.window.as_ref().unwrap()).ok();
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => window,
+            Err(err) => {
+                error!("Creating the window failed: {err}");
+                event_loop.exit();
+                return;
+            }
+        };
+        match Renderer::init(&window) {
+            Ok(renderer) => self.renderer = Some(renderer),
+            Err(err) => {
+                error!("Initializing the renderer failed: {err}");
+                event_loop.exit();
+            }
+        }
+        self.window = Some(window);
     }
 
     fn window_event(
@@ -27,15 +238,104 @@ impl ApplicationHandler for App {
         window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        self.renderer
-            .as_mut()
-            .unwrap()
-            .egui_renderer
-            .integration
-            .input(self.window.as_mut().unwrap(), &event);
-        self.renderer
-            .as_mut()
-            .unwrap()
-            .display(self.window.as_mut().unwrap());
+        match &event {
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::KeyboardInput { event: key_event, .. }
+                if self.is_fullscreen_hotkey(key_event) =>
+            {
+                if let Err(err) = self.toggle_fullscreen() {
+                    error!("Toggling fullscreen failed: {err}");
+                }
+                return;
+            }
+            _ => {}
+        }
+        let (Some(window), Some(renderer)) = (self.window.as_ref(), self.renderer.as_mut()) else {
+            return;
+        };
+        if window_id != window.id() {
+            // secondary windows are drawn with the main one and follow their size on their own
+            if event == WindowEvent::CloseRequested
+                && let Err(err) = renderer.remove_window(window_id)
+            {
+                error!("Closing window failed: {err}");
+            }
+            return;
+        }
+        if let Some(egui_renderer) = renderer.egui_renderer.as_mut() {
+            egui_renderer.on_window_event(window, &event);
+        }
+        renderer.on_window_event(&event);
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::RedrawRequested => {
+                if let Some(timestep) = self.timestep.as_mut() {
+                    for _ in 0..timestep.advance(Instant::now()) {
+                        renderer.update(timestep.step());
+                    }
+                    renderer.set_interpolation(Some(timestep.alpha()));
+                }
+                if let Err(err) = renderer.display(window) {
+                    error!("Frame failed: {err}");
+                }
+            }
+            // Continuous mode redraws anyway, see `about_to_wait`.
+            _ if self.render_mode == RenderMode::Reactive => renderer.invalidate(),
+            _ => {}
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let (Some(window), Some(renderer)) = (self.window.as_ref(), self.renderer.as_ref()) else {
+            return;
+        };
+        if is_zero_sized(window) {
+            // Nothing to render while minimized, the restore resizes the window and wakes us up.
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+        match self.render_mode {
+            RenderMode::Continuous => {
+                window.request_redraw();
+                event_loop.set_control_flow(ControlFlow::Poll);
+            }
+            RenderMode::Reactive => {
+                if renderer.needs_redraw() {
+                    window.request_redraw();
+                }
+                event_loop.set_control_flow(match renderer.next_redraw_deadline() {
+                    Some(deadline) => ControlFlow::WaitUntil(deadline),
+                    None => ControlFlow::Wait,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::FixedTimestep;
+
+    #[test]
+    fn steps_accumulate_and_leave_the_remainder_as_alpha() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+        assert_eq!(timestep.advance(start), 0);
+        assert_eq!(timestep.advance(start + Duration::from_millis(25)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-5);
+        assert_eq!(timestep.advance(start + Duration::from_millis(30)), 1);
+        assert!(timestep.alpha().abs() < 1e-5);
+    }
+
+    #[test]
+    fn stalls_run_a_bounded_number_of_steps() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+        timestep.advance(start);
+        assert_eq!(timestep.advance(start + Duration::from_secs(5)), FixedTimestep::MAX_STEPS);
+        assert!(timestep.alpha() <= 0.5);
+        assert_eq!(timestep.advance(start + Duration::from_millis(5010)), 1);
     }
 }
@@ -1,19 +1,52 @@
-use std::{io::Error, ops::Deref, sync::Arc};
+use std::{fmt::Debug, io::Error, ops::Deref, sync::Arc};
 
 use ash::vk::{
     BufferCopy, BufferImageCopy, DeviceSize, Extent2D, Extent3D, Format, Framebuffer, FramebufferCreateInfo, Image, ImageAspectFlags, ImageLayout, ImageView, MemoryPropertyFlags, Offset3D
 };
 use ash::vk::Buffer;
+use vk_mem::{Allocation, AllocationInfo, Allocator};
+
 use super::{
-    command_buffers::VkCommandPool, device::VkDevice, image_util::image_subresource_layers, queue::VkQueue, render_pass::VkRenderPass, swapchain::ImageDetails
+    command_buffers::ImmediateSubmit, device::VkDevice, image_util::image_subresource_layers, render_pass::VkRenderPass, swapchain::ImageDetails
 };
 
+/// Destroys the view, the image and its memory once the last `AllocatedImage` sharing them is
+/// dropped. The allocator goes before the device it was created for.
+struct ImageOwner {
+    allocator: Arc<Allocator>,
+    device: Arc<VkDevice>,
+    image_details: ImageDetails,
+    allocation: Allocation,
+}
+
+impl Drop for ImageOwner {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.image_details.image_view, None);
+            self.allocator.destroy_image(self.image_details.image, &mut self.allocation);
+        }
+    }
+}
 
-#[derive(Debug, Clone, Copy)]
+/// An image with its view. Clones share them, they are destroyed with the last of them.
+/// Dropping it while a frame that uses it is in flight has to be deferred, see
+/// `FType::DROP`. Images created with `new` own nothing.
+#[derive(Clone)]
 pub struct AllocatedImage {
     pub image_details: ImageDetails,
     pub extent: Extent3D,
     pub image_format: Format,
+    _owner: Option<Arc<ImageOwner>>,
+}
+
+impl Debug for AllocatedImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AllocatedImage")
+            .field("image_details", &self.image_details)
+            .field("extent", &self.extent)
+            .field("image_format", &self.image_format)
+            .finish()
+    }
 }
 
 impl AllocatedImage {
@@ -26,6 +59,29 @@ impl AllocatedImage {
             image_details,
             extent,
             image_format,
+            _owner: None,
+        }
+    }
+
+    /// Takes over `image_details` and `allocation`, which were allocated from `allocator`.
+    pub(super) fn owned(
+        allocator: Arc<Allocator>,
+        device: Arc<VkDevice>,
+        image_details: ImageDetails,
+        allocation: Allocation,
+        extent: Extent3D,
+        image_format: Format,
+    ) -> Self {
+        Self {
+            image_details,
+            extent,
+            image_format,
+            _owner: Some(Arc::new(ImageOwner {
+                allocator,
+                device,
+                image_details,
+                allocation,
+            })),
         }
     }
 }
@@ -35,6 +91,9 @@ pub enum IDENTIFIER {
     SWAPCHAIN,
     DRAW, 
     DEPTH,
+    ANALYSIS,
+    POST,
+    GBUFFER,
 }
 
 #[allow(dead_code)]
@@ -52,6 +111,12 @@ impl Deref for VkFrameBuffer {
     }
 }
 
+impl Drop for VkFrameBuffer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_framebuffer(self.frame_buffer, None) };
+    }
+}
+
 impl VkFrameBuffer {
     fn new(
         identifier: IDENTIFIER,
@@ -100,10 +165,36 @@ impl VkFrameBuffer {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Destroys the buffer and frees its memory once the last `VkBuffer` sharing it is dropped.
+struct BufferOwner {
+    allocator: Arc<Allocator>,
+    _device: Arc<VkDevice>,
+    buffer: Buffer,
+    allocation: Allocation,
+}
+
+impl Drop for BufferOwner {
+    fn drop(&mut self) {
+        unsafe { self.allocator.destroy_buffer(self.buffer, &mut self.allocation) };
+    }
+}
+
+/// A buffer and its device address. Clones share the buffer, which is destroyed with the last
+/// of them, see `AllocatedImage`. The default value owns nothing.
+#[derive(Clone, Default)]
 pub struct VkBuffer {
     pub buffer: Buffer,
-    pub address: u64
+    pub address: u64,
+    _owner: Option<Arc<BufferOwner>>,
+}
+
+impl Debug for VkBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VkBuffer")
+            .field("buffer", &self.buffer)
+            .field("address", &self.address)
+            .finish()
+    }
 }
 
 impl Deref for VkBuffer {
@@ -115,32 +206,67 @@ impl Deref for VkBuffer {
 }
 
 impl VkBuffer {
-    pub fn copy_buffer(
-        src: VkBuffer,
-        dst: VkBuffer,
-        size: DeviceSize,
-        queue: Arc<VkQueue>,
-        command_pool: &VkCommandPool,
-    ) {
-        let command_buffer = command_pool.single_time_command().unwrap();
+    /// Takes over `buffer` and `allocation`, which were allocated from `allocator`.
+    pub(super) fn owned(
+        allocator: Arc<Allocator>,
+        device: Arc<VkDevice>,
+        buffer: Buffer,
+        address: u64,
+        allocation: Allocation,
+    ) -> Self {
+        Self {
+            buffer,
+            address,
+            _owner: Some(Arc::new(BufferOwner {
+                allocator,
+                _device: device,
+                buffer,
+                allocation,
+            })),
+        }
+    }
+
+    /// Memory type, size and persistent mapping of the allocation, `None` if it owns nothing.
+    pub fn allocation_info(&self) -> Option<AllocationInfo> {
+        self._owner
+            .as_ref()
+            .map(|owner| owner.allocator.get_allocation_info(&owner.allocation))
+    }
+
+    /// Start of the persistent mapping of the buffer, null if its memory is not host visible.
+    pub fn mapped_data(&self) -> *mut u8 {
+        self.allocation_info()
+            .map_or(std::ptr::null_mut(), |info| info.mapped_data as *mut u8)
+    }
+
+    pub fn copy_buffer(src: &VkBuffer, dst: &VkBuffer, size: DeviceSize, submit: &ImmediateSubmit) {
         let buffer_copy = vec![BufferCopy::default().src_offset(0).dst_offset(0).size(size)];
         unsafe {
-            command_pool
-                .device
-                .cmd_copy_buffer(command_buffer, *src, *dst, &buffer_copy)
+            submit
+                .device()
+                .cmd_copy_buffer(submit.command_buffer(), **src, **dst, &buffer_copy)
         };
-        command_pool.end_single_time_command(queue, command_buffer);
     }
 
     pub fn copy_buffer_to_image(
         src: Buffer,
         dst: Image,
         extent: Extent3D,
-        queue: Arc<VkQueue>,
-        command_pool: &VkCommandPool,
+        submit: &ImmediateSubmit,
     ) -> Result<(), Error> {
-        let command_buffer = command_pool.single_time_command().unwrap();
-        let image_subresource= image_subresource_layers(ImageAspectFlags::COLOR);
+        Self::copy_buffer_to_image_layers(src, dst, extent, 1, submit)
+    }
+
+    /// Copies `layer_count` tightly packed layers (e.g. the 6 faces of a cube map) from `src`.
+    pub fn copy_buffer_to_image_layers(
+        src: Buffer,
+        dst: Image,
+        extent: Extent3D,
+        layer_count: u32,
+        submit: &ImmediateSubmit,
+    ) -> Result<(), Error> {
+        let image_subresource =
+            image_subresource_layers(ImageAspectFlags::COLOR).layer_count(layer_count);
         let buffer_image_copy = BufferImageCopy::default()
             .buffer_offset(0)
             .image_offset(Offset3D::default().x(0).y(0).z(0))
@@ -150,15 +276,111 @@ impl VkBuffer {
             .buffer_image_height(0);
 
         unsafe {
-            command_pool.device.cmd_copy_buffer_to_image(
-                command_buffer,
+            submit.device().cmd_copy_buffer_to_image(
+                submit.command_buffer(),
+                src,
+                dst,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[buffer_image_copy],
+            )
+        };
+        Ok(())
+    }
+
+    /// Copies a tightly packed `extent` sized region into `dst` at `offset`.
+    pub fn copy_buffer_to_image_region(
+        src: Buffer,
+        dst: Image,
+        offset: Offset3D,
+        extent: Extent3D,
+        submit: &ImmediateSubmit,
+    ) -> Result<(), Error> {
+        let buffer_image_copy = BufferImageCopy::default()
+            .buffer_offset(0)
+            .image_offset(offset)
+            .image_subresource(image_subresource_layers(ImageAspectFlags::COLOR))
+            .image_extent(extent)
+            .buffer_row_length(0)
+            .buffer_image_height(0);
+
+        unsafe {
+            submit.device().cmd_copy_buffer_to_image(
+                submit.command_buffer(),
                 src,
                 dst,
                 ImageLayout::TRANSFER_DST_OPTIMAL,
                 &[buffer_image_copy],
             )
         };
-        command_pool.end_single_time_command(queue, command_buffer);
+        Ok(())
+    }
+
+    /// Copies tightly packed mip levels into `dst`, `level_offsets[i]` is the byte offset of
+    /// level i in `src`.
+    pub fn copy_buffer_to_image_mips(
+        src: Buffer,
+        dst: Image,
+        extent: Extent3D,
+        level_offsets: &[u64],
+        submit: &ImmediateSubmit,
+    ) -> Result<(), Error> {
+        let regions = level_offsets
+            .iter()
+            .enumerate()
+            .map(|(level, offset)| {
+                BufferImageCopy::default()
+                    .buffer_offset(*offset)
+                    .image_offset(Offset3D::default().x(0).y(0).z(0))
+                    .image_subresource(
+                        image_subresource_layers(ImageAspectFlags::COLOR).mip_level(level as u32),
+                    )
+                    .image_extent(Extent3D {
+                        width: (extent.width >> level).max(1),
+                        height: (extent.height >> level).max(1),
+                        depth: 1,
+                    })
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            submit.device().cmd_copy_buffer_to_image(
+                submit.command_buffer(),
+                src,
+                dst,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            )
+        };
+        Ok(())
+    }
+
+    /// Copies the first mip level of `src`, which has to be in TRANSFER_SRC_OPTIMAL, tightly
+    /// packed into `dst`.
+    pub fn copy_image_to_buffer(
+        src: Image,
+        dst: Buffer,
+        extent: Extent3D,
+        submit: &ImmediateSubmit,
+    ) -> Result<(), Error> {
+        let buffer_image_copy = BufferImageCopy::default()
+            .buffer_offset(0)
+            .image_offset(Offset3D::default().x(0).y(0).z(0))
+            .image_subresource(image_subresource_layers(ImageAspectFlags::COLOR))
+            .image_extent(extent)
+            .buffer_row_length(0)
+            .buffer_image_height(0);
+
+        unsafe {
+            submit.device().cmd_copy_image_to_buffer(
+                submit.command_buffer(),
+                src,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst,
+                &[buffer_image_copy],
+            )
+        };
         Ok(())
     }
 
@@ -0,0 +1,329 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, Buffer, BufferMemoryBarrier2, CommandBuffer, CommandBufferBeginInfo,
+    CommandBufferSubmitInfo, CommandBufferUsageFlags, Fence, Image, ImageAspectFlags, ImageLayout,
+    ImageMemoryBarrier2, PipelineStageFlags2, Semaphore, SemaphoreCreateInfo, SemaphoreSubmitInfo,
+    SemaphoreType, SemaphoreTypeCreateInfo, SubmitInfo2, QUEUE_FAMILY_IGNORED, WHOLE_SIZE,
+};
+
+use super::{
+    command_buffers::VkCommandPool, device::VkDevice, image_util::image_subresource_range,
+    queue::VkQueue,
+};
+
+/// Stages of the graphics queue that wait for async compute passes, their results must not be
+/// used before them.
+pub const COMPUTE_DST_STAGES: PipelineStageFlags2 = PipelineStageFlags2::from_raw(
+    PipelineStageFlags2::DRAW_INDIRECT.as_raw()
+        | PipelineStageFlags2::VERTEX_INPUT.as_raw()
+        | PipelineStageFlags2::VERTEX_SHADER.as_raw()
+        | PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+        | PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+);
+
+const COMPUTE_DST_ACCESS: AccessFlags2 = AccessFlags2::from_raw(
+    AccessFlags2::INDIRECT_COMMAND_READ.as_raw()
+        | AccessFlags2::VERTEX_ATTRIBUTE_READ.as_raw()
+        | AccessFlags2::INDEX_READ.as_raw()
+        | AccessFlags2::SHADER_READ.as_raw(),
+);
+
+/// A submitted batch, its command buffer is freed once the semaphore reached `value`.
+struct InFlightBatch {
+    value: u64,
+    command_buffer: CommandBuffer,
+}
+
+/// Records compute passes like culling or post-processing on the compute queue, so they
+/// overlap with the graphics work of the frame. Each submitted batch signals the next value of
+/// a timeline semaphore, which the next frame drawn waits on. In the other direction the
+/// graphics queue signals `frame_semaphore` for every frame, batches can wait for the frames
+/// submitted before them to read what those rendered.
+///
+/// On devices with a dedicated compute family the results have to change ownership, resources
+/// written by a batch are handed to the graphics family with `release_buffer` and
+/// `release_image`. Without one the compute queue is the graphics queue, batches still work
+/// but don't overlap.
+pub struct AsyncCompute {
+    device: Arc<VkDevice>,
+    compute_queue: Arc<VkQueue>,
+    graphics_queue: Arc<VkQueue>,
+    command_pool: VkCommandPool,
+    semaphore: Semaphore,
+    /// Signaled by the graphics queue with the number of frames submitted.
+    frame_semaphore: Semaphore,
+    /// Value signaled by the last submitted batch.
+    submitted: u64,
+    /// Frames the graphics queue submitted, the value `frame_semaphore` reaches last.
+    frames_submitted: u64,
+    /// Set when batches were submitted that no frame waited on yet.
+    unconsumed: bool,
+    recording: Option<CommandBuffer>,
+    /// Acquire barriers of the batch being recorded, they move to the pending ones on submit.
+    recorded_buffer_acquires: Vec<BufferMemoryBarrier2<'static>>,
+    recorded_image_acquires: Vec<ImageMemoryBarrier2<'static>>,
+    pending_buffer_acquires: Vec<BufferMemoryBarrier2<'static>>,
+    pending_image_acquires: Vec<ImageMemoryBarrier2<'static>>,
+    in_flight: VecDeque<InFlightBatch>,
+}
+
+impl AsyncCompute {
+    pub fn new(
+        device: Arc<VkDevice>,
+        compute_queue: Arc<VkQueue>,
+        graphics_queue: Arc<VkQueue>,
+    ) -> Result<Self> {
+        let create_timeline = || -> Result<Semaphore> {
+            let mut type_create_info = SemaphoreTypeCreateInfo::default()
+                .semaphore_type(SemaphoreType::TIMELINE)
+                .initial_value(0);
+            Ok(unsafe {
+                device.create_semaphore(
+                    &SemaphoreCreateInfo::default().push_next(&mut type_create_info),
+                    None,
+                )?
+            })
+        };
+        let semaphore = create_timeline()?;
+        let frame_semaphore = create_timeline()?;
+        Ok(Self {
+            command_pool: VkCommandPool::new(compute_queue.clone()),
+            device,
+            compute_queue,
+            graphics_queue,
+            semaphore,
+            frame_semaphore,
+            submitted: 0,
+            frames_submitted: 0,
+            unconsumed: false,
+            recording: None,
+            recorded_buffer_acquires: vec![],
+            recorded_image_acquires: vec![],
+            pending_buffer_acquires: vec![],
+            pending_image_acquires: vec![],
+            in_flight: VecDeque::new(),
+        })
+    }
+
+    /// Timeline semaphore signaled by the compute batches.
+    pub fn semaphore(&self) -> Semaphore {
+        self.semaphore
+    }
+
+    /// Timeline semaphore the graphics queue signals with the number of frames submitted.
+    pub fn frame_semaphore(&self) -> Semaphore {
+        self.frame_semaphore
+    }
+
+    /// Whether compute and graphics queue are of different families, only then batches run
+    /// alongside rendering and their results need to be released.
+    pub fn dedicated_compute(&self) -> bool {
+        self.compute_queue.queue_family_index != self.graphics_queue.queue_family_index
+    }
+
+    pub fn compute_queue(&self) -> &Arc<VkQueue> {
+        &self.compute_queue
+    }
+
+    /// The command buffer of the batch being recorded, begun on first use. Pipelines bound to
+    /// it have to be compute pipelines.
+    pub fn command_buffer(&mut self) -> Result<CommandBuffer> {
+        if let Some(cmd) = self.recording {
+            return Ok(cmd);
+        }
+        let cmd = self.command_pool.allocate_command_buffer();
+        unsafe {
+            self.device.begin_command_buffer(
+                cmd,
+                &CommandBufferBeginInfo::default().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?
+        };
+        self.recording = Some(cmd);
+        Ok(cmd)
+    }
+
+    /// Makes the writes of the batch to `buffer` visible to the frames drawn after `submit`,
+    /// releasing it to the graphics family if there is a dedicated compute one.
+    pub fn release_buffer(&mut self, buffer: Buffer) -> Result<()> {
+        if !self.dedicated_compute() {
+            // the semaphore wait alone makes the writes visible to the graphics queue
+            return Ok(());
+        }
+        let cmd = self.command_buffer()?;
+        let barrier = BufferMemoryBarrier2::default()
+            .buffer(buffer)
+            .offset(0)
+            .size(WHOLE_SIZE)
+            .src_queue_family_index(self.compute_queue.queue_family_index)
+            .dst_queue_family_index(self.graphics_queue.queue_family_index);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &[barrier
+                    .src_stage_mask(PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(AccessFlags2::SHADER_WRITE)],
+                &[],
+            );
+        }
+        self.recorded_buffer_acquires.push(
+            barrier
+                .dst_stage_mask(COMPUTE_DST_STAGES)
+                .dst_access_mask(COMPUTE_DST_ACCESS),
+        );
+        Ok(())
+    }
+
+    /// Moves the color `image` written by the batch from `old_layout` to `new_layout`, as part
+    /// of the ownership transfer if there is one.
+    pub fn release_image(
+        &mut self,
+        image: Image,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+    ) -> Result<()> {
+        let (src_family, dst_family) = if self.dedicated_compute() {
+            (
+                self.compute_queue.queue_family_index,
+                self.graphics_queue.queue_family_index,
+            )
+        } else {
+            (QUEUE_FAMILY_IGNORED, QUEUE_FAMILY_IGNORED)
+        };
+        let cmd = self.command_buffer()?;
+        let barrier = ImageMemoryBarrier2::default()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .image(image)
+            .subresource_range(image_subresource_range(ImageAspectFlags::COLOR));
+        // the transition has to finish before the semaphore signal, a release ignores the
+        // destination stage
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &[],
+                &[barrier
+                    .src_stage_mask(PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(AccessFlags2::SHADER_WRITE)
+                    .dst_stage_mask(PipelineStageFlags2::ALL_COMMANDS)],
+            );
+        }
+        if self.dedicated_compute() {
+            self.recorded_image_acquires.push(
+                barrier
+                    .dst_stage_mask(COMPUTE_DST_STAGES)
+                    .dst_access_mask(COMPUTE_DST_ACCESS),
+            );
+        }
+        Ok(())
+    }
+
+    /// Submits the batch recorded since the last call to the compute queue, after the frames
+    /// submitted so far finished on the graphics queue if `after_frames` is set. The next
+    /// frame drawn waits for it. Returns the semaphore value the batch signals, `None` if
+    /// nothing was recorded.
+    pub fn submit(&mut self, after_frames: bool) -> Result<Option<u64>> {
+        let Some(cmd) = self.recording.take() else {
+            return Ok(None);
+        };
+        self.submitted += 1;
+        let command_buffers = [CommandBufferSubmitInfo::default().command_buffer(cmd)];
+        let signal_semaphores = [SemaphoreSubmitInfo::default()
+            .semaphore(self.semaphore)
+            .value(self.submitted)
+            .stage_mask(PipelineStageFlags2::ALL_COMMANDS)];
+        let wait_semaphores = if after_frames {
+            vec![SemaphoreSubmitInfo::default()
+                .semaphore(self.frame_semaphore)
+                .value(self.frames_submitted)
+                .stage_mask(PipelineStageFlags2::ALL_COMMANDS)]
+        } else {
+            vec![]
+        };
+        unsafe {
+            self.device.end_command_buffer(cmd)?;
+            self.device.queue_submit2(
+                **self.compute_queue,
+                &[SubmitInfo2::default()
+                    .command_buffer_infos(&command_buffers)
+                    .wait_semaphore_infos(&wait_semaphores)
+                    .signal_semaphore_infos(&signal_semaphores)],
+                Fence::null(),
+            )?;
+        }
+        self.in_flight.push_back(InFlightBatch {
+            value: self.submitted,
+            command_buffer: cmd,
+        });
+        self.pending_buffer_acquires
+            .append(&mut self.recorded_buffer_acquires);
+        self.pending_image_acquires
+            .append(&mut self.recorded_image_acquires);
+        self.unconsumed = true;
+        Ok(Some(self.submitted))
+    }
+
+    /// Semaphore value of the submitted batches the next frame has to wait on, `None` if it
+    /// waited on every batch already.
+    pub(crate) fn take_wait(&mut self) -> Option<u64> {
+        std::mem::take(&mut self.unconsumed).then_some(self.submitted)
+    }
+
+    /// Value the frame being submitted signals `frame_semaphore` with.
+    pub(crate) fn next_frame_value(&mut self) -> u64 {
+        self.frames_submitted += 1;
+        self.frames_submitted
+    }
+
+    /// Records the acquire barriers of the submitted batches into `cmd` of the graphics queue,
+    /// which has to wait on the value returned by `take_wait`.
+    pub(crate) fn record_acquires(&mut self, cmd: CommandBuffer) {
+        if self.pending_buffer_acquires.is_empty() && self.pending_image_acquires.is_empty() {
+            return;
+        }
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &self.pending_buffer_acquires,
+                &self.pending_image_acquires,
+            );
+        }
+        self.pending_buffer_acquires.clear();
+        self.pending_image_acquires.clear();
+    }
+
+    /// Frees the command buffers of every batch the compute queue finished.
+    pub fn collect(&mut self) -> Result<()> {
+        let completed = unsafe { self.device.get_semaphore_counter_value(self.semaphore)? };
+        while self
+            .in_flight
+            .front()
+            .is_some_and(|batch| batch.value <= completed)
+        {
+            let batch = self.in_flight.pop_front().unwrap();
+            unsafe {
+                self.device
+                    .free_command_buffers(*self.command_pool, &[batch.command_buffer])
+            };
+        }
+        Ok(())
+    }
+
+    /// Destroys the semaphores and the command pool, the device has to be idle.
+    pub fn destroy(&mut self) {
+        self.in_flight.clear();
+        self.recording = None;
+        unsafe {
+            self.device.destroy_semaphore(self.semaphore, None);
+            self.device.destroy_semaphore(self.frame_semaphore, None);
+            // frees the command buffers still allocated from it
+            self.device.destroy_command_pool(*self.command_pool, None);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    self, BufferUsageFlags, DescriptorBindingFlags, DescriptorPool, DescriptorPoolCreateFlags,
+    DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo,
+    DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutBindingFlagsCreateInfo,
+    DescriptorSetLayoutCreateFlags, DescriptorSetLayoutCreateInfo,
+    DescriptorSetVariableDescriptorCountAllocateInfo, DescriptorType, ImageLayout, ImageView,
+    MemoryPropertyFlags, Sampler, ShaderStageFlags, WHOLE_SIZE,
+};
+use log::debug;
+use vk_mem::MemoryUsage;
+
+use crate::misc::material::BindlessMaterial;
+
+use super::{
+    deletion_queue::{DeletionQueue, FType},
+    descriptors::DescriptorWriter,
+    device::VkDevice,
+    memory_allocator::MemoryAllocator,
+    queue::VkQueue,
+    sampler::VkSampler,
+};
+
+/// Size of the texture array, slots are handed out in order and never reused.
+pub const MAX_BINDLESS_TEXTURES: u32 = 4096;
+/// Number of materials the material buffer has room for.
+pub const MAX_BINDLESS_MATERIALS: u32 = 1024;
+
+const MATERIAL_BINDING: u32 = 0;
+const TEXTURE_BINDING: u32 = 1;
+
+/// One descriptor set (set 1 of the mesh pipelines) holding every material texture in a
+/// variable sized, update after bind array and a storage buffer of `BindlessMaterial`s indexing
+/// into it. Draws only push their material index, the set is bound once per frame.
+pub struct BindlessDescriptors {
+    device: Arc<VkDevice>,
+    pub layout: DescriptorSetLayout,
+    set: DescriptorSet,
+    materials: *mut BindlessMaterial,
+    material_count: u32,
+    /// Texture slots by image view and sampler, materials sharing a texture share the slot.
+    textures: HashMap<(ImageView, Sampler), u32>,
+    /// Keeps the samplers written to the texture array alive as long as the set.
+    samplers: HashMap<Sampler, VkSampler>,
+}
+
+impl BindlessDescriptors {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: &MemoryAllocator,
+        queues: &[Arc<VkQueue>],
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let bindings = [
+            DescriptorSetLayoutBinding::default()
+                .binding(MATERIAL_BINDING)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT),
+            DescriptorSetLayoutBinding::default()
+                .binding(TEXTURE_BINDING)
+                .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(MAX_BINDLESS_TEXTURES)
+                .stage_flags(ShaderStageFlags::FRAGMENT),
+        ];
+        let binding_flags = [
+            DescriptorBindingFlags::UPDATE_AFTER_BIND,
+            DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | DescriptorBindingFlags::PARTIALLY_BOUND
+                | DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+        ];
+        let mut binding_flags_info =
+            DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+        let layout = unsafe {
+            device.create_descriptor_set_layout(
+                &DescriptorSetLayoutCreateInfo::default()
+                    .bindings(&bindings)
+                    .flags(DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                    .push_next(&mut binding_flags_info),
+                None,
+            )?
+        };
+
+        let pool_sizes = [
+            DescriptorPoolSize::default()
+                .ty(DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1),
+            DescriptorPoolSize::default()
+                .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(MAX_BINDLESS_TEXTURES),
+        ];
+        let pool: DescriptorPool = unsafe {
+            device.create_descriptor_pool(
+                &DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&pool_sizes)
+                    .flags(DescriptorPoolCreateFlags::UPDATE_AFTER_BIND),
+                None,
+            )?
+        };
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_pool(pool, None);
+            device.destroy_descriptor_set_layout(layout, None);
+        })));
+
+        let variable_counts = [MAX_BINDLESS_TEXTURES];
+        let mut variable_count_info = DescriptorSetVariableDescriptorCountAllocateInfo::default()
+            .descriptor_counts(&variable_counts);
+        let layouts = [layout];
+        let set = unsafe {
+            device.allocate_descriptor_sets(
+                &DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&layouts)
+                    .push_next(&mut variable_count_info),
+            )?[0]
+        };
+
+        let material_buffer = memory_allocator.allocate_single_buffer(
+            (size_of::<BindlessMaterial>() * MAX_BINDLESS_MATERIALS as usize) as u64,
+            queues,
+            BufferUsageFlags::STORAGE_BUFFER,
+            MemoryUsage::Unknown,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let materials = material_buffer.mapped_data() as *mut BindlessMaterial;
+        deletion_queue.enqueue(FType::DROP(Box::new(material_buffer.clone())));
+        if materials.is_null() {
+            return Err(anyhow!("Bindless material buffer is not host mapped"));
+        }
+
+        let mut writer = DescriptorWriter::new();
+        writer.write_buffer(
+            MATERIAL_BINDING,
+            &material_buffer,
+            WHOLE_SIZE,
+            0,
+            DescriptorType::STORAGE_BUFFER,
+        );
+        writer.update_set(device.clone(), set);
+        debug!("Bindless descriptors with {MAX_BINDLESS_TEXTURES} texture slots created");
+
+        Ok(Self {
+            device,
+            layout,
+            set,
+            materials,
+            material_count: 0,
+            textures: HashMap::new(),
+            samplers: HashMap::new(),
+        })
+    }
+
+    /// The set to bind as set 1 of the mesh pipelines.
+    pub fn descriptor_set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    /// Slot of `image_view` sampled with `sampler` in the texture array, written on first use.
+    pub fn texture_index(&mut self, image_view: ImageView, vk_sampler: &VkSampler) -> Result<u32> {
+        let sampler = **vk_sampler;
+        if let Some(index) = self.textures.get(&(image_view, sampler)) {
+            return Ok(*index);
+        }
+        let index = self.textures.len() as u32;
+        if index == MAX_BINDLESS_TEXTURES {
+            return Err(anyhow!("All {MAX_BINDLESS_TEXTURES} bindless texture slots are used"));
+        }
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_view(image_view)
+            .sampler(sampler)
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(TEXTURE_BINDING)
+            .dst_array_element(index)
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+        self.textures.insert((image_view, sampler), index);
+        self.samplers
+            .entry(sampler)
+            .or_insert_with(|| vk_sampler.clone());
+        Ok(index)
+    }
+
+    /// Appends `material` to the material buffer and returns its index. Frames in flight only
+    /// read the slots of earlier materials, so this never has to wait for the GPU.
+    pub fn add_material(&mut self, material: BindlessMaterial) -> Result<u32> {
+        let index = self.material_count;
+        if index == MAX_BINDLESS_MATERIALS {
+            return Err(anyhow!("All {MAX_BINDLESS_MATERIALS} bindless materials are used"));
+        }
+        unsafe { self.materials.add(index as usize).write(material) };
+        self.material_count += 1;
+        Ok(index)
+    }
+
+    /// Constants and texture slots of the material added as `index`.
+    pub fn material(&self, index: u32) -> Option<BindlessMaterial> {
+        (index < self.material_count).then(|| unsafe { self.materials.add(index as usize).read() })
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{BufferCopy, BufferUsageFlags, DeviceAddress, MemoryPropertyFlags};
+use log::debug;
+use vk_mem::MemoryUsage;
+
+use super::{
+    allocation_types::VkBuffer,
+    command_buffers::ImmediateSubmit,
+    memory_allocator::MemoryAllocator,
+    queue::VkQueue,
+};
+
+/// Free ranges of a block, sorted by offset and never adjacent to each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FreeList {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl FreeList {
+    fn new(size: u64) -> Self {
+        Self {
+            ranges: vec![(0, size)],
+        }
+    }
+
+    /// First fit, returns the offset of `size` bytes aligned to `alignment`.
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let (idx, offset) = self.ranges.iter().enumerate().find_map(|(idx, (start, len))| {
+            let offset = start.next_multiple_of(alignment);
+            (offset + size <= start + len).then_some((idx, offset))
+        })?;
+        let (start, len) = self.ranges.remove(idx);
+        let tail = (offset + size, start + len - offset - size);
+        // the padding in front of the aligned offset stays free
+        for (range_start, range_len) in [(start, offset - start), tail].into_iter().rev() {
+            if range_len > 0 {
+                self.ranges.insert(idx, (range_start, range_len));
+            }
+        }
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u64, size: u64) {
+        let idx = self.ranges.partition_point(|(start, _)| *start < offset);
+        self.ranges.insert(idx, (offset, size));
+        if idx + 1 < self.ranges.len() && offset + size == self.ranges[idx + 1].0 {
+            self.ranges[idx].1 += self.ranges.remove(idx + 1).1;
+        }
+        if idx > 0 && self.ranges[idx - 1].0 + self.ranges[idx - 1].1 == offset {
+            self.ranges[idx - 1].1 += self.ranges.remove(idx).1;
+        }
+    }
+}
+
+struct ArenaBlock {
+    buffer: VkBuffer,
+    free_list: FreeList,
+}
+
+/// Range of one of the large buffers of a `BufferArena`.
+#[derive(Debug, Clone)]
+pub struct BufferSlice {
+    /// The whole buffer the slice is part of, shared with other slices and kept alive by them.
+    pub buffer: VkBuffer,
+    pub offset: u64,
+    pub size: u64,
+    block: usize,
+}
+
+impl BufferSlice {
+    pub fn address(&self) -> DeviceAddress {
+        self.buffer.address + self.offset
+    }
+
+    /// Index of the first element of the slice, for elements of type `T`.
+    pub fn first_element<T>(&self) -> u32 {
+        (self.offset / size_of::<T>() as u64) as u32
+    }
+}
+
+/// Suballocates slices of a few large device local buffers instead of creating a buffer and an
+/// allocation per mesh, so the vertex and index data of a whole scene shares a handful of
+/// buffers. The arena holds its blocks until `destroy`, whoever created it calls that on
+/// teardown, their memory is freed once the last slice of a block is dropped as well.
+#[derive(Clone)]
+pub struct BufferArena {
+    /// Blocks are named after it for debuggers.
+    name: String,
+    memory_allocator: Arc<MemoryAllocator>,
+    queues: Vec<Arc<VkQueue>>,
+    usage: BufferUsageFlags,
+    block_size: u64,
+    blocks: Arc<Mutex<Vec<ArenaBlock>>>,
+}
+
+impl BufferArena {
+    /// Blocks are `block_size` bytes, or larger for slices that don't fit into one.
+    pub fn new(
+        name: &str,
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        usage: BufferUsageFlags,
+        block_size: u64,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            memory_allocator,
+            queues: queues.to_vec(),
+            usage: usage | BufferUsageFlags::TRANSFER_DST | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            block_size,
+            blocks: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Reserves `size` bytes at an offset aligned to `alignment`, a new block is created once
+    /// none of the existing ones has enough contiguous space.
+    pub fn allocate(&self, size: u64, alignment: u64) -> Result<BufferSlice> {
+        if size == 0 {
+            return Err(anyhow!("Empty slices can't be allocated"));
+        }
+        let mut blocks = self.blocks.lock().unwrap();
+        for (idx, block) in blocks.iter_mut().enumerate() {
+            if let Some(offset) = block.free_list.allocate(size, alignment) {
+                return Ok(BufferSlice {
+                    buffer: block.buffer.clone(),
+                    offset,
+                    size,
+                    block: idx,
+                });
+            }
+        }
+        let block_size = self.block_size.max(size);
+        #[allow(deprecated)]
+        let buffer = self.memory_allocator.allocate_single_buffer(
+            block_size,
+            &self.queues,
+            self.usage,
+            MemoryUsage::GpuOnly,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.memory_allocator
+            .device()
+            .debug_utils
+            .name(*buffer, &format!("{} block {}", self.name, blocks.len()));
+        let mut free_list = FreeList::new(block_size);
+        let offset = free_list.allocate(size, alignment).unwrap();
+        blocks.push(ArenaBlock {
+            buffer: buffer.clone(),
+            free_list,
+        });
+        Ok(BufferSlice {
+            buffer,
+            offset,
+            size,
+            block: blocks.len() - 1,
+        })
+    }
+
+    /// Allocates a slice for `elements` and records the copy into it, the data is there once
+    /// `submit` was submitted.
+    pub fn upload<T: Clone>(&self, submit: &ImmediateSubmit, elements: &[T]) -> Result<BufferSlice> {
+        let size = (size_of::<T>() * elements.len()) as u64;
+        // covers index offsets as well as the vertex structs read through the device address
+        let slice = self.allocate(size, 16)?;
+        let staging_buffer = self
+            .memory_allocator
+            .staging_buffer(size, elements, &self.queues)?;
+        unsafe {
+            submit.device().cmd_copy_buffer(
+                submit.command_buffer(),
+                *staging_buffer,
+                *slice.buffer,
+                &[BufferCopy::default().dst_offset(slice.offset).size(size)],
+            )
+        };
+        submit.keep_until_submitted(staging_buffer);
+        Ok(slice)
+    }
+
+    /// Makes the range of `slice` available again, the GPU must not use it anymore. Slices of
+    /// an arena that was destroyed meanwhile are ignored.
+    pub fn free(&self, slice: BufferSlice) {
+        if let Some(block) = self.blocks.lock().unwrap().get_mut(slice.block) {
+            block.free_list.free(slice.offset, slice.size);
+        }
+    }
+
+    /// Releases every block of the arena, the buffers are freed as soon as no slice of them
+    /// is left.
+    pub fn destroy(&self) {
+        self.blocks.lock().unwrap().clear();
+        debug!("{} blocks have been deleted", self.name);
+    }
+
+    /// Number of large buffers the arena allocated so far.
+    pub fn block_count(&self) -> usize {
+        self.blocks.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FreeList;
+
+    #[test]
+    fn allocates_aligned_first_fit() {
+        let mut free_list = FreeList::new(100);
+        assert_eq!(free_list.allocate(10, 1), Some(0));
+        assert_eq!(free_list.allocate(10, 16), Some(16));
+        // the padding between both stays usable
+        assert_eq!(free_list.ranges, vec![(10, 6), (26, 74)]);
+        assert_eq!(free_list.allocate(6, 2), Some(10));
+        assert_eq!(free_list.allocate(80, 1), None);
+    }
+
+    #[test]
+    fn freeing_merges_neighbours() {
+        let mut free_list = FreeList::new(64);
+        let a = free_list.allocate(16, 16).unwrap();
+        let b = free_list.allocate(16, 16).unwrap();
+        let c = free_list.allocate(16, 16).unwrap();
+        free_list.free(a, 16);
+        free_list.free(c, 16);
+        assert_eq!(free_list.ranges, vec![(0, 16), (32, 32)]);
+        free_list.free(b, 16);
+        assert_eq!(free_list, FreeList::new(64));
+    }
+}
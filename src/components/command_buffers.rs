@@ -1,13 +1,13 @@
-use std::{ops::Deref, sync::Arc};
+use std::{cell::RefCell, ops::Deref, sync::Arc};
 
 use anyhow::Error;
 use ash::vk::{
     CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel,
-    CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo, Fence,
-    SubmitInfo,
+    CommandBufferSubmitInfo, CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags,
+    CommandPoolCreateInfo, Fence, FenceCreateInfo, SubmitInfo2,
 };
 
-use super::{device::VkDevice, queue::VkQueue};
+use super::{allocation_types::VkBuffer, device::VkDevice, queue::VkQueue};
 
 #[derive(Clone)]
 pub struct VkCommandPool {
@@ -65,6 +65,19 @@ impl VkCommandPool {
         }
     }
 
+    /// Secondary buffers are executed from a primary one, see `SecondaryCommands`.
+    pub fn allocate_secondary_command_buffers(&self, count: u32) -> Vec<CommandBuffer> {
+        let allocate_info = CommandBufferAllocateInfo::default()
+            .command_pool(self.command_pool)
+            .level(CommandBufferLevel::SECONDARY)
+            .command_buffer_count(count);
+        unsafe {
+            self.device
+                .allocate_command_buffers(&allocate_info)
+                .unwrap()
+        }
+    }
+
     pub fn single_time_command(&self) -> Result<CommandBuffer, Error> {
         let command_buffer_allocate_info = CommandBufferAllocateInfo::default()
             .level(CommandBufferLevel::PRIMARY)
@@ -92,9 +105,11 @@ impl VkCommandPool {
         let command_buffers = vec![command_buffer];
         unsafe {
             self.device.end_command_buffer(command_buffer).unwrap();
-            let submit_info = vec![SubmitInfo::default().command_buffers(&command_buffers)];
+            let command_buffer_infos =
+                [CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+            let submit_info = vec![SubmitInfo2::default().command_buffer_infos(&command_buffer_infos)];
             self.device
-                .queue_submit(**queue, &submit_info, Fence::null())
+                .queue_submit2(**queue, &submit_info, Fence::null())
                 .unwrap();
             self.device.queue_wait_idle(**queue).unwrap();
             self.device
@@ -102,3 +117,68 @@ impl VkCommandPool {
         };
     }
 }
+
+/// Records any number of buffer copies and image transitions into a single command buffer,
+/// which `submit` executes and waits on with one fence instead of a wait per command. Dropping
+/// it without submitting discards the recorded commands.
+pub struct ImmediateSubmit {
+    command_pool: VkCommandPool,
+    queue: Arc<VkQueue>,
+    command_buffer: CommandBuffer,
+    /// Sources of the recorded copies, dropped once the batch completed or was discarded.
+    staging_buffers: RefCell<Vec<VkBuffer>>,
+}
+
+impl ImmediateSubmit {
+    pub fn begin(command_pool: &VkCommandPool, queue: Arc<VkQueue>) -> Result<Self, Error> {
+        Ok(Self {
+            command_buffer: command_pool.single_time_command()?,
+            command_pool: command_pool.clone(),
+            queue,
+            staging_buffers: RefCell::new(vec![]),
+        })
+    }
+
+    pub fn device(&self) -> &Arc<VkDevice> {
+        &self.command_pool.device
+    }
+
+    pub fn command_buffer(&self) -> CommandBuffer {
+        self.command_buffer
+    }
+
+    /// Keeps `staging_buffer` alive until the recorded commands reading it completed.
+    pub fn keep_until_submitted(&self, staging_buffer: VkBuffer) {
+        self.staging_buffers.borrow_mut().push(staging_buffer);
+    }
+
+    /// Submits everything recorded so far and blocks until the queue executed it, the command
+    /// buffer and staging buffers are freed when `self` is dropped on return.
+    pub fn submit(self) -> Result<(), Error> {
+        let device = self.device().clone();
+        let command_buffer_infos =
+            [CommandBufferSubmitInfo::default().command_buffer(self.command_buffer)];
+        unsafe {
+            device.end_command_buffer(self.command_buffer)?;
+            let fence = device.create_fence(&FenceCreateInfo::default(), None)?;
+            let result = device
+                .queue_submit2(
+                    **self.queue,
+                    &[SubmitInfo2::default().command_buffer_infos(&command_buffer_infos)],
+                    fence,
+                )
+                .and_then(|_| device.wait_for_fences(&[fence], true, u64::MAX));
+            device.destroy_fence(fence, None);
+            Ok(result?)
+        }
+    }
+}
+
+impl Drop for ImmediateSubmit {
+    fn drop(&mut self) {
+        unsafe {
+            self.device()
+                .free_command_buffers(*self.command_pool, &[self.command_buffer]);
+        }
+    }
+}
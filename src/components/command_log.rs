@@ -0,0 +1,504 @@
+use std::sync::atomic::Ordering;
+
+use ash::vk::{
+    Buffer, BufferCopy, BufferImageCopy, BufferMemoryBarrier2, CommandBuffer, DependencyInfo,
+    DeviceSize, Filter, Handle, Image, ImageBlit, ImageLayout, ImageMemoryBarrier2, MemoryBarrier2,
+    Pipeline, PipelineBindPoint, PipelineStageFlags2, RenderPassBeginInfo, SubpassContents,
+};
+use serde::Serialize;
+
+use super::device::VkDevice;
+
+/// One command put into a logged command buffer, handles are their raw values and flags their
+/// `Debug` names so logs of different runs can be diffed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RecordedCommand {
+    BeginRenderPass {
+        render_pass: u64,
+        framebuffer: u64,
+        width: u32,
+        height: u32,
+    },
+    EndRenderPass,
+    BindPipeline {
+        bind_point: String,
+        pipeline: u64,
+    },
+    Draw {
+        vertex_count: u32,
+        instance_count: u32,
+    },
+    DrawIndexed {
+        index_count: u32,
+        instance_count: u32,
+    },
+    Dispatch {
+        group_count: [u32; 3],
+    },
+    ExecuteCommands {
+        command_buffers: usize,
+    },
+    Barrier {
+        src_stage: String,
+        dst_stage: String,
+        memory_barriers: usize,
+        buffer_barriers: usize,
+        image_barriers: usize,
+    },
+    Copy {
+        kind: String,
+        regions: usize,
+    },
+}
+
+/// Commands recorded into the frame command buffers while logging was on, in recording order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CommandLog {
+    pub commands: Vec<RecordedCommand>,
+}
+
+impl CommandLog {
+    /// `Draw` and `DrawIndexed` commands.
+    pub fn draw_count(&self) -> usize {
+        self.count(|command| {
+            matches!(
+                command,
+                RecordedCommand::Draw { .. } | RecordedCommand::DrawIndexed { .. }
+            )
+        })
+    }
+
+    pub fn pipeline_bind_count(&self) -> usize {
+        self.count(|command| matches!(command, RecordedCommand::BindPipeline { .. }))
+    }
+
+    pub fn render_pass_count(&self) -> usize {
+        self.count(|command| matches!(command, RecordedCommand::BeginRenderPass { .. }))
+    }
+
+    pub fn dispatch_count(&self) -> usize {
+        self.count(|command| matches!(command, RecordedCommand::Dispatch { .. }))
+    }
+
+    pub fn barrier_count(&self) -> usize {
+        self.count(|command| matches!(command, RecordedCommand::Barrier { .. }))
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    fn count(&self, filter: impl Fn(&RecordedCommand) -> bool) -> usize {
+        self.commands
+            .iter()
+            .filter(|command| filter(command))
+            .count()
+    }
+}
+
+/// Log in progress, commands into other command buffers (uploads, other threads) are ignored.
+pub(super) struct CommandRecorder {
+    command_buffers: Vec<CommandBuffer>,
+    log: CommandLog,
+}
+
+impl VkDevice {
+    /// Starts logging the commands recorded into `command_buffers` through this device, a log
+    /// that is still running is dropped.
+    pub fn begin_command_log(&self, command_buffers: &[CommandBuffer]) {
+        *self.command_recorder.lock().unwrap() = Some(CommandRecorder {
+            command_buffers: command_buffers.to_vec(),
+            log: CommandLog::default(),
+        });
+        self.command_logging.store(true, Ordering::Release);
+    }
+
+    pub fn command_log_running(&self) -> bool {
+        self.command_logging.load(Ordering::Acquire)
+    }
+
+    /// Stops logging, `None` if no log was running.
+    pub fn end_command_log(&self) -> Option<CommandLog> {
+        self.command_logging.store(false, Ordering::Release);
+        self.command_recorder
+            .lock()
+            .unwrap()
+            .take()
+            .map(|recorder| recorder.log)
+    }
+
+    fn log_command(
+        &self,
+        command_buffer: CommandBuffer,
+        command: impl FnOnce() -> RecordedCommand,
+    ) {
+        if !self.command_log_running() {
+            return;
+        }
+        if let Some(recorder) = self.command_recorder.lock().unwrap().as_mut()
+            && recorder.command_buffers.contains(&command_buffer)
+        {
+            recorder.log.commands.push(command());
+        }
+    }
+
+    // The methods below shadow the ones of `ash::Device` reached through `Deref`, so every call
+    // site is logged without knowing about it. They have the contracts of the Vulkan commands
+    // they record, which the safety sections sum up.
+
+    /// # Safety
+    /// `command_buffer` is recording outside a render pass and `render_pass_begin` names a
+    /// render pass and framebuffer compatible with each other.
+    pub unsafe fn cmd_begin_render_pass(
+        &self,
+        command_buffer: CommandBuffer,
+        render_pass_begin: &RenderPassBeginInfo<'_>,
+        contents: SubpassContents,
+    ) {
+        self.log_command(command_buffer, || RecordedCommand::BeginRenderPass {
+            render_pass: render_pass_begin.render_pass.as_raw(),
+            framebuffer: render_pass_begin.framebuffer.as_raw(),
+            width: render_pass_begin.render_area.extent.width,
+            height: render_pass_begin.render_area.extent.height,
+        });
+        unsafe {
+            self.device
+                .cmd_begin_render_pass(command_buffer, render_pass_begin, contents)
+        }
+    }
+
+    /// # Safety
+    /// `command_buffer` is recording the last subpass of a render pass.
+    pub unsafe fn cmd_end_render_pass(&self, command_buffer: CommandBuffer) {
+        self.log_command(command_buffer, || RecordedCommand::EndRenderPass);
+        unsafe { self.device.cmd_end_render_pass(command_buffer) }
+    }
+
+    /// # Safety
+    /// `command_buffer` is recording and `pipeline` lives until it completed.
+    pub unsafe fn cmd_bind_pipeline(
+        &self,
+        command_buffer: CommandBuffer,
+        pipeline_bind_point: PipelineBindPoint,
+        pipeline: Pipeline,
+    ) {
+        self.log_command(command_buffer, || RecordedCommand::BindPipeline {
+            bind_point: format!("{pipeline_bind_point:?}"),
+            pipeline: pipeline.as_raw(),
+        });
+        unsafe {
+            self.device
+                .cmd_bind_pipeline(command_buffer, pipeline_bind_point, pipeline)
+        }
+    }
+
+    /// # Safety
+    /// `command_buffer` is recording inside a render pass with a graphics pipeline and every
+    /// resource it reads bound.
+    pub unsafe fn cmd_draw(
+        &self,
+        command_buffer: CommandBuffer,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) {
+        self.log_command(command_buffer, || RecordedCommand::Draw {
+            vertex_count,
+            instance_count,
+        });
+        unsafe {
+            self.device.cmd_draw(
+                command_buffer,
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            )
+        }
+    }
+
+    /// # Safety
+    /// As for `cmd_draw`, with an index buffer bound that holds the indexed range.
+    pub unsafe fn cmd_draw_indexed(
+        &self,
+        command_buffer: CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) {
+        self.log_command(command_buffer, || RecordedCommand::DrawIndexed {
+            index_count,
+            instance_count,
+        });
+        unsafe {
+            self.device.cmd_draw_indexed(
+                command_buffer,
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            )
+        }
+    }
+
+    /// # Safety
+    /// `primary_command_buffer` is recording and the secondary command buffers are executable
+    /// and compatible with its current render pass.
+    pub unsafe fn cmd_execute_commands(
+        &self,
+        primary_command_buffer: CommandBuffer,
+        secondary_command_buffers: &[CommandBuffer],
+    ) {
+        self.log_command(primary_command_buffer, || RecordedCommand::ExecuteCommands {
+            command_buffers: secondary_command_buffers.len(),
+        });
+        unsafe {
+            self.device
+                .cmd_execute_commands(primary_command_buffer, secondary_command_buffers)
+        }
+    }
+
+    /// # Safety
+    /// `command_buffer` is recording outside a render pass with a compute pipeline and every
+    /// resource it reads bound.
+    pub unsafe fn cmd_dispatch(
+        &self,
+        command_buffer: CommandBuffer,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        self.log_command(command_buffer, || RecordedCommand::Dispatch {
+            group_count: [group_count_x, group_count_y, group_count_z],
+        });
+        unsafe {
+            self.device
+                .cmd_dispatch(command_buffer, group_count_x, group_count_y, group_count_z)
+        }
+    }
+
+    /// Records one vkCmdPipelineBarrier2 with the barriers, each carries its own stages. Logged
+    /// with the union of their stages.
+    ///
+    /// # Safety
+    /// `command_buffer` is recording and the barriers name live resources. Inside a render pass
+    /// only the barriers its subpass dependencies allow are valid.
+    pub unsafe fn cmd_pipeline_barrier2(
+        &self,
+        command_buffer: CommandBuffer,
+        memory_barriers: &[MemoryBarrier2<'_>],
+        buffer_memory_barriers: &[BufferMemoryBarrier2<'_>],
+        image_memory_barriers: &[ImageMemoryBarrier2<'_>],
+    ) {
+        self.log_command(command_buffer, || {
+            let (src_stage, dst_stage) = memory_barriers
+                .iter()
+                .map(|barrier| (barrier.src_stage_mask, barrier.dst_stage_mask))
+                .chain(
+                    buffer_memory_barriers
+                        .iter()
+                        .map(|barrier| (barrier.src_stage_mask, barrier.dst_stage_mask)),
+                )
+                .chain(
+                    image_memory_barriers
+                        .iter()
+                        .map(|barrier| (barrier.src_stage_mask, barrier.dst_stage_mask)),
+                )
+                .fold(
+                    (PipelineStageFlags2::NONE, PipelineStageFlags2::NONE),
+                    |(src, dst), (barrier_src, barrier_dst)| (src | barrier_src, dst | barrier_dst),
+                );
+            RecordedCommand::Barrier {
+                src_stage: format!("{src_stage:?}"),
+                dst_stage: format!("{dst_stage:?}"),
+                memory_barriers: memory_barriers.len(),
+                buffer_barriers: buffer_memory_barriers.len(),
+                image_barriers: image_memory_barriers.len(),
+            }
+        });
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                command_buffer,
+                &DependencyInfo::default()
+                    .memory_barriers(memory_barriers)
+                    .buffer_memory_barriers(buffer_memory_barriers)
+                    .image_memory_barriers(image_memory_barriers),
+            )
+        }
+    }
+
+    /// # Safety
+    /// `command_buffer` is recording outside a render pass, both buffers live until it completed
+    /// and the regions lie within them.
+    pub unsafe fn cmd_copy_buffer(
+        &self,
+        command_buffer: CommandBuffer,
+        src_buffer: Buffer,
+        dst_buffer: Buffer,
+        regions: &[BufferCopy],
+    ) {
+        self.log_copy(command_buffer, "buffer", regions.len());
+        unsafe {
+            self.device
+                .cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, regions)
+        }
+    }
+
+    /// # Safety
+    /// As for `cmd_copy_buffer`, with `dst_image` in `dst_image_layout`.
+    pub unsafe fn cmd_copy_buffer_to_image(
+        &self,
+        command_buffer: CommandBuffer,
+        src_buffer: Buffer,
+        dst_image: Image,
+        dst_image_layout: ImageLayout,
+        regions: &[BufferImageCopy],
+    ) {
+        self.log_copy(command_buffer, "buffer_to_image", regions.len());
+        unsafe {
+            self.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                src_buffer,
+                dst_image,
+                dst_image_layout,
+                regions,
+            )
+        }
+    }
+
+    /// # Safety
+    /// As for `cmd_copy_buffer`, with `src_image` in `src_image_layout`.
+    pub unsafe fn cmd_copy_image_to_buffer(
+        &self,
+        command_buffer: CommandBuffer,
+        src_image: Image,
+        src_image_layout: ImageLayout,
+        dst_buffer: Buffer,
+        regions: &[BufferImageCopy],
+    ) {
+        self.log_copy(command_buffer, "image_to_buffer", regions.len());
+        unsafe {
+            self.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                src_image,
+                src_image_layout,
+                dst_buffer,
+                regions,
+            )
+        }
+    }
+
+    /// # Safety
+    /// As for `cmd_copy_buffer`, with both images in their given layouts and `filter` supported
+    /// by their formats.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn cmd_blit_image(
+        &self,
+        command_buffer: CommandBuffer,
+        src_image: Image,
+        src_image_layout: ImageLayout,
+        dst_image: Image,
+        dst_image_layout: ImageLayout,
+        regions: &[ImageBlit],
+        filter: Filter,
+    ) {
+        self.log_copy(command_buffer, "blit", regions.len());
+        unsafe {
+            self.device.cmd_blit_image(
+                command_buffer,
+                src_image,
+                src_image_layout,
+                dst_image,
+                dst_image_layout,
+                regions,
+                filter,
+            )
+        }
+    }
+
+    /// # Safety
+    /// As for `cmd_copy_buffer`, with `offset` and `size` multiples of 4 within `buffer`.
+    pub unsafe fn cmd_fill_buffer(
+        &self,
+        command_buffer: CommandBuffer,
+        buffer: Buffer,
+        offset: DeviceSize,
+        size: DeviceSize,
+        data: u32,
+    ) {
+        self.log_copy(command_buffer, "fill", 1);
+        unsafe {
+            self.device
+                .cmd_fill_buffer(command_buffer, buffer, offset, size, data)
+        }
+    }
+
+    fn log_copy(&self, command_buffer: CommandBuffer, kind: &str, regions: usize) {
+        self.log_command(command_buffer, || RecordedCommand::Copy {
+            kind: kind.to_owned(),
+            regions,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandLog, RecordedCommand};
+
+    fn log() -> CommandLog {
+        CommandLog {
+            commands: vec![
+                RecordedCommand::BeginRenderPass {
+                    render_pass: 1,
+                    framebuffer: 2,
+                    width: 800,
+                    height: 600,
+                },
+                RecordedCommand::BindPipeline {
+                    bind_point: "GRAPHICS".to_owned(),
+                    pipeline: 3,
+                },
+                RecordedCommand::DrawIndexed {
+                    index_count: 36,
+                    instance_count: 1,
+                },
+                RecordedCommand::Draw {
+                    vertex_count: 3,
+                    instance_count: 1,
+                },
+                RecordedCommand::EndRenderPass,
+                RecordedCommand::Dispatch {
+                    group_count: [50, 38, 1],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn counts_commands_by_kind() {
+        let log = log();
+        assert_eq!(log.draw_count(), 2);
+        assert_eq!(log.pipeline_bind_count(), 1);
+        assert_eq!(log.render_pass_count(), 1);
+        assert_eq!(log.dispatch_count(), 1);
+        assert_eq!(log.barrier_count(), 0);
+    }
+
+    #[test]
+    fn json_tags_every_command() {
+        let json: serde_json::Value = serde_json::from_str(&log().to_json().unwrap()).unwrap();
+        let commands = json["commands"].as_array().unwrap();
+        assert_eq!(commands.len(), 6);
+        assert_eq!(commands[0]["command"], "begin_render_pass");
+        assert_eq!(commands[0]["width"], 800);
+        assert_eq!(commands[2]["command"], "draw_indexed");
+        assert_eq!(commands[2]["index_count"], 36);
+        assert_eq!(commands[4]["command"], "end_render_pass");
+        assert_eq!(commands[5]["group_count"][0], 50);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::{debug, error};
+use vk_mem::{AllocatorCreateFlags, AllocatorCreateInfo};
+
+use super::{
+    command_buffers::VkCommandPool,
+    device::VkDevice,
+    instance::{Validation, VkInstance},
+    memory_allocator::MemoryAllocator,
+    queue::{QueueType, VkQueue},
+};
+
+/// The Vulkan objects every component is built from, without a window or surface. Lets
+/// components like `MemoryAllocator`, `DescriptorAllocator` or `VkPipeline` be created and
+/// tested on their own instead of through `Renderer::init`.
+pub struct VkContext {
+    pub memory_allocator: Arc<MemoryAllocator>,
+    pub command_pool: VkCommandPool,
+    pub graphics_queue: Arc<VkQueue>,
+    /// Same queue as `graphics_queue` on devices without a dedicated transfer family.
+    pub transfer_queue: Arc<VkQueue>,
+    pub device: Arc<VkDevice>,
+    pub instance: Arc<VkInstance>,
+}
+
+impl VkContext {
+    /// Creates the instance without surface extensions and picks a device that does not need
+    /// to support presentation. Validation is on in debug builds.
+    pub fn new_headless() -> Result<Self> {
+        let instance = Arc::new(VkInstance::new(None, Validation::default())?);
+        let device = Arc::new(VkDevice::new(instance.clone(), None)?);
+        let graphics_queue = Arc::new(VkQueue::new(
+            device.clone(),
+            None,
+            QueueType::GRAPHICS_QUEUE,
+        )?);
+        let transfer_queue = Arc::new(VkQueue::new(
+            device.clone(),
+            None,
+            QueueType::TRANSFER_QUEUE,
+        )?);
+        let mut alloc_info = AllocatorCreateInfo::new(&instance, &device, device.physical_device);
+        alloc_info.flags = AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS;
+        let memory_allocator = Arc::new(MemoryAllocator::new(
+            device.clone(),
+            &[graphics_queue.clone()],
+            alloc_info,
+        ));
+        let command_pool = VkCommandPool::new(graphics_queue.clone());
+        Ok(Self {
+            memory_allocator,
+            command_pool,
+            graphics_queue,
+            transfer_queue,
+            device,
+            instance,
+        })
+    }
+}
+
+/// Everything created from the context has to be destroyed before it is dropped, the
+/// allocator, device and instance go once their last owner let go.
+impl Drop for VkContext {
+    fn drop(&mut self) {
+        if let Err(err) = unsafe { self.device.device_wait_idle() } {
+            error!("Waiting for the device before dropping the context failed: {err}");
+        }
+        unsafe { self.device.destroy_command_pool(*self.command_pool, None) };
+        debug!("VkContext has been destroyed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{BufferUsageFlags, MemoryPropertyFlags};
+    use vk_mem::MemoryUsage;
+
+    use super::VkContext;
+    use crate::components::instance::vulkan_available;
+
+    #[test]
+    fn reads_back_uploaded_buffer() {
+        if !vulkan_available() {
+            return;
+        }
+        let context = VkContext::new_headless().unwrap();
+        let data: Vec<u32> = (0..64).collect();
+        let buffer = context
+            .memory_allocator
+            .create_buffer_with_mapped_memory(
+                &data,
+                &[context.graphics_queue.clone()],
+                BufferUsageFlags::TRANSFER_SRC | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                MemoryUsage::AutoPreferDevice,
+                MemoryPropertyFlags::DEVICE_LOCAL,
+                &context.command_pool,
+            )
+            .unwrap();
+        let bytes = context
+            .memory_allocator
+            .read_buffer(&buffer, 16, 16, &context.command_pool)
+            .unwrap();
+        assert_eq!(bytes, [4u32, 5, 6, 7].map(u32::to_le_bytes).concat());
+    }
+}
@@ -0,0 +1,92 @@
+use std::ffi::CString;
+
+use ash::{
+    ext::debug_utils,
+    vk::{CommandBuffer, DebugUtilsLabelEXT, DebugUtilsObjectNameInfoEXT, Handle},
+    Device, Instance,
+};
+use log::warn;
+
+/// Names Vulkan objects and labels command buffer regions through VK_EXT_debug_utils, so
+/// RenderDoc captures and validation messages show what an object or pass is. Every call is a
+/// no-op when the instance was created without the extension.
+pub struct DebugUtils {
+    device: Option<debug_utils::Device>,
+}
+
+impl DebugUtils {
+    pub fn new(instance: &Instance, device: &Device, enabled: bool) -> Self {
+        Self {
+            device: enabled.then(|| debug_utils::Device::new(instance, device)),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.device.is_some()
+    }
+
+    /// Shows `name` for `handle` in validation messages and debuggers. Failures are only
+    /// logged, a missing name must not break rendering.
+    pub fn name<H: Handle>(&self, handle: H, name: &str) {
+        let Some(device) = &self.device else {
+            return;
+        };
+        let name = c_string(name);
+        let name_info = DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+        if let Err(err) = unsafe { device.set_debug_utils_object_name(&name_info) } {
+            warn!("Naming {name:?} failed: {err}");
+        }
+    }
+
+    /// Opens a labeled region of `cmd`, closed by the matching `end_label`. Regions can nest.
+    pub fn begin_label(&self, cmd: CommandBuffer, name: &str, color: [f32; 4]) {
+        if let Some(device) = &self.device {
+            let name = c_string(name);
+            unsafe {
+                device.cmd_begin_debug_utils_label(
+                    cmd,
+                    &DebugUtilsLabelEXT::default().label_name(&name).color(color),
+                )
+            };
+        }
+    }
+
+    pub fn end_label(&self, cmd: CommandBuffer) {
+        if let Some(device) = &self.device {
+            unsafe { device.cmd_end_debug_utils_label(cmd) };
+        }
+    }
+
+    /// Records `record` inside a region labeled `name`.
+    pub fn label<R>(
+        &self,
+        cmd: CommandBuffer,
+        name: &str,
+        color: [f32; 4],
+        record: impl FnOnce() -> R,
+    ) -> R {
+        self.begin_label(cmd, name, color);
+        let result = record();
+        self.end_label(cmd);
+        result
+    }
+}
+
+/// Names with interior nul bytes are cut at the first one instead of being dropped.
+fn c_string(name: &str) -> CString {
+    let name = name.split('\0').next().unwrap_or_default();
+    CString::new(name).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::c_string;
+
+    #[test]
+    fn cuts_names_at_nul() {
+        assert_eq!(c_string("mesh\0arena").as_bytes(), b"mesh");
+        assert_eq!(c_string("draw image").as_bytes(), b"draw image");
+    }
+}
@@ -1,18 +1,12 @@
-use std::{cell::RefCell, collections::VecDeque, marker::PhantomData, rc::Rc, sync::Arc};
+use std::{any::Any, cell::RefCell, collections::VecDeque, marker::PhantomData, rc::Rc, sync::Arc};
 
-use ash::vk::{Buffer, CommandPool, Image};
+use ash::vk::CommandPool;
 use log::debug;
-use vk_mem::Allocation;
 
 use super::{
     descriptors::DescriptorAllocator, device::VkDevice, memory_allocator::MemoryAllocator,
 };
 
-pub struct DestroyImageTask {
-    pub image: Image,
-    pub allocation: Allocation,
-}
-
 pub struct DestroyDescriptorPools {
     pub allocator: RefCell<DescriptorAllocator>,
 }
@@ -21,22 +15,10 @@ pub struct DestroyCommandPoolTask {
     pub pool: CommandPool,
 }
 
-pub struct DestroyBufferTask {
-    pub buffer: Buffer,
-    pub allocation: Allocation
-}
-
 pub trait CleanUpTask<'a> {
     fn execute(&mut self, device: Arc<VkDevice>, malloc: Arc<MemoryAllocator>);
 }
 
-impl CleanUpTask<'static> for DestroyImageTask {
-    fn execute(&mut self, _device: Arc<VkDevice>, malloc: Arc<MemoryAllocator>) {
-        unsafe { malloc.destroy_image(self.image, &mut self.allocation) };
-        debug!("Image ({:?}) has been deleted", self.image);
-    }
-}
-
 impl CleanUpTask<'static> for DestroyCommandPoolTask {
     fn execute(&mut self, device: Arc<VkDevice>, malloc: Arc<MemoryAllocator>) {
         unsafe { device.destroy_command_pool(self.pool, None) }
@@ -52,23 +34,31 @@ impl CleanUpTask<'static> for DestroyDescriptorPools {
     }
 }
 
-impl CleanUpTask<'static> for DestroyBufferTask {
-    fn execute(&mut self, device: Arc<VkDevice>, malloc: Arc<MemoryAllocator>) {
-        unsafe { malloc.destroy_buffer(self.buffer, &mut self.allocation) };
-    }
-}
-
 
 pub enum FType {
     DEVICE(Box<dyn FnOnce(Arc<VkDevice>)>),
     MALLOC(Box<dyn FnOnce(Arc<MemoryAllocator>) + 'static>),
     TASK(Box<dyn CleanUpTask<'static>>),
+    /// Keeps owned resources like a `VkBuffer` or an `AllocatedImage` alive until the entry
+    /// runs, for the ones frames in flight may still use after their owner is gone.
+    DROP(Box<dyn Any>),
 }
 
 pub struct DeletionQueue {
     device: Arc<VkDevice>,
     memory_allocator: Arc<MemoryAllocator>,
     queue: VecDeque<FType>,
+    /// Entries with the number of frames that have to complete before they run.
+    deferred: VecDeque<(u64, FType)>,
+}
+
+/// Removes the entries whose frames completed, in the order they were queued.
+fn take_completed<T>(deferred: &mut VecDeque<(u64, T)>, completed_frames: u64) -> Vec<T> {
+    let (completed, pending): (VecDeque<_>, VecDeque<_>) = std::mem::take(deferred)
+        .into_iter()
+        .partition(|(frames, _)| *frames <= completed_frames);
+    *deferred = pending;
+    completed.into_iter().map(|(_, func)| func).collect()
 }
 
 impl DeletionQueue {
@@ -77,6 +67,7 @@ impl DeletionQueue {
             device,
             memory_allocator,
             queue: VecDeque::new(),
+            deferred: VecDeque::new(),
         }
     }
 
@@ -84,16 +75,62 @@ impl DeletionQueue {
         self.queue.push_back(func);
     }
 
+    /// Queues `func` to run once the first `frames` frames completed, for resources that frames
+    /// still in flight may use. Runs on `collect`, or on `flush` at the latest.
+    pub fn enqueue_after(&mut self, frames: u64, func: FType) {
+        self.deferred.push_back((frames, func));
+    }
+
+    /// Moves everything queued in `queue` over like `enqueue_after`.
+    pub fn defer_queue(&mut self, frames: u64, mut queue: DeletionQueue) {
+        self.deferred.extend(queue.queue.drain(..).map(|func| (frames, func)));
+        self.deferred.append(&mut queue.deferred);
+    }
+
+    /// Runs the deferred entries whose frames are among the first `completed_frames`, which the
+    /// caller knows the GPU finished because it waited on their fences.
+    pub fn collect(&mut self, completed_frames: u64) {
+        for func in take_completed(&mut self.deferred, completed_frames) {
+            self.execute(func);
+        }
+    }
+
+    /// Runs every entry, deferred ones included, the device must not use any of them anymore.
     pub fn flush(&mut self) {
-        for i in self.queue.drain(..) {
-            match i {
-                FType::DEVICE(fn_once) => fn_once(self.device.clone()),
-                FType::MALLOC(fn_once) => fn_once(self.memory_allocator.clone()),
-                FType::TASK(mut fn_once) => {
-                    fn_once.execute(self.device.clone(), self.memory_allocator.clone())
-                }
+        let queued: Vec<_> = self.queue.drain(..).collect();
+        let deferred: Vec<_> = self.deferred.drain(..).map(|(_, func)| func).collect();
+        for func in queued.into_iter().chain(deferred) {
+            self.execute(func);
+        }
+    }
+
+    fn execute(&self, func: FType) {
+        match func {
+            FType::DEVICE(fn_once) => fn_once(self.device.clone()),
+            FType::MALLOC(fn_once) => fn_once(self.memory_allocator.clone()),
+            FType::TASK(mut fn_once) => {
+                fn_once.execute(self.device.clone(), self.memory_allocator.clone())
             }
+            FType::DROP(resource) => drop(resource),
         }
-        self.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_only_completed_frames() {
+        let mut deferred = VecDeque::from([(1, "a"), (3, "b"), (2, "c")]);
+        assert_eq!(take_completed(&mut deferred, 2), vec!["a", "c"]);
+        assert_eq!(deferred, VecDeque::from([(3, "b")]));
+    }
+
+    #[test]
+    fn nothing_completed_before_the_first_frame() {
+        let mut deferred = VecDeque::from([(1, "a")]);
+        assert!(take_completed(&mut deferred, 0).is_empty());
+        assert_eq!(deferred.len(), 1);
     }
 }
@@ -1,13 +1,15 @@
 use std::{
+    collections::HashMap,
     io::Error,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use anyhow::Result;
 use ash::{
+    prelude::VkResult,
     vk::{
-        DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool, DescriptorPoolCreateFlags,
+        self, DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool, DescriptorPoolCreateFlags,
         DescriptorPoolCreateInfo, DescriptorPoolResetFlags, DescriptorPoolSize, DescriptorSet,
         DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding,
         DescriptorSetLayoutCreateFlags, DescriptorSetLayoutCreateInfo, DescriptorType, ImageLayout,
@@ -20,6 +22,9 @@ use log::debug;
 use super::{allocation_types::VkBuffer, device::VkDevice, sampler::VkSampler};
 
 
+/// `layout` is shared with every other user of the same bindings when the set came from
+/// `write_image_descriptors` or `write_buffer_descriptors`, it belongs to the layout cache
+/// of the allocator and must not be destroyed.
 #[derive(Debug, Clone, Default)]
 pub struct DescriptorSetDetails {
     descriptor_set: Vec<DescriptorSet>,
@@ -99,13 +104,13 @@ impl DescriptorWriter {
     pub fn write_buffer(
         &mut self,
         binding: u32,
-        buffer: VkBuffer,
+        buffer: &VkBuffer,
         size: u64,
         offset: u64,
         d_type: DescriptorType,
     ) {
         let descriptor_buffer_info = DescriptorBufferInfo::default()
-            .buffer(*buffer)
+            .buffer(**buffer)
             .offset(offset)
             .range(size);
         self.buffer_infos.push(descriptor_buffer_info);
@@ -117,6 +122,17 @@ impl DescriptorWriter {
         })
     }
 
+    /// A standalone sampler for shaders that combine it with separately bound sampled images.
+    pub fn write_sampler(&mut self, binding: u32, sampler: VkSampler) {
+        self.write_image(
+            binding,
+            ImageView::null(),
+            Some(sampler),
+            ImageLayout::UNDEFINED,
+            DescriptorType::SAMPLER,
+        );
+    }
+
     pub fn update_set(&mut self, device: Arc<VkDevice>, set: DescriptorSet) {
         let mut writes: Vec<WriteDescriptorSet> = vec![];
         for image_write in &self.pending_image_writes {
@@ -155,11 +171,207 @@ impl DescriptorWriter {
 pub struct DescriptorAllocator {
     device: Arc<VkDevice>,
     ratios: Vec<PoolSizeRatio>,
-    pub full_pools: Vec<DescriptorPool>,
-    pub ready_pools: Vec<DescriptorPool>,
+    pools: DescriptorPools,
+    recycled: RecycledSets,
+    layout_cache: DescriptorLayoutCache,
+}
+
+/// Upper bound for the sets of a single pool, pools grow by half of their size each time a new
+/// one is needed until they reach it.
+const MAX_SETS_PER_POOL: u32 = 4092;
+
+/// Pool bookkeeping of `DescriptorAllocator`. Pools with space left are in `ready`, the ones an
+/// allocation failed in are in `full` until the next reset.
+#[derive(Debug, Clone, Default)]
+struct DescriptorPools {
+    ready: Vec<DescriptorPool>,
+    full: Vec<DescriptorPool>,
     sets_per_pool: u32,
 }
 
+impl DescriptorPools {
+    fn get_pool(
+        &mut self,
+        create_pool: &mut impl FnMut(u32) -> Result<DescriptorPool>,
+    ) -> Result<DescriptorPool> {
+        if let Some(pool) = self.ready.pop() {
+            return Ok(pool);
+        }
+        let pool = create_pool(self.sets_per_pool)?;
+        self.sets_per_pool = ((self.sets_per_pool as f32 * 1.5) as u32).min(MAX_SETS_PER_POOL);
+        Ok(pool)
+    }
+
+    fn return_pool(&mut self, pool: DescriptorPool) {
+        self.ready.push(pool);
+    }
+
+    /// Tries the last ready pool first and moves on to a fresh one if it is exhausted, the pool
+    /// that served the allocation stays ready for the next one.
+    fn allocate<T>(
+        &mut self,
+        mut create_pool: impl FnMut(u32) -> Result<DescriptorPool>,
+        mut allocate: impl FnMut(DescriptorPool) -> VkResult<T>,
+    ) -> Result<T> {
+        let pool = self.get_pool(&mut create_pool)?;
+        let pool = match allocate(pool) {
+            Ok(sets) => {
+                self.return_pool(pool);
+                return Ok(sets);
+            }
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.full.push(pool);
+                self.get_pool(&mut create_pool)?
+            }
+            Err(err) => {
+                self.return_pool(pool);
+                return Err(err.into());
+            }
+        };
+        let sets = allocate(pool);
+        self.return_pool(pool);
+        Ok(sets?)
+    }
+
+    /// Every pool is ready again afterwards, none of them is destroyed.
+    fn reset(&mut self, mut reset_pool: impl FnMut(DescriptorPool) -> VkResult<()>) -> Result<()> {
+        for pool in self.ready.iter().chain(&self.full) {
+            reset_pool(*pool)?;
+        }
+        self.ready.append(&mut self.full);
+        Ok(())
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = DescriptorPool> + '_ {
+        self.ready.drain(..).chain(self.full.drain(..))
+    }
+}
+
+/// Sets handed back with `DescriptorAllocator::recycle`, by their layout. The pools are created
+/// without `FREE_DESCRIPTOR_SET`, so sets are reused instead of freed.
+#[derive(Debug, Clone, Default)]
+struct RecycledSets(HashMap<DescriptorSetLayout, Vec<DescriptorSet>>);
+
+impl RecycledSets {
+    fn put(&mut self, layout: DescriptorSetLayout, set: DescriptorSet) {
+        self.0.entry(layout).or_default().push(set);
+    }
+
+    /// One set for each of `layouts`, only if there are enough recycled for all of them.
+    fn take(&mut self, layouts: &[DescriptorSetLayout]) -> Option<Vec<DescriptorSet>> {
+        let mut needed: HashMap<DescriptorSetLayout, usize> = HashMap::new();
+        for layout in layouts {
+            *needed.entry(*layout).or_default() += 1;
+        }
+        if needed
+            .iter()
+            .any(|(layout, count)| self.0.get(layout).map_or(0, Vec::len) < *count)
+        {
+            return None;
+        }
+        Some(
+            layouts
+                .iter()
+                .filter_map(|layout| self.0.get_mut(layout)?.pop())
+                .collect(),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Everything `DescriptorLayoutBuilder` puts into a layout, immutable samplers are never set
+/// by it and not part of the key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DescriptorLayoutKey {
+    bindings: Vec<(u32, DescriptorType, u32, ShaderStageFlags)>,
+    flags: DescriptorSetLayoutCreateFlags,
+}
+
+impl DescriptorLayoutKey {
+    fn new(bindings: &[DescriptorSetLayoutBinding], flags: DescriptorSetLayoutCreateFlags) -> Self {
+        let mut bindings = bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.descriptor_count,
+                    binding.stage_flags,
+                )
+            })
+            .collect::<Vec<_>>();
+        // the order bindings were added in doesn't change the layout
+        bindings.sort_by_key(|binding| binding.0);
+        Self { bindings, flags }
+    }
+}
+
+/// Destroys the cached layouts once the last `DescriptorLayoutCache` sharing them is dropped.
+struct LayoutCacheOwner {
+    device: Arc<VkDevice>,
+    layouts: Mutex<HashMap<DescriptorLayoutKey, DescriptorSetLayout>>,
+}
+
+impl Drop for LayoutCacheOwner {
+    fn drop(&mut self) {
+        let layouts = self.layouts.get_mut().unwrap_or_else(|err| err.into_inner());
+        for (_, layout) in layouts.drain() {
+            unsafe { self.device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+}
+
+/// Creates every distinct descriptor set layout once, clones share the layouts. Whoever uses a
+/// layout from the cache has to keep a clone alive for as long as it is needed.
+#[derive(Clone)]
+pub struct DescriptorLayoutCache {
+    owner: Arc<LayoutCacheOwner>,
+}
+
+impl DescriptorLayoutCache {
+    pub fn new(device: Arc<VkDevice>) -> Self {
+        Self {
+            owner: Arc::new(LayoutCacheOwner {
+                device,
+                layouts: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn get(
+        &self,
+        bindings: &[DescriptorSetLayoutBinding],
+        flags: DescriptorSetLayoutCreateFlags,
+    ) -> DescriptorSetLayout {
+        let mut layouts = self.owner.layouts.lock().unwrap();
+        *layouts
+            .entry(DescriptorLayoutKey::new(bindings, flags))
+            .or_insert_with(|| {
+                let create_info = DescriptorSetLayoutCreateInfo::default()
+                    .bindings(bindings)
+                    .flags(flags);
+                unsafe {
+                    self.owner
+                        .device
+                        .create_descriptor_set_layout(&create_info, None)
+                        .unwrap()
+                }
+            })
+    }
+
+    /// Number of distinct layouts created so far.
+    pub fn len(&self) -> usize {
+        self.owner.layouts.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct PoolSizeRatio {
     descriptor_type: DescriptorType,
@@ -177,6 +389,49 @@ impl PoolSizeRatio {
             ratio,
         }
     }
+
+    /// Room for every descriptor type the write helpers support, for allocators whose sets
+    /// aren't known up front like the ones of compute passes.
+    pub fn defaults() -> Vec<PoolSizeRatio> {
+        vec![
+            Self::new(DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+            Self::new(DescriptorType::SAMPLED_IMAGE, 4.0),
+            Self::new(DescriptorType::SAMPLER, 1.0),
+            Self::new(DescriptorType::STORAGE_IMAGE, 2.0),
+            Self::new(DescriptorType::UNIFORM_BUFFER, 2.0),
+            Self::new(DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+            Self::new(DescriptorType::STORAGE_BUFFER, 2.0),
+        ]
+    }
+}
+
+/// Descriptor types written through `DescriptorImageInfo`.
+pub fn is_image_descriptor(descriptor_type: DescriptorType) -> bool {
+    matches!(
+        descriptor_type,
+        DescriptorType::COMBINED_IMAGE_SAMPLER
+            | DescriptorType::SAMPLED_IMAGE
+            | DescriptorType::STORAGE_IMAGE
+            | DescriptorType::SAMPLER
+    )
+}
+
+/// Descriptor types written through `DescriptorBufferInfo`.
+pub fn is_buffer_descriptor(descriptor_type: DescriptorType) -> bool {
+    matches!(
+        descriptor_type,
+        DescriptorType::UNIFORM_BUFFER
+            | DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            | DescriptorType::STORAGE_BUFFER
+            | DescriptorType::STORAGE_BUFFER_DYNAMIC
+    )
+}
+
+fn needs_sampler(descriptor_type: DescriptorType) -> bool {
+    matches!(
+        descriptor_type,
+        DescriptorType::COMBINED_IMAGE_SAMPLER | DescriptorType::SAMPLER
+    )
 }
 
 impl DescriptorAllocator {
@@ -185,31 +440,23 @@ impl DescriptorAllocator {
         max_sets: u32,
         pool_sizes: Vec<PoolSizeRatio>,
     ) -> DescriptorAllocator {
-        let mut ready_pools = vec![];
-        let full_pools = vec![];
         let pool = Self::create_pool(&device, max_sets, &pool_sizes).unwrap();
-        ready_pools.push(pool);
         Self {
             device: device.clone(),
             ratios: pool_sizes,
-            full_pools,
-            ready_pools,
-            sets_per_pool: (max_sets as f32 * 1.5) as u32,
+            pools: DescriptorPools {
+                ready: vec![pool],
+                full: vec![],
+                sets_per_pool: ((max_sets as f32 * 1.5) as u32).min(MAX_SETS_PER_POOL),
+            },
+            recycled: RecycledSets::default(),
+            layout_cache: DescriptorLayoutCache::new(device),
         }
     }
 
-    fn get_pool(&mut self) -> Result<DescriptorPool> {
-        let mut new_pool: Option<DescriptorPool> = None;
-        if !self.ready_pools.is_empty() {
-            new_pool = Some(self.ready_pools.pop().unwrap());
-        } else {
-            new_pool = Some(Self::create_pool(
-                &self.device,
-                self.sets_per_pool,
-                &self.ratios,
-            )?)
-        }
-        Ok(new_pool.unwrap())
+    /// Shared by every clone of the allocator.
+    pub fn layout_cache(&self) -> &DescriptorLayoutCache {
+        &self.layout_cache
     }
 
     fn create_pool(
@@ -222,7 +469,7 @@ impl DescriptorAllocator {
             descriptor_pool_sizes.push(
                 DescriptorPoolSize::default()
                     .ty(pool_size.descriptor_type)
-                    .descriptor_count(pool_size.ratio as u32 * set_count),
+                    .descriptor_count((pool_size.ratio * set_count as f32).ceil() as u32),
             );
         }
         let create_info = DescriptorPoolCreateInfo::default()
@@ -230,9 +477,11 @@ impl DescriptorAllocator {
             .pool_sizes(&descriptor_pool_sizes)
             .flags(DescriptorPoolCreateFlags::empty());
 
-        unsafe { Ok(device.create_descriptor_pool(&create_info, None).unwrap()) }
+        unsafe { Ok(device.create_descriptor_pool(&create_info, None)?) }
     }
 
+    /// Storage images have to be in `ImageLayout::GENERAL`, `sampler` is required for
+    /// combined image samplers and ignored for the other image types.
     pub fn write_image_descriptors(
         &mut self,
         image_view: &ImageView,
@@ -241,11 +490,25 @@ impl DescriptorAllocator {
         descriptor_type: DescriptorType,
         sampler: Option<VkSampler>,
     ) -> Result<DescriptorSetDetails, Error> {
+        if !is_image_descriptor(descriptor_type) {
+            return Err(Error::other(format!(
+                "{descriptor_type:?} is not an image descriptor"
+            )));
+        }
+        if needs_sampler(descriptor_type) && sampler.is_none() {
+            return Err(Error::other(format!("{descriptor_type:?} needs a sampler")));
+        }
+        if descriptor_type == DescriptorType::STORAGE_IMAGE && *image_layout != ImageLayout::GENERAL
+        {
+            return Err(Error::other(format!(
+                "Storage images have to be in the GENERAL layout, not {image_layout:?}"
+            )));
+        }
         let mut writer = DescriptorWriter::new();
         let mut descriptor_layout_builder = DescriptorLayoutBuilder::new();
         descriptor_layout_builder.add_binding(0, descriptor_type, shader_stage);
-        let layout = descriptor_layout_builder.build(
-            self.device.clone(),
+        let layout = descriptor_layout_builder.build_cached(
+            &self.layout_cache,
             shader_stage,
             DescriptorSetLayoutCreateFlags::empty(),
         );
@@ -265,6 +528,8 @@ impl DescriptorAllocator {
         })
     }
 
+    /// For dynamic buffers `size` is the range a single dynamic offset selects, not the size
+    /// of the whole buffer.
     pub fn write_buffer_descriptors(
         &mut self,
         buffer: &VkBuffer,
@@ -272,18 +537,23 @@ impl DescriptorAllocator {
         shader_stage: ShaderStageFlags,
         descriptor_type: DescriptorType,
     ) -> Result<DescriptorSetDetails, Error> {
+        if !is_buffer_descriptor(descriptor_type) {
+            return Err(Error::other(format!(
+                "{descriptor_type:?} is not a buffer descriptor"
+            )));
+        }
         let mut writer = DescriptorWriter::new();
         debug!("{size:?}");
         let mut descriptor_layout_builder = DescriptorLayoutBuilder::new();
         descriptor_layout_builder.add_binding(0, descriptor_type, shader_stage);
-        let layout = descriptor_layout_builder.build(
-            self.device.clone(),
+        let layout = descriptor_layout_builder.build_cached(
+            &self.layout_cache,
             shader_stage,
             DescriptorSetLayoutCreateFlags::empty(),
         );
 
         let descriptor_set = self.allocate(self.device.clone(), &[layout]);
-        writer.write_buffer(0, *buffer, size, 0, descriptor_type);
+        writer.write_buffer(0, buffer, size, 0, descriptor_type);
         writer.update_set(self.device.clone(), descriptor_set[0]);
         Ok(DescriptorSetDetails {
             descriptor_set: descriptor_set.to_vec(),
@@ -291,71 +561,74 @@ impl DescriptorAllocator {
         })
     }
 
-    pub fn reset_descriptors(&mut self, device: Arc<VkDevice>) {
-        unsafe {
-            for pool in &self.ready_pools {
-                device
-                    .reset_descriptor_pool(*pool, DescriptorPoolResetFlags::empty())
-                    .unwrap()
-            }
-
-            for pool in &self.full_pools {
-                device
-                    .reset_descriptor_pool(*pool, DescriptorPoolResetFlags::empty())
-                    .unwrap();
-                self.ready_pools.push(*pool);
-            }
-            self.clear_pools();
-        }
+    pub fn write_sampler_descriptors(
+        &mut self,
+        sampler: VkSampler,
+        shader_stage: ShaderStageFlags,
+    ) -> Result<DescriptorSetDetails, Error> {
+        self.write_image_descriptors(
+            &ImageView::null(),
+            &ImageLayout::UNDEFINED,
+            shader_stage,
+            DescriptorType::SAMPLER,
+            Some(sampler),
+        )
     }
 
-    pub fn clear_pools(&mut self) {
-        self.full_pools.clear();
-        self.ready_pools.clear();
+    /// Frees every set allocated so far, the pools are kept for the next allocations.
+    pub fn reset_descriptors(&mut self, device: Arc<VkDevice>) {
+        self.recycled.clear();
+        self.pools
+            .reset(|pool| unsafe {
+                device.reset_descriptor_pool(pool, DescriptorPoolResetFlags::empty())
+            })
+            .unwrap();
     }
 
     pub fn destroy_pools(&mut self, device: Arc<VkDevice>) {
-        for pool in &self.ready_pools {
-            unsafe { device.destroy_descriptor_pool(*pool, None) }
+        self.recycled.clear();
+        for pool in self.pools.drain() {
+            unsafe { device.destroy_descriptor_pool(pool, None) }
         }
-        self.ready_pools.clear();
-        for pool in &self.full_pools {
-            unsafe { device.destroy_descriptor_pool(*pool, None) }
+    }
+
+    /// Hands the sets of `details` back for later allocations with the same layouts, which
+    /// rewrite them. No frame in flight may use them anymore.
+    pub fn recycle(&mut self, details: DescriptorSetDetails) {
+        for (set, layout) in details.descriptor_set.into_iter().zip(details.layout) {
+            self.recycled.put(layout, set);
         }
-        self.full_pools.clear();
     }
 
+    /// Allocates one set for each of `layouts`, reusing recycled sets when there is one for
+    /// every layout. A new and larger pool is created when the ready ones are exhausted.
     pub fn allocate(
         &mut self,
         device: Arc<VkDevice>,
         layouts: &[DescriptorSetLayout],
     ) -> DescriptorSetDetails {
-        let mut pool_to_use = self.get_pool().unwrap();
-        let mut allocate_info = DescriptorSetAllocateInfo::default()
-            .descriptor_pool(pool_to_use)
-            .set_layouts(layouts);
-        allocate_info.descriptor_set_count = 1;
-
-        let descriptor_sets = match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
-            Ok(sets) => sets,
-            Err(error) => {
-                if error == ash::vk::Result::ERROR_OUT_OF_POOL_MEMORY
-                    || error == ash::vk::Result::ERROR_FRAGMENTED_POOL
-                {
-                    if !self.full_pools.contains(&pool_to_use) {
-                        self.full_pools.push(pool_to_use);
-                    };
-                    pool_to_use = self.get_pool().unwrap();
-                    allocate_info.descriptor_pool = pool_to_use;
-                    unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap() }
-                } else {
-                    panic!("Something else went wrong here");
-                }
-            }
-        };
+        if let Some(descriptor_sets) = self.recycled.take(layouts) {
+            return DescriptorSetDetails {
+                descriptor_set: descriptor_sets,
+                layout: layouts.to_vec(),
+            };
+        }
+        let ratios = &self.ratios;
+        let descriptor_sets = self
+            .pools
+            .allocate(
+                |set_count| Self::create_pool(&device, set_count, ratios),
+                |pool| {
+                    let allocate_info = DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(pool)
+                        .set_layouts(layouts);
+                    unsafe { device.allocate_descriptor_sets(&allocate_info) }
+                },
+            )
+            .unwrap();
         DescriptorSetDetails {
             descriptor_set: descriptor_sets,
-                layout: layouts.to_vec()
+            layout: layouts.to_vec(),
         }
     }
 }
@@ -394,9 +667,7 @@ impl DescriptorLayoutBuilder<'_> {
         shader_stages: ShaderStageFlags,
         flags: DescriptorSetLayoutCreateFlags,
     ) -> DescriptorSetLayout {
-        for binding in &mut self.bindings {
-            binding.stage_flags |= shader_stages
-        }
+        self.add_stages(shader_stages);
 
         let descriptor_set_create_info = DescriptorSetLayoutCreateInfo::default()
             .bindings(&self.bindings)
@@ -408,4 +679,219 @@ impl DescriptorLayoutBuilder<'_> {
                 .unwrap()
         }
     }
+
+    /// Same as `build`, but returns the layout `cache` already holds for these bindings. The
+    /// layout is owned by the cache.
+    pub fn build_cached(
+        &mut self,
+        cache: &DescriptorLayoutCache,
+        shader_stages: ShaderStageFlags,
+        flags: DescriptorSetLayoutCreateFlags,
+    ) -> DescriptorSetLayout {
+        self.add_stages(shader_stages);
+        cache.get(&self.bindings, flags)
+    }
+
+    fn add_stages(&mut self, shader_stages: ShaderStageFlags) {
+        for binding in &mut self.bindings {
+            binding.stage_flags |= shader_stages
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use ash::vk::Handle;
+
+    use super::*;
+
+    /// Stands in for the device, each pool holds as many sets as it was created with.
+    #[derive(Default)]
+    struct FakePools {
+        capacities: HashMap<u64, u32>,
+        created: Vec<u32>,
+    }
+
+    impl FakePools {
+        fn create(&mut self, set_count: u32) -> Result<DescriptorPool> {
+            self.created.push(set_count);
+            let raw = self.created.len() as u64;
+            self.capacities.insert(raw, set_count);
+            Ok(DescriptorPool::from_raw(raw))
+        }
+
+        fn allocate(&mut self, pool: DescriptorPool) -> VkResult<DescriptorPool> {
+            let capacity = self.capacities.get_mut(&pool.as_raw()).unwrap();
+            if *capacity == 0 {
+                return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+            }
+            *capacity -= 1;
+            Ok(pool)
+        }
+    }
+
+    fn allocate(pools: &mut DescriptorPools, fake: &RefCell<FakePools>) -> DescriptorPool {
+        pools
+            .allocate(
+                |set_count| fake.borrow_mut().create(set_count),
+                |pool| fake.borrow_mut().allocate(pool),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn recycled_sets_are_taken_only_for_every_layout() {
+        let (first, second) = (DescriptorSetLayout::from_raw(1), DescriptorSetLayout::from_raw(2));
+        let mut recycled = RecycledSets::default();
+        recycled.put(first, DescriptorSet::from_raw(10));
+        assert_eq!(recycled.take(&[first, second]), None);
+        assert_eq!(recycled.take(&[first, first]), None);
+        recycled.put(second, DescriptorSet::from_raw(20));
+        assert_eq!(
+            recycled.take(&[second, first]),
+            Some(vec![DescriptorSet::from_raw(20), DescriptorSet::from_raw(10)])
+        );
+        assert_eq!(recycled.take(&[first]), None);
+    }
+
+    #[test]
+    fn exhausted_pools_are_replaced_by_larger_ones() {
+        let fake = RefCell::new(FakePools::default());
+        let mut pools = DescriptorPools {
+            sets_per_pool: 2,
+            ..Default::default()
+        };
+        let used = (0..5)
+            .map(|_| allocate(&mut pools, &fake).as_raw())
+            .collect::<Vec<_>>();
+        assert_eq!(used, [1, 1, 2, 2, 2]);
+        assert_eq!(fake.borrow().created, [2, 3]);
+        assert_eq!(pools.full, [DescriptorPool::from_raw(1)]);
+        assert_eq!(pools.ready, [DescriptorPool::from_raw(2)]);
+    }
+
+    #[test]
+    fn pool_growth_is_capped() {
+        let fake = RefCell::new(FakePools::default());
+        let mut pools = DescriptorPools {
+            sets_per_pool: MAX_SETS_PER_POOL - 1,
+            ..Default::default()
+        };
+        allocate(&mut pools, &fake);
+        assert_eq!(pools.sets_per_pool, MAX_SETS_PER_POOL);
+    }
+
+    #[test]
+    fn reset_makes_every_pool_ready_again() {
+        let fake = RefCell::new(FakePools::default());
+        let mut pools = DescriptorPools {
+            sets_per_pool: 1,
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            allocate(&mut pools, &fake);
+        }
+        assert_eq!(pools.full.len(), 2);
+
+        let mut reset = vec![];
+        pools
+            .reset(|pool| {
+                reset.push(pool.as_raw());
+                fake.borrow_mut().capacities.insert(pool.as_raw(), 1);
+                Ok(())
+            })
+            .unwrap();
+        reset.sort();
+        assert_eq!(reset, [1, 2, 3]);
+        assert!(pools.full.is_empty());
+        assert_eq!(pools.ready.len(), 3);
+
+        // the pools are reused instead of creating new ones
+        for _ in 0..3 {
+            allocate(&mut pools, &fake);
+        }
+        assert_eq!(fake.borrow().created.len(), 3);
+        assert_eq!(pools.drain().count(), 3);
+    }
+
+    fn binding(
+        binding: u32,
+        descriptor_type: DescriptorType,
+        stages: ShaderStageFlags,
+    ) -> DescriptorSetLayoutBinding<'static> {
+        DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+            .stage_flags(stages)
+    }
+
+    #[test]
+    fn layout_key_ignores_the_binding_order() {
+        let uniform = binding(0, DescriptorType::UNIFORM_BUFFER, ShaderStageFlags::VERTEX);
+        let image = binding(
+            1,
+            DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ShaderStageFlags::FRAGMENT,
+        );
+        assert_eq!(
+            DescriptorLayoutKey::new(&[uniform, image], DescriptorSetLayoutCreateFlags::empty()),
+            DescriptorLayoutKey::new(&[image, uniform], DescriptorSetLayoutCreateFlags::empty())
+        );
+    }
+
+    #[test]
+    fn layout_key_tells_stages_and_flags_apart() {
+        let vertex = binding(0, DescriptorType::UNIFORM_BUFFER, ShaderStageFlags::VERTEX);
+        let fragment = binding(0, DescriptorType::UNIFORM_BUFFER, ShaderStageFlags::FRAGMENT);
+        let key = DescriptorLayoutKey::new(&[vertex], DescriptorSetLayoutCreateFlags::empty());
+        assert_ne!(
+            key,
+            DescriptorLayoutKey::new(&[fragment], DescriptorSetLayoutCreateFlags::empty())
+        );
+        assert_ne!(
+            key,
+            DescriptorLayoutKey::new(
+                &[vertex],
+                DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL
+            )
+        );
+    }
+
+    #[test]
+    fn default_ratios_cover_every_writable_type() {
+        let defaults = PoolSizeRatio::defaults();
+        for descriptor_type in defaults.iter().map(|ratio| ratio.descriptor_type) {
+            assert!(is_image_descriptor(descriptor_type) || is_buffer_descriptor(descriptor_type));
+        }
+        for descriptor_type in [
+            DescriptorType::STORAGE_BUFFER,
+            DescriptorType::STORAGE_IMAGE,
+            DescriptorType::SAMPLED_IMAGE,
+            DescriptorType::SAMPLER,
+            DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+        ] {
+            assert!(defaults
+                .iter()
+                .any(|ratio| ratio.descriptor_type == descriptor_type));
+        }
+    }
+
+    #[test]
+    fn image_and_buffer_descriptors_are_disjoint() {
+        for descriptor_type in [
+            DescriptorType::COMBINED_IMAGE_SAMPLER,
+            DescriptorType::SAMPLED_IMAGE,
+            DescriptorType::STORAGE_IMAGE,
+            DescriptorType::SAMPLER,
+        ] {
+            assert!(is_image_descriptor(descriptor_type));
+            assert!(!is_buffer_descriptor(descriptor_type));
+        }
+        assert!(is_buffer_descriptor(DescriptorType::UNIFORM_BUFFER_DYNAMIC));
+        assert!(!is_image_descriptor(DescriptorType::STORAGE_BUFFER));
+        assert!(!is_image_descriptor(DescriptorType::INPUT_ATTACHMENT));
+    }
 }
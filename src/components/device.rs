@@ -1,58 +1,98 @@
-use std::{io::Error, ops::Deref, sync::Arc};
+use std::{
+    io::Error,
+    ops::Deref,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
 
 use ash::{
+    khr::push_descriptor,
     vk::{
-        DeviceCreateInfo, DeviceQueueCreateInfo, PhysicalDevice, PhysicalDeviceVulkan12Features,
-        QueueFlags, KHR_PORTABILITY_SUBSET_NAME, KHR_SWAPCHAIN_NAME,
+        DeviceCreateInfo, DeviceQueueCreateInfo, PhysicalDevice, PhysicalDeviceFeatures2,
+        PhysicalDeviceVulkan12Features, PhysicalDeviceVulkan13Features, QueueFlags, SampleCountFlags, KHR_PORTABILITY_SUBSET_NAME,
+        KHR_SWAPCHAIN_NAME, TRUE,
     },
     Device, Instance,
 };
-use log::error;
+use log::{debug, error};
 use winit::window::{Window};
 
 use super::{
-    instance::VkInstance, surface::KHRSurface, swapchain_support_details::SwapchainSupportDetails,
+    command_log::CommandRecorder, debug_utils::DebugUtils, instance::VkInstance,
+    surface::KHRSurface,
+    swapchain_support_details::SwapchainSupportDetails,
 };
 
 #[derive(Default, Clone, Copy)]
 pub struct QueueFamilyIndices {
     pub graphics_q_idx: Option<u32>,
     pub presentation_q_idx: Option<u32>,
+    /// A family with TRANSFER but without GRAPHICS if the device has one, so uploads run
+    /// alongside rendering, the graphics family otherwise.
+    pub transfer_q_idx: Option<u32>,
+    /// A family with COMPUTE but without GRAPHICS if the device has one, so compute passes
+    /// overlap with rendering, the graphics family otherwise.
+    pub compute_q_idx: Option<u32>,
 }
 
 impl QueueFamilyIndices {
+    /// Without a `surface` there is no presentation family.
     pub fn find_queue_family_indices(
         physical_device: PhysicalDevice,
         instance: &Instance,
-        surface: Arc<KHRSurface>,
+        surface: Option<Arc<KHRSurface>>,
     ) -> QueueFamilyIndices {
-        let queue_family_properties =
-            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
-
-        let mut indices = QueueFamilyIndices {
-            graphics_q_idx: None,
-            presentation_q_idx: None,
-        };
-
-        for (idx, property) in queue_family_properties.iter().enumerate() {
-            if property.queue_flags.contains(QueueFlags::GRAPHICS) {
-                indices.graphics_q_idx = Some(idx as u32);
-                let surface_support = unsafe {
+        let queue_flags = unsafe {
+            instance.get_physical_device_queue_family_properties(physical_device)
+        }
+        .iter()
+        .map(|property| property.queue_flags)
+        .collect::<Vec<_>>();
+        let present_support = (0..queue_flags.len() as u32)
+            .map(|idx| {
+                surface.as_ref().is_some_and(|surface| unsafe {
                     surface
                         .instance
-                        .get_physical_device_surface_support(physical_device, idx as u32, **surface)
-                        .unwrap()
-                };
-                if surface_support {
-                    indices.presentation_q_idx = Some(idx as u32);
-                }
-            }
+                        .get_physical_device_surface_support(physical_device, idx, ***surface)
+                        .unwrap_or(false)
+                })
+            })
+            .collect::<Vec<_>>();
+        Self::from_families(&queue_flags, &present_support)
+    }
+
+    /// Prefers a graphics family that can present as well, presenting from another family
+    /// needs a second queue and swapchain images shared between both.
+    fn from_families(queue_flags: &[QueueFlags], present_support: &[bool]) -> QueueFamilyIndices {
+        let families = || (0..queue_flags.len() as u32).zip(queue_flags.iter().zip(present_support));
+        let graphics_q_idx = families()
+            .filter(|(_, (flags, _))| flags.contains(QueueFlags::GRAPHICS))
+            .min_by_key(|(_, (_, presents))| !**presents)
+            .map(|(idx, _)| idx);
+        let presentation_q_idx = graphics_q_idx
+            .filter(|idx| present_support[*idx as usize])
+            .or_else(|| families().find(|(_, (_, presents))| **presents).map(|(idx, _)| idx));
+        let transfer_q_idx = families()
+            .find(|(_, (flags, _))| {
+                flags.contains(QueueFlags::TRANSFER) && !flags.contains(QueueFlags::GRAPHICS)
+            })
+            .map(|(idx, _)| idx)
+            .or(graphics_q_idx);
+        let compute_q_idx = families()
+            .find(|(_, (flags, _))| {
+                flags.contains(QueueFlags::COMPUTE) && !flags.contains(QueueFlags::GRAPHICS)
+            })
+            .map(|(idx, _)| idx)
+            .or(graphics_q_idx);
+        QueueFamilyIndices {
+            graphics_q_idx,
+            presentation_q_idx,
+            transfer_q_idx,
+            compute_q_idx,
         }
-        indices
     }
 
-    pub fn is_complete(&self) -> bool {
-        self.graphics_q_idx.is_some() && self.presentation_q_idx.is_some()
+    pub fn is_complete(&self, presents: bool) -> bool {
+        self.graphics_q_idx.is_some() && (!presents || self.presentation_q_idx.is_some())
     }
 }
 
@@ -60,6 +100,26 @@ pub struct VkDevice {
     pub device: Device,
     pub physical_device: PhysicalDevice,
     pub instance: Instance,
+    /// Set while a `CommandLog` is recorded, see `begin_command_log`.
+    pub(super) command_recorder: Mutex<Option<CommandRecorder>>,
+    /// Whether `command_recorder` holds a recorder, checked without locking it so threads
+    /// recording in parallel don't contend on every command.
+    pub(super) command_logging: AtomicBool,
+    /// Object names and command buffer labels, no-ops without VK_EXT_debug_utils.
+    pub debug_utils: DebugUtils,
+    /// `None` without VK_KHR_push_descriptor.
+    pub push_descriptor: Option<push_descriptor::Device>,
+    /// Keeps the instance alive until the device is destroyed.
+    _vk_instance: Arc<VkInstance>,
+}
+
+/// Runs once the last owner let go, everything created from the device has to be destroyed and
+/// the device idle by then.
+impl Drop for VkDevice {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_device(None) };
+        debug!("Device has been destroyed");
+    }
 }
 
 impl Deref for VkDevice {
@@ -71,53 +131,141 @@ impl Deref for VkDevice {
 }
 
 impl VkDevice {
+    /// Picks a device that can present to `surface`, or any suitable device without the
+    /// swapchain extension when rendering headless.
     pub fn new(
         instance: Arc<VkInstance>,
-        surface: Arc<KHRSurface>,
-        window: &Window,
+        surface: Option<(Arc<KHRSurface>, &Window)>,
     ) -> Result<VkDevice, Error> {
-        let physical_device = Self::pick_physical_device(&instance, &surface, window);
-        let device = match Self::create_device(&instance, physical_device, surface) {
-            Some(device) => device,
-            None => panic!(),
+        let physical_device = Self::pick_physical_device(
+            &instance,
+            surface.as_ref().map(|(surface, window)| (surface, *window)),
+        );
+        let surface = surface.map(|(surface, _)| surface);
+        let Some(physical_device) = physical_device else {
+            return Err(Error::other("No device supports the Vulkan features the renderer needs"));
+        };
+        let Some(device) = Self::create_device(&instance, Some(physical_device), surface) else {
+            return Err(Error::other("Creating the logical device failed"));
         };
+        let push_descriptor = Self::supports_push_descriptor(physical_device, &instance)
+            .then(|| push_descriptor::Device::new(&instance, &device));
         Ok(Self {
-            physical_device: physical_device.unwrap(),
+            physical_device,
+            debug_utils: DebugUtils::new(&instance, &device, instance.debug_utils),
+            push_descriptor,
             device,
             instance: instance.instance.clone(),
+            command_recorder: Mutex::new(None),
+            command_logging: AtomicBool::new(false),
+            _vk_instance: instance,
         })
     }
 
+    /// Bytes of push constants a pipeline layout can hold, at least 128.
+    pub fn max_push_constants_size(&self) -> u32 {
+        unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+                .max_push_constants_size
+        }
+    }
+
+    /// Highest sample count not above `requested` that can be used for both color and depth
+    /// framebuffer attachments.
+    pub fn max_usable_sample_count(&self, requested: SampleCountFlags) -> SampleCountFlags {
+        let limits = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+        };
+        let supported =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        [
+            SampleCountFlags::TYPE_64,
+            SampleCountFlags::TYPE_32,
+            SampleCountFlags::TYPE_16,
+            SampleCountFlags::TYPE_8,
+            SampleCountFlags::TYPE_4,
+            SampleCountFlags::TYPE_2,
+        ]
+        .into_iter()
+        .find(|samples| {
+            samples.as_raw() <= requested.as_raw() && supported.contains(*samples)
+        })
+        .unwrap_or(SampleCountFlags::TYPE_1)
+    }
+
     pub fn create_device(
         instance: &VkInstance,
         physical_device: Option<PhysicalDevice>,
-        surface: Arc<KHRSurface>,
+        surface: Option<Arc<KHRSurface>>,
     ) -> Option<ash::Device> {
         match physical_device {
             Some(physical_device) => {
+                let presents = surface.is_some();
                 let indices = QueueFamilyIndices::find_queue_family_indices(
                     physical_device,
                     instance,
                     surface,
                 );
                 let features = unsafe { instance.get_physical_device_features(physical_device) };
-                let extensions = vec![
-                    KHR_SWAPCHAIN_NAME.as_ptr(),
-                    KHR_PORTABILITY_SUBSET_NAME.as_ptr(),
-                ];
+                let mut extensions = vec![];
+                if presents {
+                    extensions.push(KHR_SWAPCHAIN_NAME.as_ptr());
+                }
+                // has to be enabled when the implementation is not fully conformant, like MoltenVK
+                let portability_subset = KHR_PORTABILITY_SUBSET_NAME.to_str().unwrap();
+                if Self::extension_names(physical_device, instance)
+                    .iter()
+                    .any(|name| name == portability_subset)
+                {
+                    extensions.push(KHR_PORTABILITY_SUBSET_NAME.as_ptr());
+                }
+                // optional, see `RendererConfig::push_descriptors`
+                if Self::supports_push_descriptor(physical_device, instance) {
+                    extensions.push(push_descriptor::NAME.as_ptr());
+                }
 
                 let mut extra_features = PhysicalDeviceVulkan12Features::default()
                     .buffer_device_address(true)
-                    .separate_depth_stencil_layouts(true);
-                let device_queue_create_infos = vec![DeviceQueueCreateInfo::default()
-                    .queue_family_index(indices.graphics_q_idx.unwrap())
-                    .queue_priorities(&[1.0])];
+                    .separate_depth_stencil_layouts(true)
+                    // bindless material textures
+                    .descriptor_indexing(true)
+                    .runtime_descriptor_array(true)
+                    .shader_sampled_image_array_non_uniform_indexing(true)
+                    .descriptor_binding_partially_bound(true)
+                    .descriptor_binding_variable_descriptor_count(true)
+                    .descriptor_binding_sampled_image_update_after_bind(true)
+                    .descriptor_binding_storage_buffer_update_after_bind(true)
+                    // transfer batches of the UploadContext and AsyncCompute
+                    .timeline_semaphore(true);
+                // vkCmdPipelineBarrier2 and vkQueueSubmit2
+                let mut features_13 = PhysicalDeviceVulkan13Features::default().synchronization2(true);
+                let mut queue_family_indices = vec![
+                    indices.graphics_q_idx.unwrap(),
+                    indices.transfer_q_idx.unwrap(),
+                    indices.compute_q_idx.unwrap(),
+                ];
+                queue_family_indices.extend(indices.presentation_q_idx);
+                queue_family_indices.sort_unstable();
+                queue_family_indices.dedup();
+                let device_queue_create_infos = queue_family_indices
+                    .into_iter()
+                    .map(|queue_family_index| {
+                        DeviceQueueCreateInfo::default()
+                            .queue_family_index(queue_family_index)
+                            .queue_priorities(&[1.0])
+                    })
+                    .collect::<Vec<_>>();
                 let device_create_infos = DeviceCreateInfo::default()
                     .enabled_features(&features)
                     .queue_create_infos(&device_queue_create_infos)
                     .enabled_features(&features)
                     .enabled_extension_names(&extensions)
-                    .push_next(&mut extra_features);
+                    .push_next(&mut extra_features)
+                    .push_next(&mut features_13);
                 let device = unsafe {
                     instance
                         .create_device(physical_device, &device_create_infos, None)
@@ -131,16 +279,13 @@ impl VkDevice {
 
     fn pick_physical_device(
         instance: &VkInstance,
-        surface: &Arc<KHRSurface>,
-        window: &Window,
+        surface: Option<(&Arc<KHRSurface>, &Window)>,
     ) -> Option<PhysicalDevice> {
         match unsafe { instance.enumerate_physical_devices() } {
             Ok(devices) => {
                 devices
                     .into_iter()
-                    .filter(|device| {
-                        Self::is_device_suitable(*device, instance, surface.clone(), window)
-                    })
+                    .filter(|device| Self::is_device_suitable(*device, instance, surface))
                     .collect::<Vec<PhysicalDevice>>()
                     .first()
                     .map(|dev| dev.to_owned()) // we want an owned value to return
@@ -152,23 +297,13 @@ impl VkDevice {
         }
     }
 
-    fn check_device_extensions(device: PhysicalDevice, instance: &VkInstance) -> bool {
-        let extensions = vec![KHR_SWAPCHAIN_NAME.to_str().unwrap().to_string()];
-        let p_device_extensions = unsafe {
-            instance
-                .enumerate_device_extension_properties(device)
-                .unwrap()
-                .iter()
-                .map(|extension| {
-                    extension
-                        .extension_name_as_c_str()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_string()
-                })
-                .collect::<Vec<String>>()
+    fn check_device_extensions(device: PhysicalDevice, instance: &VkInstance, presents: bool) -> bool {
+        let extensions = if presents {
+            vec![KHR_SWAPCHAIN_NAME.to_str().unwrap().to_string()]
+        } else {
+            vec![]
         };
+        let p_device_extensions = Self::extension_names(device, instance);
         let mut count = 0;
         for extension in &extensions {
             if p_device_extensions.contains(extension) {
@@ -178,21 +313,125 @@ impl VkDevice {
         extensions.len() == count
     }
 
+    fn extension_names(device: PhysicalDevice, instance: &Instance) -> Vec<String> {
+        unsafe {
+            instance
+                .enumerate_device_extension_properties(device)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|extension| {
+                    Some(extension.extension_name_as_c_str().ok()?.to_str().ok()?.to_string())
+                })
+                .collect()
+        }
+    }
+
+    fn supports_push_descriptor(device: PhysicalDevice, instance: &Instance) -> bool {
+        let name = push_descriptor::NAME.to_str().unwrap();
+        Self::extension_names(device, instance)
+            .iter()
+            .any(|extension| extension == name)
+    }
+
+    /// Whether the descriptor indexing features `BindlessDescriptors` relies on are supported.
+    fn supports_bindless(device: PhysicalDevice, instance: &VkInstance) -> bool {
+        let mut features_12 = PhysicalDeviceVulkan12Features::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut features_12);
+        unsafe { instance.get_physical_device_features2(device, &mut features) };
+        features_12.descriptor_indexing == TRUE
+            && features_12.runtime_descriptor_array == TRUE
+            && features_12.shader_sampled_image_array_non_uniform_indexing == TRUE
+            && features_12.descriptor_binding_partially_bound == TRUE
+            && features_12.descriptor_binding_variable_descriptor_count == TRUE
+            && features_12.descriptor_binding_sampled_image_update_after_bind == TRUE
+            && features_12.descriptor_binding_storage_buffer_update_after_bind == TRUE
+    }
+
+    fn supports_synchronization2(device: PhysicalDevice, instance: &VkInstance) -> bool {
+        let mut features_13 = PhysicalDeviceVulkan13Features::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut features_13);
+        unsafe { instance.get_physical_device_features2(device, &mut features) };
+        features_13.synchronization2 == TRUE
+    }
+
     fn is_device_suitable(
         device: PhysicalDevice,
         instance: &VkInstance,
-        surface: Arc<KHRSurface>,
-        window: &Window,
+        surface: Option<(&Arc<KHRSurface>, &Window)>,
     ) -> bool {
-        let queue_family_indices =
-            QueueFamilyIndices::find_queue_family_indices(device, instance, surface.clone());
+        let presents = surface.is_some();
+        let queue_family_indices = QueueFamilyIndices::find_queue_family_indices(
+            device,
+            instance,
+            surface.map(|(surface, _)| surface.clone()),
+        );
         let _features = unsafe { instance.get_physical_device_features(device) };
         let _properties = unsafe { instance.get_physical_device_properties(device) };
-        let swapchain_support_details =
+        let swapchain_adequate = surface.is_none_or(|(surface, window)| {
             SwapchainSupportDetails::get_swapchain_support_details(device, surface.clone(), window)
-                .unwrap();
-        queue_family_indices.is_complete()
-            && Self::check_device_extensions(device, instance)
-            && swapchain_support_details.is_swapchain_adequate()
+                .is_ok_and(|details| details.is_swapchain_adequate())
+        });
+        queue_family_indices.is_complete(presents)
+            && Self::check_device_extensions(device, instance, presents)
+            && Self::supports_bindless(device, instance)
+            && Self::supports_synchronization2(device, instance)
+            && swapchain_adequate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::QueueFlags;
+
+    use super::QueueFamilyIndices;
+
+    const GRAPHICS: QueueFlags = QueueFlags::from_raw(
+        QueueFlags::GRAPHICS.as_raw() | QueueFlags::COMPUTE.as_raw() | QueueFlags::TRANSFER.as_raw(),
+    );
+
+    #[test]
+    fn prefers_a_graphics_family_that_presents() {
+        let indices = QueueFamilyIndices::from_families(
+            &[GRAPHICS, QueueFlags::TRANSFER, GRAPHICS],
+            &[false, false, true],
+        );
+        assert_eq!(indices.graphics_q_idx, Some(2));
+        assert_eq!(indices.presentation_q_idx, Some(2));
+        assert_eq!(indices.transfer_q_idx, Some(1));
+        assert_eq!(indices.compute_q_idx, Some(2));
+    }
+
+    #[test]
+    fn picks_a_dedicated_compute_family() {
+        let compute = QueueFlags::from_raw(
+            QueueFlags::COMPUTE.as_raw() | QueueFlags::TRANSFER.as_raw(),
+        );
+        let indices = QueueFamilyIndices::from_families(
+            &[GRAPHICS, compute, QueueFlags::TRANSFER],
+            &[true, false, false],
+        );
+        assert_eq!(indices.graphics_q_idx, Some(0));
+        assert_eq!(indices.compute_q_idx, Some(1));
+        assert_eq!(indices.transfer_q_idx, Some(1));
+    }
+
+    #[test]
+    fn presents_from_another_family() {
+        let indices = QueueFamilyIndices::from_families(
+            &[GRAPHICS, QueueFlags::COMPUTE],
+            &[false, true],
+        );
+        assert_eq!(indices.graphics_q_idx, Some(0));
+        assert_eq!(indices.presentation_q_idx, Some(1));
+        assert_eq!(indices.transfer_q_idx, Some(0));
+        assert!(indices.is_complete(true));
+    }
+
+    #[test]
+    fn headless_needs_no_presentation() {
+        let indices = QueueFamilyIndices::from_families(&[GRAPHICS], &[false]);
+        assert_eq!(indices.presentation_q_idx, None);
+        assert!(indices.is_complete(false));
+        assert!(!indices.is_complete(true));
     }
 }
@@ -0,0 +1,31 @@
+use std::path::Path;
+
+macro_rules! embed_shader {
+    ($file_name:literal) => {
+        (
+            $file_name,
+            include_bytes!(concat!(env!("OUT_DIR"), "/", $file_name)).as_slice(),
+        )
+    };
+}
+
+/// SPIR-V compiled by `build.rs`, keyed by the file name the shader has in `shaders/`.
+const EMBEDDED_SHADERS: [(&str, &[u8]); 7] = [
+    embed_shader!("2D_vertex_shader.spv"),
+    embed_shader!("2D_fragment_shader.spv"),
+    embed_shader!("2D_texture_fragment_shader.spv"),
+    embed_shader!("debug_line.vert.spv"),
+    embed_shader!("debug_line.frag.spv"),
+    embed_shader!("scene_data_mesh.vert.spv"),
+    embed_shader!("error_material.frag.spv"),
+];
+
+/// Built in copy of the shader at `file_path`, matched by file name. `None` if the shader is
+/// not embedded.
+pub fn embedded_shader(file_path: &str) -> Option<&'static [u8]> {
+    let file_name = Path::new(file_path).file_name()?.to_str()?;
+    EMBEDDED_SHADERS
+        .iter()
+        .find(|(name, _)| *name == file_name)
+        .map(|(_, code)| *code)
+}
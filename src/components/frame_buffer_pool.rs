@@ -0,0 +1,137 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{BufferUsageFlags, MemoryPropertyFlags};
+use log::debug;
+use vk_mem::MemoryUsage;
+
+use crate::renderer::MAX_FRAMES;
+
+use super::{
+    allocation_types::VkBuffer,
+    deletion_queue::{CleanUpTask, DeletionQueue, FType},
+    device::VkDevice,
+    memory_allocator::MemoryAllocator,
+    queue::VkQueue,
+};
+
+struct MappedBuffer<T> {
+    buffer: VkBuffer,
+    mapped: *mut T,
+    capacity: usize,
+}
+
+/// Persistently mapped, host visible buffers for geometry that is rebuilt from time to time
+/// and drawn every frame in between (egui). There is one buffer per frame in flight, every
+/// rebuild writes into the next one so the GPU never reads a buffer that is being written.
+/// A buffer is only reallocated when a rebuild does not fit into it anymore.
+pub struct FrameBufferPool<T: Copy> {
+    memory_allocator: Arc<MemoryAllocator>,
+    queues: Vec<Arc<VkQueue>>,
+    usage: BufferUsageFlags,
+    buffers: Arc<Mutex<Vec<MappedBuffer<T>>>>,
+    current: usize,
+    len: usize,
+}
+
+pub struct DestroyFrameBufferPoolTask<T> {
+    buffers: Arc<Mutex<Vec<MappedBuffer<T>>>>,
+}
+
+impl<T> CleanUpTask<'static> for DestroyFrameBufferPoolTask<T> {
+    fn execute(&mut self, _device: Arc<VkDevice>, _malloc: Arc<MemoryAllocator>) {
+        self.buffers.lock().unwrap().clear();
+        debug!("FrameBufferPool buffers have been deleted");
+    }
+}
+
+impl<T: Copy + 'static> FrameBufferPool<T> {
+    pub fn new(
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        capacity: usize,
+        usage: BufferUsageFlags,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let buffers = (0..MAX_FRAMES)
+            .map(|_| allocate_mapped(&memory_allocator, queues, capacity, usage))
+            .collect::<Result<Vec<_>>>()?;
+        let buffers = Arc::new(Mutex::new(buffers));
+        deletion_queue.enqueue(FType::TASK(Box::new(DestroyFrameBufferPoolTask {
+            buffers: buffers.clone(),
+        })));
+        Ok(Self {
+            memory_allocator,
+            queues: queues.to_vec(),
+            usage,
+            buffers,
+            current: 0,
+            len: 0,
+        })
+    }
+
+    /// Buffer holding the geometry of the last rebuild.
+    pub fn buffer(&self) -> VkBuffer {
+        self.buffers.lock().unwrap()[self.current].buffer.clone()
+    }
+
+    /// Starts a rebuild in the next buffer, which was last drawn from `MAX_FRAMES` rebuilds
+    /// ago and is therefore no longer read by any frame in flight.
+    pub fn begin_rebuild(&mut self) {
+        self.current = (self.current + 1) % MAX_FRAMES;
+        self.len = 0;
+    }
+
+    /// Appends `elements` to the current rebuild and returns the element offset they were
+    /// written at. Grows the buffer, keeping what was already written, if they don't fit.
+    pub fn write(&mut self, elements: &[T]) -> Result<u32> {
+        let mut buffers = self.buffers.lock().unwrap();
+        let required = self.len + elements.len();
+        if required > buffers[self.current].capacity {
+            let capacity = required.max(buffers[self.current].capacity * 2);
+            let grown =
+                allocate_mapped(&self.memory_allocator, &self.queues, capacity, self.usage)?;
+            let old = std::mem::replace(&mut buffers[self.current], grown);
+            unsafe {
+                std::ptr::copy_nonoverlapping(old.mapped, buffers[self.current].mapped, self.len);
+            }
+            // only the rebuild in progress used the old buffer, nothing was recorded yet
+            drop(old);
+            debug!("FrameBufferPool buffer grown to {capacity} elements");
+        }
+        let offset = self.len;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                elements.as_ptr(),
+                buffers[self.current].mapped.add(offset),
+                elements.len(),
+            );
+        }
+        self.len = required;
+        Ok(offset as u32)
+    }
+}
+
+fn allocate_mapped<T>(
+    memory_allocator: &MemoryAllocator,
+    queues: &[Arc<VkQueue>],
+    capacity: usize,
+    usage: BufferUsageFlags,
+) -> Result<MappedBuffer<T>> {
+    let buffer = memory_allocator.allocate_single_buffer(
+        (size_of::<T>() * capacity.max(1)) as u64,
+        queues,
+        usage,
+        MemoryUsage::Unknown,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    let mapped = buffer.mapped_data() as *mut T;
+    if mapped.is_null() {
+        return Err(anyhow!("Frame buffer pool memory is not host mapped"));
+    }
+    Ok(MappedBuffer {
+        buffer,
+        mapped,
+        capacity: capacity.max(1),
+    })
+}
@@ -1,16 +1,27 @@
 use std::{cell::RefCell, sync::Arc};
 
+use anyhow::{anyhow, Result};
 use ash::vk::{
-    CommandBuffer, CommandBufferAllocateInfo, CommandBufferLevel, CommandPool, DescriptorType,
-    Fence, FenceCreateFlags, FenceCreateInfo, Semaphore, SemaphoreCreateFlags, SemaphoreCreateInfo,
+    BufferUsageFlags, CommandBuffer, CommandBufferAllocateInfo, CommandBufferLevel, CommandPool,
+    DescriptorBufferInfo, DescriptorSet, DescriptorSetLayout, DescriptorType, Fence,
+    FenceCreateFlags, FenceCreateInfo, MemoryPropertyFlags, PipelineBindPoint, PipelineLayout,
+    SemaphoreCreateFlags, SemaphoreCreateInfo, WriteDescriptorSet,
 };
+use vk_mem::MemoryUsage;
 
 use super::{
     command_buffers::VkCommandPool,
-    deletion_queue::{DeletionQueue, DestroyCommandPoolTask, DestroyDescriptorPools, FType},
+    deletion_queue::{
+        DeletionQueue, DestroyCommandPoolTask, DestroyDescriptorPools, FType,
+    },
     descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
     device::VkDevice,
     memory_allocator::MemoryAllocator, queue::VkQueue,
+    secondary_commands::SecondaryCommands,
+};
+use crate::{
+    geom::scene::SceneData,
+    misc::{jobs::JobSystem, views::MAX_VIEWS},
 };
 
 pub struct FrameResources {
@@ -19,18 +30,101 @@ pub struct FrameResources {
     pub descriptor_writer: DescriptorWriter,
     pub per_frame_deletion_queue: DeletionQueue,
     pub main_deletion_queue: DeletionQueue,
+    /// Scene draws recorded in parallel, reset once the frame's previous submission completed.
+    pub secondary_commands: SecondaryCommands,
+    /// Persistently mapped uniform buffer holding a `SceneData` slot per view of this frame.
+    scene_data_mapped: *mut u8,
+    /// Bytes between the slots, the uniform buffer offset alignment rounded up.
+    scene_data_stride: u64,
+    /// Set 0 of the mesh pipelines for each view, points at its scene data slot for the whole
+    /// lifetime.
+    scene_data: [SceneDataBinding; MAX_VIEWS],
+}
+
+/// How set 0 of the mesh pipelines reaches the scene data buffer of a frame.
+#[derive(Debug, Clone, Copy)]
+pub enum SceneDataBinding {
+    /// A set allocated once that is bound with the other sets.
+    Set(DescriptorSet),
+    /// Pushed with VK_KHR_push_descriptor instead, the pipelines have to be created with a set 0
+    /// layout that has `DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR`.
+    Push(DescriptorBufferInfo),
+}
+
+impl SceneDataBinding {
+    /// Binds the scene data as set 0 of `layout` and `sets` from set 1 on.
+    pub fn bind(
+        &self,
+        device: &VkDevice,
+        cmd: CommandBuffer,
+        layout: PipelineLayout,
+        sets: &[DescriptorSet],
+    ) {
+        unsafe {
+            match self {
+                SceneDataBinding::Set(scene_data_set) => {
+                    let sets = [&[*scene_data_set], sets].concat();
+                    device.cmd_bind_descriptor_sets(
+                        cmd,
+                        PipelineBindPoint::GRAPHICS,
+                        layout,
+                        0,
+                        &sets,
+                        &[],
+                    );
+                }
+                SceneDataBinding::Push(buffer_info) => {
+                    let write = WriteDescriptorSet::default()
+                        .dst_binding(0)
+                        .descriptor_type(DescriptorType::UNIFORM_BUFFER)
+                        .buffer_info(std::slice::from_ref(buffer_info));
+                    device
+                        .push_descriptor
+                        .as_ref()
+                        .expect("Pushing descriptors needs VK_KHR_push_descriptor")
+                        .cmd_push_descriptor_set(
+                            cmd,
+                            PipelineBindPoint::GRAPHICS,
+                            layout,
+                            0,
+                            &[write],
+                        );
+                    if !sets.is_empty() {
+                        device.cmd_bind_descriptor_sets(
+                            cmd,
+                            PipelineBindPoint::GRAPHICS,
+                            layout,
+                            1,
+                            sets,
+                            &[],
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub struct FrameData {
     pub command_buffer: CommandBuffer,
     pub egui_command_buffer: CommandBuffer,
-    pub render_semaphore: Vec<Semaphore>,
-    pub swapchain_semaphore: Vec<Semaphore>,
     pub render_fence: Vec<Fence>,
     pub frame_resources: FrameResources
 }
 
 impl FrameResources {
+    /// Copies `scene_data` into the uniform buffer slot of view `view_idx` of this frame and
+    /// returns how it is bound. The frame's previous submission must have completed.
+    pub fn write_scene_data(&mut self, view_idx: usize, scene_data: &SceneData) -> SceneDataBinding {
+        unsafe {
+            let slot = self
+                .scene_data_mapped
+                .add(view_idx * self.scene_data_stride as usize);
+            std::ptr::copy_nonoverlapping(scene_data, slot.cast::<SceneData>(), 1);
+        }
+        self.scene_data[view_idx]
+    }
+
     pub fn enqueue_destroy_pools(&mut self) {
       self.per_frame_deletion_queue.enqueue(FType::TASK(Box::new(DestroyDescriptorPools {
              allocator: self.descriptor_allocator.clone()
@@ -39,12 +133,84 @@ impl FrameResources {
 }
 
 impl FrameData {
+    /// Destroys everything the frame owns, its submissions must have completed.
+    pub fn destroy(&mut self, device: Arc<VkDevice>) {
+        self.frame_resources.per_frame_deletion_queue.flush();
+        self.frame_resources.main_deletion_queue.flush();
+        self.frame_resources
+            .descriptor_allocator
+            .borrow_mut()
+            .destroy_pools(device.clone());
+        unsafe {
+            for fence in self.render_fence.drain(..) {
+                device.destroy_fence(fence, None);
+            }
+        }
+    }
+
+    /// The scene data sets of the frame are allocated from `descriptor_allocator`, which must never
+    /// be reset while the frame is alive. With `push_scene_data` nothing is allocated, the
+    /// buffer is pushed whenever the set is bound.
     pub fn new(
         device: Arc<VkDevice>,
         memory_allocator: Arc<MemoryAllocator>,
-        queue: Arc<VkQueue>
-    ) -> Self {
+        queue: Arc<VkQueue>,
+        descriptor_allocator: &mut DescriptorAllocator,
+        scene_data_layout: DescriptorSetLayout,
+        push_scene_data: bool,
+    ) -> Result<Self> {
         let mut main_deletion_queue = DeletionQueue::new(device.clone(), memory_allocator.clone());
+        let scene_data_size = size_of::<SceneData>() as u64;
+        let alignment = unsafe {
+            device
+                .instance
+                .get_physical_device_properties(device.physical_device)
+                .limits
+                .min_uniform_buffer_offset_alignment
+        };
+        let scene_data_stride = scene_data_size.next_multiple_of(alignment.max(1));
+        let scene_data = memory_allocator.allocate_single_buffer(
+            scene_data_stride * MAX_VIEWS as u64,
+            &[queue.clone()],
+            BufferUsageFlags::UNIFORM_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryUsage::Auto,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let scene_data_mapped = scene_data.mapped_data();
+        main_deletion_queue.enqueue(FType::DROP(Box::new(scene_data.clone())));
+        if scene_data_mapped.is_null() {
+            return Err(anyhow!("Scene data buffer is not host mapped"));
+        }
+        let scene_data = std::array::from_fn(|view_idx| {
+            let offset = view_idx as u64 * scene_data_stride;
+            if push_scene_data {
+                SceneDataBinding::Push(
+                    DescriptorBufferInfo::default()
+                        .buffer(*scene_data)
+                        .offset(offset)
+                        .range(scene_data_size),
+                )
+            } else {
+                let scene_data_set =
+                    descriptor_allocator.allocate(device.clone(), &[scene_data_layout])[0];
+                let mut writer = DescriptorWriter::new();
+                writer.write_buffer(
+                    0,
+                    &scene_data,
+                    scene_data_size,
+                    offset,
+                    DescriptorType::UNIFORM_BUFFER,
+                );
+                writer.update_set(device.clone(), scene_data_set);
+                SceneDataBinding::Set(scene_data_set)
+            }
+        });
+        let secondary_commands = SecondaryCommands::new(
+            device.clone(),
+            queue.clone(),
+            JobSystem::global().thread_count(),
+            &mut main_deletion_queue,
+        );
         let command_pool = VkCommandPool::new(queue);
         let descriptor_allocator = RefCell::new(DescriptorAllocator::new(
                     device.clone(),
@@ -54,33 +220,31 @@ impl FrameData {
                         1.0,
                     )],
                 ));
-         main_deletion_queue.enqueue(FType::TASK(Box::new(DestroyDescriptorPools {
-             allocator: descriptor_allocator.clone()
+         main_deletion_queue.enqueue(FType::TASK(Box::new(DestroyCommandPoolTask {
+             pool: *command_pool,
          })));
          let per_frame_deletion_queue = DeletionQueue::new(device.clone(), memory_allocator.clone());
         unsafe {
-            Self {
+            Ok(Self {
                 command_buffer: device
                     .allocate_command_buffers(&allocate_command_buffer_info(*command_pool))
                     .unwrap()[0],
                 egui_command_buffer: device
                     .allocate_command_buffers(&allocate_command_buffer_info(*command_pool))
                     .unwrap()[0],
-                render_semaphore: vec![device
-                    .create_semaphore(&create_semaphore_info(), None)
-                    .unwrap()],
-                swapchain_semaphore: vec![device
-                    .create_semaphore(&create_semaphore_info(), None)
-                    .unwrap()],
                 render_fence: vec![device.create_fence(&create_fence_info(), None).unwrap()],
                 frame_resources: FrameResources {
                     descriptor_allocator: descriptor_allocator.clone(),
                     descriptor_layout_builder: DescriptorLayoutBuilder::new(),
                     descriptor_writer: DescriptorWriter::new(),
                     main_deletion_queue,
-                    per_frame_deletion_queue
+                    per_frame_deletion_queue,
+                    secondary_commands,
+                    scene_data_mapped,
+                    scene_data_stride,
+                    scene_data,
                 }
-            }
+            })
         }
     }
 }
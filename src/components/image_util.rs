@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use ash::{
     vk::{
-        AccessFlags, CommandBuffer, DependencyFlags, Extent2D, Extent3D, Filter, Format, Image, ImageAspectFlags, ImageBlit, ImageCreateInfo, ImageLayout, ImageMemoryBarrier, ImageSubresourceLayers, ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags, ImageViewCreateInfo, ImageViewType, Offset3D, PipelineStageFlags, SampleCountFlags, REMAINING_ARRAY_LAYERS, REMAINING_MIP_LEVELS
+        AccessFlags2, CommandBuffer, Extent2D, Offset2D, Rect2D, Viewport, Extent3D, Filter, Format, Image, ImageAspectFlags, ImageBlit, ImageCreateFlags, ImageCreateInfo, ImageLayout, ImageMemoryBarrier2, ImageSubresourceLayers, ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags, ImageViewCreateInfo, ImageViewType, Offset3D, PipelineStageFlags2, SampleCountFlags, REMAINING_ARRAY_LAYERS, REMAINING_MIP_LEVELS
     },
     Device,
 };
@@ -33,6 +33,30 @@ pub fn image_create_info<'a>(
     info
 }
 
+pub fn cube_image_create_info<'a>(
+    format: Format,
+    flags: ImageUsageFlags,
+    extent: Extent3D,
+) -> ImageCreateInfo<'a> {
+    image_create_info(format, flags, extent, None, false)
+        .flags(ImageCreateFlags::CUBE_COMPATIBLE)
+        .array_layers(6)
+}
+
+pub fn cube_image_view_create_info<'a>(
+    image: Image,
+    format: Format,
+    aspect_flags: ImageAspectFlags,
+) -> ImageViewCreateInfo<'a> {
+    image_view_create_info(image, format, aspect_flags)
+        .view_type(ImageViewType::CUBE)
+        .subresource_range(
+            image_subresource_range(aspect_flags)
+                .level_count(1)
+                .layer_count(6),
+        )
+}
+
 pub fn image_view_create_info<'a>(
     image: Image,
     format: Format,
@@ -49,6 +73,95 @@ pub fn image_view_create_info<'a>(
         )
 }
 
+/// Stages using an image in `layout` and the accesses those stages make, writes and reads.
+fn layout_usage(layout: ImageLayout) -> (PipelineStageFlags2, AccessFlags2, AccessFlags2) {
+    match layout {
+        ImageLayout::UNDEFINED | ImageLayout::PREINITIALIZED => (
+            PipelineStageFlags2::NONE,
+            AccessFlags2::empty(),
+            AccessFlags2::empty(),
+        ),
+        ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            PipelineStageFlags2::TRANSFER,
+            AccessFlags2::empty(),
+            AccessFlags2::TRANSFER_READ,
+        ),
+        ImageLayout::TRANSFER_DST_OPTIMAL => (
+            PipelineStageFlags2::TRANSFER,
+            AccessFlags2::TRANSFER_WRITE,
+            AccessFlags2::empty(),
+        ),
+        ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            AccessFlags2::COLOR_ATTACHMENT_READ,
+        ),
+        ImageLayout::DEPTH_ATTACHMENT_OPTIMAL | ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            PipelineStageFlags2::EARLY_FRAGMENT_TESTS | PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ,
+        ),
+        ImageLayout::DEPTH_READ_ONLY_OPTIMAL | ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => (
+            PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | PipelineStageFlags2::LATE_FRAGMENT_TESTS
+                | PipelineStageFlags2::FRAGMENT_SHADER
+                | PipelineStageFlags2::COMPUTE_SHADER,
+            AccessFlags2::empty(),
+            AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags2::SHADER_READ,
+        ),
+        ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            PipelineStageFlags2::VERTEX_SHADER
+                | PipelineStageFlags2::FRAGMENT_SHADER
+                | PipelineStageFlags2::COMPUTE_SHADER,
+            AccessFlags2::empty(),
+            AccessFlags2::SHADER_READ,
+        ),
+        // the presentation engine waits on a semaphore, which makes the writes visible
+        ImageLayout::PRESENT_SRC_KHR => (
+            PipelineStageFlags2::BOTTOM_OF_PIPE,
+            AccessFlags2::empty(),
+            AccessFlags2::empty(),
+        ),
+        // GENERAL and anything else can be used by every stage
+        _ => (
+            PipelineStageFlags2::ALL_COMMANDS,
+            AccessFlags2::MEMORY_WRITE,
+            AccessFlags2::MEMORY_READ,
+        ),
+    }
+}
+
+fn is_depth_layout(layout: ImageLayout) -> bool {
+    matches!(
+        layout,
+        ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+            | ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            | ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+            | ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+    )
+}
+
+/// Stages and access masks of a barrier from `old_layout` to `new_layout`: the writes made in
+/// the old layout have to be available to every access of the new one.
+fn transition_masks(
+    old_layout: ImageLayout,
+    new_layout: ImageLayout,
+) -> (PipelineStageFlags2, AccessFlags2, PipelineStageFlags2, AccessFlags2) {
+    let (src_stage, src_writes, _) = layout_usage(old_layout);
+    let (dst_stage, dst_writes, dst_reads) = layout_usage(new_layout);
+    // nothing to wait for, but the transition has to follow a semaphore wait at the stage the
+    // image is used in next, e.g. of an acquired swapchain image
+    let src_stage = if src_stage == PipelineStageFlags2::NONE {
+        dst_stage
+    } else {
+        src_stage
+    };
+    (src_stage, src_writes, dst_stage, dst_writes | dst_reads)
+}
+
+/// Moves the whole `image` from `current_image_layout` to `new_image_layout`, with stages and
+/// access masks derived from both layouts. Images in a depth layout on either side get the
+/// depth aspect.
 pub fn image_transition(
     device: Arc<VkDevice>,
     command_buffer: CommandBuffer,
@@ -58,16 +171,20 @@ pub fn image_transition(
     new_image_layout: ImageLayout,
 ) {
     let sub_resource_range = image_subresource_range(
-        if new_image_layout.eq(&ImageLayout::DEPTH_ATTACHMENT_OPTIMAL) {
+        if is_depth_layout(current_image_layout) || is_depth_layout(new_image_layout) {
             ImageAspectFlags::DEPTH
         } else {
             ImageAspectFlags::COLOR
         },
     );
+    let (src_stage, src_access, dst_stage, dst_access) =
+        transition_masks(current_image_layout, new_image_layout);
 
-    let image_memory_barrier = ImageMemoryBarrier::default()
-        .src_access_mask(AccessFlags::COLOR_ATTACHMENT_READ)
-        .dst_access_mask(AccessFlags::SHADER_READ)
+    let image_memory_barrier = ImageMemoryBarrier2::default()
+        .src_stage_mask(src_stage)
+        .src_access_mask(src_access)
+        .dst_stage_mask(dst_stage)
+        .dst_access_mask(dst_access)
         .old_layout(current_image_layout)
         .new_layout(new_image_layout)
         .src_queue_family_index(queue_family_idx)
@@ -75,17 +192,25 @@ pub fn image_transition(
         .image(image)
         .subresource_range(sub_resource_range);
 
-    unsafe {
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            PipelineStageFlags::FRAGMENT_SHADER,
-            DependencyFlags::empty(),
-            &[],
-            &[],
-            &[image_memory_barrier],
-        )
-    };
+    unsafe { device.cmd_pipeline_barrier2(command_buffer, &[], &[], &[image_memory_barrier]) };
+}
+
+/// Bytes per texel of the uncompressed color formats, `None` for anything else.
+pub fn format_texel_size(format: Format) -> Option<u64> {
+    match format {
+        Format::R8_UNORM => Some(1),
+        Format::R8G8B8A8_UNORM
+        | Format::R8G8B8A8_SRGB
+        | Format::B8G8R8A8_UNORM
+        | Format::B8G8R8A8_SRGB
+        | Format::A2B10G10R10_UNORM_PACK32
+        | Format::B10G11R11_UFLOAT_PACK32
+        | Format::R32_SFLOAT
+        | Format::R32_UINT => Some(4),
+        Format::R16G16B16A16_SFLOAT | Format::R32G32_SFLOAT => Some(8),
+        Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
 }
 
 pub fn image_subresource_range(aspect_flag: ImageAspectFlags) -> ImageSubresourceRange {
@@ -105,35 +230,109 @@ pub fn image_subresource_layers(aspect_flag: ImageAspectFlags) -> ImageSubresour
         .aspect_mask(aspect_flag)
 }
 
+/// How the final image is placed on a swapchain image of another aspect ratio.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PresentationPolicy {
+    /// Covers the whole swapchain image and distorts the image.
+    #[default]
+    Stretch,
+    /// Shows the whole image with its aspect ratio, bars fill the rest of the swapchain image.
+    Fit,
+    /// Covers the whole swapchain image with the aspect ratio kept, the image is cropped.
+    Fill,
+}
+
+impl PresentationPolicy {
+    /// Source and destination rectangles of the blit of a `src` sized image onto a `dst` sized
+    /// one, both centered.
+    pub fn blit_regions(self, src: Extent2D, dst: Extent2D) -> (Rect2D, Rect2D) {
+        let full = |extent: Extent2D| Rect2D::default().extent(extent);
+        // src is wider than dst if src.width / src.height > dst.width / dst.height
+        let src_wider =
+            u64::from(src.width) * u64::from(dst.height) > u64::from(dst.width) * u64::from(src.height);
+        let scaled = |size: u32, numerator: u32, denominator: u32| {
+            ((u64::from(size) * u64::from(numerator) + u64::from(denominator) / 2)
+                / u64::from(denominator).max(1)) as u32
+        };
+        match self {
+            Self::Stretch => (full(src), full(dst)),
+            Self::Fit => {
+                let inner = if src_wider {
+                    Extent2D::default()
+                        .width(dst.width)
+                        .height(scaled(dst.width, src.height, src.width))
+                } else {
+                    Extent2D::default()
+                        .width(scaled(dst.height, src.width, src.height))
+                        .height(dst.height)
+                };
+                (full(src), centered(inner, dst))
+            }
+            Self::Fill => {
+                let inner = if src_wider {
+                    Extent2D::default()
+                        .width(scaled(src.height, dst.width, dst.height))
+                        .height(src.height)
+                } else {
+                    Extent2D::default()
+                        .width(src.width)
+                        .height(scaled(src.width, dst.height, dst.width))
+                };
+                (centered(inner, src), full(dst))
+            }
+        }
+    }
+
+    /// Where the whole `src` image ends up in the coordinates of the `dst` image, reaching
+    /// past its edges with `Fill`. Maps window positions into the drawn image.
+    pub fn image_viewport(self, src: Extent2D, dst: Extent2D) -> Viewport {
+        let (src_region, dst_region) = self.blit_regions(src, dst);
+        let scale_x = dst_region.extent.width as f32 / src_region.extent.width.max(1) as f32;
+        let scale_y = dst_region.extent.height as f32 / src_region.extent.height.max(1) as f32;
+        Viewport::default()
+            .x(dst_region.offset.x as f32 - src_region.offset.x as f32 * scale_x)
+            .y(dst_region.offset.y as f32 - src_region.offset.y as f32 * scale_y)
+            .width(src.width as f32 * scale_x)
+            .height(src.height as f32 * scale_y)
+            .min_depth(0.0)
+            .max_depth(1.0)
+    }
+}
+
+/// `inner` placed in the middle of `outer`.
+fn centered(inner: Extent2D, outer: Extent2D) -> Rect2D {
+    Rect2D::default()
+        .offset(
+            Offset2D::default()
+                .x(((outer.width - inner.width) / 2) as i32)
+                .y(((outer.height - inner.height) / 2) as i32),
+        )
+        .extent(inner)
+}
+
+/// Blits `src_region` of `src_image` onto `dst_region` of `dst_image`, scaled and mirrored on
+/// both axes.
 pub fn copy_image_to_image(
     device: &Device,
     command_buffer: CommandBuffer,
     src_image: Image,
     dst_image: Image,
-    src_extent: Extent2D,
-    dst_extent: Extent2D,
+    src_region: Rect2D,
+    dst_region: Rect2D,
 ) {
-    let src_offset_3d = [
-        Offset3D::default()
-            .x(src_extent.width as i32)
-            .y(src_extent.height as i32)
-            .z(0),
-        Offset3D::default()
-            .x(0)
-            .y(0)
-            .z(1),
-    ];
-
-    let dst_offset_3d = [
-           Offset3D::default()
-            .x(0)
-            .y(0)
-            .z(0),
-        Offset3D::default()
-            .x(dst_extent.width as i32)
-            .y(dst_extent.height as i32)
-            .z(1),
-    ];
+    let corners = |region: Rect2D| {
+        (
+            Offset3D::default().x(region.offset.x).y(region.offset.y),
+            Offset3D::default()
+                .x(region.offset.x + region.extent.width as i32)
+                .y(region.offset.y + region.extent.height as i32),
+        )
+    };
+    let (src_min, src_max) = corners(src_region);
+    let src_offset_3d = [src_max, src_min.z(1)];
+
+    let (dst_min, dst_max) = corners(dst_region);
+    let dst_offset_3d = [dst_min, dst_max.z(1)];
 
     let src_image_subresource_layers = ImageSubresourceLayers::default()
         .aspect_mask(ImageAspectFlags::COLOR)
@@ -165,3 +364,69 @@ pub fn copy_image_to_image(
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{AccessFlags2, Extent2D, ImageLayout, Offset2D, PipelineStageFlags2, Rect2D};
+
+    use super::{transition_masks, PresentationPolicy};
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rect2D {
+        Rect2D::default()
+            .offset(Offset2D::default().x(x).y(y))
+            .extent(Extent2D::default().width(width).height(height))
+    }
+
+    #[test]
+    fn upload_transitions_wait_for_the_copy() {
+        let (src_stage, src_access, dst_stage, dst_access) = transition_masks(
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        assert_eq!(src_stage, PipelineStageFlags2::TRANSFER);
+        assert_eq!(src_access, AccessFlags2::TRANSFER_WRITE);
+        assert!(dst_stage.contains(PipelineStageFlags2::FRAGMENT_SHADER));
+        assert_eq!(dst_access, AccessFlags2::SHADER_READ);
+    }
+
+    #[test]
+    fn undefined_layouts_need_no_src_access() {
+        let (src_stage, src_access, dst_stage, dst_access) =
+            transition_masks(ImageLayout::UNDEFINED, ImageLayout::TRANSFER_DST_OPTIMAL);
+        // chains with a semaphore wait at the transfer stage
+        assert_eq!(src_stage, PipelineStageFlags2::TRANSFER);
+        assert!(src_access.is_empty());
+        assert_eq!(dst_stage, PipelineStageFlags2::TRANSFER);
+        assert_eq!(dst_access, AccessFlags2::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn fit_letterboxes_and_fill_crops_a_wider_image() {
+        let src = Extent2D::default().width(1600).height(900);
+        let dst = Extent2D::default().width(1000).height(1000);
+        assert_eq!(
+            PresentationPolicy::Fit.blit_regions(src, dst),
+            (rect(0, 0, 1600, 900), rect(0, 218, 1000, 563))
+        );
+        assert_eq!(
+            PresentationPolicy::Fill.blit_regions(src, dst),
+            (rect(350, 0, 900, 900), rect(0, 0, 1000, 1000))
+        );
+        assert_eq!(
+            PresentationPolicy::Stretch.blit_regions(src, dst),
+            (rect(0, 0, 1600, 900), rect(0, 0, 1000, 1000))
+        );
+    }
+
+    #[test]
+    fn cropped_images_reach_past_the_window() {
+        let src = Extent2D::default().width(400).height(100);
+        let dst = Extent2D::default().width(100).height(100);
+        let viewport = PresentationPolicy::Fill.image_viewport(src, dst);
+        assert_eq!((viewport.x, viewport.y), (-150.0, 0.0));
+        assert_eq!((viewport.width, viewport.height), (400.0, 100.0));
+        let viewport = PresentationPolicy::Fit.image_viewport(src, dst);
+        assert_eq!((viewport.x, viewport.y), (0.0, 37.0));
+        assert_eq!((viewport.width, viewport.height), (100.0, 25.0));
+    }
+}
@@ -1,8 +1,8 @@
 use std::{
     env,
-    ffi::{c_void, CStr},
+    ffi::{c_char, c_void, CStr},
     io::Error,
-    ops::{Add, Deref},
+    ops::Deref,
     sync::Arc,
 };
 
@@ -16,18 +16,129 @@ use ash::{
 use log::{debug, info, warn};
 use winit::{raw_window_handle::HasDisplayHandle, window::Window};
 
-#[allow(warnings)]
+const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+/// Which validation messages are reported, through the debug messenger and the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Neither the validation layer nor the debug messenger are enabled.
+    Off,
+    /// Only errors are reported.
+    ErrorsOnly,
+    /// Verbose, info, warning and error messages.
+    Full,
+}
+
+/// `Full` in debug builds, `Off` in release builds.
+impl Default for Validation {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Validation::Full
+        } else {
+            Validation::Off
+        }
+    }
+}
+
+impl Validation {
+    pub fn message_severity(self) -> DebugUtilsMessageSeverityFlagsEXT {
+        match self {
+            Validation::Off => DebugUtilsMessageSeverityFlagsEXT::empty(),
+            Validation::ErrorsOnly => DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            Validation::Full => {
+                DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | DebugUtilsMessageSeverityFlagsEXT::INFO
+                    | DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                    | DebugUtilsMessageSeverityFlagsEXT::ERROR
+            }
+        }
+    }
+
+    fn debug_create_info<'a>(self) -> DebugUtilsMessengerCreateInfoEXT<'a> {
+        DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(self.message_severity())
+            .message_type(
+                DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(VkInstance::debug_callback))
+    }
+}
+
+/// Points at a Vulkan loader (or MoltenVK) to use instead of the one found on the library path.
+pub const VK_LOADER_PATH: &str = "VK_LOADER_PATH";
+
+/// Loads the library `VK_LOADER_PATH` points to, the system loader otherwise. On macOS the
+/// Vulkan SDK is tried as well, it is not on the library path unless its setup script ran.
 pub fn load_vulkan_library() -> Result<Entry, LoadingError> {
+    if let Some(path) = env::var_os(VK_LOADER_PATH) {
+        return unsafe { Entry::load_from(path) };
+    }
+    let system_loader = unsafe { Entry::load() };
     #[cfg(target_os = "macos")]
-    let entry_path = env::home_dir().unwrap().to_str().unwrap().to_owned()
-        + "/VulkanSDK/1.4.313.1/macOS/lib/libvulkan.dylib";
-    Ok(unsafe { Entry::load_from(entry_path)? })
+    if system_loader.is_err()
+        && let Some(entry) = vulkan_sdk_loaders()
+            .into_iter()
+            .find_map(|path| unsafe { Entry::load_from(path) }.ok())
+    {
+        return Ok(entry);
+    }
+    system_loader
+}
+
+/// The loader of the SDK `VULKAN_SDK` points to, then the ones installed to ~/VulkanSDK with
+/// the newest version first.
+#[cfg(target_os = "macos")]
+fn vulkan_sdk_loaders() -> Vec<std::path::PathBuf> {
+    let mut loaders = vec![];
+    if let Some(sdk) = env::var_os("VULKAN_SDK") {
+        loaders.push(std::path::PathBuf::from(sdk).join("lib/libvulkan.dylib"));
+    }
+    if let Some(home) = env::home_dir() {
+        let mut versions = std::fs::read_dir(home.join("VulkanSDK"))
+            .map(|dir| dir.filter_map(|entry| Some(entry.ok()?.path())).collect::<Vec<_>>())
+            .unwrap_or_default();
+        // the directories are named after the SDK version, e.g. 1.4.313.1
+        versions.sort_by_cached_key(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            std::cmp::Reverse(
+                name.split('.')
+                    .map(|part| part.parse::<u32>().unwrap_or(0))
+                    .collect::<Vec<_>>(),
+            )
+        });
+        loaders.extend(
+            versions
+                .into_iter()
+                .map(|version| version.join("macOS/lib/libvulkan.dylib")),
+        );
+    }
+    loaders
+}
+
+/// Whether a Vulkan loader with at least one device is present, so tests and CI jobs can skip
+/// GPU work on machines without one.
+pub fn vulkan_available() -> bool {
+    VkInstance::new(None, Validation::Off).is_ok_and(|instance| {
+        unsafe { instance.enumerate_physical_devices() }.is_ok_and(|devices| !devices.is_empty())
+    })
 }
 
-#[derive(Clone)]
 pub struct VkInstance {
     pub entry: Entry,
     pub instance: Instance,
+    /// Whether VK_EXT_debug_utils was enabled, `create_debugger` needs it.
+    pub debug_utils: bool,
+    /// Severities the debug messenger reports.
+    pub validation: Validation,
+}
+
+impl Drop for VkInstance {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy_instance(None) };
+        debug!("Instance has been destroyed");
+    }
 }
 
 
@@ -40,14 +151,20 @@ impl Deref for VkInstance {
 }
 
 impl VkInstance {
-    pub fn new(window: &Window) -> Result<VkInstance, Error> {
-        let entry = load_vulkan_library().unwrap();
+    /// `None` leaves out the surface extensions, for rendering without a window. A missing
+    /// validation layer only disables the validation, debug messages of the driver are still
+    /// reported if VK_EXT_debug_utils is there.
+    pub fn new(window: Option<&Window>, validation: Validation) -> Result<VkInstance, Error> {
+        let entry = load_vulkan_library().map_err(Error::other)?;
         let application_info = Self::app_create_info(c"PULPIP", c"PIPLUP");
-        let mut required_extensions =
-            ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
-                .unwrap()
-                .to_vec();
-        required_extensions.push(ash::khr::portability_enumeration::NAME.as_ptr());
+        let mut required_extensions = match window {
+            Some(window) => ash_window::enumerate_required_extensions(
+                window.display_handle().unwrap().as_raw(),
+            )
+            .map_err(Error::other)?
+            .to_vec(),
+            None => vec![],
+        };
 
         let extension_properties = unsafe {
             entry
@@ -69,24 +186,38 @@ impl VkInstance {
             extension_properties.len()
         );
 
-        let mut debug_create_info = DebugUtilsMessengerCreateInfoEXT::default()
-            .message_severity(
-                DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | DebugUtilsMessageSeverityFlagsEXT::INFO
-                    | DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                    | DebugUtilsMessageSeverityFlagsEXT::ERROR,
-            )
-            .message_type(
-                DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                    | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-            )
-            .pfn_user_callback(Some(Self::debug_callback));
+        // VK_KHR_surface and the window system one, e.g. VK_KHR_win32_surface, VK_KHR_xlib_surface
+        // or VK_KHR_wayland_surface
+        let missing_extensions = required_extensions
+            .iter()
+            .map(|name| unsafe { CStr::from_ptr(*name) }.to_string_lossy())
+            .filter(|name| !extension_properties.iter().any(|available| available == name))
+            .collect::<Vec<_>>();
+        if !missing_extensions.is_empty() {
+            return Err(Error::other(format!(
+                "The Vulkan loader does not support the surface extensions {missing_extensions:?}"
+            )));
+        }
+        // only MoltenVK and other non-conformant implementations need to be enumerated explicitly
+        let portability =
+            Self::supports(&extension_properties, ash::khr::portability_enumeration::NAME);
+        if portability {
+            required_extensions.push(ash::khr::portability_enumeration::NAME.as_ptr());
+        }
+
+        // exposes the wide gamut surface color spaces used by the display transforms
+        if window.is_some()
+            && Self::supports(&extension_properties, ash::ext::swapchain_colorspace::NAME)
+        {
+            required_extensions.push(ash::ext::swapchain_colorspace::NAME.as_ptr());
+        }
+
+        let mut debug_create_info = validation.debug_create_info();
 
-        let validation_layers = unsafe {
+        let layer_properties = unsafe {
             entry
                 .enumerate_instance_layer_properties()
-                .unwrap()
+                .map_err(Error::other)?
                 .iter()
                 .map(|layer| {
                     layer
@@ -99,9 +230,19 @@ impl VkInstance {
                 .collect::<Vec<String>>()
         };
 
-        let enabled_layer_support = Self::check_validation_layers(validation_layers);
-        if enabled_layer_support {
-            debug!("ENABLED LAYER SUPPORT");
+        let mut enabled_layers = vec![];
+        if validation != Validation::Off {
+            if Self::supports(&layer_properties, VALIDATION_LAYER) {
+                enabled_layers.push(VALIDATION_LAYER.as_ptr());
+            } else {
+                warn!("{VALIDATION_LAYER:?} is not installed, the Vulkan usage is not validated");
+            }
+        }
+        // the validation layer implements VK_EXT_debug_utils itself
+        let debug_utils = validation != Validation::Off
+            && (!enabled_layers.is_empty()
+                || Self::supports(&extension_properties, EXT_DEBUG_UTILS_NAME));
+        if debug_utils {
             required_extensions.push(EXT_DEBUG_UTILS_NAME.as_ptr());
         }
 
@@ -111,31 +252,47 @@ impl VkInstance {
                     &Self::instance_create_info(
                         &application_info,
                         &required_extensions,
-                        enabled_layer_support,
+                        &enabled_layers,
+                        debug_utils,
+                        portability,
                         &mut debug_create_info,
                     ),
                     None,
                 )
-                .unwrap()
+                .map_err(Error::other)?
         };
         Ok(Self {
             entry,
             instance,
+            debug_utils,
+            validation,
         })
     }
 
+    fn supports(properties: &[String], name: &CStr) -> bool {
+        properties
+            .iter()
+            .any(|property| property.as_bytes() == name.to_bytes())
+    }
+
     fn instance_create_info<'a>(
         app_info: &'a ApplicationInfo,
-        required_extensions: &'a [*const i8],
-        layers_enabled: bool,
+        required_extensions: &'a [*const c_char],
+        enabled_layers: &'a [*const c_char],
+        debug_utils: bool,
+        portability: bool,
         debug_create_info: &'a mut DebugUtilsMessengerCreateInfoEXT<'a>,
     ) -> InstanceCreateInfo<'a> {
         let mut create_info = InstanceCreateInfo::default()
             .application_info(app_info)
-            .flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR)
-            .enabled_extension_names(required_extensions);
+            .enabled_extension_names(required_extensions)
+            .enabled_layer_names(enabled_layers);
+        if portability {
+            create_info = create_info.flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR);
+        }
         
-        if layers_enabled {
+        // reports messages of instance creation and destruction as well
+        if debug_utils {
             create_info = create_info.push_next(debug_create_info);
         }
 
@@ -149,41 +306,21 @@ impl VkInstance {
             .application_name(app_name)
     }
 
+    /// `None` when the instance was created without VK_EXT_debug_utils, e.g. with
+    /// `Validation::Off`.
     pub fn create_debugger(
         instance: Arc<VkInstance>
-    ) -> (debug_utils::Instance, DebugUtilsMessengerEXT) {
-        let  debug_create_info = DebugUtilsMessengerCreateInfoEXT::default()
-            .message_severity(
-                DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | DebugUtilsMessageSeverityFlagsEXT::INFO
-                    | DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                    | DebugUtilsMessageSeverityFlagsEXT::ERROR,
-            )
-            .message_type(
-                DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | DebugUtilsMessageTypeFlagsEXT::VALIDATION | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-            )
-            .pfn_user_callback(Some(Self::debug_callback)); 
-        let debug_instance = debug_utils::Instance::new(&instance.entry, &instance);
-        let debugger = unsafe {
-            debug_instance
-                .create_debug_utils_messenger(&debug_create_info, None)
-                .unwrap()
-        };
-        (debug_instance, debugger)
-    }
-
-    fn check_validation_layers(validation_layers: Vec<String>) -> bool {
-        let validation_layer_tbc = vec![String::from("VK_LAYER_KHRONOS_validation")];
-        let mut count = 0;
-
-        for layer in validation_layer_tbc.clone() {
-            if validation_layers.contains(&layer) {
-                count = count.add(1);
-            }
+    ) -> Option<(debug_utils::Instance, DebugUtilsMessengerEXT)> {
+        if !instance.debug_utils {
+            return None;
         }
-        debug!("{count:?}, {:?}", validation_layer_tbc.len());
-        count == validation_layer_tbc.len()
+        let debug_create_info = instance.validation.debug_create_info();
+        let debug_instance = debug_utils::Instance::new(&instance.entry, &instance);
+        let debugger =
+            unsafe { debug_instance.create_debug_utils_messenger(&debug_create_info, None) }
+                .inspect_err(|err| warn!("Creating the debug messenger failed: {err}"))
+                .ok()?;
+        Some((debug_instance, debugger))
     }
 
     unsafe extern "system" fn debug_callback(
@@ -225,3 +362,22 @@ impl VkInstance {
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::DebugUtilsMessageSeverityFlagsEXT;
+
+    use super::Validation;
+
+    #[test]
+    fn errors_only_reports_errors() {
+        assert_eq!(
+            Validation::ErrorsOnly.message_severity(),
+            DebugUtilsMessageSeverityFlagsEXT::ERROR
+        );
+        assert!(Validation::Off.message_severity().is_empty());
+        assert!(Validation::Full
+            .message_severity()
+            .contains(DebugUtilsMessageSeverityFlagsEXT::WARNING));
+    }
+}
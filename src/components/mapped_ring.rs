@@ -0,0 +1,163 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{BufferUsageFlags, MemoryPropertyFlags};
+use vk_mem::MemoryUsage;
+
+use super::{
+    allocation_types::VkBuffer,
+    deletion_queue::{DeletionQueue, FType},
+    memory_allocator::MemoryAllocator,
+    queue::VkQueue,
+};
+use crate::renderer::MAX_FRAMES;
+
+/// Persistently mapped, host visible buffer that per-frame geometry (egui, debug lines) is
+/// written into directly. Every frame continues where the previous one stopped and wraps
+/// around once the end is reached, so the data never goes through an intermediate Vec.
+pub struct MappedRing<T: Copy> {
+    buffer: VkBuffer,
+    mapped: *mut T,
+    space: RingSpace,
+    _marker: PhantomData<T>,
+}
+
+/// Where the frames in flight wrote into a ring. Positions count every element handed out
+/// since the ring was created, the skipped ones at the end before a wrap included, so the
+/// space between the oldest frame in flight and the head is never ambiguous.
+#[derive(Debug)]
+struct RingSpace {
+    capacity: usize,
+    head: usize,
+    /// Where the last frame recorded in each frame slot started, `None` until one was.
+    frame_starts: [Option<usize>; MAX_FRAMES],
+    frame_slot: usize,
+}
+
+impl RingSpace {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            head: 0,
+            frame_starts: [None; MAX_FRAMES],
+            frame_slot: 0,
+        }
+    }
+
+    /// The frame slot `frame_slot` was recorded in before has completed, its space is free.
+    fn begin_frame(&mut self, frame_slot: usize) {
+        self.frame_starts[frame_slot] = Some(self.head);
+        self.frame_slot = frame_slot;
+    }
+
+    /// Start of the oldest frame still in flight, the current one included.
+    fn tail(&self) -> usize {
+        (1..=MAX_FRAMES)
+            .filter_map(|step| self.frame_starts[(self.frame_slot + step) % MAX_FRAMES])
+            .next()
+            .unwrap_or(self.head)
+    }
+
+    /// Hands out `len` contiguous elements and returns the offset of the first.
+    fn reserve(&mut self, len: usize) -> Result<usize> {
+        if len > self.capacity {
+            return Err(anyhow!(
+                "{len} elements do not fit into a ring of capacity {}",
+                self.capacity
+            ));
+        }
+        let mut head = self.head;
+        if head % self.capacity + len > self.capacity {
+            head = head.next_multiple_of(self.capacity);
+        }
+        if head + len - self.tail() > self.capacity {
+            return Err(anyhow!("Ring buffer is exhausted by the frames in flight"));
+        }
+        self.head = head + len;
+        Ok(head % self.capacity)
+    }
+}
+
+impl<T: Copy> MappedRing<T> {
+    pub fn new(
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        capacity: usize,
+        usage: BufferUsageFlags,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let buffer = memory_allocator.allocate_single_buffer(
+            (size_of::<T>() * capacity) as u64,
+            queues,
+            usage,
+            MemoryUsage::Unknown,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = buffer.mapped_data() as *mut T;
+        if mapped.is_null() {
+            return Err(anyhow!("Ring buffer memory is not host mapped"));
+        }
+        deletion_queue.enqueue(FType::DROP(Box::new(buffer.clone())));
+        Ok(Self {
+            buffer,
+            mapped,
+            space: RingSpace::new(capacity),
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn buffer(&self) -> &VkBuffer {
+        &self.buffer
+    }
+
+    /// Marks the beginning of a new frame in `frame_slot`, the frame recorded in it before must
+    /// have completed. Has to be called for every frame, written into or not, the space of the
+    /// frames still in flight is not overwritten.
+    pub fn begin_frame(&mut self, frame_slot: usize) {
+        self.space.begin_frame(frame_slot);
+    }
+
+    /// Copies `elements` into the ring and returns the element offset they were written at.
+    pub fn write(&mut self, elements: &[T]) -> Result<u32> {
+        let offset = self.space.reserve(elements.len())?;
+        unsafe {
+            let len = elements.len();
+            std::ptr::copy_nonoverlapping(elements.as_ptr(), self.mapped.add(offset), len);
+        }
+        Ok(offset as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingSpace;
+
+    #[test]
+    fn frames_in_flight_are_not_overwritten() {
+        let mut space = RingSpace::new(8);
+        space.begin_frame(0);
+        assert_eq!(space.reserve(3).unwrap(), 0);
+        space.begin_frame(1);
+        assert_eq!(space.reserve(3).unwrap(), 3);
+        // frame 0 completed, its space is reused after the wrap
+        space.begin_frame(0);
+        assert_eq!(space.reserve(3).unwrap(), 0);
+        // frame 1 is still in flight at 3..6
+        assert!(space.reserve(1).is_err());
+        space.begin_frame(1);
+        assert_eq!(space.reserve(2).unwrap(), 3);
+    }
+
+    #[test]
+    fn a_frame_can_fill_the_whole_ring() {
+        let mut space = RingSpace::new(8);
+        space.begin_frame(0);
+        assert_eq!(space.reserve(8).unwrap(), 0);
+        assert!(space.reserve(1).is_err());
+        assert!(space.reserve(9).is_err());
+        space.begin_frame(1);
+        assert!(space.reserve(1).is_err());
+        space.begin_frame(0);
+        assert_eq!(space.reserve(1).unwrap(), 0);
+    }
+}
@@ -1,11 +1,25 @@
-use std::{any::Any, ffi, fmt::Debug, io::Error, ops::Deref, sync::Arc};
+use std::{
+    fmt::Debug,
+    io::Error,
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use ash::vk::{
-    BufferCreateInfo, BufferDeviceAddressInfo, BufferUsageFlags, Extent3D, Format,
-    ImageAspectFlags, ImageLayout, ImageUsageFlags, MemoryPropertyFlags, Packed24_8, SharingMode,
+    AccessFlags2, BufferCopy, BufferCreateInfo, BufferDeviceAddressInfo, BufferUsageFlags,
+    Extent3D, Format, FormatFeatureFlags, Image, ImageAspectFlags, ImageCreateInfo, ImageLayout,
+    ImageMemoryBarrier2, ImageUsageFlags, ImageView, MemoryBarrier2, MemoryHeapFlags,
+    MemoryPropertyFlags, Offset3D, PipelineStageFlags2, QUEUE_FAMILY_IGNORED, SampleCountFlags,
+    SharingMode,
 };
 use egui::{Color32, ImageData};
+use image::imageops::FilterType;
 use log::debug;
+use serde::Serialize;
 use vk_mem::{
     Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, AllocatorCreateInfo,
     MemoryUsage,
@@ -13,47 +27,157 @@ use vk_mem::{
 
 use super::{
     allocation_types::{AllocatedImage, VkBuffer},
-    command_buffers::{self, VkCommandPool},
+    command_buffers::{ImmediateSubmit, VkCommandPool},
     device::VkDevice,
-    image_util::{image_create_info, image_transition, image_view_create_info},
+    image_util::{
+        cube_image_create_info, cube_image_view_create_info, format_texel_size, image_create_info,
+        image_subresource_range, image_transition, image_view_create_info,
+    },
     queue::VkQueue,
     swapchain::{ImageDetails, KHRSwapchain},
 };
+use crate::misc::jobs::JobSystem;
+#[cfg(feature = "sparse-textures")]
+use super::sparse_image::{SparseImage, SparsePage};
+#[cfg(feature = "sparse-textures")]
+use ash::vk::{
+    BindSparseInfo, DeviceMemory, Extent2D, FenceCreateInfo, ImageCreateFlags, ImageSubresource,
+    QueueFlags, SparseImageMemoryBind, SparseImageMemoryBindInfo, SparseImageOpaqueMemoryBindInfo,
+    SparseMemoryBind, SparseMemoryBindFlags,
+};
 
-#[derive(Debug, Clone, Copy)]
-pub enum AllocationUnitType {
-    Buffer(VkBuffer),
-    Image(AllocatedImage),
+/// Usage of a single memory heap, as reported by the allocator.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HeapStatistics {
+    pub heap_index: u32,
+    pub device_local: bool,
+    /// Size of the heap as reported by the physical device.
+    pub size: u64,
+    /// `VkDeviceMemory` objects allocated from this heap.
+    pub block_count: u32,
+    pub allocation_count: u32,
+    pub block_bytes: u64,
+    pub allocation_bytes: u64,
+    /// Estimated usage of the whole process, including memory not allocated through us.
+    pub usage: u64,
+    /// Estimated amount of memory available to the process.
+    pub budget: u64,
 }
 
-#[derive(Debug)]
-pub struct AllocationUnit {
-    pub unit: AllocationUnitType,
-    pub allocation: Allocation,
+/// Snapshot of the allocator across all heaps, cheap enough to take every frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryStatistics {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    pub block_bytes: u64,
+    pub allocation_bytes: u64,
+    pub heaps: Vec<HeapStatistics>,
 }
 
-impl AllocationUnitType {
-    pub fn get_cloned<T: Any + Clone>(&self) -> T {
-        match self {
-            AllocationUnitType::Buffer(buffer) => (buffer as &dyn Any).downcast_ref::<T>().cloned(),
-            AllocationUnitType::Image(image) => (image as &dyn Any).downcast_ref::<T>().cloned(),
+impl MemoryStatistics {
+    pub fn from_heaps(heaps: Vec<HeapStatistics>) -> Self {
+        Self {
+            block_count: heaps.iter().map(|heap| heap.block_count).sum(),
+            allocation_count: heaps.iter().map(|heap| heap.allocation_count).sum(),
+            block_bytes: heaps.iter().map(|heap| heap.block_bytes).sum(),
+            allocation_bytes: heaps.iter().map(|heap| heap.allocation_bytes).sum(),
+            heaps,
         }
-        .unwrap()
     }
 
-    pub fn get_copied<T: Any + Copy>(&self) -> T {
-        match self {
-            AllocationUnitType::Buffer(buffer) => (buffer as &dyn Any).downcast_ref::<T>().copied(),
-            AllocationUnitType::Image(image) => (image as &dyn Any).downcast_ref::<T>().copied(),
+    /// Bytes allocated from device local heaps.
+    pub fn device_local_bytes(&self) -> u64 {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.device_local)
+            .map(|heap| heap.allocation_bytes)
+            .sum()
+    }
+
+    /// Lists the totals followed by one line per heap that has anything allocated.
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        ui.label(format!(
+            "GPU memory {:.1} MiB in {} allocations, {} blocks ({:.1} MiB)",
+            mib(self.allocation_bytes),
+            self.allocation_count,
+            self.block_count,
+            mib(self.block_bytes),
+        ));
+        for heap in self.heaps.iter().filter(|heap| heap.block_count > 0) {
+            ui.label(format!(
+                "Heap {}{}: {:.1} / {:.1} MiB budget",
+                heap.heap_index,
+                if heap.device_local { " (device local)" } else { "" },
+                mib(heap.usage),
+                mib(heap.budget),
+            ));
         }
-        .unwrap()
     }
 }
 
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Pixels of an image copied back to the host, rows are tightly packed.
+#[derive(Debug, Clone)]
+pub struct ReadbackImage {
+    pub extent: Extent3D,
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+
+impl ReadbackImage {
+    /// Converts 8 bit and half float RGBA pixels, float values are clamped to [0, 1] without
+    /// any tonemapping. `None` for other formats.
+    pub fn to_rgba8(&self) -> Option<image::RgbaImage> {
+        let pixels = match self.format {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => self.data.clone(),
+            Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => self
+                .data
+                .chunks_exact(4)
+                .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])
+                .collect(),
+            Format::R16G16B16A16_SFLOAT => self
+                .data
+                .chunks_exact(2)
+                .map(|half| {
+                    let value = f16_to_f32(u16::from_le_bytes([half[0], half[1]]));
+                    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+                })
+                .collect(),
+            _ => return None,
+        };
+        image::RgbaImage::from_raw(self.extent.width, self.extent.height, pixels)
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Buffers and images created by a `MemoryAllocator` since it was created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationCounts {
+    pub buffers: u64,
+    pub images: u64,
+}
+
 pub struct MemoryAllocator {
-    allocator: vk_mem::Allocator,
+    /// Shared with the buffers and images allocated from it, which free their memory on drop.
+    allocator: Arc<vk_mem::Allocator>,
     device: Arc<VkDevice>,
     queues: Vec<Arc<VkQueue>>,
+    buffers_created: AtomicU64,
+    images_created: AtomicU64,
 }
 
 impl Deref for MemoryAllocator {
@@ -65,6 +189,10 @@ impl Deref for MemoryAllocator {
 }
 
 impl MemoryAllocator {
+    pub(super) fn device(&self) -> &Arc<VkDevice> {
+        &self.device
+    }
+
     pub fn new(
         device: Arc<VkDevice>,
         queues: &[Arc<VkQueue>],
@@ -72,11 +200,39 @@ impl MemoryAllocator {
     ) -> Self {
         Self {
             device: device.clone(),
-            allocator: unsafe { vk_mem::Allocator::new(allocator_create_info).unwrap() },
+            allocator: Arc::new(unsafe { vk_mem::Allocator::new(allocator_create_info).unwrap() }),
             queues: queues.to_vec(),
+            buffers_created: AtomicU64::new(0),
+            images_created: AtomicU64::new(0),
         }
     }
 
+    pub fn allocation_counts(&self) -> AllocationCounts {
+        AllocationCounts {
+            buffers: self.buffers_created.load(Ordering::Relaxed),
+            images: self.images_created.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Hands `image`, `image_view` and `allocation` over to an `AllocatedImage` owning them.
+    fn owned_image(
+        &self,
+        image: Image,
+        image_view: ImageView,
+        allocation: Allocation,
+        extent: Extent3D,
+        format: Format,
+    ) -> AllocatedImage {
+        AllocatedImage::owned(
+            self.allocator.clone(),
+            self.device.clone(),
+            ImageDetails { image, image_view },
+            allocation,
+            extent,
+            format,
+        )
+    }
+
     #[allow(deprecated)]
     pub fn create_image(
         &self,
@@ -86,7 +242,7 @@ impl MemoryAllocator {
         flags: ImageUsageFlags,
         aspect_flags: ImageAspectFlags,
         mipmapped: bool,
-    ) -> Result<AllocationUnit, Error> {
+    ) -> Result<AllocatedImage, Error> {
         let image_create_info = image_create_info(
             format,
             ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST | flags,
@@ -94,14 +250,39 @@ impl MemoryAllocator {
             initial_layout,
             mipmapped,
         );
+        self.allocate_image(&image_create_info, extent, format, aspect_flags)
+    }
+
+    /// Single mip, single layer image with `samples` samples per texel, used as a multisampled
+    /// color or depth attachment.
+    pub fn create_multisampled_image(
+        &self,
+        extent: Extent3D,
+        format: Format,
+        flags: ImageUsageFlags,
+        aspect_flags: ImageAspectFlags,
+        samples: SampleCountFlags,
+    ) -> Result<AllocatedImage, Error> {
+        let image_create_info =
+            image_create_info(format, flags, extent, None, false).samples(samples);
+        self.allocate_image(&image_create_info, extent, format, aspect_flags)
+    }
 
+    fn allocate_image(
+        &self,
+        image_create_info: &ImageCreateInfo,
+        extent: Extent3D,
+        format: Format,
+        aspect_flags: ImageAspectFlags,
+    ) -> Result<AllocatedImage, Error> {
         let mut allocation_create_info = AllocationCreateInfo::default();
         allocation_create_info.required_flags = MemoryPropertyFlags::DEVICE_LOCAL;
         allocation_create_info.usage = MemoryUsage::GpuOnly;
 
+        self.images_created.fetch_add(1, Ordering::Relaxed);
         let (image, allocation) = unsafe {
             self.allocator
-                .create_image(&image_create_info, &allocation_create_info)
+                .create_image(image_create_info, &allocation_create_info)
                 .unwrap()
         };
 
@@ -111,13 +292,7 @@ impl MemoryAllocator {
                 .create_image_view(&image_view_create_info, None)
                 .unwrap()
         };
-        let allocated_image =
-            AllocatedImage::new(ImageDetails { image, image_view }, extent, format);
-        let allocation_unit = AllocationUnit {
-            unit: AllocationUnitType::Image(allocated_image),
-            allocation,
-        };
-        Ok(allocation_unit)
+        Ok(self.owned_image(image, image_view, allocation, extent, format))
     }
 
     pub fn create_image_with_data(
@@ -129,11 +304,10 @@ impl MemoryAllocator {
         aspect_flags: ImageAspectFlags,
         command_pool: &VkCommandPool,
         mipmapped: bool,
-    ) -> Result<AllocationUnit, Error> {
+    ) -> Result<AllocatedImage, Error> {
         let data_size: u64 = (extent.depth * extent.width * extent.height * 4) as u64;
-        let staging_buffer_unit = self.staging_buffer(data_size, data, &self.queues)?;
-        let staging_buffer = staging_buffer_unit.unit.get_copied::<VkBuffer>();
-        let image_unit = self.create_image(
+        let staging_buffer = self.staging_buffer(data_size, data, &self.queues)?;
+        let image = self.create_image(
             extent,
             format,
             None,
@@ -141,42 +315,315 @@ impl MemoryAllocator {
             aspect_flags,
             mipmapped,
         )?;
-        let image = image_unit.unit.get_copied::<AllocatedImage>();
 
-        let cmd_buffer = command_pool.single_time_command().unwrap();
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone()).unwrap();
         image_transition(
             self.device.clone(),
-            cmd_buffer,
+            submit.command_buffer(),
             self.queues[0].queue_family_index,
             image.image_details.image,
             ImageLayout::UNDEFINED,
             ImageLayout::TRANSFER_DST_OPTIMAL,
         );
-        command_pool.end_single_time_command(self.queues[0].clone(), cmd_buffer);
-
-        VkBuffer::copy_buffer_to_image(
-            *staging_buffer,
+        VkBuffer::copy_buffer_to_image(*staging_buffer, image.image_details.image, extent, &submit)
+            .unwrap();
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
             image.image_details.image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        submit.keep_until_submitted(staging_buffer);
+        submit.submit().unwrap();
+        Ok(image)
+    }
+
+    /// Creates a CUBE_COMPATIBLE image with 6 array layers and a CUBE view, `faces` are the
+    /// tightly packed pixels of +X, -X, +Y, -Y, +Z, -Z in that order.
+    pub fn create_cube_image_with_data(
+        &self,
+        faces: &[Vec<u8>; 6],
+        extent: Extent3D,
+        format: Format,
+        usage: ImageUsageFlags,
+        command_pool: &VkCommandPool,
+    ) -> Result<AllocatedImage, anyhow::Error> {
+        let data = faces.concat();
+        let staging_buffer = self.staging_buffer(data.len() as u64, &data, &self.queues)?;
+
+        let image_create_info = cube_image_create_info(
+            format,
+            usage | ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST,
             extent,
-            self.queues[0].clone(),
+        );
+        let allocation_create_info = Self::allocation_create_info(
+            AllocationCreateFlags::empty(),
+            MemoryPropertyFlags::DEVICE_LOCAL,
+            None,
+            MemoryUsage::AutoPreferDevice,
+            None,
+        );
+        self.images_created.fetch_add(1, Ordering::Relaxed);
+        let (image, mut allocation) = unsafe {
+            self.allocator
+                .create_image(&image_create_info, &allocation_create_info)?
+        };
+        let image_view = match unsafe {
+            self.device.create_image_view(
+                &cube_image_view_create_info(image, format, ImageAspectFlags::COLOR),
+                None,
+            )
+        } {
+            Ok(image_view) => image_view,
+            Err(err) => {
+                unsafe { self.allocator.destroy_image(image, &mut allocation) };
+                return Err(err.into());
+            }
+        };
+        let cube_image = self.owned_image(image, image_view, allocation, extent, format);
+
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone())?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image,
+            ImageLayout::UNDEFINED,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        VkBuffer::copy_buffer_to_image_layers(*staging_buffer, image, extent, 6, &submit)?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        submit.keep_until_submitted(staging_buffer);
+        submit.submit()?;
+        Ok(cube_image)
+    }
+
+    /// Loads a PNG or JPEG file into a sampled RGBA8 image. `srgb` picks R8G8B8A8_SRGB for color
+    /// textures, R8G8B8A8_UNORM is used for data like normal or metallic roughness maps. With
+    /// `mipmapped` the full mip chain is downsampled on the cpu and uploaded with the image.
+    pub fn create_image_from_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        srgb: bool,
+        mipmapped: bool,
+        usage: ImageUsageFlags,
+        command_pool: &VkCommandPool,
+    ) -> Result<AllocatedImage, anyhow::Error> {
+        let path = path.as_ref();
+        let image = JobSystem::global()
+            .run("image_decode", || image::open(path))?
+            .to_rgba8();
+        self.create_image_from_rgba(&image, srgb, mipmapped, usage, command_pool)
+    }
+
+    /// Uploads decoded pixels like `create_image_from_file`.
+    pub fn create_image_from_rgba(
+        &self,
+        image: &image::RgbaImage,
+        srgb: bool,
+        mipmapped: bool,
+        usage: ImageUsageFlags,
+        command_pool: &VkCommandPool,
+    ) -> Result<AllocatedImage, anyhow::Error> {
+        let jobs = JobSystem::global();
+        let (width, height) = image.dimensions();
+        let level_count = if mipmapped {
+            width.max(height).ilog2() + 1
+        } else {
+            1
+        };
+        let levels: Vec<u32> = (0..level_count).collect();
+        let levels = jobs.map("mip_generation", &levels, |&level| {
+            if level == 0 {
+                return image.as_raw().clone();
+            }
+            image::imageops::resize(
+                image,
+                (width >> level).max(1),
+                (height >> level).max(1),
+                FilterType::Triangle,
+            )
+            .into_raw()
+        });
+        let format = if srgb {
+            Format::R8G8B8A8_SRGB
+        } else {
+            Format::R8G8B8A8_UNORM
+        };
+        self.create_image_with_mips(
+            &levels,
+            Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            format,
+            usage | ImageUsageFlags::SAMPLED,
             command_pool,
         )
-        .unwrap();
+    }
+
+    /// Whether images of `format` can be sampled with optimal tiling, filled from a buffer and
+    /// copied from, `create_image_with_mips` creates them as transfer sources.
+    pub fn supports_sampled_format(&self, format: Format) -> bool {
+        let properties = unsafe {
+            self.device
+                .instance
+                .get_physical_device_format_properties(self.device.physical_device, format)
+        };
+        properties.optimal_tiling_features.contains(
+            FormatFeatureFlags::SAMPLED_IMAGE
+                | FormatFeatureFlags::TRANSFER_SRC
+                | FormatFeatureFlags::TRANSFER_DST,
+        )
+    }
 
-        let cmd = command_pool.single_time_command().unwrap();
+    /// Creates a 2D image with `levels.len()` mips and uploads `levels` (largest first) through
+    /// a single staging buffer. Works for block compressed formats as long as every level is
+    /// a whole number of blocks.
+    pub fn create_image_with_mips(
+        &self,
+        levels: &[Vec<u8>],
+        extent: Extent3D,
+        format: Format,
+        usage: ImageUsageFlags,
+        command_pool: &VkCommandPool,
+    ) -> Result<AllocatedImage, anyhow::Error> {
+        let level_offsets = levels
+            .iter()
+            .scan(0u64, |offset, level| {
+                let level_offset = *offset;
+                *offset += level.len() as u64;
+                Some(level_offset)
+            })
+            .collect::<Vec<_>>();
+        let data = levels.concat();
+        let staging_buffer = self.staging_buffer(data.len() as u64, &data, &self.queues)?;
+
+        let image_create_info = image_create_info(
+            format,
+            usage | ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST,
+            extent,
+            None,
+            false,
+        )
+        .mip_levels(levels.len() as u32);
+        let allocation_create_info = Self::allocation_create_info(
+            AllocationCreateFlags::empty(),
+            MemoryPropertyFlags::DEVICE_LOCAL,
+            None,
+            MemoryUsage::AutoPreferDevice,
+            None,
+        );
+        self.images_created.fetch_add(1, Ordering::Relaxed);
+        let (image, mut allocation) = unsafe {
+            self.allocator
+                .create_image(&image_create_info, &allocation_create_info)?
+        };
+        let image_view = match unsafe {
+            self.device.create_image_view(
+                &image_view_create_info(image, format, ImageAspectFlags::COLOR).subresource_range(
+                    image_subresource_range(ImageAspectFlags::COLOR).layer_count(1),
+                ),
+                None,
+            )
+        } {
+            Ok(image_view) => image_view,
+            Err(err) => {
+                unsafe { self.allocator.destroy_image(image, &mut allocation) };
+                return Err(err.into());
+            }
+        };
+        let mip_image = self.owned_image(image, image_view, allocation, extent, format);
+
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone())?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image,
+            ImageLayout::UNDEFINED,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        VkBuffer::copy_buffer_to_image_mips(
+            *staging_buffer,
+            image,
+            extent,
+            &level_offsets,
+            &submit,
+        )?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        submit.keep_until_submitted(staging_buffer);
+        submit.submit()?;
+        Ok(mip_image)
+    }
+
+    /// Overwrites the region of an egui texture starting at `pos` with `image_data`, for partial
+    /// texture deltas. The image is expected in SHADER_READ_ONLY_OPTIMAL and left there.
+    pub fn update_egui_texture_image(
+        &self,
+        command_pool: &VkCommandPool,
+        image: &AllocatedImage,
+        pos: [usize; 2],
+        image_data: &ImageData,
+    ) -> Result<(), anyhow::Error> {
+        let pixels = match image_data {
+            ImageData::Color(color_image) => color_image.pixels.clone(),
+            ImageData::Font(font_image) => font_image.srgba_pixels(None).collect::<Vec<Color32>>(),
+        };
+        let staging_buffer = self.staging_buffer(
+            (size_of::<Color32>() * pixels.len()) as u64,
+            &pixels,
+            &self.queues,
+        )?;
+
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone())?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image.image_details.image,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        VkBuffer::copy_buffer_to_image_region(
+            *staging_buffer,
+            image.image_details.image,
+            Offset3D::default().x(pos[0] as i32).y(pos[1] as i32),
+            Extent3D {
+                width: image_data.width() as u32,
+                height: image_data.height() as u32,
+                depth: 1,
+            },
+            &submit,
+        )?;
         image_transition(
             self.device.clone(),
-            cmd,
+            submit.command_buffer(),
             self.queues[0].queue_family_index,
             image.image_details.image,
             ImageLayout::TRANSFER_DST_OPTIMAL,
             ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         );
-        command_pool.end_single_time_command(self.queues[0].clone(), cmd);
-        Ok(AllocationUnit {
-            unit: image_unit.unit,
-            allocation: image_unit.allocation,
-        })
+        submit.keep_until_submitted(staging_buffer);
+        submit.submit()?;
+        Ok(())
     }
 
     //egui only
@@ -185,7 +632,7 @@ impl MemoryAllocator {
         command_pool: &VkCommandPool,
         image_data: &ImageData,
         mipmapped: bool,
-    ) -> Result<AllocationUnit, &str> {
+    ) -> Result<AllocatedImage, &str> {
         let pixels = match image_data {
             ImageData::Color(color_image) => color_image.pixels.clone(),
             ImageData::Font(font_image) => font_image.srgba_pixels(None).collect::<Vec<Color32>>(),
@@ -218,89 +665,45 @@ impl MemoryAllocator {
             )
             .unwrap();
 
-        let single_time_command = command_pool.single_time_command().unwrap();
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone()).unwrap();
         image_transition(
             command_pool.device.clone(),
-            single_time_command,
+            submit.command_buffer(),
             self.queues[0].queue_family_index,
-            image
-                .unit
-                .get_copied::<AllocatedImage>()
-                .image_details
-                .image,
+            image.image_details.image,
             ImageLayout::UNDEFINED,
             ImageLayout::TRANSFER_DST_OPTIMAL,
         );
-        command_pool.end_single_time_command(self.queues[0].clone(), single_time_command);
-
         VkBuffer::copy_buffer_to_image(
-            *staging_buffer.unit.get_copied::<VkBuffer>(),
-            image
-                .unit
-                .get_copied::<AllocatedImage>()
-                .image_details
-                .image,
+            *staging_buffer,
+            image.image_details.image,
             extent,
-            self.queues[0].clone(),
-            command_pool,
+            &submit,
         )
         .unwrap();
-
-        let single_time_command = command_pool.single_time_command().unwrap();
         image_transition(
             command_pool.device.clone(),
-            single_time_command,
+            submit.command_buffer(),
             self.queues[0].queue_family_index,
-            image
-                .unit
-                .get_copied::<AllocatedImage>()
-                .image_details
-                .image,
+            image.image_details.image,
             ImageLayout::TRANSFER_DST_OPTIMAL,
             ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         );
-        command_pool.end_single_time_command(self.queues[0].clone(), single_time_command);
-        let image_view_create_info = image_view_create_info(
-            image
-                .unit
-                .get_copied::<AllocatedImage>()
-                .image_details
-                .image,
-            format,
-            ImageAspectFlags::COLOR,
-        );
-        let image_view = unsafe {
-            self.device
-                .create_image_view(&image_view_create_info, None)
-                .unwrap()
-        };
-        Ok(AllocationUnit {
-            unit: AllocationUnitType::Image(AllocatedImage {
-                image_details: ImageDetails {
-                    image: image
-                        .unit
-                        .get_copied::<AllocatedImage>()
-                        .image_details
-                        .image,
-                    image_view,
-                },
-                extent,
-                image_format: format,
-            }),
-            allocation: image.allocation,
-        })
+        submit.keep_until_submitted(staging_buffer);
+        submit.submit().unwrap();
+        Ok(image)
     }
 
-    fn staging_buffer<T>(
+    pub(crate) fn staging_buffer<T>(
         &self,
         buffer_size: u64,
         buffer_elements: &[T],
         queues: &[Arc<VkQueue>],
-    ) -> Result<AllocationUnit, Error>
+    ) -> Result<VkBuffer, Error>
     where
         T: Clone,
     {
-        let mut staging_buffer = self.allocate_single_buffer(
+        let staging_buffer = self.allocate_single_buffer(
             buffer_size,
             queues,
             BufferUsageFlags::TRANSFER_SRC | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
@@ -308,14 +711,16 @@ impl MemoryAllocator {
             MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
         )?;
 
-        let data = unsafe { self.map_memory(&mut staging_buffer.allocation).unwrap() };
+        let data = staging_buffer.mapped_data();
+        if data.is_null() {
+            return Err(Error::other("Staging buffer is not host mapped"));
+        }
         unsafe {
             std::ptr::copy_nonoverlapping(
                 buffer_elements.as_ptr(),
                 data as *mut T,
                 buffer_elements.len(),
             );
-            self.unmap_memory(&mut staging_buffer.allocation);
         };
         Ok(staging_buffer)
     }
@@ -328,21 +733,39 @@ impl MemoryAllocator {
         memory_usage: MemoryUsage,
         memory_property_flags: MemoryPropertyFlags,
         command_pool: &VkCommandPool,
-    ) -> Result<AllocationUnit, anyhow::Error>
+    ) -> Result<VkBuffer, anyhow::Error>
+    where
+        T: Clone + Debug,
+    {
+        let submit = ImmediateSubmit::begin(command_pool, queues[0].clone())?;
+        let buffer = self.record_buffer_with_data(
+            &submit,
+            buffer_elements,
+            queues,
+            buffer_usage,
+            memory_usage,
+            memory_property_flags,
+        )?;
+        submit.submit()?;
+        Ok(buffer)
+    }
+
+    /// Creates the buffer of `create_buffer_with_mapped_memory` and records the copy of
+    /// `buffer_elements` into `submit`, the buffer is filled once `submit` was submitted.
+    pub fn record_buffer_with_data<T>(
+        &self,
+        submit: &ImmediateSubmit,
+        buffer_elements: &[T],
+        queues: &[Arc<VkQueue>],
+        buffer_usage: BufferUsageFlags,
+        memory_usage: MemoryUsage,
+        memory_property_flags: MemoryPropertyFlags,
+    ) -> Result<VkBuffer, anyhow::Error>
     where
         T: Clone + Debug,
     {
         let buffer_size = (size_of::<T>() * buffer_elements.len()) as u64;
-        let mut staging_buffer = self.staging_buffer(buffer_size, buffer_elements, queues)?;
-        let data = unsafe { self.map_memory(&mut staging_buffer.allocation).unwrap() };
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                buffer_elements.as_ptr(),
-                data as *mut T,
-                buffer_elements.len(),
-            );
-            self.unmap_memory(&mut staging_buffer.allocation);
-        };
+        let staging_buffer = self.staging_buffer(buffer_size, buffer_elements, queues)?;
 
         let buffer = self.allocate_single_buffer(
             buffer_size,
@@ -352,20 +775,8 @@ impl MemoryAllocator {
             memory_property_flags,
         )?;
 
-        VkBuffer::copy_buffer(
-            staging_buffer.unit.get_copied::<VkBuffer>(),
-            buffer.unit.get_copied::<VkBuffer>(),
-            buffer_size as u64,
-            queues[0].clone(),
-            command_pool,
-        );
-
-        unsafe {
-            self.destroy_buffer(
-                *staging_buffer.unit.get_copied::<VkBuffer>(),
-                &mut staging_buffer.allocation,
-            )
-        };
+        VkBuffer::copy_buffer(&staging_buffer, &buffer, buffer_size, submit);
+        submit.keep_until_submitted(staging_buffer);
         Ok(buffer)
     }
 
@@ -376,7 +787,7 @@ impl MemoryAllocator {
         buffer_usage: BufferUsageFlags,
         memory_usage: MemoryUsage,
         memory_property_flags: MemoryPropertyFlags,
-    ) -> Result<AllocationUnit, Error> {
+    ) -> Result<VkBuffer, Error> {
         let queue_family_indices = queues
             .iter()
             .map(|queue| queue.queue_family_index)
@@ -393,6 +804,7 @@ impl MemoryAllocator {
             flags: AllocationCreateFlags::MAPPED | AllocationCreateFlags::HOST_ACCESS_RANDOM,
             ..Default::default()
         };
+        self.buffers_created.fetch_add(1, Ordering::Relaxed);
         let (buffer, allocation) = unsafe {
             self.allocator
                 .create_buffer(&buffer_info, &create_info)
@@ -400,12 +812,154 @@ impl MemoryAllocator {
         };
         let info = BufferDeviceAddressInfo::default().buffer(buffer);
         let address = unsafe { self.device.get_buffer_device_address(&info) };
-        Ok(AllocationUnit {
-            unit: AllocationUnitType::Buffer(VkBuffer { buffer, address }),
+        Ok(VkBuffer::owned(
+            self.allocator.clone(),
+            self.device.clone(),
+            buffer,
+            address,
             allocation,
+        ))
+    }
+
+    /// Copies `size` bytes at `offset` of `buffer` back to the host and blocks until the copy
+    /// completed. Work writing `buffer` must have completed before.
+    pub fn read_buffer(
+        &self,
+        buffer: &VkBuffer,
+        offset: u64,
+        size: u64,
+        command_pool: &VkCommandPool,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let readback = self.readback_buffer(size)?;
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone())?;
+        unsafe {
+            submit.device().cmd_copy_buffer(
+                submit.command_buffer(),
+                **buffer,
+                *readback,
+                &[BufferCopy::default().src_offset(offset).size(size)],
+            )
+        };
+        self.finish_readback(submit, readback, size)
+    }
+
+    /// Copies the first mip level of a color image back to the host, `layout` is the layout the
+    /// image is in and is restored afterwards. Blocks until the copy completed, work writing
+    /// the image must have completed before.
+    pub fn read_image(
+        &self,
+        image: &AllocatedImage,
+        layout: ImageLayout,
+        command_pool: &VkCommandPool,
+    ) -> Result<ReadbackImage, anyhow::Error> {
+        let texel_size = format_texel_size(image.image_format).ok_or_else(|| {
+            anyhow::anyhow!("Reading back {:?} images is not supported", image.image_format)
+        })?;
+        let extent = image.extent;
+        let size = extent.width as u64 * extent.height as u64 * extent.depth as u64 * texel_size;
+        let readback = self.readback_buffer(size)?;
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone())?;
+        let transition = |old_layout, new_layout, (src_stage, src_access), (dst_stage, dst_access)| {
+            let barrier = ImageMemoryBarrier2::default()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(image.image_details.image)
+                .subresource_range(image_subresource_range(ImageAspectFlags::COLOR));
+            unsafe {
+                submit
+                    .device()
+                    .cmd_pipeline_barrier2(submit.command_buffer(), &[], &[], &[barrier])
+            };
+        };
+        transition(
+            layout,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            (PipelineStageFlags2::ALL_COMMANDS, AccessFlags2::MEMORY_WRITE),
+            (PipelineStageFlags2::COPY, AccessFlags2::TRANSFER_READ),
+        );
+        VkBuffer::copy_image_to_buffer(image.image_details.image, *readback, extent, &submit)?;
+        transition(
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            layout,
+            (PipelineStageFlags2::COPY, AccessFlags2::empty()),
+            (
+                PipelineStageFlags2::ALL_COMMANDS,
+                AccessFlags2::MEMORY_READ | AccessFlags2::MEMORY_WRITE,
+            ),
+        );
+        let data = self.finish_readback(submit, readback, size)?;
+        Ok(ReadbackImage {
+            extent,
+            format: image.image_format,
+            data,
         })
     }
 
+    fn readback_buffer(&self, size: u64) -> Result<VkBuffer, Error> {
+        self.allocate_single_buffer(
+            size,
+            &self.queues,
+            BufferUsageFlags::TRANSFER_DST | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryUsage::Auto,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )
+    }
+
+    /// Makes the copies into `readback` visible to the host, submits and copies the first
+    /// `size` bytes out. `readback` is dropped either way.
+    fn finish_readback(
+        &self,
+        submit: ImmediateSubmit,
+        readback: VkBuffer,
+        size: u64,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let host_barrier = MemoryBarrier2::default()
+            .src_stage_mask(PipelineStageFlags2::COPY)
+            .src_access_mask(AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(PipelineStageFlags2::HOST)
+            .dst_access_mask(AccessFlags2::HOST_READ);
+        unsafe {
+            submit
+                .device()
+                .cmd_pipeline_barrier2(submit.command_buffer(), &[host_barrier], &[], &[])
+        };
+        submit.submit()?;
+        let mapped = readback.mapped_data();
+        if mapped.is_null() {
+            return Err(anyhow::anyhow!("Readback buffer is not host mapped"));
+        }
+        Ok(unsafe { std::slice::from_raw_parts(mapped, size as usize) }.to_vec())
+    }
+
+    /// Current per-heap budgets and allocation counts of the allocator.
+    pub fn statistics(&self) -> Result<MemoryStatistics, Error> {
+        let memory_heaps = unsafe { self.allocator.get_memory_properties() }.memory_heaps;
+        let budgets = self.allocator.get_heap_budgets().map_err(Error::other)?;
+        let heaps = budgets
+            .iter()
+            .zip(memory_heaps)
+            .enumerate()
+            .map(|(heap_index, (budget, heap))| HeapStatistics {
+                heap_index: heap_index as u32,
+                device_local: heap.flags.contains(MemoryHeapFlags::DEVICE_LOCAL),
+                size: heap.size,
+                block_count: budget.statistics.blockCount,
+                allocation_count: budget.statistics.allocationCount,
+                block_bytes: budget.statistics.blockBytes,
+                allocation_bytes: budget.statistics.allocationBytes,
+                usage: budget.usage,
+                budget: budget.budget,
+            })
+            .collect();
+        Ok(MemoryStatistics::from_heaps(heaps))
+    }
+
     fn allocation_create_info(
         flags: AllocationCreateFlags,
         required_flags: MemoryPropertyFlags,
@@ -423,3 +977,342 @@ impl MemoryAllocator {
         allocation_create_info
     }
 }
+
+#[cfg(feature = "sparse-textures")]
+impl MemoryAllocator {
+    /// Whether the device can create sparse resident 2D images and `queue` can bind them.
+    pub fn supports_sparse_residency(&self, queue: &VkQueue) -> bool {
+        let features = unsafe {
+            self.device
+                .instance
+                .get_physical_device_features(self.device.physical_device)
+        };
+        let queue_families = unsafe {
+            self.device
+                .instance
+                .get_physical_device_queue_family_properties(self.device.physical_device)
+        };
+        features.sparse_binding == 1
+            && features.sparse_residency_image2_d == 1
+            && queue_families
+                .get(queue.queue_family_index as usize)
+                .is_some_and(|family| family.queue_flags.contains(QueueFlags::SPARSE_BINDING))
+    }
+
+    /// Creates a sparse resident image without any memory bound, except for the mip tail which
+    /// is always resident. Pages are bound with `bind_sparse_pages`.
+    pub fn create_sparse_image(
+        &self,
+        extent: Extent2D,
+        format: Format,
+        usage: ImageUsageFlags,
+        queue: &VkQueue,
+    ) -> Result<SparseImage, anyhow::Error> {
+        if !self.supports_sparse_residency(queue) {
+            return Err(anyhow::anyhow!("Sparse residency is not supported by this device"));
+        }
+        let extent = Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+        let image_create_info = image_create_info(
+            format,
+            usage | ImageUsageFlags::TRANSFER_DST,
+            extent,
+            None,
+            false,
+        )
+        .flags(ImageCreateFlags::SPARSE_BINDING | ImageCreateFlags::SPARSE_RESIDENCY);
+        self.images_created.fetch_add(1, Ordering::Relaxed);
+        let image = unsafe { self.device.create_image(&image_create_info, None)? };
+
+        // the requirements are those of the whole image, its alignment is the sparse block size
+        let image_requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let page_requirements = ash::vk::MemoryRequirements {
+            size: image_requirements.alignment,
+            ..image_requirements
+        };
+        let sparse_requirements = unsafe {
+            self.device.get_image_sparse_memory_requirements(image)
+        };
+        let Some(color_requirements) = sparse_requirements.iter().find(|requirements| {
+            requirements
+                .format_properties
+                .aspect_mask
+                .contains(ImageAspectFlags::COLOR)
+        }) else {
+            unsafe { self.device.destroy_image(image, None) };
+            return Err(anyhow::anyhow!("{format:?} has no sparse color aspect"));
+        };
+
+        let mut sparse_image = SparseImage {
+            image_details: ImageDetails {
+                image,
+                image_view: Default::default(),
+            },
+            extent,
+            format,
+            page_extent: color_requirements.format_properties.image_granularity,
+            page_requirements,
+            pages: Default::default(),
+            mip_tail: None,
+        };
+
+        // with a single mip level the tail only exists for images smaller than a page
+        if color_requirements.image_mip_tail_first_lod == 0 {
+            let tail_requirements = ash::vk::MemoryRequirements {
+                size: color_requirements.image_mip_tail_size,
+                ..page_requirements
+            };
+            let allocation = self.allocate_sparse_memory(&tail_requirements)?;
+            let info = self.get_allocation_info(&allocation);
+            let binds = [SparseMemoryBind::default()
+                .resource_offset(color_requirements.image_mip_tail_offset)
+                .size(color_requirements.image_mip_tail_size)
+                .memory(info.device_memory)
+                .memory_offset(info.offset)];
+            let opaque_binds = [SparseImageOpaqueMemoryBindInfo::default()
+                .image(image)
+                .binds(&binds)];
+            self.submit_sparse_binds(
+                queue,
+                BindSparseInfo::default().image_opaque_binds(&opaque_binds),
+            )?;
+            sparse_image.mip_tail = Some(allocation);
+        }
+
+        sparse_image.image_details.image_view = unsafe {
+            self.device.create_image_view(
+                &image_view_create_info(image, format, ImageAspectFlags::COLOR),
+                None,
+            )?
+        };
+        Ok(sparse_image)
+    }
+
+    /// Backs `pages` with memory, pages that are already resident are skipped. Blocks until
+    /// the bind has been executed by `queue`.
+    pub fn bind_sparse_pages(
+        &self,
+        image: &mut SparseImage,
+        pages: &[SparsePage],
+        queue: &VkQueue,
+    ) -> Result<(), anyhow::Error> {
+        // out of range pages fail before anything is allocated
+        for page in pages {
+            image.page_region(*page)?;
+        }
+        let mut binds = vec![];
+        let mut allocations = vec![];
+        for page in pages.iter().filter(|page| !image.is_resident(**page)) {
+            let allocation = self.allocate_sparse_memory(&image.page_requirements)?;
+            let info = self.get_allocation_info(&allocation);
+            binds.push(Self::sparse_page_bind(
+                image,
+                *page,
+                info.device_memory,
+                info.offset,
+            )?);
+            allocations.push((*page, allocation));
+        }
+        if binds.is_empty() {
+            return Ok(());
+        }
+        let image_binds = [SparseImageMemoryBindInfo::default()
+            .image(image.image_details.image)
+            .binds(&binds)];
+        self.submit_sparse_binds(queue, BindSparseInfo::default().image_binds(&image_binds))?;
+        image.pages.extend(allocations);
+        Ok(())
+    }
+
+    /// Uploads the tightly packed texels of resident pages, each covering the `page_region` of
+    /// its page. The image is expected in SHADER_READ_ONLY_OPTIMAL and left there.
+    pub fn fill_sparse_pages(
+        &self,
+        command_pool: &VkCommandPool,
+        image: &SparseImage,
+        pages: &[(SparsePage, Vec<u8>)],
+    ) -> Result<(), anyhow::Error> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let texel_size = format_texel_size(image.format)
+            .ok_or_else(|| anyhow::anyhow!("Can't fill pages of {:?} images", image.format))?;
+        let submit = ImmediateSubmit::begin(command_pool, self.queues[0].clone())?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image.image_details.image,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        for (page, texels) in pages {
+            let (offset, extent) = image.page_region(*page)?;
+            if !image.is_resident(*page) {
+                return Err(anyhow::anyhow!("{page:?} has no memory bound to fill"));
+            }
+            let size = extent.width as u64 * extent.height as u64 * texel_size;
+            if texels.len() as u64 != size {
+                return Err(anyhow::anyhow!(
+                    "{page:?} needs {size} bytes of texels, got {}",
+                    texels.len()
+                ));
+            }
+            let staging_buffer = self.staging_buffer(size, texels, &self.queues)?;
+            VkBuffer::copy_buffer_to_image_region(
+                *staging_buffer,
+                image.image_details.image,
+                Offset3D::default().x(offset[0]).y(offset[1]),
+                extent,
+                &submit,
+            )?;
+            submit.keep_until_submitted(staging_buffer);
+        }
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.queues[0].queue_family_index,
+            image.image_details.image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        submit.submit()?;
+        Ok(())
+    }
+
+    /// Unbinds `pages` and frees their memory. The image must not be in use by the GPU.
+    pub fn evict_sparse_pages(
+        &self,
+        image: &mut SparseImage,
+        pages: &[SparsePage],
+        queue: &VkQueue,
+    ) -> Result<(), anyhow::Error> {
+        let binds = pages
+            .iter()
+            .filter(|page| image.is_resident(**page))
+            .map(|page| Self::sparse_page_bind(image, *page, DeviceMemory::null(), 0))
+            .collect::<Result<Vec<_>, _>>()?;
+        if binds.is_empty() {
+            return Ok(());
+        }
+        let image_binds = [SparseImageMemoryBindInfo::default()
+            .image(image.image_details.image)
+            .binds(&binds)];
+        self.submit_sparse_binds(queue, BindSparseInfo::default().image_binds(&image_binds))?;
+        for page in pages {
+            if let Some(mut allocation) = image.pages.remove(page) {
+                unsafe { self.free_memory(&mut allocation) };
+            }
+        }
+        Ok(())
+    }
+
+    pub fn destroy_sparse_image(&self, mut image: SparseImage) {
+        unsafe {
+            self.device
+                .destroy_image_view(image.image_details.image_view, None);
+            self.device.destroy_image(image.image_details.image, None);
+            for (_, mut allocation) in image.pages.drain() {
+                self.free_memory(&mut allocation);
+            }
+            if let Some(mut allocation) = image.mip_tail.take() {
+                self.free_memory(&mut allocation);
+            }
+        }
+    }
+
+    fn allocate_sparse_memory(
+        &self,
+        requirements: &ash::vk::MemoryRequirements,
+    ) -> Result<Allocation, anyhow::Error> {
+        let allocation_create_info = Self::allocation_create_info(
+            AllocationCreateFlags::empty(),
+            MemoryPropertyFlags::DEVICE_LOCAL,
+            None,
+            MemoryUsage::Unknown,
+            Some(requirements.memory_type_bits),
+        );
+        Ok(unsafe {
+            self.allocator
+                .allocate_memory(requirements, &allocation_create_info)?
+        })
+    }
+
+    fn sparse_page_bind(
+        image: &SparseImage,
+        page: SparsePage,
+        memory: DeviceMemory,
+        memory_offset: u64,
+    ) -> Result<SparseImageMemoryBind, anyhow::Error> {
+        let (offset, extent) = image.page_region(page)?;
+        Ok(SparseImageMemoryBind::default()
+            .subresource(ImageSubresource {
+                aspect_mask: ImageAspectFlags::COLOR,
+                mip_level: 0,
+                array_layer: 0,
+            })
+            .offset(Offset3D {
+                x: offset[0],
+                y: offset[1],
+                z: 0,
+            })
+            .extent(extent)
+            .memory(memory)
+            .memory_offset(memory_offset)
+            .flags(SparseMemoryBindFlags::empty()))
+    }
+
+    fn submit_sparse_binds(
+        &self,
+        queue: &VkQueue,
+        bind_info: BindSparseInfo,
+    ) -> Result<(), anyhow::Error> {
+        unsafe {
+            let fence = self.device.create_fence(&FenceCreateInfo::default(), None)?;
+            let result = self
+                .device
+                .queue_bind_sparse(**queue, &[bind_info], fence)
+                .and_then(|_| self.device.wait_for_fences(&[fence], true, u64::MAX));
+            self.device.destroy_fence(fence, None);
+            Ok(result?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{f16_to_f32, HeapStatistics, MemoryStatistics};
+
+    #[test]
+    fn converts_half_floats() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.33325195);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn sums_heaps() {
+        let heap = |heap_index, device_local, allocation_bytes| HeapStatistics {
+            heap_index,
+            device_local,
+            block_count: 1,
+            allocation_count: 2,
+            block_bytes: 64,
+            allocation_bytes,
+            ..Default::default()
+        };
+        let statistics =
+            MemoryStatistics::from_heaps(vec![heap(0, true, 48), heap(1, false, 16)]);
+        assert_eq!(statistics.block_count, 2);
+        assert_eq!(statistics.allocation_count, 4);
+        assert_eq!(statistics.block_bytes, 128);
+        assert_eq!(statistics.allocation_bytes, 64);
+        assert_eq!(statistics.device_local_bytes(), 48);
+    }
+}
@@ -1,17 +1,35 @@
 pub mod instance;
 pub mod device;
+pub mod debug_utils;
+pub mod context;
 pub mod swapchain_support_details;
 pub mod queue;
 pub mod swapchain;
+pub mod swapchain_sync;
+pub mod window_target;
 pub mod surface;
 pub mod descriptors;
+pub mod bindless;
 pub mod deletion_queue;
 pub mod pipeline;
+pub mod shader_reflection;
 pub mod util;
+pub mod embedded_shaders;
 pub mod render_pass;
 pub mod frame_data;
 pub mod allocation_types;
 pub mod memory_allocator;
 pub mod command_buffers;
+pub mod command_log;
+pub mod secondary_commands;
 pub mod image_util;
 pub mod sampler;
+pub mod mapped_ring;
+pub mod buffer_arena;
+pub mod frame_buffer_pool;
+pub mod sync_pool;
+pub mod upload_context;
+pub mod async_compute;
+pub mod raw;
+#[cfg(feature = "sparse-textures")]
+pub mod sparse_image;
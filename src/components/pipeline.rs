@@ -10,12 +10,21 @@ use ash::vk::{
     PipelineLayoutCreateInfo, PipelineMultisampleStateCreateInfo,
     PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo,
     PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PolygonMode,
-    PrimitiveTopology, PushConstantRange, Rect2D, SampleCountFlags, ShaderStageFlags,
+    PrimitiveTopology, PushConstantRange, Rect2D, SampleCountFlags, ShaderModule,
+    ShaderStageFlags,
     VertexInputAttributeDescription, VertexInputBindingDescription, Viewport,
 };
 use ash::vk::{ColorComponentFlags, CompareOp, PipelineDepthStencilStateCreateInfo};
+use ash::Device;
 
-use super::{device::VkDevice, render_pass::VkRenderPass, util::load_shader_module};
+use crate::geom::{push_constants::PushConstantLayout, VertexAttributes};
+
+use super::{
+    device::VkDevice,
+    render_pass::VkRenderPass,
+    shader_reflection::{reflect, PipelineReflection, ShaderReflection},
+    util::{load_shader_module, read_shader_code},
+};
 
 #[derive(Debug, Clone)]
 #[allow(unused)]
@@ -49,6 +58,40 @@ impl ShaderInformation {
             entry_point: String::from("main"),
         }
     }
+
+    /// Descriptors, push constants and vertex inputs the shader declares.
+    pub fn reflect(&self) -> anyhow::Result<ShaderReflection> {
+        reflect(&read_shader_code(&self.shader_file_path)?)
+            .map_err(|err| err.context(self.shader_file_path.clone()))
+    }
+}
+
+/// How the vertex shader of a graphics pipeline gets its vertices.
+#[derive(Debug, Clone, Default)]
+pub enum VertexInput {
+    /// No fixed function vertex input. The shader reads its vertices through the buffer device
+    /// address in its push constants, or generates them like the fullscreen passes, so no
+    /// vertex buffers are bound.
+    #[default]
+    Pulling,
+    /// Fixed function vertex input from the vertex buffer bound to binding 0.
+    Bindings {
+        bindings: Vec<VertexInputBindingDescription>,
+        attributes: Vec<VertexInputAttributeDescription>,
+    },
+    /// The inputs the vertex shader declares, packed into one vertex buffer in the order of
+    /// their locations, see `PipelineReflection::vertex_input`.
+    Reflected,
+}
+
+impl VertexInput {
+    /// Fixed function vertex input of `T`, for shaders declaring its attributes as inputs.
+    pub fn bindings<T: VertexAttributes>() -> Self {
+        Self::Bindings {
+            bindings: T::get_binding_description(),
+            attributes: T::get_attribute_description(),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -58,12 +101,37 @@ pub enum PipelineType {
     COMPUTE,
 }
 
+/// Destroys the pipeline and its layout once the last `VkPipeline` sharing them is dropped.
+struct PipelineOwner {
+    device: Arc<VkDevice>,
+    pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+    /// Layouts created from the reflection of the shaders, see `create_new_pipeline`.
+    set_layouts: Vec<DescriptorSetLayout>,
+}
+
+impl Drop for PipelineOwner {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            for &set_layout in &self.set_layouts {
+                self.device.destroy_descriptor_set_layout(set_layout, None);
+            }
+        }
+    }
+}
+
+/// Clones share the pipeline, which is destroyed with the last of them. Dropping it while a
+/// frame that uses it is in flight has to be deferred, see `DeletionQueue::enqueue_after`.
+/// The default value owns nothing.
 #[allow(unused)]
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Default)]
 pub struct VkPipeline {
     pipeline: Pipeline,
     pub pipeline_layout: PipelineLayout,
     pub pipeline_type: PipelineType,
+    _owner: Option<Arc<PipelineOwner>>,
 }
 
 impl Debug for VkPipeline {
@@ -83,17 +151,49 @@ impl Deref for VkPipeline {
 }
 
 impl VkPipeline {
-    pub fn create_new_pipeline<T>(
+    fn owned(
+        device: Arc<VkDevice>,
+        pipeline: Pipeline,
+        pipeline_layout: PipelineLayout,
+        pipeline_type: PipelineType,
+        set_layouts: Vec<DescriptorSetLayout>,
+    ) -> Self {
+        Self {
+            pipeline,
+            pipeline_layout,
+            pipeline_type,
+            _owner: Some(Arc::new(PipelineOwner {
+                device,
+                pipeline,
+                pipeline_layout,
+                set_layouts,
+            })),
+        }
+    }
+
+    /// Merged reflection of `shader_information`, to build layouts with overrides before
+    /// creating the pipeline.
+    pub fn reflect(shader_information: &[ShaderInformation]) -> anyhow::Result<PipelineReflection> {
+        let shaders = shader_information
+            .iter()
+            .map(ShaderInformation::reflect)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(PipelineReflection::merge(&shaders))
+    }
+
+    /// Creates a graphics pipeline. Descriptor set layouts, the push constant range and the
+    /// vertex input left out with `None` or `VertexInput::Reflected` are taken from the
+    /// reflection of the shaders, the ones passed override it. Set layouts created from the
+    /// reflection are destroyed with the pipeline.
+    pub fn create_new_pipeline(
         device: Arc<VkDevice>,
         dynamic_state_list: &[DynamicState],
         topology: PrimitiveTopology,
-        shader_stage_flags: ShaderStageFlags,
         shader_information: &[ShaderInformation],
         layouts: Option<&[DescriptorSetLayout]>,
         extent: &Extent2D,
-        push_constant_range_type: Option<T>,
-        vertex_binding_description: Vec<VertexInputBindingDescription>,
-        vertex_attribute_description: Vec<VertexInputAttributeDescription>,
+        push_constants: Option<PushConstantLayout>,
+        vertex_input: VertexInput,
         color_attachment: &[PipelineColorBlendAttachmentState],
         rasterizer_info: PipelineRasterizationStateCreateInfo,
         multisampling_info: PipelineMultisampleStateCreateInfo,
@@ -101,9 +201,10 @@ impl VkPipeline {
         enable_depth_test: bool,
     ) -> Result<VkPipeline, Error> {
         let dynamic_states_create_info = dynamic_states(dynamic_state_list);
+        let mut shader_modules = ShaderModules::new(&device);
         let mut pipeline_stage_create_info: Vec<PipelineShaderStageCreateInfo> = Vec::new();
         for information in shader_information {
-            let shader_module = load_shader_module(&information.shader_file_path, &device)?;
+            let shader_module = shader_modules.load(&information.shader_file_path)?;
             pipeline_stage_create_info.push(
                 PipelineShaderStageCreateInfo::default()
                     .name(c"main")
@@ -111,6 +212,25 @@ impl VkPipeline {
                     .stage(information.stages),
             );
         }
+        let reflection = if layouts.is_none()
+            || push_constants.is_none()
+            || matches!(vertex_input, VertexInput::Reflected)
+        {
+            Self::reflect(shader_information).map_err(Error::other)?
+        } else {
+            PipelineReflection::default()
+        };
+        let vertex_input = match vertex_input {
+            VertexInput::Reflected => reflection.vertex_input(),
+            vertex_input => vertex_input,
+        };
+        let (vertex_binding_description, vertex_attribute_description) = match vertex_input {
+            VertexInput::Bindings {
+                bindings,
+                attributes,
+            } => (bindings, attributes),
+            _ => (vec![], vec![]),
+        };
         let vertex_input_state = PipelineVertexInputStateCreateInfo::default()
             .vertex_binding_descriptions(&vertex_binding_description)
             .vertex_attribute_descriptions(&vertex_attribute_description);
@@ -127,24 +247,35 @@ impl VkPipeline {
         let mut pipeline_layout_create_info = PipelineLayoutCreateInfo::default();
 
         let mut push_constant_range: Vec<PushConstantRange> = vec![];
-        if push_constant_range_type.is_some() {
+        if let Some(push_constants) = push_constants.or(reflection.push_constants) {
+            let pipeline_stages = shader_information
+                .iter()
+                .fold(ShaderStageFlags::empty(), |stages, shader| stages | shader.stages);
             push_constant_range.push(
-                PushConstantRange::default()
-                    .stage_flags(shader_stage_flags)
-                    .size(size_of::<T>() as u32),
+                push_constants
+                    .validate(device.max_push_constants_size(), pipeline_stages)
+                    .map_err(Error::other)?,
             );
             pipeline_layout_create_info =
                 pipeline_layout_create_info.push_constant_ranges(&push_constant_range);
         }
 
-        if layouts.is_some() {
-            pipeline_layout_create_info = pipeline_layout_create_info.set_layouts(layouts.unwrap());
-        }
-        let pipeline_layout = unsafe {
-            device
-                .create_pipeline_layout(&pipeline_layout_create_info, None)
-                .unwrap()
+        let reflected_layouts = match layouts {
+            Some(_) => vec![],
+            None => reflection
+                .create_set_layouts(&device)
+                .map_err(Error::other)?,
         };
+        pipeline_layout_create_info =
+            pipeline_layout_create_info.set_layouts(layouts.unwrap_or(&reflected_layouts));
+        let pipeline_layout =
+            match unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) } {
+                Ok(pipeline_layout) => pipeline_layout,
+                Err(err) => {
+                    destroy_layouts(&device, PipelineLayout::null(), &reflected_layouts);
+                    return Err(Error::other(err));
+                }
+            };
         let color_blending_state_info = create_color_blending_state(color_blending_attachments);
         let depth_stencil_state_info = if enable_depth_test {
             enable_depth_stencil_state()
@@ -166,24 +297,29 @@ impl VkPipeline {
             .base_pipeline_handle(Pipeline::null())
             .depth_stencil_state(&depth_stencil_state_info);
 
-        let pipeline = unsafe {
-            &device
-                .create_graphics_pipelines(
-                    PipelineCache::default(),
-                    &[graphics_pipeline_create_info],
-                    None,
-                )
-                .unwrap()
-                .into_iter()
-                .map(|pipeline| Self {
-                    pipeline,
-                    pipeline_layout,
-                    pipeline_type: PipelineType::GRAPHICS,
-                })
-                .collect::<Vec<VkPipeline>>()[0]
+        let pipeline = match unsafe {
+            device.create_graphics_pipelines(
+                PipelineCache::default(),
+                &[graphics_pipeline_create_info],
+                None,
+            )
+        } {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                destroy_layouts(&device, pipeline_layout, &reflected_layouts);
+                return Err(Error::other(err));
+            }
         };
+        // the pipeline keeps what it needs of the modules
+        drop(shader_modules);
 
-        Ok(*pipeline)
+        Ok(Self::owned(
+            device,
+            pipeline,
+            pipeline_layout,
+            PipelineType::GRAPHICS,
+            reflected_layouts,
+        ))
     }
 
     pub fn compute_pipelines(
@@ -191,9 +327,35 @@ impl VkPipeline {
         layouts: &[DescriptorSetLayout],
         shader_file_path: &str,
     ) -> Result<VkPipeline, Error> {
-        let create_info = PipelineLayoutCreateInfo::default().set_layouts(layouts);
-        let pipeline_layout = unsafe { device.create_pipeline_layout(&create_info, None).unwrap() };
-        let shader_module = load_shader_module(shader_file_path, &device.device).unwrap();
+        Self::create_compute_pipeline(device, layouts, &[], shader_file_path)
+    }
+
+    /// Same as `compute_pipelines` with a `size_of::<T>()` push constant range for the compute
+    /// stage.
+    pub fn compute_pipeline_with_push_constant<T>(
+        device: Arc<VkDevice>,
+        layouts: &[DescriptorSetLayout],
+        shader_file_path: &str,
+    ) -> Result<VkPipeline, Error> {
+        let push_constant_range = PushConstantLayout::of::<T>(ShaderStageFlags::COMPUTE)
+            .validate(device.max_push_constants_size(), ShaderStageFlags::COMPUTE)
+            .map_err(Error::other)?;
+        Self::create_compute_pipeline(device, layouts, &[push_constant_range], shader_file_path)
+    }
+
+    fn create_compute_pipeline(
+        device: Arc<VkDevice>,
+        layouts: &[DescriptorSetLayout],
+        push_constant_ranges: &[PushConstantRange],
+        shader_file_path: &str,
+    ) -> Result<VkPipeline, Error> {
+        let create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(layouts)
+            .push_constant_ranges(push_constant_ranges);
+        let mut shader_modules = ShaderModules::new(&device);
+        let shader_module = shader_modules.load(shader_file_path)?;
+        let pipeline_layout =
+            unsafe { device.create_pipeline_layout(&create_info, None) }.map_err(Error::other)?;
         let shader_stage_info = PipelineShaderStageCreateInfo::default()
             .module(shader_module)
             .name(c"main")
@@ -202,22 +364,69 @@ impl VkPipeline {
         let pipeline_create_info = vec![ComputePipelineCreateInfo::default()
             .stage(shader_stage_info)
             .layout(pipeline_layout)];
-        let pipelines = unsafe {
+        let pipeline = match unsafe {
             device.create_compute_pipelines(PipelineCache::null(), &pipeline_create_info, None)
-        }
-        .unwrap()
-        .into_iter()
-        .map(|pipeline| VkPipeline {
+        } {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                destroy_layouts(&device, pipeline_layout, &[]);
+                return Err(Error::other(err));
+            }
+        };
+        drop(shader_modules);
+
+        Ok(Self::owned(
+            device,
             pipeline,
             pipeline_layout,
-            pipeline_type: PipelineType::COMPUTE,
-        })
-        .collect::<Vec<VkPipeline>>()[0];
+            PipelineType::COMPUTE,
+            vec![],
+        ))
+    }
+}
+
+/// Shader modules loaded for one pipeline, destroyed once it is created or failed to be.
+struct ShaderModules<'a> {
+    device: &'a Device,
+    modules: Vec<ShaderModule>,
+}
+
+impl<'a> ShaderModules<'a> {
+    fn new(device: &'a Device) -> Self {
+        Self {
+            device,
+            modules: vec![],
+        }
+    }
+
+    fn load(&mut self, file_path: &str) -> Result<ShaderModule, Error> {
+        let module = load_shader_module(file_path, self.device)?;
+        self.modules.push(module);
+        Ok(module)
+    }
+}
 
-        Ok(pipelines)
+impl Drop for ShaderModules<'_> {
+    fn drop(&mut self) {
+        for &module in &self.modules {
+            unsafe { self.device.destroy_shader_module(module, None) };
+        }
     }
 }
 
+/// Cleans up after a pipeline that failed to be created.
+fn destroy_layouts(
+    device: &Device,
+    pipeline_layout: PipelineLayout,
+    set_layouts: &[DescriptorSetLayout],
+) {
+    unsafe {
+        device.destroy_pipeline_layout(pipeline_layout, None);
+        for &set_layout in set_layouts {
+            device.destroy_descriptor_set_layout(set_layout, None);
+        }
+    }
+}
 
 fn disable_depth_stencil_state<'a>() -> PipelineDepthStencilStateCreateInfo<'a> {
     PipelineDepthStencilStateCreateInfo::default()
@@ -225,7 +434,8 @@ fn disable_depth_stencil_state<'a>() -> PipelineDepthStencilStateCreateInfo<'a>
         .depth_write_enable(false)
         .stencil_test_enable(false)
         .depth_bounds_test_enable(false)
-        .depth_compare_op(CompareOp::LESS)
+        // equal passes so surfaces drawn again over a depth pre-pass are not rejected
+        .depth_compare_op(CompareOp::LESS_OR_EQUAL)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
 
@@ -237,7 +447,8 @@ fn enable_depth_stencil_state<'a>() -> PipelineDepthStencilStateCreateInfo<'a> {
         .depth_write_enable(true)
         .stencil_test_enable(false)
         .depth_bounds_test_enable(false)
-        .depth_compare_op(CompareOp::LESS)
+        // equal passes so surfaces drawn again over a depth pre-pass are not rejected
+        .depth_compare_op(CompareOp::LESS_OR_EQUAL)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
 }
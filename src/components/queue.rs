@@ -9,6 +9,10 @@ use super::{device::{QueueFamilyIndices, VkDevice}, surface::KHRSurface};
 pub enum QueueType {
     GRAPHICS_QUEUE,
     PRESENT_QUEUE,
+    /// Same queue as GRAPHICS_QUEUE on devices without a dedicated transfer family.
+    TRANSFER_QUEUE,
+    /// Same queue as GRAPHICS_QUEUE on devices without a dedicated compute family.
+    COMPUTE_QUEUE,
 }
 
 #[derive(Clone)]
@@ -30,7 +34,7 @@ impl Deref for VkQueue {
 impl VkQueue {
     pub fn new(
         device: Arc<VkDevice>,
-        surface: Arc<KHRSurface>,
+        surface: Option<Arc<KHRSurface>>,
         queue_type: QueueType
     ) -> Result<Self, Error> {
         let queue_family_indices = QueueFamilyIndices::find_queue_family_indices(
@@ -42,6 +46,8 @@ impl VkQueue {
         let queue_family_index = match queue_type {
             QueueType::GRAPHICS_QUEUE => queue_family_indices.graphics_q_idx.unwrap(),
             QueueType::PRESENT_QUEUE => queue_family_indices.presentation_q_idx.unwrap(),
+            QueueType::TRANSFER_QUEUE => queue_family_indices.transfer_q_idx.unwrap(),
+            QueueType::COMPUTE_QUEUE => queue_family_indices.compute_q_idx.unwrap(),
         };
         let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
         Ok(Self {
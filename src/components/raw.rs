@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use ash::vk::{CommandBuffer, PhysicalDevice, Queue};
+use nalgebra::Matrix4;
+
+use super::{allocation_types::AllocatedImage, device::VkDevice};
+
+/// Escape hatch to the Vulkan objects behind the renderer, to prototype what the safe API does
+/// not cover yet without forking. Nothing created through these handles is tracked, it has to
+/// be destroyed by the caller before the renderer is dropped. The queues must not be used while
+/// a frame is submitted.
+pub trait RawVulkan {
+    fn raw_instance(&self) -> &ash::Instance;
+    fn raw_device(&self) -> &ash::Device;
+    fn raw_physical_device(&self) -> PhysicalDevice;
+    /// The graphics queue and its family index.
+    fn raw_graphics_queue(&self) -> (Queue, u32);
+    /// The presentation queue and its family index, may be the graphics queue.
+    fn raw_present_queue(&self) -> (Queue, u32);
+    fn raw_allocator(&self) -> &vk_mem::Allocator;
+    /// Command buffer the next frame is recorded into. It is only recording inside a
+    /// `Renderer::with_raw_frame` callback, which gets it as `RawFrameContext::command_buffer`.
+    fn raw_frame_command_buffer(&self) -> CommandBuffer;
+}
+
+/// State a `Renderer::with_raw_frame` callback records with. It runs right after the scene
+/// render pass ended, before depth picking, the analysis passes and post processing.
+pub struct RawFrameContext<'a> {
+    /// Commands recorded through it show up in the command log.
+    pub device: &'a Arc<VkDevice>,
+    pub command_buffer: CommandBuffer,
+    pub frame_idx: usize,
+    pub graphics_queue_family_index: u32,
+    /// Resolved scene colors in TRANSFER_SRC_OPTIMAL, has to be left in that layout.
+    pub draw_image: &'a AllocatedImage,
+    /// Scene depth in DEPTH_ATTACHMENT_OPTIMAL, has to be left in that layout. Multisampled
+    /// when the renderer uses MSAA.
+    pub depth_image: &'a AllocatedImage,
+    pub view: Matrix4<f32>,
+    pub proj: Matrix4<f32>,
+}
+
+pub type RawFrameFn = Box<dyn FnOnce(&RawFrameContext)>;
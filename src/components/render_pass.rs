@@ -4,6 +4,7 @@ use ash::vk::{
     AccessFlags, AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp,
     DependencyFlags, Format, ImageLayout, PipelineBindPoint, PipelineStageFlags, RenderPass,
     RenderPassCreateInfo, SampleCountFlags, SubpassDependency, SubpassDescription,
+    SUBPASS_EXTERNAL,
 };
 
 use super::device::VkDevice;
@@ -13,6 +14,7 @@ pub struct VkRenderPass {
     render_pass: RenderPass,
     device: Arc<VkDevice>,
     format: Format,
+    samples: SampleCountFlags,
 }
 
 impl Deref for VkRenderPass {
@@ -23,6 +25,12 @@ impl Deref for VkRenderPass {
     }
 }
 
+impl Drop for VkRenderPass {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_render_pass(self.render_pass, None) };
+    }
+}
+
 impl VkRenderPass {
     pub fn new(
         device: Arc<VkDevice>,
@@ -32,28 +40,243 @@ impl VkRenderPass {
         attachment_load_op: AttachmentLoadOp,
         depth: bool,
     ) -> Result<VkRenderPass, Error> {
-        let attachment = create_attachment(
+        Self::new_multisampled(
+            device,
             format,
+            SampleCountFlags::TYPE_1,
+            initial_layout,
+            final_layout,
+            attachment_load_op,
+            depth,
+        )
+    }
+
+    /// Same as `new`, but with `samples` color and depth attachments. When `samples` is above
+    /// one, the multisampled color attachment is resolved at the end of the subpass into a third
+    /// single-sampled attachment (the second one without depth) which takes `initial_layout` and
+    /// `final_layout`.
+    pub fn new_multisampled(
+        device: Arc<VkDevice>,
+        format: Format,
+        samples: SampleCountFlags,
+        initial_layout: ImageLayout,
+        final_layout: ImageLayout,
+        attachment_load_op: AttachmentLoadOp,
+        depth: bool,
+    ) -> Result<VkRenderPass, Error> {
+        Self::create(
+            device,
+            format,
+            samples,
+            initial_layout,
+            final_layout,
+            attachment_load_op,
+            depth.then_some(AttachmentLoadOp::CLEAR),
+        )
+    }
+
+    /// Same as `new_multisampled` with depth, but the depth attachment keeps what a
+    /// `depth_only` pass wrote into it. It has to be in DEPTH_ATTACHMENT_OPTIMAL when the pass
+    /// begins.
+    pub fn new_multisampled_after_prepass(
+        device: Arc<VkDevice>,
+        format: Format,
+        samples: SampleCountFlags,
+        initial_layout: ImageLayout,
+        final_layout: ImageLayout,
+        attachment_load_op: AttachmentLoadOp,
+    ) -> Result<VkRenderPass, Error> {
+        Self::create(
+            device,
+            format,
+            samples,
             initial_layout,
             final_layout,
-            AttachmentStoreOp::STORE,
             attachment_load_op,
+            Some(AttachmentLoadOp::LOAD),
+        )
+    }
+
+    /// A single cleared `samples` depth attachment and no color, for depth pre-passes. The depth
+    /// ends up in DEPTH_ATTACHMENT_OPTIMAL.
+    pub fn depth_only(
+        device: Arc<VkDevice>,
+        samples: SampleCountFlags,
+    ) -> Result<VkRenderPass, Error> {
+        let attachments = [create_attachment(
+            Format::D32_SFLOAT,
+            samples,
+            ImageLayout::UNDEFINED,
+            ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            AttachmentStoreOp::STORE,
+            AttachmentLoadOp::CLEAR,
+        )];
+        let depth_ref = create_attachment_ref(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, 0);
+        let descriptions = [create_subpass_description(&[], Some(&depth_ref))];
+        let subpass_dependency = create_subpass_dependency(
+            DependencyFlags::BY_REGION,
+            SUBPASS_EXTERNAL,
+            0,
+            PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
         );
+        Ok(unsafe {
+            Self {
+                render_pass: device
+                    .create_render_pass(
+                        &render_pass_create_info(
+                            &attachments,
+                            &descriptions,
+                            &[subpass_dependency],
+                        ),
+                        None,
+                    )
+                    .unwrap(),
+                device,
+                format: Format::D32_SFLOAT,
+                samples,
+            }
+        })
+    }
+
+    /// One cleared single sampled attachment per entry of `color_formats` plus the scene depth,
+    /// for G-buffer passes. The colors end up in SHADER_READ_ONLY_OPTIMAL for a lighting pass to
+    /// sample, the depth in DEPTH_ATTACHMENT_OPTIMAL. With a `LOAD` `depth_load_op` the depth
+    /// has to be in DEPTH_ATTACHMENT_OPTIMAL when the pass begins.
+    pub fn gbuffer(
+        device: Arc<VkDevice>,
+        color_formats: &[Format],
+        depth_load_op: AttachmentLoadOp,
+    ) -> Result<VkRenderPass, Error> {
+        let mut attachments: Vec<AttachmentDescription> = color_formats
+            .iter()
+            .map(|format| {
+                create_attachment(
+                    *format,
+                    SampleCountFlags::TYPE_1,
+                    ImageLayout::UNDEFINED,
+                    ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    AttachmentStoreOp::STORE,
+                    AttachmentLoadOp::CLEAR,
+                )
+            })
+            .collect();
+        attachments.push(create_attachment(
+            Format::D32_SFLOAT,
+            SampleCountFlags::TYPE_1,
+            if depth_load_op == AttachmentLoadOp::LOAD {
+                ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+            } else {
+                ImageLayout::UNDEFINED
+            },
+            ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            AttachmentStoreOp::STORE,
+            depth_load_op,
+        ));
+        let color_refs: Vec<AttachmentReference> = (0..color_formats.len() as u32)
+            .map(|idx| create_attachment_ref(ImageLayout::COLOR_ATTACHMENT_OPTIMAL, idx))
+            .collect();
+        let depth_ref = create_attachment_ref(
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            color_formats.len() as u32,
+        );
+        let descriptions = [create_subpass_description(&color_refs, Some(&depth_ref))];
+        let subpass_dependencies = [
+            // a depth pre-pass may have written the depth
+            create_subpass_dependency(
+                DependencyFlags::BY_REGION,
+                SUBPASS_EXTERNAL,
+                0,
+                PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            // the lighting pass samples the colors and the scene pass keeps testing the depth
+            create_subpass_dependency(
+                DependencyFlags::empty(),
+                0,
+                SUBPASS_EXTERNAL,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                AccessFlags::COLOR_ATTACHMENT_WRITE | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                AccessFlags::SHADER_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        ];
+        Ok(unsafe {
+            Self {
+                render_pass: device
+                    .create_render_pass(
+                        &render_pass_create_info(
+                            &attachments,
+                            &descriptions,
+                            &subpass_dependencies,
+                        ),
+                        None,
+                    )
+                    .unwrap(),
+                device,
+                format: color_formats[0],
+                samples: SampleCountFlags::TYPE_1,
+            }
+        })
+    }
+
+    fn create(
+        device: Arc<VkDevice>,
+        format: Format,
+        samples: SampleCountFlags,
+        initial_layout: ImageLayout,
+        final_layout: ImageLayout,
+        attachment_load_op: AttachmentLoadOp,
+        depth_load_op: Option<AttachmentLoadOp>,
+    ) -> Result<VkRenderPass, Error> {
+        let depth = depth_load_op.is_some();
+        let multisampled = samples != SampleCountFlags::TYPE_1;
+        let attachment = if multisampled {
+            create_attachment(
+                format,
+                samples,
+                ImageLayout::UNDEFINED,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                AttachmentStoreOp::DONT_CARE,
+                attachment_load_op,
+            )
+        } else {
+            create_attachment(
+                format,
+                samples,
+                initial_layout,
+                final_layout,
+                AttachmentStoreOp::STORE,
+                attachment_load_op,
+            )
+        };
         let attachment_ref = vec![create_attachment_ref(
             ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             0,
         )];
-        let depth_attachment = if depth {
-            Some(create_attachment(
+        let depth_attachment = depth_load_op.map(|depth_load_op| {
+            create_attachment(
                 Format::D32_SFLOAT,
-                ImageLayout::UNDEFINED,
+                samples,
+                if depth_load_op == AttachmentLoadOp::LOAD {
+                    ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+                } else {
+                    ImageLayout::UNDEFINED
+                },
                 ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
-                AttachmentStoreOp::DONT_CARE,
-                AttachmentLoadOp::CLEAR,
-            ))
-        } else {
-            None
-        };
+                // kept for the depth picker
+                AttachmentStoreOp::STORE,
+                depth_load_op,
+            )
+        });
         let depth_ref = if depth {
             Some(create_attachment_ref(
                 ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
@@ -63,7 +286,27 @@ impl VkRenderPass {
             None
         };
 
-        let subpass_description = create_subpass_description(&attachment_ref, depth_ref.as_ref());
+        let resolve_index = if depth { 2 } else { 1 };
+        let resolve_attachment = multisampled.then(|| {
+            create_attachment(
+                format,
+                SampleCountFlags::TYPE_1,
+                initial_layout,
+                final_layout,
+                AttachmentStoreOp::STORE,
+                AttachmentLoadOp::DONT_CARE,
+            )
+        });
+        let resolve_ref = vec![create_attachment_ref(
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            resolve_index,
+        )];
+
+        let mut subpass_description =
+            create_subpass_description(&attachment_ref, depth_ref.as_ref());
+        if multisampled {
+            subpass_description = subpass_description.resolve_attachments(&resolve_ref);
+        }
 
         let subpass_dependency = create_subpass_dependency(
             DependencyFlags::BY_REGION,
@@ -74,11 +317,12 @@ impl VkRenderPass {
             AccessFlags::COLOR_ATTACHMENT_READ,
             AccessFlags::SHADER_READ,
         );
-        let attachments = if depth {
+        let mut attachments = if depth {
             vec![attachment, depth_attachment.unwrap()]
         } else {
             vec![attachment]
         };
+        attachments.extend(resolve_attachment);
         let descriptions = vec![subpass_description];
         Ok(unsafe {
             Self {
@@ -94,9 +338,16 @@ impl VkRenderPass {
                     .unwrap(),
                 device,
                 format,
+                samples,
             }
         })
     }
+
+    /// Sample count of the color and depth attachments, pipelines used in this render pass
+    /// need a matching multisample state.
+    pub fn samples(&self) -> SampleCountFlags {
+        self.samples
+    }
 }
 
 fn render_pass_create_info<'a>(
@@ -131,6 +382,7 @@ fn create_subpass_dependency(
 
 fn create_attachment(
     image_format: Format,
+    samples: SampleCountFlags,
     initial_layout: ImageLayout,
     final_layout: ImageLayout,
     store_op: AttachmentStoreOp,
@@ -138,7 +390,7 @@ fn create_attachment(
 ) -> AttachmentDescription {
     AttachmentDescription::default()
         .format(image_format)
-        .samples(SampleCountFlags::TYPE_1)
+        .samples(samples)
         .load_op(load_op)
         .store_op(store_op)
         .stencil_load_op(AttachmentLoadOp::CLEAR)
@@ -1,16 +1,43 @@
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
 
 use ash::vk::{
-    BorderColor, CompareOp, Filter, Sampler, SamplerAddressMode, SamplerCreateInfo,
-    SamplerMipmapMode,
+    BorderColor, CompareOp, Filter, Sampler, SamplerAddressMode, SamplerCreateFlags,
+    SamplerCreateInfo, SamplerMipmapMode, LOD_CLAMP_NONE,
 };
 
 use super::device::VkDevice;
 
+/// Destroys the sampler once the last `VkSampler` sharing it is dropped.
+struct SamplerOwner {
+    device: Arc<VkDevice>,
+    sampler: Sampler,
+}
+
+impl Drop for SamplerOwner {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_sampler(self.sampler, None) };
+    }
+}
+
+/// Clones share the sampler, whoever writes it into a descriptor set has to keep a clone for
+/// as long as the set is used.
 #[derive(Clone)]
 pub struct VkSampler {
     sampler: Sampler,
-    _device: Arc<VkDevice>,
+    _owner: Arc<SamplerOwner>,
+}
+
+impl Debug for VkSampler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sampler")
+            .field("Sampler", &self.sampler)
+            .finish()
+    }
 }
 
 impl Deref for VkSampler {
@@ -22,24 +49,48 @@ impl Deref for VkSampler {
 }
 
 impl VkSampler {
-    pub fn with_filter(device: Arc<VkDevice>, min_filter: Filter, mag_filter: Filter) -> VkSampler {
-        let create_info = SamplerCreateInfo::default()
-            .mag_filter(mag_filter)
-            .min_filter(min_filter);
-
+    fn create(device: Arc<VkDevice>, create_info: &SamplerCreateInfo) -> VkSampler {
+        let sampler = unsafe { device.create_sampler(create_info, None).unwrap() };
         VkSampler {
-            sampler: unsafe { device.create_sampler(&create_info, None).unwrap() },
-            _device: device,
+            sampler,
+            _owner: Arc::new(SamplerOwner { device, sampler }),
         }
     }
 
+    pub fn with_filter(device: Arc<VkDevice>, min_filter: Filter, mag_filter: Filter) -> VkSampler {
+        Self::create(device, &Self::filter_info(min_filter, mag_filter))
+    }
+
     pub fn get_font_sampler(device: Arc<VkDevice>) -> VkSampler {
+        let create_info = Self::font_info(&device);
+        Self::create(device, &create_info)
+    }
+
+    pub fn get_texture_sampler(device: Arc<VkDevice>) -> VkSampler {
+        let create_info = Self::texture_info(&device);
+        Self::create(device, &create_info)
+    }
+
+    /// Trilinear, repeating sampler that reads every mip level, for textures created with
+    /// `MemoryAllocator::create_image_from_file`.
+    pub fn get_mipmapped_sampler(device: Arc<VkDevice>) -> VkSampler {
+        let create_info = Self::mipmapped_info(&device);
+        Self::create(device, &create_info)
+    }
+
+    fn filter_info(min_filter: Filter, mag_filter: Filter) -> SamplerCreateInfo<'static> {
+        SamplerCreateInfo::default()
+            .mag_filter(mag_filter)
+            .min_filter(min_filter)
+    }
+
+    fn font_info(device: &VkDevice) -> SamplerCreateInfo<'static> {
         let properties = unsafe {
             device
                 .instance
                 .get_physical_device_properties(device.physical_device)
         };
-        let create_info = SamplerCreateInfo::default()
+        SamplerCreateInfo::default()
             .mag_filter(Filter::LINEAR)
             .min_filter(Filter::LINEAR)
             // REPEAT NOT FOR UI FONTS
@@ -55,20 +106,16 @@ impl VkSampler {
             .mipmap_mode(SamplerMipmapMode::NEAREST)
             .mip_lod_bias(0.0)
             .min_lod(0.0)
-            .max_lod(0.0);
-        Self {
-            sampler: unsafe { device.create_sampler(&create_info, None).unwrap() },
-            _device: device,
-        }
+            .max_lod(0.0)
     }
 
-    pub fn get_texture_sampler(device: Arc<VkDevice>) -> VkSampler {
+    fn texture_info(device: &VkDevice) -> SamplerCreateInfo<'static> {
         let properties = unsafe {
             device
                 .instance
                 .get_physical_device_properties(device.physical_device)
         };
-        let create_info = SamplerCreateInfo::default()
+        SamplerCreateInfo::default()
             .mag_filter(Filter::LINEAR)
             .min_filter(Filter::LINEAR)
             // REPEAT NOT FOR UI FONTS
@@ -84,10 +131,157 @@ impl VkSampler {
             .mipmap_mode(SamplerMipmapMode::NEAREST)
             .mip_lod_bias(0.0)
             .min_lod(0.0)
-            .max_lod(0.0);
+            .max_lod(0.0)
+    }
+
+    fn mipmapped_info(device: &VkDevice) -> SamplerCreateInfo<'static> {
+        let properties = unsafe {
+            device
+                .instance
+                .get_physical_device_properties(device.physical_device)
+        };
+        SamplerCreateInfo::default()
+            .mag_filter(Filter::LINEAR)
+            .min_filter(Filter::LINEAR)
+            .address_mode_u(SamplerAddressMode::REPEAT)
+            .address_mode_v(SamplerAddressMode::REPEAT)
+            .address_mode_w(SamplerAddressMode::REPEAT)
+            .anisotropy_enable(true)
+            .max_anisotropy(properties.limits.max_sampler_anisotropy)
+            .border_color(BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_op(CompareOp::ALWAYS)
+            .compare_enable(false)
+            .mipmap_mode(SamplerMipmapMode::LINEAR)
+            .mip_lod_bias(0.0)
+            .min_lod(0.0)
+            .max_lod(LOD_CLAMP_NONE)
+    }
+}
+
+/// The fields of a `SamplerCreateInfo` that end up in the sampler, floats by their bits.
+/// Extension structs in `p_next` are not part of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    flags: SamplerCreateFlags,
+    mag_filter: Filter,
+    min_filter: Filter,
+    mipmap_mode: SamplerMipmapMode,
+    address_modes: [SamplerAddressMode; 3],
+    mip_lod_bias: u32,
+    anisotropy_enable: u32,
+    max_anisotropy: u32,
+    compare_enable: u32,
+    compare_op: CompareOp,
+    min_lod: u32,
+    max_lod: u32,
+    border_color: BorderColor,
+    unnormalized_coordinates: u32,
+}
+
+impl SamplerKey {
+    fn new(create_info: &SamplerCreateInfo) -> Self {
+        Self {
+            flags: create_info.flags,
+            mag_filter: create_info.mag_filter,
+            min_filter: create_info.min_filter,
+            mipmap_mode: create_info.mipmap_mode,
+            address_modes: [
+                create_info.address_mode_u,
+                create_info.address_mode_v,
+                create_info.address_mode_w,
+            ],
+            mip_lod_bias: create_info.mip_lod_bias.to_bits(),
+            anisotropy_enable: create_info.anisotropy_enable,
+            max_anisotropy: create_info.max_anisotropy.to_bits(),
+            compare_enable: create_info.compare_enable,
+            compare_op: create_info.compare_op,
+            min_lod: create_info.min_lod.to_bits(),
+            max_lod: create_info.max_lod.to_bits(),
+            border_color: create_info.border_color,
+            unnormalized_coordinates: create_info.unnormalized_coordinates,
+        }
+    }
+}
+
+/// Creates one sampler per distinct `SamplerCreateInfo`, clones of the cache share the
+/// samplers. A sampler stays alive until the cache and every clone handed out are dropped.
+#[derive(Clone)]
+pub struct SamplerCache {
+    device: Arc<VkDevice>,
+    samplers: Arc<Mutex<HashMap<SamplerKey, VkSampler>>>,
+}
+
+impl SamplerCache {
+    pub fn new(device: Arc<VkDevice>) -> Self {
         Self {
-            sampler: unsafe { device.create_sampler(&create_info, None).unwrap() },
-            _device: device,
+            device,
+            samplers: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+
+    pub fn get(&self, create_info: &SamplerCreateInfo) -> VkSampler {
+        self.samplers
+            .lock()
+            .unwrap()
+            .entry(SamplerKey::new(create_info))
+            .or_insert_with(|| VkSampler::create(self.device.clone(), create_info))
+            .clone()
+    }
+
+    pub fn with_filter(&self, min_filter: Filter, mag_filter: Filter) -> VkSampler {
+        self.get(&VkSampler::filter_info(min_filter, mag_filter))
+    }
+
+    pub fn font_sampler(&self) -> VkSampler {
+        self.get(&VkSampler::font_info(&self.device))
+    }
+
+    pub fn texture_sampler(&self) -> VkSampler {
+        self.get(&VkSampler::texture_info(&self.device))
+    }
+
+    pub fn mipmapped_sampler(&self) -> VkSampler {
+        self.get(&VkSampler::mipmapped_info(&self.device))
+    }
+
+    /// Number of distinct samplers created so far.
+    pub fn len(&self) -> usize {
+        self.samplers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampler_key_matches_equal_create_infos() {
+        let nearest = VkSampler::filter_info(Filter::NEAREST, Filter::NEAREST);
+        assert_eq!(
+            SamplerKey::new(&nearest),
+            SamplerKey::new(&VkSampler::filter_info(Filter::NEAREST, Filter::NEAREST))
+        );
+        assert_ne!(
+            SamplerKey::new(&nearest),
+            SamplerKey::new(&VkSampler::filter_info(Filter::NEAREST, Filter::LINEAR))
+        );
+    }
+
+    #[test]
+    fn sampler_key_compares_float_fields() {
+        let clamped = SamplerCreateInfo::default().max_lod(0.0);
+        assert_ne!(
+            SamplerKey::new(&clamped),
+            SamplerKey::new(&clamped.max_lod(LOD_CLAMP_NONE))
+        );
+        assert_ne!(
+            SamplerKey::new(&clamped),
+            SamplerKey::new(&clamped.mip_lod_bias(0.5))
+        );
+    }
 }
@@ -0,0 +1,147 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Result;
+use ash::vk::{
+    CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferUsageFlags,
+    CommandPoolResetFlags, Framebuffer, RenderPass,
+};
+
+use super::{
+    command_buffers::VkCommandPool,
+    deletion_queue::{DeletionQueue, DestroyCommandPoolTask, FType},
+    device::VkDevice,
+    queue::VkQueue,
+};
+
+/// Secondary command buffers of one frame in flight, recorded in parallel and executed inside
+/// the scene render pass. Command pools are externally synchronized, so every buffer that can
+/// be recorded on its own thread comes from a pool of its own.
+pub struct SecondaryCommands {
+    device: Arc<VkDevice>,
+    pools: Vec<VkCommandPool>,
+    /// One per recording thread, each allocated from its own pool.
+    chunks: Vec<CommandBuffer>,
+    /// Recorded on the calling thread before and after the chunks, from the last pool.
+    prologue: CommandBuffer,
+    epilogue: CommandBuffer,
+}
+
+impl SecondaryCommands {
+    /// The pools are destroyed with `deletion_queue`.
+    pub fn new(
+        device: Arc<VkDevice>,
+        queue: Arc<VkQueue>,
+        threads: usize,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Self {
+        let pools: Vec<VkCommandPool> = (0..=threads.max(1))
+            .map(|_| VkCommandPool::new(queue.clone()))
+            .collect();
+        for pool in &pools {
+            deletion_queue.enqueue(FType::TASK(Box::new(DestroyCommandPoolTask { pool: **pool })));
+        }
+        let (main_pool, chunk_pools) = pools.split_last().unwrap();
+        let chunks = chunk_pools
+            .iter()
+            .map(|pool| pool.allocate_secondary_command_buffers(1)[0])
+            .collect();
+        let main_buffers = main_pool.allocate_secondary_command_buffers(2);
+        Self {
+            device,
+            chunks,
+            prologue: main_buffers[0],
+            epilogue: main_buffers[1],
+            pools,
+        }
+    }
+
+    /// Most chunks the draws can be split into, one per recording thread.
+    pub fn max_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn chunk(&self, idx: usize) -> CommandBuffer {
+        self.chunks[idx]
+    }
+
+    pub fn prologue(&self) -> CommandBuffer {
+        self.prologue
+    }
+
+    pub fn epilogue(&self) -> CommandBuffer {
+        self.epilogue
+    }
+
+    /// Resets every buffer for recording again, the previous submission of the frame must
+    /// have completed.
+    pub fn reset(&self) -> Result<()> {
+        for pool in &self.pools {
+            unsafe {
+                self.device
+                    .reset_command_pool(**pool, CommandPoolResetFlags::empty())?
+            };
+        }
+        Ok(())
+    }
+
+    /// Begins `cmd` for recording inside the first subpass of `render_pass` on `framebuffer`.
+    /// Nothing is inherited from the primary buffer but the render pass, dynamic state has to
+    /// be set again.
+    pub fn begin(
+        &self,
+        cmd: CommandBuffer,
+        render_pass: RenderPass,
+        framebuffer: Framebuffer,
+    ) -> Result<()> {
+        let inheritance_info = CommandBufferInheritanceInfo::default()
+            .render_pass(render_pass)
+            .subpass(0)
+            .framebuffer(framebuffer);
+        unsafe {
+            self.device.begin_command_buffer(
+                cmd,
+                &CommandBufferBeginInfo::default()
+                    .flags(
+                        CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                            | CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+                    )
+                    .inheritance_info(&inheritance_info),
+            )?
+        };
+        Ok(())
+    }
+}
+
+/// Splits `len` draws into at most `max_chunks` contiguous ranges of at least
+/// `min_chunk_len` draws each, sized as evenly as possible. Fewer than two ranges mean the
+/// draws are not worth recording in parallel.
+pub fn chunk_ranges(len: usize, max_chunks: usize, min_chunk_len: usize) -> Vec<Range<usize>> {
+    let chunks = (len / min_chunk_len.max(1)).clamp(1, max_chunks.max(1));
+    let (size, remainder) = (len / chunks, len % chunks);
+    let mut start = 0;
+    (0..chunks)
+        .map(|idx| {
+            let end = start + size + usize::from(idx < remainder);
+            let range = start..end;
+            start = end;
+            range
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chunk_ranges;
+
+    #[test]
+    fn splits_draws_evenly() {
+        assert_eq!(chunk_ranges(10, 3, 2), vec![0..4, 4..7, 7..10]);
+        assert_eq!(chunk_ranges(10, 8, 4), vec![0..5, 5..10]);
+    }
+
+    #[test]
+    fn small_draw_lists_stay_in_one_chunk() {
+        assert_eq!(chunk_ranges(3, 8, 4), vec![0..3]);
+        assert_eq!(chunk_ranges(0, 8, 4), vec![0..0]);
+    }
+}
@@ -0,0 +1,695 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    DescriptorBindingFlags, DescriptorSetLayout, DescriptorSetLayoutBinding,
+    DescriptorSetLayoutBindingFlagsCreateInfo, DescriptorSetLayoutCreateFlags,
+    DescriptorSetLayoutCreateInfo, DescriptorType, Format, ShaderStageFlags,
+    VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate,
+};
+
+use crate::geom::{format_size, push_constants::PushConstantLayout};
+
+use super::{device::VkDevice, pipeline::VertexInput};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// A descriptor a shader declares. A `count` of 0 is a runtime sized array, like the bindless
+/// textures, whose size has to be given with `PipelineReflection::override_binding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: DescriptorType,
+    pub count: u32,
+    pub stages: ShaderStageFlags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectedInput {
+    pub location: u32,
+    pub format: Format,
+}
+
+/// Resources one shader module declares, see `reflect`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShaderReflection {
+    pub stage: ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Bytes of the push constant block, 0 without one.
+    pub push_constant_size: u32,
+    /// Vertex inputs by location, only filled for vertex shaders.
+    pub inputs: Vec<ReflectedInput>,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar { width: u32, float: bool, signed: bool },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, columns: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    AccelerationStructure,
+    Array { element: u32, length: Option<u32> },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+    Other,
+}
+
+#[derive(Default)]
+struct Module {
+    stage: ShaderStageFlags,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Decoration literals of every id, by decoration.
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    /// Byte size of `type_id` as laid out by the explicit offsets and strides of the module.
+    fn size_of(&self, type_id: u32) -> u32 {
+        match self.types.get(&type_id) {
+            Some(Type::Scalar { width, .. }) => width / 8,
+            Some(Type::Vector { component, count }) => self.size_of(*component) * count,
+            Some(Type::Matrix { column, columns }) => {
+                let stride = self
+                    .decoration(type_id, DECORATION_MATRIX_STRIDE)
+                    .unwrap_or_else(|| self.size_of(*column));
+                stride * columns
+            }
+            Some(Type::Array { element, length }) => {
+                let stride = self
+                    .decoration(type_id, DECORATION_ARRAY_STRIDE)
+                    .unwrap_or_else(|| self.size_of(*element));
+                stride * length.unwrap_or(0)
+            }
+            Some(Type::Struct { members }) => members
+                .iter()
+                .enumerate()
+                .map(|(idx, &member)| {
+                    let offset = self
+                        .member_decorations
+                        .get(&(type_id, idx as u32, DECORATION_OFFSET))
+                        .copied()
+                        .unwrap_or(0);
+                    let size = match self.types.get(&member) {
+                        Some(Type::Matrix { columns, .. }) => self
+                            .member_decorations
+                            .get(&(type_id, idx as u32, DECORATION_MATRIX_STRIDE))
+                            .map_or_else(|| self.size_of(member), |stride| stride * columns),
+                        _ => self.size_of(member),
+                    };
+                    offset + size
+                })
+                .max()
+                .unwrap_or(0),
+            // buffer references are 64 bit device addresses
+            Some(Type::Pointer { .. }) => 8,
+            _ => 0,
+        }
+    }
+
+    fn descriptor(&self, type_id: u32, storage_class: u32) -> Option<(DescriptorType, u32)> {
+        let mut count = 1;
+        let mut type_id = type_id;
+        while let Some(Type::Array { element, length }) = self.types.get(&type_id) {
+            count = length.map_or(0, |length| count * length);
+            type_id = *element;
+        }
+        let descriptor_type = match (self.types.get(&type_id)?, storage_class) {
+            (Type::Struct { .. }, STORAGE_STORAGE_BUFFER) => DescriptorType::STORAGE_BUFFER,
+            (Type::Struct { .. }, STORAGE_UNIFORM) => {
+                if self.decoration(type_id, DECORATION_BUFFER_BLOCK).is_some() {
+                    DescriptorType::STORAGE_BUFFER
+                } else if self.decoration(type_id, DECORATION_BLOCK).is_some() {
+                    DescriptorType::UNIFORM_BUFFER
+                } else {
+                    return None;
+                }
+            }
+            (Type::SampledImage, _) => DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (Type::Sampler, _) => DescriptorType::SAMPLER,
+            (Type::AccelerationStructure, _) => DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (Type::Image { dim, sampled }, _) => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => DescriptorType::STORAGE_IMAGE,
+                _ => DescriptorType::SAMPLED_IMAGE,
+            },
+            _ => return None,
+        };
+        Some((descriptor_type, count))
+    }
+
+    fn input_format(&self, type_id: u32) -> Option<Format> {
+        let (component, count) = match self.types.get(&type_id)? {
+            Type::Vector { component, count } => (*component, *count),
+            Type::Scalar { .. } => (type_id, 1),
+            _ => return None,
+        };
+        let Type::Scalar { width: 32, float, signed } = self.types.get(&component)? else {
+            return None;
+        };
+        let formats = match (float, signed) {
+            (true, _) => [
+                Format::R32_SFLOAT,
+                Format::R32G32_SFLOAT,
+                Format::R32G32B32_SFLOAT,
+                Format::R32G32B32A32_SFLOAT,
+            ],
+            (false, true) => [
+                Format::R32_SINT,
+                Format::R32G32_SINT,
+                Format::R32G32B32_SINT,
+                Format::R32G32B32A32_SINT,
+            ],
+            (false, false) => [
+                Format::R32_UINT,
+                Format::R32G32_UINT,
+                Format::R32G32B32_UINT,
+                Format::R32G32B32A32_UINT,
+            ],
+        };
+        formats.get(count as usize - 1).copied()
+    }
+}
+
+fn execution_stage(model: u32) -> ShaderStageFlags {
+    match model {
+        0 => ShaderStageFlags::VERTEX,
+        1 => ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => ShaderStageFlags::GEOMETRY,
+        4 => ShaderStageFlags::FRAGMENT,
+        5 => ShaderStageFlags::COMPUTE,
+        _ => ShaderStageFlags::empty(),
+    }
+}
+
+/// Reads the descriptors, push constant block and vertex inputs the SPIR-V `code` declares.
+/// Only what the layout of a pipeline needs is parsed, the module is not validated.
+pub fn reflect(code: &[u32]) -> Result<ShaderReflection> {
+    if code.len() < 5 || code[0] != SPIRV_MAGIC {
+        return Err(anyhow!("Not a SPIR-V module"));
+    }
+    let mut module = Module::default();
+    let mut words = &code[5..];
+    while let Some(&first) = words.first() {
+        let (length, opcode) = ((first >> 16) as usize, first & 0xffff);
+        if length == 0 || length > words.len() {
+            return Err(anyhow!("SPIR-V instruction {opcode} is truncated"));
+        }
+        let operands = &words[1..length];
+        words = &words[length..];
+        let operand = |idx: usize| operands.get(idx).copied().unwrap_or(0);
+        match opcode {
+            OP_ENTRY_POINT => module.stage |= execution_stage(operand(0)),
+            OP_DECORATE => {
+                module
+                    .decorations
+                    .insert((operand(0), operand(1)), operand(2));
+            }
+            OP_MEMBER_DECORATE => {
+                module
+                    .member_decorations
+                    .insert((operand(0), operand(1), operand(2)), operand(3));
+            }
+            OP_CONSTANT => {
+                module.constants.insert(operand(1), operand(2));
+            }
+            OP_VARIABLE => module.variables.push((operand(0), operand(1), operand(2))),
+            _ => {
+                let ty = match opcode {
+                    OP_TYPE_BOOL => Type::Scalar {
+                        width: 32,
+                        float: false,
+                        signed: false,
+                    },
+                    OP_TYPE_INT => Type::Scalar {
+                        width: operand(1),
+                        float: false,
+                        signed: operand(2) == 1,
+                    },
+                    OP_TYPE_FLOAT => Type::Scalar {
+                        width: operand(1),
+                        float: true,
+                        signed: true,
+                    },
+                    OP_TYPE_VECTOR => Type::Vector {
+                        component: operand(1),
+                        count: operand(2),
+                    },
+                    OP_TYPE_MATRIX => Type::Matrix {
+                        column: operand(1),
+                        columns: operand(2),
+                    },
+                    OP_TYPE_IMAGE => Type::Image {
+                        dim: operand(2),
+                        sampled: operand(6),
+                    },
+                    OP_TYPE_SAMPLER => Type::Sampler,
+                    OP_TYPE_SAMPLED_IMAGE => Type::SampledImage,
+                    OP_TYPE_ACCELERATION_STRUCTURE => Type::AccelerationStructure,
+                    OP_TYPE_ARRAY => Type::Array {
+                        element: operand(1),
+                        length: Some(module.constants.get(&operand(2)).copied().unwrap_or(1)),
+                    },
+                    OP_TYPE_RUNTIME_ARRAY => Type::Array {
+                        element: operand(1),
+                        length: None,
+                    },
+                    OP_TYPE_STRUCT => Type::Struct {
+                        members: operands[1..].to_vec(),
+                    },
+                    OP_TYPE_POINTER => Type::Pointer {
+                        pointee: operand(2),
+                    },
+                    _ => Type::Other,
+                };
+                if !matches!(ty, Type::Other) {
+                    module.types.insert(operand(0), ty);
+                }
+            }
+        }
+    }
+
+    let mut reflection = ShaderReflection {
+        stage: module.stage,
+        ..Default::default()
+    };
+    for &(pointer_type, id, storage_class) in &module.variables {
+        let Some(Type::Pointer { pointee }) = module.types.get(&pointer_type) else {
+            continue;
+        };
+        match storage_class {
+            STORAGE_PUSH_CONSTANT => reflection.push_constant_size = module.size_of(*pointee),
+            STORAGE_INPUT if module.stage == ShaderStageFlags::VERTEX => {
+                if module.decoration(id, DECORATION_BUILT_IN).is_some() {
+                    continue;
+                }
+                let (Some(location), Some(format)) = (
+                    module.decoration(id, DECORATION_LOCATION),
+                    module.input_format(*pointee),
+                ) else {
+                    continue;
+                };
+                reflection.inputs.push(ReflectedInput { location, format });
+            }
+            STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                let (Some(set), Some(binding)) = (
+                    module.decoration(id, DECORATION_DESCRIPTOR_SET),
+                    module.decoration(id, DECORATION_BINDING),
+                ) else {
+                    continue;
+                };
+                if let Some((descriptor_type, count)) = module.descriptor(*pointee, storage_class) {
+                    reflection.bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type,
+                        count,
+                        stages: module.stage,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    reflection.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    reflection.inputs.sort_by_key(|input| input.location);
+    Ok(reflection)
+}
+
+/// The layout every stage of a pipeline needs, merged from their `ShaderReflection`s.
+/// Bindings can be overridden before the set layouts are created, for runtime sized arrays or
+/// descriptors the pipeline should see differently than the shaders declare them.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineReflection {
+    pub bindings: Vec<ReflectedBinding>,
+    pub push_constants: Option<PushConstantLayout>,
+    pub inputs: Vec<ReflectedInput>,
+    binding_flags: HashMap<(u32, u32), DescriptorBindingFlags>,
+}
+
+impl PipelineReflection {
+    pub fn merge(shaders: &[ShaderReflection]) -> Self {
+        let mut merged = Self::default();
+        for shader in shaders {
+            for binding in &shader.bindings {
+                match merged
+                    .bindings
+                    .iter_mut()
+                    .find(|known| (known.set, known.binding) == (binding.set, binding.binding))
+                {
+                    Some(known) => known.stages |= binding.stages,
+                    None => merged.bindings.push(*binding),
+                }
+            }
+            if shader.push_constant_size > 0 {
+                let layout = merged
+                    .push_constants
+                    .get_or_insert(PushConstantLayout::new(0, ShaderStageFlags::empty()));
+                layout.size = layout.size.max(shader.push_constant_size);
+                layout.stages |= shader.stage;
+            }
+            if shader.stage == ShaderStageFlags::VERTEX {
+                merged.inputs = shader.inputs.clone();
+            }
+        }
+        merged.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        merged
+    }
+
+    /// Replaces the type and count of a binding, or adds it for every stage of the pipeline.
+    pub fn override_binding(
+        &mut self,
+        set: u32,
+        binding: u32,
+        descriptor_type: DescriptorType,
+        count: u32,
+    ) -> &mut Self {
+        let stages = self
+            .bindings
+            .iter()
+            .fold(ShaderStageFlags::empty(), |stages, binding| stages | binding.stages);
+        match self
+            .bindings
+            .iter_mut()
+            .find(|known| (known.set, known.binding) == (set, binding))
+        {
+            Some(known) => {
+                known.descriptor_type = descriptor_type;
+                known.count = count;
+            }
+            None => {
+                self.bindings.push(ReflectedBinding {
+                    set,
+                    binding,
+                    descriptor_type,
+                    count,
+                    stages,
+                });
+                self.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+            }
+        }
+        self
+    }
+
+    /// Creates a binding with `flags`, like the partially bound, update after bind arrays of
+    /// the bindless set. Their sets get the update after bind pool flag.
+    pub fn binding_flags(
+        &mut self,
+        set: u32,
+        binding: u32,
+        flags: DescriptorBindingFlags,
+    ) -> &mut Self {
+        self.binding_flags.insert((set, binding), flags);
+        self
+    }
+
+    /// Bindings of every set up to the highest one used, sets in between stay empty.
+    pub fn set_layout_bindings(&self) -> Result<Vec<Vec<DescriptorSetLayoutBinding<'static>>>> {
+        let mut sets: BTreeMap<u32, Vec<DescriptorSetLayoutBinding<'static>>> = BTreeMap::new();
+        for binding in &self.bindings {
+            if binding.count == 0 {
+                return Err(anyhow!(
+                    "Set {} binding {} is a runtime sized array, its count has to be overridden",
+                    binding.set,
+                    binding.binding
+                ));
+            }
+            sets.entry(binding.set).or_default().push(
+                DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stages),
+            );
+        }
+        let set_count = sets.keys().last().map_or(0, |last| last + 1);
+        Ok((0..set_count)
+            .map(|set| sets.remove(&set).unwrap_or_default())
+            .collect())
+    }
+
+    /// Creates a layout for every set, the caller destroys them with the pipeline layout.
+    pub fn create_set_layouts(&self, device: &Arc<VkDevice>) -> Result<Vec<DescriptorSetLayout>> {
+        self.set_layout_bindings()?
+            .into_iter()
+            .enumerate()
+            .map(|(set, bindings)| {
+                let flags: Vec<DescriptorBindingFlags> = bindings
+                    .iter()
+                    .map(|binding| {
+                        self.binding_flags
+                            .get(&(set as u32, binding.binding))
+                            .copied()
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                let mut binding_flags =
+                    DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&flags);
+                let create_flags = if flags
+                    .iter()
+                    .any(|flags| flags.contains(DescriptorBindingFlags::UPDATE_AFTER_BIND))
+                {
+                    DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL
+                } else {
+                    DescriptorSetLayoutCreateFlags::empty()
+                };
+                let create_info = DescriptorSetLayoutCreateInfo::default()
+                    .bindings(&bindings)
+                    .flags(create_flags)
+                    .push_next(&mut binding_flags);
+                Ok(unsafe { device.create_descriptor_set_layout(&create_info, None)? })
+            })
+            .collect()
+    }
+
+    /// Fixed function input of the vertex shader attributes from one vertex buffer, packed
+    /// in the order of their locations.
+    pub fn vertex_input(&self) -> VertexInput {
+        if self.inputs.is_empty() {
+            return VertexInput::Pulling;
+        }
+        let mut offset = 0;
+        let attributes: Vec<VertexInputAttributeDescription> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let attribute = VertexInputAttributeDescription::default()
+                    .binding(0)
+                    .location(input.location)
+                    .format(input.format)
+                    .offset(offset);
+                offset += format_size(input.format) as u32;
+                attribute
+            })
+            .collect();
+        VertexInput::Bindings {
+            bindings: vec![VertexInputBindingDescription::default()
+                .binding(0)
+                .stride(offset)
+                .input_rate(VertexInputRate::VERTEX)],
+            attributes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    /// A vertex shader with `vec3` and `uvec2` inputs, a `mat4` and buffer address push
+    /// constant block, a uniform block in set 0 and a runtime array of combined image samplers
+    /// in set 1.
+    fn vertex_module() -> Vec<u32> {
+        let (float, uint, vec3, uvec2, vec4, mat4) = (1, 2, 3, 4, 5, 6);
+        let (push_struct, push_pointer, push_variable) = (10, 11, 12);
+        let (address_pointer, uniform_struct, uniform_pointer, uniform_variable) = (13, 14, 15, 16);
+        let (image, sampled_image, textures, textures_pointer, textures_variable) =
+            (20, 21, 22, 23, 24);
+        let (vec3_pointer, uvec2_pointer, position, joints, vertex_index) = (30, 31, 32, 33, 34);
+        let uint_pointer = 35;
+        let mut code = vec![SPIRV_MAGIC, 0x0001_0500, 0, 100, 0];
+        for words in [
+            op(OP_ENTRY_POINT, &[0, 99, 0x6e69_616d, 0]),
+            op(OP_DECORATE, &[push_struct, DECORATION_BLOCK]),
+            op(OP_MEMBER_DECORATE, &[push_struct, 0, DECORATION_OFFSET, 0]),
+            op(OP_MEMBER_DECORATE, &[push_struct, 0, DECORATION_MATRIX_STRIDE, 16]),
+            op(OP_MEMBER_DECORATE, &[push_struct, 1, DECORATION_OFFSET, 64]),
+            op(OP_DECORATE, &[uniform_struct, DECORATION_BLOCK]),
+            op(OP_DECORATE, &[uniform_variable, DECORATION_DESCRIPTOR_SET, 0]),
+            op(OP_DECORATE, &[uniform_variable, DECORATION_BINDING, 0]),
+            op(OP_DECORATE, &[textures_variable, DECORATION_DESCRIPTOR_SET, 1]),
+            op(OP_DECORATE, &[textures_variable, DECORATION_BINDING, 1]),
+            op(OP_DECORATE, &[position, DECORATION_LOCATION, 0]),
+            op(OP_DECORATE, &[joints, DECORATION_LOCATION, 1]),
+            op(OP_DECORATE, &[vertex_index, DECORATION_BUILT_IN, 42]),
+            op(OP_TYPE_FLOAT, &[float, 32]),
+            op(OP_TYPE_INT, &[uint, 32, 0]),
+            op(OP_TYPE_VECTOR, &[vec3, float, 3]),
+            op(OP_TYPE_VECTOR, &[uvec2, uint, 2]),
+            op(OP_TYPE_VECTOR, &[vec4, float, 4]),
+            op(OP_TYPE_MATRIX, &[mat4, vec4, 4]),
+            op(OP_TYPE_POINTER, &[address_pointer, 5349, vec4]),
+            op(OP_TYPE_STRUCT, &[push_struct, mat4, address_pointer]),
+            op(OP_TYPE_POINTER, &[push_pointer, STORAGE_PUSH_CONSTANT, push_struct]),
+            op(OP_VARIABLE, &[push_pointer, push_variable, STORAGE_PUSH_CONSTANT]),
+            op(OP_TYPE_STRUCT, &[uniform_struct, mat4]),
+            op(OP_TYPE_POINTER, &[uniform_pointer, STORAGE_UNIFORM, uniform_struct]),
+            op(OP_VARIABLE, &[uniform_pointer, uniform_variable, STORAGE_UNIFORM]),
+            op(OP_TYPE_IMAGE, &[image, float, 1, 0, 0, 0, 1, 0]),
+            op(OP_TYPE_SAMPLED_IMAGE, &[sampled_image, image]),
+            op(OP_TYPE_RUNTIME_ARRAY, &[textures, sampled_image]),
+            op(OP_TYPE_POINTER, &[textures_pointer, STORAGE_UNIFORM_CONSTANT, textures]),
+            op(OP_VARIABLE, &[textures_pointer, textures_variable, STORAGE_UNIFORM_CONSTANT]),
+            op(OP_TYPE_POINTER, &[vec3_pointer, STORAGE_INPUT, vec3]),
+            op(OP_TYPE_POINTER, &[uvec2_pointer, STORAGE_INPUT, uvec2]),
+            op(OP_TYPE_POINTER, &[uint_pointer, STORAGE_INPUT, uint]),
+            op(OP_VARIABLE, &[uvec2_pointer, joints, STORAGE_INPUT]),
+            op(OP_VARIABLE, &[vec3_pointer, position, STORAGE_INPUT]),
+            op(OP_VARIABLE, &[uint_pointer, vertex_index, STORAGE_INPUT]),
+        ] {
+            code.extend(words);
+        }
+        code
+    }
+
+    #[test]
+    fn reflects_descriptors_push_constants_and_inputs() {
+        let reflection = reflect(&vertex_module()).unwrap();
+        assert_eq!(reflection.stage, ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.push_constant_size, 72);
+        assert_eq!(
+            reflection.bindings,
+            vec![
+                ReflectedBinding {
+                    set: 0,
+                    binding: 0,
+                    descriptor_type: DescriptorType::UNIFORM_BUFFER,
+                    count: 1,
+                    stages: ShaderStageFlags::VERTEX,
+                },
+                ReflectedBinding {
+                    set: 1,
+                    binding: 1,
+                    descriptor_type: DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    count: 0,
+                    stages: ShaderStageFlags::VERTEX,
+                },
+            ]
+        );
+        assert_eq!(
+            reflection.inputs,
+            vec![
+                ReflectedInput {
+                    location: 0,
+                    format: Format::R32G32B32_SFLOAT,
+                },
+                ReflectedInput {
+                    location: 1,
+                    format: Format::R32G32_UINT,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_stages_and_requires_runtime_array_sizes() {
+        let vertex = reflect(&vertex_module()).unwrap();
+        let fragment = ShaderReflection {
+            stage: ShaderStageFlags::FRAGMENT,
+            bindings: vec![ReflectedBinding {
+                stages: ShaderStageFlags::FRAGMENT,
+                ..vertex.bindings[1]
+            }],
+            push_constant_size: 76,
+            inputs: vec![],
+        };
+        let mut pipeline = PipelineReflection::merge(&[vertex, fragment]);
+        let all = ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT;
+        assert_eq!(pipeline.bindings[1].stages, all);
+        assert_eq!(pipeline.push_constants, Some(PushConstantLayout::new(76, all)));
+        assert!(pipeline.set_layout_bindings().is_err());
+
+        pipeline.override_binding(1, 1, DescriptorType::COMBINED_IMAGE_SAMPLER, 1024);
+        let sets = pipeline.set_layout_bindings().unwrap();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[1][0].descriptor_count, 1024);
+    }
+
+    #[test]
+    fn packs_reflected_vertex_inputs() {
+        let pipeline = PipelineReflection::merge(&[reflect(&vertex_module()).unwrap()]);
+        let VertexInput::Bindings {
+            bindings,
+            attributes,
+        } = pipeline.vertex_input()
+        else {
+            panic!("the inputs need a vertex buffer");
+        };
+        assert_eq!(bindings[0].stride, 20);
+        assert_eq!(attributes[1].offset, 12);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(reflect(&[0x1234_5678, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ash::vk::{Extent3D, Format, MemoryRequirements};
+use vk_mem::Allocation;
+
+use crate::misc::sparse_feedback::FEEDBACK_HEADER_LEN;
+
+use super::swapchain::ImageDetails;
+
+/// Page of the first mip level of a `SparseImage`, in units of the sparse block size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SparsePage {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// A 2D image created with sparse residency, only the pages bound through
+/// `MemoryAllocator::bind_sparse_pages` are backed by memory.
+pub struct SparseImage {
+    pub image_details: ImageDetails,
+    pub extent: Extent3D,
+    pub format: Format,
+    /// Texel size of a single page as reported by the sparse image format properties.
+    pub page_extent: Extent3D,
+    /// Size, alignment and memory types of the allocation backing one page, a page is a single
+    /// sparse block.
+    pub(crate) page_requirements: MemoryRequirements,
+    pub(crate) pages: HashMap<SparsePage, Allocation>,
+    /// Opaque allocation backing the mip tail, which can't be bound page by page.
+    pub(crate) mip_tail: Option<Allocation>,
+}
+
+impl SparseImage {
+    /// Number of pages along x and y.
+    pub fn page_count(&self) -> (u32, u32) {
+        (
+            self.extent.width.div_ceil(self.page_extent.width),
+            self.extent.height.div_ceil(self.page_extent.height),
+        )
+    }
+
+    pub fn is_resident(&self, page: SparsePage) -> bool {
+        self.pages.contains_key(&page)
+    }
+
+    pub fn resident_pages(&self) -> impl Iterator<Item = &SparsePage> {
+        self.pages.keys()
+    }
+
+    /// Texel region covered by `page`, clamped to the image extent.
+    pub fn page_region(&self, page: SparsePage) -> Result<([i32; 2], Extent3D)> {
+        let (pages_x, pages_y) = self.page_count();
+        if page.x >= pages_x || page.y >= pages_y {
+            return Err(anyhow!("{page:?} is outside of the {pages_x}x{pages_y} pages"));
+        }
+        let offset = [
+            page.x * self.page_extent.width,
+            page.y * self.page_extent.height,
+        ];
+        let extent = Extent3D {
+            width: self.page_extent.width.min(self.extent.width - offset[0]),
+            height: self.page_extent.height.min(self.extent.height - offset[1]),
+            depth: 1,
+        };
+        Ok(([offset[0] as i32, offset[1] as i32], extent))
+    }
+
+    /// Decodes the buffer written by the feedback pass (see `shaders/sparse_feedback.glsl`),
+    /// the page count followed by one u32 per page in row-major order, and returns the
+    /// requested pages that are not resident yet.
+    pub fn requested_pages(&self, feedback: &[u32]) -> Vec<SparsePage> {
+        let (pages_x, pages_y) = self.page_count();
+        feedback
+            .iter()
+            .skip(FEEDBACK_HEADER_LEN)
+            .take((pages_x * pages_y) as usize)
+            .enumerate()
+            .filter(|(_, requested)| **requested != 0)
+            .map(|(idx, _)| SparsePage {
+                x: idx as u32 % pages_x,
+                y: idx as u32 / pages_x,
+            })
+            .filter(|page| !self.is_resident(*page))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Extent3D, Format, MemoryRequirements};
+
+    use crate::components::swapchain::ImageDetails;
+
+    use super::{SparseImage, SparsePage};
+
+    fn sparse_image(width: u32, height: u32) -> SparseImage {
+        SparseImage {
+            image_details: ImageDetails {
+                image: Default::default(),
+                image_view: Default::default(),
+            },
+            extent: Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            format: Format::R8G8B8A8_UNORM,
+            page_extent: Extent3D {
+                width: 128,
+                height: 128,
+                depth: 1,
+            },
+            page_requirements: MemoryRequirements::default(),
+            pages: Default::default(),
+            mip_tail: None,
+        }
+    }
+
+    #[test]
+    fn edge_pages_are_clamped_and_outside_pages_rejected() {
+        let image = sparse_image(300, 200);
+        assert_eq!(image.page_count(), (3, 2));
+        let (offset, extent) = image.page_region(SparsePage { x: 2, y: 1 }).unwrap();
+        assert_eq!(offset, [256, 128]);
+        assert_eq!((extent.width, extent.height), (44, 72));
+        assert!(image.page_region(SparsePage { x: 3, y: 0 }).is_err());
+        assert!(image.page_region(SparsePage { x: 0, y: 2 }).is_err());
+    }
+
+    #[test]
+    fn requested_pages_skip_the_page_count_header() {
+        let image = sparse_image(300, 200);
+        // pageCount (3, 2), then the flags of row 0 and row 1
+        let feedback = [3, 2, 0, 1, 0, 1, 0, 0];
+        assert_eq!(
+            image.requested_pages(&feedback),
+            vec![SparsePage { x: 1, y: 0 }, SparsePage { x: 0, y: 1 }]
+        );
+    }
+}
@@ -8,6 +8,14 @@ use super::instance::VkInstance;
 pub struct KHRSurface {
     pub instance: surface::Instance,
     pub surface_khr: SurfaceKHR,
+    /// Keeps the instance alive until the surface is destroyed.
+    _vk_instance: Arc<VkInstance>,
+}
+
+impl Drop for KHRSurface {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy_surface(self.surface_khr, None) };
+    }
 }
 
 impl Deref for KHRSurface {
@@ -34,7 +42,8 @@ impl KHRSurface {
 
         Ok(Self {
             instance: surface_instance,
-            surface_khr
+            surface_khr,
+            _vk_instance: instance,
         })
     }
 }
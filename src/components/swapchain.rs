@@ -3,13 +3,13 @@ use std::{io::Error, ops::Deref, sync::Arc};
 use ash::{
     khr::swapchain,
     vk::{
-        ComponentMapping, ComponentSwizzle, CompositeAlphaFlagsKHR, Image, ImageAspectFlags, ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType, SharingMode, SwapchainCreateInfoKHR, SwapchainKHR
+        ColorSpaceKHR, ComponentMapping, ComponentSwizzle, CompositeAlphaFlagsKHR, Extent2D, Image, ImageAspectFlags, ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType, Format, PresentModeKHR, SharingMode, SwapchainCreateInfoKHR, SwapchainKHR
     },
 };
 use winit::window::Window;
 
 use super::{
-    device::VkDevice, image_util::image_subresource_range, instance::VkInstance, queue::VkQueue, swapchain_support_details::SwapchainSupportDetails
+    device::VkDevice, image_util::image_subresource_range, instance::VkInstance, queue::VkQueue, surface::KHRSurface, swapchain_support_details::SwapchainSupportDetails
 };
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -24,8 +24,17 @@ pub struct KHRSwapchain {
     pub s_device: swapchain::Device,
     swapchain: SwapchainKHR,
     pub details: SwapchainSupportDetails,
+    pub color_space: ColorSpaceKHR,
+    /// Format of the swapchain images, picked by `choose_swapchain_format_in`.
+    pub format: Format,
+    /// Mode picked from the requested one, see `choose_swapchain_present_mode`.
+    pub present_mode: PresentModeKHR,
+    /// Size of the swapchain images, follows the window.
+    pub extent: Extent2D,
     pub device: Arc<VkDevice>,
     instance: Arc<VkInstance>,
+    surface: Arc<KHRSurface>,
+    queue_family_indices: [u32; 2],
 }
 
 impl Deref for KHRSwapchain {
@@ -40,9 +49,49 @@ impl KHRSwapchain {
     pub fn new(
         instance: Arc<VkInstance>,
         device: Arc<VkDevice>,
-        surface: Arc<super::surface::KHRSurface>,
+        surface: Arc<KHRSurface>,
         window: &Window,
         queues: [Arc<VkQueue>; 2],
+        color_space: ColorSpaceKHR,
+        present_mode: PresentModeKHR,
+    ) -> Result<Self, Error> {
+        Self::create(
+            instance,
+            device,
+            surface,
+            window,
+            [queues[0].queue_family_index, queues[1].queue_family_index],
+            color_space,
+            present_mode,
+            SwapchainKHR::null(),
+        )
+    }
+
+    /// Creates a swapchain with `present_mode` and the current size of `window` that replaces
+    /// this one, which has to be destroyed with `destroy` once it is no longer in use. The color
+    /// space stays the same.
+    pub fn recreate(&self, window: &Window, present_mode: PresentModeKHR) -> Result<Self, Error> {
+        Self::create(
+            self.instance.clone(),
+            self.device.clone(),
+            self.surface.clone(),
+            window,
+            self.queue_family_indices,
+            self.color_space,
+            present_mode,
+            self.swapchain,
+        )
+    }
+
+    fn create(
+        instance: Arc<VkInstance>,
+        device: Arc<VkDevice>,
+        surface: Arc<KHRSurface>,
+        window: &Window,
+        queue_family_indices: [u32; 2],
+        color_space: ColorSpaceKHR,
+        present_mode: PresentModeKHR,
+        old_swapchain: SwapchainKHR,
     ) -> Result<Self, Error> {
         let s_device = swapchain::Device::new(&instance, &device);
         let swapchain_support_details = SwapchainSupportDetails::get_swapchain_support_details(
@@ -51,10 +100,10 @@ impl KHRSwapchain {
             window,
         )
         .unwrap();
-        let surface_format = swapchain_support_details.clone().choose_swapchain_format();
+        let surface_format = swapchain_support_details.choose_swapchain_format_in(color_space)?;
         let present_mode = swapchain_support_details
             .clone()
-            .choose_swapchain_present_mode();
+            .choose_swapchain_present_mode(present_mode);
         let extent = swapchain_support_details
             .clone()
             .choose_swapchain_extent(window);
@@ -72,13 +121,13 @@ impl KHRSwapchain {
             .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
             .clipped(true)
             .present_mode(present_mode)
+            .old_swapchain(old_swapchain)
             .image_extent(extent);
 
-        let indices_vec = [queues[0].queue_family_index, queues[1].queue_family_index];
-        if indices_vec[0] != indices_vec[1] {
+        if queue_family_indices[0] != queue_family_indices[1] {
             create_info = create_info
                 .image_sharing_mode(SharingMode::CONCURRENT)
-                .queue_family_indices(&indices_vec);
+                .queue_family_indices(&queue_family_indices);
         } else {
             create_info = create_info.image_sharing_mode(SharingMode::EXCLUSIVE);
         }
@@ -91,9 +140,25 @@ impl KHRSwapchain {
             device,
             instance,
             details: swapchain_support_details,
+            color_space: surface_format.color_space,
+            format: surface_format.format,
+            present_mode,
+            extent,
+            surface,
+            queue_family_indices,
         })
     }
-    
+
+    /// Destroys the swapchain and the views `create_image_details` created for it, the device
+    /// must not use either anymore.
+    pub fn destroy(&self, image_details: &[ImageDetails]) {
+        unsafe {
+            for details in image_details {
+                self.device.destroy_image_view(details.image_view, None);
+            }
+            self.s_device.destroy_swapchain(self.swapchain, None);
+        }
+    }
 
     pub fn create_image_details(&self) -> Result<Vec<ImageDetails>, Error> {
         unsafe {
@@ -103,12 +168,7 @@ impl KHRSwapchain {
                 .map(|image| -> ImageDetails {
                     let image_view_create_info = ImageViewCreateInfo::default()
                         .image(image)
-                        .format(
-                            self.details
-                                .clone()
-                                .choose_swapchain_format()
-                                .format,
-                        )
+                        .format(self.format)
                         .subresource_range(image_subresource_range(ImageAspectFlags::COLOR))
                         .view_type(ImageViewType::TYPE_2D)
                         .components(
@@ -1,4 +1,7 @@
-use std::{io::Error, sync::Arc};
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
 
 use ash::
     vk::{
@@ -55,25 +58,34 @@ impl SwapchainSupportDetails {
         !self.formats.is_empty() && !self.present_modes.is_empty()
     }
 
-    pub fn choose_swapchain_format(self) -> SurfaceFormatKHR {
+    pub fn choose_swapchain_format(&self) -> Result<SurfaceFormatKHR, Error> {
+        self.choose_swapchain_format_in(ColorSpaceKHR::SRGB_NONLINEAR)
+    }
+
+    /// Color spaces the surface can present the R16G16B16A16_SFLOAT swapchain in.
+    pub fn supported_color_spaces(&self) -> Vec<ColorSpaceKHR> {
         self.formats
-            .into_iter()
-            .filter(|format| {
-                format.format.eq(&Format::R16G16B16A16_SFLOAT) 
-                    && format.color_space.eq(&ColorSpaceKHR::SRGB_NONLINEAR)
-            })
-            .collect::<Vec<SurfaceFormatKHR>>()
-            .first().copied()
-            .unwrap()
+            .iter()
+            .filter(|format| format.format == Format::R16G16B16A16_SFLOAT)
+            .map(|format| format.color_space)
+            .collect()
     }
 
-    pub fn choose_swapchain_present_mode(self) -> PresentModeKHR {
-        self.present_modes
-            .into_iter()
-            .filter(|mode| mode.eq(&PresentModeKHR::FIFO))
-            .collect::<Vec<PresentModeKHR>>()
-            .first().copied()
-            .unwrap()
+    /// Presents in `color_space` when the surface supports it, see `pick_surface_format` for
+    /// the formats tried.
+    pub fn choose_swapchain_format_in(
+        &self,
+        color_space: ColorSpaceKHR,
+    ) -> Result<SurfaceFormatKHR, Error> {
+        pick_surface_format(&self.formats, color_space).ok_or_else(|| {
+            Error::new(ErrorKind::Unsupported, "The surface reports no formats")
+        })
+    }
+
+    /// `preferred` when the surface supports it, otherwise the closest supported mode. FIFO is
+    /// the last resort since every surface has to support it.
+    pub fn choose_swapchain_present_mode(self, preferred: PresentModeKHR) -> PresentModeKHR {
+        fallback_present_mode(&self.present_modes, preferred)
     }
 
     pub fn choose_swapchain_extent(self, window: &Window) -> Extent2D {
@@ -104,3 +116,134 @@ impl SwapchainSupportDetails {
         min_image_count
     }
 }
+
+/// R16G16B16A16_SFLOAT in `color_space` or sRGB, otherwise B8G8R8A8 which most Win32, X11 and
+/// Wayland surfaces offer. UNORM goes before SRGB since the display transform encodes itself.
+/// The first reported format is the last resort.
+fn pick_surface_format(
+    formats: &[SurfaceFormatKHR],
+    color_space: ColorSpaceKHR,
+) -> Option<SurfaceFormatKHR> {
+    let color_spaces = [color_space, ColorSpaceKHR::SRGB_NONLINEAR];
+    let candidates = [
+        Format::R16G16B16A16_SFLOAT,
+        Format::B8G8R8A8_UNORM,
+        Format::B8G8R8A8_SRGB,
+    ];
+    candidates
+        .iter()
+        .find_map(|&candidate| {
+            color_spaces.iter().find_map(|&color_space| {
+                formats.iter().copied().find(|format| {
+                    format.format == candidate && format.color_space == color_space
+                })
+            })
+        })
+        .or_else(|| formats.first().copied())
+}
+
+/// IMMEDIATE and MAILBOX fall back to each other so an uncapped frame rate stays uncapped,
+/// FIFO_RELAXED and anything else to FIFO.
+fn fallback_present_mode(
+    supported: &[PresentModeKHR],
+    preferred: PresentModeKHR,
+) -> PresentModeKHR {
+    let candidates: &[PresentModeKHR] = match preferred {
+        PresentModeKHR::IMMEDIATE => &[PresentModeKHR::IMMEDIATE, PresentModeKHR::MAILBOX],
+        PresentModeKHR::MAILBOX => &[PresentModeKHR::MAILBOX, PresentModeKHR::IMMEDIATE],
+        PresentModeKHR::FIFO_RELAXED => &[PresentModeKHR::FIFO_RELAXED],
+        _ => &[],
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentModeKHR::FIFO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_format(format: Format, color_space: ColorSpaceKHR) -> SurfaceFormatKHR {
+        SurfaceFormatKHR::default().format(format).color_space(color_space)
+    }
+
+    #[test]
+    fn float_format_is_preferred_in_the_requested_color_space() {
+        let formats = [
+            surface_format(Format::B8G8R8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR),
+            surface_format(Format::R16G16B16A16_SFLOAT, ColorSpaceKHR::SRGB_NONLINEAR),
+            surface_format(Format::R16G16B16A16_SFLOAT, ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT),
+        ];
+        assert_eq!(
+            pick_surface_format(&formats, ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT),
+            Some(formats[2])
+        );
+        assert_eq!(
+            pick_surface_format(&formats, ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+            Some(formats[1])
+        );
+    }
+
+    #[test]
+    fn eight_bit_surfaces_fall_back_to_bgra() {
+        let formats = [
+            surface_format(Format::B8G8R8A8_SRGB, ColorSpaceKHR::SRGB_NONLINEAR),
+            surface_format(Format::B8G8R8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR),
+        ];
+        assert_eq!(
+            pick_surface_format(&formats, ColorSpaceKHR::SRGB_NONLINEAR),
+            Some(formats[1])
+        );
+        assert_eq!(
+            pick_surface_format(&formats[..1], ColorSpaceKHR::SRGB_NONLINEAR),
+            Some(formats[0])
+        );
+        let formats = [surface_format(Format::R8G8B8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR)];
+        assert_eq!(
+            pick_surface_format(&formats, ColorSpaceKHR::SRGB_NONLINEAR),
+            Some(formats[0])
+        );
+        assert_eq!(pick_surface_format(&[], ColorSpaceKHR::SRGB_NONLINEAR), None);
+    }
+
+    #[test]
+    fn supported_present_mode_is_kept() {
+        let supported = [
+            PresentModeKHR::FIFO,
+            PresentModeKHR::MAILBOX,
+            PresentModeKHR::IMMEDIATE,
+        ];
+        for mode in supported {
+            assert_eq!(fallback_present_mode(&supported, mode), mode);
+        }
+    }
+
+    #[test]
+    fn uncapped_modes_fall_back_to_each_other() {
+        let supported = [PresentModeKHR::FIFO, PresentModeKHR::MAILBOX];
+        assert_eq!(
+            fallback_present_mode(&supported, PresentModeKHR::IMMEDIATE),
+            PresentModeKHR::MAILBOX
+        );
+        let supported = [PresentModeKHR::FIFO, PresentModeKHR::IMMEDIATE];
+        assert_eq!(
+            fallback_present_mode(&supported, PresentModeKHR::MAILBOX),
+            PresentModeKHR::IMMEDIATE
+        );
+    }
+
+    #[test]
+    fn falls_back_to_fifo() {
+        let supported = [PresentModeKHR::FIFO];
+        assert_eq!(
+            fallback_present_mode(&supported, PresentModeKHR::FIFO_RELAXED),
+            PresentModeKHR::FIFO
+        );
+        assert_eq!(
+            fallback_present_mode(&supported, PresentModeKHR::IMMEDIATE),
+            PresentModeKHR::FIFO
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{Fence, Semaphore};
+
+use super::{device::VkDevice, frame_data::create_semaphore_info};
+
+/// Synchronization tied to the swapchain images rather than to the frames in flight. The
+/// presentation engine holds on to the semaphore a present waits on until the image is
+/// acquired again, so the semaphore signaled for it can only be reused with the same image,
+/// and an image may be acquired while another frame still renders to it when the image count
+/// differs from `MAX_FRAMES`.
+pub struct SwapchainSync {
+    device: Arc<VkDevice>,
+    /// Signaled by the frame rendering to the image, waited on by its present.
+    render_semaphores: Vec<Semaphore>,
+    images_in_flight: ImagesInFlight,
+}
+
+impl SwapchainSync {
+    pub fn new(device: Arc<VkDevice>, image_count: usize) -> Result<Self> {
+        let mut render_semaphores = Vec::with_capacity(image_count);
+        for _ in 0..image_count {
+            match unsafe { device.create_semaphore(&create_semaphore_info(), None) } {
+                Ok(semaphore) => render_semaphores.push(semaphore),
+                Err(err) => {
+                    for semaphore in render_semaphores {
+                        unsafe { device.destroy_semaphore(semaphore, None) };
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(Self {
+            device,
+            render_semaphores,
+            images_in_flight: ImagesInFlight::new(image_count),
+        })
+    }
+
+    pub fn render_semaphore(&self, image_index: u32) -> Semaphore {
+        self.render_semaphores[image_index as usize]
+    }
+
+    /// Marks `image_index` as rendered to by the frame guarded by `fence` and waits for the
+    /// frame that rendered to it before, unless that frame was the same one.
+    pub fn claim(&mut self, image_index: u32, fence: Fence) -> Result<()> {
+        if let Some(previous) = self.images_in_flight.claim(image_index, fence) {
+            unsafe { self.device.wait_for_fences(&[previous], true, u64::MAX)? };
+        }
+        Ok(())
+    }
+
+    /// The device must be idle, no present may still wait on the semaphores.
+    pub fn destroy(&mut self) {
+        for semaphore in self.render_semaphores.drain(..) {
+            unsafe { self.device.destroy_semaphore(semaphore, None) };
+        }
+        self.images_in_flight = ImagesInFlight::new(0);
+    }
+}
+
+/// The render fence of the frame each swapchain image was last rendered to by.
+struct ImagesInFlight {
+    fences: Vec<Fence>,
+}
+
+impl ImagesInFlight {
+    fn new(image_count: usize) -> Self {
+        Self {
+            fences: vec![Fence::null(); image_count],
+        }
+    }
+
+    /// Returns the fence to wait on before `image_index` can be rendered to by the frame of
+    /// `fence`.
+    fn claim(&mut self, image_index: u32, fence: Fence) -> Option<Fence> {
+        let previous = std::mem::replace(&mut self.fences[image_index as usize], fence);
+        (previous != Fence::null() && previous != fence).then_some(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Fence, Handle};
+
+    use super::ImagesInFlight;
+
+    #[test]
+    fn waits_for_the_previous_frame_of_an_image() {
+        let (first, second) = (Fence::from_raw(1), Fence::from_raw(2));
+        let mut images = ImagesInFlight::new(3);
+        assert_eq!(images.claim(0, first), None);
+        assert_eq!(images.claim(1, second), None);
+        // the frame of `first` already waited on its own fence
+        assert_eq!(images.claim(0, first), None);
+        assert_eq!(images.claim(0, second), Some(first));
+        assert_eq!(images.claim(2, first), None);
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+
+use ash::vk::{Fence, FenceCreateInfo, Semaphore, SemaphoreCreateInfo};
+use log::debug;
+
+use super::{
+    deletion_queue::{CleanUpTask, DeletionQueue, FType},
+    device::VkDevice,
+    memory_allocator::MemoryAllocator,
+};
+
+/// Sync objects handed back with `SyncPool::retire`, reusable once `fence` is signaled.
+struct InFlight {
+    fence: Fence,
+    semaphores: Vec<Semaphore>,
+}
+
+#[derive(Default)]
+struct SyncObjects {
+    free_fences: Vec<Fence>,
+    free_semaphores: Vec<Semaphore>,
+    in_flight: Vec<InFlight>,
+}
+
+/// Hands out transient fences and binary semaphores and recycles them once the GPU is done
+/// with them. Everything the pool ever created is destroyed when the deletion queue it was
+/// registered with is flushed.
+#[derive(Clone)]
+pub struct SyncPool {
+    device: Arc<VkDevice>,
+    objects: Arc<Mutex<SyncObjects>>,
+}
+
+pub struct DestroySyncPoolTask {
+    objects: Arc<Mutex<SyncObjects>>,
+}
+
+impl CleanUpTask<'static> for DestroySyncPoolTask {
+    fn execute(&mut self, device: Arc<VkDevice>, _malloc: Arc<MemoryAllocator>) {
+        let mut objects = self.objects.lock().unwrap();
+        let in_flight = std::mem::take(&mut objects.in_flight);
+        let fences = std::mem::take(&mut objects.free_fences)
+            .into_iter()
+            .chain(in_flight.iter().map(|in_flight| in_flight.fence));
+        let semaphores = std::mem::take(&mut objects.free_semaphores)
+            .into_iter()
+            .chain(in_flight.iter().flat_map(|in_flight| in_flight.semaphores.clone()));
+        unsafe {
+            fences.for_each(|fence| device.destroy_fence(fence, None));
+            semaphores.for_each(|semaphore| device.destroy_semaphore(semaphore, None));
+        }
+        debug!("SyncPool objects have been deleted");
+    }
+}
+
+impl SyncPool {
+    pub fn new(device: Arc<VkDevice>, deletion_queue: &mut DeletionQueue) -> Self {
+        let objects = Arc::new(Mutex::new(SyncObjects::default()));
+        deletion_queue.enqueue(FType::TASK(Box::new(DestroySyncPoolTask {
+            objects: objects.clone(),
+        })));
+        Self { device, objects }
+    }
+
+    /// Unsignaled fence, either recycled or newly created.
+    pub fn acquire_fence(&self) -> Fence {
+        let recycled = self.objects.lock().unwrap().free_fences.pop();
+        recycled.unwrap_or_else(|| unsafe {
+            self.device
+                .create_fence(&FenceCreateInfo::default(), None)
+                .unwrap()
+        })
+    }
+
+    pub fn acquire_semaphore(&self) -> Semaphore {
+        let recycled = self.objects.lock().unwrap().free_semaphores.pop();
+        recycled.unwrap_or_else(|| unsafe {
+            self.device
+                .create_semaphore(&SemaphoreCreateInfo::default(), None)
+                .unwrap()
+        })
+    }
+
+    /// Gives `fence` and `semaphores` back after they were used in a submission signaling
+    /// `fence`, they are handed out again once `recycle` sees the fence signaled.
+    pub fn retire(&self, fence: Fence, semaphores: Vec<Semaphore>) {
+        self.objects
+            .lock()
+            .unwrap()
+            .in_flight
+            .push(InFlight { fence, semaphores });
+    }
+
+    /// Returns a fence that was never submitted or already waited on.
+    pub fn release_fence(&self, fence: Fence) {
+        unsafe { self.device.reset_fences(&[fence]).unwrap() };
+        self.objects.lock().unwrap().free_fences.push(fence);
+    }
+
+    /// Returns a semaphore that has no pending signal or wait operation.
+    pub fn release_semaphore(&self, semaphore: Semaphore) {
+        self.objects.lock().unwrap().free_semaphores.push(semaphore);
+    }
+
+    /// Moves every retired batch whose fence is signaled back to the free lists, called once
+    /// per frame by the renderer.
+    pub fn recycle(&self) {
+        let mut objects = self.objects.lock().unwrap();
+        let in_flight = std::mem::take(&mut objects.in_flight);
+        let (signaled, pending): (Vec<InFlight>, Vec<InFlight>) =
+            in_flight.into_iter().partition(|in_flight| unsafe {
+                self.device.get_fence_status(in_flight.fence).unwrap_or(false)
+            });
+        objects.in_flight = pending;
+        for in_flight in signaled {
+            unsafe { self.device.reset_fences(&[in_flight.fence]).unwrap() };
+            objects.free_fences.push(in_flight.fence);
+            objects.free_semaphores.extend(in_flight.semaphores);
+        }
+    }
+
+    /// Number of fences and semaphores that are free or waiting to be recycled, objects handed
+    /// out and not retired yet are not counted.
+    pub fn pooled_counts(&self) -> (usize, usize) {
+        let objects = self.objects.lock().unwrap();
+        (
+            objects.free_fences.len() + objects.in_flight.len(),
+            objects.free_semaphores.len()
+                + objects
+                    .in_flight
+                    .iter()
+                    .map(|in_flight| in_flight.semaphores.len())
+                    .sum::<usize>(),
+        )
+    }
+}
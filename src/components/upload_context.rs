@@ -0,0 +1,402 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, Buffer, BufferCopy, BufferImageCopy, BufferMemoryBarrier2, BufferUsageFlags,
+    CommandBuffer, CommandBufferBeginInfo, CommandBufferSubmitInfo, CommandBufferUsageFlags,
+    Extent3D, Fence, Format, Image, ImageAspectFlags, ImageLayout, ImageMemoryBarrier2,
+    ImageUsageFlags, MemoryPropertyFlags, Offset3D, PipelineStageFlags2, Semaphore,
+    SemaphoreCreateInfo, SemaphoreSubmitInfo, SemaphoreType, SemaphoreTypeCreateInfo,
+    SubmitInfo2, QUEUE_FAMILY_IGNORED, WHOLE_SIZE,
+};
+use tracing::info_span;
+use vk_mem::MemoryUsage;
+
+use super::{
+    allocation_types::{AllocatedImage, VkBuffer},
+    buffer_arena::{BufferArena, BufferSlice},
+    command_buffers::VkCommandPool,
+    device::VkDevice,
+    image_util::{image_subresource_layers, image_subresource_range},
+    memory_allocator::MemoryAllocator,
+    queue::VkQueue,
+};
+
+/// Stages of the graphics queue that wait for uploads, uploaded resources must not be used
+/// before them.
+pub const UPLOAD_DST_STAGES: PipelineStageFlags2 = PipelineStageFlags2::from_raw(
+    PipelineStageFlags2::VERTEX_INPUT.as_raw()
+        | PipelineStageFlags2::VERTEX_SHADER.as_raw()
+        | PipelineStageFlags2::FRAGMENT_SHADER.as_raw(),
+);
+
+const UPLOAD_DST_ACCESS: AccessFlags2 = AccessFlags2::from_raw(
+    AccessFlags2::VERTEX_ATTRIBUTE_READ.as_raw()
+        | AccessFlags2::INDEX_READ.as_raw()
+        | AccessFlags2::SHADER_READ.as_raw(),
+);
+
+/// A submitted batch, its staging buffers are dropped once the semaphore reached `value`.
+struct InFlightBatch {
+    value: u64,
+    command_buffer: CommandBuffer,
+    _staging_buffers: Vec<VkBuffer>,
+}
+
+/// Records buffer and image uploads on the transfer queue and submits them as one batch per
+/// frame instead of waiting for every copy. Each batch signals the next value of a timeline
+/// semaphore, which the frame using the uploads waits on. On devices with a dedicated transfer
+/// family the ownership of uploaded resources is released to the graphics family, the
+/// matching acquire barriers are recorded into the next frame with `record_acquires`.
+pub struct UploadContext {
+    device: Arc<VkDevice>,
+    memory_allocator: Arc<MemoryAllocator>,
+    transfer_queue: Arc<VkQueue>,
+    graphics_queue: Arc<VkQueue>,
+    command_pool: VkCommandPool,
+    semaphore: Semaphore,
+    /// Value signaled by the last submitted batch.
+    submitted: u64,
+    /// Set when batches were submitted that no frame waited on yet.
+    unconsumed: bool,
+    recording: Option<CommandBuffer>,
+    staging_buffers: Vec<VkBuffer>,
+    /// Acquire barriers of the batch being recorded, they move to the pending ones on submit.
+    recorded_buffer_acquires: Vec<BufferMemoryBarrier2<'static>>,
+    recorded_image_acquires: Vec<ImageMemoryBarrier2<'static>>,
+    pending_buffer_acquires: Vec<BufferMemoryBarrier2<'static>>,
+    pending_image_acquires: Vec<ImageMemoryBarrier2<'static>>,
+    in_flight: VecDeque<InFlightBatch>,
+}
+
+impl UploadContext {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: Arc<MemoryAllocator>,
+        transfer_queue: Arc<VkQueue>,
+        graphics_queue: Arc<VkQueue>,
+    ) -> Result<Self> {
+        let mut type_create_info = SemaphoreTypeCreateInfo::default()
+            .semaphore_type(SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore = unsafe {
+            device.create_semaphore(
+                &SemaphoreCreateInfo::default().push_next(&mut type_create_info),
+                None,
+            )?
+        };
+        Ok(Self {
+            command_pool: VkCommandPool::new(transfer_queue.clone()),
+            device,
+            memory_allocator,
+            transfer_queue,
+            graphics_queue,
+            semaphore,
+            submitted: 0,
+            unconsumed: false,
+            recording: None,
+            staging_buffers: vec![],
+            recorded_buffer_acquires: vec![],
+            recorded_image_acquires: vec![],
+            pending_buffer_acquires: vec![],
+            pending_image_acquires: vec![],
+            in_flight: VecDeque::new(),
+        })
+    }
+
+    /// Timeline semaphore signaled by the upload batches.
+    pub fn semaphore(&self) -> Semaphore {
+        self.semaphore
+    }
+
+    /// Whether transfer and graphics queue are of different families, which requires the
+    /// ownership of uploaded resources to be transferred.
+    pub fn dedicated_transfer(&self) -> bool {
+        self.transfer_queue.queue_family_index != self.graphics_queue.queue_family_index
+    }
+
+    /// Creates a device local buffer with `elements` and records the copy into it. The buffer
+    /// can be used by frames drawn after the next `submit`.
+    pub fn upload_buffer<T: Clone>(
+        &mut self,
+        elements: &[T],
+        usage: BufferUsageFlags,
+    ) -> Result<VkBuffer> {
+        let size = (size_of::<T>() * elements.len()) as u64;
+        let _span = info_span!("upload buffer", size).entered();
+        let queues = [self.graphics_queue.clone()];
+        let staging_buffer = self.memory_allocator.staging_buffer(size, elements, &queues)?;
+        #[allow(deprecated)]
+        let buffer = self.memory_allocator.allocate_single_buffer(
+            size,
+            &queues,
+            usage | BufferUsageFlags::TRANSFER_DST | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryUsage::GpuOnly,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let cmd = self.command_buffer()?;
+        unsafe {
+            self.device.cmd_copy_buffer(
+                cmd,
+                *staging_buffer,
+                *buffer,
+                &[BufferCopy::default().size(size)],
+            );
+        }
+        self.staging_buffers.push(staging_buffer);
+        self.release_buffer(cmd, *buffer, 0, WHOLE_SIZE);
+        Ok(buffer)
+    }
+
+    /// Allocates a slice of `arena` for `elements` and records the copy into it, like
+    /// `upload_buffer` the slice can be used by frames drawn after the next `submit`.
+    pub fn upload_to_arena<T: Clone>(
+        &mut self,
+        arena: &BufferArena,
+        elements: &[T],
+    ) -> Result<BufferSlice> {
+        let size = (size_of::<T>() * elements.len()) as u64;
+        let _span = info_span!("upload to arena", size).entered();
+        let queues = [self.graphics_queue.clone()];
+        let staging_buffer = self.memory_allocator.staging_buffer(size, elements, &queues)?;
+        // same alignment as `BufferArena::upload`
+        let slice = arena.allocate(size, 16)?;
+        let cmd = self.command_buffer()?;
+        unsafe {
+            self.device.cmd_copy_buffer(
+                cmd,
+                *staging_buffer,
+                *slice.buffer,
+                &[BufferCopy::default().dst_offset(slice.offset).size(size)],
+            );
+        }
+        self.staging_buffers.push(staging_buffer);
+        self.release_buffer(cmd, *slice.buffer, slice.offset, size);
+        Ok(slice)
+    }
+
+    /// Creates a single mip 2D image with the tightly packed texels of `data` and records the
+    /// upload, the image is in SHADER_READ_ONLY_OPTIMAL for frames drawn after the next
+    /// `submit`.
+    pub fn upload_image(
+        &mut self,
+        data: &[u8],
+        extent: Extent3D,
+        format: Format,
+        usage: ImageUsageFlags,
+    ) -> Result<AllocatedImage> {
+        let _span = info_span!("upload image", size = data.len()).entered();
+        let queues = [self.graphics_queue.clone()];
+        let staging_buffer =
+            self.memory_allocator
+                .staging_buffer(data.len() as u64, data, &queues)?;
+        let image = self.memory_allocator.create_image(
+            extent,
+            format,
+            None,
+            usage | ImageUsageFlags::SAMPLED,
+            ImageAspectFlags::COLOR,
+            false,
+        )?;
+        let cmd = self.command_buffer()?;
+        let image_handle = image.image_details.image;
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &[],
+                &[ImageMemoryBarrier2::default()
+                    .dst_stage_mask(PipelineStageFlags2::COPY)
+                    .dst_access_mask(AccessFlags2::TRANSFER_WRITE)
+                    .old_layout(ImageLayout::UNDEFINED)
+                    .new_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .image(image_handle)
+                    .subresource_range(image_subresource_range(ImageAspectFlags::COLOR))],
+            );
+            self.device.cmd_copy_buffer_to_image(
+                cmd,
+                *staging_buffer,
+                image_handle,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[BufferImageCopy::default()
+                    .image_offset(Offset3D::default())
+                    .image_subresource(image_subresource_layers(ImageAspectFlags::COLOR))
+                    .image_extent(extent)],
+            );
+        }
+        self.staging_buffers.push(staging_buffer);
+        self.release_image(cmd, image_handle);
+        Ok(image)
+    }
+
+    /// Submits the uploads recorded since the last call to the transfer queue. Returns the
+    /// semaphore value the next frame has to wait on, `None` if no upload is outstanding.
+    pub fn submit(&mut self) -> Result<Option<u64>> {
+        if let Some(cmd) = self.recording.take() {
+            self.submitted += 1;
+            let command_buffers = [CommandBufferSubmitInfo::default().command_buffer(cmd)];
+            let signal_semaphores = [SemaphoreSubmitInfo::default()
+                .semaphore(self.semaphore)
+                .value(self.submitted)
+                .stage_mask(PipelineStageFlags2::ALL_COMMANDS)];
+            unsafe {
+                self.device.end_command_buffer(cmd)?;
+                self.device.queue_submit2(
+                    **self.transfer_queue,
+                    &[SubmitInfo2::default()
+                        .command_buffer_infos(&command_buffers)
+                        .signal_semaphore_infos(&signal_semaphores)],
+                    Fence::null(),
+                )?;
+            }
+            self.in_flight.push_back(InFlightBatch {
+                value: self.submitted,
+                command_buffer: cmd,
+                _staging_buffers: std::mem::take(&mut self.staging_buffers),
+            });
+            self.pending_buffer_acquires
+                .append(&mut self.recorded_buffer_acquires);
+            self.pending_image_acquires
+                .append(&mut self.recorded_image_acquires);
+            self.unconsumed = true;
+        }
+        Ok(std::mem::take(&mut self.unconsumed).then_some(self.submitted))
+    }
+
+    /// Records the acquire barriers of the submitted uploads into `cmd` of the graphics queue,
+    /// which has to be submitted waiting on the value returned by `submit`.
+    pub fn record_acquires(&mut self, cmd: CommandBuffer) {
+        if self.pending_buffer_acquires.is_empty() && self.pending_image_acquires.is_empty() {
+            return;
+        }
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &self.pending_buffer_acquires,
+                &self.pending_image_acquires,
+            );
+        }
+        self.pending_buffer_acquires.clear();
+        self.pending_image_acquires.clear();
+    }
+
+    /// Frees the staging buffers of every batch the transfer queue finished.
+    pub fn collect(&mut self) -> Result<()> {
+        let completed = unsafe { self.device.get_semaphore_counter_value(self.semaphore)? };
+        while self
+            .in_flight
+            .front()
+            .is_some_and(|batch| batch.value <= completed)
+        {
+            let batch = self.in_flight.pop_front().unwrap();
+            self.free_batch(batch);
+        }
+        Ok(())
+    }
+
+    /// Destroys everything the context owns, the device has to be idle.
+    pub fn destroy(&mut self) {
+        for batch in std::mem::take(&mut self.in_flight) {
+            self.free_batch(batch);
+        }
+        self.staging_buffers.clear();
+        self.recording = None;
+        unsafe {
+            self.device.destroy_semaphore(self.semaphore, None);
+            self.device.destroy_command_pool(*self.command_pool, None);
+        }
+    }
+
+    fn free_batch(&self, batch: InFlightBatch) {
+        unsafe {
+            self.device
+                .free_command_buffers(*self.command_pool, &[batch.command_buffer])
+        };
+    }
+
+    fn command_buffer(&mut self) -> Result<CommandBuffer> {
+        if let Some(cmd) = self.recording {
+            return Ok(cmd);
+        }
+        let cmd = self.command_pool.allocate_command_buffer();
+        unsafe {
+            self.device.begin_command_buffer(
+                cmd,
+                &CommandBufferBeginInfo::default().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?
+        };
+        self.recording = Some(cmd);
+        Ok(cmd)
+    }
+
+    fn release_buffer(&mut self, cmd: CommandBuffer, buffer: Buffer, offset: u64, size: u64) {
+        if !self.dedicated_transfer() {
+            // the semaphore wait alone makes the copy visible to the graphics queue
+            return;
+        }
+        let barrier = BufferMemoryBarrier2::default()
+            .buffer(buffer)
+            .offset(offset)
+            .size(size)
+            .src_queue_family_index(self.transfer_queue.queue_family_index)
+            .dst_queue_family_index(self.graphics_queue.queue_family_index);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &[barrier
+                    .src_stage_mask(PipelineStageFlags2::COPY)
+                    .src_access_mask(AccessFlags2::TRANSFER_WRITE)],
+                &[],
+            );
+        }
+        self.recorded_buffer_acquires.push(
+            barrier
+                .dst_stage_mask(UPLOAD_DST_STAGES)
+                .dst_access_mask(UPLOAD_DST_ACCESS),
+        );
+    }
+
+    /// Moves `image` to SHADER_READ_ONLY_OPTIMAL, as part of the ownership transfer if there
+    /// is one.
+    fn release_image(&mut self, cmd: CommandBuffer, image: Image) {
+        let (src_family, dst_family) = if self.dedicated_transfer() {
+            (
+                self.transfer_queue.queue_family_index,
+                self.graphics_queue.queue_family_index,
+            )
+        } else {
+            (QUEUE_FAMILY_IGNORED, QUEUE_FAMILY_IGNORED)
+        };
+        let barrier = ImageMemoryBarrier2::default()
+            .old_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .image(image)
+            .subresource_range(image_subresource_range(ImageAspectFlags::COLOR));
+        // the transition has to finish before the semaphore signal, a release ignores the
+        // destination stage
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &[],
+                &[barrier
+                    .src_stage_mask(PipelineStageFlags2::COPY)
+                    .src_access_mask(AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(PipelineStageFlags2::ALL_COMMANDS)],
+            );
+        }
+        if self.dedicated_transfer() {
+            self.recorded_image_acquires.push(
+                barrier
+                    .dst_stage_mask(UPLOAD_DST_STAGES)
+                    .dst_access_mask(UPLOAD_DST_ACCESS),
+            );
+        }
+    }
+}
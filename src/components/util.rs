@@ -1,12 +1,45 @@
 use std::{ io::{Cursor, Error}, path::Path};
 
 use ash::{util::read_spv, vk::{ShaderModule, ShaderModuleCreateInfo}, Device};
+use log::warn;
 
+use super::embedded_shaders::embedded_shader;
 
+
+/// Loads the SPIR-V at `file_path`, see `read_shader_code` for when the copy embedded at build
+/// time is used instead.
 pub fn load_shader_module(file_path: &str, device: &Device) -> Result<ShaderModule, Error> {
-    let code: _ = read_spv(&mut read_file_as_cursor(file_path)).unwrap();
+    let code = read_shader_code(file_path)?;
     let create_info = ShaderModuleCreateInfo::default().code(&code);
-    Ok(unsafe { device.create_shader_module(&create_info, None).unwrap() })
+    unsafe { device.create_shader_module(&create_info, None) }.map_err(Error::other)
+}
+
+/// SPIR-V words of the shader at `file_path`. Embedded shaders are compiled from the sources
+/// when the crate is built, the file only replaces them if it was written after the executable,
+/// a file that is older may be stale.
+pub fn read_shader_code(file_path: &str) -> Result<Vec<u32>, Error> {
+    let Some(embedded) = embedded_shader(file_path) else {
+        return read_spv_file(file_path);
+    };
+    if newer_than_executable(file_path) {
+        match read_spv_file(file_path) {
+            Ok(code) => return Ok(code),
+            Err(err) => warn!("{file_path}: {err}, using the embedded copy"),
+        }
+    }
+    read_spv(&mut Cursor::new(embedded))
+}
+
+fn read_spv_file(file_path: &str) -> Result<Vec<u32>, Error> {
+    std::fs::read(file_path).and_then(|bytes| read_spv(&mut Cursor::new(bytes)))
+}
+
+fn newer_than_executable(file_path: &str) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(Path::new(file_path)), std::env::current_exe()) {
+        (Ok(file), Ok(exe)) => modified(&exe).is_ok_and(|exe| file > exe),
+        _ => false,
+    }
 }
 
 pub fn read_file_as_cursor<P: AsRef<Path>>(path: P) -> Cursor<Vec<u8>> {
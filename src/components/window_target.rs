@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{Fence, PresentModeKHR, Semaphore};
+use winit::window::{Window, WindowId};
+
+use crate::renderer::{ImageIndex, MAX_FRAMES};
+
+use super::{
+    device::VkDevice,
+    frame_data::create_semaphore_info,
+    swapchain::{ImageDetails, KHRSwapchain},
+    swapchain_sync::SwapchainSync,
+};
+
+/// What a window is presented through: the swapchain on its surface, the views of the
+/// swapchain images and the synchronization tied to them. Every window drawn by a renderer has
+/// its own, they share the device and the frames in flight.
+pub struct WindowTarget {
+    device: Arc<VkDevice>,
+    pub window_id: WindowId,
+    /// Holds the surface of the window, which goes with the last reference.
+    pub swapchain: Arc<KHRSwapchain>,
+    pub image_details: Vec<ImageDetails>,
+    pub sync: SwapchainSync,
+    /// Signaled by the acquire of each frame in flight, waited on by the copy of the frame.
+    acquire_semaphores: Vec<Semaphore>,
+    /// Set while the window has no area to present to, e.g. minimized. Nothing is presented
+    /// and the swapchain is recreated once it has a size again.
+    pub minimized: bool,
+    /// Set when the window changed its size or display mode, the swapchain is recreated before
+    /// the next frame instead of waiting for it to be reported out of date.
+    pub outdated: bool,
+}
+
+impl WindowTarget {
+    pub fn new(
+        device: Arc<VkDevice>,
+        window_id: WindowId,
+        swapchain: Arc<KHRSwapchain>,
+    ) -> Result<Self> {
+        let image_details = swapchain.create_image_details()?;
+        let sync = SwapchainSync::new(device.clone(), image_details.len())?;
+        let mut acquire_semaphores = Vec::with_capacity(MAX_FRAMES);
+        for _ in 0..MAX_FRAMES {
+            acquire_semaphores
+                .push(unsafe { device.create_semaphore(&create_semaphore_info(), None)? });
+        }
+        Ok(Self {
+            device,
+            window_id,
+            swapchain,
+            image_details,
+            sync,
+            acquire_semaphores,
+            minimized: false,
+            outdated: false,
+        })
+    }
+
+    pub fn acquire_semaphore(&self, frame_idx: usize) -> Semaphore {
+        self.acquire_semaphores[frame_idx]
+    }
+
+    /// Acquires the next swapchain image for the frame `frame_idx` guarded by `render_fence`,
+    /// `None` when the swapchain is out of date and has to be recreated first.
+    pub fn acquire(&mut self, frame_idx: usize, render_fence: Fence) -> Result<Option<ImageIndex>> {
+        match unsafe {
+            self.swapchain.s_device.acquire_next_image(
+                **self.swapchain,
+                u64::MAX,
+                self.acquire_semaphores[frame_idx],
+                Fence::null(),
+            )
+        } {
+            Ok(acquired) => {
+                let image_index = ImageIndex::new(acquired);
+                self.sync.claim(*image_index, render_fence)?;
+                Ok(Some(image_index))
+            }
+            Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the swapchain with one of the current size of `window` and `present_mode`, or
+    /// the closest mode the surface supports. The device must be idle and the window must have
+    /// an area.
+    pub fn recreate(&mut self, window: &Window, present_mode: PresentModeKHR) -> Result<()> {
+        let swapchain = Arc::new(self.swapchain.recreate(window, present_mode)?);
+        let image_details = swapchain.create_image_details()?;
+        let sync = SwapchainSync::new(self.device.clone(), image_details.len())?;
+        self.swapchain.destroy(&self.image_details);
+        self.swapchain = swapchain;
+        self.image_details = image_details;
+        self.sync.destroy();
+        self.sync = sync;
+        self.minimized = false;
+        self.outdated = false;
+        Ok(())
+    }
+
+    /// The device must be idle. The surface is destroyed once the target is dropped.
+    pub fn destroy(&mut self) {
+        self.swapchain.destroy(&self.image_details);
+        self.image_details.clear();
+        self.sync.destroy();
+        for semaphore in self.acquire_semaphores.drain(..) {
+            unsafe { self.device.destroy_semaphore(semaphore, None) };
+        }
+    }
+}
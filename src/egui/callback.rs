@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use ash::vk::{CommandBuffer, Rect2D, RenderPass, Viewport};
+
+use crate::components::device::VkDevice;
+
+/// Handle of a paint callback registered with `EguiRenderer::register_paint_callback`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PaintCallbackId(pub u64);
+
+impl PaintCallbackId {
+    /// Shape running the registered callback inside `rect` (in points), add it with
+    /// `ui.painter().add(..)`.
+    pub fn paint_callback(self, rect: egui::Rect) -> egui::PaintCallback {
+        egui::PaintCallback {
+            rect,
+            callback: Arc::new(self),
+        }
+    }
+}
+
+/// State a paint callback records with. Viewport and scissor are already set on the command
+/// buffer, the egui pipeline state is restored after the callback returns.
+pub struct PaintCallbackInfo<'a> {
+    pub device: &'a Arc<VkDevice>,
+    pub command_buffer: CommandBuffer,
+    /// egui render pass the callback is recorded in, pipelines have to be compatible with it.
+    pub render_pass: RenderPass,
+    /// The callback rect in physical pixels.
+    pub viewport: Viewport,
+    /// The clip rect of the callback, clamped to the render area.
+    pub scissor: Rect2D,
+    pub pixels_per_point: f32,
+}
+
+pub type PaintCallbackFn = Box<dyn Fn(&PaintCallbackInfo)>;
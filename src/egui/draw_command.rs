@@ -0,0 +1,38 @@
+use ash::vk::{Rect2D, Viewport};
+use egui::TextureId;
+
+use super::callback::PaintCallbackId;
+
+/// A single egui draw call referencing geometry that already lives in the egui frame buffers.
+#[derive(Debug, Clone, Copy)]
+pub struct EguiDrawCommand {
+    pub texture_id: TextureId,
+    pub scissors: Rect2D,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+}
+
+/// A `Primitive::Callback` whose payload is a registered `PaintCallbackId`.
+#[derive(Debug, Clone, Copy)]
+pub struct EguiCallbackCommand {
+    pub callback_id: PaintCallbackId,
+    pub viewport: Viewport,
+    pub scissors: Rect2D,
+}
+
+/// Everything egui asked to be recorded, in paint order.
+#[derive(Debug, Clone, Copy)]
+pub enum EguiCommand {
+    Draw(EguiDrawCommand),
+    Callback(EguiCallbackCommand),
+}
+
+impl EguiCommand {
+    pub fn scissors_mut(&mut self) -> &mut Rect2D {
+        match self {
+            EguiCommand::Draw(draw_command) => &mut draw_command.scissors,
+            EguiCommand::Callback(callback_command) => &mut callback_command.scissors,
+        }
+    }
+}
@@ -3,14 +3,43 @@ use anyhow::Error;
 use egui::{epaint::ImageDelta, ImageData, TextureId};
 
 use crate::components::{
-  allocation_types::AllocatedImage, descriptors::DescriptorSetDetails
+  allocation_types::AllocatedImage, descriptors::DescriptorSetDetails, sampler::VkSampler,
 };
 
+/// Decides which egui pipeline draws a texture, independent of the id egui assigned to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureKind {
+    Font,
+    Image,
+}
+
+impl TextureKind {
+    pub fn of(image_data: &ImageData) -> Self {
+        match image_data {
+            ImageData::Font(_) => TextureKind::Font,
+            ImageData::Color(_) => TextureKind::Image,
+        }
+    }
+
+    /// Index into the egui pipelines, which are created font shader first.
+    pub fn pipeline_index(self) -> usize {
+        match self {
+            TextureKind::Font => 0,
+            TextureKind::Image => 1,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TextureInformationData {
+    /// Freed with the texture for the ones created from egui deltas, user textures share it
+    /// with the application.
     pub allocated_image: AllocatedImage,
     pub descriptor_set_details: DescriptorSetDetails,
     pub texture_id: TextureId,
+    pub kind: TextureKind,
+    /// Sampler of user textures, kept alive as long as the descriptor set using it.
+    pub sampler: Option<VkSampler>,
 }
 
 impl TextureInformationData {
@@ -23,12 +52,72 @@ impl TextureInformationData {
        T: FnOnce(&ImageData) -> AllocatedImage,
         D: FnOnce(&AllocatedImage) -> Result<DescriptorSetDetails, Error>
     {
+        let kind = TextureKind::of(&texture_delta_tuple.1.image);
         let allocated_image = image_creator(&texture_delta_tuple.1.image);
         let descriptor_set_details = descriptor_creator(&allocated_image).unwrap();
         Self {
             allocated_image,
             descriptor_set_details,
             texture_id: texture_delta_tuple.0,
+            kind,
+            sampler: None,
+        }
+    }
+    /// Texture owned by the application, drawn with the image pipeline.
+    pub fn user(
+        texture_id: TextureId,
+        allocated_image: AllocatedImage,
+        descriptor_set_details: DescriptorSetDetails,
+        sampler: VkSampler,
+    ) -> Self {
+        Self {
+            allocated_image,
+            descriptor_set_details,
+            texture_id,
+            kind: TextureKind::Image,
+            sampler: Some(sampler),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use egui::{epaint::ImageDelta, ColorImage, FontImage, ImageData, TextureId, TextureOptions};
+
+    use super::TextureKind;
+
+    #[test]
+    fn pipeline_selection_follows_the_image_data() {
+        let font = || {
+            ImageDelta::full(
+                ImageData::Font(FontImage::new([4, 4])),
+                TextureOptions::LINEAR,
+            )
+        };
+        let image = |size| {
+            ImageDelta::full(
+                ImageData::Color(Arc::new(ColorImage::new(size, egui::Color32::WHITE))),
+                TextureOptions::LINEAR,
+            )
+        };
+        // the font atlas is not always id 0 and an image may get id 0, the pipeline must
+        // follow the kind of data either way: 0 is the font pipeline, 1 the image pipeline
+        let deltas = [
+            (TextureId::Managed(0), font(), 0),
+            (TextureId::Managed(0), image([2, 2]), 1),
+            (TextureId::Managed(1), image([8, 4]), 1),
+            (TextureId::Managed(3), font(), 0),
+            (TextureId::Managed(7), image([1, 1]), 1),
+            (TextureId::User(0), image([1, 1]), 1),
+        ];
+        for (id, delta, pipeline) in deltas {
+            assert_eq!(
+                TextureKind::of(&delta.image).pipeline_index(),
+                pipeline,
+                "texture {id:?}"
+            );
         }
     }
 }
@@ -1,60 +1,62 @@
 
-use std::mem::offset_of;
-
-use ash::vk::{
-    Extent2D, Format, Offset2D, Rect2D,
-    VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate, Viewport,
-};
+use anyhow::Result;
+use ash::vk::{Extent2D, Offset2D, Rect2D, Viewport};
 use egui::{
-    epaint::{Primitive, Vertex},
+    epaint::{ClippedShape, Primitive, Vertex},
     text::Fonts,
     ClippedPrimitive, Context, FullOutput, ViewportId,
 };
 use egui_winit::{EventResponse, State};
+use log::warn;
 use winit::{
     event::WindowEvent,
     window::{Theme, Window},
 };
 
-use crate::
-    geom::{mesh::Mesh, VertexAttributes}
-;
+use crate::macros::vertex_attributes::vertex_attributes;
 
-impl VertexAttributes for Vertex {
-    fn get_binding_description() -> Vec<VertexInputBindingDescription> {
-        vec![VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(size_of::<Vertex>() as u32)
-            .input_rate(VertexInputRate::VERTEX)]
-    }
+use super::{
+    callback::PaintCallbackId,
+    draw_command::{EguiCallbackCommand, EguiCommand, EguiDrawCommand},
+};
+
+vertex_attributes!(Vertex {
+    pos: R32G32_SFLOAT,
+    uv: R32G32_SFLOAT,
+    color: R8G8B8A8_SRGB,
+});
 
-    fn get_attribute_description() -> Vec<VertexInputAttributeDescription> {
-        let mut attribute_descriptons: [VertexInputAttributeDescription; 3] =
-            [Default::default(); 3];
-        attribute_descriptons[0] = attribute_descriptons[0]
-            .binding(0)
-            .location(0)
-            .format(Format::R32G32_SFLOAT)
-            .offset(offset_of!(Vertex, pos) as u32);
-
-        attribute_descriptons[1] = attribute_descriptons[1]
-            .binding(0)
-            .location(1)
-            .format(Format::R32G32_SFLOAT)
-            .offset(offset_of!(Vertex, uv) as u32);
-
-        attribute_descriptons[2] = attribute_descriptons[2]
-            .binding(0)
-            .location(2)
-            .format(Format::R8G8B8A8_SRGB)
-            .offset(offset_of!(Vertex, color) as u32);
-        attribute_descriptons.to_vec()
+/// Converts an egui clip rect (in points) into a scissor in physical pixels clamped to
+/// `extent`, `None` if nothing of it is visible.
+fn clip_rect_to_scissor(
+    clip_rect: egui::Rect,
+    pixels_per_point: f32,
+    extent: Extent2D,
+) -> Option<Rect2D> {
+    let to_pixels =
+        |value: f32, max: u32| ((value * pixels_per_point).round() as i32).clamp(0, max as i32);
+    let min_x = to_pixels(clip_rect.min.x, extent.width);
+    let min_y = to_pixels(clip_rect.min.y, extent.height);
+    let max_x = to_pixels(clip_rect.max.x, extent.width);
+    let max_y = to_pixels(clip_rect.max.y, extent.height);
+    if max_x <= min_x || max_y <= min_y {
+        return None;
     }
+    Some(
+        Rect2D::default()
+            .offset(Offset2D::default().x(min_x).y(min_y))
+            .extent(
+                Extent2D::default()
+                    .width((max_x - min_x) as u32)
+                    .height((max_y - min_y) as u32),
+            ),
+    )
 }
 
 pub struct EguiIntegration {
     state: State,
     has_run: bool,
+    screen_rect: Option<egui::Rect>,
 }
 
 impl EguiIntegration {
@@ -73,6 +75,7 @@ impl EguiIntegration {
         Self {
             state,
             has_run: false,
+            screen_rect: None,
         }
     }
 
@@ -80,8 +83,26 @@ impl EguiIntegration {
         self.state.on_window_event(window, event)
     }
 
+    /// Whether egui uses the pointer, e.g. it hovers a window or drags a widget.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.state.egui_ctx().wants_pointer_input()
+    }
+
+    pub fn pixels_per_point(&self) -> f32 {
+        self.state.egui_ctx().pixels_per_point()
+    }
+
+    /// Overrides the screen rect (in points) egui lays its windows out in, `None` uses the
+    /// whole window.
+    pub fn set_screen_rect(&mut self, screen_rect: Option<egui::Rect>) {
+        self.screen_rect = screen_rect;
+    }
+
     pub fn run(&mut self, run_ui: impl FnMut(&Context), window: &Window) -> FullOutput {
-        let raw_input = self.state.take_egui_input(window);
+        let mut raw_input = self.state.take_egui_input(window);
+        if let Some(screen_rect) = self.screen_rect {
+            raw_input.screen_rect = Some(screen_rect);
+        }
         let output = self.state.egui_ctx().run(raw_input.clone(), run_ui);
         self.has_run = true;
         self.state
@@ -97,14 +118,19 @@ impl EguiIntegration {
         }
     }
 
-    #[allow(unused)]
-    pub fn convert(&mut self, extent: Extent2D, output: &FullOutput) -> Vec<Mesh<Vertex, u32>> {
-        let clipped_primitives = self.state.egui_ctx().tessellate(
-            output.shapes.clone(),
-            self.state.egui_ctx().pixels_per_point(),
-        );
+    /// Tessellates `shapes` once and hands every mesh straight to `upload`, which is expected
+    /// to write it into GPU visible memory and return the `(vertex_offset, first_index)` it
+    /// ended up at.
+    pub fn convert(
+        &mut self,
+        extent: Extent2D,
+        shapes: Vec<ClippedShape>,
+        mut upload: impl FnMut(&[Vertex], &[u32]) -> Result<(i32, u32)>,
+    ) -> Result<Vec<EguiCommand>> {
+        let scale_factor = self.state.egui_ctx().pixels_per_point(); // egui provides scale factor
+        let clipped_primitives = self.state.egui_ctx().tessellate(shapes, scale_factor);
 
-        let mut meshes: Vec<Mesh<Vertex, u32>> = Vec::new();
+        let mut draw_commands: Vec<EguiCommand> = Vec::with_capacity(clipped_primitives.len());
 
         for ClippedPrimitive {
             primitive,
@@ -113,42 +139,138 @@ impl EguiIntegration {
         {
             match primitive {
                 Primitive::Mesh(mesh) => {
-                    let vertices = mesh.vertices;
-                    let indices = mesh.indices;
-                    let scale_factor = self.state.egui_ctx().pixels_per_point(); // egui provides scale factor
-
-                    let clip_min_x = (clip_rect.min.x * scale_factor).round() as i32;
-                    let clip_min_y = (clip_rect.min.y * scale_factor).round() as i32;
-                    let clip_max_x = (clip_rect.max.x * scale_factor).round() as i32;
-                    let clip_max_y = (clip_rect.max.y * scale_factor).round() as i32;
+                    if mesh.indices.is_empty() {
+                        continue;
+                    }
+                    let Some(scissor_rect) = clip_rect_to_scissor(clip_rect, scale_factor, extent)
+                    else {
+                        continue;
+                    };
+                    let (vertex_offset, first_index) = upload(&mesh.vertices, &mesh.indices)?;
+                    draw_commands.push(EguiCommand::Draw(EguiDrawCommand {
+                        texture_id: mesh.texture_id,
+                        scissors: scissor_rect,
+                        first_index,
+                        index_count: mesh.indices.len() as u32,
+                        vertex_offset,
+                    }));
+                }
+                Primitive::Callback(paint_callback) => {
+                    let Some(callback_id) =
+                        paint_callback.callback.downcast_ref::<PaintCallbackId>()
+                    else {
+                        warn!("Skipping egui paint callback that is not a PaintCallbackId");
+                        continue;
+                    };
+                    let rect = paint_callback.rect;
+                    let viewport = Viewport::default()
+                        .x(rect.min.x * scale_factor)
+                        .y(rect.min.y * scale_factor)
+                        .width(rect.width() * scale_factor)
+                        .height(rect.height() * scale_factor)
+                        .min_depth(0.0)
+                        .max_depth(1.0);
+                    let Some(scissors) =
+                        clip_rect_to_scissor(clip_rect.intersect(rect), scale_factor, extent)
+                    else {
+                        continue;
+                    };
+                    draw_commands.push(EguiCommand::Callback(EguiCallbackCommand {
+                        callback_id: *callback_id,
+                        viewport,
+                        scissors,
+                    }));
+                }
+            }
+        }
+        Ok(draw_commands)
+    }
+}
 
-                    // Calculate the physical extent
-                    let scissor_width = (clip_max_x - clip_min_x).max(0) as u32;
-                    let scissor_height = (clip_max_y - clip_min_y).max(0) as u32;
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Extent2D, Offset2D};
+    use egui::{pos2, Rect};
 
-                    // Calculate the physical offset
-                    let scissor_x = clip_min_x.max(0); // Clamp to 0
-                    let scissor_y = clip_min_y.max(0); // Clamp to 0
+    use super::clip_rect_to_scissor;
 
-                    let scissor_rect = Rect2D::default()
-                        .offset(Offset2D::default().x(scissor_x).y(scissor_y))
-                        .extent(extent); // Use clamped_width/height if clamping to render area
+    const EXTENT: Extent2D = Extent2D {
+        width: 1600,
+        height: 1200,
+    };
 
-                    let viewport = Viewport::default()
-                        .height(clip_rect.height())
-                        .width(clip_rect.width());
+    #[test]
+    fn scissor_uses_the_clip_rect_size() {
+        let scissor = clip_rect_to_scissor(
+            Rect::from_min_max(pos2(10.0, 20.0), pos2(110.0, 70.0)),
+            1.0,
+            EXTENT,
+        )
+        .unwrap();
+        assert_eq!(scissor.offset, Offset2D { x: 10, y: 20 });
+        assert_eq!(
+            scissor.extent,
+            Extent2D {
+                width: 100,
+                height: 50
+            }
+        );
+    }
 
-                    meshes.push(Mesh {
-                        vertices,
-                        indices,
-                        texture_id: Some(mesh.texture_id),
-                        scissors: scissor_rect,
-                        viewport,
-                    });
+    #[test]
+    fn scissor_scales_with_hidpi_factors() {
+        let clip_rect = Rect::from_min_max(pos2(10.0, 20.0), pos2(110.0, 70.0));
+        for (pixels_per_point, offset, extent) in [
+            (2.0, (20, 40), (200, 100)),
+            (1.5, (15, 30), (150, 75)),
+            (1.25, (13, 25), (125, 63)),
+        ] {
+            let scissor = clip_rect_to_scissor(clip_rect, pixels_per_point, EXTENT).unwrap();
+            assert_eq!(
+                scissor.offset,
+                Offset2D {
+                    x: offset.0,
+                    y: offset.1
                 }
-                Primitive::Callback(paint_callback) => todo!(),
-            }
+            );
+            assert_eq!(
+                scissor.extent,
+                Extent2D {
+                    width: extent.0,
+                    height: extent.1
+                }
+            );
         }
-        meshes
+    }
+
+    #[test]
+    fn scissor_is_clamped_to_the_extent() {
+        // egui hands out clip rects reaching past the screen, e.g. Rect::EVERYTHING for tooltips
+        let scissor = clip_rect_to_scissor(Rect::EVERYTHING, 2.0, EXTENT).unwrap();
+        assert_eq!(scissor.offset, Offset2D { x: 0, y: 0 });
+        assert_eq!(scissor.extent, EXTENT);
+
+        let scissor = clip_rect_to_scissor(
+            Rect::from_min_max(pos2(-50.0, 500.0), pos2(100.0, 700.0)),
+            2.0,
+            EXTENT,
+        )
+        .unwrap();
+        assert_eq!(scissor.offset, Offset2D { x: 0, y: 1000 });
+        assert_eq!(
+            scissor.extent,
+            Extent2D {
+                width: 200,
+                height: 200
+            }
+        );
+    }
+
+    #[test]
+    fn scissor_outside_of_the_extent_is_skipped() {
+        let offscreen = Rect::from_min_max(pos2(900.0, 10.0), pos2(1000.0, 20.0));
+        assert!(clip_rect_to_scissor(offscreen, 2.0, EXTENT).is_none());
+        let empty = Rect::from_min_max(pos2(10.0, 10.0), pos2(10.0, 20.0));
+        assert!(clip_rect_to_scissor(empty, 1.0, EXTENT).is_none());
     }
 }
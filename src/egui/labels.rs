@@ -0,0 +1,37 @@
+use egui::{Align2, Color32, Context, FontId, Id, LayerId, Order, Pos2};
+
+/// Text drawn over the scene at a point of the render target, e.g. a projected world position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenLabel {
+    /// Position of the label center in physical pixels.
+    pub position: [f32; 2],
+    pub text: String,
+    pub color: Color32,
+}
+
+impl ScreenLabel {
+    pub fn new(position: [f32; 2], text: impl Into<String>, color: Color32) -> Self {
+        Self {
+            position,
+            text: text.into(),
+            color,
+        }
+    }
+}
+
+/// Paints `labels` below all egui windows.
+pub fn paint_labels(ctx: &Context, labels: &[ScreenLabel], pixels_per_point: f32) {
+    if labels.is_empty() {
+        return;
+    }
+    let painter = ctx.layer_painter(LayerId::new(Order::Background, Id::new("screen_labels")));
+    for label in labels {
+        let position = Pos2::new(label.position[0], label.position[1]) / pixels_per_point;
+        let galley =
+            painter.layout_no_wrap(label.text.clone(), FontId::proportional(14.0), label.color);
+        let rect = Align2::CENTER_BOTTOM.anchor_size(position, galley.size());
+        // keeps the text readable on bright geometry
+        painter.rect_filled(rect.expand(2.0), 2.0, Color32::from_black_alpha(160));
+        painter.galley(rect.min, galley, label.color);
+    }
+}
@@ -1,45 +1,73 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Instant,
+};
 
 use anyhow::{anyhow, Result};
 use ash::vk::{
     AttachmentLoadOp, BlendFactor, BlendOp, ClearValue, ColorComponentFlags, CommandBuffer,
-    CommandBufferBeginInfo, CommandBufferResetFlags, CommandBufferUsageFlags, CullModeFlags,
-    DescriptorType, DynamicState, Extent2D, Format, FrontFace, ImageLayout, IndexType,
-    PipelineBindPoint, PolygonMode, PrimitiveTopology, Rect2D, RenderPass, RenderPassBeginInfo,
+    BufferUsageFlags, CommandBufferBeginInfo, CommandBufferResetFlags, CommandBufferUsageFlags,
+    CullModeFlags, DescriptorType, DynamicState, Extent2D, Fence, Format, FrontFace, ImageLayout,
+    IndexType,
+    Offset2D, PipelineBindPoint, PolygonMode, PrimitiveTopology, Rect2D, RenderPass,
+    RenderPassBeginInfo,
     SampleCountFlags, ShaderStageFlags, SubpassContents, Viewport,
 };
-use egui::{epaint::Vertex, TextureId, WidgetText};
-use image_information_data::TextureInformationData;
+use callback::{PaintCallbackFn, PaintCallbackId, PaintCallbackInfo};
+use draw_command::EguiCommand;
+use egui::{
+    epaint::{ImageDelta, Vertex},
+    TextureId, TexturesDelta, ViewportId, WidgetText,
+};
+use image_information_data::{TextureInformationData, TextureKind};
 use integration::EguiIntegration;
 use log::debug;
 use nalgebra::Matrix4;
+use labels::{paint_labels, ScreenLabel};
+use notifications::{NotificationLevel, Notifications};
+use scene_editor::{SceneEdit, SceneEditor, SceneOutline};
 use thiserror::Error;
-use winit::window::Window;
+use winit::{event::WindowEvent, window::Window};
 
 use crate::{
     components::{
-        allocation_types::{AllocatedImage, VkBuffer, VkFrameBuffer, IDENTIFIER},
+        allocation_types::{AllocatedImage, VkFrameBuffer, IDENTIFIER},
         command_buffers::{self, VkCommandPool},
         deletion_queue::DeletionQueue,
         descriptors::{DescriptorAllocator, PoolSizeRatio},
         device::VkDevice,
         image_util::image_transition,
-        memory_allocator::MemoryAllocator,
+        frame_buffer_pool::FrameBufferPool,
+        memory_allocator::{MemoryAllocator, MemoryStatistics},
         pipeline::{
             self, create_multisampling_state, create_rasterizer_state, ShaderInformation,
-            VkPipeline,
+            VertexInput, VkPipeline,
         },
         queue::VkQueue,
         render_pass::VkRenderPass,
-        sampler::VkSampler,
+        sampler::{SamplerCache, VkSampler},
         swapchain::ImageDetails,
     },
-    geom::{egui_push_constant, mesh::MeshBuffers, VertexAttributes},
-    renderer::ImageIndex,
+    geom::{
+        egui_push_constant, egui_rect_push_constant,
+        push_constants::{PushConstant, PushConstantLayout},
+    },
+    misc::{analysis::AnalysisResults, auto_quality::AutoQualitySettings},
+    renderer::{ImageIndex, MAX_FRAMES},
 };
 
+pub mod callback;
+pub mod draw_command;
 pub mod image_information_data;
 pub mod integration;
+pub mod labels;
+pub mod notifications;
+pub mod scene_editor;
+
+/// Initial sizes of the egui geometry buffers, they grow when a frame needs more.
+const EGUI_VERTEX_CAPACITY: usize = 1 << 14;
+const EGUI_INDEX_CAPACITY: usize = 1 << 15;
 
 #[derive(Error, Debug)]
 pub enum EguiRenderError {
@@ -55,7 +83,8 @@ pub struct EguiRenderer {
     descriptor_allocator: DescriptorAllocator,
     texture_informations: HashMap<TextureId, TextureInformationData>,
     pub integration: EguiIntegration,
-    mesh_buffers: Vec<MeshBuffers<Vertex, u32>>,
+    vertex_buffers: FrameBufferPool<Vertex>,
+    index_buffers: FrameBufferPool<u32>,
     memory_allocator: Arc<MemoryAllocator>,
     graphics_queue: Arc<VkQueue>,
     command_pool: VkCommandPool,
@@ -64,6 +93,94 @@ pub struct EguiRenderer {
     extent: Extent2D,
     framebuffers: Vec<VkFrameBuffer>,
     main_deletion_queue: DeletionQueue,
+    target_rect: Option<Rect2D>,
+    draw_commands: Vec<EguiCommand>,
+    repaint_requested: bool,
+    repaint_deadline: Option<Instant>,
+    analysis_results: Option<AnalysisResults>,
+    memory_statistics: Option<MemoryStatistics>,
+    /// Clear color of the scene pass, edited in the debug window. Also used by this pass when
+    /// it was created with `AttachmentLoadOp::CLEAR`.
+    clear_color: [f32; 4],
+    /// Thresholds of the auto quality controller, edited in the debug window. `None` while the
+    /// controller is disabled.
+    auto_quality_settings: Option<AutoQualitySettings>,
+    next_user_texture: u64,
+    paint_callbacks: HashMap<PaintCallbackId, PaintCallbackFn>,
+    next_paint_callback: u64,
+    /// Toasts shown in the corner of the screen.
+    notifications: Notifications,
+    screen_labels: Vec<ScreenLabel>,
+    scene_editor: SceneEditor,
+    /// Replaced, freed or unregistered textures with the number of draws left before they are
+    /// destroyed, frames still in flight may sample them.
+    retired_textures: Vec<(usize, TextureInformationData)>,
+}
+
+fn rect_to_points(rect: Rect2D, pixels_per_point: f32) -> egui::Rect {
+    egui::Rect::from_min_size(
+        egui::pos2(
+            rect.offset.x as f32 / pixels_per_point,
+            rect.offset.y as f32 / pixels_per_point,
+        ),
+        egui::vec2(
+            rect.extent.width as f32 / pixels_per_point,
+            rect.extent.height as f32 / pixels_per_point,
+        ),
+    )
+}
+
+/// Creates the image and descriptor set for a full (non partial) egui texture delta.
+fn upload_texture(
+    memory_allocator: &MemoryAllocator,
+    command_pool: &VkCommandPool,
+    descriptor_allocator: &mut DescriptorAllocator,
+    font_sampler: &VkSampler,
+    texture_sampler: &VkSampler,
+    delta: (TextureId, ImageDelta),
+) -> Result<TextureInformationData> {
+    if let TextureId::User(_) = delta.0 {
+        return Err(anyhow!(EguiRenderError::NotManaged(String::from(
+            "User handled texture data",
+        ))));
+    }
+    let sampler = match TextureKind::of(&delta.1.image) {
+        TextureKind::Font => font_sampler.clone(),
+        TextureKind::Image => texture_sampler.clone(),
+    };
+    Ok(TextureInformationData::new(
+        delta,
+        |image_data| {
+            memory_allocator
+                .create_egui_texture_image(command_pool, image_data, false)
+                .unwrap()
+        },
+        |allocated_image| {
+            Ok(descriptor_allocator.write_image_descriptors(
+                &allocated_image.image_details.image_view,
+                &ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ShaderStageFlags::FRAGMENT,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                Some(sampler),
+            )?)
+        },
+    ))
+}
+
+fn clamp_rect(rect: Rect2D, bounds: Rect2D) -> Rect2D {
+    let min_x = rect.offset.x.max(bounds.offset.x);
+    let min_y = rect.offset.y.max(bounds.offset.y);
+    let max_x = (rect.offset.x + rect.extent.width as i32)
+        .min(bounds.offset.x + bounds.extent.width as i32);
+    let max_y = (rect.offset.y + rect.extent.height as i32)
+        .min(bounds.offset.y + bounds.extent.height as i32);
+    Rect2D::default()
+        .offset(Offset2D::default().x(min_x).y(min_y))
+        .extent(
+            Extent2D::default()
+                .width((max_x - min_x).max(0) as u32)
+                .height((max_y - min_y).max(0) as u32),
+        )
 }
 
 impl EguiRenderer {
@@ -75,12 +192,16 @@ impl EguiRenderer {
         extent: Extent2D,
         format: Format,
         image_details: Vec<ImageDetails>,
+        load_op: AttachmentLoadOp,
+        clear_color: [f32; 4],
+        samplers: &SamplerCache,
     ) -> Result<Self> {
-        let main_deletion_queue = DeletionQueue::new(vk_device.clone(), memory_allocator.clone());
+        let mut main_deletion_queue =
+            DeletionQueue::new(vk_device.clone(), memory_allocator.clone());
         let egui_cmd_pool: VkCommandPool =
             command_buffers::VkCommandPool::new(graphics_queue.clone());
-        let egui_font_sampler = VkSampler::get_font_sampler(vk_device.clone());
-        let egui_texture_sampler = VkSampler::get_texture_sampler(vk_device.clone());
+        let egui_font_sampler = samplers.font_sampler();
+        let egui_texture_sampler = samplers.texture_sampler();
         let mut egui_descriptor_allocator = DescriptorAllocator::new(
             vk_device.clone(),
             10,
@@ -97,7 +218,7 @@ impl EguiRenderer {
             format,
             ImageLayout::GENERAL,
             ImageLayout::PRESENT_SRC_KHR,
-            AttachmentLoadOp::LOAD,
+            load_op,
             false,
         )?);
 
@@ -137,43 +258,14 @@ impl EguiRenderer {
             for delta in textures_delta_set {
                 texture_informations.insert(
                     delta.0,
-                    TextureInformationData::new(
-                        delta.clone(),
-                        |image_data| {
-                            memory_allocator
-                                .create_egui_texture_image(
-                                    &egui_cmd_pool,
-                                    image_data,
-                                    false,
-                                )
-                                .unwrap()
-                                .unit
-                                .get_copied::<AllocatedImage>()
-                        },
-                        |allocated_image| {
-                            let sampler = match delta.0 {
-                                TextureId::Managed(id) => match id {
-                                    0 => Some(egui_font_sampler.clone()),
-                                    _ => Some(egui_texture_sampler.clone()),
-                                },
-                                TextureId::User(_) => {
-                                    return Err(anyhow!(EguiRenderError::NotManaged(
-                                        String::from("User handled texture data",)
-                                    )));
-                                }
-                            };
-
-                            Ok(egui_descriptor_allocator
-                                .write_image_descriptors(
-                                    &allocated_image.image_details.image_view,
-                                    &ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                                    ShaderStageFlags::FRAGMENT,
-                                    DescriptorType::COMBINED_IMAGE_SAMPLER,
-                                    sampler,
-                                )
-                                .unwrap())
-                        },
-                    ),
+                    upload_texture(
+                        &memory_allocator,
+                        &egui_cmd_pool,
+                        &mut egui_descriptor_allocator,
+                        &egui_font_sampler,
+                        &egui_texture_sampler,
+                        delta,
+                    )?,
                 );
             }
         }
@@ -203,7 +295,6 @@ impl EguiRenderer {
                 vk_device.clone(),
                 &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
                 PrimitiveTopology::TRIANGLE_LIST,
-                ShaderStageFlags::VERTEX,
                 &[
                     ShaderInformation::vertex_2d_information(
                         "/Users/zapzap/Projects/piplup/shaders/2D_vertex_shader.spv".to_string(),
@@ -216,9 +307,10 @@ impl EguiRenderer {
                     .descriptor_set_details
                     .layout),
                 &extent,
-                Some(Matrix4::<f32>::identity()),
-                Vertex::get_binding_description(),
-                Vertex::get_attribute_description(),
+                Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+                    ShaderStageFlags::VERTEX,
+                )),
+                VertexInput::Pulling,
                 &[pipeline::create_color_blending_attachment_state(
                     ColorComponentFlags::R
                         | ColorComponentFlags::G
@@ -242,6 +334,20 @@ impl EguiRenderer {
                 false
             )?);
         }
+        let vertex_buffers = FrameBufferPool::<Vertex>::new(
+            memory_allocator.clone(),
+            &[graphics_queue.clone()],
+            EGUI_VERTEX_CAPACITY,
+            BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            &mut main_deletion_queue,
+        )?;
+        let index_buffers = FrameBufferPool::<u32>::new(
+            memory_allocator.clone(),
+            &[graphics_queue.clone()],
+            EGUI_INDEX_CAPACITY,
+            BufferUsageFlags::INDEX_BUFFER,
+            &mut main_deletion_queue,
+        )?;
         Ok(Self {
             device: vk_device.clone(),
             font_sampler: egui_font_sampler,
@@ -256,11 +362,286 @@ impl EguiRenderer {
             render_pass,
             framebuffers,
             graphics_queue,
-            mesh_buffers: vec![],
+            vertex_buffers,
+            index_buffers,
             main_deletion_queue,
+            target_rect: None,
+            draw_commands: vec![],
+            repaint_requested: true,
+            repaint_deadline: None,
+            analysis_results: None,
+            memory_statistics: None,
+            clear_color,
+            auto_quality_settings: None,
+            next_user_texture: 0,
+            paint_callbacks: HashMap::new(),
+            next_paint_callback: 0,
+            notifications: Notifications::default(),
+            screen_labels: vec![],
+            scene_editor: SceneEditor::default(),
+            retired_textures: vec![],
         })
     }
 
+    /// Replaces the framebuffers on the swapchain images after the swapchain was recreated, the
+    /// old ones must not be in use anymore.
+    pub fn recreate_framebuffers(&mut self, image_details: &[ImageDetails], extent: Extent2D) {
+        self.extent = extent;
+        self.request_repaint();
+        self.framebuffers = VkFrameBuffer::create_framebuffers(
+            IDENTIFIER::SWAPCHAIN,
+            self.device.clone(),
+            self.render_pass.clone(),
+            self.extent,
+            image_details,
+        );
+    }
+
+    /// Restricts egui to the given physical pixel region of the swapchain image, `None` uses
+    /// the whole render area again.
+    pub fn set_target_rect(&mut self, target_rect: Option<Rect2D>) {
+        self.target_rect = target_rect;
+        self.request_repaint();
+    }
+
+    /// Forwards a window event to egui and schedules a repaint if egui cares about it.
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) {
+        if self.integration.input(window, event).repaint {
+            self.request_repaint();
+        }
+    }
+
+    /// Whether the pointer is busy with the UI, clicks are not meant for the scene then.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.integration.wants_pointer_input()
+    }
+
+    pub fn request_repaint(&mut self) {
+        self.repaint_requested = true;
+    }
+
+    /// Whether the UI has to be rebuilt this frame, either because of input or because egui
+    /// asked for it (animations, tooltips, ...). Otherwise the last frame's geometry is reused.
+    pub fn needs_repaint(&self) -> bool {
+        self.repaint_requested
+            || self
+                .repaint_deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Point in time egui asked to be repainted at even without new input, `None` if it
+    /// only needs to repaint on input.
+    pub fn repaint_deadline(&self) -> Option<Instant> {
+        self.repaint_deadline
+    }
+
+    /// Makes `image` usable in egui through the returned `TextureId`, e.g. with `ui.image`.
+    /// The image has to be in SHADER_READ_ONLY_OPTIMAL whenever the UI is drawn and stays owned
+    /// by the caller, `None` samples it with the egui texture sampler.
+    pub fn register_user_texture(
+        &mut self,
+        image: &AllocatedImage,
+        sampler: Option<VkSampler>,
+    ) -> Result<TextureId> {
+        let sampler = sampler.unwrap_or_else(|| self.texture_sampler.clone());
+        let descriptor_set_details = self.descriptor_allocator.write_image_descriptors(
+            &image.image_details.image_view,
+            &ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ShaderStageFlags::FRAGMENT,
+            DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Some(sampler.clone()),
+        )?;
+        let texture_id = TextureId::User(self.next_user_texture);
+        self.next_user_texture += 1;
+        self.texture_informations.insert(
+            texture_id,
+            TextureInformationData::user(
+                texture_id,
+                image.clone(),
+                descriptor_set_details,
+                sampler,
+            ),
+        );
+        self.request_repaint();
+        Ok(texture_id)
+    }
+
+    /// Stops drawing `texture_id`, the image itself is left to the caller.
+    pub fn unregister_user_texture(&mut self, texture_id: TextureId) {
+        if let TextureId::User(_) = texture_id {
+            // frames in flight may still sample it, its descriptor set is recycled afterwards
+            if let Some(texture) = self.texture_informations.remove(&texture_id) {
+                self.retired_textures.push((MAX_FRAMES, texture));
+                self.request_repaint();
+            }
+        }
+    }
+
+    /// Replaces the labels drawn over the scene, repaints only if they changed.
+    pub fn set_screen_labels(&mut self, labels: Vec<ScreenLabel>) {
+        if self.screen_labels != labels {
+            self.screen_labels = labels;
+            self.request_repaint();
+        }
+    }
+
+    /// Shows `message` in the bottom right corner until the timeout of its level runs out.
+    pub fn notify(&mut self, level: NotificationLevel, message: impl Into<String>) {
+        self.notifications.push(level, message);
+        self.request_repaint();
+    }
+
+    /// Registers custom Vulkan rendering that egui can embed through
+    /// `PaintCallbackId::paint_callback`, e.g. a 3D viewport inside a panel. The callback is
+    /// recorded into the egui render pass every frame the shape is part of the UI.
+    pub fn register_paint_callback(
+        &mut self,
+        callback: impl Fn(&PaintCallbackInfo) + 'static,
+    ) -> PaintCallbackId {
+        let callback_id = PaintCallbackId(self.next_paint_callback);
+        self.next_paint_callback += 1;
+        self.paint_callbacks.insert(callback_id, Box::new(callback));
+        callback_id
+    }
+
+    /// Callback shapes still referencing `callback_id` are skipped from now on.
+    pub fn unregister_paint_callback(&mut self, callback_id: PaintCallbackId) {
+        self.paint_callbacks.remove(&callback_id);
+    }
+
+    /// Uploads new and partially updated egui textures and retires the freed ones.
+    /// Partial updates patch the image in place, so the frames in `in_flight_fences` that may
+    /// still sample it are waited for first. Replaced and freed textures are retired instead.
+    fn apply_textures_delta(
+        &mut self,
+        textures_delta: TexturesDelta,
+        in_flight_fences: &[Fence],
+    ) -> Result<()> {
+        let mut waited = false;
+        for (texture_id, image_delta) in textures_delta.set {
+            match (image_delta.pos, self.texture_informations.get(&texture_id)) {
+                (Some(pos), Some(texture)) => {
+                    if !waited && !in_flight_fences.is_empty() {
+                        unsafe { self.device.wait_for_fences(in_flight_fences, true, u64::MAX)? };
+                        waited = true;
+                    }
+                    self.memory_allocator.update_egui_texture_image(
+                        &self.command_pool,
+                        &texture.allocated_image,
+                        pos,
+                        &image_delta.image,
+                    )?
+                }
+                _ => {
+                    let texture = upload_texture(
+                        &self.memory_allocator,
+                        &self.command_pool,
+                        &mut self.descriptor_allocator,
+                        &self.font_sampler,
+                        &self.texture_sampler,
+                        (texture_id, image_delta),
+                    )?;
+                    if let Some(replaced) = self.texture_informations.insert(texture_id, texture) {
+                        self.retired_textures.push((MAX_FRAMES, replaced));
+                    }
+                }
+            }
+        }
+        for texture_id in textures_delta.free {
+            if let Some(freed) = self.texture_informations.remove(&texture_id) {
+                self.retired_textures.push((MAX_FRAMES, freed));
+            }
+        }
+        Ok(())
+    }
+
+    fn destroy_retired_textures(&mut self) {
+        for (frames_left, _) in self.retired_textures.iter_mut() {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+        let (expired, retired): (Vec<_>, Vec<_>) = std::mem::take(&mut self.retired_textures)
+            .into_iter()
+            .partition(|(frames_left, _)| *frames_left == 0);
+        self.retired_textures = retired;
+        for (_, texture) in expired {
+            self.destroy_texture(texture);
+        }
+    }
+
+    /// Drops the image of a texture, the descriptor set goes to the next texture.
+    fn destroy_texture(&mut self, texture: TextureInformationData) {
+        self.descriptor_allocator.recycle(texture.descriptor_set_details);
+        debug!("egui texture {:?} has been deleted", texture.texture_id);
+    }
+
+    /// Destroys the textures, pipelines and pools of the UI pass, the device must be idle.
+    /// User textures only lose their descriptor set layout, their images stay with the caller.
+    pub fn destroy(&mut self) {
+        let textures = std::mem::take(&mut self.texture_informations).into_values();
+        let retired = std::mem::take(&mut self.retired_textures)
+            .into_iter()
+            .map(|(_, texture)| texture);
+        for texture in textures.chain(retired) {
+            self.destroy_texture(texture);
+        }
+        self.main_deletion_queue.flush();
+        self.descriptor_allocator.destroy_pools(self.device.clone());
+        self.pipelines.clear();
+        unsafe { self.device.destroy_command_pool(*self.command_pool, None) };
+    }
+
+    pub fn clear_color(&self) -> [f32; 4] {
+        self.clear_color
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+        self.request_repaint();
+    }
+
+    pub fn auto_quality_settings(&self) -> Option<AutoQualitySettings> {
+        self.auto_quality_settings
+    }
+
+    /// Shows the thresholds for editing next to the clear color, `None` hides them.
+    pub fn set_auto_quality_settings(&mut self, settings: Option<AutoQualitySettings>) {
+        if settings != self.auto_quality_settings {
+            self.auto_quality_settings = settings;
+            self.request_repaint();
+        }
+    }
+
+    /// Shows the latest GPU analysis numbers in the debug window, `None` hides them. Only
+    /// repaints when the numbers changed.
+    pub fn set_analysis_results(&mut self, analysis_results: Option<AnalysisResults>) {
+        if analysis_results != self.analysis_results {
+            self.request_repaint();
+        }
+        self.analysis_results = analysis_results;
+    }
+
+    /// Shows the allocator statistics in the debug window, `None` hides them. Only repaints
+    /// when the numbers changed.
+    pub fn set_memory_statistics(&mut self, memory_statistics: Option<MemoryStatistics>) {
+        if memory_statistics != self.memory_statistics {
+            self.request_repaint();
+        }
+        self.memory_statistics = memory_statistics;
+    }
+
+    /// Shows the scene editor panel for `outline`, `None` closes it. Only repaints when the
+    /// outline changed.
+    pub fn set_scene_outline(&mut self, outline: Option<SceneOutline>) {
+        if self.scene_editor.set_outline(outline) {
+            self.request_repaint();
+        }
+    }
+
+    /// Edits made in the scene editor since the last call, for the renderer to apply.
+    pub fn take_scene_edits(&mut self) -> Vec<SceneEdit> {
+        self.scene_editor.take_edits()
+    }
+
     pub fn draw(
         &mut self,
         command_buffer: CommandBuffer,
@@ -268,92 +649,172 @@ impl EguiRenderer {
         window: &Window,
         viewports: Vec<Viewport>,
         render_area: Rect2D,
+        in_flight_fences: &[Fence],
     ) -> Result<()> {
-        let full_output = self.integration.run(
-            |ctx| {
-                egui::Window::new(WidgetText::default().strong())
-                    .open(&mut true)
-                    .vscroll(true)
-                    .resizable(true)
-                    .show(ctx, |ui| {
-                        ui.label("Hello world!");
-                        if ui.button("Click me").clicked() {
-                            debug!("CLICKED");
-                        }
-                        ui.image(egui::include_image!(
-                            "/Users/zapzap/Projects/piplup/shaders/ferris.png"
-                        ));
-                        if ui.button("WHAT THE HEEEEEEELLL").clicked() {
-                            debug!("WHAT THE HEEEEELL");
-                        }
-                    });
-            },
-            window,
-        );
-        
-        self.mesh_buffers = self
-            .integration
-            .convert(self.extent, &full_output)
-            .into_iter()
-            .map(|mesh| {
-                MeshBuffers::<Vertex, u32>::new(
-                    mesh,
-                    |elements, flags, usage, mem_flags| {
-                        self.memory_allocator
-                            .create_buffer_with_mapped_memory(
-                                &elements,
-                                &[self.graphics_queue.clone()],
-                                flags,
-                                usage,
-                                mem_flags,
-                                &self.command_pool,
-                            )
-                            .unwrap()
-                            .unit
-                            .get_copied::<VkBuffer>()
-                    },
-                    |elements, flags, usage, mem_flags| {
-                        self.memory_allocator
-                            .create_buffer_with_mapped_memory(
-                                &elements,
-                                &[self.graphics_queue.clone()],
-                                flags,
-                                usage,
-                                mem_flags,
-                                &self.command_pool,
-                            )
-                            .unwrap()
-                            .unit
-                            .get_copied::<VkBuffer>()
-                    },
+        let target_points = self
+            .target_rect
+            .map(|rect| rect_to_points(rect, self.integration.pixels_per_point()));
+        self.integration.set_screen_rect(target_points);
+        self.destroy_retired_textures();
+        if self.notifications.expire() {
+            self.request_repaint();
+        }
+        if self.needs_repaint() {
+            let analysis_results = self.analysis_results.as_ref();
+            let memory_statistics = self.memory_statistics.as_ref();
+            let clear_color = &mut self.clear_color;
+            let auto_quality_settings = &mut self.auto_quality_settings;
+            let notifications = &self.notifications;
+            let screen_labels = &self.screen_labels;
+            let scene_editor = &mut self.scene_editor;
+            let pixels_per_point = self.integration.pixels_per_point();
+            let full_output = self.integration.run(
+                |ctx| {
+                    // panels go first so windows float above the space they leave
+                    scene_editor.ui(ctx);
+                    egui::Window::new(WidgetText::default().strong())
+                        .open(&mut true)
+                        .vscroll(true)
+                        .resizable(true)
+                        .show(ctx, |ui| {
+                            ui.label("Hello world!");
+                            if ui.button("Click me").clicked() {
+                                debug!("CLICKED");
+                            }
+                            ui.image(egui::include_image!(
+                                "/Users/zapzap/Projects/piplup/shaders/ferris.png"
+                            ));
+                            if ui.button("WHAT THE HEEEEEEELLL").clicked() {
+                                debug!("WHAT THE HEEEEELL");
+                            }
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Clear color");
+                                ui.color_edit_button_rgba_unmultiplied(clear_color);
+                            });
+                            if let Some(settings) = auto_quality_settings {
+                                settings.ui(ui);
+                            }
+                            if let Some(analysis_results) = analysis_results {
+                                ui.separator();
+                                analysis_results.ui(ui);
+                            }
+                            if let Some(memory_statistics) = memory_statistics {
+                                ui.separator();
+                                memory_statistics.ui(ui);
+                            }
+                        });
+                    paint_labels(ctx, screen_labels, pixels_per_point);
+                    notifications.ui(ctx);
+                },
+                window,
+            );
+
+            self.apply_textures_delta(full_output.textures_delta, in_flight_fences)?;
+            self.vertex_buffers.begin_rebuild();
+            self.index_buffers.begin_rebuild();
+            let (vertex_buffers, index_buffers) =
+                (&mut self.vertex_buffers, &mut self.index_buffers);
+            self.draw_commands = self.integration.convert(
+                self.extent,
+                full_output.shapes,
+                |vertices, indices| {
+                    let vertex_offset = vertex_buffers.write(vertices)?;
+                    let first_index = index_buffers.write(indices)?;
+                    Ok((vertex_offset as i32, first_index))
+                },
+            )?;
+            self.repaint_requested = false;
+            self.repaint_deadline = full_output
+                .viewport_output
+                .get(&ViewportId::ROOT)
+                .and_then(|viewport_output| {
+                    Instant::now().checked_add(viewport_output.repaint_delay)
+                });
+        }
+        let (viewports, render_area, push_constant) = match (self.target_rect, target_points) {
+            (Some(target_rect), Some(points)) => {
+                for draw_command in &mut self.draw_commands {
+                    let scissors = draw_command.scissors_mut();
+                    *scissors = clamp_rect(*scissors, target_rect);
+                }
+                (
+                    vec![Viewport::default()
+                        .x(target_rect.offset.x as f32)
+                        .y(target_rect.offset.y as f32)
+                        .width(target_rect.extent.width as f32)
+                        .height(target_rect.extent.height as f32)
+                        .min_depth(0.0)
+                        .max_depth(1.0)],
+                    target_rect,
+                    egui_rect_push_constant(
+                        points.min.x,
+                        points.min.y,
+                        points.width(),
+                        points.height(),
+                    ),
                 )
-                .unwrap()
-            })
-            .collect();
+            }
+            _ => (viewports, render_area, egui_push_constant(window)),
+        };
         self.record_command_buffer(
             command_buffer,
             image_index,
-            &self.mesh_buffers,
+            &self.draw_commands,
             &self.framebuffers,
             **self.render_pass,
             render_area,
             viewports,
-            window,
+            &push_constant,
         )?;
 
         Ok(())
     }
 
+    /// Viewport, push constants and geometry buffers every egui draw relies on, set again
+    /// after a paint callback may have changed them.
+    unsafe fn bind_egui_state(
+        &self,
+        command_buffer: CommandBuffer,
+        viewports: &[Viewport],
+        push_constant: &[u8],
+    ) {
+        unsafe {
+            self.device.cmd_set_viewport(command_buffer, 0, viewports);
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipelines[0].pipeline_layout,
+                ShaderStageFlags::VERTEX,
+                0,
+                push_constant,
+            );
+            // the vertex shader pulls the vertices through the address behind the matrix
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipelines[0].pipeline_layout,
+                ShaderStageFlags::VERTEX,
+                size_of::<Matrix4<f32>>() as u32,
+                &self.vertex_buffers.buffer().address.to_ne_bytes(),
+            );
+            self.device.cmd_bind_index_buffer(
+                command_buffer,
+                *self.index_buffers.buffer(),
+                0,
+                IndexType::UINT32,
+            );
+        }
+    }
+
     fn record_command_buffer(
         &self,
         command_buffer: CommandBuffer,
         image_index: &ImageIndex,
-        mesh_buffers: &[MeshBuffers<Vertex, u32>],
+        draw_commands: &[EguiCommand],
         framebuffers: &[VkFrameBuffer],
         render_pass: RenderPass,
         render_area: Rect2D,
         viewports: Vec<Viewport>,
-        window: &Window,
+        push_constant: &[u8],
     ) -> Result<()> {
         unsafe {
             self.device
@@ -365,9 +826,12 @@ impl EguiRenderer {
 
             let clear_value = vec![ClearValue {
                 color: ash::vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
+                    float32: self.clear_color,
                 },
             }];
+            self.device
+                .debug_utils
+                .begin_label(command_buffer, "egui", [1.0, 0.6, 0.2, 1.0]);
             self.device.cmd_begin_render_pass(
                 command_buffer,
                 &RenderPassBeginInfo::default()
@@ -377,61 +841,71 @@ impl EguiRenderer {
                     .render_pass(render_pass),
                 SubpassContents::INLINE,
             );
-            self.device.cmd_set_viewport(command_buffer, 0, &viewports);
-            self.device.cmd_push_constants(
-                command_buffer,
-                self.pipelines[0].pipeline_layout,
-                ShaderStageFlags::VERTEX,
-                0,
-                &egui_push_constant(window),
-            );
+            self.bind_egui_state(command_buffer, &viewports, push_constant);
 
-            for mesh_buffer in mesh_buffers {
-                let texture_information_data = self
-                    .texture_informations
-                    .get(&mesh_buffer.mesh.texture_id.unwrap());
-                if texture_information_data.iter().len() > 0 {
-                    match texture_information_data.unwrap().texture_id {
-                        TextureId::Managed(id) => {
-                            self.device.cmd_bind_pipeline(
-                                command_buffer,
-                                PipelineBindPoint::GRAPHICS,
-                                *self.pipelines[id as usize],
-                            );
-
-                            self.device.cmd_bind_descriptor_sets(
-                                command_buffer,
-                                PipelineBindPoint::GRAPHICS,
-                                self.pipelines[id as usize].pipeline_layout,
-                                0,
-                                &texture_information_data.unwrap().descriptor_set_details,
-                                &[],
-                            );
-                        }
-                        TextureId::User(_) => todo!(),
+            for draw_command in draw_commands {
+                let draw_command = match draw_command {
+                    EguiCommand::Draw(draw_command) => draw_command,
+                    EguiCommand::Callback(callback_command) => {
+                        let Some(callback) =
+                            self.paint_callbacks.get(&callback_command.callback_id)
+                        else {
+                            continue;
+                        };
+                        self.device.cmd_set_viewport(
+                            command_buffer,
+                            0,
+                            &[callback_command.viewport],
+                        );
+                        self.device.cmd_set_scissor(
+                            command_buffer,
+                            0,
+                            &[callback_command.scissors],
+                        );
+                        callback(&PaintCallbackInfo {
+                            device: &self.device,
+                            command_buffer,
+                            render_pass,
+                            viewport: callback_command.viewport,
+                            scissor: callback_command.scissors,
+                            pixels_per_point: self.integration.pixels_per_point(),
+                        });
+                        self.bind_egui_state(command_buffer, &viewports, push_constant);
+                        continue;
                     }
+                };
+                if let Some(texture_information_data) =
+                    self.texture_informations.get(&draw_command.texture_id)
+                {
+                    let pipeline = &self.pipelines[texture_information_data.kind.pipeline_index()];
+                    self.device.cmd_bind_pipeline(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        **pipeline,
+                    );
+
+                    self.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        pipeline.pipeline_layout,
+                        0,
+                        &texture_information_data.descriptor_set_details,
+                        &[],
+                    );
                 }
                 self.device
-                    .cmd_set_scissor(command_buffer, 0, &[mesh_buffer.mesh.scissors]);
-                let vertex_buffer = vec![mesh_buffer.vertex_buffer.buffer];
-                self.device
-                    .cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffer, &[0]);
-                self.device.cmd_bind_index_buffer(
-                    command_buffer,
-                    mesh_buffer.index_buffer.buffer,
-                    0,
-                    IndexType::UINT32,
-                );
+                    .cmd_set_scissor(command_buffer, 0, &[draw_command.scissors]);
                 self.device.cmd_draw_indexed(
                     command_buffer,
-                    mesh_buffer.mesh.indices.len() as u32,
+                    draw_command.index_count,
                     1,
-                    0,
-                    0,
+                    draw_command.first_index,
+                    draw_command.vertex_offset,
                     0,
                 );
             }
             self.device.cmd_end_render_pass(command_buffer);
+            self.device.debug_utils.end_label(command_buffer);
             self.device.end_command_buffer(command_buffer)?;
         }
         Ok(())
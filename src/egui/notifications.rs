@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+use egui::{Align2, Color32, Context};
+
+/// Toasts above this count push out the oldest one.
+const MAX_NOTIFICATIONS: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl NotificationLevel {
+    /// How long a toast of this level stays on screen.
+    pub fn timeout(self) -> Duration {
+        match self {
+            NotificationLevel::Info => Duration::from_secs(4),
+            NotificationLevel::Warn => Duration::from_secs(6),
+            NotificationLevel::Error => Duration::from_secs(8),
+        }
+    }
+
+    fn color(self) -> Color32 {
+        match self {
+            NotificationLevel::Info => Color32::LIGHT_GRAY,
+            NotificationLevel::Warn => Color32::YELLOW,
+            NotificationLevel::Error => Color32::LIGHT_RED,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub level: NotificationLevel,
+    pub message: String,
+    expires_at: Instant,
+}
+
+/// Toasts shown stacked in the bottom right corner until they time out.
+#[derive(Debug, Default)]
+pub struct Notifications {
+    queue: Vec<Notification>,
+}
+
+impl Notifications {
+    pub fn push(&mut self, level: NotificationLevel, message: impl Into<String>) {
+        if self.queue.len() == MAX_NOTIFICATIONS {
+            self.queue.remove(0);
+        }
+        self.queue.push(Notification {
+            level,
+            message: message.into(),
+            expires_at: Instant::now() + level.timeout(),
+        });
+    }
+
+    /// Drops timed out toasts, returns whether any were removed.
+    pub fn expire(&mut self) -> bool {
+        let count = self.queue.len();
+        let now = Instant::now();
+        self.queue.retain(|notification| notification.expires_at > now);
+        self.queue.len() != count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn ui(&self, ctx: &Context) {
+        let Some(next_expiry) = self.queue.iter().map(|notification| notification.expires_at).min()
+        else {
+            return;
+        };
+        ctx.request_repaint_after(next_expiry.saturating_duration_since(Instant::now()));
+        egui::Area::new(egui::Id::new("notifications"))
+            .anchor(Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+            .show(ctx, |ui| {
+                for notification in &self.queue {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.colored_label(notification.level.color(), &notification.message);
+                    });
+                }
+            });
+    }
+}
@@ -0,0 +1,305 @@
+use egui::{Context, DragValue, ScrollArea, SidePanel, Ui};
+use nalgebra::{Matrix3, Matrix4, Rotation3, Vector3};
+
+use crate::geom::scene::{InstanceId, SceneLights};
+
+/// Shown for overrides that are not in the material library.
+pub const CUSTOM_MATERIAL: &str = "custom";
+
+/// The parts of a scene the editor shows, rebuilt by the renderer every frame the editor is
+/// open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneOutline {
+    pub name: String,
+    /// Names of the renderables of the scene, they are listed but can't be edited.
+    pub nodes: Vec<String>,
+    /// Indexed by `InstanceId`.
+    pub instances: Vec<InstanceOutline>,
+    pub lights: SceneLights,
+    /// Names of the library materials offered as overrides.
+    pub materials: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceOutline {
+    pub transform: Matrix4<f32>,
+    pub nodes: Vec<NodeOutline>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeOutline {
+    pub name: String,
+    pub local_transform: Matrix4<f32>,
+    /// Library name of the material override, `None` while the mesh draws its own materials.
+    pub material_override: Option<String>,
+}
+
+/// A change made in the editor, applied to the scene by the renderer after the frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneEdit {
+    InstanceTransform(InstanceId, Matrix4<f32>),
+    NodeTransform(InstanceId, String, Matrix4<f32>),
+    /// `None` restores the materials of the mesh.
+    NodeMaterial(InstanceId, String, Option<String>),
+    Lights(SceneLights),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selection {
+    Instance(InstanceId),
+    Node(InstanceId, String),
+    Lights,
+}
+
+/// Translation, rotation in degrees and scale of a transform, as edited with drag values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransformFields {
+    pub translation: Vector3<f32>,
+    /// Roll, pitch and yaw in degrees.
+    pub rotation: Vector3<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl TransformFields {
+    /// Splits an affine transform without shear. Mirroring is folded into the rotation.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let linear: Matrix3<f32> = matrix.fixed_view::<3, 3>(0, 0).into_owned();
+        let scale = Vector3::new(
+            linear.column(0).norm(),
+            linear.column(1).norm(),
+            linear.column(2).norm(),
+        );
+        let mut unscaled = linear;
+        for (idx, scale) in scale.iter().enumerate() {
+            if *scale > f32::EPSILON {
+                unscaled.column_mut(idx).unscale_mut(*scale);
+            }
+        }
+        let (roll, pitch, yaw) = Rotation3::from_matrix(&unscaled).euler_angles();
+        Self {
+            translation: matrix.fixed_view::<3, 1>(0, 3).into_owned(),
+            rotation: Vector3::new(roll, pitch, yaw).map(f32::to_degrees),
+            scale,
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let rotation = self.rotation.map(f32::to_radians);
+        Matrix4::new_translation(&self.translation)
+            * Rotation3::from_euler_angles(rotation.x, rotation.y, rotation.z).to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+/// Side panel listing the prefab instances of a scene with their nodes, and an inspector for
+/// the transform and material of the selected one and the scene lights. It only reads the
+/// outline and queues edits, the renderer owns the scene.
+#[derive(Debug, Default)]
+pub struct SceneEditor {
+    outline: Option<SceneOutline>,
+    selection: Option<Selection>,
+    edits: Vec<SceneEdit>,
+}
+
+impl SceneEditor {
+    /// Replaces the shown outline, `None` closes the panel. Returns whether it changed.
+    pub fn set_outline(&mut self, outline: Option<SceneOutline>) -> bool {
+        if self.outline == outline {
+            return false;
+        }
+        self.outline = outline;
+        true
+    }
+
+    /// Edits made since the last call, oldest first.
+    pub fn take_edits(&mut self) -> Vec<SceneEdit> {
+        std::mem::take(&mut self.edits)
+    }
+
+    pub fn ui(&mut self, ctx: &Context) {
+        let Some(outline) = &self.outline else {
+            return;
+        };
+        let (selection, edits) = (&mut self.selection, &mut self.edits);
+        SidePanel::left("scene_editor")
+            .resizable(true)
+            .default_width(260.0)
+            .show(ctx, |ui| {
+                ui.heading(&outline.name);
+                ScrollArea::vertical().show(ui, |ui| {
+                    hierarchy_ui(ui, outline, selection);
+                    ui.separator();
+                    inspector_ui(ui, outline, selection, edits);
+                });
+            });
+    }
+}
+
+fn hierarchy_ui(ui: &mut Ui, outline: &SceneOutline, selection: &mut Option<Selection>) {
+    for name in &outline.nodes {
+        ui.weak(name);
+    }
+    if ui
+        .selectable_label(*selection == Some(Selection::Lights), "Lights")
+        .clicked()
+    {
+        *selection = Some(Selection::Lights);
+    }
+    for (idx, instance) in outline.instances.iter().enumerate() {
+        let id = InstanceId(idx);
+        let selected = *selection == Some(Selection::Instance(id));
+        if ui
+            .selectable_label(selected, format!("Instance {idx}"))
+            .clicked()
+        {
+            *selection = Some(Selection::Instance(id));
+        }
+        ui.indent(("instance", idx), |ui| {
+            for node in &instance.nodes {
+                let selected = matches!(
+                    selection,
+                    Some(Selection::Node(node_id, name)) if *node_id == id && *name == node.name
+                );
+                if ui.selectable_label(selected, &node.name).clicked() {
+                    *selection = Some(Selection::Node(id, node.name.clone()));
+                }
+            }
+        });
+    }
+}
+
+fn inspector_ui(
+    ui: &mut Ui,
+    outline: &SceneOutline,
+    selection: &Option<Selection>,
+    edits: &mut Vec<SceneEdit>,
+) {
+    match selection {
+        Some(Selection::Instance(id)) => {
+            let Some(instance) = outline.instances.get(id.0) else {
+                return;
+            };
+            ui.strong(format!("Instance {}", id.0));
+            if let Some(transform) = transform_ui(ui, &instance.transform) {
+                edits.push(SceneEdit::InstanceTransform(*id, transform));
+            }
+        }
+        Some(Selection::Node(id, name)) => {
+            let Some(node) = outline
+                .instances
+                .get(id.0)
+                .and_then(|instance| instance.nodes.iter().find(|node| node.name == *name))
+            else {
+                return;
+            };
+            ui.strong(&node.name);
+            if let Some(transform) = transform_ui(ui, &node.local_transform) {
+                edits.push(SceneEdit::NodeTransform(*id, name.clone(), transform));
+            }
+            let mut material = node.material_override.clone();
+            egui::ComboBox::from_label("Material")
+                .selected_text(material.as_deref().unwrap_or("mesh"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut material, None, "mesh");
+                    for name in &outline.materials {
+                        ui.selectable_value(&mut material, Some(name.clone()), name);
+                    }
+                });
+            if material != node.material_override {
+                edits.push(SceneEdit::NodeMaterial(*id, name.clone(), material));
+            }
+        }
+        Some(Selection::Lights) => {
+            if let Some(lights) = lights_ui(ui, &outline.lights) {
+                edits.push(SceneEdit::Lights(lights));
+            }
+        }
+        None => {
+            ui.weak("Select an instance, a node or the lights");
+        }
+    }
+}
+
+/// Returns the edited transform if any field changed.
+fn transform_ui(ui: &mut Ui, transform: &Matrix4<f32>) -> Option<Matrix4<f32>> {
+    let mut fields = TransformFields::from_matrix(transform);
+    let mut changed = vector_ui(ui, "Translation", &mut fields.translation, 0.05, "");
+    changed |= vector_ui(ui, "Rotation", &mut fields.rotation, 1.0, "°");
+    changed |= vector_ui(ui, "Scale", &mut fields.scale, 0.01, "");
+    changed.then(|| fields.matrix())
+}
+
+fn vector_ui(
+    ui: &mut Ui,
+    label: &str,
+    vector: &mut Vector3<f32>,
+    speed: f32,
+    suffix: &str,
+) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let mut changed = false;
+        for value in vector.iter_mut() {
+            changed |= ui
+                .add(DragValue::new(value).speed(speed).suffix(suffix))
+                .changed();
+        }
+        changed
+    })
+    .inner
+}
+
+/// Returns the edited lights if any field changed. The alpha of the sunlight color is its
+/// intensity, the ambient alpha is kept.
+fn lights_ui(ui: &mut Ui, lights: &SceneLights) -> Option<SceneLights> {
+    let mut edited = lights.clone();
+    let mut changed = false;
+    let mut ambient: [f32; 3] = edited.ambient_color.xyz().into();
+    let mut sunlight: [f32; 3] = edited.sunlight_color.xyz().into();
+    ui.horizontal(|ui| {
+        ui.label("Ambient");
+        changed |= ui.color_edit_button_rgb(&mut ambient).changed();
+    });
+    ui.horizontal(|ui| {
+        ui.label("Sunlight");
+        changed |= ui.color_edit_button_rgb(&mut sunlight).changed();
+        changed |= ui
+            .add(
+                DragValue::new(&mut edited.sunlight_color.w)
+                    .speed(0.05)
+                    .range(0.0..=f32::MAX),
+            )
+            .on_hover_text("Intensity")
+            .changed();
+    });
+    let mut direction = edited.sunlight_direction.xyz();
+    changed |= vector_ui(ui, "Direction", &mut direction, 0.01, "");
+    edited.ambient_color.fixed_rows_mut::<3>(0).copy_from_slice(&ambient);
+    edited.sunlight_color.fixed_rows_mut::<3>(0).copy_from_slice(&sunlight);
+    edited.sunlight_direction.fixed_rows_mut::<3>(0).copy_from(&direction);
+    changed.then_some(edited)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix4, Vector3};
+
+    use super::TransformFields;
+
+    #[test]
+    fn transform_fields_roundtrip() {
+        let fields = TransformFields {
+            translation: Vector3::new(1.0, -2.0, 3.0),
+            rotation: Vector3::new(10.0, 20.0, -30.0),
+            scale: Vector3::new(2.0, 0.5, 1.0),
+        };
+        let parsed = TransformFields::from_matrix(&fields.matrix());
+        assert!((parsed.translation - fields.translation).norm() < 1e-5);
+        assert!((parsed.rotation - fields.rotation).norm() < 1e-3);
+        assert!((parsed.scale - fields.scale).norm() < 1e-5);
+        assert_eq!(
+            TransformFields::from_matrix(&Matrix4::identity()).matrix(),
+            Matrix4::identity()
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+
+/// Translation, rotation and scale of a joint or node relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for LocalTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.0),
+        }
+    }
+}
+
+impl LocalTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint, `None` for the roots of the scene.
+    pub parent: Option<usize>,
+    /// Local transform of the joint while no animation is playing.
+    pub rest: LocalTransform,
+    /// Moves mesh space into the space of the joint in the bind pose.
+    pub inverse_bind: Matrix4<f32>,
+}
+
+/// The joints of a glTF skin and the nodes above them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Skeleton {
+    /// The joints of the skin, `JOINTS_0` of the vertices indexes into them, followed by the
+    /// ancestors of the joints that are not joints themselves, like the armature of a Blender
+    /// export, up to the roots of the scene.
+    pub joints: Vec<Joint>,
+    /// Number of joints of the skin at the front of `joints`.
+    pub skin_joints: usize,
+    /// Inverse of the global transform of the node drawing the mesh in the file, the skinned
+    /// vertices stay in the space of the mesh like the vertices of meshes without a skin.
+    pub inverse_mesh_global: Matrix4<f32>,
+}
+
+impl Skeleton {
+    pub fn rest_pose(&self) -> Vec<LocalTransform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Skinning matrices for `pose`, one local transform per entry of `joints` and one matrix
+    /// per joint of the skin. They move a vertex from the bind pose into the pose in the space
+    /// of the mesh node, as the skinned vertex shader expects.
+    pub fn joint_matrices(&self, pose: &[LocalTransform]) -> Vec<Matrix4<f32>> {
+        let mut globals: Vec<Option<Matrix4<f32>>> = vec![None; self.joints.len()];
+        (0..self.skin_joints)
+            .map(|idx| {
+                self.inverse_mesh_global
+                    * self.global_transform(idx, pose, &mut globals)
+                    * self.joints[idx].inverse_bind
+            })
+            .collect()
+    }
+
+    fn global_transform(
+        &self,
+        idx: usize,
+        pose: &[LocalTransform],
+        globals: &mut [Option<Matrix4<f32>>],
+    ) -> Matrix4<f32> {
+        if let Some(global) = globals[idx] {
+            return global;
+        }
+        let local = pose[idx].matrix();
+        let global = match self.joints[idx].parent {
+            Some(parent) => self.global_transform(parent, pose, globals) * local,
+            None => local,
+        };
+        globals[idx] = Some(global);
+        global
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    /// Spherical for rotations. Cubic spline channels are sampled like this as well, their
+    /// tangents are dropped when loading.
+    Linear,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelValues {
+    Translations(Vec<Vector3<f32>>),
+    Rotations(Vec<UnitQuaternion<f32>>),
+    Scales(Vec<Vector3<f32>>),
+}
+
+/// Keyframes of one property of one joint or node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Index of the joint of a `Skeleton`, or of the node in `NodeAnimation::targets`.
+    pub target: usize,
+    /// Seconds of the keyframes in increasing order, one per value.
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    fn apply(&self, time: f32, transform: &mut LocalTransform) {
+        let Some((from, to, t)) = keyframes(&self.times, time) else {
+            return;
+        };
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => t,
+        };
+        match &self.values {
+            ChannelValues::Translations(values) => {
+                transform.translation = values[from].lerp(&values[to], t)
+            }
+            ChannelValues::Rotations(values) => {
+                transform.rotation = values[from]
+                    .try_slerp(&values[to], t, f32::EPSILON)
+                    .unwrap_or(values[from])
+            }
+            ChannelValues::Scales(values) => transform.scale = values[from].lerp(&values[to], t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Time of the last keyframe of any channel.
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Local transforms of the targets at `time` seconds into the clip, targets without a
+    /// channel keep their `rest` transform.
+    pub fn sample(&self, rest: &[LocalTransform], time: f32) -> Vec<LocalTransform> {
+        let mut pose = rest.to_vec();
+        for channel in &self.channels {
+            if let Some(transform) = pose.get_mut(channel.target) {
+                channel.apply(time, transform);
+            }
+        }
+        pose
+    }
+}
+
+/// A skeleton and the clips animating it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Skin {
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl Skin {
+    pub fn clip_index(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+
+    /// Skinning matrices of the rest pose, or of `playback` at `now` on the animation clock.
+    pub fn joint_matrices(
+        &self,
+        playback: Option<&AnimationPlayback>,
+        now: f32,
+    ) -> Vec<Matrix4<f32>> {
+        let pose = match playback.and_then(|playback| {
+            let clip = self.clips.get(playback.clip)?;
+            let rest = self.skeleton.rest_pose();
+            Some(clip.sample(&rest, playback.clip_time(now, clip.duration)))
+        }) {
+            Some(pose) => pose,
+            None => self.skeleton.rest_pose(),
+        };
+        self.skeleton.joint_matrices(&pose)
+    }
+}
+
+/// Clips moving the mesh nodes of a glTF file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodeAnimation {
+    /// Names of the meshes of the animated nodes, the nodes of a prefab are keyed by them.
+    pub targets: Vec<String>,
+    /// Local transforms of the targets in the file, used for the properties a clip leaves out.
+    pub rest: Vec<LocalTransform>,
+    pub clips: Vec<AnimationClip>,
+}
+
+/// A clip playing on one mesh node, timed by the renderer's animation clock so drawing never
+/// has to advance it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationPlayback {
+    pub clip: usize,
+    /// Animation clock time the clip started at.
+    pub start: f32,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl AnimationPlayback {
+    pub fn clip_time(&self, now: f32, duration: f32) -> f32 {
+        let time = (now - self.start) * self.speed;
+        if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        }
+    }
+
+    /// Whether the pose still changes at `now`.
+    pub fn is_running(&self, now: f32, duration: f32) -> bool {
+        self.looping || (now - self.start) * self.speed < duration
+    }
+}
+
+/// The keyframes around `time` and how far it is from the first to the second. Times before
+/// the first or after the last keyframe hold it.
+fn keyframes(times: &[f32], time: f32) -> Option<(usize, usize, f32)> {
+    let last = times.len().checked_sub(1)?;
+    let next = times.partition_point(|&keyframe| keyframe <= time);
+    if next == 0 {
+        return Some((0, 0, 0.0));
+    }
+    if next > last {
+        return Some((last, last, 0.0));
+    }
+    let (start, end) = (times[next - 1], times[next]);
+    Some((next - 1, next, (time - start) / (end - start)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
+
+    use super::{
+        keyframes, AnimationClip, AnimationPlayback, Channel, ChannelValues, Interpolation, Joint,
+        LocalTransform, Skeleton,
+    };
+
+    fn joint(parent: Option<usize>, translation: Vector3<f32>) -> Joint {
+        Joint {
+            name: String::new(),
+            parent,
+            rest: LocalTransform {
+                translation,
+                ..Default::default()
+            },
+            inverse_bind: Matrix4::new_translation(&-translation),
+        }
+    }
+
+    #[test]
+    fn finds_the_keyframes_around_a_time() {
+        let times = [0.0, 1.0, 3.0];
+        assert_eq!(keyframes(&times, -1.0), Some((0, 0, 0.0)));
+        assert_eq!(keyframes(&times, 2.0), Some((1, 2, 0.5)));
+        assert_eq!(keyframes(&times, 5.0), Some((2, 2, 0.0)));
+        assert_eq!(keyframes(&[], 1.0), None);
+    }
+
+    #[test]
+    fn the_rest_pose_skins_to_identity() {
+        // the child's inverse bind undoes its rest transform in mesh space
+        let mut child = joint(Some(0), Vector3::y());
+        child.inverse_bind = Matrix4::new_translation(&Vector3::new(-1.0, -1.0, 0.0));
+        let skeleton = Skeleton {
+            joints: vec![joint(None, Vector3::x()), child],
+            skin_joints: 2,
+            inverse_mesh_global: Matrix4::identity(),
+        };
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert!((matrix - Matrix4::identity()).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn children_follow_their_animated_parent() {
+        let skeleton = Skeleton {
+            joints: vec![joint(None, Vector3::zeros()), joint(Some(0), Vector3::x())],
+            skin_joints: 2,
+            inverse_mesh_global: Matrix4::identity(),
+        };
+        let clip = AnimationClip {
+            name: "move".into(),
+            duration: 2.0,
+            channels: vec![Channel {
+                target: 0,
+                times: vec![0.0, 2.0],
+                values: ChannelValues::Translations(vec![
+                    Vector3::zeros(),
+                    Vector3::new(0.0, 4.0, 0.0),
+                ]),
+                interpolation: Interpolation::Linear,
+            }],
+        };
+        let matrices = skeleton.joint_matrices(&clip.sample(&skeleton.rest_pose(), 1.0));
+        // a vertex bound to the child moves with the root
+        let moved = matrices[1].transform_point(&Point3::new(1.0, 0.0, 0.0));
+        assert!((moved - Point3::new(1.0, 2.0, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn joints_follow_their_armature_in_mesh_space() {
+        // a joint under a scaled armature that is not a joint, the mesh is a sibling of the
+        // joint under the armature
+        let armature = LocalTransform {
+            scale: Vector3::repeat(2.0),
+            ..Default::default()
+        };
+        let mesh_global = armature.matrix();
+        let mut bone = joint(Some(1), Vector3::x());
+        bone.inverse_bind = Matrix4::new_translation(&-Vector3::x());
+        let skeleton = Skeleton {
+            joints: vec![
+                bone,
+                Joint {
+                    name: "Armature".into(),
+                    parent: None,
+                    rest: armature,
+                    inverse_bind: Matrix4::identity(),
+                },
+            ],
+            skin_joints: 1,
+            inverse_mesh_global: mesh_global.try_inverse().unwrap(),
+        };
+        let rest = skeleton.joint_matrices(&skeleton.rest_pose());
+        assert_eq!(rest.len(), 1);
+        assert!((rest[0] - Matrix4::identity()).norm() < 1e-6);
+        // turning the armature turns the vertices around the mesh node
+        let mut pose = skeleton.rest_pose();
+        pose[1].rotation = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2);
+        let turned = (mesh_global * skeleton.joint_matrices(&pose)[0])
+            .transform_point(&Point3::new(1.0, 0.0, 0.0));
+        assert!((turned - Point3::new(0.0, 2.0, 0.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn playback_loops_or_holds_the_end() {
+        let looping = AnimationPlayback {
+            clip: 0,
+            start: 1.0,
+            speed: 2.0,
+            looping: true,
+        };
+        assert_eq!(looping.clip_time(3.0, 3.0), 1.0);
+        assert!(looping.is_running(100.0, 3.0));
+        let once = AnimationPlayback {
+            looping: false,
+            ..looping
+        };
+        assert_eq!(once.clip_time(3.0, 3.0), 3.0);
+        assert!(!once.is_running(3.0, 3.0));
+    }
+}
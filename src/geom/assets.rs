@@ -1,17 +1,29 @@
-use std::{fmt::Display, ops::DerefMut, path::Path, sync::{Arc, Mutex}, usize};
+use std::{collections::HashMap, fs, iter, ops::{DerefMut, Range}, path::Path, sync::{Arc, Mutex}, usize};
 
 use anyhow::{anyhow, Result};
 use ash::vk::{Rect2D, Viewport};
-use log::debug;
-use nalgebra::{Vector2, Vector3, Vector4};
+use log::{debug, warn};
+use gltf::animation::util::ReadOutputs;
+use image::RgbaImage;
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector2, Vector3, Vector4};
 
 use crate::{components::{
-    allocation_types::VkBuffer, command_buffers::VkCommandPool, memory_allocator::MemoryAllocator,
+    buffer_arena::{BufferArena, BufferSlice}, command_buffers::{ImmediateSubmit, VkCommandPool},
     queue::VkQueue,
-}, misc::{material::MaterialInstance, render_object::Node}};
+    upload_context::UploadContext,
+}, misc::{material::MaterialInstance, material_library::{MaterialDefinition, MaterialPassDefinition}, render_object::{MeshNode, Node}}};
 
 use super::{
+    animation::{
+        AnimationClip, Channel, ChannelValues, Interpolation, Joint, LocalTransform, NodeAnimation,
+        Skeleton, Skin,
+    },
+    bounds::Aabb,
+    gltf_source::GltfSource,
+    lod::{self, select_lod, LodSettings},
     mesh::{self, MeshBuffers},
+    mesh_optimizer::{optimize_mesh, OptimizationStats},
+    obj,
     vertex_3d::Vertex3D,
     VertexAttributes,
 };
@@ -25,7 +37,17 @@ pub struct GLTFMaterial {
 pub struct GeoSurface {
     pub start_index: u32,
     pub count: usize,
-    pub material: Option<Arc<GLTFMaterial>>
+    pub material: Option<Arc<GLTFMaterial>>,
+    /// Coarser versions of the surface in the same index buffer, by increasing distance.
+    pub lods: Vec<SurfaceLod>,
+}
+
+/// Index range of a generated level of a `GeoSurface`, see `lod::generate_lods`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceLod {
+    pub min_distance: f32,
+    pub start_index: u32,
+    pub count: usize,
 }
 
 #[derive(Debug)]
@@ -33,10 +55,91 @@ pub struct MeshAsset<T: VertexAttributes> {
     pub name: String,
     pub surfaces: Vec<GeoSurface>,
     pub mesh_buffers: MeshBuffers<T, u32>,
+    /// Skeleton and clips of the first node using the mesh with a skin, its surfaces are drawn
+    /// with the skinned pipeline of their material.
+    pub skin: Option<Arc<Skin>>,
+    /// Bounds of the vertices in mesh space, meshes without them can't be picked. Skinned
+    /// meshes keep the bounds of their bind pose.
+    pub bounds: Option<Aabb>,
 }
 
 
+/// The meshes of a loaded glTF file, kept around as a prefab that scenes instantiate without
+/// uploading the buffers again.
+pub struct LoadedGLTF {
+    pub name: String,
+    pub nodes: HashMap<String, MeshNode<Vertex3D>>,
+    /// Clips moving the nodes, every instance plays them with an `AnimationPlayer` of its own.
+    pub animation: Option<Arc<NodeAnimation>>,
+}
+
+impl LoadedGLTF {
+    pub fn from_meshes(
+        name: impl Into<String>,
+        meshes: &[Arc<Mutex<MeshAsset<Vertex3D>>>],
+    ) -> Self {
+        let nodes = meshes
+            .iter()
+            .map(|mesh| {
+                let node = Arc::new(Node::new(
+                    std::rc::Weak::new(),
+                    vec![],
+                    Matrix4::identity(),
+                    Matrix4::identity(),
+                ));
+                (mesh.lock().unwrap().name.clone(), MeshNode::new(node, mesh.clone()))
+            })
+            .collect();
+        Self {
+            name: name.into(),
+            nodes,
+            animation: None,
+        }
+    }
+
+    /// Loads the animations of `file_path` moving the nodes of the meshes of this prefab.
+    /// Channels of nodes without a mesh of the prefab, like the joints of skins, are left out.
+    pub fn load_animations<P: AsRef<Path>>(mut self, file_path: P) -> Result<Self> {
+        let source = GltfSource::open(file_path)?;
+        let mut targets: Vec<String> = vec![];
+        let mut rest = vec![];
+        let mut target_index = HashMap::new();
+        for node in source.document.nodes() {
+            let Some(name) = node.mesh().and_then(|mesh| mesh.name().map(str::to_owned)) else {
+                continue;
+            };
+            if !self.nodes.contains_key(&name) || targets.contains(&name) {
+                continue;
+            }
+            target_index.insert(node.index(), targets.len());
+            targets.push(name);
+            rest.push(local_transform(&node));
+        }
+        let clips = load_clips(&source, |node| target_index.get(&node).copied())?;
+        debug!("{} has {} node animations", self.name, clips.len());
+        self.animation = (!clips.is_empty()).then(|| {
+            Arc::new(NodeAnimation {
+                targets,
+                rest,
+                clips,
+            })
+        });
+        Ok(self)
+    }
+}
+
 impl GeoSurface {
+    /// First index and index count of the level `select_lod` picks at `distance`, for the
+    /// surface drawn `scale` times its size, see `lod::max_axis_scale`.
+    pub fn lod_range(&self, distance: f32, scale: f32, settings: LodSettings) -> (u32, usize) {
+        let min_distances = self.lods.iter().map(|lod| lod.min_distance * scale);
+        let level = select_lod(min_distances, distance, settings);
+        match level.checked_sub(1).map(|idx| self.lods[idx]) {
+            Some(lod) => (lod.start_index, lod.count),
+            None => (self.start_index, self.count),
+        }
+    }
+
     pub fn material(&mut self, material: Option<Arc<GLTFMaterial>>) -> Self {
         self.material = material;
         self.clone()
@@ -49,138 +152,677 @@ impl<T: VertexAttributes> MeshAsset<T> {
             name,
             surfaces,
             mesh_buffers,
+            skin: None,
+            bounds: None,
         }
     }
 
-    pub fn load_gltf_meshes<P: AsRef<Path> + Display>(
+    pub fn with_skin(mut self, skin: Option<Arc<Skin>>) -> Self {
+        self.skin = skin;
+        self
+    }
+
+    pub fn with_bounds(mut self, bounds: Option<Aabb>) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    /// Reads the meshes of `file_path` and uploads them into `arena` with a single submission.
+    pub fn load_gltf_meshes<P: AsRef<Path>>(
         file_path: P,
         scissors: Rect2D,
         viewport: Viewport,
-        memory_allocator: Arc<MemoryAllocator>,
         queues: &[Arc<VkQueue>],
         command_pool: VkCommandPool,
+        arena: &BufferArena,
     ) -> Result<Vec<Arc<Mutex<MeshAsset<Vertex3D>>>>> {
-        let mut mesh_assets: Vec<Arc<Mutex<MeshAsset<Vertex3D>>>> = vec![];
-        let mut vertices: Vec<Vertex3D> = vec![];
-        let mut indices: Vec<usize> = vec![];
-        let mut surfaces: Vec<GeoSurface> = vec![];
-        let mut meshes: Vec<mesh::Mesh<Vertex3D, u32>> = vec![];
-        let gltf = gltf::Gltf::open(&file_path)?;
-        let gltf_meshes = gltf.meshes();
-        let blob = &gltf.blob;
-        for mesh in gltf_meshes {
-            meshes.clear();
-            indices.clear();
-            vertices.clear();
-            surfaces.clear();
-            let primitives = mesh.primitives();
-            for primitive in primitives {
-                let reader = primitive.reader(|_buffer| blob.as_deref());
-                let surface = GeoSurface {
+        let meshes = read_gltf_meshes(file_path, scissors, viewport)?;
+        // every buffer of the file is uploaded with a single submission
+        let submit = ImmediateSubmit::begin(&command_pool, queues[0].clone())?;
+        let mesh_assets = meshes
+            .into_iter()
+            .map(|data| {
+                let asset = MeshAsset::<Vertex3D>::upload(data, &submit, arena)?;
+                Ok(Arc::new(Mutex::new(asset)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        submit.submit()?;
+        Ok(mesh_assets)
+    }
+
+    /// Records the upload of `data` into `arena`, the buffers hold it once `submit` was
+    /// submitted.
+    pub fn upload(
+        data: MeshData,
+        submit: &ImmediateSubmit,
+        arena: &BufferArena,
+    ) -> Result<MeshAsset<Vertex3D>> {
+        let mesh_buffer = MeshBuffers::new(
+            data.mesh,
+            // the arena buffers cover every usage of mesh buffers
+            |buffer_elements, _, _, _| arena.upload(submit, &buffer_elements).unwrap(),
+            |buffer_elements, _, _, _| arena.upload(submit, &buffer_elements).unwrap(),
+        )?;
+        Ok(MeshAsset::new(data.name, data.surfaces, mesh_buffer)
+            .with_skin(data.skin)
+            .with_bounds(data.bounds))
+    }
+
+    /// Like `upload`, but streams the buffers through the transfer queue. They can be drawn by
+    /// frames that wait on the next `UploadContext::submit`.
+    pub fn upload_streamed(
+        data: MeshData,
+        upload_context: &mut UploadContext,
+        arena: &BufferArena,
+    ) -> Result<MeshAsset<Vertex3D>> {
+        let vertex_buffer = upload_context.upload_to_arena(arena, &data.mesh.vertices)?;
+        let index_buffer = upload_context.upload_to_arena(arena, &data.mesh.indices)?;
+        let mesh_buffer =
+            MeshBuffers::new(data.mesh, |_, _, _, _| vertex_buffer, |_, _, _, _| index_buffer)?;
+        Ok(MeshAsset::new(data.name, data.surfaces, mesh_buffer)
+            .with_skin(data.skin)
+            .with_bounds(data.bounds))
+    }
+
+    /// Slices of the vertex and index buffers, to give them back to their arena once no frame
+    /// uses them anymore.
+    pub fn buffer_slices(&self) -> [BufferSlice; 2] {
+        [
+            self.mesh_buffers.vertex_buffer.clone(),
+            self.mesh_buffers.index_buffer.clone(),
+        ]
+    }
+
+    /// Takes over the buffers, surfaces, skin and bounds of `reloaded`, surfaces keep the
+    /// material of the surface they replace. Returns the slices of the previous buffers.
+    pub fn replace(&mut self, mut reloaded: MeshAsset<T>) -> [BufferSlice; 2] {
+        for (surface, previous) in reloaded.surfaces.iter_mut().zip(&self.surfaces) {
+            surface.material = previous.material.clone();
+        }
+        let previous = self.buffer_slices();
+        self.surfaces = reloaded.surfaces;
+        self.mesh_buffers = reloaded.mesh_buffers;
+        self.skin = reloaded.skin;
+        self.bounds = reloaded.bounds;
+        previous
+    }
+}
+
+/// A primitive of a glTF mesh as it is read from the file, before its buffers are uploaded.
+/// Reading needs no GPU access, so hot reloads can do it on a job thread.
+pub struct MeshData {
+    pub name: String,
+    pub surfaces: Vec<GeoSurface>,
+    pub mesh: mesh::Mesh<Vertex3D, u32>,
+    pub skin: Option<Arc<Skin>>,
+    pub bounds: Option<Aabb>,
+    /// Name of the material of every surface in the file, to look them up in the material
+    /// library.
+    pub surface_materials: Vec<Option<String>>,
+}
+
+impl MeshData {
+    /// Deduplicates the vertices and reorders the indices of every surface and level of detail
+    /// for the vertex cache and less overdraw, see `mesh_optimizer::optimize_mesh`.
+    pub fn optimize(&mut self) -> OptimizationStats {
+        let ranges: Vec<Range<usize>> = self
+            .surfaces
+            .iter()
+            .flat_map(|surface| {
+                iter::once((surface.start_index, surface.count))
+                    .chain(surface.lods.iter().map(|lod| (lod.start_index, lod.count)))
+            })
+            .map(|(start, count)| start as usize..start as usize + count)
+            .collect();
+        let stats = optimize_mesh(&mut self.mesh.vertices, &mut self.mesh.indices, &ranges);
+        debug!(
+            "{}: {} of {} vertices left, ACMR {:.3} -> {:.3}",
+            self.name,
+            stats.vertices_after,
+            stats.vertices_before,
+            stats.acmr_before,
+            stats.acmr_after
+        );
+        stats
+    }
+}
+
+/// Meshes of an OBJ file together with the materials of the MTL files it references.
+pub struct ObjMeshes {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialDefinition>,
+}
+
+/// Reads every object of the OBJ file at `file_path` as a mesh with a surface per material,
+/// like `read_gltf_meshes`. A missing MTL file is logged, its surfaces keep the material name
+/// without a definition for it.
+pub fn read_obj_meshes<P: AsRef<Path>>(
+    file_path: P,
+    scissors: Rect2D,
+    viewport: Viewport,
+) -> Result<ObjMeshes> {
+    let file_path = file_path.as_ref();
+    let base_directory = file_path.parent().unwrap_or(Path::new(""));
+    let model = obj::parse_obj(&fs::read_to_string(file_path)?)?;
+    let mut materials = vec![];
+    for library in &model.material_libraries {
+        let path = base_directory.join(library);
+        match fs::read_to_string(&path) {
+            Ok(source) => materials.extend(obj::parse_mtl(
+                &source,
+                path.parent().unwrap_or(base_directory),
+            )),
+            Err(err) => warn!("Material library {}: {err}", path.display()),
+        }
+    }
+    let meshes = model
+        .objects
+        .into_iter()
+        .map(|object| {
+            let mut vertices = object.vertices;
+            let mut indices: Vec<u32> = vec![];
+            let mut surfaces = vec![];
+            for surface in &object.surfaces {
+                surfaces.push(GeoSurface {
                     start_index: indices.len() as u32,
-                    count: primitive.indices().unwrap().count(),
-                    material: None
-                };
-                surfaces.push(surface);
-                //let initial_vtx = vertices.len();
-                let positions = reader
-                    .read_positions()
-                    .ok_or(anyhow!("There are no positions in this mesh"))?
-                    .collect::<Vec<_>>();
-                let normals = reader
-                    .read_normals()
-                    .ok_or(anyhow!("There are no normals in this mesh"))?
-                    .collect::<Vec<_>>();
-                let indices = reader
-                    .read_indices()
-                    .ok_or(anyhow!("There are no indices in this mesh"))?
-                    .into_u32()
-                    .collect::<Vec<_>>();
-
-                let uvs = reader
-                    .read_tex_coords(0)
-                    .ok_or(anyhow!("There are uv"))?
-                    .into_f32()
-                    .collect::<Vec<_>>();
-                let colors = match reader.read_colors(0) {
-                    Some(colors) => colors.into_rgba_f32().collect::<Vec<_>>(),
-                    None => normals
-                        .iter()
-                        .map(|normal| [normal[0], normal[1], normal[2], 1.0])
-                        .collect::<Vec<_>>(),
-                };
-                let override_color = false;
-                let white_color = [1.0, 1.0, 1.0, 1.0];
-
-                for (idx, pos_arr) in positions.into_iter().enumerate() {
-                    let pos = Vector3::new(pos_arr[0], pos_arr[1], pos_arr[2]);
-                    let normal_arr = normals[idx];
-                    let normal = Vector3::new(normal_arr[0], normal_arr[1], normal_arr[2]);
-                    let uv_arr = uvs[idx];
-                    let color_arr = if override_color { colors[idx] } else { white_color };
-                    let color =
-                        Vector4::<f32>::new(color_arr[0], color_arr[1], color_arr[2], color_arr[3]);
-                    vertices.push(Vertex3D::new(
-                        pos,
-                        Vector2::new(uv_arr[0] as f32, uv_arr[1] as f32),
-                        normal,
-                        color,
-                    ));
+                    count: surface.indices.len(),
+                    material: None,
+                    lods: vec![],
+                });
+                indices.extend(&surface.indices);
+            }
+            Vertex3D::generate_tangents(&mut vertices, &indices);
+            let positions: Vec<Vector3<f32>> = vertices.iter().map(|vertex| vertex.pos).collect();
+            // the coarser levels of every surface go behind the full detail indices
+            for (surface, obj_surface) in surfaces.iter_mut().zip(&object.surfaces) {
+                for lod in lod::generate_lods(&positions, &obj_surface.indices) {
+                    surface.lods.push(SurfaceLod {
+                        min_distance: lod.min_distance,
+                        start_index: indices.len() as u32,
+                        count: lod.indices.len(),
+                    });
+                    indices.extend(lod.indices);
                 }
+            }
+            let mut data = MeshData {
+                name: object.name,
+                surfaces,
+                bounds: Aabb::from_points(positions),
+                skin: None,
+                surface_materials: object
+                    .surfaces
+                    .into_iter()
+                    .map(|surface| surface.material)
+                    .collect(),
+                mesh: mesh::Mesh::<Vertex3D, u32> {
+                    vertices,
+                    indices,
+                    texture_id: None,
+                    scissors,
+                    viewport,
+                },
+            };
+            data.optimize();
+            data
+        })
+        .collect();
+    Ok(ObjMeshes { meshes, materials })
+}
 
-                
-                let mesh_buffer = MeshBuffers::new(
-                    mesh::Mesh::<Vertex3D, u32> {
-                        vertices: vertices.clone(),
-                        indices,
-                        texture_id: None,
-                        scissors,
-                        viewport,
-                    },
-                    |buffer_elements, buffer_usage, memory_usage, memory_property_flags| {
-                        memory_allocator
-                            .create_buffer_with_mapped_memory(
-                                &buffer_elements,
-                                queues,
-                                buffer_usage,
-                                memory_usage,
-                                memory_property_flags,
-                                &command_pool,
-                            )
-                            .unwrap()
-                            .unit
-                            .get_copied::<VkBuffer>()
-                    },
-                    |buffer_elements, buffer_usage, memory_usage, memory_property_flags| {
-                        memory_allocator
-                            .create_buffer_with_mapped_memory(
-                                &buffer_elements,
-                                queues,
-                                buffer_usage,
-                                memory_usage,
-                                memory_property_flags,
-                                &command_pool,
-                            )
-                            .unwrap()
-                            .unit
-                            .get_copied::<VkBuffer>()
-                    },
-                )?;
-                
-                mesh_assets.push(Arc::new(Mutex::new(MeshAsset::new(
-                    mesh.name().map(|s| s.to_owned()).unwrap(),
-                    surfaces.clone(),
-                    mesh_buffer,
-                ))));
+/// Meshes of a glTF file together with its materials.
+pub struct GltfMeshes {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialDefinition>,
+}
+
+/// Reads the meshes of `file_path` like `read_gltf_meshes` and its materials like
+/// `read_gltf_materials`, from a single read of the file.
+pub fn read_gltf_file<P: AsRef<Path>>(
+    file_path: P,
+    scissors: Rect2D,
+    viewport: Viewport,
+) -> Result<GltfMeshes> {
+    let source = GltfSource::open(&file_path)?;
+    Ok(GltfMeshes {
+        meshes: read_source_meshes(&source, file_path.as_ref(), scissors, viewport)?,
+        materials: read_gltf_materials(&source, file_path.as_ref())?,
+    })
+}
+
+/// Reads every primitive of the meshes of `file_path` with its generated levels of detail.
+pub fn read_gltf_meshes<P: AsRef<Path>>(
+    file_path: P,
+    scissors: Rect2D,
+    viewport: Viewport,
+) -> Result<Vec<MeshData>> {
+    let source = GltfSource::open(&file_path)?;
+    read_source_meshes(&source, file_path.as_ref(), scissors, viewport)
+}
+
+fn read_source_meshes(
+    source: &GltfSource,
+    file_path: &Path,
+    scissors: Rect2D,
+    viewport: Viewport,
+) -> Result<Vec<MeshData>> {
+    let mut mesh_data: Vec<MeshData> = vec![];
+    let mut vertices: Vec<Vertex3D> = vec![];
+    let mut indices: Vec<usize> = vec![];
+    let mut surfaces: Vec<GeoSurface> = vec![];
+    let mut surface_materials: Vec<Option<String>> = vec![];
+    let gltf_meshes = source.document.meshes();
+    let skins = load_skins(source)?;
+    for mesh in gltf_meshes {
+        indices.clear();
+        vertices.clear();
+        surfaces.clear();
+        surface_materials.clear();
+        let primitives = mesh.primitives();
+        for primitive in primitives {
+            let reader = primitive.reader(|buffer| source.buffer(buffer));
+            let surface = GeoSurface {
+                start_index: indices.len() as u32,
+                count: primitive.indices().unwrap().count(),
+                material: None,
+                lods: vec![],
+            };
+            surfaces.push(surface);
+            surface_materials.push(gltf_material_name(file_path, &primitive.material()));
+            //let initial_vtx = vertices.len();
+            let positions = reader
+                .read_positions()
+                .ok_or(anyhow!("There are no positions in this mesh"))?
+                .collect::<Vec<_>>();
+            let normals = reader
+                .read_normals()
+                .ok_or(anyhow!("There are no normals in this mesh"))?
+                .collect::<Vec<_>>();
+            let mut indices = reader
+                .read_indices()
+                .ok_or(anyhow!("There are no indices in this mesh"))?
+                .into_u32()
+                .collect::<Vec<_>>();
+
+            let uvs = reader
+                .read_tex_coords(0)
+                .ok_or(anyhow!("There are uv"))?
+                .into_f32()
+                .collect::<Vec<_>>();
+            let tangents = reader.read_tangents().map(|tangents| tangents.collect::<Vec<_>>());
+            let joints = reader
+                .read_joints(0)
+                .map(|joints| joints.into_u16().collect::<Vec<_>>());
+            let weights = reader
+                .read_weights(0)
+                .map(|weights| weights.into_f32().collect::<Vec<_>>());
+            let colors = match reader.read_colors(0) {
+                Some(colors) => colors.into_rgba_f32().collect::<Vec<_>>(),
+                None => normals
+                    .iter()
+                    .map(|normal| [normal[0], normal[1], normal[2], 1.0])
+                    .collect::<Vec<_>>(),
+            };
+            let override_color = false;
+            let white_color = [1.0, 1.0, 1.0, 1.0];
+
+            for (idx, pos_arr) in positions.into_iter().enumerate() {
+                let pos = Vector3::new(pos_arr[0], pos_arr[1], pos_arr[2]);
+                let normal_arr = normals[idx];
+                let normal = Vector3::new(normal_arr[0], normal_arr[1], normal_arr[2]);
+                let uv_arr = uvs[idx];
+                let color_arr = if override_color { colors[idx] } else { white_color };
+                let color =
+                    Vector4::<f32>::new(color_arr[0], color_arr[1], color_arr[2], color_arr[3]);
+                let vertex = Vertex3D::new(
+                    pos,
+                    Vector2::new(uv_arr[0] as f32, uv_arr[1] as f32),
+                    normal,
+                    color,
+                );
+                let vertex = match &tangents {
+                    Some(tangents) => vertex.tangent(Vector4::from(tangents[idx])),
+                    None => vertex,
+                };
+                vertices.push(match (&joints, &weights) {
+                    (Some(joints), Some(weights)) => vertex.skin(
+                        Vector4::from(joints[idx].map(u32::from)),
+                        Vector4::from(weights[idx]),
+                    ),
+                    _ => vertex,
+                });
+            }
+            if tangents.is_none() {
+                Vertex3D::generate_tangents(&mut vertices, &indices);
+            }
+            // the coarser levels index the same vertices, behind the full detail indices
+            let vertex_positions: Vec<Vector3<f32>> =
+                vertices.iter().map(|vertex| vertex.pos).collect();
+            let bounds = Aabb::from_points(vertex_positions.iter().copied());
+            let surface = surfaces.last_mut().unwrap();
+            for lod in lod::generate_lods(&vertex_positions, &indices) {
+                surface.lods.push(SurfaceLod {
+                    min_distance: lod.min_distance,
+                    start_index: surface.start_index + indices.len() as u32,
+                    count: lod.indices.len(),
+                });
+                indices.extend(lod.indices);
             }
+
+            let mut data = MeshData {
+                name: mesh.name().map(|s| s.to_owned()).unwrap(),
+                surfaces: surfaces.clone(),
+                mesh: mesh::Mesh::<Vertex3D, u32> {
+                    vertices: vertices.clone(),
+                    indices,
+                    texture_id: None,
+                    scissors,
+                    viewport,
+                },
+                skin: skins.get(&mesh.index()).cloned(),
+                bounds,
+                surface_materials: surface_materials.clone(),
+            };
+            data.optimize();
+            mesh_data.push(data);
         }
+    }
+    Ok(mesh_data)
+}
 
-        Ok(mesh_assets)
+/// The materials of a glTF file as definitions for the material library, with the images of
+/// their textures decoded. Alpha blended materials are transparent, masked ones opaque.
+pub fn read_gltf_materials(
+    source: &GltfSource,
+    file_path: &Path,
+) -> Result<Vec<MaterialDefinition>> {
+    // materials often share images, each one is decoded once
+    let mut images: HashMap<usize, Arc<RgbaImage>> = HashMap::new();
+    let mut texture_image = |texture: gltf::Texture| -> Result<Arc<RgbaImage>> {
+        let image = texture.source();
+        if let Some(decoded) = images.get(&image.index()) {
+            return Ok(decoded.clone());
+        }
+        let decoded = Arc::new(source.decode_image(&image)?);
+        images.insert(image.index(), decoded.clone());
+        Ok(decoded)
+    };
+    let mut materials = vec![];
+    for material in source.document.materials() {
+        let Some(name) = gltf_material_name(file_path, &material) else {
+            continue;
+        };
+        let pbr = material.pbr_metallic_roughness();
+        let mut definition = MaterialDefinition::new(name);
+        if material.alpha_mode() == gltf::material::AlphaMode::Blend {
+            definition.pass = MaterialPassDefinition::Transparent;
+        }
+        definition.color_factors = pbr.base_color_factor();
+        definition.metal_rough_factors = [pbr.metallic_factor(), pbr.roughness_factor(), 0.0, 0.0];
+        let images = &mut definition.images;
+        images.color = pbr
+            .base_color_texture()
+            .map(|info| texture_image(info.texture()))
+            .transpose()?;
+        images.metal_rough = pbr
+            .metallic_roughness_texture()
+            .map(|info| texture_image(info.texture()))
+            .transpose()?;
+        images.normal = material
+            .normal_texture()
+            .map(|normal| texture_image(normal.texture()))
+            .transpose()?;
+        images.emissive = material
+            .emissive_texture()
+            .map(|info| texture_image(info.texture()))
+            .transpose()?;
+        definition.emissive_factor = material.emissive_factor();
+        if let Some(occlusion) = material.occlusion_texture() {
+            definition.images.occlusion = Some(texture_image(occlusion.texture())?);
+            definition.occlusion_strength = occlusion.strength();
+        }
+        materials.push(definition);
+    }
+    Ok(materials)
+}
+
+/// Name the material of a glTF file is registered under, unnamed ones are named after the file
+/// and their index. `None` for the default material of primitives without one.
+fn gltf_material_name(file_path: &Path, material: &gltf::Material) -> Option<String> {
+    let index = material.index()?;
+    Some(material.name().map_or_else(
+        || format!("{}#{index}", file_path.file_name().unwrap_or_default().to_string_lossy()),
+        str::to_owned,
+    ))
+}
+
+/// Skins of the file by the index of the mesh they deform, with the animations moving their
+/// joints or the nodes above them. A mesh used by several skinned nodes gets the skin of the
+/// first.
+fn load_skins(source: &GltfSource) -> Result<HashMap<usize, Arc<Skin>>> {
+    let node_parents: HashMap<usize, usize> = source
+        .document
+        .nodes()
+        .flat_map(|node| node.children().map(move |child| (child.index(), node.index())))
+        .collect();
+    let nodes: Vec<gltf::Node> = source.document.nodes().collect();
+    let mut skins: HashMap<usize, Arc<Skin>> = HashMap::new();
+    for node in source.document.nodes() {
+        let (Some(mesh), Some(skin)) = (node.mesh(), node.skin()) else {
+            continue;
+        };
+        if skins.contains_key(&mesh.index()) {
+            continue;
+        }
+        // the joints of the skin come first, the ancestors of the root up to the scene follow
+        // so a transformed or animated armature node moves the skeleton as well
+        let mut joint_nodes: Vec<gltf::Node> = skin.joints().collect();
+        let skin_joints = joint_nodes.len();
+        let mut joint_index: HashMap<usize, usize> = joint_nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, joint)| (joint.index(), idx))
+            .collect();
+        for idx in 0..skin_joints {
+            let mut current = joint_nodes[idx].index();
+            while let Some(&parent) = node_parents.get(&current) {
+                if joint_index.contains_key(&parent) {
+                    break;
+                }
+                joint_index.insert(parent, joint_nodes.len());
+                joint_nodes.push(nodes[parent].clone());
+                current = parent;
+            }
+        }
+        let parents: Vec<Option<usize>> = joint_nodes
+            .iter()
+            .map(|joint| {
+                let parent = node_parents.get(&joint.index())?;
+                joint_index.get(parent).copied()
+            })
+            .collect();
+        let mut mesh_global = local_transform(&node).matrix();
+        let mut current = node.index();
+        while let Some(&parent) = node_parents.get(&current) {
+            mesh_global = local_transform(&nodes[parent]).matrix() * mesh_global;
+            current = parent;
+        }
+        let inverse_binds = skin
+            .reader(|buffer| source.buffer(buffer))
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(Matrix4::from).collect::<Vec<_>>());
+        let joints = joint_nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, joint)| Joint {
+                name: joint.name().unwrap_or_default().to_owned(),
+                parent: parents[idx],
+                rest: local_transform(joint),
+                inverse_bind: inverse_binds
+                    .as_ref()
+                    .filter(|_| idx < skin_joints)
+                    .and_then(|matrices| matrices.get(idx).copied())
+                    .unwrap_or_else(Matrix4::identity),
+            })
+            .collect();
+        let clips = load_clips(source, |node| joint_index.get(&node).copied())?;
+        debug!(
+            "{} skins {} with {} joints under {} more nodes and {} clips",
+            node.name().unwrap_or_default(),
+            mesh.name().unwrap_or_default(),
+            skin_joints,
+            joint_nodes.len() - skin_joints,
+            clips.len()
+        );
+        skins.insert(
+            mesh.index(),
+            Arc::new(Skin {
+                skeleton: Skeleton {
+                    joints,
+                    skin_joints,
+                    inverse_mesh_global: mesh_global
+                        .try_inverse()
+                        .unwrap_or_else(Matrix4::identity),
+                },
+                clips,
+            }),
+        );
+    }
+    Ok(skins)
+}
+
+/// Clips of the animations of the file moving the nodes `target` maps to a channel target,
+/// animations moving none of them are left out.
+fn load_clips(
+    source: &GltfSource,
+    target: impl Fn(usize) -> Option<usize>,
+) -> Result<Vec<AnimationClip>> {
+    let mut clips = vec![];
+    for animation in source.document.animations() {
+        let mut channels = vec![];
+        for channel in animation.channels() {
+            let Some(target) = target(channel.target().node().index()) else {
+                continue;
+            };
+            let reader = channel.reader(|buffer| source.buffer(buffer));
+            let times: Vec<f32> = reader
+                .read_inputs()
+                .ok_or(anyhow!("There are no keyframe times in this animation"))?
+                .collect();
+            // cubic spline outputs hold an in tangent, the value and an out tangent per
+            // keyframe, only the values are kept
+            let (interpolation, stride, offset) = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => (Interpolation::Step, 1, 0),
+                gltf::animation::Interpolation::Linear => (Interpolation::Linear, 1, 0),
+                gltf::animation::Interpolation::CubicSpline => (Interpolation::Linear, 3, 1),
+            };
+            let values = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(translations)) => ChannelValues::Translations(
+                    translations.skip(offset).step_by(stride).map(Vector3::from).collect(),
+                ),
+                Some(ReadOutputs::Rotations(rotations)) => ChannelValues::Rotations(
+                    rotations
+                        .into_f32()
+                        .skip(offset)
+                        .step_by(stride)
+                        .map(|rotation| {
+                            UnitQuaternion::new_normalize(Quaternion::from(Vector4::from(rotation)))
+                        })
+                        .collect(),
+                ),
+                Some(ReadOutputs::Scales(scales)) => ChannelValues::Scales(
+                    scales.skip(offset).step_by(stride).map(Vector3::from).collect(),
+                ),
+                // morph targets are not supported
+                Some(ReadOutputs::MorphTargetWeights(_)) | None => continue,
+            };
+            channels.push(Channel {
+                target,
+                times,
+                values,
+                interpolation,
+            });
+        }
+        if channels.is_empty() {
+            continue;
+        }
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        clips.push(AnimationClip {
+            name: animation
+                .name()
+                .map_or_else(|| format!("animation {}", animation.index()), str::to_owned),
+            duration,
+            channels,
+        });
+    }
+    Ok(clips)
+}
+
+fn local_transform(node: &gltf::Node) -> LocalTransform {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    LocalTransform {
+        translation: Vector3::from(translation),
+        rotation: UnitQuaternion::new_normalize(Quaternion::from(Vector4::from(rotation))),
+        scale: Vector3::from(scale),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
+    use image::{Rgba, RgbaImage};
+
+    use crate::{geom::gltf_source::GltfSource, misc::material_library::MaterialPassDefinition};
+
+    use super::read_gltf_materials;
+
+    #[test]
+    fn reads_the_textures_of_gltf_materials() {
+        let directory =
+            std::env::temp_dir().join(format!("piplup-gltf-materials-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        RgbaImage::from_pixel(1, 1, Rgba([128, 128, 255, 255]))
+            .save(directory.join("normal.png"))
+            .unwrap();
+        let path = directory.join("materials.gltf");
+        fs::write(
+            &path,
+            r#"{
+                "asset": {"version": "2.0"},
+                "images": [{"uri": "normal.png"}],
+                "textures": [{"source": 0}],
+                "materials": [
+                    {
+                        "name": "bumpy",
+                        "normalTexture": {"index": 0},
+                        "occlusionTexture": {"index": 0, "strength": 0.5},
+                        "emissiveFactor": [1.0, 0.5, 0.0],
+                        "pbrMetallicRoughness": {"metallicFactor": 0.25}
+                    },
+                    {"alphaMode": "BLEND"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let materials = read_gltf_materials(&GltfSource::open(&path).unwrap(), &path);
+        fs::remove_dir_all(&directory).unwrap();
+        let materials = materials.unwrap();
+
+        assert_eq!(materials[0].name, "bumpy");
+        assert_eq!(materials[0].metal_rough_factors[..2], [0.25, 1.0]);
+        let normal = materials[0].images.normal.as_ref().unwrap();
+        assert_eq!(normal.get_pixel(0, 0).0, [128, 128, 255, 255]);
+        assert!(materials[0].images.color.is_none());
+        assert!(materials[0].images.emissive.is_none());
+        assert!(materials[0].images.occlusion.is_some());
+        assert_eq!(materials[0].occlusion_strength, 0.5);
+        assert_eq!(materials[0].emissive_factor, [1.0, 0.5, 0.0]);
+        assert_eq!(materials[1].name, "materials.gltf#1");
+        assert_eq!(materials[1].pass, MaterialPassDefinition::Transparent);
+    }
 
     #[test]
     fn load_gltf_meshes() {
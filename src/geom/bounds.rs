@@ -0,0 +1,151 @@
+use ash::vk::Viewport;
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    /// The smallest box containing `points`, `None` without any.
+    pub fn from_points(points: impl IntoIterator<Item = Vector3<f32>>) -> Option<Self> {
+        points.into_iter().fold(None, |aabb, point| {
+            Some(match aabb {
+                Some(Aabb { min, max }) => Aabb {
+                    min: min.inf(&point),
+                    max: max.sup(&point),
+                },
+                None => Aabb {
+                    min: point,
+                    max: point,
+                },
+            })
+        })
+    }
+
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|corner| {
+            Vector3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            )
+        })
+    }
+
+    /// Box around the transformed corners, which is larger than the box itself once rotated.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let corners = self
+            .corners()
+            .map(|corner| transform.transform_point(&Point3::from(corner)).coords);
+        Self::from_points(corners).unwrap()
+    }
+
+    /// Distance along `ray` to where it enters the box, 0 if it starts inside. `None` if it
+    /// misses the box or the box is behind it.
+    pub fn ray_intersection(&self, ray: &Ray) -> Option<f32> {
+        let (mut near, mut far) = (0.0_f32, f32::INFINITY);
+        for axis in 0..3 {
+            // a direction of 0 gives infinite distances, the slab is either never left or
+            // never entered
+            let inverse = 1.0 / ray.direction[axis];
+            let enter = (self.min[axis] - ray.origin[axis]) * inverse;
+            let exit = (self.max[axis] - ray.origin[axis]) * inverse;
+            near = near.max(enter.min(exit));
+            far = far.min(enter.max(exit));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Normalized.
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// `direction` doesn't have to be normalized, `None` if it is zero.
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Option<Self> {
+        Some(Self {
+            origin,
+            direction: direction.try_normalize(f32::EPSILON)?,
+        })
+    }
+
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Ray from the near to the far plane through `position` (physical pixels) of `viewport`,
+    /// the inverse of `project_to_viewport`. Expects the clip space depth of
+    /// `Projection::matrix`, which runs from -1 at the near plane to 1 at the far plane.
+    pub fn from_viewport(
+        view_proj: Matrix4<f32>,
+        position: [f32; 2],
+        viewport: &Viewport,
+    ) -> Option<Self> {
+        let inverse = view_proj.try_inverse()?;
+        let ndc_x = (position[0] - viewport.x) / viewport.width * 2.0 - 1.0;
+        let ndc_y = (position[1] - viewport.y) / viewport.height * 2.0 - 1.0;
+        let unproject = |depth: f32| {
+            let world = inverse * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+            (world.w.abs() > f32::EPSILON).then(|| world.xyz() / world.w)
+        };
+        let near = unproject(-1.0)?;
+        Self::new(near, unproject(1.0)? - near)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Viewport;
+    use nalgebra::{Matrix4, Vector3};
+
+    use crate::misc::{camera::Projection, measurement::project_to_viewport};
+
+    use super::{Aabb, Ray};
+
+    fn unit_box() -> Aabb {
+        Aabb::from_points([Vector3::repeat(-1.0), Vector3::repeat(1.0), Vector3::zeros()]).unwrap()
+    }
+
+    #[test]
+    fn rays_hit_the_front_of_the_box() {
+        let aabb = unit_box();
+        let towards = Ray::new(Vector3::new(0.0, 0.0, 5.0), -Vector3::z()).unwrap();
+        assert_eq!(aabb.ray_intersection(&towards), Some(4.0));
+        let away = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::z()).unwrap();
+        assert_eq!(aabb.ray_intersection(&away), None);
+        let beside = Ray::new(Vector3::new(2.0, 0.0, 5.0), -Vector3::z()).unwrap();
+        assert_eq!(aabb.ray_intersection(&beside), None);
+        let inside = Ray::new(Vector3::zeros(), Vector3::x()).unwrap();
+        assert_eq!(aabb.ray_intersection(&inside), Some(0.0));
+    }
+
+    #[test]
+    fn transformed_boxes_contain_the_moved_corners() {
+        let transform = Matrix4::<f32>::new_translation(&Vector3::x())
+            * Matrix4::new_rotation(Vector3::z() * std::f32::consts::FRAC_PI_4);
+        let moved = unit_box().transformed(&transform);
+        let diagonal = 2.0_f32.sqrt();
+        assert!((moved.max - Vector3::new(1.0 + diagonal, diagonal, 1.0)).norm() < 1e-5);
+        assert!((moved.min - Vector3::new(1.0 - diagonal, -diagonal, -1.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn viewport_rays_go_through_the_projected_point() {
+        let viewport = Viewport::default().width(800.0).height(600.0);
+        let view = Matrix4::new_translation(&Vector3::new(0.0, 0.0, -5.0));
+        let view_proj = Projection::default().matrix(800.0 / 600.0) * view;
+        let point = Vector3::new(1.0, 0.5, -2.0);
+        let position = project_to_viewport(view_proj, point, &viewport).unwrap();
+        let ray = Ray::from_viewport(view_proj, position, &viewport).unwrap();
+        let distance = (point - ray.origin).dot(&ray.direction);
+        assert!((ray.at(distance) - point).norm() < 1e-3);
+    }
+}
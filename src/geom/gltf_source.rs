@@ -0,0 +1,180 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use gltf::{buffer, image::Source, Document};
+use image::RgbaImage;
+
+/// A glTF document together with the data of its buffers. Buffers come from the GLB blob, from
+/// files relative to the document or from base64 `data:` URIs, so `.glb` files as well as
+/// `.gltf` files with external `.bin` and image files can be read.
+pub struct GltfSource {
+    pub document: Document,
+    buffers: Vec<Vec<u8>>,
+    base_directory: PathBuf,
+}
+
+impl GltfSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let gltf::Gltf { document, mut blob } = gltf::Gltf::open(path)?;
+        let base_directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let buffers = document
+            .buffers()
+            .map(|buffer| {
+                let mut data = match buffer.source() {
+                    buffer::Source::Bin => blob
+                        .take()
+                        .ok_or(anyhow!("Buffer {} uses a missing GLB blob", buffer.index()))?,
+                    buffer::Source::Uri(uri) => resolve_uri(uri, &base_directory)?,
+                };
+                if data.len() < buffer.length() {
+                    return Err(anyhow!(
+                        "Buffer {} has {} of {} bytes",
+                        buffer.index(),
+                        data.len(),
+                        buffer.length()
+                    ));
+                }
+                // the blob is padded to four bytes
+                data.truncate(buffer.length());
+                Ok(data)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            document,
+            buffers,
+            base_directory,
+        })
+    }
+
+    /// Data of `buffer`, for the readers of the gltf crate.
+    pub fn buffer(&self, buffer: gltf::Buffer) -> Option<&[u8]> {
+        self.buffers.get(buffer.index()).map(Vec::as_slice)
+    }
+
+    /// Encoded bytes of `image`, from a buffer view, a file or a data URI.
+    pub fn image_bytes(&self, image: &gltf::Image) -> Result<Cow<'_, [u8]>> {
+        match image.source() {
+            Source::View { view, .. } => {
+                let buffer = self
+                    .buffer(view.buffer())
+                    .ok_or(anyhow!("Image {} has no buffer", image.index()))?;
+                buffer
+                    .get(view.offset()..view.offset() + view.length())
+                    .map(Cow::Borrowed)
+                    .ok_or(anyhow!("Image {} is outside of its buffer", image.index()))
+            }
+            Source::Uri { uri, .. } => Ok(Cow::Owned(resolve_uri(uri, &self.base_directory)?)),
+        }
+    }
+
+    /// Decodes `image` to RGBA8, PNG and JPEG images can be read.
+    pub fn decode_image(&self, image: &gltf::Image) -> Result<RgbaImage> {
+        Ok(image::load_from_memory(&self.image_bytes(image)?)?.to_rgba8())
+    }
+}
+
+/// Contents of a buffer or image URI, either a base64 `data:` URI or a percent encoded path
+/// relative to `base_directory`.
+pub fn resolve_uri(uri: &str, base_directory: &Path) -> Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (media_type, data) = data
+            .split_once(',')
+            .ok_or(anyhow!("Data URI without data"))?;
+        if !media_type.ends_with(";base64") {
+            return Err(anyhow!("Data URIs have to be base64 encoded"));
+        }
+        return decode_base64(data).ok_or(anyhow!("Data URI with invalid base64"));
+    }
+    if uri.contains("://") {
+        return Err(anyhow!("Only relative paths and data URIs are supported, not {uri}"));
+    }
+    let path = base_directory.join(percent_decode(uri));
+    fs::read(&path).map_err(|err| anyhow!("{}: {err}", path.display()))
+}
+
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let sextet = |byte: u8| match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+    let data = data.trim_end_matches('=').as_bytes();
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .try_fold(0u32, |bits, (idx, &byte)| {
+                Some(bits | (sextet(byte)? as u32) << (18 - 6 * idx))
+            })?;
+        decoded.extend(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(decoded)
+}
+
+/// Replaces `%XX` escapes, invalid ones are kept as they are.
+fn percent_decode(uri: &str) -> PathBuf {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = (bytes[idx] == b'%')
+            .then(|| bytes.get(idx + 1..idx + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_base64_with_and_without_padding() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8").unwrap(), b"hello");
+        assert_eq!(decode_base64("AAECAw==").unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(decode_base64("").unwrap(), Vec::<u8>::new());
+        assert!(decode_base64("a*==").is_none());
+    }
+
+    #[test]
+    fn resolves_data_uris() {
+        let data = resolve_uri("data:application/octet-stream;base64,AAECAw==", Path::new(""));
+        assert_eq!(data.unwrap(), vec![0, 1, 2, 3]);
+        assert!(resolve_uri("data:text/plain,hello", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("my%20mesh.bin"), PathBuf::from("my mesh.bin"));
+        assert_eq!(percent_decode("100%.bin"), PathBuf::from("100%.bin"));
+    }
+
+    #[test]
+    fn rejects_remote_uris() {
+        assert!(resolve_uri("https://example.com/mesh.bin", Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use nalgebra::{Matrix4, Vector3};
+
+/// Coarser levels generated per surface at most.
+pub const MAX_GENERATED_LODS: usize = 3;
+/// Grid cells along the bounding box diagonal of the first generated level, halved per level.
+const FIRST_LOD_GRID_CELLS: f32 = 64.0;
+/// A level is used once its cell size covers less than this angle, in radians, from the
+/// camera. About a pixel of a 1080p screen with a 60 degree field of view.
+const LOD_ANGULAR_ERROR: f32 = 0.001;
+/// Levels that keep more of the triangles of the level before are not worth the memory.
+const MIN_REDUCTION: f32 = 0.8;
+
+/// Index list of a coarser level, drawn with the vertices of the full detail mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedLod {
+    /// Camera distance from which on the level replaces the finer ones.
+    pub min_distance: f32,
+    pub indices: Vec<u32>,
+}
+
+/// How `select_lod` picks a level, shared by every mesh of a scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodSettings {
+    /// Scales the camera distance, above 1 switches to coarser levels sooner.
+    pub bias: f32,
+    /// Draws this level everywhere instead, clamped to the levels a mesh has.
+    pub forced: Option<usize>,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            bias: 1.0,
+            forced: None,
+        }
+    }
+}
+
+/// Generates up to `MAX_GENERATED_LODS` coarser levels of the triangle list `indices` by
+/// vertex clustering, each with twice the cell size of the level before.
+pub fn generate_lods(positions: &[Vector3<f32>], indices: &[u32]) -> Vec<GeneratedLod> {
+    let Some(diagonal) = bounding_diagonal(positions, indices) else {
+        return vec![];
+    };
+    let mut lods: Vec<GeneratedLod> = vec![];
+    let mut cell_size = diagonal / FIRST_LOD_GRID_CELLS;
+    for _ in 0..MAX_GENERATED_LODS {
+        let previous_len = lods.last().map_or(indices.len(), |lod| lod.indices.len());
+        let simplified = cluster_vertices(positions, indices, cell_size);
+        if simplified.is_empty() || simplified.len() as f32 > previous_len as f32 * MIN_REDUCTION
+        {
+            break;
+        }
+        lods.push(GeneratedLod {
+            min_distance: cell_size / LOD_ANGULAR_ERROR,
+            indices: simplified,
+        });
+        cell_size *= 2.0;
+    }
+    lods
+}
+
+/// Snaps the vertices of the triangle list `indices` to a grid of `cell_size`. Every cell
+/// collapses into the first vertex found in it and triangles that lost an edge are dropped.
+pub fn cluster_vertices(positions: &[Vector3<f32>], indices: &[u32], cell_size: f32) -> Vec<u32> {
+    let mut representatives: HashMap<[i32; 3], u32> = HashMap::new();
+    let mut representative = |index: u32| {
+        let cell = positions[index as usize].map(|coordinate| (coordinate / cell_size).floor() as i32);
+        *representatives.entry([cell.x, cell.y, cell.z]).or_insert(index)
+    };
+    let mut simplified = vec![];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(&mut representative);
+        if a != b && b != c && a != c {
+            simplified.extend_from_slice(&[a, b, c]);
+        }
+    }
+    simplified
+}
+
+/// The level drawn at `distance` from the camera, `min_distances` are the ones of the
+/// coarser levels in increasing order. 0 is the full detail mesh.
+pub fn select_lod(
+    min_distances: impl ExactSizeIterator<Item = f32>,
+    distance: f32,
+    settings: LodSettings,
+) -> usize {
+    if let Some(forced) = settings.forced {
+        return forced.min(min_distances.len());
+    }
+    let distance = distance * settings.bias;
+    min_distances
+        .take_while(|&min_distance| distance >= min_distance)
+        .count()
+}
+
+/// Largest scale of the axes of a node matrix. The min distances of generated levels hold for
+/// the mesh as loaded, a mesh drawn twice as large keeps its detail up to twice as far.
+pub fn max_axis_scale(matrix: &Matrix4<f32>) -> f32 {
+    let axes = matrix.fixed_view::<3, 3>(0, 0);
+    axes.column_iter().map(|axis| axis.norm()).fold(0.0, f32::max)
+}
+
+fn bounding_diagonal(positions: &[Vector3<f32>], indices: &[u32]) -> Option<f32> {
+    let mut used = indices.iter().map(|&index| positions[index as usize]);
+    let first = used.next()?;
+    let (min, max) = used.fold((first, first), |(min, max), position| {
+        (min.inf(&position), max.sup(&position))
+    });
+    Some((max - min).norm()).filter(|diagonal| *diagonal > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix4, Rotation3, Vector3};
+
+    use super::{cluster_vertices, generate_lods, max_axis_scale, select_lod, LodSettings};
+
+    /// A flat grid of `n` by `n` quads in the xz plane.
+    fn grid(n: u32) -> (Vec<Vector3<f32>>, Vec<u32>) {
+        let positions = (0..=n)
+            .flat_map(|z| (0..=n).map(move |x| Vector3::new(x as f32, 0.0, z as f32)))
+            .collect();
+        let indices = (0..n)
+            .flat_map(|z| {
+                (0..n).flat_map(move |x| {
+                    let corner = z * (n + 1) + x;
+                    [corner, corner + n + 1, corner + 1, corner + 1, corner + n + 1, corner + n + 2]
+                })
+            })
+            .collect();
+        (positions, indices)
+    }
+
+    #[test]
+    fn clustering_drops_collapsed_triangles() {
+        let (positions, indices) = grid(4);
+        // every cell holds 2 by 2 vertices
+        let simplified = cluster_vertices(&positions, &indices, 2.0);
+        assert_eq!(simplified.len() % 3, 0);
+        assert!(simplified.len() < indices.len());
+        assert!(simplified.chunks_exact(3).all(|t| t[0] != t[1] && t[1] != t[2] && t[0] != t[2]));
+        // a cell smaller than the vertex spacing keeps the mesh as it is
+        assert_eq!(cluster_vertices(&positions, &indices, 0.5), indices);
+    }
+
+    #[test]
+    fn generated_levels_get_coarser_and_farther() {
+        let (positions, indices) = grid(64);
+        let lods = generate_lods(&positions, &indices);
+        assert!(!lods.is_empty());
+        let mut previous = (indices.len(), 0.0);
+        for lod in &lods {
+            assert!(lod.indices.len() < previous.0);
+            assert!(lod.min_distance > previous.1);
+            previous = (lod.indices.len(), lod.min_distance);
+        }
+    }
+
+    #[test]
+    fn selects_levels_by_biased_distance() {
+        let distances = [10.0, 20.0, 40.0];
+        let settings = LodSettings::default();
+        assert_eq!(select_lod(distances.into_iter(), 5.0, settings), 0);
+        assert_eq!(select_lod(distances.into_iter(), 25.0, settings), 2);
+        assert_eq!(select_lod(distances.into_iter(), 100.0, settings), 3);
+        let biased = LodSettings { bias: 2.0, ..settings };
+        assert_eq!(select_lod(distances.into_iter(), 5.0, biased), 1);
+        let forced = LodSettings { forced: Some(7), ..settings };
+        assert_eq!(select_lod(distances.into_iter(), 5.0, forced), 3);
+    }
+
+    #[test]
+    fn the_largest_axis_scale_ignores_rotation_and_translation() {
+        let matrix = Matrix4::new_translation(&Vector3::new(5.0, 0.0, -3.0))
+            * Rotation3::from_euler_angles(0.3, 1.1, -0.4).to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(0.5, 3.0, 2.0));
+        assert!((max_axis_scale(&matrix) - 3.0).abs() < 1e-5);
+        assert_eq!(max_axis_scale(&Matrix4::identity()), 1.0);
+    }
+}
@@ -1,8 +1,8 @@
 use std::{fmt::Debug, iter::Sum, marker::PhantomData};
 
-use crate::components::allocation_types::VkBuffer;
+use crate::components::buffer_arena::BufferSlice;
 use anyhow::Error;
-use ash::vk::{BufferUsageFlags, MemoryPropertyFlags, Rect2D, Viewport};
+use ash::vk::{BufferUsageFlags, DeviceAddress, MemoryPropertyFlags, Rect2D, Viewport};
 use egui::TextureId;
 use vk_mem::MemoryUsage;
 
@@ -27,8 +27,8 @@ where
     T: VertexAttributes,
     U: Sum,
 {
-    pub vertex_buffer: VkBuffer,
-    pub index_buffer: VkBuffer,
+    pub vertex_buffer: BufferSlice,
+    pub index_buffer: BufferSlice,
     pub mesh: Mesh<T, U>,
     pub indices: PhantomData<U>,
 }
@@ -46,17 +46,17 @@ where
             BufferUsageFlags,
             MemoryUsage,
             MemoryPropertyFlags,
-        ) -> VkBuffer,
+        ) -> BufferSlice,
         create_index_buffer: impl FnOnce(
             Vec<U>,
             BufferUsageFlags,
             MemoryUsage,
             MemoryPropertyFlags,
-        ) -> VkBuffer,
+        ) -> BufferSlice,
     ) -> Result<MeshBuffers<T, U>, Error> {
         let vertex_buffer = create_vertex_buffer(
             mesh.vertices.clone(),
-             BufferUsageFlags::VERTEX_BUFFER | BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
             MemoryUsage::GpuOnly,
             MemoryPropertyFlags::DEVICE_LOCAL,
         );
@@ -74,3 +74,21 @@ where
         })
     }
 }
+
+impl<T, U> MeshBuffers<T, U>
+where
+    T: VertexAttributes,
+    U: Sum,
+{
+    /// Address of the first vertex, the vertex shaders pull the vertices through it instead of
+    /// binding a vertex buffer.
+    pub fn vertex_address(&self) -> DeviceAddress {
+        self.vertex_buffer.address()
+    }
+
+    /// Offset of the mesh indices in the shared index buffer, to be added to `first_index` of
+    /// the draws.
+    pub fn first_index(&self) -> u32 {
+        self.index_buffer.first_element::<U>()
+    }
+}
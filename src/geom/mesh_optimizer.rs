@@ -0,0 +1,401 @@
+use std::{collections::HashMap, ops::Range};
+
+use nalgebra::Vector3;
+
+use super::vertex_3d::Vertex3D;
+
+/// Vertices the vertex cache optimization assumes the GPU keeps around.
+const VERTEX_CACHE_SIZE: usize = 32;
+/// FIFO cache size of the ACMR reported in the stats and of the overdraw cluster splitting,
+/// small enough to be pessimistic for current GPUs.
+const FIFO_CACHE_SIZE: usize = 16;
+
+/// What `optimize_mesh` changed. The ACMR is the average number of vertex shader invocations
+/// per triangle with a FIFO post-transform cache, between 0.5 and 3, lower is better.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OptimizationStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+/// Import time optimization similar to meshoptimizer's pipeline: identical vertices are merged,
+/// the triangles of every index range are reordered for the vertex cache and then in clusters
+/// for less overdraw, and the vertices are put in the order they are first used. Ranges keep
+/// their place in the index buffer, so index ranges of surfaces and their levels of detail stay
+/// valid. Ranges overlapping an earlier one or reaching past `indices` are left as they are.
+pub fn optimize_mesh(
+    vertices: &mut Vec<Vertex3D>,
+    indices: &mut [u32],
+    ranges: &[Range<usize>],
+) -> OptimizationStats {
+    let vertices_before = vertices.len();
+    let acmr_before = acmr(indices, FIFO_CACHE_SIZE);
+    let (unique, remap) = deduplicate_vertices(vertices);
+    *vertices = unique;
+    for index in indices.iter_mut() {
+        *index = remap[*index as usize];
+    }
+    let positions: Vec<Vector3<f32>> = vertices.iter().map(|vertex| vertex.pos).collect();
+    let mut optimized: Vec<Range<usize>> = vec![];
+    let mut sorted_ranges = ranges.to_vec();
+    sorted_ranges.sort_by_key(|range| range.start);
+    for range in sorted_ranges {
+        let overlaps = optimized.last().is_some_and(|last| last.end > range.start);
+        if overlaps || range.end > indices.len() || range.len() % 3 != 0 {
+            continue;
+        }
+        let reordered = optimize_vertex_cache(&indices[range.clone()], vertices.len());
+        indices[range.clone()].copy_from_slice(&reordered);
+        optimize_overdraw(&mut indices[range.clone()], &positions);
+        optimized.push(range);
+    }
+    *vertices = optimize_vertex_fetch(vertices, indices);
+    OptimizationStats {
+        vertices_before,
+        vertices_after: vertices.len(),
+        acmr_before,
+        acmr_after: acmr(indices, FIFO_CACHE_SIZE),
+    }
+}
+
+/// Bit patterns of every attribute, vertices are merged only if all of them are identical.
+fn vertex_key(vertex: &Vertex3D) -> [u32; 24] {
+    let mut key = [0; 24];
+    let floats = vertex
+        .pos
+        .iter()
+        .chain(vertex.uv.iter())
+        .chain(vertex.normal.iter())
+        .chain(vertex.color.iter())
+        .chain(vertex.tangent.iter())
+        .chain(vertex.weights.iter());
+    for (slot, value) in key.iter_mut().zip(floats) {
+        *slot = value.to_bits();
+    }
+    key[20..].copy_from_slice(vertex.joints.as_slice());
+    key
+}
+
+/// The distinct vertices and the index of the distinct one for every vertex of `vertices`.
+pub fn deduplicate_vertices(vertices: &[Vertex3D]) -> (Vec<Vertex3D>, Vec<u32>) {
+    let mut unique: Vec<Vertex3D> = vec![];
+    let mut by_key: HashMap<[u32; 24], u32> = HashMap::new();
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            *by_key.entry(vertex_key(vertex)).or_insert_with(|| {
+                unique.push(vertex.clone());
+                unique.len() as u32 - 1
+            })
+        })
+        .collect();
+    (unique, remap)
+}
+
+/// Score of a vertex as in Tom Forsyth's linear-speed vertex cache optimization, vertices
+/// recently used and those with few triangles left are preferred.
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: u32) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // the vertices of the last triangle are scored lower so strips are not favored
+        Some(position) if position < 3 => 0.75,
+        Some(position) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).max(0.0).powf(1.5)
+        }
+    };
+    cache_score + 2.0 * (remaining_triangles as f32).powf(-0.5)
+}
+
+/// Reorders the triangles of `indices` so consecutive ones share vertices still in the
+/// post-transform cache.
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let mut remaining = vec![0u32; vertex_count];
+    for &index in indices {
+        remaining[index as usize] += 1;
+    }
+    // triangles of every vertex, the first `remaining` of each vertex are not emitted yet
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for vertex in 0..vertex_count {
+        offsets[vertex + 1] = offsets[vertex] + remaining[vertex] as usize;
+    }
+    let mut adjacency = vec![0usize; indices.len()];
+    let mut filled = offsets.clone();
+    for (idx, &index) in indices.iter().enumerate() {
+        adjacency[filled[index as usize]] = idx / 3;
+        filled[index as usize] += 1;
+    }
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = (0..vertex_count)
+        .map(|vertex| vertex_score(None, remaining[vertex]))
+        .collect();
+    let triangle_score = |triangle: usize, vertex_scores: &[f32]| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|&index| vertex_scores[index as usize])
+            .sum()
+    };
+    let mut triangle_scores: Vec<f32> = (0..triangle_count)
+        .map(|triangle| triangle_score(triangle, &vertex_scores))
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = vec![];
+    let mut optimized = Vec::with_capacity(indices.len());
+    let mut next_unemitted = 0;
+    let mut best = (0..triangle_count).max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+    while let Some(triangle) = best {
+        emitted[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        optimized.extend_from_slice(corners);
+        for &vertex in corners {
+            let vertex = vertex as usize;
+            let live = offsets[vertex]..offsets[vertex] + remaining[vertex] as usize;
+            if let Some(slot) = adjacency[live.clone()].iter().position(|&t| t == triangle) {
+                adjacency.swap(live.start + slot, live.end - 1);
+                remaining[vertex] -= 1;
+            }
+        }
+        let mut touched: Vec<u32> = cache.clone();
+        let mut new_cache: Vec<u32> = vec![];
+        for &vertex in corners.iter().chain(cache.iter()) {
+            if !new_cache.contains(&vertex) {
+                new_cache.push(vertex);
+            }
+        }
+        for &vertex in new_cache.iter().skip(VERTEX_CACHE_SIZE) {
+            cache_position[vertex as usize] = None;
+        }
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            cache_position[vertex as usize] = Some(position);
+        }
+        touched.extend_from_slice(corners);
+        cache = new_cache;
+        for &vertex in &touched {
+            let vertex = vertex as usize;
+            vertex_scores[vertex] = vertex_score(cache_position[vertex], remaining[vertex]);
+        }
+        best = None;
+        let mut best_score = f32::MIN;
+        for &vertex in &touched {
+            let vertex = vertex as usize;
+            for &adjacent in &adjacency[offsets[vertex]..offsets[vertex] + remaining[vertex] as usize] {
+                triangle_scores[adjacent] = triangle_score(adjacent, &vertex_scores);
+                if triangle_scores[adjacent] > best_score {
+                    best_score = triangle_scores[adjacent];
+                    best = Some(adjacent);
+                }
+            }
+        }
+        if best.is_none() {
+            // nothing in the cache has triangles left, continue in input order
+            while next_unemitted < triangle_count && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            best = (next_unemitted < triangle_count).then_some(next_unemitted);
+        }
+    }
+    optimized
+}
+
+/// Splits the triangles of the vertex cache optimized `indices` into clusters where the FIFO
+/// cache starts over, then draws the clusters facing away from the mesh center first. Those
+/// are the outer surfaces of mostly convex meshes, which hide what is drawn after them.
+pub fn optimize_overdraw(indices: &mut [u32], positions: &[Vector3<f32>]) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count < 2 {
+        return;
+    }
+    let mut cluster_starts = vec![0];
+    let mut cache: Vec<u32> = vec![];
+    for triangle in 0..triangle_count {
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        let misses = corners.iter().filter(|index| !cache.contains(index)).count();
+        if misses == 3 && triangle > 0 {
+            cluster_starts.push(triangle);
+        }
+        for &index in corners {
+            if !cache.contains(&index) {
+                cache.push(index);
+                if cache.len() > FIFO_CACHE_SIZE {
+                    cache.remove(0);
+                }
+            }
+        }
+    }
+    if cluster_starts.len() < 2 {
+        return;
+    }
+    let corner = |index: u32| positions[index as usize];
+    let mesh_center = indices.iter().map(|&index| corner(index)).sum::<Vector3<f32>>()
+        / indices.len() as f32;
+    let mut clusters: Vec<(f32, Range<usize>)> = cluster_starts
+        .iter()
+        .zip(cluster_starts.iter().skip(1).chain([&triangle_count]))
+        .map(|(&start, &end)| {
+            let range = start * 3..end * 3;
+            let mut center = Vector3::zeros();
+            let mut normal = Vector3::zeros();
+            for triangle in indices[range.clone()].chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|idx| corner(triangle[idx]));
+                center += a + b + c;
+                normal += (b - a).cross(&(c - a));
+            }
+            center /= range.len() as f32;
+            let facing = normal
+                .try_normalize(f32::EPSILON)
+                .map_or(0.0, |normal| (center - mesh_center).dot(&normal));
+            (facing, range)
+        })
+        .collect();
+    clusters.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    let sorted: Vec<u32> = clusters
+        .iter()
+        .flat_map(|(_, range)| indices[range.clone()].to_vec())
+        .collect();
+    indices.copy_from_slice(&sorted);
+}
+
+/// The vertices in the order `indices` first uses them, with `indices` pointing at the new
+/// positions. Vertices no index uses are dropped.
+pub fn optimize_vertex_fetch(vertices: &[Vertex3D], indices: &mut [u32]) -> Vec<Vertex3D> {
+    let mut remap: Vec<Option<u32>> = vec![None; vertices.len()];
+    let mut fetched = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let new_index = *remap[*index as usize].get_or_insert_with(|| {
+            fetched.push(vertices[*index as usize].clone());
+            fetched.len() as u32 - 1
+        });
+        *index = new_index;
+    }
+    fetched
+}
+
+/// Average cache misses per triangle of `indices` drawn with a FIFO cache of `cache_size`.
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    if indices.len() < 3 {
+        return 0.0;
+    }
+    let mut cache: Vec<u32> = vec![];
+    let mut misses = 0;
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            cache.push(index);
+            if cache.len() > cache_size {
+                cache.remove(0);
+            }
+        }
+    }
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector2, Vector4};
+
+    use super::*;
+
+    fn vertex(x: f32, y: f32) -> Vertex3D {
+        Vertex3D::new(
+            Vector3::new(x, y, 0.0),
+            Vector2::zeros(),
+            Vector3::z(),
+            Vector4::from_element(1.0),
+        )
+    }
+
+    /// Triangles of a `size` x `size` grid of quads, row by row.
+    fn grid(size: u32) -> (Vec<Vertex3D>, Vec<u32>) {
+        let vertices = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| vertex(x as f32, y as f32)))
+            .collect();
+        let indices = (0..size)
+            .flat_map(|y| {
+                (0..size).flat_map(move |x| {
+                    let corner = y * (size + 1) + x;
+                    let above = corner + size + 1;
+                    [corner, corner + 1, above, corner + 1, above + 1, above]
+                })
+            })
+            .collect();
+        (vertices, indices)
+    }
+
+    fn sorted_triangles(indices: &[u32], vertices: &[Vertex3D]) -> Vec<[[u32; 2]; 3]> {
+        let mut triangles: Vec<_> = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let mut corners = [0, 1, 2].map(|idx| {
+                    let pos = vertices[triangle[idx] as usize].pos;
+                    [pos.x as u32, pos.y as u32]
+                });
+                corners.sort();
+                corners
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn merges_identical_vertices() {
+        let vertices = vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 0.0)];
+        let (unique, remap) = deduplicate_vertices(&vertices);
+        assert_eq!(unique.len(), 2);
+        assert_eq!(remap, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn vertex_cache_optimization_keeps_triangles_and_lowers_acmr() {
+        let (vertices, indices) = grid(64);
+        let optimized = optimize_vertex_cache(&indices, vertices.len());
+        assert_eq!(
+            sorted_triangles(&optimized, &vertices),
+            sorted_triangles(&indices, &vertices)
+        );
+        assert!(acmr(&optimized, FIFO_CACHE_SIZE) < acmr(&indices, FIFO_CACHE_SIZE));
+    }
+
+    #[test]
+    fn vertex_fetch_follows_first_use() {
+        let vertices = vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(2.0, 0.0), vertex(3.0, 0.0)];
+        let mut indices = vec![2, 0, 3];
+        let fetched = optimize_vertex_fetch(&vertices, &mut indices);
+        assert_eq!(indices, vec![0, 1, 2]);
+        let xs: Vec<f32> = fetched.iter().map(|vertex| vertex.pos.x).collect();
+        assert_eq!(xs, vec![2.0, 0.0, 3.0]);
+    }
+
+    #[test]
+    fn optimizes_ranges_in_place() {
+        let (mut grid_vertices, grid_indices) = grid(8);
+        // every vertex twice, the second copy used by a coarser level behind the first range
+        let copies = grid_vertices.len() as u32;
+        grid_vertices.extend(grid_vertices.clone());
+        let mut indices = grid_indices.clone();
+        indices.extend(grid_indices.iter().take(6).map(|index| index + copies));
+        let original = grid_vertices.clone();
+        let mut vertices = grid_vertices;
+        let ranges = [0..grid_indices.len(), grid_indices.len()..indices.len()];
+        let stats = optimize_mesh(&mut vertices, &mut indices, &ranges);
+        assert_eq!(stats.vertices_before, original.len());
+        assert_eq!(stats.vertices_after, original.len() / 2);
+        assert!(stats.acmr_after <= stats.acmr_before);
+        let (full, lod) = indices.split_at(grid_indices.len());
+        assert_eq!(
+            sorted_triangles(full, &vertices),
+            sorted_triangles(&grid_indices, &original)
+        );
+        assert_eq!(
+            sorted_triangles(lod, &vertices),
+            sorted_triangles(&grid_indices[..6], &original)
+        );
+    }
+}
@@ -1,14 +1,22 @@
 use ash::vk::{
-    DeviceAddress, Extent2D, VertexInputAttributeDescription, VertexInputBindingDescription,
+    DeviceAddress, Extent2D, Format, VertexInputAttributeDescription,
+    VertexInputBindingDescription,
 };
 use nalgebra::{Matrix4, Perspective3, Vector3};
 use push_constants::PushConstant;
 use winit::window::Window;
 
+pub mod animation;
 pub mod assets;
+pub mod bounds;
+pub mod gltf_source;
+pub mod lod;
 pub mod mesh;
+pub mod mesh_optimizer;
+pub mod obj;
 pub mod push_constants;
 pub mod scene;
+pub mod texture_loader;
 pub mod vertex_2d;
 pub mod vertex_3d;
 
@@ -18,29 +26,70 @@ pub trait VertexAttributes {
     fn get_attribute_description() -> Vec<VertexInputAttributeDescription>;
 }
 
+/// Bytes of one element of `format`, for the vertex attribute formats `vertex_attributes!`
+/// accepts. 0 for every other format, which fails its layout check.
+pub const fn format_size(format: Format) -> usize {
+    match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UINT => 4,
+        Format::R32_SFLOAT | Format::R32_UINT | Format::R32_SINT => 4,
+        Format::R32G32_SFLOAT | Format::R32G32_UINT | Format::R32G32_SINT => 8,
+        Format::R32G32B32_SFLOAT | Format::R32G32B32_UINT | Format::R32G32B32_SINT => 12,
+        Format::R32G32B32A32_SFLOAT | Format::R32G32B32A32_UINT | Format::R32G32B32A32_SINT => 16,
+        _ => 0,
+    }
+}
+
+/// Size of the field `field` borrows, `field` is never called.
+pub const fn field_size<T, F>(_field: fn(&T) -> &F) -> usize {
+    size_of::<F>()
+}
+
 pub fn egui_push_constant(window: &Window) -> Vec<u8> {
     let scale_factor = window.scale_factor();
     let logical_size = window.inner_size().to_logical::<f32>(scale_factor);
+    egui_rect_push_constant(0.0, 0.0, logical_size.width, logical_size.height)
+}
 
-    let sx = 2.0 / logical_size.width;
-    let sy = 2.0 / logical_size.height;
-    let tx = -1.0;
-    let ty = -1.0;
+/// Maps the logical egui rect starting at (x, y) onto the whole bound viewport.
+pub fn egui_rect_push_constant(x: f32, y: f32, width: f32, height: f32) -> Vec<u8> {
+    let sx = 2.0 / width;
+    let sy = 2.0 / height;
+    let tx = -1.0 - x * sx;
+    let ty = -1.0 - y * sy;
 
+    // Matrix4::new takes the rows, the translation ends up in the last column
     let push_constant = PushConstant::new(
         Matrix4::new(
-            sx, 0.0, 0.0, tx, // Column 1
-            0.0, sy, 0.0, ty, // Column 2
-            0.0, 0.0, 1.0, 0.0, // Column 3
-            0.0, 0.0, 0.0, 1.0, // Column 4
+            sx, 0.0, 0.0, tx, // Row 1
+            0.0, sy, 0.0, ty, // Row 2
+            0.0, 0.0, 1.0, 0.0, // Row 3
+            0.0, 0.0, 0.0, 1.0, // Row 4
         ),
         u64::default(),
     );
     push_constant.raw_data_of_T()
 }
 
-pub fn gpu_scene_push_constant(transform: Matrix4<f32>, buffer_address: DeviceAddress) -> Vec<u8> {
-    PushConstant::new(transform, buffer_address).raw_data()
+pub fn gpu_scene_push_constant(
+    transform: Matrix4<f32>,
+    buffer_address: DeviceAddress,
+    material_index: u32,
+) -> Vec<u8> {
+    skinned_scene_push_constant(transform, buffer_address, material_index, 0)
+}
+
+/// `gpu_scene_push_constant` for the skinned mesh pipelines, `joint_address` points at the
+/// first joint matrix of the mesh.
+pub fn skinned_scene_push_constant(
+    transform: Matrix4<f32>,
+    buffer_address: DeviceAddress,
+    material_index: u32,
+    joint_address: DeviceAddress,
+) -> Vec<u8> {
+    PushConstant::new(transform, buffer_address)
+        .with_index(material_index)
+        .with_second_address(joint_address)
+        .raw_data()
 }
 
 
@@ -48,8 +97,8 @@ pub fn triangle_push_constant(buffer_address: DeviceAddress, extent: Extent2D) -
     let (height, width) = (extent.height, extent.width);
     let view = Matrix4::<f32>::new_translation(&Vector3::new(0.0, 0.0, -2.0));
     let mut proj = Perspective3::new(
-        70.0_f32.to_radians(),
         width as f32 / height as f32,
+        70.0_f32.to_radians(),
         0.1,
         1000.0,
     )
@@ -59,3 +108,139 @@ pub fn triangle_push_constant(buffer_address: DeviceAddress, extent: Extent2D) -
     let push_constant = PushConstant::new(wm, buffer_address);
     push_constant.raw_data()
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Extent2D;
+    use nalgebra::{Matrix4, Vector4};
+
+    use super::{
+        egui_rect_push_constant, format_size, gpu_scene_push_constant,
+        skinned_scene_push_constant, triangle_push_constant, vertex_3d::Vertex3D,
+        VertexAttributes,
+    };
+
+    /// Reads the column major matrix the shaders see at the start of a push constant.
+    fn matrix(bytes: &[u8]) -> Matrix4<f32> {
+        Matrix4::from_iterator(
+            bytes[..64]
+                .chunks_exact(4)
+                .map(|float| f32::from_ne_bytes(float.try_into().unwrap())),
+        )
+    }
+
+    fn project(matrix: &Matrix4<f32>, x: f32, y: f32, z: f32) -> Vector4<f32> {
+        let clip = matrix * Vector4::new(x, y, z, 1.0);
+        clip / clip.w
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn egui_projection_maps_logical_pixels_to_ndc() {
+        let bytes = egui_rect_push_constant(0.0, 0.0, 800.0, 600.0);
+        assert_eq!(bytes.len(), 64);
+        let projection = matrix(&bytes);
+        for (x, y, ndc_x, ndc_y) in [
+            (0.0, 0.0, -1.0, -1.0),
+            (800.0, 600.0, 1.0, 1.0),
+            (400.0, 300.0, 0.0, 0.0),
+            (200.0, 450.0, -0.5, 0.5),
+        ] {
+            let ndc = project(&projection, x, y, 0.0);
+            assert_close(ndc.x, ndc_x);
+            assert_close(ndc.y, ndc_y);
+        }
+    }
+
+    #[test]
+    fn egui_projection_keeps_the_translation_in_the_last_column() {
+        let bytes = egui_rect_push_constant(0.0, 0.0, 800.0, 600.0);
+        let floats: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|float| f32::from_ne_bytes(float.try_into().unwrap()))
+            .collect();
+        assert_close(floats[0], 2.0 / 800.0);
+        assert_close(floats[5], 2.0 / 600.0);
+        assert_close(floats[12], -1.0);
+        assert_close(floats[13], -1.0);
+        assert_close(floats[3], 0.0);
+        assert_close(floats[7], 0.0);
+    }
+
+    #[test]
+    fn egui_rect_projection_starts_at_the_rect() {
+        let projection = matrix(&egui_rect_push_constant(100.0, 50.0, 400.0, 200.0));
+        let top_left = project(&projection, 100.0, 50.0, 0.0);
+        assert_close(top_left.x, -1.0);
+        assert_close(top_left.y, -1.0);
+        let bottom_right = project(&projection, 500.0, 250.0, 0.0);
+        assert_close(bottom_right.x, 1.0);
+        assert_close(bottom_right.y, 1.0);
+    }
+
+    #[test]
+    fn gpu_scene_push_constant_layout() {
+        let transform = Matrix4::new_scaling(2.0);
+        let bytes = gpu_scene_push_constant(transform, 0x1122_3344_5566_7788, 42);
+        // mat4, device address, material index and padding, then the joint address of
+        // scene_data_mesh_skinned.vert
+        assert_eq!(bytes.len(), 88);
+        assert_eq!(matrix(&bytes), transform);
+        assert_eq!(
+            u64::from_ne_bytes(bytes[64..72].try_into().unwrap()),
+            0x1122_3344_5566_7788
+        );
+        assert_eq!(u32::from_ne_bytes(bytes[72..76].try_into().unwrap()), 42);
+        assert_eq!(u64::from_ne_bytes(bytes[80..88].try_into().unwrap()), 0);
+    }
+
+    #[test]
+    fn skinned_push_constant_ends_with_the_joint_address() {
+        let bytes = skinned_scene_push_constant(Matrix4::identity(), 0x10, 1, 0x2000);
+        assert_eq!(bytes.len(), 88);
+        assert_eq!(u64::from_ne_bytes(bytes[80..88].try_into().unwrap()), 0x2000);
+    }
+
+    #[test]
+    fn triangle_push_constant_projects_the_origin_to_the_center() {
+        let extent = Extent2D {
+            width: 1600,
+            height: 800,
+        };
+        let bytes = triangle_push_constant(0x10, extent);
+        assert_eq!(bytes.len(), 88);
+        assert_eq!(u64::from_ne_bytes(bytes[64..72].try_into().unwrap()), 0x10);
+
+        let view_proj = matrix(&bytes);
+        let center = project(&view_proj, 0.0, 0.0, 0.0);
+        assert_close(center.x, 0.0);
+        assert_close(center.y, 0.0);
+        assert!(center.z > -1.0 && center.z < 1.0);
+
+        // a 2:1 extent squeezes x by the aspect ratio
+        let right = project(&view_proj, 1.0, 0.0, 0.0);
+        let up = project(&view_proj, 0.0, 1.0, 0.0);
+        assert_close(up.y.abs(), right.x.abs() * 2.0);
+        assert!(right.x > 0.0);
+    }
+
+    #[test]
+    fn vertex_3d_attributes_get_their_own_locations() {
+        let attributes = Vertex3D::get_attribute_description();
+        let locations: Vec<u32> = attributes.iter().map(|attribute| attribute.location).collect();
+        assert_eq!(locations, (0..7).collect::<Vec<_>>());
+        let stride = Vertex3D::get_binding_description()[0].stride as usize;
+        for pair in attributes.windows(2) {
+            let end = pair[0].offset as usize + format_size(pair[0].format);
+            assert!(end <= pair[1].offset as usize);
+        }
+        let last = attributes.last().unwrap();
+        assert!(last.offset as usize + format_size(last.format) <= stride);
+    }
+}
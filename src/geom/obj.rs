@@ -0,0 +1,350 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use nalgebra::{Vector2, Vector3, Vector4};
+
+use crate::misc::material_library::{MaterialDefinition, MaterialPassDefinition};
+
+use super::vertex_3d::Vertex3D;
+
+/// Triangles of an object drawn with one material.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjSurface {
+    /// Name of the `newmtl` block selected with `usemtl`.
+    pub material: Option<String>,
+    pub indices: Vec<u32>,
+}
+
+/// An `o` or `g` block of an OBJ file, with its own vertices.
+#[derive(Debug, Clone)]
+pub struct ObjObject {
+    pub name: String,
+    pub vertices: Vec<Vertex3D>,
+    pub surfaces: Vec<ObjSurface>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjModel {
+    pub objects: Vec<ObjObject>,
+    /// `mtllib` files, relative to the OBJ file.
+    pub material_libraries: Vec<PathBuf>,
+}
+
+/// Attribute indices of a face corner, already resolved from the 1-based or negative ones of
+/// the file.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct ObjectBuilder {
+    name: String,
+    corners: HashMap<Corner, u32>,
+    vertices: Vec<Vertex3D>,
+    surfaces: Vec<ObjSurface>,
+    /// Whether the vertex normals come from the file or have to be generated.
+    has_normals: bool,
+}
+
+impl ObjectBuilder {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    fn surface(&mut self, material: &Option<String>) -> &mut ObjSurface {
+        if self.surfaces.last().is_none_or(|surface| &surface.material != material) {
+            self.surfaces.push(ObjSurface {
+                material: material.clone(),
+                indices: vec![],
+            });
+        }
+        self.surfaces.last_mut().unwrap()
+    }
+
+    fn build(mut self) -> Option<ObjObject> {
+        self.surfaces.retain(|surface| !surface.indices.is_empty());
+        if self.surfaces.is_empty() {
+            return None;
+        }
+        if !self.has_normals {
+            generate_normals(&mut self.vertices, &self.surfaces);
+        }
+        Some(ObjObject {
+            name: self.name,
+            vertices: self.vertices,
+            surfaces: self.surfaces,
+        })
+    }
+}
+
+/// Parses the geometry of an OBJ file. Polygons are triangulated as fans, vertices are shared
+/// by the corners using the same position, texture coordinate and normal. Objects without
+/// normals get smooth ones generated.
+pub fn parse_obj(source: &str) -> Result<ObjModel> {
+    let mut positions: Vec<Vector3<f32>> = vec![];
+    let mut uvs: Vec<Vector2<f32>> = vec![];
+    let mut normals: Vec<Vector3<f32>> = vec![];
+    let mut model = ObjModel::default();
+    let mut material: Option<String> = None;
+    let mut object = ObjectBuilder::new("default".to_owned());
+    for (line_idx, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let error = |message: &str| anyhow!("Line {}: {message}", line_idx + 1);
+        match keyword {
+            "v" => positions.push(Vector3::from(floats::<3>(tokens).ok_or(error("bad position"))?)),
+            "vt" => uvs.push(Vector2::from(floats::<2>(tokens).ok_or(error("bad uv"))?)),
+            "vn" => normals.push(Vector3::from(floats::<3>(tokens).ok_or(error("bad normal"))?)),
+            "o" | "g" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let previous = std::mem::replace(&mut object, ObjectBuilder::new(name));
+                model.objects.extend(previous.build());
+            }
+            "usemtl" => material = tokens.next().map(str::to_owned),
+            "mtllib" => model.material_libraries.extend(tokens.map(PathBuf::from)),
+            "f" => {
+                let corners = tokens
+                    .map(|corner| {
+                        parse_corner(corner, positions.len(), uvs.len(), normals.len())
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(error("bad face"))?;
+                if corners.len() < 3 {
+                    return Err(error("faces need at least three corners"));
+                }
+                let indices: Vec<u32> = corners
+                    .into_iter()
+                    .map(|corner| {
+                        let (position, uv, normal) = corner;
+                        object.has_normals |= normal.is_some();
+                        let next_index = object.vertices.len() as u32;
+                        *object.corners.entry(corner).or_insert_with(|| {
+                            object.vertices.push(Vertex3D::new(
+                                positions[position],
+                                // OBJ puts v = 0 at the bottom of the image
+                                uv.map_or(Vector2::zeros(), |uv| {
+                                    Vector2::new(uvs[uv].x, 1.0 - uvs[uv].y)
+                                }),
+                                normal.map_or(Vector3::zeros(), |normal| normals[normal]),
+                                Vector4::from_element(1.0),
+                            ));
+                            next_index
+                        })
+                    })
+                    .collect();
+                let surface = object.surface(&material);
+                for idx in 1..indices.len() - 1 {
+                    surface.indices.extend([indices[0], indices[idx], indices[idx + 1]]);
+                }
+            }
+            // smoothing groups, lines and points are not drawn
+            _ => {}
+        }
+    }
+    model.objects.extend(object.build());
+    Ok(model)
+}
+
+/// Reads `f` corners like `1`, `1/2`, `1//3` or `1/2/3`.
+fn parse_corner(corner: &str, positions: usize, uvs: usize, normals: usize) -> Option<Corner> {
+    let mut parts = corner.split('/');
+    let position = resolve_index(parts.next()?, positions)?;
+    let uv = match parts.next() {
+        None | Some("") => None,
+        Some(uv) => Some(resolve_index(uv, uvs)?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(normal) => Some(resolve_index(normal, normals)?),
+    };
+    Some((position, uv, normal))
+}
+
+/// Turns the 1-based index, or the negative one counting back from the last element, into an
+/// index of the `len` elements read so far.
+fn resolve_index(index: &str, len: usize) -> Option<usize> {
+    let index: i64 = index.parse().ok()?;
+    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+    (0..len as i64).contains(&resolved).then_some(resolved as usize)
+}
+
+fn floats<'a, const N: usize>(tokens: impl Iterator<Item = &'a str>) -> Option<[f32; N]> {
+    let values = tokens
+        .take(N)
+        .map(|token| token.parse().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    values.try_into().ok()
+}
+
+/// Area weighted normals of the triangles around every vertex.
+fn generate_normals(vertices: &mut [Vertex3D], surfaces: &[ObjSurface]) {
+    let mut accumulated = vec![Vector3::<f32>::zeros(); vertices.len()];
+    for triangle in surfaces
+        .iter()
+        .flat_map(|surface| surface.indices.chunks_exact(3))
+    {
+        let [a, b, c] = [0, 1, 2].map(|corner| vertices[triangle[corner] as usize].pos);
+        let normal = (b - a).cross(&(c - a));
+        for &idx in triangle {
+            accumulated[idx as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(accumulated) {
+        vertex.normal = normal.try_normalize(f32::EPSILON).unwrap_or(Vector3::y());
+    }
+}
+
+/// Turns the `newmtl` blocks of an MTL file into material definitions. Diffuse color and
+/// dissolve become the color factors, `Pm`/`Pr` the metallic and roughness factors, with the
+/// roughness derived from the specular exponent `Ns` when `Pr` is missing. Texture paths are
+/// resolved against `base_directory`.
+pub fn parse_mtl(source: &str, base_directory: &Path) -> Vec<MaterialDefinition> {
+    let mut materials: Vec<MaterialDefinition> = vec![];
+    // the roughness is only derived from Ns for materials that don't set Pr
+    let mut explicit_roughness = false;
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            materials.push(MaterialDefinition {
+                metal_rough_factors: [0.0, 0.5, 0.0, 0.0],
+                ..MaterialDefinition::new(name)
+            });
+            explicit_roughness = false;
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            continue;
+        };
+        // texture statements may carry options in front of the file name
+        let texture = || line.split_whitespace().last().map(|path| base_directory.join(path));
+        match keyword {
+            "Kd" => {
+                if let Some([r, g, b]) = floats::<3>(tokens) {
+                    material.color_factors[..3].copy_from_slice(&[r, g, b]);
+                }
+            }
+            "d" => {
+                if let Some([alpha]) = floats::<1>(tokens) {
+                    material.color_factors[3] = alpha;
+                }
+            }
+            "Tr" => {
+                if let Some([transparency]) = floats::<1>(tokens) {
+                    material.color_factors[3] = 1.0 - transparency;
+                }
+            }
+            "Ke" => {
+                if let Some(emissive) = floats::<3>(tokens) {
+                    material.emissive_factor = emissive;
+                }
+            }
+            "Pm" => {
+                if let Some([metallic]) = floats::<1>(tokens) {
+                    material.metal_rough_factors[0] = metallic;
+                }
+            }
+            "Pr" => {
+                if let Some([roughness]) = floats::<1>(tokens) {
+                    material.metal_rough_factors[1] = roughness;
+                    explicit_roughness = true;
+                }
+            }
+            "Ns" if !explicit_roughness => {
+                if let Some([exponent]) = floats::<1>(tokens) {
+                    // Blinn-Phong exponent to GGX roughness
+                    material.metal_rough_factors[1] = (2.0 / (exponent.max(0.0) + 2.0)).sqrt();
+                }
+            }
+            "map_Kd" => material.textures.color = texture(),
+            "map_Bump" | "map_bump" | "bump" | "norm" => material.textures.normal = texture(),
+            "map_Ke" => material.textures.emissive = texture(),
+            _ => {}
+        }
+    }
+    for material in &mut materials {
+        if material.color_factors[3] < 1.0 {
+            material.pass = MaterialPassDefinition::Transparent;
+        }
+    }
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangulates_quads_and_shares_corners() {
+        let model = parse_obj(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\n",
+        )
+        .unwrap();
+        let object = &model.objects[0];
+        assert_eq!(object.vertices.len(), 4);
+        assert_eq!(object.surfaces[0].indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(object.vertices[0].normal, Vector3::z());
+    }
+
+    #[test]
+    fn splits_objects_and_materials() {
+        let model = parse_obj(
+            "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\no a\nusemtl red\nf 1 2 3\n\
+             usemtl blue\nf -3 -2 -1\no b\nf 1 2 3\n",
+        )
+        .unwrap();
+        assert_eq!(model.material_libraries, vec![PathBuf::from("scene.mtl")]);
+        let names: Vec<_> = model.objects.iter().map(|object| object.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        let materials: Vec<_> = model.objects[0]
+            .surfaces
+            .iter()
+            .map(|surface| surface.material.as_deref())
+            .collect();
+        assert_eq!(materials, vec![Some("red"), Some("blue")]);
+        // the material stays selected across objects
+        assert_eq!(model.objects[1].surfaces[0].material.as_deref(), Some("blue"));
+    }
+
+    #[test]
+    fn generates_normals_without_vn() {
+        let model = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        for vertex in &model.objects[0].vertices {
+            assert_eq!(vertex.normal, Vector3::z());
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        assert!(parse_obj("v 0 0 0\nf 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn reads_mtl_factors_and_textures() {
+        let materials = parse_mtl(
+            "newmtl glass\nKd 0.5 0.6 0.7\nd 0.25\nNs 0\nmap_Kd -s 1 1 1 glass.png\n\
+             newmtl metal\nPm 1\nPr 0.2\nNs 1000\n",
+            Path::new("textures"),
+        );
+        assert_eq!(materials[0].color_factors, [0.5, 0.6, 0.7, 0.25]);
+        assert_eq!(materials[0].pass, MaterialPassDefinition::Transparent);
+        assert_eq!(materials[0].metal_rough_factors[1], 1.0);
+        assert_eq!(
+            materials[0].textures.color,
+            Some(PathBuf::from("textures/glass.png"))
+        );
+        assert_eq!(materials[1].metal_rough_factors[..2], [1.0, 0.2]);
+        assert_eq!(materials[1].pass, MaterialPassDefinition::Opaque);
+    }
+}
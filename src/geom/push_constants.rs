@@ -1,10 +1,21 @@
+use std::mem::offset_of;
+
+use ash::vk::{PushConstantRange, ShaderStageFlags};
+use nalgebra::Matrix4;
+use thiserror::Error;
+
 
 #[repr(C)]
 #[derive(Default, Copy, Clone)]
 pub struct PushConstant<T: Sized + Default> {
     push_constant: T,
     device_address: u64,
-    _padding: [u8; 8], // <--- Add 8 bytes of explicit padding here
+    /// Free for the pipeline to use, the mesh pipelines read their bindless material index here.
+    index: u32,
+    _padding: [u8; 4],
+    /// Free for the pipeline to use like `index`, the skinned mesh pipelines read their joint
+    /// matrices through it.
+    second_address: u64,
 }
 
 impl<T: Sized> PushConstant<T>
@@ -19,6 +30,16 @@ where
         }
     }
 
+    pub fn with_index(mut self, index: u32) -> Self {
+        self.index = index;
+        self
+    }
+
+    pub fn with_second_address(mut self, second_address: u64) -> Self {
+        self.second_address = second_address;
+        self
+    }
+
     pub fn size(&self) -> usize {
         std::mem::size_of::<Self>()
     }
@@ -39,3 +60,132 @@ where
         unsafe { std::slice::from_raw_parts(data_ptr as *const u8, self.size()).to_vec() }
     }
 }
+
+// the push constant blocks of the mesh shaders declare these offsets, see
+// scene_data_mesh.vert and scene_data_mesh_skinned.vert
+const _: () = {
+    type MeshPushConstant = PushConstant<Matrix4<f32>>;
+    assert!(offset_of!(MeshPushConstant, device_address) == 64);
+    assert!(offset_of!(MeshPushConstant, index) == 72);
+    assert!(offset_of!(MeshPushConstant, second_address) == 80);
+    assert!(size_of::<MeshPushConstant>() == 88);
+};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PushConstantError {
+    #[error("The push constant range is empty")]
+    Empty,
+    #[error("Push constant offset {offset} and size {size} have to be multiples of 4")]
+    Misaligned { offset: u32, size: u32 },
+    #[error("{end} bytes of push constants exceed the {max} bytes the device supports")]
+    TooLarge { end: u32, max: u32 },
+    #[error("Push constants for {stages:?} are not limited to the pipeline stages {pipeline:?}")]
+    StageMismatch {
+        stages: ShaderStageFlags,
+        pipeline: ShaderStageFlags,
+    },
+}
+
+/// Push constant range of a pipeline layout, sized by the type that is pushed. `validate`
+/// checks it against the device and the stages of the pipeline before the layout is created,
+/// so a range the shaders can't see fails pipeline creation instead of reading garbage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushConstantLayout {
+    pub offset: u32,
+    pub size: u32,
+    pub stages: ShaderStageFlags,
+}
+
+impl PushConstantLayout {
+    pub fn new(size: u32, stages: ShaderStageFlags) -> Self {
+        Self {
+            offset: 0,
+            size,
+            stages,
+        }
+    }
+
+    /// Range holding a `T`, like a `PushConstant` or a `#[repr(C)]` struct of a pass.
+    pub fn of<T>(stages: ShaderStageFlags) -> Self {
+        Self::new(size_of::<T>() as u32, stages)
+    }
+
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// The range for a pipeline with the shader stages `pipeline_stages` on a device allowing
+    /// `max_size` bytes of push constants.
+    pub fn validate(
+        &self,
+        max_size: u32,
+        pipeline_stages: ShaderStageFlags,
+    ) -> Result<PushConstantRange, PushConstantError> {
+        if self.size == 0 || self.stages.is_empty() {
+            return Err(PushConstantError::Empty);
+        }
+        if self.offset % 4 != 0 || self.size % 4 != 0 {
+            return Err(PushConstantError::Misaligned {
+                offset: self.offset,
+                size: self.size,
+            });
+        }
+        let end = self.offset + self.size;
+        if end > max_size {
+            return Err(PushConstantError::TooLarge { end, max: max_size });
+        }
+        if !pipeline_stages.contains(self.stages) {
+            return Err(PushConstantError::StageMismatch {
+                stages: self.stages,
+                pipeline: pipeline_stages,
+            });
+        }
+        Ok(PushConstantRange::default()
+            .offset(self.offset)
+            .size(self.size)
+            .stage_flags(self.stages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The minimum every Vulkan device supports.
+    const MAX_SIZE: u32 = 128;
+
+    #[test]
+    fn sizes_ranges_by_their_type() {
+        let layout = PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(ShaderStageFlags::VERTEX);
+        let range = layout.validate(MAX_SIZE, ShaderStageFlags::ALL_GRAPHICS).unwrap();
+        assert_eq!((range.offset, range.size), (0, 88));
+        assert_eq!(range.stage_flags, ShaderStageFlags::VERTEX);
+    }
+
+    #[test]
+    fn rejects_ranges_the_device_cant_hold() {
+        let layout = PushConstantLayout::new(96, ShaderStageFlags::FRAGMENT).offset(64);
+        assert_eq!(
+            layout.validate(MAX_SIZE, ShaderStageFlags::FRAGMENT).unwrap_err(),
+            PushConstantError::TooLarge { end: 160, max: MAX_SIZE }
+        );
+        assert!(PushConstantLayout::new(6, ShaderStageFlags::FRAGMENT)
+            .validate(MAX_SIZE, ShaderStageFlags::FRAGMENT)
+            .is_err_and(|err| matches!(err, PushConstantError::Misaligned { .. })));
+        assert_eq!(
+            PushConstantLayout::of::<()>(ShaderStageFlags::FRAGMENT)
+                .validate(MAX_SIZE, ShaderStageFlags::FRAGMENT)
+                .unwrap_err(),
+            PushConstantError::Empty
+        );
+    }
+
+    #[test]
+    fn rejects_stages_outside_of_the_pipeline() {
+        let layout = PushConstantLayout::of::<u32>(ShaderStageFlags::COMPUTE);
+        assert!(layout
+            .validate(MAX_SIZE, ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT)
+            .is_err_and(|err| matches!(err, PushConstantError::StageMismatch { .. })));
+    }
+}
@@ -1,4 +1,18 @@
-use nalgebra::{Matrix4, Vector4};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::misc::{
+    animation_player::AnimationPlayer, camera::Camera, render_object::MeshNode, tween::Tweens,
+    DrawContext, Renderable,
+};
+
+use super::{
+    animation::AnimationPlayback,
+    assets::{GLTFMaterial, LoadedGLTF},
+    bounds::Ray,
+    vertex_3d::Vertex3D,
+};
 
 #[repr(C)]
 #[derive(Debug, Default, Clone)]
@@ -9,6 +23,8 @@ pub struct SceneData {
     pub ambient_color: Vector4<f32>,
     pub sunlight_direction: Vector4<f32>,
     pub sunlight_color: Vector4<f32>,
+    /// x is the bindless texture slot of the SSAO result, y is 1 when SSAO is enabled.
+    pub ambient_occlusion: Vector4<u32>,
 }
 
 impl SceneData {
@@ -27,6 +43,216 @@ impl SceneData {
             ambient_color,
             sunlight_direction,
             sunlight_color,
+            ambient_occlusion: Vector4::zeros(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SceneId(pub usize);
+
+/// Lighting state that travels with a scene and is copied into `SceneData` every frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneLights {
+    pub ambient_color: Vector4<f32>,
+    pub sunlight_direction: Vector4<f32>,
+    pub sunlight_color: Vector4<f32>,
+}
+
+impl Default for SceneLights {
+    fn default() -> Self {
+        Self {
+            ambient_color: Vector4::from_element(0.1),
+            sunlight_direction: Vector4::new(0.0, 1.0, 0.5, 1.0),
+            sunlight_color: Vector4::from_element(1.0),
+        }
+    }
+}
+
+/// A set of loaded nodes together with its own camera and lights. Nodes only reference GPU
+/// resources, so several scenes can stay loaded and be switched between without re-uploading.
+pub struct Scene {
+    pub name: String,
+    pub nodes: HashMap<String, Box<dyn Renderable>>,
+    pub camera: Camera,
+    pub lights: SceneLights,
+    pub instances: Vec<PrefabInstance>,
+    pub tweens: Tweens,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceId(pub usize);
+
+/// A mesh node of a prefab instance, named like the mesh it draws.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NodeHandle {
+    pub instance: InstanceId,
+    pub node: String,
+}
+
+/// A copy of a prefab's nodes placed in a scene, the mesh buffers stay shared with the prefab.
+pub struct PrefabInstance {
+    pub transform: Matrix4<f32>,
+    pub nodes: HashMap<String, MeshNode<Vertex3D>>,
+    /// Plays the node animations of the prefab on this instance's nodes.
+    pub animation_player: Option<AnimationPlayer>,
+}
+
+impl PrefabInstance {
+    /// Overrides the material of every mesh of this instance, `None` restores the prefab's.
+    pub fn set_material_override(&mut self, material: Option<Arc<GLTFMaterial>>) {
+        for node in self.nodes.values_mut() {
+            node.material_override = material.clone();
+        }
+    }
+
+    /// Plays the clip named `clip` on every skinned mesh of this instance that has it, from
+    /// `start` on the renderer's `animation_time`. Returns whether any mesh has the clip.
+    pub fn play_animation(&mut self, clip: &str, start: f32, looping: bool) -> bool {
+        let mut found = false;
+        for node in self.nodes.values_mut() {
+            let clip_index = node
+                .mesh_asset
+                .lock()
+                .unwrap()
+                .skin
+                .as_ref()
+                .and_then(|skin| skin.clip_index(clip));
+            if let Some(clip_index) = clip_index {
+                node.animation = Some(AnimationPlayback {
+                    clip: clip_index,
+                    start,
+                    speed: 1.0,
+                    looping,
+                });
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Puts the skinned meshes of this instance back into their rest pose.
+    pub fn stop_animation(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.animation = None;
         }
     }
 }
+
+impl Renderable for PrefabInstance {
+    fn draw(&self, top_matrix: Matrix4<f32>, draw_ctx: &mut DrawContext) {
+        for node in self.nodes.values() {
+            node.draw(top_matrix * self.transform, draw_ctx);
+        }
+    }
+
+    fn ray_cast(&self, top_matrix: Matrix4<f32>, ray: &Ray) -> Option<f32> {
+        self.nodes
+            .values()
+            .filter_map(|node| node.ray_cast(top_matrix * self.transform, ray))
+            .min_by(f32::total_cmp)
+    }
+}
+
+impl Scene {
+    pub fn new(name: impl Into<String>, nodes: HashMap<String, Box<dyn Renderable>>) -> Self {
+        Self {
+            name: name.into(),
+            nodes,
+            camera: Camera::new(Vector3::new(0.0, 0.0, 2.0)),
+            lights: SceneLights::default(),
+            instances: vec![],
+            tweens: Tweens::default(),
+        }
+    }
+
+    pub fn instantiate(&mut self, prefab: &LoadedGLTF, transform: Matrix4<f32>) -> InstanceId {
+        let nodes: HashMap<String, MeshNode<Vertex3D>> = prefab
+            .nodes
+            .iter()
+            .map(|(name, node)| (name.clone(), node.instantiate()))
+            .collect();
+        let animation_player = prefab.animation.clone().map(|animation| {
+            AnimationPlayer::new(animation, |name| nodes.get(name).map(|node| node.node.clone()))
+        });
+        self.instances.push(PrefabInstance {
+            transform,
+            nodes,
+            animation_player,
+        });
+        InstanceId(self.instances.len() - 1)
+    }
+
+    pub fn instance_mut(&mut self, instance_id: InstanceId) -> Option<&mut PrefabInstance> {
+        self.instances.get_mut(instance_id.0)
+    }
+
+    pub fn node(&self, handle: &NodeHandle) -> Option<&MeshNode<Vertex3D>> {
+        self.instances.get(handle.instance.0)?.nodes.get(&handle.node)
+    }
+
+    pub fn node_mut(&mut self, handle: &NodeHandle) -> Option<&mut MeshNode<Vertex3D>> {
+        self.instances.get_mut(handle.instance.0)?.nodes.get_mut(&handle.node)
+    }
+
+    /// The instance node whose bounds the world space `ray` hits first, with the distance to
+    /// them. The other nodes of the scene are not tested.
+    pub fn ray_cast(&self, ray: &Ray) -> Option<(NodeHandle, f32)> {
+        self.instances
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, instance)| {
+                instance.nodes.iter().filter_map(move |(name, node)| {
+                    let distance = node.ray_cast(instance.transform, ray)?;
+                    let handle = NodeHandle {
+                        instance: InstanceId(idx),
+                        node: name.clone(),
+                    };
+                    Some((handle, distance))
+                })
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    /// Points every instance material override at `replaced` to `material` instead.
+    pub fn replace_material(
+        &mut self,
+        replaced: &Arc<GLTFMaterial>,
+        material: &Arc<GLTFMaterial>,
+    ) {
+        self.instances
+            .iter_mut()
+            .flat_map(|instance| instance.nodes.values_mut())
+            .filter(|node| {
+                node.material_override
+                    .as_ref()
+                    .is_some_and(|current| Arc::ptr_eq(current, replaced))
+            })
+            .for_each(|node| node.material_override = Some(material.clone()));
+    }
+
+    /// Advances the running tweens, called by the renderer before the scene is updated.
+    pub fn update_tweens(&mut self, delta: Duration) {
+        self.tweens.update(delta, &mut self.camera, &mut self.instances);
+    }
+
+    /// Advances the animation players of the instances, called by the renderer before the
+    /// scene is updated.
+    pub fn update_animations(&mut self, delta: Duration) {
+        for player in self
+            .instances
+            .iter_mut()
+            .filter_map(|instance| instance.animation_player.as_mut())
+        {
+            player.update(delta.as_secs_f32());
+        }
+    }
+
+    /// Whether an animation player of an instance still moves its nodes.
+    pub fn is_animating(&self) -> bool {
+        self.instances
+            .iter()
+            .filter_map(|instance| instance.animation_player.as_ref())
+            .any(AnimationPlayer::is_playing)
+    }
+}
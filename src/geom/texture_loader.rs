@@ -0,0 +1,450 @@
+use std::path::Path;
+
+use ash::vk::{Extent3D, Format, ImageUsageFlags};
+use thiserror::Error;
+
+use crate::{
+    components::{
+        allocation_types::AllocatedImage,
+        command_buffers::VkCommandPool,
+        memory_allocator::MemoryAllocator,
+    },
+    misc::jobs::JobSystem,
+};
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+#[derive(Error, Debug)]
+pub enum TextureLoadError {
+    #[error("not a KTX2 container")]
+    InvalidIdentifier,
+    #[error("KTX2 container is truncated")]
+    Truncated,
+    #[error("KTX2 supercompression scheme {0} is not supported")]
+    Supercompressed(u32),
+    #[error("only 2D KTX2 textures without layers or faces are supported")]
+    UnsupportedLayout,
+    #[error("{0:?} is neither supported by the device nor decodable on the cpu")]
+    UnsupportedFormat(Format),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Contents of a KTX2 container, `levels` holds the raw data of every mip level, largest first.
+pub struct Ktx2Texture {
+    pub format: Format,
+    pub extent: Extent3D,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2Texture {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TextureLoadError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, TextureLoadError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(TextureLoadError::Truncated);
+        }
+        if bytes[..12] != KTX2_IDENTIFIER {
+            return Err(TextureLoadError::InvalidIdentifier);
+        }
+        let vk_format = read_u32(bytes, 12);
+        let (width, height, depth) = (
+            read_u32(bytes, 20),
+            read_u32(bytes, 24),
+            read_u32(bytes, 28),
+        );
+        let (layer_count, face_count) = (read_u32(bytes, 32), read_u32(bytes, 36));
+        let level_count = read_u32(bytes, 40).max(1) as usize;
+        let supercompression = read_u32(bytes, 44);
+        if supercompression != 0 {
+            return Err(TextureLoadError::Supercompressed(supercompression));
+        }
+        if depth > 1 || layer_count > 1 || face_count != 1 {
+            return Err(TextureLoadError::UnsupportedLayout);
+        }
+        // VK_FORMAT_UNDEFINED means the payload is Basis Universal, which needs transcoding
+        let format = Format::from_raw(vk_format as i32);
+        if format == Format::UNDEFINED {
+            return Err(TextureLoadError::UnsupportedFormat(format));
+        }
+
+        let level_index_end = HEADER_SIZE + level_count * LEVEL_INDEX_ENTRY_SIZE;
+        if bytes.len() < level_index_end {
+            return Err(TextureLoadError::Truncated);
+        }
+        let levels = (0..level_count)
+            .map(|level| {
+                let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+                let offset = read_u64(bytes, entry) as usize;
+                let length = read_u64(bytes, entry + 8) as usize;
+                // a corrupt index may point past the end of the address space
+                offset
+                    .checked_add(length)
+                    .and_then(|end| bytes.get(offset..end))
+                    .map(<[u8]>::to_vec)
+                    .ok_or(TextureLoadError::Truncated)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            format,
+            extent: Extent3D {
+                width,
+                height: height.max(1),
+                depth: 1,
+            },
+            levels,
+        })
+    }
+
+    fn level_extent(&self, level: usize) -> (u32, u32) {
+        (
+            (self.extent.width >> level).max(1),
+            (self.extent.height >> level).max(1),
+        )
+    }
+
+    /// Decodes every level to RGBA8, only BC1 to BC5 can be decoded.
+    pub fn decode_rgba8(&self) -> Result<Ktx2Texture, TextureLoadError> {
+        let decoded_format = decoded_format(self.format)
+            .ok_or(TextureLoadError::UnsupportedFormat(self.format))?;
+        let levels: Vec<_> = self.levels.iter().enumerate().collect();
+        let levels = JobSystem::global()
+            .map("bc_decode", &levels, |(level, data)| {
+                let (width, height) = self.level_extent(*level);
+                decode_bc(self.format, width, height, data)
+            })
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or(TextureLoadError::Truncated)?;
+        Ok(Ktx2Texture {
+            format: decoded_format,
+            extent: self.extent,
+            levels,
+        })
+    }
+}
+
+/// Loads a KTX2 texture and uploads its whole mip chain. Pre-compressed data (BCn, ASTC, ...)
+/// is uploaded as is when the device can sample the format, otherwise BC1 to BC5 are decoded to
+/// RGBA8 on the cpu first.
+pub fn load_ktx2<P: AsRef<Path>>(
+    path: P,
+    memory_allocator: &MemoryAllocator,
+    command_pool: &VkCommandPool,
+    usage: ImageUsageFlags,
+) -> Result<AllocatedImage, anyhow::Error> {
+    let mut texture = Ktx2Texture::open(path)?;
+    if !memory_allocator.supports_sampled_format(texture.format) {
+        texture = texture.decode_rgba8()?;
+    }
+    memory_allocator.create_image_with_mips(
+        &texture.levels,
+        texture.extent,
+        texture.format,
+        usage,
+        command_pool,
+    )
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn decoded_format(format: Format) -> Option<Format> {
+    match format {
+        Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK
+        | Format::BC2_SRGB_BLOCK
+        | Format::BC3_SRGB_BLOCK => Some(Format::R8G8B8A8_SRGB),
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC2_UNORM_BLOCK
+        | Format::BC3_UNORM_BLOCK
+        | Format::BC4_UNORM_BLOCK
+        | Format::BC5_UNORM_BLOCK => Some(Format::R8G8B8A8_UNORM),
+        _ => None,
+    }
+}
+
+/// Decodes a BC1-BC5 level into tightly packed RGBA8, `None` if `data` is too short.
+fn decode_bc(format: Format, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let block_size = match format {
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK
+        | Format::BC4_UNORM_BLOCK => 8,
+        _ => 16,
+    };
+    let (blocks_x, blocks_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+    if data.len() < blocks_x * blocks_y * block_size {
+        return None;
+    }
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    for (idx, block) in data.chunks_exact(block_size).take(blocks_x * blocks_y).enumerate() {
+        let texels: [[u8; 4]; 16] = match format {
+            Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGB_SRGB_BLOCK => {
+                decode_color_block(block, false).map(|[r, g, b, _]| [r, g, b, 255])
+            }
+            Format::BC1_RGBA_UNORM_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => {
+                decode_color_block(block, false)
+            }
+            Format::BC2_UNORM_BLOCK | Format::BC2_SRGB_BLOCK => {
+                let mut texels = decode_color_block(&block[8..], true);
+                for (i, texel) in texels.iter_mut().enumerate() {
+                    let alpha = (block[i / 2] >> ((i % 2) * 4)) & 0xF;
+                    texel[3] = alpha * 17;
+                }
+                texels
+            }
+            Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK => {
+                let alpha = decode_channel_block(&block[..8]);
+                let mut texels = decode_color_block(&block[8..], true);
+                for (texel, alpha) in texels.iter_mut().zip(alpha) {
+                    texel[3] = alpha;
+                }
+                texels
+            }
+            Format::BC4_UNORM_BLOCK => decode_channel_block(block).map(|r| [r, 0, 0, 255]),
+            Format::BC5_UNORM_BLOCK => {
+                let red = decode_channel_block(&block[..8]);
+                let green = decode_channel_block(&block[8..]);
+                std::array::from_fn(|i| [red[i], green[i], 0, 255])
+            }
+            _ => return None,
+        };
+
+        let (block_x, block_y) = ((idx % blocks_x) * 4, (idx / blocks_x) * 4);
+        for (i, texel) in texels.iter().enumerate() {
+            let (x, y) = (block_x + i % 4, block_y + i / 4);
+            if x < width as usize && y < height as usize {
+                let offset = (y * width as usize + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    Some(pixels)
+}
+
+fn unpack_565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1F) as u32;
+    let g = ((color >> 5) & 0x3F) as u32;
+    let b = (color & 0x1F) as u32;
+    [
+        ((r * 255 + 15) / 31) as u8,
+        ((g * 255 + 31) / 63) as u8,
+        ((b * 255 + 15) / 31) as u8,
+    ]
+}
+
+/// BC1 style color block, BC2 and BC3 always use the four color mode.
+fn decode_color_block(block: &[u8], four_color: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (unpack_565(c0), unpack_565(c1));
+    let mix = |a: u32, b: u32, wa: u32, wb: u32| ((a * wa + b * wb) / (wa + wb)) as u8;
+    let blend = |wa: u32, wb: u32| -> [u8; 4] {
+        [
+            mix(e0[0] as u32, e1[0] as u32, wa, wb),
+            mix(e0[1] as u32, e1[1] as u32, wa, wb),
+            mix(e0[2] as u32, e1[2] as u32, wa, wb),
+            255,
+        ]
+    };
+    let palette = if four_color || c0 > c1 {
+        [
+            [e0[0], e0[1], e0[2], 255],
+            [e1[0], e1[1], e1[2], 255],
+            blend(2, 1),
+            blend(1, 2),
+        ]
+    } else {
+        [
+            [e0[0], e0[1], e0[2], 255],
+            [e1[0], e1[1], e1[2], 255],
+            blend(1, 1),
+            [0, 0, 0, 0],
+        ]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| palette[((indices >> (i * 2)) & 0b11) as usize])
+}
+
+/// Single channel block shared by BC3 alpha, BC4 and BC5.
+fn decode_channel_block(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let palette: [u8; 8] = std::array::from_fn(|i| match i {
+        0 => a0 as u8,
+        1 => a1 as u8,
+        _ if a0 > a1 => ((a0 * (8 - i as u32) + a1 * (i as u32 - 1)) / 7) as u8,
+        6 => 0,
+        7 => 255,
+        _ => ((a0 * (6 - i as u32) + a1 * (i as u32 - 1)) / 5) as u8,
+    });
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[((indices >> (i * 3)) & 0b111) as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Extent3D, Format};
+
+    use super::{decode_bc, Ktx2Texture, TextureLoadError};
+
+    /// 4x4 BC1 texture with two levels, the smaller level is stored first like KTX2 writers do.
+    #[rustfmt::skip]
+    const BC1_KTX2: [u8; 144] = [
+        // identifier
+        0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+        131, 0, 0, 0, // vkFormat, BC1_RGB_UNORM_BLOCK
+        1, 0, 0, 0, // typeSize
+        4, 0, 0, 0, // pixelWidth
+        4, 0, 0, 0, // pixelHeight
+        0, 0, 0, 0, // pixelDepth
+        0, 0, 0, 0, // layerCount
+        1, 0, 0, 0, // faceCount
+        2, 0, 0, 0, // levelCount
+        0, 0, 0, 0, // supercompressionScheme
+        // data format descriptor, key/value data and supercompression global data
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        // level 0: byteOffset, byteLength, uncompressedByteLength
+        136, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0,
+        // level 1
+        128, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0,
+        // level 1 data, then level 0 data
+        1, 2, 3, 4, 5, 6, 7, 8,
+        11, 12, 13, 14, 15, 16, 17, 18,
+    ];
+
+    fn texel(pixels: &[u8], idx: usize) -> [u8; 4] {
+        pixels[idx * 4..idx * 4 + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn parses_the_header_and_level_index() {
+        let texture = Ktx2Texture::parse(&BC1_KTX2).unwrap();
+        assert_eq!(texture.format, Format::BC1_RGB_UNORM_BLOCK);
+        assert_eq!(
+            texture.extent,
+            Extent3D {
+                width: 4,
+                height: 4,
+                depth: 1
+            }
+        );
+        assert_eq!(
+            texture.levels,
+            vec![vec![11, 12, 13, 14, 15, 16, 17, 18], vec![1, 2, 3, 4, 5, 6, 7, 8]]
+        );
+    }
+
+    #[test]
+    fn rejects_broken_containers() {
+        let patched = |offset: usize, patch: &[u8]| {
+            let mut bytes = BC1_KTX2.to_vec();
+            bytes[offset..offset + patch.len()].copy_from_slice(patch);
+            Ktx2Texture::parse(&bytes)
+        };
+        assert!(matches!(patched(0, &[0]), Err(TextureLoadError::InvalidIdentifier)));
+        assert!(matches!(
+            Ktx2Texture::parse(&BC1_KTX2[..79]),
+            Err(TextureLoadError::Truncated)
+        ));
+        assert!(matches!(
+            Ktx2Texture::parse(&BC1_KTX2[..100]),
+            Err(TextureLoadError::Truncated)
+        ));
+        assert!(matches!(patched(44, &[2]), Err(TextureLoadError::Supercompressed(2))));
+        assert!(matches!(patched(36, &[6]), Err(TextureLoadError::UnsupportedLayout)));
+        assert!(matches!(
+            patched(12, &[0]),
+            Err(TextureLoadError::UnsupportedFormat(Format::UNDEFINED))
+        ));
+        // level 0 reaching past the end of the file, and past the end of the address space
+        assert!(matches!(patched(88, &[9]), Err(TextureLoadError::Truncated)));
+        assert!(matches!(patched(88, &[0xFF; 8]), Err(TextureLoadError::Truncated)));
+    }
+
+    #[test]
+    fn decodes_bc1_blocks() {
+        // red, blue and the two thirds between them
+        let pixels = decode_bc(
+            Format::BC1_RGB_UNORM_BLOCK,
+            4,
+            4,
+            &[0x00, 0xF8, 0x1F, 0x00, 0b11_10_01_00, 0, 0, 0],
+        )
+        .unwrap();
+        assert_eq!(texel(&pixels, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&pixels, 1), [0, 0, 255, 255]);
+        assert_eq!(texel(&pixels, 2), [170, 0, 85, 255]);
+        assert_eq!(texel(&pixels, 3), [85, 0, 170, 255]);
+        assert_eq!(texel(&pixels, 15), [255, 0, 0, 255]);
+        // the first endpoint not above the second selects the mode with a transparent texel
+        let pixels = decode_bc(
+            Format::BC1_RGBA_UNORM_BLOCK,
+            4,
+            4,
+            &[0x1F, 0x00, 0x00, 0xF8, 0b11_10_01_00, 0, 0, 0],
+        )
+        .unwrap();
+        assert_eq!(texel(&pixels, 2), [127, 0, 127, 255]);
+        assert_eq!(texel(&pixels, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_bc2_blocks() {
+        // explicit 4 bit alpha of 0 and 15 for the first two texels, then green texels
+        let block = [0xF0, 0, 0, 0, 0, 0, 0, 0, 0xE0, 0x07, 0, 0, 0, 0, 0, 0];
+        let pixels = decode_bc(Format::BC2_UNORM_BLOCK, 4, 4, &block).unwrap();
+        assert_eq!(texel(&pixels, 0), [0, 255, 0, 0]);
+        assert_eq!(texel(&pixels, 1), [0, 255, 0, 255]);
+        assert_eq!(texel(&pixels, 2), [0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_bc3_blocks() {
+        // alpha indices 0, 1 and 2 of the eight value mode, white texels
+        let block = [255, 0, 0b10_001_000, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0];
+        let pixels = decode_bc(Format::BC3_UNORM_BLOCK, 4, 4, &block).unwrap();
+        assert_eq!(texel(&pixels, 0), [255, 255, 255, 255]);
+        assert_eq!(texel(&pixels, 1), [255, 255, 255, 0]);
+        assert_eq!(texel(&pixels, 2), [255, 255, 255, 218]);
+        assert_eq!(texel(&pixels, 3), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_bc4_blocks() {
+        // indices 6, 7 and 2 of the six value mode, which has black and white at the end
+        let block = [0, 100, 0b10_111_110, 0, 0, 0, 0, 0];
+        let pixels = decode_bc(Format::BC4_UNORM_BLOCK, 4, 4, &block).unwrap();
+        assert_eq!(texel(&pixels, 0), [0, 0, 0, 255]);
+        assert_eq!(texel(&pixels, 1), [255, 0, 0, 255]);
+        assert_eq!(texel(&pixels, 2), [20, 0, 0, 255]);
+        assert_eq!(texel(&pixels, 3), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc5_blocks() {
+        let block = [200, 100, 0, 0, 0, 0, 0, 0, 10, 20, 0b001, 0, 0, 0, 0, 0];
+        let pixels = decode_bc(Format::BC5_UNORM_BLOCK, 4, 4, &block).unwrap();
+        assert_eq!(texel(&pixels, 0), [200, 20, 0, 255]);
+        assert_eq!(texel(&pixels, 1), [200, 10, 0, 255]);
+        // a block short of data is not decoded
+        assert_eq!(decode_bc(Format::BC5_UNORM_BLOCK, 4, 4, &block[..8]), None);
+    }
+}
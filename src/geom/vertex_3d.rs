@@ -1,11 +1,6 @@
-use std::mem::offset_of;
-
-use ash::vk::{
-    Format, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate,
-};
 use nalgebra::{Vector2, Vector3, Vector4};
 
-use super::VertexAttributes;
+use crate::macros::vertex_attributes::vertex_attributes;
 
 #[repr(C)]
 #[derive(Debug, Default, Clone)]
@@ -17,6 +12,11 @@ pub struct Vertex3D {
     pub normal: Vector3<f32>,
     _padding2: f32,
     pub color: Vector4<f32>,
+    /// xyz is the tangent, w the bitangent sign as in glTF.
+    pub tangent: Vector4<f32>,
+    /// Skin joints moving the vertex, weighted by `weights`. Unskinned vertices weigh nothing.
+    pub joints: Vector4<u32>,
+    pub weights: Vector4<f32>,
 }
 
 impl Vertex3D {
@@ -30,38 +30,70 @@ impl Vertex3D {
         }
     }
 
-}
+    pub fn tangent(mut self, tangent: Vector4<f32>) -> Self {
+        self.tangent = tangent;
+        self
+    }
 
-impl VertexAttributes for Vertex3D {
-    fn get_binding_description() -> Vec<ash::vk::VertexInputBindingDescription> {
-        vec![VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(size_of::<Vertex3D>() as u32)
-            .input_rate(VertexInputRate::VERTEX)]
+    pub fn skin(mut self, joints: Vector4<u32>, weights: Vector4<f32>) -> Self {
+        self.joints = joints;
+        self.weights = weights;
+        self
     }
 
-    fn get_attribute_description() -> Vec<ash::vk::VertexInputAttributeDescription> {
-        vec![
-            VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(0)
-                .format(Format::R32G32B32_SFLOAT)
-                .offset(offset_of!(Vertex3D, pos) as u32),
-            VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(1)
-                .format(Format::R32G32_SFLOAT)
-                .offset(offset_of!(Vertex3D, uv) as u32),
-            VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(2)
-                .format(Format::R32G32B32_SFLOAT)
-                .offset(offset_of!(Vertex3D, normal) as u32),
-            VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(2)
-                .format(Format::R32G32B32A32_SFLOAT)
-                .offset(offset_of!(Vertex3D, color) as u32),
-        ]
+    /// Computes per vertex tangents from the uv layout of the triangles in `indices`, for
+    /// meshes that don't ship their own.
+    pub fn generate_tangents(vertices: &mut [Vertex3D], indices: &[u32]) {
+        let mut tangents = vec![Vector3::<f32>::zeros(); vertices.len()];
+        let mut bitangents = vec![Vector3::<f32>::zeros(); vertices.len()];
+        for triangle in indices.chunks_exact(3) {
+            let [i0, i1, i2] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+            let (v0, v1, v2) = (&vertices[i0], &vertices[i1], &vertices[i2]);
+            let (edge1, edge2) = (v1.pos - v0.pos, v2.pos - v0.pos);
+            let (duv1, duv2) = (v1.uv - v0.uv, v2.uv - v0.uv);
+            let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+            let r = 1.0 / determinant;
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) * r;
+            let bitangent = (edge2 * duv1.x - edge1 * duv2.x) * r;
+            for i in [i0, i1, i2] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+        for (vertex, (tangent, bitangent)) in vertices
+            .iter_mut()
+            .zip(tangents.into_iter().zip(bitangents))
+        {
+            // Gram-Schmidt against the normal, falling back to any perpendicular axis for
+            // vertices without a usable uv mapping
+            let orthogonal = tangent - vertex.normal * vertex.normal.dot(&tangent);
+            let tangent = orthogonal.try_normalize(f32::EPSILON).unwrap_or_else(|| {
+                let axis = if vertex.normal.x.abs() < 0.9 {
+                    Vector3::x()
+                } else {
+                    Vector3::y()
+                };
+                vertex.normal.cross(&axis).normalize()
+            });
+            let handedness = if vertex.normal.cross(&tangent).dot(&bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = Vector4::new(tangent.x, tangent.y, tangent.z, handedness);
+        }
     }
 }
+
+vertex_attributes!(Vertex3D {
+    pos: R32G32B32_SFLOAT,
+    uv: R32G32_SFLOAT,
+    normal: R32G32B32_SFLOAT,
+    color: R32G32B32A32_SFLOAT,
+    tangent: R32G32B32A32_SFLOAT,
+    joints: R32G32B32A32_UINT,
+    weights: R32G32B32A32_SFLOAT,
+});
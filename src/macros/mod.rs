@@ -1 +1,2 @@
 pub mod new;
+pub mod vertex_attributes;
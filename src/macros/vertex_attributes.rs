@@ -0,0 +1,55 @@
+/// Implements `VertexAttributes` for a vertex read from a single per vertex binding. Every
+/// listed field becomes an attribute with the given `vk::Format`, locations are assigned in the
+/// order of the list so no two attributes can share one. Compilation fails if the size of a
+/// format doesn't match the size of its field.
+///
+/// ```ignore
+/// vertex_attributes!(DebugVertex {
+///     pos: R32G32B32_SFLOAT,
+///     color: R32G32B32A32_SFLOAT,
+/// });
+/// ```
+macro_rules! vertex_attributes {
+    ($vertex:ty { $($field:ident: $format:ident),+ $(,)? }) => {
+        const _: () = {
+            $(
+                assert!(
+                    $crate::geom::format_size(ash::vk::Format::$format)
+                        == $crate::geom::field_size(|vertex: &$vertex| &vertex.$field),
+                    concat!(
+                        "The format of ",
+                        stringify!($vertex),
+                        "::",
+                        stringify!($field),
+                        " doesn't match the size of the field"
+                    )
+                );
+            )+
+        };
+
+        impl $crate::geom::VertexAttributes for $vertex {
+            fn get_binding_description() -> Vec<ash::vk::VertexInputBindingDescription> {
+                vec![ash::vk::VertexInputBindingDescription::default()
+                    .binding(0)
+                    .stride(size_of::<$vertex>() as u32)
+                    .input_rate(ash::vk::VertexInputRate::VERTEX)]
+            }
+
+            fn get_attribute_description() -> Vec<ash::vk::VertexInputAttributeDescription> {
+                [$((ash::vk::Format::$format, std::mem::offset_of!($vertex, $field))),+]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(location, (format, offset))| {
+                        ash::vk::VertexInputAttributeDescription::default()
+                            .binding(0)
+                            .location(location as u32)
+                            .format(format)
+                            .offset(offset as u32)
+                    })
+                    .collect()
+            }
+        }
+    };
+}
+
+pub(crate) use vertex_attributes;
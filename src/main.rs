@@ -11,6 +11,8 @@ fn main() {
         .filter_level(LevelFilter::Debug)
         .try_init()
         .unwrap();
+    #[cfg(feature = "tracy")]
+    tracy_client::Client::start();
 
     debug!("START APP");
     event_loop.run_app(&mut app).unwrap();
@@ -0,0 +1,483 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, AttachmentLoadOp, BufferUsageFlags, ClearValue, CommandBuffer, CullModeFlags,
+    DescriptorSetLayoutCreateFlags, DescriptorType, DynamicState, Extent2D,
+    Extent3D, Format, FrontFace, ImageAspectFlags, ImageLayout, ImageUsageFlags, IndexType,
+    MemoryBarrier2, MemoryPropertyFlags, PipelineBindPoint, PipelineStageFlags2, PolygonMode,
+    PrimitiveTopology, Rect2D, RenderPassBeginInfo, SampleCountFlags, ShaderStageFlags,
+    SubpassContents, Viewport, WHOLE_SIZE,
+};
+use nalgebra::Matrix4;
+use vk_mem::MemoryUsage;
+
+use crate::{
+    components::{
+        allocation_types::{AllocatedImage, VkBuffer, VkFrameBuffer, IDENTIFIER},
+        deletion_queue::{DeletionQueue, FType},
+        descriptors::{
+            DescriptorAllocator, DescriptorLayoutBuilder, DescriptorSetDetails, DescriptorWriter,
+            PoolSizeRatio,
+        },
+        device::VkDevice,
+        image_util::image_transition,
+        memory_allocator::MemoryAllocator,
+        pipeline::{
+            additive_blending, create_multisampling_state, create_rasterizer_state,
+            ShaderInformation, VertexInput, VkPipeline,
+        },
+        queue::VkQueue,
+        render_pass::VkRenderPass,
+    },
+    geom::{gpu_scene_push_constant, push_constants::{PushConstant, PushConstantLayout}},
+    renderer::MAX_FRAMES,
+};
+
+use super::DrawContext;
+
+pub const HISTOGRAM_BINS: usize = 64;
+/// Log2 luminance range covered by the histogram, values outside land in the first/last bin.
+pub const HISTOGRAM_MIN_EV: f32 = -10.0;
+pub const HISTOGRAM_MAX_EV: f32 = 2.0;
+
+/// Layout of the storage buffer the analysis shaders accumulate into, must match
+/// `shaders/analysis_stats.glsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct AnalysisStats {
+    histogram: [u32; HISTOGRAM_BINS],
+    covered_pixels: u32,
+    shaded_fragments: u32,
+    max_overdraw: u32,
+    quad_lanes_used: u32,
+    quad_lanes_launched: u32,
+}
+
+/// Numbers read back from the analysis passes, `MAX_FRAMES` frames behind the current one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AnalysisResults {
+    /// Pixel counts per log2 luminance bucket between `HISTOGRAM_MIN_EV` and
+    /// `HISTOGRAM_MAX_EV`, empty if the histogram pass is disabled.
+    pub luminance_histogram: Vec<u32>,
+    /// Fragments shaded per covered pixel.
+    pub average_overdraw: f32,
+    pub max_overdraw: u32,
+    /// Share of the 2x2 quad lanes launched for the overdraw that covered a pixel, lower values
+    /// mean more helper lanes wasted on small or thin triangles.
+    pub quad_occupancy: f32,
+}
+
+impl AnalysisResults {
+    fn from_stats(stats: &AnalysisStats, histogram: bool) -> Self {
+        Self {
+            luminance_histogram: if histogram {
+                stats.histogram.to_vec()
+            } else {
+                vec![]
+            },
+            average_overdraw: stats.shaded_fragments as f32 / stats.covered_pixels.max(1) as f32,
+            max_overdraw: stats.max_overdraw,
+            quad_occupancy: stats.quad_lanes_used as f32
+                / stats.quad_lanes_launched.max(1) as f32,
+        }
+    }
+
+    /// Draws the histogram as a bar plot followed by the overdraw numbers.
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        if !self.luminance_histogram.is_empty() {
+            ui.label(format!(
+                "Luminance ({HISTOGRAM_MIN_EV} to {HISTOGRAM_MAX_EV} EV)"
+            ));
+            let (rect, _) =
+                ui.allocate_exact_size(egui::vec2(256.0, 96.0), egui::Sense::hover());
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(160));
+            let max = *self.luminance_histogram.iter().max().unwrap_or(&1).max(&1) as f32;
+            let bar_width = rect.width() / self.luminance_histogram.len() as f32;
+            for (bin, count) in self.luminance_histogram.iter().enumerate() {
+                let height = rect.height() * *count as f32 / max;
+                let x = rect.min.x + bin as f32 * bar_width;
+                painter.rect_filled(
+                    egui::Rect::from_min_max(
+                        egui::pos2(x, rect.max.y - height),
+                        egui::pos2(x + bar_width, rect.max.y),
+                    ),
+                    0.0,
+                    egui::Color32::LIGHT_GRAY,
+                );
+            }
+        }
+        ui.label(format!(
+            "Overdraw avg {:.2} / max {}",
+            self.average_overdraw, self.max_overdraw
+        ));
+        ui.label(format!("Quad occupancy {:.0}%", self.quad_occupancy * 100.0));
+    }
+}
+
+/// Optional GPU analysis passes run after the scene is drawn: a luminance histogram of the
+/// draw image, an additive overdraw count of the opaque surfaces and the quad occupancy
+/// estimated from it, plus a heatmap overlay of the overdraw.
+pub struct GpuAnalysis {
+    pub histogram_enabled: bool,
+    pub overdraw_enabled: bool,
+    /// Replaces the draw image with the overdraw heatmap, needs `overdraw_enabled`.
+    pub show_heatmap: bool,
+    overdraw_render_pass: Arc<VkRenderPass>,
+    overdraw_framebuffer: VkFrameBuffer,
+    overdraw_pipeline: VkPipeline,
+    histogram_pipeline: VkPipeline,
+    overdraw_stats_pipeline: VkPipeline,
+    heatmap_pipeline: VkPipeline,
+    descriptor_sets: Vec<DescriptorSetDetails>,
+    stats_buffers: Vec<(VkBuffer, *mut AnalysisStats)>,
+    results: Option<AnalysisResults>,
+}
+
+impl GpuAnalysis {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        extent: Extent2D,
+        draw_image: &AllocatedImage,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let overdraw_image = memory_allocator.create_image(
+            Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            Format::R16_SFLOAT,
+            None,
+            ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::STORAGE,
+            ImageAspectFlags::COLOR,
+            false,
+        )?;
+        deletion_queue.enqueue(FType::DROP(Box::new(overdraw_image.clone())));
+
+        let overdraw_render_pass = Arc::new(VkRenderPass::new(
+            device.clone(),
+            Format::R16_SFLOAT,
+            ImageLayout::UNDEFINED,
+            ImageLayout::GENERAL,
+            AttachmentLoadOp::CLEAR,
+            false,
+        )?);
+        let overdraw_framebuffer = VkFrameBuffer::create_framebuffer(
+            IDENTIFIER::ANALYSIS,
+            device.clone(),
+            overdraw_render_pass.clone(),
+            extent,
+            &[overdraw_image.image_details],
+        );
+        let overdraw_pipeline = VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[
+                ShaderInformation::vertex_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/overdraw.vert.spv".to_string(),
+                ),
+                ShaderInformation::fragment_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/overdraw.frag.spv".to_string(),
+                ),
+            ],
+            None,
+            &extent,
+            Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+                ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            )),
+            VertexInput::Pulling,
+            &[additive_blending()],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            overdraw_render_pass.clone(),
+            // every fragment counts, hidden ones included
+            false)?;
+
+        let layout = DescriptorLayoutBuilder::new()
+            .add_binding(0, DescriptorType::STORAGE_IMAGE, ShaderStageFlags::COMPUTE)
+            .add_binding(1, DescriptorType::STORAGE_IMAGE, ShaderStageFlags::COMPUTE)
+            .add_binding(2, DescriptorType::STORAGE_BUFFER, ShaderStageFlags::COMPUTE)
+            .build(
+                device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            );
+        let compute_pipeline = |shader: &str| {
+            VkPipeline::compute_pipelines(
+                device.clone(),
+                &[layout],
+                &format!("/Users/zapzap/Projects/piplup/shaders/{shader}.spv"),
+            )
+        };
+        let histogram_pipeline = compute_pipeline("luminance_histogram.comp")?;
+        let overdraw_stats_pipeline = compute_pipeline("overdraw_stats.comp")?;
+        let heatmap_pipeline = compute_pipeline("overdraw_heatmap.comp")?;
+
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            device.clone(),
+            MAX_FRAMES as u32,
+            vec![
+                PoolSizeRatio::new(DescriptorType::STORAGE_IMAGE, 2.0),
+                PoolSizeRatio::new(DescriptorType::STORAGE_BUFFER, 1.0),
+            ],
+        );
+        let mut writer = DescriptorWriter::new();
+        let mut descriptor_sets = vec![];
+        let mut stats_buffers = vec![];
+        for _ in 0..MAX_FRAMES {
+            let buffer = memory_allocator.allocate_single_buffer(
+                size_of::<AnalysisStats>() as u64,
+                queues,
+                BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::TRANSFER_DST,
+                MemoryUsage::Unknown,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped = buffer.mapped_data() as *mut AnalysisStats;
+            deletion_queue.enqueue(FType::DROP(Box::new(buffer.clone())));
+
+            let descriptor_set = descriptor_allocator.allocate(device.clone(), &[layout]);
+            writer.clear();
+            writer.write_image(
+                0,
+                draw_image.image_details.image_view,
+                None,
+                ImageLayout::GENERAL,
+                DescriptorType::STORAGE_IMAGE,
+            );
+            writer.write_image(
+                1,
+                overdraw_image.image_details.image_view,
+                None,
+                ImageLayout::GENERAL,
+                DescriptorType::STORAGE_IMAGE,
+            );
+            writer.write_buffer(
+                2,
+                &buffer,
+                size_of::<AnalysisStats>() as u64,
+                0,
+                DescriptorType::STORAGE_BUFFER,
+            );
+            writer.update_set(device.clone(), descriptor_set[0]);
+            descriptor_sets.push(descriptor_set);
+            stats_buffers.push((buffer, mapped));
+        }
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_set_layout(layout, None);
+            descriptor_allocator.destroy_pools(device);
+        })));
+
+        Ok(Self {
+            histogram_enabled: false,
+            overdraw_enabled: false,
+            show_heatmap: false,
+            overdraw_render_pass,
+            overdraw_framebuffer,
+            overdraw_pipeline,
+            histogram_pipeline,
+            overdraw_stats_pipeline,
+            heatmap_pipeline,
+            descriptor_sets,
+            stats_buffers,
+            results: None,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.histogram_enabled || self.overdraw_enabled
+    }
+
+    pub fn results(&self) -> Option<&AnalysisResults> {
+        self.results.as_ref()
+    }
+
+    /// Picks up what the passes recorded for `frame_idx` last time, must only be called once
+    /// the frame's fence has been waited on.
+    pub fn read_back(&mut self, frame_idx: usize) {
+        if !self.is_enabled() {
+            self.results = None;
+            return;
+        }
+        let stats = unsafe { self.stats_buffers[frame_idx].1.read() };
+        self.results = Some(AnalysisResults::from_stats(&stats, self.histogram_enabled));
+    }
+
+    /// Records the enabled passes, `draw_image` has to be in TRANSFER_SRC_OPTIMAL like it is
+    /// after the scene render pass and is left in that layout.
+    pub fn record(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        frame_idx: usize,
+        draw_ctx: &DrawContext,
+        view_proj: Matrix4<f32>,
+        viewports: &[Viewport],
+        render_area: &Rect2D,
+        draw_image: &AllocatedImage,
+        queue_family_index: u32,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+        let (stats_buffer, _) = &self.stats_buffers[frame_idx];
+        let extent = draw_image.extent;
+        unsafe {
+            device.cmd_fill_buffer(cmd, **stats_buffer, 0, WHOLE_SIZE, 0);
+            if self.overdraw_enabled {
+                self.record_overdraw(cmd, device, draw_ctx, view_proj, viewports, render_area);
+            }
+            image_transition(
+                device.clone(),
+                cmd,
+                queue_family_index,
+                draw_image.image_details.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ImageLayout::GENERAL,
+            );
+            Self::barrier(
+                cmd,
+                device,
+                PipelineStageFlags2::TRANSFER | PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                AccessFlags2::TRANSFER_WRITE | AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_READ | AccessFlags2::SHADER_WRITE,
+            );
+            let dispatch = |pipeline: &VkPipeline, texels_per_group: u32| {
+                device.cmd_bind_pipeline(cmd, PipelineBindPoint::COMPUTE, **pipeline);
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    PipelineBindPoint::COMPUTE,
+                    pipeline.pipeline_layout,
+                    0,
+                    &self.descriptor_sets[frame_idx],
+                    &[],
+                );
+                device.cmd_dispatch(
+                    cmd,
+                    extent.width.div_ceil(texels_per_group),
+                    extent.height.div_ceil(texels_per_group),
+                    1,
+                );
+            };
+            if self.histogram_enabled {
+                dispatch(&self.histogram_pipeline, 16);
+            }
+            if self.overdraw_enabled {
+                // one invocation per 2x2 quad in 8x8 groups
+                dispatch(&self.overdraw_stats_pipeline, 16);
+                if self.show_heatmap {
+                    // the histogram reads the draw image the heatmap overwrites
+                    Self::barrier(
+                        cmd,
+                        device,
+                        PipelineStageFlags2::COMPUTE_SHADER,
+                        AccessFlags2::SHADER_READ | AccessFlags2::SHADER_WRITE,
+                        PipelineStageFlags2::COMPUTE_SHADER,
+                        AccessFlags2::SHADER_READ | AccessFlags2::SHADER_WRITE,
+                    );
+                    dispatch(&self.heatmap_pipeline, 16);
+                }
+            }
+            Self::barrier(
+                cmd,
+                device,
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_WRITE,
+                PipelineStageFlags2::TRANSFER | PipelineStageFlags2::HOST,
+                AccessFlags2::TRANSFER_READ | AccessFlags2::HOST_READ,
+            );
+            image_transition(
+                device.clone(),
+                cmd,
+                queue_family_index,
+                draw_image.image_details.image,
+                ImageLayout::GENERAL,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+        }
+    }
+
+    unsafe fn record_overdraw(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        draw_ctx: &DrawContext,
+        view_proj: Matrix4<f32>,
+        viewports: &[Viewport],
+        render_area: &Rect2D,
+    ) {
+        let clear_value = [ClearValue {
+            color: ash::vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 0.0],
+            },
+        }];
+        unsafe {
+            device.cmd_begin_render_pass(
+                cmd,
+                &RenderPassBeginInfo::default()
+                    .render_pass(**self.overdraw_render_pass)
+                    .framebuffer(*self.overdraw_framebuffer)
+                    .render_area(*render_area)
+                    .clear_values(&clear_value),
+                SubpassContents::INLINE,
+            );
+            device.cmd_set_scissor(cmd, 0, &[*render_area]);
+            device.cmd_set_viewport(cmd, 0, viewports);
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, *self.overdraw_pipeline);
+            let mut bound_index_buffer = None;
+            for render_obj in &draw_ctx.opaque_surfaces {
+                if bound_index_buffer != Some(*render_obj.index_buffer) {
+                    device.cmd_bind_index_buffer(cmd, *render_obj.index_buffer, 0, IndexType::UINT32);
+                    bound_index_buffer = Some(*render_obj.index_buffer);
+                }
+                device.cmd_push_constants(
+                    cmd,
+                    self.overdraw_pipeline.pipeline_layout,
+                    ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                    0,
+                    &gpu_scene_push_constant(
+                        view_proj * render_obj.transform,
+                        render_obj.vertex_buffer_address,
+                        render_obj.material.material_index,
+                    ),
+                );
+                device.cmd_draw_indexed(
+                    cmd,
+                    render_obj.index_count,
+                    1,
+                    render_obj.first_index,
+                    0,
+                    0,
+                );
+            }
+            device.cmd_end_render_pass(cmd);
+        }
+    }
+
+    unsafe fn barrier(
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        src_stage: PipelineStageFlags2,
+        src_access: AccessFlags2,
+        dst_stage: PipelineStageFlags2,
+        dst_access: AccessFlags2,
+    ) {
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[MemoryBarrier2::default()
+                    .src_stage_mask(src_stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(dst_stage)
+                    .dst_access_mask(dst_access)],
+                &[],
+                &[],
+            );
+        }
+    }
+}
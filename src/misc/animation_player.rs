@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+
+use crate::geom::animation::NodeAnimation;
+
+use super::render_object::Node;
+
+/// Plays the clips of a `NodeAnimation` on the nodes of one prefab instance by writing their
+/// local transforms, one clip at a time.
+#[derive(Debug)]
+pub struct AnimationPlayer {
+    animation: Arc<NodeAnimation>,
+    /// Node of every target of `animation`, `None` for targets the instance doesn't have.
+    nodes: Vec<Option<Arc<Node>>>,
+    /// Local transforms of the nodes before the first clip played, restored by `stop`.
+    initial: Vec<Matrix4<f32>>,
+    clip: Option<usize>,
+    /// Seconds into the clip.
+    time: f32,
+    speed: f32,
+    looping: bool,
+    paused: bool,
+}
+
+impl AnimationPlayer {
+    /// `node` looks up the node of an instance by the name of its mesh.
+    pub fn new(animation: Arc<NodeAnimation>, node: impl Fn(&str) -> Option<Arc<Node>>) -> Self {
+        let nodes: Vec<Option<Arc<Node>>> =
+            animation.targets.iter().map(|target| node(target)).collect();
+        let initial = nodes
+            .iter()
+            .map(|node| {
+                node.as_ref()
+                    .map_or_else(Matrix4::identity, |node| node.local_transform())
+            })
+            .collect();
+        Self {
+            animation,
+            nodes,
+            initial,
+            clip: None,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            paused: false,
+        }
+    }
+
+    pub fn clip_names(&self) -> impl Iterator<Item = &str> {
+        self.animation.clips.iter().map(|clip| clip.name.as_str())
+    }
+
+    /// Starts the clip named `name` from its beginning, returns false if there is none.
+    pub fn play(&mut self, name: &str) -> bool {
+        let Some(clip) = self.animation.clips.iter().position(|clip| clip.name == name) else {
+            return false;
+        };
+        self.clip = Some(clip);
+        self.time = 0.0;
+        self.paused = false;
+        true
+    }
+
+    /// Stops the clip and puts the nodes back where they were before it played.
+    pub fn stop(&mut self) {
+        self.clip = None;
+        self.time = 0.0;
+        for (node, initial) in self.nodes.iter().zip(&self.initial) {
+            if let Some(node) = node {
+                node.set_local_transform(*initial);
+                node.refresh_transform(Matrix4::identity());
+            }
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Playback rate of the clip, negative rates play it backwards.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// A clip that doesn't loop holds its last pose once it ended.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    /// Seconds into the playing clip.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Whether the next `update` moves the nodes.
+    pub fn is_playing(&self) -> bool {
+        let Some(clip) = self.clip.map(|clip| &self.animation.clips[clip]) else {
+            return false;
+        };
+        let ended = if self.speed < 0.0 {
+            self.time <= 0.0
+        } else {
+            self.time >= clip.duration
+        };
+        !self.paused && self.speed != 0.0 && (self.looping || !ended)
+    }
+
+    /// Advances the clip by `delta` seconds and writes the sampled transforms into the local
+    /// transforms of the nodes, which are refreshed for the next draw.
+    pub fn update(&mut self, delta: f32) {
+        if !self.is_playing() {
+            return;
+        }
+        let Some(clip) = self.clip.map(|clip| &self.animation.clips[clip]) else {
+            return;
+        };
+        let time = self.time + delta * self.speed;
+        self.time = if self.looping && clip.duration > 0.0 {
+            time.rem_euclid(clip.duration)
+        } else {
+            time.clamp(0.0, clip.duration)
+        };
+        let pose = clip.sample(&self.animation.rest, self.time);
+        for (node, transform) in self.nodes.iter().zip(pose) {
+            if let Some(node) = node {
+                node.set_local_transform(transform.matrix());
+                // instance nodes have no parent, the instance transform is applied when drawn
+                node.refresh_transform(Matrix4::identity());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Weak, sync::Arc};
+
+    use nalgebra::{Matrix4, Vector3};
+
+    use crate::{
+        geom::animation::{
+            AnimationClip, Channel, ChannelValues, Interpolation, LocalTransform, NodeAnimation,
+        },
+        misc::render_object::Node,
+    };
+
+    use super::AnimationPlayer;
+
+    fn slide() -> (Arc<Node>, AnimationPlayer) {
+        let node = Arc::new(Node::new(
+            Weak::new(),
+            vec![],
+            Matrix4::identity(),
+            Matrix4::identity(),
+        ));
+        let animation = NodeAnimation {
+            targets: vec!["cube".into()],
+            rest: vec![LocalTransform::default()],
+            clips: vec![AnimationClip {
+                name: "slide".into(),
+                duration: 2.0,
+                channels: vec![Channel {
+                    target: 0,
+                    times: vec![0.0, 2.0],
+                    values: ChannelValues::Translations(vec![
+                        Vector3::zeros(),
+                        Vector3::new(2.0, 0.0, 0.0),
+                    ]),
+                    interpolation: Interpolation::Linear,
+                }],
+            }],
+        };
+        let target = node.clone();
+        let player = AnimationPlayer::new(Arc::new(animation), move |name| {
+            (name == "cube").then(|| target.clone())
+        });
+        (node, player)
+    }
+
+    fn x(node: &Node) -> f32 {
+        node.world_transform()[(0, 3)]
+    }
+
+    #[test]
+    fn moves_the_world_transform_of_its_nodes() {
+        let (node, mut player) = slide();
+        assert!(!player.play("jump"));
+        assert!(player.play("slide"));
+        player.set_speed(2.0);
+        player.update(0.25);
+        assert!((x(&node) - 0.5).abs() < 1e-6);
+        player.update(1.0);
+        // looping wraps around the end of the clip
+        assert!((x(&node) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn holds_the_last_pose_and_stop_restores_the_first() {
+        let (node, mut player) = slide();
+        player.set_looping(false);
+        player.play("slide");
+        player.update(5.0);
+        assert!((x(&node) - 2.0).abs() < 1e-6);
+        assert!(!player.is_playing());
+        player.stop();
+        assert_eq!(x(&node), 0.0);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    components::allocation_types::AllocatedImage,
+    geom::{
+        assets::{GLTFMaterial, MeshAsset},
+        vertex_3d::Vertex3D,
+    },
+};
+
+/// Where an asset came from, registering a second asset with the same key returns the handle
+/// of the first one instead.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AssetKey {
+    Path(PathBuf),
+    /// A named part of a file, like one mesh of a glTF file.
+    Part(PathBuf, String),
+    /// Assets registered by name, like the materials of the material library.
+    Name(String),
+    /// Hash of the contents of assets that don't come from a file.
+    Hash(u64),
+}
+
+impl AssetKey {
+    pub fn of_bytes(bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        AssetKey::Hash(hasher.finish())
+    }
+}
+
+/// Slot of an asset of type `T` in an `AssetRegistry`. The generation tells handles of a
+/// released asset apart from the one reusing its slot.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    asset: PhantomData<fn() -> T>,
+}
+
+pub type MeshHandle = Handle<Arc<Mutex<MeshAsset<Vertex3D>>>>;
+pub type TextureHandle = Handle<AllocatedImage>;
+pub type MaterialHandle = Handle<Arc<GLTFMaterial>>;
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle")
+            .field("index", &self.index)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    entry: Option<Entry<T>>,
+}
+
+struct Entry<T> {
+    asset: T,
+    key: Option<AssetKey>,
+    refs: u32,
+}
+
+/// Reference counted assets of one type, deduplicated by their `AssetKey`.
+pub struct Assets<T> {
+    slots: Vec<Slot<T>>,
+    free_slots: Vec<u32>,
+    by_key: HashMap<AssetKey, u32>,
+}
+
+impl<T> Default for Assets<T> {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            free_slots: vec![],
+            by_key: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Assets<T> {
+    /// Handle of the asset registered under `key`, with one more reference to it.
+    pub fn find(&mut self, key: &AssetKey) -> Option<Handle<T>> {
+        let index = *self.by_key.get(key)?;
+        let slot = &mut self.slots[index as usize];
+        slot.entry.as_mut()?.refs += 1;
+        Some(Handle {
+            index,
+            generation: slot.generation,
+            asset: PhantomData,
+        })
+    }
+
+    /// Adds `asset` with a single reference. An asset already registered under `key` is
+    /// returned instead, together with `asset` for the caller to destroy.
+    pub fn insert(&mut self, key: Option<AssetKey>, asset: T) -> (Handle<T>, Option<T>) {
+        if let Some(handle) = key.as_ref().and_then(|key| self.find(key)) {
+            return (handle, Some(asset));
+        }
+        let index = self.free_slots.pop().unwrap_or_else(|| {
+            self.slots.push(Slot {
+                generation: 0,
+                entry: None,
+            });
+            self.slots.len() as u32 - 1
+        });
+        if let Some(key) = &key {
+            self.by_key.insert(key.clone(), index);
+        }
+        let slot = &mut self.slots[index as usize];
+        slot.entry = Some(Entry { asset, key, refs: 1 });
+        let handle = Handle {
+            index,
+            generation: slot.generation,
+            asset: PhantomData,
+        };
+        (handle, None)
+    }
+
+    /// Looks up or creates the asset of `key`, `create` only runs if it isn't registered yet.
+    pub fn get_or_insert_with<E>(
+        &mut self,
+        key: AssetKey,
+        create: impl FnOnce() -> Result<T, E>,
+    ) -> Result<Handle<T>, E> {
+        match self.find(&key) {
+            Some(handle) => Ok(handle),
+            None => Ok(self.insert(Some(key), create()?).0),
+        }
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.entry(handle).map(|entry| &entry.asset)
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.entry.as_mut().map(|entry| &mut entry.asset)
+    }
+
+    /// References held on the asset of `handle`, 0 once it was released.
+    pub fn ref_count(&self, handle: Handle<T>) -> u32 {
+        self.entry(handle).map_or(0, |entry| entry.refs)
+    }
+
+    /// Adds a reference for another owner of `handle`, which has to `release` it as well.
+    pub fn retain(&mut self, handle: Handle<T>) -> bool {
+        let Some(slot) = self.slots.get_mut(handle.index as usize) else {
+            return false;
+        };
+        match slot.entry.as_mut() {
+            Some(entry) if slot.generation == handle.generation => {
+                entry.refs += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops a reference to the asset of `handle`. Returns the asset once the last one is
+    /// gone, the caller destroys it when the GPU doesn't use it anymore.
+    pub fn release(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let entry = slot.entry.as_mut()?;
+        entry.refs -= 1;
+        if entry.refs > 0 {
+            return None;
+        }
+        let entry = slot.entry.take().unwrap();
+        slot.generation += 1;
+        if let Some(key) = &entry.key {
+            self.by_key.remove(key);
+        }
+        self.free_slots.push(handle.index);
+        Some(entry.asset)
+    }
+
+    /// Removes every asset regardless of its references, for destroying them on shutdown.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.by_key.clear();
+        self.free_slots.clear();
+        self.slots.drain(..).filter_map(|slot| slot.entry.map(|entry| entry.asset))
+    }
+
+    /// Every asset that is still referenced.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots
+            .iter_mut()
+            .filter_map(|slot| slot.entry.as_mut().map(|entry| &mut entry.asset))
+    }
+
+    pub fn contains_key(&self, key: &AssetKey) -> bool {
+        self.by_key.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn entry(&self, handle: Handle<T>) -> Option<&Entry<T>> {
+        let slot = self.slots.get(handle.index as usize)?;
+        (slot.generation == handle.generation)
+            .then_some(slot.entry.as_ref())
+            .flatten()
+    }
+}
+
+/// Meshes, textures and materials shared between nodes through typed handles. Loading an
+/// asset that is already registered hands out another reference instead of a copy, assets are
+/// returned for destruction once their last reference is released.
+#[derive(Default)]
+pub struct AssetRegistry {
+    pub meshes: Assets<Arc<Mutex<MeshAsset<Vertex3D>>>>,
+    pub textures: Assets<AllocatedImage>,
+    pub materials: Assets<Arc<GLTFMaterial>>,
+    /// Names of the meshes loaded from every file, they are registered as `AssetKey::Part`.
+    mesh_files: HashMap<PathBuf, Vec<String>>,
+}
+
+impl AssetRegistry {
+    /// Handles of the meshes loaded from `path`, `None` unless every one of them is still
+    /// registered.
+    pub fn find_meshes(&mut self, path: &Path) -> Option<Vec<MeshHandle>> {
+        let keys: Vec<AssetKey> = self
+            .mesh_files
+            .get(path)?
+            .iter()
+            .map(|name| AssetKey::Part(path.to_path_buf(), name.clone()))
+            .collect();
+        if !keys.iter().all(|key| self.meshes.contains_key(key)) {
+            return None;
+        }
+        keys.iter().map(|key| self.meshes.find(key)).collect()
+    }
+
+    /// Registers the meshes loaded from `path` under their names, see `find_meshes`.
+    pub fn insert_meshes(
+        &mut self,
+        path: &Path,
+        meshes: Vec<Arc<Mutex<MeshAsset<Vertex3D>>>>,
+    ) -> Vec<MeshHandle> {
+        let mut names = vec![];
+        let handles = meshes
+            .into_iter()
+            .map(|mesh| {
+                let name = mesh.lock().unwrap().name.clone();
+                names.push(name.clone());
+                let key = AssetKey::Part(path.to_path_buf(), name);
+                self.meshes.insert(Some(key), mesh).0
+            })
+            .collect();
+        self.mesh_files.insert(path.to_path_buf(), names);
+        handles
+    }
+
+    /// Points the material handles resolving to `replaced` to `material` instead.
+    pub fn replace_material(&mut self, replaced: &Arc<GLTFMaterial>, material: &Arc<GLTFMaterial>) {
+        self.materials
+            .iter_mut()
+            .filter(|current| Arc::ptr_eq(current, replaced))
+            .for_each(|current| *current = material.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deduplicates_by_key() {
+        let mut assets = Assets::<&str>::default();
+        let key = AssetKey::Path("a.png".into());
+        let (first, duplicate) = assets.insert(Some(key.clone()), "a");
+        assert!(duplicate.is_none());
+        let (second, duplicate) = assets.insert(Some(key), "copy of a");
+        assert_eq!(first, second);
+        assert_eq!(duplicate, Some("copy of a"));
+        assert_eq!(assets.get(first), Some(&"a"));
+        assert_eq!(assets.ref_count(first), 2);
+    }
+
+    #[test]
+    fn releases_with_the_last_reference() {
+        let mut assets = Assets::<&str>::default();
+        let (handle, _) = assets.insert(Some(AssetKey::Name("a".into())), "a");
+        assert!(assets.retain(handle));
+        assert_eq!(assets.release(handle), None);
+        assert_eq!(assets.release(handle), Some("a"));
+        assert_eq!(assets.get(handle), None);
+        assert!(assets.find(&AssetKey::Name("a".into())).is_none());
+    }
+
+    #[test]
+    fn stale_handles_miss_reused_slots() {
+        let mut assets = Assets::<&str>::default();
+        let (released, _) = assets.insert(None, "a");
+        assets.release(released);
+        let (reused, _) = assets.insert(None, "b");
+        assert_eq!(assets.get(released), None);
+        assert!(!assets.retain(released));
+        assert_eq!(assets.get(reused), Some(&"b"));
+    }
+
+    #[test]
+    fn hashes_contents() {
+        assert_eq!(AssetKey::of_bytes(b"abc"), AssetKey::of_bytes(b"abc"));
+        assert_ne!(AssetKey::of_bytes(b"abc"), AssetKey::of_bytes(b"abd"));
+    }
+}
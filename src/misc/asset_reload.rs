@@ -0,0 +1,97 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Result;
+use ash::vk::{Rect2D, Viewport};
+use log::debug;
+
+use crate::geom::{
+    assets::{read_gltf_meshes, MeshAsset, MeshData},
+    vertex_3d::Vertex3D,
+};
+
+use super::{file_watcher::FileWatcher, jobs::JobSystem};
+
+/// Meshes read again from a changed glTF file, ready to be uploaded.
+pub struct ReloadedFile {
+    pub path: PathBuf,
+    pub meshes: Result<Vec<MeshData>>,
+}
+
+/// Re-reads watched glTF files on the job system when they change on disk. The renderer uploads
+/// the meshes of `finished` files and swaps them into the assets loaded from the file, which
+/// every node drawing them shares.
+pub struct AssetReloader {
+    watcher: FileWatcher,
+    /// Assets loaded from every watched file, reloaded meshes replace the one of the same name.
+    assets: HashMap<PathBuf, Vec<Arc<Mutex<MeshAsset<Vertex3D>>>>>,
+    /// Files read on a job right now, a change while reading is picked up by the next poll.
+    reading: HashSet<PathBuf>,
+    scissors: Rect2D,
+    viewport: Viewport,
+    sender: Sender<ReloadedFile>,
+    receiver: Receiver<ReloadedFile>,
+}
+
+impl AssetReloader {
+    pub fn new(scissors: Rect2D, viewport: Viewport) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            watcher: FileWatcher::default(),
+            assets: HashMap::new(),
+            reading: HashSet::new(),
+            scissors,
+            viewport,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Reloads `assets` whenever the file at `path` they were loaded from changes.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P, assets: &[Arc<Mutex<MeshAsset<Vertex3D>>>]) {
+        let path = path.as_ref().to_path_buf();
+        self.watcher.watch(&path);
+        self.assets.entry(path).or_default().extend_from_slice(assets);
+    }
+
+    /// Starts reading the watched files that changed since the last call on the job system.
+    pub fn poll_changes(&mut self) {
+        for path in self.watcher.changed_files() {
+            if !self.reading.insert(path.clone()) {
+                continue;
+            }
+            debug!("{} changed, reloading its meshes", path.display());
+            let sender = self.sender.clone();
+            let (scissors, viewport) = (self.scissors, self.viewport);
+            JobSystem::global().spawn("reload gltf", move || {
+                let meshes = read_gltf_meshes(&path, scissors, viewport);
+                // the reloader is gone when the renderer shut down meanwhile
+                let _ = sender.send(ReloadedFile { path, meshes });
+            });
+        }
+    }
+
+    /// Files whose reading finished since the last call.
+    pub fn finished(&mut self) -> Vec<ReloadedFile> {
+        let finished: Vec<ReloadedFile> = self.receiver.try_iter().collect();
+        for file in &finished {
+            self.reading.remove(&file.path);
+        }
+        finished
+    }
+
+    /// The asset named `name` that was loaded from `path`.
+    pub fn asset(&self, path: &Path, name: &str) -> Option<Arc<Mutex<MeshAsset<Vertex3D>>>> {
+        self.assets
+            .get(path)?
+            .iter()
+            .find(|asset| asset.lock().unwrap().name == name)
+            .cloned()
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{Extent3D, Format, ImageUsageFlags, Rect2D, Viewport};
+use image::RgbaImage;
+use log::debug;
+
+use crate::{
+    components::{
+        allocation_types::AllocatedImage,
+        buffer_arena::BufferArena,
+        deletion_queue::{DeletionQueue, FType},
+        upload_context::UploadContext,
+    },
+    geom::{
+        assets::{read_gltf_meshes, LoadedGLTF, MeshAsset, MeshData},
+        vertex_3d::Vertex3D,
+    },
+};
+
+use super::jobs::JobSystem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AssetHandle(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetStatus {
+    /// Read and decoded on a job, or waiting for its upload to be recorded.
+    Loading,
+    /// Uploaded, frames drawn from now on can use it.
+    Ready,
+    Failed(String),
+}
+
+/// An asset that finished loading.
+pub enum LoadedAsset {
+    Gltf(LoadedGLTF),
+    /// Sampled RGBA8 image, destroyed with the deletion queue of the renderer.
+    Texture(AllocatedImage),
+}
+
+/// CPU side result of a load job, uploaded on the thread that owns the `UploadContext`.
+enum Decoded {
+    Gltf(Vec<MeshData>),
+    Texture { image: RgbaImage, srgb: bool },
+}
+
+struct AssetEntry {
+    path: PathBuf,
+    status: AssetStatus,
+    asset: Option<LoadedAsset>,
+}
+
+/// Loads glTF files and textures without blocking the caller. Reading and decoding runs on the
+/// job system, the results are picked up by `upload_decoded` once per frame and streamed to the
+/// GPU through the transfer queue. Every load gets a handle whose `AssetStatus` tells when the
+/// asset can be used.
+pub struct AssetServer {
+    assets: Vec<AssetEntry>,
+    scissors: Rect2D,
+    viewport: Viewport,
+    sender: Sender<(AssetHandle, Result<Decoded>)>,
+    receiver: Receiver<(AssetHandle, Result<Decoded>)>,
+}
+
+impl AssetServer {
+    pub fn new(scissors: Rect2D, viewport: Viewport) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            assets: vec![],
+            scissors,
+            viewport,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Starts loading the meshes of the glTF file at `path` as a prefab. Its surfaces have no
+    /// material, instances draw them with the error material unless they override it.
+    pub fn load_gltf<P: AsRef<Path>>(&mut self, path: P) -> AssetHandle {
+        let (scissors, viewport) = (self.scissors, self.viewport);
+        self.spawn("load gltf", path.as_ref(), move |path| {
+            read_gltf_meshes(path, scissors, viewport).map(Decoded::Gltf)
+        })
+    }
+
+    /// Starts loading the PNG or JPEG file at `path` as a single mip texture. `srgb` picks
+    /// R8G8B8A8_SRGB for color textures, R8G8B8A8_UNORM is used for data textures.
+    pub fn load_texture<P: AsRef<Path>>(&mut self, path: P, srgb: bool) -> AssetHandle {
+        self.spawn("load texture", path.as_ref(), move |path| {
+            let image = image::open(path)?.to_rgba8();
+            Ok(Decoded::Texture { image, srgb })
+        })
+    }
+
+    pub fn status(&self, handle: AssetHandle) -> Option<&AssetStatus> {
+        self.assets.get(handle.0).map(|entry| &entry.status)
+    }
+
+    pub fn path(&self, handle: AssetHandle) -> Option<&Path> {
+        self.assets.get(handle.0).map(|entry| entry.path.as_path())
+    }
+
+    /// The prefab of a ready glTF load.
+    pub fn gltf(&self, handle: AssetHandle) -> Option<&LoadedGLTF> {
+        match self.assets.get(handle.0)?.asset.as_ref()? {
+            LoadedAsset::Gltf(gltf) => Some(gltf),
+            LoadedAsset::Texture(_) => None,
+        }
+    }
+
+    /// The image of a ready texture load.
+    pub fn texture(&self, handle: AssetHandle) -> Option<AllocatedImage> {
+        match self.assets.get(handle.0)?.asset.as_ref()? {
+            LoadedAsset::Texture(image) => Some(image.clone()),
+            LoadedAsset::Gltf(_) => None,
+        }
+    }
+
+    /// Whether any load has not finished yet.
+    pub fn is_loading(&self) -> bool {
+        self.assets
+            .iter()
+            .any(|entry| entry.status == AssetStatus::Loading)
+    }
+
+    /// Records the uploads of everything decoded since the last call into `upload_context`,
+    /// mesh buffers are suballocated from `mesh_arena`. Returns the handles whose status
+    /// changed, their assets can be drawn by frames waiting on the next
+    /// `UploadContext::submit`.
+    pub fn upload_decoded(
+        &mut self,
+        upload_context: &mut UploadContext,
+        mesh_arena: &BufferArena,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Vec<AssetHandle> {
+        let decoded: Vec<_> = self.receiver.try_iter().collect();
+        let mut finished = vec![];
+        for (handle, result) in decoded {
+            let entry = &mut self.assets[handle.0];
+            let uploaded = result.and_then(|decoded| {
+                upload(&entry.path, decoded, upload_context, mesh_arena, deletion_queue)
+            });
+            match uploaded {
+                Ok(asset) => {
+                    debug!("{} is ready", entry.path.display());
+                    entry.status = AssetStatus::Ready;
+                    entry.asset = Some(asset);
+                }
+                Err(err) => entry.status = AssetStatus::Failed(format!("{err:#}")),
+            }
+            finished.push(handle);
+        }
+        finished
+    }
+
+    fn spawn(
+        &mut self,
+        name: &'static str,
+        path: &Path,
+        load: impl FnOnce(&Path) -> Result<Decoded> + Send + 'static,
+    ) -> AssetHandle {
+        let handle = AssetHandle(self.assets.len());
+        let path = path.to_path_buf();
+        self.assets.push(AssetEntry {
+            path: path.clone(),
+            status: AssetStatus::Loading,
+            asset: None,
+        });
+        let sender = self.sender.clone();
+        JobSystem::global().spawn(name, move || {
+            // the server is gone when the renderer shut down meanwhile
+            let _ = sender.send((handle, load(&path)));
+        });
+        handle
+    }
+}
+
+fn upload(
+    path: &Path,
+    decoded: Decoded,
+    upload_context: &mut UploadContext,
+    mesh_arena: &BufferArena,
+    deletion_queue: &mut DeletionQueue,
+) -> Result<LoadedAsset> {
+    match decoded {
+        Decoded::Gltf(meshes) => {
+            if meshes.is_empty() {
+                return Err(anyhow!("There are no meshes in this file"));
+            }
+            let meshes = meshes
+                .into_iter()
+                .map(|data| {
+                    let asset =
+                        MeshAsset::<Vertex3D>::upload_streamed(data, upload_context, mesh_arena)?;
+                    Ok(Arc::new(Mutex::new(asset)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let name = path
+                .file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+            Ok(LoadedAsset::Gltf(LoadedGLTF::from_meshes(name, &meshes)))
+        }
+        Decoded::Texture { image, srgb } => {
+            let format = if srgb {
+                Format::R8G8B8A8_SRGB
+            } else {
+                Format::R8G8B8A8_UNORM
+            };
+            let extent = Extent3D {
+                width: image.width(),
+                height: image.height(),
+                depth: 1,
+            };
+            let texture = upload_context.upload_image(
+                image.as_raw(),
+                extent,
+                format,
+                ImageUsageFlags::empty(),
+            )?;
+            deletion_queue.enqueue(FType::DROP(Box::new(texture.clone())));
+            Ok(LoadedAsset::Texture(texture))
+        }
+    }
+}
@@ -0,0 +1,215 @@
+/// Hysteresis of the `AutoQuality` controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoQualitySettings {
+    /// GPU milliseconds a frame may take.
+    pub target_ms: f32,
+    /// Consecutive frames over `target_ms` before the next quality step is taken.
+    pub over_budget_frames: u32,
+    /// Fraction of `target_ms` a frame has to stay below to count as having headroom.
+    pub headroom: f32,
+    /// Consecutive frames with headroom before the last quality step is undone. Higher than
+    /// `over_budget_frames` so a restored effect does not immediately push the frame back over.
+    pub under_budget_frames: u32,
+}
+
+impl Default for AutoQualitySettings {
+    fn default() -> Self {
+        Self {
+            target_ms: 16.6,
+            over_budget_frames: 10,
+            headroom: 0.7,
+            under_budget_frames: 120,
+        }
+    }
+}
+
+impl AutoQualitySettings {
+    /// Edits the thresholds.
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Auto quality");
+        ui.horizontal(|ui| {
+            ui.label("Target");
+            ui.add(
+                egui::DragValue::new(&mut self.target_ms)
+                    .range(1.0..=100.0)
+                    .speed(0.1)
+                    .suffix(" ms"),
+            );
+            ui.label("lower after");
+            ui.add(egui::DragValue::new(&mut self.over_budget_frames).range(1..=600));
+            ui.label("frames");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Headroom");
+            ui.add(egui::Slider::new(&mut self.headroom, 0.1..=1.0));
+            ui.label("restore after");
+            ui.add(egui::DragValue::new(&mut self.under_budget_frames).range(1..=6000));
+            ui.label("frames");
+        });
+    }
+}
+
+/// Each `QualityStep::ReduceRenderScale` draws at this fraction of the render scale before.
+pub const RENDER_SCALE_STEP: f32 = 0.75;
+
+/// Ways to cut GPU time, in the order they are taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityStep {
+    DisableSsao,
+    /// Lowers the render scale by `RENDER_SCALE_STEP`, may be taken more than once.
+    ReduceRenderScale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityChange {
+    Lower(QualityStep),
+    Restore(QualityStep),
+}
+
+/// Lowers the quality one step at a time while the GPU frame time stays over budget and
+/// restores it in reverse order once there is headroom again.
+#[derive(Debug, Clone)]
+pub struct AutoQuality {
+    pub settings: AutoQualitySettings,
+    /// Steps available with the enabled effects.
+    steps: Vec<QualityStep>,
+    /// Number of `steps` currently taken.
+    level: usize,
+    over_budget: u32,
+    under_budget: u32,
+}
+
+impl AutoQuality {
+    pub fn new(settings: AutoQualitySettings, steps: Vec<QualityStep>) -> Self {
+        Self {
+            settings,
+            steps,
+            level: 0,
+            over_budget: 0,
+            under_budget: 0,
+        }
+    }
+
+    /// Steps currently taken, lowest quality last.
+    pub fn taken_steps(&self) -> &[QualityStep] {
+        &self.steps[..self.level]
+    }
+
+    /// Feeds the GPU time of a frame, returns the step to apply or undo if one is due.
+    pub fn update(&mut self, gpu_ms: f32) -> Option<QualityChange> {
+        if gpu_ms > self.settings.target_ms {
+            self.over_budget += 1;
+            self.under_budget = 0;
+        } else if gpu_ms < self.settings.target_ms * self.settings.headroom {
+            self.under_budget += 1;
+            self.over_budget = 0;
+        } else {
+            self.over_budget = 0;
+            self.under_budget = 0;
+        }
+
+        if self.over_budget >= self.settings.over_budget_frames && self.level < self.steps.len() {
+            self.over_budget = 0;
+            self.level += 1;
+            return Some(QualityChange::Lower(self.steps[self.level - 1]));
+        }
+        if self.under_budget >= self.settings.under_budget_frames && self.level > 0 {
+            self.under_budget = 0;
+            self.level -= 1;
+            return Some(QualityChange::Restore(self.steps[self.level]));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> AutoQuality {
+        AutoQuality::new(
+            AutoQualitySettings {
+                target_ms: 10.0,
+                over_budget_frames: 3,
+                headroom: 0.5,
+                under_budget_frames: 5,
+            },
+            vec![QualityStep::DisableSsao, QualityStep::ReduceRenderScale],
+        )
+    }
+
+    #[test]
+    fn lowers_after_consecutive_frames_over_budget() {
+        let mut auto_quality = controller();
+        assert_eq!(auto_quality.update(12.0), None);
+        assert_eq!(auto_quality.update(12.0), None);
+        assert_eq!(
+            auto_quality.update(12.0),
+            Some(QualityChange::Lower(QualityStep::DisableSsao))
+        );
+        assert_eq!(auto_quality.taken_steps(), &[QualityStep::DisableSsao]);
+    }
+
+    #[test]
+    fn frame_within_budget_resets_the_count() {
+        let mut auto_quality = controller();
+        auto_quality.update(12.0);
+        auto_quality.update(12.0);
+        assert_eq!(auto_quality.update(8.0), None);
+        assert_eq!(auto_quality.update(12.0), None);
+        assert!(auto_quality.taken_steps().is_empty());
+    }
+
+    #[test]
+    fn restores_only_with_headroom() {
+        let mut auto_quality = controller();
+        for _ in 0..3 {
+            auto_quality.update(12.0);
+        }
+        // Within budget but without headroom, stays lowered.
+        for _ in 0..10 {
+            assert_eq!(auto_quality.update(8.0), None);
+        }
+        for _ in 0..4 {
+            assert_eq!(auto_quality.update(4.0), None);
+        }
+        assert_eq!(
+            auto_quality.update(4.0),
+            Some(QualityChange::Restore(QualityStep::DisableSsao))
+        );
+        assert!(auto_quality.taken_steps().is_empty());
+    }
+
+    #[test]
+    fn steps_are_restored_in_reverse_order() {
+        let mut auto_quality = controller();
+        let lowered = (0..6)
+            .filter_map(|_| auto_quality.update(12.0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lowered,
+            [
+                QualityChange::Lower(QualityStep::DisableSsao),
+                QualityChange::Lower(QualityStep::ReduceRenderScale),
+            ]
+        );
+        let restored = (0..10)
+            .filter_map(|_| auto_quality.update(4.0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            restored,
+            [
+                QualityChange::Restore(QualityStep::ReduceRenderScale),
+                QualityChange::Restore(QualityStep::DisableSsao),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_change_without_steps_left() {
+        let mut auto_quality = AutoQuality::new(AutoQualitySettings::default(), vec![]);
+        for _ in 0..100 {
+            assert_eq!(auto_quality.update(100.0), None);
+        }
+    }
+}
@@ -1,17 +1,102 @@
-use nalgebra::{Matrix4, Quaternion, Unit, UnitQuaternion, Vector2, Vector3, Vector4};
+use nalgebra::{
+    Matrix4, Orthographic3, Perspective3, Quaternion, Unit, UnitQuaternion, Vector2, Vector3,
+    Vector4,
+};
 use winit::{
     event::{KeyEvent, WindowEvent},
     keyboard::KeyCode,
 };
 
+/// How the camera maps view space onto the screen, both keep the aspect ratio of the viewport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// `fov_y` is the vertical field of view in radians.
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// Parallel projection for 2D, isometric and editor views, `height` is the height of the
+    /// visible area in world units, centered on the camera.
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fov_y: 90.0_f32.to_radians(),
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+}
+
+impl Projection {
+    /// `aspect` is the width of the viewport divided by its height.
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fov_y, near, far } => {
+                Perspective3::new(aspect, fov_y, near, far).to_homogeneous()
+            }
+            Projection::Orthographic { height, near, far } => {
+                let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+                Orthographic3::new(-half_width, half_width, -half_height, half_height, near, far)
+                    .to_homogeneous()
+            }
+        }
+    }
+}
+
+/// Where a camera is and where it looks, what the view matrix is made from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub position: Vector3<f32>,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl CameraPose {
+    /// The pose `alpha` of the way from this one to `next`, for drawing between two updates.
+    pub fn lerp(&self, next: &CameraPose, alpha: f32) -> CameraPose {
+        CameraPose {
+            position: self.position.lerp(&next.position, alpha),
+            pitch: self.pitch + (next.pitch - self.pitch) * alpha,
+            yaw: self.yaw + (next.yaw - self.yaw) * alpha,
+        }
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        let translation = Matrix4::new_translation(&self.position);
+        let camera_rotation = self.rotation_matrix();
+        let matrix = translation * camera_rotation;
+        matrix.try_inverse().unwrap()
+    }
+
+    pub fn rotation_matrix(&self) -> Matrix4<f32> {
+        let pitch_rotation =
+            UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.pitch);
+        let yaw_rotation =
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.yaw);
+        let x = yaw_rotation * pitch_rotation;
+        x.to_homogeneous()
+    }
+}
+
 pub struct Camera {
     velocity: Vector3<f32>,
     position: Vector3<f32>,
     pitch: f32,
     yaw: f32,
+    projection: Projection,
 }
 
 impl Camera {
+    pub fn new(position: Vector3<f32>) -> Self {
+        Self {
+            velocity: Vector3::zeros(),
+            position,
+            pitch: 0.0,
+            yaw: 0.0,
+            projection: Projection::default(),
+        }
+    }
+
     pub fn update(&mut self) {
         let camera_rotation = self.get_rotation_matrix();
         let velocity = self.velocity * 0.5;
@@ -25,20 +110,41 @@ impl Camera {
             );
     }
 
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vector3<f32>) {
+        self.position = position;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Projection matrix for a viewport with the given width / height `aspect`.
+    pub fn get_projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        self.projection.matrix(aspect)
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.position,
+            pitch: self.pitch,
+            yaw: self.yaw,
+        }
+    }
+
     pub fn get_view_matrix(&self) -> Matrix4<f32> {
-        let translation = Matrix4::new_translation(&self.position);
-        let camera_rotation = self.get_rotation_matrix();
-        let matrix = camera_rotation * translation;
-        matrix.try_inverse().unwrap()
+        self.pose().view_matrix()
     }
 
     pub fn get_rotation_matrix(&self) -> Matrix4<f32> {
-        let pitch_rotation =
-            UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.pitch);
-        let yaw_rotation =
-            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.yaw);
-        let x = yaw_rotation * pitch_rotation;
-        x.to_homogeneous()
+        self.pose().rotation_matrix()
     }
 
     pub fn process_events(&mut self, window_event: WindowEvent) {
@@ -68,3 +174,97 @@ impl Camera {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use nalgebra::{Vector3, Vector4};
+
+    use super::{Camera, CameraPose, Projection};
+
+    fn assert_close(actual: Vector4<f32>, expected: Vector4<f32>) {
+        assert!(
+            (actual - expected).norm() < 1e-5,
+            "{actual:?} is not {expected:?}"
+        );
+    }
+
+    #[test]
+    fn view_matrix_moves_the_camera_to_the_origin() {
+        let camera = Camera::new(Vector3::new(1.0, 2.0, 3.0));
+        let view = camera.get_view_matrix();
+        assert_close(view * Vector4::new(1.0, 2.0, 3.0, 1.0), Vector4::w());
+        // without rotation the camera looks down -z
+        assert_close(
+            view * Vector4::new(1.0, 2.0, 2.0, 1.0),
+            Vector4::new(0.0, 0.0, -1.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn view_matrix_follows_the_yaw() {
+        let mut camera = Camera::new(Vector3::new(0.0, 0.0, 5.0));
+        camera.yaw = FRAC_PI_2;
+        let view = camera.get_view_matrix();
+        // a quarter turn around +y points the camera down -x
+        assert_close(
+            view * Vector4::new(-1.0, 0.0, 5.0, 1.0),
+            Vector4::new(0.0, 0.0, -1.0, 1.0),
+        );
+        assert_close(view * Vector4::new(0.0, 0.0, 5.0, 1.0), Vector4::w());
+    }
+
+    #[test]
+    fn poses_interpolate_between_updates() {
+        let previous = CameraPose {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            pitch: 0.0,
+            yaw: 1.0,
+        };
+        let next = CameraPose {
+            position: Vector3::new(2.0, 4.0, -6.0),
+            pitch: 0.5,
+            yaw: 2.0,
+        };
+        assert_eq!(previous.lerp(&next, 0.0), previous);
+        assert_eq!(previous.lerp(&next, 1.0), next);
+        let halfway = previous.lerp(&next, 0.5);
+        assert_eq!(halfway.position, Vector3::new(1.0, 2.0, -3.0));
+        assert_eq!((halfway.pitch, halfway.yaw), (0.25, 1.5));
+    }
+
+    fn ndc(projection: Projection, aspect: f32, x: f32, y: f32, z: f32) -> Vector4<f32> {
+        let clip = projection.matrix(aspect) * Vector4::new(x, y, z, 1.0);
+        clip / clip.w
+    }
+
+    #[test]
+    fn perspective_shrinks_with_distance() {
+        let projection = Projection::Perspective {
+            fov_y: FRAC_PI_2,
+            near: 0.1,
+            far: 100.0,
+        };
+        // a 90 degree field of view reaches the top of the screen at y = -z
+        assert!((ndc(projection, 1.0, 0.0, 1.0, -1.0).y - 1.0).abs() < 1e-5);
+        assert!((ndc(projection, 1.0, 0.0, 1.0, -2.0).y - 0.5).abs() < 1e-5);
+        assert!((ndc(projection, 2.0, 2.0, 0.0, -1.0).x - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_keeps_the_size_at_every_distance() {
+        let projection = Projection::Orthographic {
+            height: 10.0,
+            near: 0.1,
+            far: 100.0,
+        };
+        for z in [-1.0, -50.0] {
+            assert!((ndc(projection, 2.0, 0.0, 5.0, z).y - 1.0).abs() < 1e-5);
+            assert!((ndc(projection, 2.0, -10.0, 0.0, z).x + 1.0).abs() < 1e-5);
+        }
+        let near = ndc(projection, 1.0, 0.0, 0.0, -0.1).z;
+        let far = ndc(projection, 1.0, 0.0, 0.0, -100.0).z;
+        assert!((near + 1.0).abs() < 1e-5 && (far - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use anyhow::Result;
+use ash::vk::{
+    BufferUsageFlags, ColorComponentFlags, CommandBuffer, CullModeFlags, DynamicState, Extent2D,
+    FrontFace, PipelineBindPoint, PolygonMode, PrimitiveTopology, ShaderStageFlags,
+};
+use log::warn;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::{
+    components::{
+        deletion_queue::DeletionQueue,
+        device::VkDevice,
+        mapped_ring::MappedRing,
+        memory_allocator::MemoryAllocator,
+        pipeline::{
+            create_color_blending_attachment_state, create_multisampling_state,
+            create_rasterizer_state, ShaderInformation, VertexInput, VkPipeline,
+        },
+        queue::VkQueue,
+        render_pass::VkRenderPass,
+    },
+    geom::push_constants::{PushConstant, PushConstantLayout},
+    macros::vertex_attributes::vertex_attributes,
+    renderer::MAX_FRAMES,
+};
+
+use super::views::ViewTarget;
+
+const DEBUG_VERTEX_RING_CAPACITY: usize = 1 << 16;
+/// Vertices a frame may draw, so the frames in flight share the ring.
+const DEBUG_VERTICES_PER_FRAME: usize = DEBUG_VERTEX_RING_CAPACITY / MAX_FRAMES;
+const DEBUG_SPHERE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DebugVertex {
+    pub pos: Vector3<f32>,
+    _padding0: f32,
+    pub color: Vector4<f32>,
+}
+
+impl DebugVertex {
+    pub fn new(pos: Vector3<f32>, color: Vector4<f32>) -> Self {
+        Self {
+            pos,
+            color,
+            ..Default::default()
+        }
+    }
+}
+
+vertex_attributes!(DebugVertex {
+    pos: R32G32B32_SFLOAT,
+    color: R32G32B32A32_SFLOAT,
+});
+
+/// Immediate mode line drawing, everything pushed during a frame is drawn once with a
+/// LINE_LIST pipeline on top of the scene geometry and discarded afterwards.
+pub struct DebugDraw {
+    pipeline: VkPipeline,
+    /// Draws `overlay_vertices` without depth testing, after the other lines.
+    overlay_pipeline: VkPipeline,
+    vertex_ring: MappedRing<DebugVertex>,
+    vertices: Vec<DebugVertex>,
+    overlay_vertices: Vec<DebugVertex>,
+    /// Set once lines were dropped for not fitting into a frame, to warn only once.
+    warned_overflow: bool,
+}
+
+fn line_pipeline(
+    device: Arc<VkDevice>,
+    extent: &Extent2D,
+    render_pass: Arc<VkRenderPass>,
+    depth_test: bool,
+) -> Result<VkPipeline> {
+    Ok(VkPipeline::create_new_pipeline(
+        device,
+        &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+        PrimitiveTopology::LINE_LIST,
+        &[
+            ShaderInformation::vertex_2d_information(
+                "/Users/zapzap/Projects/piplup/shaders/debug_line.vert.spv".to_string(),
+            ),
+            ShaderInformation::fragment_2d_information(
+                "/Users/zapzap/Projects/piplup/shaders/debug_line.frag.spv".to_string(),
+            ),
+        ],
+        None,
+        extent,
+        Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+            ShaderStageFlags::VERTEX,
+        )),
+        VertexInput::Pulling,
+        &[create_color_blending_attachment_state(
+            ColorComponentFlags::R
+                | ColorComponentFlags::G
+                | ColorComponentFlags::B
+                | ColorComponentFlags::A,
+            false,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )],
+        create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+        create_multisampling_state(false, render_pass.samples(), 1.0, false, false),
+        render_pass,
+        depth_test)?)
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        extent: &Extent2D,
+        render_pass: Arc<VkRenderPass>,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let pipeline = line_pipeline(device.clone(), extent, render_pass.clone(), true)?;
+        let overlay_pipeline = line_pipeline(device, extent, render_pass, false)?;
+        let vertex_ring = MappedRing::new(
+            memory_allocator,
+            queues,
+            DEBUG_VERTEX_RING_CAPACITY,
+            BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            deletion_queue,
+        )?;
+        Ok(Self {
+            pipeline,
+            overlay_pipeline,
+            vertex_ring,
+            vertices: vec![],
+            overlay_vertices: vec![],
+            warned_overflow: false,
+        })
+    }
+
+    pub fn line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: Vector4<f32>) {
+        self.vertices.push(DebugVertex::new(from, color));
+        self.vertices.push(DebugVertex::new(to, color));
+    }
+
+    /// A line that stays visible behind the scene geometry, e.g. for manipulation handles.
+    pub fn overlay_line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: Vector4<f32>) {
+        self.overlay_vertices.push(DebugVertex::new(from, color));
+        self.overlay_vertices.push(DebugVertex::new(to, color));
+    }
+
+    pub fn aabb(&mut self, min: Vector3<f32>, max: Vector3<f32>, color: Vector4<f32>) {
+        let corner = |x: bool, y: bool, z: bool| {
+            Vector3::new(
+                if x { max.x } else { min.x },
+                if y { max.y } else { min.y },
+                if z { max.z } else { min.z },
+            )
+        };
+        for a in [false, true] {
+            for b in [false, true] {
+                self.line(corner(false, a, b), corner(true, a, b), color);
+                self.line(corner(a, false, b), corner(a, true, b), color);
+                self.line(corner(a, b, false), corner(a, b, true), color);
+            }
+        }
+    }
+
+    /// Approximates the sphere with one circle around each axis.
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, color: Vector4<f32>) {
+        let axes = [
+            (Vector3::x(), Vector3::y()),
+            (Vector3::y(), Vector3::z()),
+            (Vector3::z(), Vector3::x()),
+        ];
+        for (u, v) in axes {
+            let point = |segment: usize| {
+                let angle = TAU * segment as f32 / DEBUG_SPHERE_SEGMENTS as f32;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for segment in 0..DEBUG_SPHERE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), color);
+            }
+        }
+    }
+
+    /// Outlines the volume `view_proj` maps onto clip space, near and far plane included.
+    /// Does nothing for matrices that can't be inverted.
+    pub fn frustum(&mut self, view_proj: Matrix4<f32>, color: Vector4<f32>) {
+        let Some(inverse) = view_proj.try_inverse() else {
+            return;
+        };
+        let corner = |x: bool, y: bool, z: bool| {
+            let sign = |positive: bool| if positive { 1.0 } else { -1.0 };
+            let world = inverse * Vector4::new(sign(x), sign(y), sign(z), 1.0);
+            world.xyz() / world.w
+        };
+        for a in [false, true] {
+            for b in [false, true] {
+                self.line(corner(false, a, b), corner(true, a, b), color);
+                self.line(corner(a, false, b), corner(a, true, b), color);
+                self.line(corner(a, b, false), corner(a, b, true), color);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty() && self.overlay_vertices.is_empty()
+    }
+
+    /// Uploads the accumulated lines and records their draw into each of `views` into `cmd`,
+    /// which has to be inside the scene render pass. Called once for every frame, see
+    /// `MappedRing::begin_frame`. Lines beyond `DEBUG_VERTICES_PER_FRAME` vertices, the
+    /// overlay ones last, are dropped with a warning.
+    pub fn record(
+        &mut self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        frame_idx: usize,
+        views: &[ViewTarget],
+    ) {
+        self.vertex_ring.begin_frame(frame_idx);
+        let mut budget = DEBUG_VERTICES_PER_FRAME;
+        for (pipeline, vertices) in [
+            (&self.pipeline, &self.vertices),
+            (&self.overlay_pipeline, &self.overlay_vertices),
+        ] {
+            // whole lines only
+            let len = vertices.len().min(budget & !1);
+            budget -= len;
+            if len < vertices.len() && !self.warned_overflow {
+                warn!(
+                    "Dropping {} debug line vertices, a frame draws at most \
+                     {DEBUG_VERTICES_PER_FRAME}",
+                    vertices.len() - len
+                );
+                self.warned_overflow = true;
+            }
+            if len == 0 {
+                continue;
+            }
+            let first_vertex = match self.vertex_ring.write(&vertices[..len]) {
+                Ok(first_vertex) => first_vertex,
+                Err(err) => {
+                    warn!("Dropping {len} debug line vertices: {err}");
+                    continue;
+                }
+            };
+            unsafe {
+                device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, **pipeline);
+                for view in views {
+                    device.cmd_set_scissor(cmd, 0, &[view.scissor]);
+                    device.cmd_set_viewport(cmd, 0, &[view.viewport]);
+                    device.cmd_push_constants(
+                        cmd,
+                        pipeline.pipeline_layout,
+                        ShaderStageFlags::VERTEX,
+                        0,
+                        &PushConstant::new(
+                            view.scene_data.view_proj,
+                            self.vertex_ring.buffer().address,
+                        )
+                        .raw_data(),
+                    );
+                    device.cmd_draw(cmd, len as u32, 1, first_vertex, 0);
+                }
+            }
+        }
+        self.vertices.clear();
+        self.overlay_vertices.clear();
+    }
+}
@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    AttachmentLoadOp, ClearColorValue, ClearDepthStencilValue, ClearValue, ColorComponentFlags,
+    CommandBuffer, CompareOp, CullModeFlags, DescriptorSet, DescriptorSetLayout,
+    DescriptorSetLayoutCreateFlags, DescriptorType, DynamicState, Extent2D, Extent3D, Filter,
+    Format, FrontFace, ImageAspectFlags, ImageLayout, ImageUsageFlags, IndexType,
+    PipelineBindPoint, PipelineColorBlendAttachmentState, PolygonMode, PrimitiveTopology, Rect2D,
+    RenderPassBeginInfo, SampleCountFlags, ShaderStageFlags, SubpassContents, Viewport,
+};
+use nalgebra::Matrix4;
+
+use crate::{
+    components::{
+        allocation_types::{AllocatedImage, VkFrameBuffer, IDENTIFIER},
+        deletion_queue::{DeletionQueue, FType},
+        descriptors::{
+            DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio,
+        },
+        device::VkDevice,
+        frame_data::SceneDataBinding,
+        memory_allocator::MemoryAllocator,
+        pipeline::{
+            create_color_blending_attachment_state, create_multisampling_state,
+            create_rasterizer_state, ShaderInformation, VertexInput, VkPipeline,
+        },
+        render_pass::VkRenderPass,
+        sampler::{SamplerCache, VkSampler},
+    },
+    geom::{gpu_scene_push_constant, push_constants::{PushConstant, PushConstantLayout}},
+};
+
+use super::{material::DEFAULT_VERTEX_SHADER, views::ViewTarget, DrawContext, DrawStats};
+
+/// Formats of the G-buffer color targets, in the order of the outputs of `shaders/gbuffer.frag`:
+/// albedo (alpha marks covered pixels), world space normal, metallic/roughness/occlusion and
+/// emissive.
+const GBUFFER_FORMATS: [Format; 4] = [
+    Format::R8G8B8A8_UNORM,
+    Format::R16G16B16A16_SFLOAT,
+    Format::R8G8B8A8_UNORM,
+    Format::R16G16B16A16_SFLOAT,
+];
+
+/// Deferred path of the scene pass. Surfaces whose material uses the default PBR shader are
+/// drawn into a G-buffer first, then shaded once per pixel by a fullscreen lighting pass at the
+/// start of the scene pass. Everything else (transparent and custom shader materials) is drawn
+/// forward on top, against the depth the G-buffer pass wrote.
+pub struct DeferredShading {
+    render_pass: Arc<VkRenderPass>,
+    framebuffer: VkFrameBuffer,
+    gbuffer_pipeline: VkPipeline,
+    lighting_pipeline: VkPipeline,
+    /// Bound in `gbuffer_set`, lives as long as it does.
+    _sampler: VkSampler,
+    gbuffer_set: DescriptorSet,
+}
+
+impl DeferredShading {
+    /// `scene_render_pass` is the render pass the lighting pass is recorded in, it has to load
+    /// the depth `depth_image` holds after the G-buffer pass.
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: &MemoryAllocator,
+        extent: Extent2D,
+        depth_image: &AllocatedImage,
+        after_depth_prepass: bool,
+        scene_render_pass: Arc<VkRenderPass>,
+        scene_data_layout: DescriptorSetLayout,
+        bindless_layout: DescriptorSetLayout,
+        samplers: &SamplerCache,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let targets = GBUFFER_FORMATS
+            .iter()
+            .map(|format| {
+                let image = memory_allocator.create_image(
+                    Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                    *format,
+                    None,
+                    ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED,
+                    ImageAspectFlags::COLOR,
+                    false,
+                )?;
+                deletion_queue.enqueue(FType::DROP(Box::new(image.clone())));
+                Ok(image)
+            })
+            .collect::<Result<Vec<AllocatedImage>>>()?;
+
+        let render_pass = Arc::new(VkRenderPass::gbuffer(
+            device.clone(),
+            &GBUFFER_FORMATS,
+            if after_depth_prepass {
+                AttachmentLoadOp::LOAD
+            } else {
+                AttachmentLoadOp::CLEAR
+            },
+        )?);
+        let mut attachments: Vec<_> = targets.iter().map(|target| target.image_details).collect();
+        attachments.push(depth_image.image_details);
+        let framebuffer = VkFrameBuffer::create_framebuffer(
+            IDENTIFIER::GBUFFER,
+            device.clone(),
+            render_pass.clone(),
+            extent,
+            &attachments,
+        );
+
+        let mut layout_builder = DescriptorLayoutBuilder::new();
+        for binding in 0..GBUFFER_FORMATS.len() as u32 {
+            layout_builder.add_binding(
+                binding,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                ShaderStageFlags::FRAGMENT,
+            );
+        }
+        let gbuffer_layout = layout_builder.build(
+            device.clone(),
+            ShaderStageFlags::empty(),
+            DescriptorSetLayoutCreateFlags::empty(),
+        );
+
+        let gbuffer_pipeline = VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[
+                DynamicState::SCISSOR,
+                DynamicState::VIEWPORT,
+                DynamicState::DEPTH_COMPARE_OP,
+            ],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[
+                ShaderInformation::vertex_2d_information(DEFAULT_VERTEX_SHADER.to_string()),
+                ShaderInformation::fragment_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/gbuffer.frag.spv".to_string(),
+                ),
+            ],
+            Some(&[scene_data_layout, bindless_layout]),
+            &extent,
+            Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+                ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            )),
+            VertexInput::Pulling,
+            &[opaque_attachment(); GBUFFER_FORMATS.len()],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            render_pass.clone(),
+            true)?;
+        let lighting_pipeline = VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[
+                ShaderInformation::vertex_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/fullscreen.vert.spv".to_string(),
+                ),
+                ShaderInformation::fragment_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/deferred_lighting.frag.spv".to_string(),
+                ),
+            ],
+            Some(&[scene_data_layout, bindless_layout, gbuffer_layout]),
+            &extent,
+            None,
+            VertexInput::Pulling,
+            &[opaque_attachment()],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, scene_render_pass.samples(), 1.0, false, false),
+            scene_render_pass,
+            // the G-buffer pass already resolved the visibility
+            false)?;
+
+        // the lighting shader only uses texelFetch, the sampler never filters
+        let sampler = samplers.with_filter(Filter::NEAREST, Filter::NEAREST);
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            device.clone(),
+            1,
+            vec![PoolSizeRatio::new(
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                GBUFFER_FORMATS.len() as f32,
+            )],
+        );
+        let gbuffer_set = descriptor_allocator.allocate(device.clone(), &[gbuffer_layout])[0];
+        let mut writer = DescriptorWriter::new();
+        for (binding, target) in targets.iter().enumerate() {
+            writer.write_image(
+                binding as u32,
+                target.image_details.image_view,
+                Some(sampler.clone()),
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+        }
+        writer.update_set(device.clone(), gbuffer_set);
+
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_set_layout(gbuffer_layout, None);
+            descriptor_allocator.destroy_pools(device);
+        })));
+
+        Ok(Self {
+            render_pass,
+            framebuffer,
+            gbuffer_pipeline,
+            lighting_pipeline,
+            _sampler: sampler,
+            gbuffer_set,
+        })
+    }
+
+    /// Draws the surfaces with a deferred material into the G-buffer, has to be recorded before
+    /// the scene pass. Leaves the depth image in DEPTH_ATTACHMENT_OPTIMAL.
+    pub fn record_gbuffer(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        draw_ctx: &DrawContext,
+        views: &[ViewTarget],
+        scene_data: &[SceneDataBinding],
+        bindless_set: DescriptorSet,
+        render_area: &Rect2D,
+        after_depth_prepass: bool,
+    ) -> DrawStats {
+        // albedo alpha stays 0 where nothing is drawn, the lighting pass skips those pixels
+        let mut clear_values = vec![
+            ClearValue {
+                color: ClearColorValue { float32: [0.0; 4] },
+            };
+            GBUFFER_FORMATS.len()
+        ];
+        clear_values.push(ClearValue {
+            depth_stencil: ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        });
+        unsafe {
+            device.cmd_begin_render_pass(
+                cmd,
+                &RenderPassBeginInfo::default()
+                    .render_pass(**self.render_pass)
+                    .framebuffer(*self.framebuffer)
+                    .render_area(*render_area)
+                    .clear_values(&clear_values),
+                SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, *self.gbuffer_pipeline);
+            device.cmd_set_depth_compare_op(
+                cmd,
+                if after_depth_prepass {
+                    CompareOp::EQUAL
+                } else {
+                    CompareOp::LESS_OR_EQUAL
+                },
+            );
+            let mut stats = DrawStats {
+                pipeline_binds: 1,
+                ..Default::default()
+            };
+            // one pass over the whole render area, each view only draws into its own part
+            for (view, scene_data) in views.iter().zip(scene_data) {
+                device.cmd_set_scissor(cmd, 0, &[view.scissor]);
+                device.cmd_set_viewport(cmd, 0, &[view.viewport]);
+                scene_data.bind(device, cmd, self.gbuffer_pipeline.pipeline_layout, &[bindless_set]);
+                stats.descriptor_set_binds += 1;
+                let mut bound_index_buffer = None;
+                for render_obj in draw_ctx
+                    .opaque_surfaces
+                    .iter()
+                    .filter(|render_obj| render_obj.material.deferred())
+                {
+                    if bound_index_buffer != Some(*render_obj.index_buffer) {
+                        device.cmd_bind_index_buffer(cmd, *render_obj.index_buffer, 0, IndexType::UINT32);
+                        bound_index_buffer = Some(*render_obj.index_buffer);
+                        stats.index_buffer_binds += 1;
+                    }
+                    stats.push_constant_updates += 1;
+                    device.cmd_push_constants(
+                        cmd,
+                        self.gbuffer_pipeline.pipeline_layout,
+                        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                        0,
+                        &gpu_scene_push_constant(
+                            render_obj.transform,
+                            render_obj.vertex_buffer_address,
+                            render_obj.material.material_index,
+                        ),
+                    );
+                    stats.record_draw(render_obj.index_count, 1);
+                    device.cmd_draw_indexed(
+                        cmd,
+                        render_obj.index_count,
+                        1,
+                        render_obj.first_index,
+                        0,
+                        0,
+                    );
+                }
+            }
+            device.cmd_end_render_pass(cmd);
+            stats
+        }
+    }
+
+    /// Shades the G-buffer into the color attachment of the scene pass, recorded inside it
+    /// before the forward surfaces.
+    pub fn record_lighting(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        scene_data: SceneDataBinding,
+        bindless_set: DescriptorSet,
+        viewports: &[Viewport],
+        render_area: &Rect2D,
+    ) {
+        unsafe {
+            device.cmd_set_scissor(cmd, 0, &[*render_area]);
+            device.cmd_set_viewport(cmd, 0, viewports);
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, *self.lighting_pipeline);
+            scene_data.bind(
+                device,
+                cmd,
+                self.lighting_pipeline.pipeline_layout,
+                &[bindless_set, self.gbuffer_set],
+            );
+            // one triangle covering the whole target, positions come from gl_VertexIndex
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+    }
+}
+
+fn opaque_attachment() -> PipelineColorBlendAttachmentState {
+    create_color_blending_attachment_state(
+        ColorComponentFlags::R
+            | ColorComponentFlags::G
+            | ColorComponentFlags::B
+            | ColorComponentFlags::A,
+        false,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+}
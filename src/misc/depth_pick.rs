@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, BufferImageCopy, BufferUsageFlags, CommandBuffer, Extent3D,
+    ImageAspectFlags, ImageLayout, ImageMemoryBarrier2, MemoryBarrier2, MemoryPropertyFlags,
+    Offset3D, PipelineStageFlags2, Viewport, QUEUE_FAMILY_IGNORED,
+};
+use nalgebra::{Matrix4, Vector3, Vector4};
+use vk_mem::MemoryUsage;
+
+use crate::{
+    components::{
+        allocation_types::{AllocatedImage, VkBuffer},
+        deletion_queue::{DeletionQueue, FType},
+        device::VkDevice,
+        image_util::{image_subresource_layers, image_subresource_range},
+        memory_allocator::MemoryAllocator,
+        queue::VkQueue,
+    },
+    renderer::MAX_FRAMES,
+};
+
+/// Value the scene pass clears the depth buffer to, nothing was drawn where it is left.
+const CLEAR_DEPTH: f32 = 1.0;
+
+/// Depth under a pixel of the render target, read back from the scene depth buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthPick {
+    /// Pixel of the render target that was sampled.
+    pub pixel: [u32; 2],
+    pub depth: f32,
+    /// World space position of the surface under the pixel, `None` where only the clear color
+    /// (or the skybox) was drawn.
+    pub position: Option<Vector3<f32>>,
+}
+
+/// What was recorded for a frame, kept until its fence has been waited on.
+#[derive(Debug, Clone, Copy)]
+struct PickRequest {
+    pixel: [u32; 2],
+    view_proj: Matrix4<f32>,
+    viewport: Viewport,
+}
+
+/// Copies the depth of a single pixel into a host visible buffer after the scene pass, so the
+/// world position under the cursor can be found without raycasting against the meshes. The
+/// result arrives once the frame that recorded the copy has finished, `MAX_FRAMES` frames later.
+/// Multisampled depth can not be copied to a buffer, picking is unavailable with MSAA.
+pub struct DepthPicker {
+    buffers: Vec<(VkBuffer, *mut f32)>,
+    pending: Option<[u32; 2]>,
+    in_flight: [Option<PickRequest>; MAX_FRAMES],
+    result: Option<DepthPick>,
+    supported: bool,
+}
+
+impl DepthPicker {
+    pub fn new(
+        memory_allocator: &MemoryAllocator,
+        queues: &[Arc<VkQueue>],
+        multisampled: bool,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let mut buffers = vec![];
+        for _ in 0..MAX_FRAMES {
+            let buffer = memory_allocator.allocate_single_buffer(
+                size_of::<f32>() as u64,
+                queues,
+                BufferUsageFlags::TRANSFER_DST,
+                MemoryUsage::Unknown,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped = buffer.mapped_data() as *mut f32;
+            deletion_queue.enqueue(FType::DROP(Box::new(buffer.clone())));
+            buffers.push((buffer, mapped));
+        }
+        Ok(Self {
+            buffers,
+            pending: None,
+            in_flight: [None; MAX_FRAMES],
+            result: None,
+            supported: !multisampled,
+        })
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    /// Samples `pixel` in the next recorded frame, replaces an earlier request that was not
+    /// recorded yet.
+    pub fn request(&mut self, pixel: [u32; 2]) {
+        if self.supported {
+            self.pending = Some(pixel);
+        }
+    }
+
+    /// Whether a requested pick has not been read back yet.
+    pub fn is_waiting(&self) -> bool {
+        self.pending.is_some() || self.in_flight.iter().any(Option::is_some)
+    }
+
+    /// The most recent pick that finished.
+    pub fn result(&self) -> Option<DepthPick> {
+        self.result
+    }
+
+    /// Picks up the depth recorded for `frame_idx` last time, must only be called once the
+    /// frame's fence has been waited on.
+    pub fn read_back(&mut self, frame_idx: usize) {
+        let Some(request) = self.in_flight[frame_idx].take() else {
+            return;
+        };
+        let depth = unsafe { self.buffers[frame_idx].1.read() };
+        let position = (depth < CLEAR_DEPTH)
+            .then(|| unproject(request.view_proj, request.pixel, depth, &request.viewport))
+            .flatten();
+        self.result = Some(DepthPick {
+            pixel: request.pixel,
+            depth,
+            position,
+        });
+    }
+
+    /// Copies the pending pixel, `depth_image` has to be in DEPTH_ATTACHMENT_OPTIMAL like it is
+    /// after the scene render pass. It is left in TRANSFER_SRC_OPTIMAL, the next scene pass
+    /// starts from UNDEFINED.
+    pub fn record(
+        &mut self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        frame_idx: usize,
+        depth_image: &AllocatedImage,
+        view_proj: Matrix4<f32>,
+        viewport: Viewport,
+    ) {
+        let Some(pixel) = self.pending.take() else {
+            return;
+        };
+        let extent = depth_image.extent;
+        let pixel = [
+            pixel[0].min(extent.width - 1),
+            pixel[1].min(extent.height - 1),
+        ];
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[],
+                &[],
+                &[ImageMemoryBarrier2::default()
+                    .src_stage_mask(PipelineStageFlags2::LATE_FRAGMENT_TESTS)
+                    .src_access_mask(AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_stage_mask(PipelineStageFlags2::COPY)
+                    .dst_access_mask(AccessFlags2::TRANSFER_READ)
+                    .old_layout(ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                    .new_layout(ImageLayout::TRANSFER_SRC_OPTIMAL)
+                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .image(depth_image.image_details.image)
+                    .subresource_range(image_subresource_range(ImageAspectFlags::DEPTH))],
+            );
+            device.cmd_copy_image_to_buffer(
+                cmd,
+                depth_image.image_details.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *self.buffers[frame_idx].0,
+                &[BufferImageCopy::default()
+                    .image_subresource(image_subresource_layers(ImageAspectFlags::DEPTH))
+                    .image_offset(Offset3D {
+                        x: pixel[0] as i32,
+                        y: pixel[1] as i32,
+                        z: 0,
+                    })
+                    .image_extent(Extent3D {
+                        width: 1,
+                        height: 1,
+                        depth: 1,
+                    })],
+            );
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[MemoryBarrier2::default()
+                    .src_stage_mask(PipelineStageFlags2::COPY)
+                    .src_access_mask(AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(PipelineStageFlags2::HOST)
+                    .dst_access_mask(AccessFlags2::HOST_READ)],
+                &[],
+                &[],
+            );
+        }
+        self.in_flight[frame_idx] = Some(PickRequest {
+            pixel,
+            view_proj,
+            viewport,
+        });
+    }
+}
+
+/// World position of the center of `pixel` at `depth`, the inverse of `project_to_viewport`.
+fn unproject(
+    view_proj: Matrix4<f32>,
+    pixel: [u32; 2],
+    depth: f32,
+    viewport: &Viewport,
+) -> Option<Vector3<f32>> {
+    let ndc_x = (pixel[0] as f32 + 0.5 - viewport.x) / viewport.width * 2.0 - 1.0;
+    let ndc_y = (pixel[1] as f32 + 0.5 - viewport.y) / viewport.height * 2.0 - 1.0;
+    let world = view_proj.try_inverse()? * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+    (world.w.abs() > f32::EPSILON).then(|| world.xyz() / world.w)
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    ClearDepthStencilValue, ClearValue, CommandBuffer, CullModeFlags, DescriptorSet,
+    DescriptorSetLayout, DynamicState, Extent2D, FrontFace, IndexType, PipelineBindPoint, PolygonMode, PrimitiveTopology, Rect2D,
+    RenderPassBeginInfo, SampleCountFlags, ShaderStageFlags, SubpassContents,
+};
+use nalgebra::Matrix4;
+
+use crate::{
+    components::{
+        allocation_types::{AllocatedImage, VkFrameBuffer, IDENTIFIER},
+        device::VkDevice,
+        frame_data::SceneDataBinding,
+        pipeline::{
+            create_multisampling_state, create_rasterizer_state, ShaderInformation, VertexInput,
+            VkPipeline,
+        },
+        render_pass::VkRenderPass,
+    },
+    geom::{gpu_scene_push_constant, push_constants::{PushConstant, PushConstantLayout}},
+};
+
+use super::{
+    material::{MaterialPass, DEFAULT_VERTEX_SHADER},
+    views::ViewTarget,
+    DrawContext, DrawStats,
+};
+
+/// Writes the depth of the opaque surfaces into the scene depth image before the main pass, which
+/// then loads it instead of clearing. Uses the default mesh vertex shader, so materials with a
+/// custom vertex shader have to produce the same positions.
+pub struct DepthPrepass {
+    render_pass: Arc<VkRenderPass>,
+    framebuffer: VkFrameBuffer,
+    pipeline: VkPipeline,
+}
+
+impl DepthPrepass {
+    pub fn new(
+        device: Arc<VkDevice>,
+        extent: Extent2D,
+        samples: SampleCountFlags,
+        depth_image: &AllocatedImage,
+        scene_data_layout: DescriptorSetLayout,
+        bindless_layout: DescriptorSetLayout,
+    ) -> Result<Self> {
+        let render_pass = Arc::new(VkRenderPass::depth_only(device.clone(), samples)?);
+        let framebuffer = VkFrameBuffer::create_framebuffer(
+            IDENTIFIER::DEPTH,
+            device.clone(),
+            render_pass.clone(),
+            extent,
+            &[depth_image.image_details],
+        );
+        // same set layouts as the material pipelines so the sets bound here stay valid
+        let pipeline = VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[ShaderInformation::vertex_2d_information(
+                DEFAULT_VERTEX_SHADER.to_string(),
+            )],
+            Some(&[scene_data_layout, bindless_layout]),
+            &extent,
+            Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+                ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            )),
+            VertexInput::Pulling,
+            &[],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, samples, 1.0, false, false),
+            render_pass.clone(),
+            true)?;
+        Ok(Self {
+            render_pass,
+            framebuffer,
+            pipeline,
+        })
+    }
+
+    /// Leaves the depth image in DEPTH_ATTACHMENT_OPTIMAL. Transparent surfaces are skipped,
+    /// they must not hide what is behind them.
+    pub fn record(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        draw_ctx: &DrawContext,
+        views: &[ViewTarget],
+        scene_data: &[SceneDataBinding],
+        bindless_set: DescriptorSet,
+        render_area: &Rect2D,
+    ) -> DrawStats {
+        let clear_value = [ClearValue {
+            depth_stencil: ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        unsafe {
+            device.cmd_begin_render_pass(
+                cmd,
+                &RenderPassBeginInfo::default()
+                    .render_pass(**self.render_pass)
+                    .framebuffer(*self.framebuffer)
+                    .render_area(*render_area)
+                    .clear_values(&clear_value),
+                SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, *self.pipeline);
+            let mut stats = DrawStats {
+                pipeline_binds: 1,
+                ..Default::default()
+            };
+            // one pass over the whole render area, each view only draws into its own part
+            for (view, scene_data) in views.iter().zip(scene_data) {
+                device.cmd_set_scissor(cmd, 0, &[view.scissor]);
+                device.cmd_set_viewport(cmd, 0, &[view.viewport]);
+                scene_data.bind(device, cmd, self.pipeline.pipeline_layout, &[bindless_set]);
+                stats.descriptor_set_binds += 1;
+                let mut bound_index_buffer = None;
+                // the pre-pass shader doesn't skin, skinned surfaces test their depth when shaded
+                for render_obj in draw_ctx.opaque_surfaces.iter().filter(|render_obj| {
+                    render_obj.material.pass != MaterialPass::GLTF_PBR_TRANSPARENT
+                        && !render_obj.material.pipeline.skinned
+                }) {
+                    if bound_index_buffer != Some(*render_obj.index_buffer) {
+                        device.cmd_bind_index_buffer(cmd, *render_obj.index_buffer, 0, IndexType::UINT32);
+                        bound_index_buffer = Some(*render_obj.index_buffer);
+                        stats.index_buffer_binds += 1;
+                    }
+                    stats.push_constant_updates += 1;
+                    device.cmd_push_constants(
+                        cmd,
+                        self.pipeline.pipeline_layout,
+                        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                        0,
+                        &gpu_scene_push_constant(
+                            render_obj.transform,
+                            render_obj.vertex_buffer_address,
+                            render_obj.material.material_index,
+                        ),
+                    );
+                    stats.record_draw(render_obj.index_count, 1);
+                    device.cmd_draw_indexed(
+                        cmd,
+                        render_obj.index_count,
+                        1,
+                        render_obj.first_index,
+                        0,
+                        0,
+                    );
+                }
+            }
+            device.cmd_end_render_pass(cmd);
+            stats
+        }
+    }
+}
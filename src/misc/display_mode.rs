@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use winit::{
+    keyboard::{KeyCode, ModifiersState},
+    monitor::VideoModeHandle,
+    window::{Fullscreen, Window},
+};
+
+/// How a window covers its monitor, see `Renderer::set_display_mode`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum DisplayMode {
+    #[default]
+    Windowed,
+    /// A window without decorations over the whole current monitor, which keeps its video mode.
+    Borderless,
+    /// Switches the monitor to the video mode, `None` picks the largest and fastest one of the
+    /// current monitor. Falls back to `Borderless` where there is none.
+    Exclusive(Option<VideoModeHandle>),
+}
+
+impl DisplayMode {
+    /// The mode `window` is shown in now.
+    pub fn of(window: &Window) -> Self {
+        match window.fullscreen() {
+            None => DisplayMode::Windowed,
+            Some(Fullscreen::Borderless(_)) => DisplayMode::Borderless,
+            Some(Fullscreen::Exclusive(video_mode)) => DisplayMode::Exclusive(Some(video_mode)),
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        *self != DisplayMode::Windowed
+    }
+
+    /// What winit has to be asked for to show `window` in this mode.
+    pub fn fullscreen(&self, window: &Window) -> Option<Fullscreen> {
+        match self {
+            DisplayMode::Windowed => None,
+            DisplayMode::Borderless => Some(Fullscreen::Borderless(None)),
+            DisplayMode::Exclusive(Some(video_mode)) => {
+                Some(Fullscreen::Exclusive(video_mode.clone()))
+            }
+            DisplayMode::Exclusive(None) => Some(
+                video_modes(window)
+                    .into_iter()
+                    .next()
+                    .map_or(Fullscreen::Borderless(None), Fullscreen::Exclusive),
+            ),
+        }
+    }
+}
+
+/// Video modes of the monitor `window` is on, the largest and fastest first. Empty where
+/// the monitor is unknown, e.g. on Wayland.
+pub fn video_modes(window: &Window) -> Vec<VideoModeHandle> {
+    let mut video_modes: Vec<VideoModeHandle> = window
+        .current_monitor()
+        .map(|monitor| monitor.video_modes().collect())
+        .unwrap_or_default();
+    video_modes.sort();
+    video_modes
+}
+
+/// A key pressed together with exactly `modifiers`, parsed from strings like `Alt+Enter` or
+/// `Ctrl+Shift+F11`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub key: KeyCode,
+    pub modifiers: ModifiersState,
+}
+
+impl Hotkey {
+    /// Toggles fullscreen by default.
+    pub const ALT_ENTER: Hotkey = Hotkey::new(KeyCode::Enter, ModifiersState::ALT);
+
+    pub const fn new(key: KeyCode, modifiers: ModifiersState) -> Self {
+        Self { key, modifiers }
+    }
+
+    pub fn matches(&self, key: KeyCode, modifiers: ModifiersState) -> bool {
+        self.key == key && self.modifiers == modifiers
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = Error;
+
+    fn from_str(hotkey: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = hotkey.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty());
+        let key = key
+            .and_then(key_code)
+            .ok_or_else(|| anyhow!("No key in hotkey {hotkey:?}"))?;
+        let mut modifiers = ModifiersState::empty();
+        for part in parts {
+            modifiers |= match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => ModifiersState::CONTROL,
+                "shift" => ModifiersState::SHIFT,
+                "alt" | "option" => ModifiersState::ALT,
+                "super" | "meta" | "cmd" | "win" => ModifiersState::SUPER,
+                _ => return Err(anyhow!("Unknown modifier {part:?} in hotkey {hotkey:?}")),
+            };
+        }
+        Ok(Hotkey::new(key, modifiers))
+    }
+}
+
+/// Letters, digits, function keys and the common named keys.
+fn key_code(name: &str) -> Option<KeyCode> {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF,
+        KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL,
+        KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR,
+        KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX,
+        KeyCode::KeyY, KeyCode::KeyZ,
+    ];
+    const DIGITS: [KeyCode; 10] = [
+        KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+        KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    ];
+    const FUNCTION_KEYS: [KeyCode; 12] = [
+        KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+        KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    ];
+    let name = name.to_ascii_lowercase();
+    let mut chars = name.chars();
+    if let (Some(char), None) = (chars.next(), chars.next()) {
+        return match char {
+            'a'..='z' => Some(LETTERS[(char as u8 - b'a') as usize]),
+            '0'..='9' => Some(DIGITS[(char as u8 - b'0') as usize]),
+            _ => None,
+        };
+    }
+    if let Some(number) = name.strip_prefix('f').and_then(|number| number.parse::<usize>().ok()) {
+        return FUNCTION_KEYS.get(number.checked_sub(1)?).copied();
+    }
+    Some(match name.as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "space" => KeyCode::Space,
+        "tab" => KeyCode::Tab,
+        "escape" | "esc" => KeyCode::Escape,
+        "backspace" => KeyCode::Backspace,
+        "insert" => KeyCode::Insert,
+        "delete" => KeyCode::Delete,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::{KeyCode, ModifiersState};
+
+    use super::Hotkey;
+
+    #[test]
+    fn parses_modifiers_and_keys() {
+        assert_eq!("Alt+Enter".parse::<Hotkey>().unwrap(), Hotkey::ALT_ENTER);
+        assert_eq!(
+            "ctrl + shift + f11".parse::<Hotkey>().unwrap(),
+            Hotkey::new(KeyCode::F11, ModifiersState::CONTROL | ModifiersState::SHIFT)
+        );
+        assert_eq!(
+            "F".parse::<Hotkey>().unwrap(),
+            Hotkey::new(KeyCode::KeyF, ModifiersState::empty())
+        );
+        assert!("Alt+".parse::<Hotkey>().is_err());
+        assert!("Hyper+Enter".parse::<Hotkey>().is_err());
+        assert!("F13".parse::<Hotkey>().is_err());
+    }
+
+    #[test]
+    fn matches_only_the_exact_modifiers() {
+        let hotkey = Hotkey::ALT_ENTER;
+        assert!(hotkey.matches(KeyCode::Enter, ModifiersState::ALT));
+        assert!(!hotkey.matches(KeyCode::Enter, ModifiersState::ALT | ModifiersState::SHIFT));
+        assert!(!hotkey.matches(KeyCode::Enter, ModifiersState::empty()));
+        assert!(!hotkey.matches(KeyCode::Space, ModifiersState::ALT));
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, ColorSpaceKHR, CommandBuffer, DescriptorSet,
+    DescriptorSetLayoutCreateFlags, DescriptorType, ImageLayout, MemoryBarrier2, PipelineBindPoint,
+    PipelineStageFlags2, ShaderStageFlags,
+};
+
+use crate::components::{
+    allocation_types::AllocatedImage,
+    deletion_queue::{DeletionQueue, FType},
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    device::VkDevice,
+    image_util::image_transition,
+    pipeline::VkPipeline,
+    swapchain_support_details::SwapchainSupportDetails,
+};
+
+/// How the linear scene colors are encoded for the display, applied to the post processed image
+/// right before it is copied into the swapchain.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayTransform {
+    /// sRGB primaries and transfer function, standard monitors.
+    #[default]
+    Srgb,
+    /// Display P3 primaries with the sRGB transfer function, wide gamut Apple displays.
+    DisplayP3,
+    /// Rec.709 primaries (shared with sRGB) with the BT.709 transfer function.
+    Rec709,
+    /// No conversion, the draw image is presented as is.
+    Raw,
+}
+
+impl DisplayTransform {
+    pub const ALL: [DisplayTransform; 4] = [
+        DisplayTransform::Srgb,
+        DisplayTransform::DisplayP3,
+        DisplayTransform::Rec709,
+        DisplayTransform::Raw,
+    ];
+
+    /// Swapchain color space this transform encodes for.
+    pub fn color_space(self) -> ColorSpaceKHR {
+        match self {
+            DisplayTransform::DisplayP3 => ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+            DisplayTransform::Rec709 => ColorSpaceKHR::BT709_NONLINEAR_EXT,
+            DisplayTransform::Srgb | DisplayTransform::Raw => ColorSpaceKHR::SRGB_NONLINEAR,
+        }
+    }
+
+    /// Picks Display P3 when the surface can present it, sRGB otherwise.
+    pub fn detect(details: &SwapchainSupportDetails) -> DisplayTransform {
+        if details
+            .supported_color_spaces()
+            .contains(&ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT)
+        {
+            DisplayTransform::DisplayP3
+        } else {
+            DisplayTransform::Srgb
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DisplayTransform::Srgb => "sRGB",
+            DisplayTransform::DisplayP3 => "Display P3",
+            DisplayTransform::Rec709 => "Rec.709",
+            DisplayTransform::Raw => "Raw",
+        }
+    }
+
+    /// Value of `mode` in `shaders/display_transform.comp`.
+    fn shader_mode(self) -> u32 {
+        match self {
+            DisplayTransform::Srgb => 0,
+            DisplayTransform::DisplayP3 => 1,
+            DisplayTransform::Rec709 => 2,
+            DisplayTransform::Raw => 3,
+        }
+    }
+}
+
+/// Compute pass encoding the post process output in place with the selected `DisplayTransform`.
+pub struct DisplayTransformPass {
+    pipeline: VkPipeline,
+    descriptor_set: DescriptorSet,
+    transform: DisplayTransform,
+    /// Color space the swapchain was created with, fixed for the lifetime of the swapchain.
+    surface_color_space: ColorSpaceKHR,
+}
+
+impl DisplayTransformPass {
+    pub fn new(
+        device: Arc<VkDevice>,
+        draw_image: &AllocatedImage,
+        transform: DisplayTransform,
+        surface_color_space: ColorSpaceKHR,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let layout = DescriptorLayoutBuilder::new()
+            .add_binding(0, DescriptorType::STORAGE_IMAGE, ShaderStageFlags::COMPUTE)
+            .build(
+                device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            );
+        let pipeline = VkPipeline::compute_pipeline_with_push_constant::<u32>(
+            device.clone(),
+            &[layout],
+            "/Users/zapzap/Projects/piplup/shaders/display_transform.comp.spv",
+        )?;
+
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            device.clone(),
+            1,
+            vec![PoolSizeRatio::new(DescriptorType::STORAGE_IMAGE, 1.0)],
+        );
+        let descriptor_set = descriptor_allocator.allocate(device.clone(), &[layout])[0];
+        let mut writer = DescriptorWriter::new();
+        writer.write_image(
+            0,
+            draw_image.image_details.image_view,
+            None,
+            ImageLayout::GENERAL,
+            DescriptorType::STORAGE_IMAGE,
+        );
+        writer.update_set(device.clone(), descriptor_set);
+
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_set_layout(layout, None);
+            descriptor_allocator.destroy_pools(device);
+        })));
+
+        Ok(Self {
+            pipeline,
+            descriptor_set,
+            transform,
+            surface_color_space,
+        })
+    }
+
+    pub fn transform(&self) -> DisplayTransform {
+        self.transform
+    }
+
+    /// Switches the encoding, the swapchain keeps the color space it was created with so
+    /// transforms for a different color space will look off on this surface.
+    pub fn set_transform(&mut self, transform: DisplayTransform) {
+        self.transform = transform;
+    }
+
+    pub fn matches_surface(&self) -> bool {
+        self.transform == DisplayTransform::Raw
+            || self.transform.color_space() == self.surface_color_space
+    }
+
+    /// Expects the draw image in TRANSFER_SRC_OPTIMAL and leaves it there.
+    pub fn record(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        draw_image: &AllocatedImage,
+        queue_family_index: u32,
+    ) {
+        if self.transform == DisplayTransform::Raw {
+            return;
+        }
+        let extent = draw_image.extent;
+        unsafe {
+            image_transition(
+                device.clone(),
+                cmd,
+                queue_family_index,
+                draw_image.image_details.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ImageLayout::GENERAL,
+            );
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[MemoryBarrier2::default()
+                    .src_stage_mask(
+                        PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+                            | PipelineStageFlags2::COMPUTE_SHADER,
+                    )
+                    .src_access_mask(
+                        AccessFlags2::COLOR_ATTACHMENT_WRITE | AccessFlags2::SHADER_WRITE,
+                    )
+                    .dst_stage_mask(PipelineStageFlags2::COMPUTE_SHADER)
+                    .dst_access_mask(AccessFlags2::SHADER_READ | AccessFlags2::SHADER_WRITE)],
+                &[],
+                &[],
+            );
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::COMPUTE, *self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline.pipeline_layout,
+                ShaderStageFlags::COMPUTE,
+                0,
+                &self.transform.shader_mode().to_ne_bytes(),
+            );
+            device.cmd_dispatch(cmd, extent.width.div_ceil(16), extent.height.div_ceil(16), 1);
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[MemoryBarrier2::default()
+                    .src_stage_mask(PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(AccessFlags2::SHADER_WRITE)
+                    .dst_stage_mask(PipelineStageFlags2::TRANSFER)
+                    .dst_access_mask(AccessFlags2::TRANSFER_READ)],
+                &[],
+                &[],
+            );
+            image_transition(
+                device.clone(),
+                cmd,
+                queue_family_index,
+                draw_image.image_details.image,
+                ImageLayout::GENERAL,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Polls the modification times of a set of files, for hot reloading assets that are edited
+/// while the renderer runs.
+#[derive(Default)]
+pub struct FileWatcher {
+    files: Vec<WatchedFile>,
+}
+
+impl FileWatcher {
+    /// Remembers the current modification time of `path` for `changed_files`.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_path_buf();
+        if self.files.iter().any(|watched| watched.path == path) {
+            return;
+        }
+        let modified = modified_time(&path);
+        self.files.push(WatchedFile { path, modified });
+    }
+
+    /// Watched files whose modification time changed since the last call.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        self.files
+            .iter_mut()
+            .filter_map(|watched| {
+                let modified = modified_time(&watched.path);
+                if modified == watched.modified {
+                    return None;
+                }
+                watched.modified = modified;
+                Some(watched.path.clone())
+            })
+            .collect()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,387 @@
+use std::f32::consts::TAU;
+
+use nalgebra::{Matrix4, Unit, UnitQuaternion, Vector3, Vector4};
+
+use crate::geom::bounds::Ray;
+
+use super::debug_draw::DebugDraw;
+
+/// Length of the handles relative to their distance from the camera, so they keep their size
+/// on screen.
+const GIZMO_SCREEN_SIZE: f32 = 0.15;
+/// How close the cursor ray has to pass a handle to grab it, relative to the handle length.
+const GIZMO_GRAB_DISTANCE: f32 = 0.08;
+const GIZMO_RING_SEGMENTS: usize = 48;
+/// Scale handles can't shrink a node below this factor of the scale the drag started at.
+const MIN_SCALE_FACTOR: f32 = 0.01;
+const HIGHLIGHT_COLOR: Vector4<f32> = Vector4::new(1.0, 1.0, 0.0, 1.0);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    /// Arrows moving the node along the world axes.
+    #[default]
+    Translate,
+    /// Rings turning the node around the world axes through its origin.
+    Rotate,
+    /// Handles stretching the node along its own axes.
+    Scale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl GizmoAxis {
+    pub const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn color(self) -> Vector4<f32> {
+        match self {
+            GizmoAxis::X => Vector4::new(1.0, 0.2, 0.2, 1.0),
+            GizmoAxis::Y => Vector4::new(0.2, 1.0, 0.2, 1.0),
+            GizmoAxis::Z => Vector4::new(0.2, 0.4, 1.0, 1.0),
+        }
+    }
+}
+
+/// Where the handles of a transform are drawn and grabbed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HandleFrame {
+    origin: Vector3<f32>,
+    axes: [Unit<Vector3<f32>>; 3],
+    /// Length of the arrows and radius of the rings.
+    size: f32,
+}
+
+impl HandleFrame {
+    fn new(mode: GizmoMode, transform: &Matrix4<f32>, camera_position: Vector3<f32>) -> Self {
+        let origin = transform.column(3).xyz();
+        let world_axes = [Vector3::x_axis(), Vector3::y_axis(), Vector3::z_axis()];
+        let axes = match mode {
+            GizmoMode::Scale => [0, 1, 2].map(|idx| {
+                Unit::try_new(transform.column(idx).xyz(), f32::EPSILON)
+                    .unwrap_or(world_axes[idx])
+            }),
+            GizmoMode::Translate | GizmoMode::Rotate => world_axes,
+        };
+        Self {
+            origin,
+            axes,
+            size: ((origin - camera_position).norm() * GIZMO_SCREEN_SIZE).max(f32::EPSILON),
+        }
+    }
+
+    fn axis(&self, axis: GizmoAxis) -> Unit<Vector3<f32>> {
+        self.axes[axis.index()]
+    }
+
+    /// Position along the line of `axis` closest to `ray`, with the distance between the two
+    /// and how far along the ray it is. `None` if the ray runs parallel to the axis.
+    fn closest_on_axis(&self, axis: GizmoAxis, ray: &Ray) -> Option<(f32, f32, f32)> {
+        let direction = self.axis(axis);
+        let offset = self.origin - ray.origin;
+        let cos = direction.dot(&ray.direction);
+        let denominator = 1.0 - cos * cos;
+        if denominator < 1e-6 {
+            return None;
+        }
+        let (along_axis, along_ray) = (direction.dot(&offset), ray.direction.dot(&offset));
+        let position = (cos * along_ray - along_axis) / denominator;
+        let distance_along_ray = (along_ray - cos * along_axis) / denominator;
+        let gap = (self.origin + direction.into_inner() * position - ray.at(distance_along_ray))
+            .norm();
+        Some((position, gap, distance_along_ray))
+    }
+
+    /// Where `ray` crosses the plane through the origin perpendicular to `axis`, with the
+    /// distance along the ray.
+    fn plane_hit(&self, axis: GizmoAxis, ray: &Ray) -> Option<(Vector3<f32>, f32)> {
+        let normal = self.axis(axis);
+        let cos = normal.dot(&ray.direction);
+        if cos.abs() < 1e-6 {
+            return None;
+        }
+        let distance = normal.dot(&(self.origin - ray.origin)) / cos;
+        (distance >= 0.0).then(|| (ray.at(distance), distance))
+    }
+
+    /// The handle of `axis` `ray` passes close enough to grab, with the distance along the ray
+    /// and the grabbed point.
+    fn hit(&self, mode: GizmoMode, axis: GizmoAxis, ray: &Ray) -> Option<(f32, Vector3<f32>)> {
+        let tolerance = self.size * GIZMO_GRAB_DISTANCE;
+        match mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let (position, gap, distance) = self.closest_on_axis(axis, ray)?;
+                let on_handle = (0.0..=self.size).contains(&position) && distance >= 0.0;
+                (on_handle && gap <= tolerance)
+                    .then(|| (distance, self.origin + self.axis(axis).into_inner() * position))
+            }
+            GizmoMode::Rotate => {
+                let (point, distance) = self.plane_hit(axis, ray)?;
+                let radius = (point - self.origin).norm();
+                ((radius - self.size).abs() <= tolerance).then_some((distance, point))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GizmoDrag {
+    axis: GizmoAxis,
+    /// Handles as they were when the drag started, the drag is measured against them.
+    frame: HandleFrame,
+    /// Transform of the node when the drag started.
+    start: Matrix4<f32>,
+    /// Point of the handle that was grabbed.
+    grab: Vector3<f32>,
+}
+
+/// Translate, rotate and scale handles of the selected node, drawn as overlay lines over the
+/// scene. Turns cursor rays into new world transforms while a handle is dragged, the caller
+/// writes them back into the scene.
+#[derive(Debug, Default)]
+pub struct Gizmo {
+    mode: GizmoMode,
+    hovered: Option<GizmoAxis>,
+    drag: Option<GizmoDrag>,
+}
+
+impl Gizmo {
+    pub fn mode(&self) -> GizmoMode {
+        self.mode
+    }
+
+    /// Ends a drag in progress.
+    pub fn set_mode(&mut self, mode: GizmoMode) {
+        self.mode = mode;
+        self.hovered = None;
+        self.drag = None;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// The handle of the node at `transform` nearest along `ray`.
+    pub fn hit(
+        &self,
+        transform: &Matrix4<f32>,
+        camera_position: Vector3<f32>,
+        ray: &Ray,
+    ) -> Option<GizmoAxis> {
+        let frame = HandleFrame::new(self.mode, transform, camera_position);
+        self.nearest_hit(&frame, ray).map(|(axis, _)| axis)
+    }
+
+    /// The handle nearest along `ray` with the point it is grabbed at.
+    fn nearest_hit(&self, frame: &HandleFrame, ray: &Ray) -> Option<(GizmoAxis, Vector3<f32>)> {
+        GizmoAxis::ALL
+            .into_iter()
+            .filter_map(|axis| {
+                let (distance, grab) = frame.hit(self.mode, axis, ray)?;
+                Some((axis, distance, grab))
+            })
+            .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+            .map(|(axis, _, grab)| (axis, grab))
+    }
+
+    /// Highlights the handle under `ray`, `None` clears the highlight. Returns whether the
+    /// highlight changed.
+    pub fn hover(
+        &mut self,
+        transform: &Matrix4<f32>,
+        camera_position: Vector3<f32>,
+        ray: Option<&Ray>,
+    ) -> bool {
+        let hovered = ray.and_then(|ray| self.hit(transform, camera_position, ray));
+        let changed = hovered != self.hovered;
+        self.hovered = hovered;
+        changed
+    }
+
+    /// Starts dragging the handle under `ray`, returns false if there is none.
+    pub fn begin_drag(
+        &mut self,
+        transform: &Matrix4<f32>,
+        camera_position: Vector3<f32>,
+        ray: &Ray,
+    ) -> bool {
+        let frame = HandleFrame::new(self.mode, transform, camera_position);
+        let Some((axis, grab)) = self.nearest_hit(&frame, ray) else {
+            return false;
+        };
+        self.drag = Some(GizmoDrag {
+            axis,
+            frame,
+            start: *transform,
+            grab,
+        });
+        self.hovered = Some(axis);
+        true
+    }
+
+    /// World transform of the dragged node with the handle moved to where `ray` points.
+    /// `None` without a drag or while the ray can't be followed, e.g. when it runs parallel
+    /// to the dragged axis.
+    pub fn drag(&self, ray: &Ray) -> Option<Matrix4<f32>> {
+        let GizmoDrag {
+            axis,
+            frame,
+            start,
+            grab,
+        } = self.drag?;
+        let direction = frame.axis(axis);
+        match self.mode {
+            GizmoMode::Translate => {
+                let (position, _, _) = frame.closest_on_axis(axis, ray)?;
+                let grabbed = direction.dot(&(grab - frame.origin));
+                let offset = direction.into_inner() * (position - grabbed);
+                Some(Matrix4::new_translation(&offset) * start)
+            }
+            GizmoMode::Rotate => {
+                let (point, _) = frame.plane_hit(axis, ray)?;
+                let (from, to) = (grab - frame.origin, point - frame.origin);
+                let angle = direction.dot(&from.cross(&to)).atan2(from.dot(&to));
+                let rotation = UnitQuaternion::from_axis_angle(&direction, angle);
+                Some(
+                    Matrix4::new_translation(&frame.origin)
+                        * rotation.to_homogeneous()
+                        * Matrix4::new_translation(&-frame.origin)
+                        * start,
+                )
+            }
+            GizmoMode::Scale => {
+                let (position, _, _) = frame.closest_on_axis(axis, ray)?;
+                let grabbed = direction.dot(&(grab - frame.origin));
+                if grabbed.abs() <= f32::EPSILON {
+                    return None;
+                }
+                let mut scale = Vector3::repeat(1.0);
+                scale[axis.index()] = (position / grabbed).max(MIN_SCALE_FACTOR);
+                Some(start * Matrix4::new_nonuniform_scaling(&scale))
+            }
+        }
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    /// Queues the handles of the node at `transform` as overlay lines for this frame.
+    pub fn draw(
+        &self,
+        transform: &Matrix4<f32>,
+        camera_position: Vector3<f32>,
+        debug_draw: &mut DebugDraw,
+    ) {
+        let frame = match &self.drag {
+            Some(drag) => drag.frame,
+            None => HandleFrame::new(self.mode, transform, camera_position),
+        };
+        let origin = frame.origin;
+        for axis in GizmoAxis::ALL {
+            let color = if self.hovered == Some(axis) {
+                HIGHLIGHT_COLOR
+            } else {
+                axis.color()
+            };
+            let direction = frame.axis(axis).into_inner();
+            // two axes perpendicular to the handle's, for arrow tips and rings
+            let u = frame.axis(GizmoAxis::ALL[(axis.index() + 1) % 3]).into_inner();
+            let v = frame.axis(GizmoAxis::ALL[(axis.index() + 2) % 3]).into_inner();
+            match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    let tip = origin + direction * frame.size;
+                    debug_draw.overlay_line(origin, tip, color);
+                    let width = frame.size * GIZMO_GRAB_DISTANCE;
+                    if self.mode == GizmoMode::Translate {
+                        let base = tip - direction * width * 2.0;
+                        for side in [u, -u, v, -v] {
+                            debug_draw.overlay_line(tip, base + side * width, color);
+                        }
+                    } else {
+                        let corner = |a: f32, b: f32| tip + (u * a + v * b) * width;
+                        let corners = [
+                            corner(1.0, 1.0),
+                            corner(-1.0, 1.0),
+                            corner(-1.0, -1.0),
+                            corner(1.0, -1.0),
+                        ];
+                        for (idx, from) in corners.iter().enumerate() {
+                            debug_draw.overlay_line(*from, corners[(idx + 1) % 4], color);
+                        }
+                    }
+                }
+                GizmoMode::Rotate => {
+                    let point = |segment: usize| {
+                        let angle = TAU * segment as f32 / GIZMO_RING_SEGMENTS as f32;
+                        origin + (u * angle.cos() + v * angle.sin()) * frame.size
+                    };
+                    for segment in 0..GIZMO_RING_SEGMENTS {
+                        debug_draw.overlay_line(point(segment), point(segment + 1), color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix4, Vector3};
+
+    use crate::geom::bounds::Ray;
+
+    use super::{Gizmo, GizmoAxis, GizmoMode};
+
+    const CAMERA: Vector3<f32> = Vector3::new(0.0, 0.0, 10.0);
+
+    /// Ray from the camera through `target`.
+    fn ray_to(target: Vector3<f32>) -> Ray {
+        Ray::new(CAMERA, target - CAMERA).unwrap()
+    }
+
+    #[test]
+    fn translate_handles_move_along_their_axis() {
+        let mut gizmo = Gizmo::default();
+        let transform = Matrix4::identity();
+        // the handles are 1.5 long at a distance of 10
+        let on_x = ray_to(Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(gizmo.hit(&transform, CAMERA, &on_x), Some(GizmoAxis::X));
+        let between = ray_to(Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(gizmo.hit(&transform, CAMERA, &between), None);
+        assert!(gizmo.begin_drag(&transform, CAMERA, &ray_to(Vector3::new(0.0, 1.0, 0.0))));
+        let moved = gizmo.drag(&ray_to(Vector3::new(0.0, 3.0, 0.0))).unwrap();
+        assert!((moved.column(3).xyz() - Vector3::new(0.0, 2.0, 0.0)).norm() < 1e-4);
+        gizmo.end_drag();
+        assert_eq!(gizmo.drag(&ray_to(Vector3::zeros())), None);
+    }
+
+    #[test]
+    fn rotate_rings_turn_around_their_axis() {
+        let mut gizmo = Gizmo::default();
+        gizmo.set_mode(GizmoMode::Rotate);
+        let transform = Matrix4::identity();
+        assert!(gizmo.begin_drag(&transform, CAMERA, &ray_to(Vector3::new(1.5, 0.0, 0.0))));
+        let turned = gizmo.drag(&ray_to(Vector3::new(0.0, 1.5, 0.0))).unwrap();
+        let x = turned.transform_vector(&Vector3::x());
+        assert!((x - Vector3::y()).norm() < 1e-4);
+    }
+
+    #[test]
+    fn scale_handles_stretch_by_the_dragged_ratio() {
+        let mut gizmo = Gizmo::default();
+        gizmo.set_mode(GizmoMode::Scale);
+        let transform = Matrix4::new_translation(&Vector3::new(0.0, -1.0, 0.0));
+        assert!(gizmo.begin_drag(&transform, CAMERA, &ray_to(Vector3::new(0.5, -1.0, 0.0))));
+        let scaled = gizmo.drag(&ray_to(Vector3::new(1.0, -1.0, 0.0))).unwrap();
+        assert!((scaled.transform_vector(&Vector3::x()).x - 2.0).abs() < 1e-4);
+        assert_eq!(scaled.column(3), transform.column(3));
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    CommandBuffer, PipelineStageFlags, QueryPool, QueryPoolCreateInfo, QueryResultFlags,
+    QueryType,
+};
+
+use crate::{
+    components::{
+        deletion_queue::{DeletionQueue, FType},
+        device::VkDevice,
+    },
+    renderer::MAX_FRAMES,
+};
+
+use super::PassTiming;
+
+/// Timestamps a frame slot has room for, the frame begin and end included.
+const QUERIES_PER_FRAME: u32 = 16;
+
+/// GPU time of the frame command buffer from the timestamps of each frame in flight. The result
+/// of a frame is read once its fence was waited on, so it lags `MAX_FRAMES` frames behind.
+pub struct GpuTimer {
+    query_pool: QueryPool,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// Whether the queries of a frame slot were written and not read back yet.
+    pending: [bool; MAX_FRAMES],
+    /// Names of the passes marked in a frame slot, in recording order.
+    marks: [Vec<&'static str>; MAX_FRAMES],
+    last_frame_ms: Option<f32>,
+    last_pass_timings: Vec<PassTiming>,
+    #[cfg(feature = "tracy")]
+    tracy: TracyZones,
+}
+
+impl GpuTimer {
+    pub fn new(device: Arc<VkDevice>, deletion_queue: &mut DeletionQueue) -> Result<Self> {
+        let timestamp_period = unsafe {
+            device
+                .instance
+                .get_physical_device_properties(device.physical_device)
+                .limits
+                .timestamp_period
+        };
+        let query_pool = unsafe {
+            device.create_query_pool(
+                &QueryPoolCreateInfo::default()
+                    .query_type(QueryType::TIMESTAMP)
+                    .query_count(QUERIES_PER_FRAME * MAX_FRAMES as u32),
+                None,
+            )?
+        };
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_query_pool(query_pool, None);
+        })));
+        Ok(Self {
+            query_pool,
+            timestamp_period,
+            pending: [false; MAX_FRAMES],
+            marks: Default::default(),
+            last_frame_ms: None,
+            last_pass_timings: vec![],
+            #[cfg(feature = "tracy")]
+            tracy: TracyZones::default(),
+        })
+    }
+
+    /// GPU milliseconds of the last frame that was read back.
+    pub fn last_frame_ms(&self) -> Option<f32> {
+        self.last_frame_ms
+    }
+
+    /// Per pass GPU milliseconds of the last frame that was read back.
+    pub fn last_pass_timings(&self) -> &[PassTiming] {
+        &self.last_pass_timings
+    }
+
+    fn first_query(frame_idx: usize) -> u32 {
+        QUERIES_PER_FRAME * frame_idx as u32
+    }
+
+    /// Has to be the first command of the frame command buffer, outside of any render pass.
+    pub fn begin(&mut self, cmd: CommandBuffer, device: &Arc<VkDevice>, frame_idx: usize) {
+        let first_query = Self::first_query(frame_idx);
+        self.marks[frame_idx].clear();
+        unsafe {
+            device.cmd_reset_query_pool(cmd, self.query_pool, first_query, QUERIES_PER_FRAME);
+            device.cmd_write_timestamp(
+                cmd,
+                PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                first_query,
+            );
+        }
+    }
+
+    /// Ends the pass `name` that was recorded since the previous mark or `begin`, outside of
+    /// any render pass. Marks beyond the room of a frame slot are dropped.
+    pub fn mark(
+        &mut self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        frame_idx: usize,
+        name: &'static str,
+    ) {
+        let marks = &mut self.marks[frame_idx];
+        // one query stays reserved for `end`
+        if marks.len() as u32 + 2 >= QUERIES_PER_FRAME {
+            return;
+        }
+        marks.push(name);
+        unsafe {
+            device.cmd_write_timestamp(
+                cmd,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                Self::first_query(frame_idx) + marks.len() as u32,
+            );
+        }
+    }
+
+    /// Has to be the last command of the frame command buffer.
+    pub fn end(&mut self, cmd: CommandBuffer, device: &Arc<VkDevice>, frame_idx: usize) {
+        unsafe {
+            device.cmd_write_timestamp(
+                cmd,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                Self::first_query(frame_idx) + self.marks[frame_idx].len() as u32 + 1,
+            );
+        }
+        self.pending[frame_idx] = true;
+    }
+
+    /// Reads the timestamps of `frame_idx`, called after its render fence was waited on and
+    /// returns the new frame time if there was one.
+    pub fn read_back(&mut self, device: &Arc<VkDevice>, frame_idx: usize) -> Option<f32> {
+        if !std::mem::take(&mut self.pending[frame_idx]) {
+            return None;
+        }
+        let marks = &self.marks[frame_idx];
+        let mut timestamps = vec![0_u64; marks.len() + 2];
+        unsafe {
+            device
+                .get_query_pool_results(
+                    self.query_pool,
+                    Self::first_query(frame_idx),
+                    &mut timestamps,
+                    QueryResultFlags::TYPE_64,
+                )
+                .ok()?;
+        }
+        let to_ms = |ticks: u64| ticks as f32 * self.timestamp_period / 1_000_000.0;
+        let frame_ms = to_ms(timestamps[timestamps.len() - 1].saturating_sub(timestamps[0]));
+        self.last_pass_timings = pass_timings(marks, &timestamps, to_ms);
+        #[cfg(feature = "tracy")]
+        self.tracy.emit(marks, &timestamps, self.timestamp_period);
+        self.last_frame_ms = Some(frame_ms);
+        Some(frame_ms)
+    }
+}
+
+/// Pairs each mark with the time since the timestamp before it, `timestamps` starts with the
+/// one written by `begin`.
+fn pass_timings(
+    marks: &[&'static str],
+    timestamps: &[u64],
+    to_ms: impl Fn(u64) -> f32,
+) -> Vec<PassTiming> {
+    marks
+        .iter()
+        .zip(timestamps.windows(2))
+        .map(|(&name, pair)| PassTiming {
+            name,
+            gpu_ms: to_ms(pair[1].saturating_sub(pair[0])),
+        })
+        .collect()
+}
+
+/// Replays the read back timestamps as GPU zones of a Tracy client, if one is running.
+#[cfg(feature = "tracy")]
+#[derive(Default)]
+struct TracyZones {
+    context: Option<tracy_client::GpuContext>,
+}
+
+#[cfg(feature = "tracy")]
+impl TracyZones {
+    fn emit(&mut self, marks: &[&'static str], timestamps: &[u64], timestamp_period: f32) {
+        let Some(client) = tracy_client::Client::running() else {
+            return;
+        };
+        // the first frame that was read back calibrates the GPU clock, close enough to line
+        // the zones up with the CPU spans of the frame
+        if self.context.is_none() {
+            self.context = client
+                .new_gpu_context(
+                    Some("frame"),
+                    tracy_client::GpuContextType::Vulkan,
+                    timestamps[0] as i64,
+                    timestamp_period,
+                )
+                .ok();
+        }
+        let Some(context) = &self.context else {
+            return;
+        };
+        let span = |name: &str| context.span_alloc(name, "GpuTimer::read_back", file!(), line!());
+        // zones nest by the order they are opened and closed in, the timestamps follow later
+        let Ok(mut frame) = span("frame") else {
+            return;
+        };
+        for (name, pair) in marks.iter().zip(timestamps.windows(2)) {
+            if let Ok(mut pass) = span(name) {
+                pass.end_zone();
+                pass.upload_timestamp_start(pair[0] as i64);
+                pass.upload_timestamp_end(pair[1] as i64);
+            }
+        }
+        frame.end_zone();
+        frame.upload_timestamp_start(timestamps[0] as i64);
+        frame.upload_timestamp_end(timestamps[timestamps.len() - 1] as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_time_the_span_since_the_previous_timestamp() {
+        let timings = pass_timings(&["ssao", "scene"], &[100, 150, 400, 420], |ticks| {
+            ticks as f32
+        });
+        assert_eq!(
+            timings,
+            vec![
+                PassTiming { name: "ssao", gpu_ms: 50.0 },
+                PassTiming { name: "scene", gpu_ms: 250.0 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::trace;
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    ThreadPool, ThreadPoolBuilder,
+};
+
+/// Accumulated timings of every job run under one name since the last `reset_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct JobStats {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+/// Named, timed jobs on a rayon thread pool. Every parallel stage of the engine goes through
+/// here so the work shows up under one name in `stats` instead of on anonymous threads.
+pub struct JobSystem {
+    pool: ThreadPool,
+    stats: Mutex<HashMap<&'static str, JobStats>>,
+}
+
+static GLOBAL_JOB_SYSTEM: OnceLock<JobSystem> = OnceLock::new();
+
+impl JobSystem {
+    /// `threads` of 0 uses one thread per logical core.
+    pub fn new(threads: usize) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|idx| format!("piplup-job-{idx}"))
+            .build()?;
+        Ok(Self {
+            pool,
+            stats: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The engine wide job system, created with one thread per logical core on first use.
+    pub fn global() -> &'static JobSystem {
+        GLOBAL_JOB_SYSTEM.get_or_init(|| JobSystem::new(0).expect("job thread pool"))
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Runs `job` on the pool and waits for it, nested jobs it starts stay on the pool.
+    pub fn run<R: Send>(&self, name: &'static str, job: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(|| self.timed(name, job))
+    }
+
+    /// Runs `job` for every item in parallel, results keep the order of `items`.
+    pub fn map<T: Sync, R: Send>(
+        &self,
+        name: &'static str,
+        items: &[T],
+        job: impl Fn(&T) -> R + Sync + Send,
+    ) -> Vec<R> {
+        self.pool.install(|| {
+            items
+                .par_iter()
+                .map(|item| self.timed(name, || job(item)))
+                .collect()
+        })
+    }
+
+    /// Runs `job` on the pool without waiting for it, results have to be sent back by the job.
+    pub fn spawn(&'static self, name: &'static str, job: impl FnOnce() + Send + 'static) {
+        self.pool.spawn(move || self.timed(name, job));
+    }
+
+    /// Jobs spawned on the `JobScope` may borrow from the caller, all of them have finished
+    /// when this returns.
+    pub fn scope<'scope, R: Send>(
+        &'scope self,
+        body: impl FnOnce(&JobScope<'_, 'scope>) -> R + Send,
+    ) -> R {
+        self.pool.scope(|scope| {
+            body(&JobScope {
+                scope,
+                system: self,
+            })
+        })
+    }
+
+    pub fn stats(&self) -> Vec<(&'static str, JobStats)> {
+        let mut stats: Vec<_> = self
+            .stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| (*name, *stats))
+            .collect();
+        stats.sort_by_key(|(name, _)| *name);
+        stats
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    fn timed<R>(&self, name: &'static str, job: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = job();
+        let elapsed = start.elapsed();
+        trace!("job {name} took {elapsed:?}");
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(name).or_default();
+        stats.count += 1;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+        result
+    }
+}
+
+pub struct JobScope<'a, 'scope> {
+    scope: &'a rayon::Scope<'scope>,
+    system: &'scope JobSystem,
+}
+
+impl<'scope> JobScope<'_, 'scope> {
+    pub fn spawn(&self, name: &'static str, job: impl FnOnce() + Send + 'scope) {
+        let system = self.system;
+        self.scope.spawn(move |_| system.timed(name, job));
+    }
+}
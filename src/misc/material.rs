@@ -2,40 +2,68 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use ash::vk::{
-    ColorComponentFlags, CullModeFlags, DescriptorSetLayout, DescriptorSetLayoutCreateFlags,
-    DescriptorType, DynamicState, Extent2D, FrontFace, ImageLayout, PipelineLayout,
-    PolygonMode, PrimitiveTopology, SampleCountFlags, ShaderStageFlags,
+    ColorComponentFlags, CullModeFlags, DescriptorSetLayout, DynamicState, Extent2D, FrontFace,
+    PipelineLayout, PolygonMode,
+    PipelineColorBlendAttachmentState, PrimitiveTopology, ShaderStageFlags,
 };
-use nalgebra::{Matrix4, Vector4};
+use log::warn;
+use nalgebra::{Matrix4, Vector3, Vector4};
 
 use crate::{
     components::{
-        allocation_types::{AllocatedImage, VkBuffer},
-        descriptors::{
-            DescriptorAllocator, DescriptorLayoutBuilder, DescriptorSetDetails, DescriptorWriter,
-        },
+        allocation_types::AllocatedImage,
+        bindless::BindlessDescriptors,
         device::VkDevice,
         pipeline::{
             additive_blending, create_color_blending_attachment_state, create_multisampling_state,
-            create_rasterizer_state, ShaderInformation, VkPipeline,
+            create_rasterizer_state, ShaderInformation, VertexInput, VkPipeline,
         },
         render_pass::VkRenderPass,
         sampler::VkSampler,
     },
-    geom::push_constants::PushConstant,
+    geom::push_constants::{PushConstant, PushConstantLayout},
 };
 
+pub const DEFAULT_VERTEX_SHADER: &str =
+    "/Users/zapzap/Projects/piplup/shaders/scene_data_mesh.vert.spv";
+pub const DEFAULT_FRAGMENT_SHADER: &str =
+    "/Users/zapzap/Projects/piplup/shaders/scene_data_mesh.frag.spv";
+/// Unlit, shows the color texture tiled, bound to the magenta checkerboard for the error material.
+pub const ERROR_FRAGMENT_SHADER: &str =
+    "/Users/zapzap/Projects/piplup/shaders/error_material.frag.spv";
+/// `DEFAULT_VERTEX_SHADER` moving the vertices with the joints of their skin.
+pub const SKINNED_VERTEX_SHADER: &str =
+    "/Users/zapzap/Projects/piplup/shaders/scene_data_mesh_skinned.vert.spv";
+
 #[derive(Clone, Debug, Default)]
 pub struct MaterialPipeline {
     pub pipeline: VkPipeline,
     pub pipeline_layout: PipelineLayout,
+    /// Surfaces can be drawn into the G-buffer of the deferred path instead, only the opaque
+    /// pipeline of the default fragment shader, which `shaders/gbuffer.frag` mirrors.
+    pub deferred: bool,
+    /// Built from `SKINNED_VERTEX_SHADER`, draws push the joint matrices of the mesh.
+    pub skinned: bool,
+}
+
+impl MaterialPipeline {
+    fn skinned(pipeline: VkPipeline) -> Self {
+        Self {
+            pipeline_layout: pipeline.pipeline_layout,
+            pipeline,
+            deferred: false,
+            skinned: true,
+        }
+    }
 }
 
 #[repr(C)]
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct MaterialConstants {
     pub color_factors: Vector4<f32>,
     pub metal_rough_factors: Vector4<f32>,
+    /// rgb is the emissive factor, w the occlusion strength.
+    pub emissive_factors: Vector4<f32>,
     //padding
     extra: Vector4<f32>,
 }
@@ -48,9 +76,36 @@ impl MaterialConstants {
         Self {
             color_factors,
             metal_rough_factors,
+            emissive_factors: Vector4::new(0.0, 0.0, 0.0, 1.0),
             ..Default::default()
         }
     }
+
+    pub fn emissive(mut self, emissive_factor: Vector3<f32>, occlusion_strength: f32) -> Self {
+        self.emissive_factors = emissive_factor.push(occlusion_strength);
+        self
+    }
+}
+
+/// Slots of the material textures in the bindless texture array.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MaterialTextureIndices {
+    pub color: u32,
+    pub metal_rough: u32,
+    pub normal: u32,
+    pub emissive: u32,
+    pub occlusion: u32,
+    _padding: [u32; 3],
+}
+
+/// Element of the bindless material buffer, must match `MaterialData` in
+/// `scene_data_input.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BindlessMaterial {
+    pub constants: MaterialConstants,
+    pub textures: MaterialTextureIndices,
 }
 
 pub struct MaterialResources {
@@ -58,16 +113,35 @@ pub struct MaterialResources {
     pub color_sampler: VkSampler,
     pub metal_rough_image: AllocatedImage,
     pub metal_rough_sampler: VkSampler,
-    pub data_buffer: VkBuffer,
-    pub buffer_offset: u64,
+    /// Tangent space normal map, a flat (0.5, 0.5, 1.0) image for materials without one.
+    pub normal_image: AllocatedImage,
+    pub normal_sampler: VkSampler,
+    /// Black for materials that don't emit light.
+    pub emissive_image: AllocatedImage,
+    pub emissive_sampler: VkSampler,
+    /// Ambient occlusion in the red channel, white for materials without one.
+    pub occlusion_image: AllocatedImage,
+    pub occlusion_sampler: VkSampler,
+    pub constants: MaterialConstants,
 }
 
 #[repr(C)]
 #[derive(Default, Debug, Clone)]
 pub struct MaterialInstance {
     pub pipeline: MaterialPipeline,
-    pub material_set: DescriptorSetDetails,
+    /// Index into the bindless material buffer, pushed with every draw.
+    pub material_index: u32,
     pub pass: MaterialPass,
+    /// Replaces `pipeline` for skinned meshes, `None` if the skinned shader failed to build.
+    pub skinned_pipeline: Option<MaterialPipeline>,
+}
+
+impl MaterialInstance {
+    /// Whether the deferred path shades this material from the G-buffer, everything else is
+    /// drawn forward.
+    pub fn deferred(&self) -> bool {
+        self.pipeline.deferred && self.pass != MaterialPass::GLTF_PBR_TRANSPARENT
+    }
 }
 
 #[allow(warnings)]
@@ -82,171 +156,216 @@ pub enum MaterialPass {
 pub struct MaterialMetallicRoughness {
     opaque_pipeline: MaterialPipeline,
     transparent_pipeline: MaterialPipeline,
-    material_layout: DescriptorSetLayout,
-    writer: DescriptorWriter,
+    /// Opaque and transparent pipelines for skinned meshes.
+    skinned_pipelines: Option<(MaterialPipeline, MaterialPipeline)>,
 }
 
 impl MaterialMetallicRoughness {
+    /// `scene_data_layout` is set 0 with the `SceneData` uniform buffer, shared by every mesh
+    /// pipeline. `bindless_layout` is the layout of the `BindlessDescriptors` set the materials
+    /// are written to, it is bound as set 1. The pipelines are named after `name` for debuggers.
     pub fn build_pipelines(
+        name: &str,
         device: Arc<VkDevice>,
         extent: &Extent2D,
         render_pass: Arc<VkRenderPass>,
+        scene_data_layout: DescriptorSetLayout,
+        bindless_layout: DescriptorSetLayout,
     ) -> Result<MaterialMetallicRoughness> {
         // TODO adjust path
+        Self::build_pipelines_with_shaders(
+            name,
+            device,
+            extent,
+            render_pass,
+            scene_data_layout,
+            bindless_layout,
+            DEFAULT_VERTEX_SHADER,
+            DEFAULT_FRAGMENT_SHADER,
+        )
+    }
+
+    /// Same as `build_pipelines` with custom SPIR-V shaders, they have to use the descriptor
+    /// layout and push constant of `scene_data_mesh`.
+    pub fn build_pipelines_with_shaders(
+        name: &str,
+        device: Arc<VkDevice>,
+        extent: &Extent2D,
+        render_pass: Arc<VkRenderPass>,
+        scene_data_layout: DescriptorSetLayout,
+        bindless_layout: DescriptorSetLayout,
+        vertex_shader: &str,
+        fragment_shader: &str,
+    ) -> Result<MaterialMetallicRoughness> {
         let shader_modules = [
-            ShaderInformation::vertex_2d_information(
-                "/Users/zapzap/Projects/piplup/shaders/scene_data_mesh.vert.spv".to_string(),
-            ),
-            ShaderInformation::fragment_2d_information(
-                "/Users/zapzap/Projects/piplup/shaders/scene_data_mesh.frag.spv".to_string(),
-            ),
+            ShaderInformation::vertex_2d_information(vertex_shader.to_string()),
+            ShaderInformation::fragment_2d_information(fragment_shader.to_string()),
         ];
 
-        let mut layout_builder = DescriptorLayoutBuilder::new();
-        layout_builder.add_binding(0, DescriptorType::UNIFORM_BUFFER, ShaderStageFlags::VERTEX);
-        layout_builder.add_binding(
-            1,
-            DescriptorType::COMBINED_IMAGE_SAMPLER,
-            ShaderStageFlags::FRAGMENT,
-        );
-        layout_builder.add_binding(
-            2,
-            DescriptorType::COMBINED_IMAGE_SAMPLER,
-            ShaderStageFlags::FRAGMENT,
-        );
-
-        let layout = layout_builder.build(
-            device.clone(),
-            ShaderStageFlags::empty(),
-            DescriptorSetLayoutCreateFlags::empty(),
+        let layouts = [scene_data_layout, bindless_layout];
+        let opaque_blending = create_color_blending_attachment_state(
+            ColorComponentFlags::R
+                | ColorComponentFlags::G
+                | ColorComponentFlags::B
+                | ColorComponentFlags::A,
+            false,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
         );
-
-        let opaque_pipeline = VkPipeline::create_new_pipeline(
+        let opaque_pipeline = mesh_pipeline(
             device.clone(),
-            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
-            PrimitiveTopology::TRIANGLE_LIST,
-            ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
             &shader_modules,
-            Some(&[
-                DescriptorLayoutBuilder::new()
-                    .add_binding(
-                        0,
-                        DescriptorType::UNIFORM_BUFFER,
-                        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
-                    )
-                    .build(
-                        device.clone(),
-                        ShaderStageFlags::empty(), // Not actually used by any binding here, just for consistency
-                        DescriptorSetLayoutCreateFlags::empty(),
-                    ),
-                layout,
-            ]),
+            &layouts,
             extent,
-            Some(PushConstant::<Matrix4<f32>>::default()),
-            vec![],
-            vec![],
-            &[create_color_blending_attachment_state(
-                ColorComponentFlags::R
-                    | ColorComponentFlags::G
-                    | ColorComponentFlags::B
-                    | ColorComponentFlags::A,
-                false,
-                None,
-                None,
-                None,
-                None,
-                None,
-                None,
-            )],
-            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
-            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            opaque_blending,
             render_pass.clone(),
             true,
         )?;
-        let transparent_pipeline = VkPipeline::create_new_pipeline(
+        let transparent_pipeline = mesh_pipeline(
             device.clone(),
-            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
-            PrimitiveTopology::TRIANGLE_LIST,
-            ShaderStageFlags::VERTEX,
             &shader_modules,
-            Some(&[
-                DescriptorLayoutBuilder::new()
-                    .add_binding(
-                        0,
-                        DescriptorType::UNIFORM_BUFFER,
-                        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
-                    )
-                    .build(
-                        device.clone(),
-                        ShaderStageFlags::empty(), // Not actually used by any binding here, just for consistency
-                        DescriptorSetLayoutCreateFlags::empty(),
-                    ),
-                layout,
-            ]),
+            &layouts,
             &Extent2D::default(),
-            Some(PushConstant::<Matrix4<f32>>::default()),
-            vec![],
-            vec![],
-            &[additive_blending()],
-            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
-            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            additive_blending(),
             render_pass.clone(),
             false,
         )?;
+        // skinned meshes fall back to their rest pose if the skinned shader is missing
+        let skinned_shader_modules = [
+            ShaderInformation::vertex_2d_information(SKINNED_VERTEX_SHADER.to_string()),
+            ShaderInformation::fragment_2d_information(fragment_shader.to_string()),
+        ];
+        let skinned_pipelines = mesh_pipeline(
+            device.clone(),
+            &skinned_shader_modules,
+            &layouts,
+            extent,
+            opaque_blending,
+            render_pass.clone(),
+            true,
+        )
+        .and_then(|opaque| {
+            let transparent = mesh_pipeline(
+                device.clone(),
+                &skinned_shader_modules,
+                &layouts,
+                &Extent2D::default(),
+                additive_blending(),
+                render_pass.clone(),
+                false,
+            )?;
+            Ok((opaque, transparent))
+        });
+        let skinned_pipelines = match skinned_pipelines {
+            Ok((opaque, transparent)) => {
+                device.debug_utils.name(*opaque, &format!("{name} skinned opaque"));
+                device
+                    .debug_utils
+                    .name(*transparent, &format!("{name} skinned transparent"));
+                Some((
+                    MaterialPipeline::skinned(opaque),
+                    MaterialPipeline::skinned(transparent),
+                ))
+            }
+            Err(err) => {
+                warn!("{name} has no skinned pipelines: {err}");
+                None
+            }
+        };
+        device
+            .debug_utils
+            .name(*opaque_pipeline, &format!("{name} opaque"));
+        device
+            .debug_utils
+            .name(*transparent_pipeline, &format!("{name} transparent"));
 
         Ok(Self {
             opaque_pipeline: MaterialPipeline {
-                pipeline: opaque_pipeline,
                 pipeline_layout: opaque_pipeline.pipeline_layout,
+                pipeline: opaque_pipeline,
+                deferred: fragment_shader == DEFAULT_FRAGMENT_SHADER,
+                skinned: false,
             },
             transparent_pipeline: MaterialPipeline {
                 pipeline_layout: transparent_pipeline.pipeline_layout,
                 pipeline: transparent_pipeline,
+                deferred: false,
+                skinned: false,
             },
-            material_layout: layout,
-            writer: DescriptorWriter::new(),
+            skinned_pipelines,
         })
     }
 
+    /// Adds the textures and constants of `resources` to `bindless` and returns an instance
+    /// drawing with them.
     pub fn write_material(
-        mut self,
-        device: Arc<VkDevice>,
+        self,
         material_pass: MaterialPass,
         resources: MaterialResources,
-        descriptor_allocator: &mut DescriptorAllocator,
+        bindless: &mut BindlessDescriptors,
     ) -> Result<MaterialInstance> {
         let mut pipeline = self.opaque_pipeline;
+        let mut skinned_pipeline = self.skinned_pipelines.clone().map(|(opaque, _)| opaque);
         if material_pass.eq(&MaterialPass::GLTF_PBR_TRANSPARENT) {
             pipeline = self.transparent_pipeline;
+            skinned_pipeline = self.skinned_pipelines.map(|(_, transparent)| transparent);
         }
-        let descriptor_set = descriptor_allocator.allocate(device.clone(), &[self.material_layout]);
-        self.writer.clear();
-        self.writer.write_buffer(
-            0,
-            resources.data_buffer,
-            size_of::<MaterialConstants>() as u64,
-            resources.buffer_offset,
-            DescriptorType::UNIFORM_BUFFER,
-        );
-        self.writer.write_image(
-            1,
-            resources.color_image.image_details.image_view,
-            Some(resources.color_sampler),
-            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            DescriptorType::COMBINED_IMAGE_SAMPLER,
-        );
-        self.writer.write_image(
-            2,
-            resources.metal_rough_image.image_details.image_view,
-            Some(resources.metal_rough_sampler),
-            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            DescriptorType::COMBINED_IMAGE_SAMPLER,
-        );
-        self.writer.update_set(device.clone(), descriptor_set[0]);
+        let mut texture_index = |image: AllocatedImage, sampler: VkSampler| {
+            bindless.texture_index(image.image_details.image_view, &sampler)
+        };
+        let textures = MaterialTextureIndices {
+            color: texture_index(resources.color_image, resources.color_sampler)?,
+            metal_rough: texture_index(resources.metal_rough_image, resources.metal_rough_sampler)?,
+            normal: texture_index(resources.normal_image, resources.normal_sampler)?,
+            emissive: texture_index(resources.emissive_image, resources.emissive_sampler)?,
+            occlusion: texture_index(resources.occlusion_image, resources.occlusion_sampler)?,
+            ..Default::default()
+        };
+        let material_index = bindless.add_material(BindlessMaterial {
+            constants: resources.constants,
+            textures,
+        })?;
         Ok(MaterialInstance {
             pipeline: pipeline,
-            material_set: descriptor_set,
+            material_index,
             pass: material_pass,
+            skinned_pipeline,
         })
     }
 }
+
+/// A pipeline of the `scene_data_mesh` layout, `depth_test` also writes depth.
+fn mesh_pipeline(
+    device: Arc<VkDevice>,
+    shader_modules: &[ShaderInformation],
+    layouts: &[DescriptorSetLayout],
+    extent: &Extent2D,
+    blending: PipelineColorBlendAttachmentState,
+    render_pass: Arc<VkRenderPass>,
+    depth_test: bool,
+) -> Result<VkPipeline> {
+    Ok(VkPipeline::create_new_pipeline(
+        device,
+        &[
+            DynamicState::SCISSOR,
+            DynamicState::VIEWPORT,
+            DynamicState::DEPTH_COMPARE_OP,
+        ],
+        PrimitiveTopology::TRIANGLE_LIST,
+        shader_modules,
+        Some(layouts),
+        extent,
+        Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+            ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+        )),
+        VertexInput::Pulling,
+        &[blending],
+        create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+        create_multisampling_state(false, render_pass.samples(), 1.0, false, false),
+        render_pass,
+        depth_test)?)
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use ash::vk::{DescriptorSetLayout, Extent2D, ImageUsageFlags};
+use image::RgbaImage;
+use nalgebra::{Vector3, Vector4};
+use serde::Deserialize;
+
+use crate::{
+    components::{
+        allocation_types::AllocatedImage,
+        bindless::BindlessDescriptors,
+        command_buffers::VkCommandPool,
+        deletion_queue::{DeletionQueue, FType},
+        device::VkDevice,
+        memory_allocator::MemoryAllocator,
+        render_pass::VkRenderPass,
+        sampler::VkSampler,
+    },
+    geom::assets::GLTFMaterial,
+};
+
+use super::{
+    file_watcher::FileWatcher,
+    material::{
+        MaterialConstants, MaterialMetallicRoughness, MaterialPass, MaterialResources,
+        DEFAULT_FRAGMENT_SHADER, DEFAULT_VERTEX_SHADER,
+    },
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaterialPassDefinition {
+    #[default]
+    Opaque,
+    Transparent,
+}
+
+/// Image files of a material, missing ones fall back to neutral defaults.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct MaterialTextures {
+    pub color: Option<PathBuf>,
+    pub metal_rough: Option<PathBuf>,
+    pub normal: Option<PathBuf>,
+    pub emissive: Option<PathBuf>,
+    pub occlusion: Option<PathBuf>,
+}
+
+/// Decoded images of a material, like the ones embedded in a glTF file. They are used instead of
+/// the files of the same slot in `MaterialTextures`.
+#[derive(Debug, Default, Clone)]
+pub struct MaterialImages {
+    pub color: Option<Arc<RgbaImage>>,
+    pub metal_rough: Option<Arc<RgbaImage>>,
+    pub normal: Option<Arc<RgbaImage>>,
+    pub emissive: Option<Arc<RgbaImage>>,
+    pub occlusion: Option<Arc<RgbaImage>>,
+}
+
+/// A material as written in a material file:
+///
+/// ```toml
+/// [[material]]
+/// name = "gold"
+/// pass = "opaque"
+/// color_factors = [1.0, 0.8, 0.3, 1.0]
+/// metal_rough_factors = [1.0, 0.3, 0.0, 0.0]
+///
+/// [material.textures]
+/// color = "textures/gold.png"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaterialDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub pass: MaterialPassDefinition,
+    /// SPIR-V files, `scene_data_mesh` when not set.
+    pub vertex_shader: Option<PathBuf>,
+    pub fragment_shader: Option<PathBuf>,
+    #[serde(default = "default_color_factors")]
+    pub color_factors: [f32; 4],
+    #[serde(default = "default_metal_rough_factors")]
+    pub metal_rough_factors: [f32; 4],
+    #[serde(default)]
+    pub emissive_factor: [f32; 3],
+    #[serde(default = "default_occlusion_strength")]
+    pub occlusion_strength: f32,
+    #[serde(default)]
+    pub textures: MaterialTextures,
+    #[serde(skip)]
+    pub images: MaterialImages,
+}
+
+fn default_color_factors() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn default_metal_rough_factors() -> [f32; 4] {
+    [1.0, 0.5, 0.0, 0.0]
+}
+
+fn default_occlusion_strength() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct MaterialFile {
+    #[serde(default, rename = "material")]
+    materials: Vec<MaterialDefinition>,
+}
+
+impl MaterialDefinition {
+    /// An opaque material with the defaults of a material file that only sets `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            pass: MaterialPassDefinition::default(),
+            vertex_shader: None,
+            fragment_shader: None,
+            color_factors: default_color_factors(),
+            metal_rough_factors: default_metal_rough_factors(),
+            emissive_factor: [0.0; 3],
+            occlusion_strength: default_occlusion_strength(),
+            textures: MaterialTextures::default(),
+            images: MaterialImages::default(),
+        }
+    }
+
+    /// Parses every `[[material]]` table of a TOML file, relative shader and texture paths are
+    /// resolved against the directory of the file.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Vec<MaterialDefinition>> {
+        let source = fs::read_to_string(&path)?;
+        Self::parse(&source, path.as_ref().parent().unwrap_or(Path::new("")))
+    }
+
+    pub fn parse(source: &str, base_directory: &Path) -> Result<Vec<MaterialDefinition>> {
+        let file: MaterialFile = toml::from_str(source)?;
+        Ok(file
+            .materials
+            .into_iter()
+            .map(|definition| definition.resolve_paths(base_directory))
+            .collect())
+    }
+
+    fn resolve_paths(mut self, base_directory: &Path) -> Self {
+        let textures = &mut self.textures;
+        [
+            &mut self.vertex_shader,
+            &mut self.fragment_shader,
+            &mut textures.color,
+            &mut textures.metal_rough,
+            &mut textures.normal,
+            &mut textures.emissive,
+            &mut textures.occlusion,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_relative())
+        .for_each(|path| *path = base_directory.join(&*path));
+        self
+    }
+
+    fn constants(&self) -> MaterialConstants {
+        MaterialConstants::new(
+            Vector4::from(self.color_factors),
+            Vector4::from(self.metal_rough_factors),
+        )
+        .emissive(Vector3::from(self.emissive_factor), self.occlusion_strength)
+    }
+}
+
+/// Images used for textures a material definition leaves out.
+pub struct MaterialDefaults {
+    pub white_image: AllocatedImage,
+    pub black_image: AllocatedImage,
+    pub flat_normal_image: AllocatedImage,
+    pub sampler: VkSampler,
+}
+
+/// Outcome of `MaterialLibrary::register`.
+pub struct Registration {
+    /// The built material, or the error material if building it failed.
+    pub material: Arc<GLTFMaterial>,
+    /// Material previously registered under the same name.
+    pub replaced: Option<Arc<GLTFMaterial>>,
+    pub error: Option<anyhow::Error>,
+}
+
+/// Materials registered by name, either from code or from material files. Registering a name
+/// again replaces the material, watched files are re-registered whenever they change on disk.
+pub struct MaterialLibrary {
+    device: Arc<VkDevice>,
+    memory_allocator: Arc<MemoryAllocator>,
+    command_pool: VkCommandPool,
+    render_pass: Arc<VkRenderPass>,
+    extent: Extent2D,
+    /// Set 0 of the material pipelines, see `MaterialMetallicRoughness::build_pipelines`.
+    scene_data_layout: DescriptorSetLayout,
+    defaults: MaterialDefaults,
+    error_material: Arc<GLTFMaterial>,
+    materials: HashMap<String, Arc<GLTFMaterial>>,
+    watched_files: FileWatcher,
+}
+
+impl MaterialLibrary {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: Arc<MemoryAllocator>,
+        command_pool: VkCommandPool,
+        render_pass: Arc<VkRenderPass>,
+        extent: Extent2D,
+        scene_data_layout: DescriptorSetLayout,
+        defaults: MaterialDefaults,
+        error_material: Arc<GLTFMaterial>,
+    ) -> Self {
+        Self {
+            device,
+            memory_allocator,
+            command_pool,
+            render_pass,
+            extent,
+            scene_data_layout,
+            defaults,
+            error_material,
+            materials: HashMap::new(),
+            watched_files: FileWatcher::default(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<GLTFMaterial>> {
+        self.materials.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.materials.keys().map(String::as_str)
+    }
+
+    /// Set 0 of the material pipelines, for passes drawing the same surfaces.
+    pub fn scene_data_layout(&self) -> DescriptorSetLayout {
+        self.scene_data_layout
+    }
+
+    /// Builds the pipelines, textures and constants of `definition` and stores the material
+    /// under its name. A material that fails to build is stored as the error material, so it
+    /// can be fixed and registered again. GPU resources are released with `deletion_queue`
+    /// since frames in flight may still use a replaced material.
+    pub fn register(
+        &mut self,
+        definition: &MaterialDefinition,
+        bindless: &mut BindlessDescriptors,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Registration {
+        let (material, error) = match self.build(definition, bindless, deletion_queue) {
+            Ok(material) => (material, None),
+            // a separate Arc per name, replacing it must not touch other broken materials
+            Err(err) => (
+                Arc::new(GLTFMaterial {
+                    data: self.error_material.data.clone(),
+                }),
+                Some(err),
+            ),
+        };
+        let replaced = self
+            .materials
+            .insert(definition.name.clone(), material.clone());
+        Registration {
+            material,
+            replaced,
+            error,
+        }
+    }
+
+    fn build(
+        &self,
+        definition: &MaterialDefinition,
+        bindless: &mut BindlessDescriptors,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Arc<GLTFMaterial>> {
+        let shader_path = |path: &Option<PathBuf>, default: &str| {
+            path.as_ref().map_or(default.to_string(), |path| {
+                path.to_string_lossy().into_owned()
+            })
+        };
+        let pipelines = MaterialMetallicRoughness::build_pipelines_with_shaders(
+            &definition.name,
+            self.device.clone(),
+            &self.extent,
+            self.render_pass.clone(),
+            self.scene_data_layout,
+            bindless.layout,
+            &shader_path(&definition.vertex_shader, DEFAULT_VERTEX_SHADER),
+            &shader_path(&definition.fragment_shader, DEFAULT_FRAGMENT_SHADER),
+        )?;
+
+        let (textures, images) = (&definition.textures, &definition.images);
+        let defaults = &self.defaults;
+        let color_image = self.load_texture(
+            &textures.color,
+            &images.color,
+            true,
+            &defaults.white_image,
+            deletion_queue,
+        )?;
+        let metal_rough_image = self.load_texture(
+            &textures.metal_rough,
+            &images.metal_rough,
+            false,
+            &defaults.white_image,
+            deletion_queue,
+        )?;
+        let normal_image = self.load_texture(
+            &textures.normal,
+            &images.normal,
+            false,
+            &defaults.flat_normal_image,
+            deletion_queue,
+        )?;
+        let emissive_image = self.load_texture(
+            &textures.emissive,
+            &images.emissive,
+            true,
+            &defaults.black_image,
+            deletion_queue,
+        )?;
+        let occlusion_image = self.load_texture(
+            &textures.occlusion,
+            &images.occlusion,
+            false,
+            &defaults.white_image,
+            deletion_queue,
+        )?;
+
+        let sampler = &self.defaults.sampler;
+        let material_pass = match definition.pass {
+            MaterialPassDefinition::Opaque => MaterialPass::GLTF_PBR_OPAQUE,
+            MaterialPassDefinition::Transparent => MaterialPass::GLTF_PBR_TRANSPARENT,
+        };
+        let data = pipelines.write_material(
+            material_pass,
+            MaterialResources {
+                color_image,
+                color_sampler: sampler.clone(),
+                metal_rough_image,
+                metal_rough_sampler: sampler.clone(),
+                normal_image,
+                normal_sampler: sampler.clone(),
+                emissive_image,
+                emissive_sampler: sampler.clone(),
+                occlusion_image,
+                occlusion_sampler: sampler.clone(),
+                constants: definition.constants(),
+            },
+            bindless,
+        )?;
+        Ok(Arc::new(GLTFMaterial { data }))
+    }
+
+    /// Uploads `image`, or else the file at `path`, falling back to `fallback` without either.
+    fn load_texture(
+        &self,
+        path: &Option<PathBuf>,
+        image: &Option<Arc<RgbaImage>>,
+        srgb: bool,
+        fallback: &AllocatedImage,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<AllocatedImage> {
+        let texture = match (image, path) {
+            (Some(image), _) => self.memory_allocator.create_image_from_rgba(
+                image,
+                srgb,
+                true,
+                ImageUsageFlags::SAMPLED,
+                &self.command_pool,
+            )?,
+            (None, Some(path)) => {
+                let texture = self.memory_allocator.create_image_from_file(
+                    path,
+                    srgb,
+                    true,
+                    ImageUsageFlags::SAMPLED,
+                    &self.command_pool,
+                )?;
+                self.device
+                    .debug_utils
+                    .name(texture.image_details.image, &path.to_string_lossy());
+                texture
+            }
+            (None, None) => return Ok(fallback.clone()),
+        };
+        deletion_queue.enqueue(FType::DROP(Box::new(texture.clone())));
+        Ok(texture)
+    }
+
+    /// Remembers the current modification time of `path` for `changed_files`.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) {
+        self.watched_files.watch(path);
+    }
+
+    /// Watched files whose modification time changed since the last call.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        self.watched_files.changed_files()
+    }
+}
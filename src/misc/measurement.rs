@@ -0,0 +1,65 @@
+use ash::vk::Viewport;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use super::debug_draw::DebugDraw;
+
+/// Size of the ticks at both ends of a measurement line, relative to its length.
+const TICK_SCALE: f32 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeasurementId(pub usize);
+
+/// A distance between two world space points, drawn as a line with its length written next
+/// to the midpoint until it is removed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub from: Vector3<f32>,
+    pub to: Vector3<f32>,
+    pub color: Vector4<f32>,
+}
+
+impl Measurement {
+    pub fn new(from: Vector3<f32>, to: Vector3<f32>, color: Vector4<f32>) -> Self {
+        Self { from, to, color }
+    }
+
+    pub fn distance(&self) -> f32 {
+        (self.to - self.from).norm()
+    }
+
+    pub fn midpoint(&self) -> Vector3<f32> {
+        (self.from + self.to) * 0.5
+    }
+
+    pub fn label(&self) -> String {
+        format!("{:.3}", self.distance())
+    }
+
+    /// Pushes the line and a small cross at both end points.
+    pub fn draw(&self, debug_draw: &mut DebugDraw) {
+        debug_draw.line(self.from, self.to, self.color);
+        let tick = (self.distance() * TICK_SCALE).max(f32::EPSILON);
+        for point in [self.from, self.to] {
+            for axis in [Vector3::x(), Vector3::y(), Vector3::z()] {
+                debug_draw.line(point - axis * tick, point + axis * tick, self.color);
+            }
+        }
+    }
+}
+
+/// Position of `point` inside `viewport` in physical pixels, `None` behind the camera.
+pub fn project_to_viewport(
+    view_proj: Matrix4<f32>,
+    point: Vector3<f32>,
+    viewport: &Viewport,
+) -> Option<[f32; 2]> {
+    let clip = view_proj * point.push(1.0);
+    if clip.w <= f32::EPSILON {
+        return None;
+    }
+    let ndc = clip.xy() / clip.w;
+    Some([
+        viewport.x + (ndc.x * 0.5 + 0.5) * viewport.width,
+        viewport.y + (ndc.y * 0.5 + 0.5) * viewport.height,
+    ])
+}
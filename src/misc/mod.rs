@@ -1,18 +1,138 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, ops::AddAssign};
 
-use nalgebra::Matrix4;
+use ash::vk::Handle;
+use nalgebra::{Matrix4, Vector3};
+use material::MaterialInstance;
+
+use crate::geom::{bounds::Ray, lod::LodSettings};
 use render_object::RenderObject;
 
+pub mod analysis;
+pub mod animation_player;
+pub mod asset_reload;
+pub mod asset_registry;
+pub mod asset_server;
+pub mod auto_quality;
 pub mod render_object;
 pub mod material;
+pub mod material_library;
 pub mod camera;
+pub mod debug_draw;
+pub mod deferred;
+pub mod depth_pick;
+pub mod depth_prepass;
+pub mod display_mode;
+pub mod display_transform;
+pub mod file_watcher;
+pub mod gizmo;
+pub mod gpu_timer;
+pub mod post_process;
+pub mod skinning;
+pub mod skybox;
+pub mod snapping;
+pub mod sparse_feedback;
+pub mod ssao;
+pub mod measurement;
+pub mod tween;
+pub mod views;
+pub mod jobs;
 
 pub struct DrawContext {
-    pub opaque_surfaces: Vec<RenderObject>
+    pub opaque_surfaces: Vec<RenderObject>,
+    /// Drawn for surfaces that have no material.
+    pub error_material: MaterialInstance,
+    /// World position the level of detail of every surface is picked by.
+    pub camera_position: Vector3<f32>,
+    pub lod_settings: LodSettings,
+    /// Joint matrices of the skinned surfaces, `RenderObject::joint_offset` indexes into them.
+    pub joint_matrices: Vec<Matrix4<f32>>,
+    /// Seconds on the clock animations are played back by, advanced with every frame.
+    pub animation_time: f32,
+    /// Set by the nodes whose animation is still running, the next frame changes their pose.
+    pub animating: bool,
+}
+
+impl DrawContext {
+    /// Orders the surfaces by pipeline, then material and mesh buffers, so consecutive draws
+    /// share as much bound state as possible. The order within one key is kept.
+    pub fn sort_for_submission(&mut self) {
+        self.opaque_surfaces.sort_by_key(|render_obj| {
+            (
+                render_obj.material.pipeline.pipeline.as_raw(),
+                render_obj.material.material_index,
+                render_obj.index_buffer.buffer.as_raw(),
+            )
+        });
+    }
+}
+
+/// Commands the forward scene draws of the last frame were recorded with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draws: u32,
+    pub instances: u32,
+    pub triangles: u64,
+    pub pipeline_binds: u32,
+    pub descriptor_set_binds: u32,
+    pub index_buffer_binds: u32,
+    pub push_constant_updates: u32,
+}
+
+impl DrawStats {
+    /// Counts an indexed triangle list draw.
+    pub fn record_draw(&mut self, index_count: u32, instance_count: u32) {
+        self.draws += 1;
+        self.instances += instance_count;
+        self.triangles += u64::from(index_count / 3) * u64::from(instance_count);
+    }
+}
+
+impl AddAssign for DrawStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draws += other.draws;
+        self.instances += other.instances;
+        self.triangles += other.triangles;
+        self.pipeline_binds += other.pipeline_binds;
+        self.descriptor_set_binds += other.descriptor_set_binds;
+        self.index_buffer_binds += other.index_buffer_binds;
+        self.push_constant_updates += other.push_constant_updates;
+    }
+}
+
+/// GPU time between two timestamps written into the frame command buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassTiming {
+    pub name: &'static str,
+    pub gpu_ms: f32,
+}
+
+/// What a frame drawn by `Renderer::display` or `Renderer::render_offscreen` cost.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrameStats {
+    pub frame_number: u64,
+    /// The forward scene draws, what `Renderer::draw_stats` returns.
+    pub scene: DrawStats,
+    /// Every mesh draw of the frame, depth pre-pass and g-buffer included.
+    pub draws: DrawStats,
+    /// Buffers and images the memory allocator created while the frame was prepared and recorded.
+    pub buffer_allocations: u64,
+    pub texture_allocations: u64,
+    /// Time spent in the frame on the CPU, from the scene update to the present.
+    pub cpu_ms: f32,
+    /// GPU time and per pass timings of the frame command buffer `MAX_FRAMES` frames back, the
+    /// latest ones that were read back.
+    pub gpu_ms: Option<f32>,
+    pub pass_timings: Vec<PassTiming>,
 }
 
 pub trait Renderable {
     fn draw(&self, top_matrix: Matrix4<f32>, draw_ctx: &mut DrawContext);
+
+    /// Distance along the world space `ray` to the bounds of what `draw` would draw below
+    /// `top_matrix`, `None` if it misses them or there are none to test against.
+    fn ray_cast(&self, _top_matrix: Matrix4<f32>, _ray: &Ray) -> Option<f32> {
+        None
+    }
 }
 
 impl Debug for dyn Renderable {
@@ -0,0 +1,620 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags2, AttachmentLoadOp, ColorComponentFlags, CommandBuffer, CullModeFlags,
+    DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutCreateFlags,
+    DescriptorType, DynamicState, Extent2D, Extent3D, Filter, Format, FrontFace, Image,
+    ImageAspectFlags, ImageLayout, ImageUsageFlags, ImageView, MemoryBarrier2, PipelineBindPoint,
+    PipelineStageFlags2, PolygonMode, PrimitiveTopology, RenderPassBeginInfo, SampleCountFlags,
+    ShaderStageFlags, SubpassContents,
+};
+
+use crate::components::{
+    allocation_types::{AllocatedImage, VkBuffer, VkFrameBuffer, IDENTIFIER},
+    deletion_queue::{DeletionQueue, FType},
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    device::VkDevice,
+    image_util::image_transition,
+    memory_allocator::MemoryAllocator,
+    pipeline::{
+        create_color_blending_attachment_state, create_multisampling_state,
+        create_rasterizer_state, create_scissor, create_viewport, ShaderInformation,
+        VertexInput, VkPipeline,
+    },
+    render_pass::VkRenderPass,
+    sampler::{SamplerCache, VkSampler},
+};
+use crate::geom::push_constants::PushConstantLayout;
+
+/// Format of the post process targets, stays linear HDR until the display transform encodes it.
+const POST_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+/// Size of the push constant range every custom fullscreen pass gets.
+pub const MAX_FULLSCREEN_PASS_CONSTANTS: usize = 128;
+
+/// Curve compressing the HDR scene colors into the displayable range.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Exposure only, values above 1 clip.
+    None,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    #[default]
+    Aces,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 3] = [Tonemapper::None, Tonemapper::Reinhard, Tonemapper::Aces];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Tonemapper::None => "None",
+            Tonemapper::Reinhard => "Reinhard",
+            Tonemapper::Aces => "ACES",
+        }
+    }
+
+    /// Value of `mode` in `shaders/tonemap.frag`.
+    fn shader_mode(self) -> u32 {
+        match self {
+            Tonemapper::None => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::Aces => 2,
+        }
+    }
+}
+
+/// Must match the push constant block of `shaders/tonemap.frag`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TonemapPushConstant {
+    exposure: f32,
+    mode: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FullscreenPassId(pub usize);
+
+/// Where a custom fullscreen pass runs relative to the tonemapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertionPoint {
+    /// Sees the linear HDR scene colors.
+    BeforeTonemap,
+    /// Sees the tonemapped colors, before the display transform encodes them.
+    AfterTonemap,
+}
+
+/// Resource bound to set 1 of a custom fullscreen pass, at the binding matching its position in
+/// the list handed to `add_pass`.
+#[derive(Clone)]
+pub enum FullscreenBinding {
+    /// Sampled image, has to be in SHADER_READ_ONLY_OPTIMAL whenever the pass runs.
+    Texture {
+        image_view: ImageView,
+        sampler: VkSampler,
+    },
+    UniformBuffer {
+        buffer: VkBuffer,
+        size: u64,
+    },
+    StorageBuffer {
+        buffer: VkBuffer,
+        size: u64,
+    },
+}
+
+struct FullscreenPass {
+    id: FullscreenPassId,
+    insertion_point: InsertionPoint,
+    pipeline: VkPipeline,
+    bindings_set: Option<DescriptorSet>,
+    /// Bound in `bindings_set`, live as long as the pass.
+    _samplers: Vec<VkSampler>,
+    _buffers: Vec<VkBuffer>,
+    constants: Vec<u8>,
+}
+
+/// Chain of fullscreen triangle passes between the scene render pass and the display transform.
+/// Every pass samples the result of the previous one (the draw image for the first) as set 0,
+/// binding 0 and the last pass writes `output`, which is what gets encoded and presented.
+/// Passes in between alternate between a scratch image and the draw image.
+pub struct PostProcess {
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    device: Arc<VkDevice>,
+    draw_image: AllocatedImage,
+    output: AllocatedImage,
+    /// Only created once the first custom pass is added.
+    scratch: Option<AllocatedImage>,
+    /// Leaves its target ready to be sampled by the next pass.
+    render_pass: Arc<VkRenderPass>,
+    /// Compatible with `render_pass`, leaves `output` ready to be copied.
+    final_render_pass: Arc<VkRenderPass>,
+    framebuffers: Vec<(Image, VkFrameBuffer)>,
+    input_layout: DescriptorSetLayout,
+    input_sets: Vec<(Image, DescriptorSet)>,
+    sampler: VkSampler,
+    tonemap_pipeline: VkPipeline,
+    passes: Vec<FullscreenPass>,
+    next_pass: usize,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: &MemoryAllocator,
+        extent: Extent2D,
+        draw_image: &AllocatedImage,
+        samplers: &SamplerCache,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let output = Self::create_target(memory_allocator, extent, deletion_queue)?;
+
+        // every pixel is written so the previous contents never matter
+        let render_pass = Arc::new(VkRenderPass::new(
+            device.clone(),
+            POST_FORMAT,
+            ImageLayout::UNDEFINED,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            AttachmentLoadOp::DONT_CARE,
+            false,
+        )?);
+        let final_render_pass = Arc::new(VkRenderPass::new(
+            device.clone(),
+            POST_FORMAT,
+            ImageLayout::UNDEFINED,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            AttachmentLoadOp::DONT_CARE,
+            false,
+        )?);
+
+        let input_layout = DescriptorLayoutBuilder::new()
+            .add_binding(
+                0,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                ShaderStageFlags::FRAGMENT,
+            )
+            .build(
+                device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            );
+        let sampler = samplers.with_filter(Filter::NEAREST, Filter::NEAREST);
+        let tonemap_pipeline = Self::create_pipeline(
+            &device,
+            "/Users/zapzap/Projects/piplup/shaders/tonemap.frag.spv",
+            &[input_layout],
+            extent,
+            PushConstantLayout::of::<TonemapPushConstant>(ShaderStageFlags::FRAGMENT),
+            &render_pass,
+        )?;
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_set_layout(input_layout, None);
+        })));
+
+        let mut post_process = Self {
+            exposure: 1.0,
+            tonemapper: Tonemapper::default(),
+            device,
+            draw_image: draw_image.clone(),
+            output: output.clone(),
+            scratch: None,
+            render_pass,
+            final_render_pass,
+            framebuffers: vec![],
+            input_layout,
+            input_sets: vec![],
+            sampler,
+            tonemap_pipeline,
+            passes: vec![],
+            next_pass: 0,
+        };
+        post_process.add_target(draw_image, true, deletion_queue);
+        post_process.add_target(&output, false, deletion_queue);
+        Ok(post_process)
+    }
+
+    /// Image holding the result of the last recorded frame, in TRANSFER_SRC_OPTIMAL.
+    pub fn output(&self) -> &AllocatedImage {
+        &self.output
+    }
+
+    /// Adds a pass running the fragment shader `shader` (SPIR-V) over the whole target, after
+    /// the passes already added at `insertion_point`. The shader gets the uv of the pixel at
+    /// location 0 from `shaders/fullscreen.vert`, the previous result as
+    /// `layout (set = 0, binding = 0) uniform sampler2D` and `bindings` in set 1.
+    pub fn add_pass(
+        &mut self,
+        memory_allocator: &MemoryAllocator,
+        shader: &str,
+        bindings: Vec<FullscreenBinding>,
+        insertion_point: InsertionPoint,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<FullscreenPassId> {
+        if self.scratch.is_none() {
+            let extent = Extent2D::default()
+                .width(self.output.extent.width)
+                .height(self.output.extent.height);
+            let scratch = Self::create_target(memory_allocator, extent, deletion_queue)?;
+            self.add_target(&scratch, true, deletion_queue);
+            self.scratch = Some(scratch);
+        }
+
+        let mut layout_builder = DescriptorLayoutBuilder::new();
+        let mut writer = DescriptorWriter::new();
+        let mut ratios = vec![];
+        let mut samplers = vec![];
+        let mut buffers = vec![];
+        for (binding, resource) in bindings.into_iter().enumerate() {
+            let binding = binding as u32;
+            let descriptor_type = match resource {
+                FullscreenBinding::Texture {
+                    image_view,
+                    sampler,
+                } => {
+                    samplers.push(sampler.clone());
+                    writer.write_image(
+                        binding,
+                        image_view,
+                        Some(sampler),
+                        ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    );
+                    DescriptorType::COMBINED_IMAGE_SAMPLER
+                }
+                FullscreenBinding::UniformBuffer { buffer, size } => {
+                    writer.write_buffer(binding, &buffer, size, 0, DescriptorType::UNIFORM_BUFFER);
+                    buffers.push(buffer);
+                    DescriptorType::UNIFORM_BUFFER
+                }
+                FullscreenBinding::StorageBuffer { buffer, size } => {
+                    writer.write_buffer(binding, &buffer, size, 0, DescriptorType::STORAGE_BUFFER);
+                    buffers.push(buffer);
+                    DescriptorType::STORAGE_BUFFER
+                }
+            };
+            layout_builder.add_binding(binding, descriptor_type, ShaderStageFlags::FRAGMENT);
+            ratios.push(PoolSizeRatio::new(descriptor_type, 1.0));
+        }
+        let bindings_layout = (!ratios.is_empty()).then(|| {
+            layout_builder.build(
+                self.device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            )
+        });
+
+        let mut layouts = vec![self.input_layout];
+        layouts.extend(bindings_layout);
+        let extent = Extent2D::default()
+            .width(self.output.extent.width)
+            .height(self.output.extent.height);
+        let pipeline = Self::create_pipeline(
+            &self.device,
+            shader,
+            &layouts,
+            extent,
+            PushConstantLayout::new(
+                MAX_FULLSCREEN_PASS_CONSTANTS as u32,
+                ShaderStageFlags::FRAGMENT,
+            ),
+            &self.render_pass,
+        )?;
+
+        let mut bindings_set = None;
+        let mut descriptor_allocator = None;
+        if let Some(bindings_layout) = bindings_layout {
+            let mut allocator = DescriptorAllocator::new(self.device.clone(), 1, ratios);
+            let set = allocator.allocate(self.device.clone(), &[bindings_layout])[0];
+            writer.update_set(self.device.clone(), set);
+            bindings_set = Some(set);
+            descriptor_allocator = Some(allocator);
+        }
+        // frames in flight may still use the set after the pass is removed
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            if let Some(bindings_layout) = bindings_layout {
+                device.destroy_descriptor_set_layout(bindings_layout, None);
+            }
+            if let Some(mut descriptor_allocator) = descriptor_allocator {
+                descriptor_allocator.destroy_pools(device);
+            }
+        })));
+
+        let id = FullscreenPassId(self.next_pass);
+        self.next_pass += 1;
+        self.passes.push(FullscreenPass {
+            id,
+            insertion_point,
+            pipeline,
+            bindings_set,
+            _samplers: samplers,
+            _buffers: buffers,
+            constants: vec![],
+        });
+        Ok(id)
+    }
+
+    /// Takes the pass out of the chain, returns false if it does not exist. Its pipeline is
+    /// dropped once the first `frames` frames completed, the frames in flight may still use it.
+    pub fn remove_pass(
+        &mut self,
+        id: FullscreenPassId,
+        deletion_queue: &mut DeletionQueue,
+        frames: u64,
+    ) -> bool {
+        let Some(index) = self.passes.iter().position(|pass| pass.id == id) else {
+            return false;
+        };
+        let pass = self.passes.remove(index);
+        deletion_queue.enqueue_after(frames, FType::DEVICE(Box::new(move |_| drop(pass))));
+        true
+    }
+
+    /// Data pushed to the fragment stage before the pass draws, at most
+    /// `MAX_FULLSCREEN_PASS_CONSTANTS` bytes.
+    pub fn set_pass_constants(&mut self, id: FullscreenPassId, constants: &[u8]) -> Result<()> {
+        if constants.len() > MAX_FULLSCREEN_PASS_CONSTANTS {
+            return Err(anyhow!(
+                "{} bytes of push constants exceed the {MAX_FULLSCREEN_PASS_CONSTANTS} bytes of \
+                 a fullscreen pass",
+                constants.len()
+            ));
+        }
+        let pass = self
+            .passes
+            .iter_mut()
+            .find(|pass| pass.id == id)
+            .ok_or(anyhow!("Fullscreen pass {id:?} does not exist"))?;
+        pass.constants = constants.to_vec();
+        Ok(())
+    }
+
+    /// Expects the draw image in TRANSFER_SRC_OPTIMAL like it is after the scene render pass
+    /// and leaves it in SHADER_READ_ONLY_OPTIMAL, the scene pass starts from UNDEFINED anyway.
+    pub fn record(&self, cmd: CommandBuffer, device: &Arc<VkDevice>, queue_family_index: u32) {
+        let tonemap_constants = TonemapPushConstant {
+            exposure: self.exposure,
+            mode: self.tonemapper.shader_mode(),
+        };
+        let tonemap_constants = unsafe {
+            std::slice::from_raw_parts(
+                (&tonemap_constants as *const TonemapPushConstant).cast::<u8>(),
+                size_of::<TonemapPushConstant>(),
+            )
+        };
+        let at = |insertion_point| {
+            self.passes
+                .iter()
+                .filter(move |pass| pass.insertion_point == insertion_point)
+                .map(|pass| (&pass.pipeline, pass.bindings_set, pass.constants.as_slice()))
+        };
+        let chain: Vec<_> = at(InsertionPoint::BeforeTonemap)
+            .chain([(&self.tonemap_pipeline, None, tonemap_constants)])
+            .chain(at(InsertionPoint::AfterTonemap))
+            .collect();
+
+        let extent = Extent2D::default()
+            .width(self.output.extent.width)
+            .height(self.output.extent.height);
+        let mut input = self.draw_image.image_details.image;
+        unsafe {
+            // the analysis passes may have written the draw image from compute
+            Self::barrier(cmd, device);
+            image_transition(
+                device.clone(),
+                cmd,
+                queue_family_index,
+                input,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+            for (idx, (pipeline, bindings_set, constants)) in chain.iter().enumerate() {
+                let last = idx == chain.len() - 1;
+                let (target, render_pass) = if last {
+                    (&self.output, &self.final_render_pass)
+                } else if idx % 2 == 0 {
+                    (
+                        self.scratch
+                            .as_ref()
+                            .expect("custom passes create the scratch image"),
+                        &self.render_pass,
+                    )
+                } else {
+                    (&self.draw_image, &self.render_pass)
+                };
+                let target = target.image_details.image;
+                if idx > 0 {
+                    Self::barrier(cmd, device);
+                }
+                device.cmd_begin_render_pass(
+                    cmd,
+                    &RenderPassBeginInfo::default()
+                        .render_pass(***render_pass)
+                        .framebuffer(**self.framebuffer(target))
+                        .render_area(create_scissor(&extent)),
+                    SubpassContents::INLINE,
+                );
+                device.cmd_set_viewport(cmd, 0, &[create_viewport(&extent)]);
+                device.cmd_set_scissor(cmd, 0, &[create_scissor(&extent)]);
+                device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, ***pipeline);
+                let mut sets = vec![self.input_set(input)];
+                sets.extend(*bindings_set);
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline_layout,
+                    0,
+                    &sets,
+                    &[],
+                );
+                if !constants.is_empty() {
+                    device.cmd_push_constants(
+                        cmd,
+                        pipeline.pipeline_layout,
+                        ShaderStageFlags::FRAGMENT,
+                        0,
+                        constants,
+                    );
+                }
+                // one triangle covering the whole target, positions come from gl_VertexIndex
+                device.cmd_draw(cmd, 3, 1, 0, 0);
+                device.cmd_end_render_pass(cmd);
+                input = target;
+            }
+        }
+    }
+
+    fn create_target(
+        memory_allocator: &MemoryAllocator,
+        extent: Extent2D,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<AllocatedImage> {
+        let image = memory_allocator.create_image(
+            Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            POST_FORMAT,
+            None,
+            ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::STORAGE | ImageUsageFlags::SAMPLED,
+            ImageAspectFlags::COLOR,
+            false,
+        )?;
+        deletion_queue.enqueue(FType::DROP(Box::new(image.clone())));
+        Ok(image)
+    }
+
+    /// Creates the framebuffer drawing into `target` and, if it is read by a later pass, the
+    /// set sampling it.
+    fn add_target(
+        &mut self,
+        target: &AllocatedImage,
+        sampled: bool,
+        deletion_queue: &mut DeletionQueue,
+    ) {
+        let extent = Extent2D::default()
+            .width(target.extent.width)
+            .height(target.extent.height);
+        let framebuffer = VkFrameBuffer::create_framebuffer(
+            IDENTIFIER::POST,
+            self.device.clone(),
+            self.render_pass.clone(),
+            extent,
+            &[target.image_details],
+        );
+        self.framebuffers
+            .push((target.image_details.image, framebuffer));
+        if !sampled {
+            return;
+        }
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            self.device.clone(),
+            1,
+            vec![PoolSizeRatio::new(
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1.0,
+            )],
+        );
+        let set = descriptor_allocator.allocate(self.device.clone(), &[self.input_layout])[0];
+        let mut writer = DescriptorWriter::new();
+        writer.write_image(
+            0,
+            target.image_details.image_view,
+            Some(self.sampler.clone()),
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            DescriptorType::COMBINED_IMAGE_SAMPLER,
+        );
+        writer.update_set(self.device.clone(), set);
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| {
+            descriptor_allocator.destroy_pools(device);
+        })));
+        self.input_sets.push((target.image_details.image, set));
+    }
+
+    fn framebuffer(&self, target: Image) -> &VkFrameBuffer {
+        &self
+            .framebuffers
+            .iter()
+            .find(|(image, _)| *image == target)
+            .expect("every post process target has a framebuffer")
+            .1
+    }
+
+    fn input_set(&self, input: Image) -> DescriptorSet {
+        self.input_sets
+            .iter()
+            .find(|(image, _)| *image == input)
+            .expect("every post process input has a descriptor set")
+            .1
+    }
+
+    fn create_pipeline(
+        device: &Arc<VkDevice>,
+        fragment_shader: &str,
+        layouts: &[DescriptorSetLayout],
+        extent: Extent2D,
+        push_constants: PushConstantLayout,
+        render_pass: &Arc<VkRenderPass>,
+    ) -> Result<VkPipeline> {
+        Ok(VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[
+                ShaderInformation::vertex_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/fullscreen.vert.spv".to_string(),
+                ),
+                ShaderInformation::fragment_2d_information(fragment_shader.to_string()),
+            ],
+            Some(layouts),
+            &extent,
+            Some(push_constants),
+            VertexInput::Pulling,
+            &[create_color_blending_attachment_state(
+                ColorComponentFlags::R
+                    | ColorComponentFlags::G
+                    | ColorComponentFlags::B
+                    | ColorComponentFlags::A,
+                false,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            render_pass.clone(),
+            false)?)
+    }
+
+    /// Makes the previous pass' writes visible to the next one and keeps the next one from
+    /// overwriting an image that is still being sampled.
+    unsafe fn barrier(cmd: CommandBuffer, device: &Arc<VkDevice>) {
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[MemoryBarrier2::default()
+                    .src_stage_mask(
+                        PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+                            | PipelineStageFlags2::COMPUTE_SHADER
+                            | PipelineStageFlags2::FRAGMENT_SHADER,
+                    )
+                    .src_access_mask(
+                        AccessFlags2::COLOR_ATTACHMENT_WRITE | AccessFlags2::SHADER_WRITE,
+                    )
+                    .dst_stage_mask(
+                        PipelineStageFlags2::FRAGMENT_SHADER
+                            | PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    )
+                    .dst_access_mask(
+                        AccessFlags2::SHADER_READ | AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    )],
+                &[],
+                &[],
+            );
+        }
+    }
+}
@@ -8,7 +8,13 @@ use nalgebra::Matrix4;
 
 use crate::{
     components::allocation_types::VkBuffer,
-    geom::{assets::MeshAsset, VertexAttributes},
+    geom::{
+        animation::AnimationPlayback,
+        assets::{GLTFMaterial, MeshAsset},
+        bounds::Ray,
+        lod,
+        VertexAttributes,
+    },
 };
 
 use super::{material::MaterialInstance, RenderNode, Renderable};
@@ -23,6 +29,9 @@ pub struct RenderObject {
     pub transform: Matrix4<f32>,
     pub material: MaterialInstance,
     pub vertex_buffer_address: DeviceAddress,
+    /// First matrix of the skin in `DrawContext::joint_matrices` if the material draws with
+    /// its skinned pipeline.
+    pub joint_offset: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -30,7 +39,8 @@ pub struct RenderObject {
 pub struct Node {
     parent: Weak<Node>,
     children: Vec<Arc<Node>>,
-    local_transform: Matrix4<f32>,
+    /// Written by the `AnimationPlayer` of the instance the node belongs to.
+    local_transform: Mutex<Matrix4<f32>>,
     world_transform: Mutex<Matrix4<f32>>, // Wrap world_transform in a Mutex
 }
 
@@ -46,19 +56,44 @@ impl Node {
         Self {
             parent,
             children,
-            local_transform,
+            local_transform: Mutex::new(local_transform),
             world_transform: Mutex::new(world_transform),
         }
     }
 
-    fn refresh_transform(&self, parent_matrix: Matrix4<f32>) {
+    pub fn local_transform(&self) -> Matrix4<f32> {
+        *self.local_transform.lock().unwrap()
+    }
+
+    /// Takes effect with the next `refresh_transform`.
+    pub fn set_local_transform(&self, local_transform: Matrix4<f32>) {
+        *self.local_transform.lock().unwrap() = local_transform;
+    }
+
+    pub fn world_transform(&self) -> Matrix4<f32> {
+        *self.world_transform.lock().unwrap()
+    }
+
+    /// Recomputes the world transforms of this node and its children below `parent_matrix`.
+    pub fn refresh_transform(&self, parent_matrix: Matrix4<f32>) {
         // Takes immutable self
         let mut world_transform = self.world_transform.lock().unwrap(); 
-        *world_transform = parent_matrix * self.local_transform;
+        *world_transform = parent_matrix * *self.local_transform.lock().unwrap();
         for child in &self.children {
             child.refresh_transform(*world_transform);  
         }
     }
+
+    /// Copies this node and all of its children with their current transforms, the copy has
+    /// no parent.
+    pub fn clone_subtree(&self) -> Arc<Node> {
+        Arc::new(Node::new(
+            Weak::new(),
+            self.children.iter().map(|child| child.clone_subtree()).collect(),
+            self.local_transform(),
+            *self.world_transform.lock().unwrap(),
+        ))
+    }
 }
 
 impl Renderable for Node {
@@ -75,13 +110,30 @@ impl Renderable for Node {
 pub struct MeshNode<T: VertexAttributes> {
     pub node: Arc<Node>,
     pub mesh_asset: Arc<Mutex<MeshAsset<T>>>,
+    /// Replaces the material of every surface of the mesh for this node only.
+    pub material_override: Option<Arc<GLTFMaterial>>,
+    /// Clip of the mesh's skin playing on this node, skinned meshes without one stay in their
+    /// rest pose.
+    pub animation: Option<AnimationPlayback>,
 }
 
 impl <T: VertexAttributes> MeshNode<T> {
     pub fn new(node: Arc<Node>, mesh_asset: Arc<Mutex<MeshAsset<T>>>) -> Self {
         Self {
             node, 
-            mesh_asset
+            mesh_asset,
+            material_override: None,
+            animation: None,
+        }
+    }
+
+    /// New node sharing the mesh buffers of `self` with its own copy of the node hierarchy.
+    pub fn instantiate(&self) -> Self {
+        Self {
+            node: self.node.clone_subtree(),
+            mesh_asset: self.mesh_asset.clone(),
+            material_override: self.material_override.clone(),
+            animation: self.animation,
         }
     }
 }
@@ -93,17 +145,55 @@ impl<T: VertexAttributes> Renderable for MeshNode<T> {
     fn draw(&self, top_matrix: Matrix4<f32>, draw_ctx: &mut super::DrawContext) {
         let node_matrix = top_matrix * *self.node.world_transform.lock().unwrap();
         let mesh_asset = self.mesh_asset.lock().unwrap();
+        let distance = (node_matrix.column(3).xyz() - draw_ctx.camera_position).norm();
+        let lod_scale = lod::max_axis_scale(&node_matrix);
+        let joint_offset = mesh_asset.skin.as_ref().map(|skin| {
+            let now = draw_ctx.animation_time;
+            if let Some(playback) = &self.animation {
+                let duration = skin.clips.get(playback.clip).map_or(0.0, |clip| clip.duration);
+                draw_ctx.animating |= playback.is_running(now, duration);
+            }
+            let offset = draw_ctx.joint_matrices.len() as u32;
+            draw_ctx
+                .joint_matrices
+                .extend(skin.joint_matrices(self.animation.as_ref(), now));
+            offset
+        });
         for surface in mesh_asset.surfaces.clone() {
+            let (start_index, count) = surface.lod_range(distance, lod_scale, draw_ctx.lod_settings);
+            let mut material = self
+                .material_override
+                .as_ref()
+                .or(surface.material.as_ref())
+                .map_or_else(
+                    || draw_ctx.error_material.clone(),
+                    |material| material.data.clone(),
+                );
+            // materials without a skinned pipeline draw the mesh in its bind pose
+            let joint_offset = match (joint_offset, material.skinned_pipeline.clone()) {
+                (Some(offset), Some(skinned_pipeline)) => {
+                    material.pipeline = skinned_pipeline;
+                    Some(offset)
+                }
+                _ => None,
+            };
             let render_obj = RenderObject {
-                index_count: surface.count as u32,
-                first_index: surface.start_index,
-                index_buffer: mesh_asset.mesh_buffers.index_buffer, 
-                material: surface.material.unwrap().data.clone(),
+                index_count: count as u32,
+                first_index: start_index + mesh_asset.mesh_buffers.first_index(),
+                index_buffer: mesh_asset.mesh_buffers.index_buffer.buffer.clone(),
+                material,
                 transform: node_matrix,
-                vertex_buffer_address: mesh_asset.mesh_buffers.vertex_buffer.address 
+                vertex_buffer_address: mesh_asset.mesh_buffers.vertex_address(),
+                joint_offset,
             };
             draw_ctx.opaque_surfaces.push(render_obj);
         }
  //     self.node.draw(top_matrix, draw_ctx);
     }
+
+    fn ray_cast(&self, top_matrix: Matrix4<f32>, ray: &Ray) -> Option<f32> {
+        let node_matrix = top_matrix * self.node.world_transform();
+        let bounds = self.mesh_asset.lock().unwrap().bounds?;
+        bounds.transformed(&node_matrix).ray_intersection(ray)
+    }
 }
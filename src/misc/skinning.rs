@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{BufferUsageFlags, DeviceAddress};
+use nalgebra::Matrix4;
+
+use crate::components::{
+    deletion_queue::DeletionQueue, mapped_ring::MappedRing, memory_allocator::MemoryAllocator,
+    queue::VkQueue,
+};
+
+/// Joint matrices the ring holds, shared by the frames in flight.
+const JOINT_RING_CAPACITY: usize = 1 << 14;
+
+/// Storage buffer the skinned mesh pipelines read the joint matrices of a frame from, through
+/// the device address in their push constant.
+pub struct JointBuffer {
+    ring: MappedRing<Matrix4<f32>>,
+}
+
+impl JointBuffer {
+    pub fn new(
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let ring = MappedRing::new(
+            memory_allocator,
+            queues,
+            JOINT_RING_CAPACITY,
+            BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            deletion_queue,
+        )?;
+        Ok(Self { ring })
+    }
+
+    /// Copies the joint matrices of the frame recorded in `frame_idx` into the ring and returns
+    /// the address of the first, `RenderObject::joint_offset` counts matrices from it.
+    pub fn upload(
+        &mut self,
+        frame_idx: usize,
+        joint_matrices: &[Matrix4<f32>],
+    ) -> Result<DeviceAddress> {
+        self.ring.begin_frame(frame_idx);
+        if joint_matrices.is_empty() {
+            return Ok(0);
+        }
+        let offset = self.ring.write(joint_matrices)?;
+        Ok(self.ring.buffer().address + offset as u64 * size_of::<Matrix4<f32>>() as u64)
+    }
+}
@@ -0,0 +1,179 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    ColorComponentFlags, CommandBuffer, CullModeFlags, DescriptorType, DynamicState, Extent2D,
+    Extent3D, Filter, Format, FrontFace, ImageLayout, ImageUsageFlags, PipelineBindPoint,
+    PolygonMode, PrimitiveTopology, Rect2D, ShaderStageFlags, Viewport,
+};
+use nalgebra::Matrix4;
+
+use crate::{
+    components::{
+        allocation_types::AllocatedImage,
+        command_buffers::VkCommandPool,
+        deletion_queue::{DeletionQueue, FType},
+        descriptors::{DescriptorAllocator, DescriptorSetDetails},
+        device::VkDevice,
+        memory_allocator::MemoryAllocator,
+        pipeline::{
+            create_color_blending_attachment_state, create_multisampling_state,
+            create_rasterizer_state, ShaderInformation, VertexInput, VkPipeline,
+        },
+        render_pass::VkRenderPass,
+        sampler::{SamplerCache, VkSampler},
+    },
+    geom::{
+        push_constants::{PushConstant, PushConstantLayout},
+        scene::SceneData,
+    },
+};
+
+/// File stems of the cube faces inside an environment map directory, in Vulkan layer order.
+const CUBE_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+pub struct Skybox {
+    pub environment_map: AllocatedImage,
+    descriptor_set: DescriptorSetDetails,
+    pipeline: VkPipeline,
+    /// Bound in `descriptor_set`, lives as long as it does.
+    _sampler: VkSampler,
+}
+
+impl Skybox {
+    /// Loads the six faces `px`, `nx`, `py`, `ny`, `pz` and `nz` (png or jpg) from `directory`
+    /// into a cube map and builds the pipeline drawing it behind the scene.
+    pub fn load<P: AsRef<Path>>(
+        directory: P,
+        device: Arc<VkDevice>,
+        memory_allocator: Arc<MemoryAllocator>,
+        command_pool: &VkCommandPool,
+        descriptor_allocator: &mut DescriptorAllocator,
+        samplers: &SamplerCache,
+        extent: &Extent2D,
+        render_pass: Arc<VkRenderPass>,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let mut faces: [Vec<u8>; 6] = Default::default();
+        let mut face_extent: Option<(u32, u32)> = None;
+        for (face, stem) in faces.iter_mut().zip(CUBE_FACES) {
+            let path = ["png", "jpg", "jpeg"]
+                .iter()
+                .map(|extension| directory.as_ref().join(format!("{stem}.{extension}")))
+                .find(|path| path.exists())
+                .ok_or(anyhow!(
+                    "Environment map face {stem} is missing in {}",
+                    directory.as_ref().display()
+                ))?;
+            let image = image::open(&path)?.to_rgba8();
+            if face_extent.is_some_and(|extent| extent != image.dimensions()) {
+                return Err(anyhow!(
+                    "Environment map face {} does not match the size of the other faces",
+                    path.display()
+                ));
+            }
+            face_extent = Some(image.dimensions());
+            *face = image.into_raw();
+        }
+        let (width, height) = face_extent.unwrap();
+
+        let environment_map = memory_allocator.create_cube_image_with_data(
+            &faces,
+            Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            Format::R8G8B8A8_SRGB,
+            ImageUsageFlags::SAMPLED,
+            command_pool,
+        )?;
+        deletion_queue.enqueue(FType::DROP(Box::new(environment_map.clone())));
+
+        let sampler = samplers.with_filter(Filter::LINEAR, Filter::LINEAR);
+        let descriptor_set = descriptor_allocator.write_image_descriptors(
+            &environment_map.image_details.image_view,
+            &ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ShaderStageFlags::FRAGMENT,
+            DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Some(sampler.clone()),
+        )?;
+
+        let pipeline = VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[
+                ShaderInformation::vertex_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/skybox.vert.spv".to_string(),
+                ),
+                ShaderInformation::fragment_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/skybox.frag.spv".to_string(),
+                ),
+            ],
+            Some(&descriptor_set.layout),
+            extent,
+            Some(PushConstantLayout::of::<Matrix4<f32>>(ShaderStageFlags::VERTEX)),
+            VertexInput::Pulling,
+            &[create_color_blending_attachment_state(
+                ColorComponentFlags::R
+                    | ColorComponentFlags::G
+                    | ColorComponentFlags::B
+                    | ColorComponentFlags::A,
+                false,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, render_pass.samples(), 1.0, false, false),
+            render_pass,
+            // drawn first without depth writes so every opaque surface ends up in front of it
+            false)?;
+
+        Ok(Self {
+            environment_map,
+            descriptor_set,
+            pipeline,
+            _sampler: sampler,
+        })
+    }
+
+    pub fn record(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        scene_data: &SceneData,
+        viewports: &[Viewport],
+        render_area: &Rect2D,
+    ) {
+        // only the camera rotation matters for the environment
+        let mut view = scene_data.view;
+        view.fixed_view_mut::<3, 1>(0, 3).fill(0.0);
+        let view_proj = scene_data.proj * view;
+        unsafe {
+            device.cmd_set_scissor(cmd, 0, &[*render_area]);
+            device.cmd_set_viewport(cmd, 0, viewports);
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, *self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline_layout,
+                0,
+                &self.descriptor_set,
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline.pipeline_layout,
+                ShaderStageFlags::VERTEX,
+                0,
+                &PushConstant::new(view_proj, u64::default()).raw_data_of_T(),
+            );
+            device.cmd_draw(cmd, 36, 1, 0, 0);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use nalgebra::Vector3;
+
+/// Increments transforms are snapped to while they are edited, `None` leaves that part free.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GridSnap {
+    /// Grid spacing in world units.
+    pub translate: Option<f32>,
+    /// Angle step in radians.
+    pub rotate: Option<f32>,
+    pub scale: Option<f32>,
+}
+
+impl GridSnap {
+    pub fn new(translate: f32, rotate: f32, scale: f32) -> Self {
+        Self {
+            translate: Some(translate),
+            rotate: Some(rotate),
+            scale: Some(scale),
+        }
+    }
+
+    /// Moves every component of `position` to the closest grid line.
+    pub fn snap_translation(&self, position: Vector3<f32>) -> Vector3<f32> {
+        position.map(|component| snap(component, self.translate))
+    }
+
+    pub fn snap_rotation(&self, angle: f32) -> f32 {
+        snap(angle, self.rotate)
+    }
+
+    /// Snaps every component of `scale`, a component is never snapped down to zero.
+    pub fn snap_scale(&self, scale: Vector3<f32>) -> Vector3<f32> {
+        scale.map(|component| match self.scale {
+            Some(increment) if component.abs() < increment => increment.copysign(component),
+            _ => snap(component, self.scale),
+        })
+    }
+}
+
+fn snap(value: f32, increment: Option<f32>) -> f32 {
+    match increment {
+        Some(increment) if increment > 0.0 => (value / increment).round() * increment,
+        _ => value,
+    }
+}
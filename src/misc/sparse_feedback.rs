@@ -0,0 +1,378 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, BufferUsageFlags, ClearDepthStencilValue, ClearValue, CommandBuffer,
+    CullModeFlags, DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutCreateFlags,
+    DescriptorType, DynamicState, Extent2D, Extent3D, Format, FrontFace, ImageAspectFlags,
+    ImageUsageFlags, IndexType, MemoryBarrier2, MemoryPropertyFlags, Offset2D, PipelineBindPoint,
+    PipelineStageFlags2, PolygonMode, PrimitiveTopology, Rect2D, RenderPassBeginInfo,
+    SampleCountFlags, ShaderStageFlags, SubpassContents, Viewport, WHOLE_SIZE,
+};
+use nalgebra::Matrix4;
+use vk_mem::MemoryUsage;
+
+use crate::{
+    components::{
+        allocation_types::{VkBuffer, VkFrameBuffer, IDENTIFIER},
+        deletion_queue::{DeletionQueue, FType},
+        descriptors::{
+            DescriptorAllocator, DescriptorLayoutBuilder, DescriptorSetDetails, DescriptorWriter,
+            PoolSizeRatio,
+        },
+        device::VkDevice,
+        frame_data::SceneDataBinding,
+        memory_allocator::MemoryAllocator,
+        pipeline::{
+            create_multisampling_state, create_rasterizer_state, ShaderInformation, VertexInput,
+            VkPipeline,
+        },
+        queue::VkQueue,
+        render_pass::VkRenderPass,
+    },
+    geom::{gpu_scene_push_constant, push_constants::{PushConstant, PushConstantLayout}},
+    renderer::MAX_FRAMES,
+};
+
+use super::{
+    material::{MaterialPass, DEFAULT_VERTEX_SHADER},
+    views::ViewTarget,
+    DrawContext,
+};
+
+/// u32s in front of the page flags of a feedback buffer, the `pageCount` of
+/// `shaders/sparse_feedback.glsl`.
+pub const FEEDBACK_HEADER_LEN: usize = 2;
+/// The feedback pass renders at this fraction of the render resolution along each axis.
+const FEEDBACK_SCALE: u32 = 4;
+
+/// Feedback buffers of one sparse texture, see `SparseFeedback::add_target`.
+struct FeedbackTarget {
+    /// Materials sampling the texture, only their surfaces are drawn.
+    materials: HashSet<u32>,
+    descriptor_sets: Vec<DescriptorSetDetails>,
+    buffers: Vec<(VkBuffer, *mut u32)>,
+    /// u32s per buffer, the page count header included.
+    len: usize,
+}
+
+/// Draws the surfaces of the materials sampling a sparse texture at a quarter of the render
+/// resolution and flags the pages their texture coordinates land on, see
+/// `shaders/sparse_feedback.glsl`. `SparseImage::requested_pages` decodes the flags once they
+/// are read back `MAX_FRAMES` frames later. Uses the default mesh vertex shader, like the depth
+/// pre-pass, so skinned surfaces don't request pages.
+pub struct SparseFeedback {
+    device: Arc<VkDevice>,
+    memory_allocator: Arc<MemoryAllocator>,
+    queues: Vec<Arc<VkQueue>>,
+    extent: Extent2D,
+    render_pass: Arc<VkRenderPass>,
+    framebuffer: VkFrameBuffer,
+    pipeline: VkPipeline,
+    layout: DescriptorSetLayout,
+    targets: Vec<FeedbackTarget>,
+}
+
+impl SparseFeedback {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: Arc<MemoryAllocator>,
+        queues: &[Arc<VkQueue>],
+        render_extent: Extent2D,
+        scene_data_layout: DescriptorSetLayout,
+        bindless_layout: DescriptorSetLayout,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let extent = Extent2D {
+            width: render_extent.width.div_ceil(FEEDBACK_SCALE),
+            height: render_extent.height.div_ceil(FEEDBACK_SCALE),
+        };
+        let depth_image = memory_allocator.create_image(
+            Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            Format::D32_SFLOAT,
+            None,
+            ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ImageAspectFlags::DEPTH,
+            false,
+        )?;
+        deletion_queue.enqueue(FType::DROP(Box::new(depth_image.clone())));
+
+        let render_pass = Arc::new(VkRenderPass::depth_only(
+            device.clone(),
+            SampleCountFlags::TYPE_1,
+        )?);
+        let framebuffer = VkFrameBuffer::create_framebuffer(
+            IDENTIFIER::DEPTH,
+            device.clone(),
+            render_pass.clone(),
+            extent,
+            &[depth_image.image_details],
+        );
+        let layout = DescriptorLayoutBuilder::new()
+            .add_binding(0, DescriptorType::STORAGE_BUFFER, ShaderStageFlags::FRAGMENT)
+            .build(
+                device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            );
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_set_layout(layout, None);
+        })));
+        // sets 0 and 1 like the material pipelines, the feedback buffer is set 2
+        let pipeline = VkPipeline::create_new_pipeline(
+            device.clone(),
+            &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
+            PrimitiveTopology::TRIANGLE_LIST,
+            &[
+                ShaderInformation::vertex_2d_information(DEFAULT_VERTEX_SHADER.to_string()),
+                ShaderInformation::fragment_2d_information(
+                    "/Users/zapzap/Projects/piplup/shaders/sparse_feedback.frag.spv".to_string(),
+                ),
+            ],
+            Some(&[scene_data_layout, bindless_layout, layout]),
+            &extent,
+            Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+                ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            )),
+            VertexInput::Pulling,
+            &[],
+            create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
+            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            render_pass.clone(),
+            true)?;
+        Ok(Self {
+            device,
+            memory_allocator,
+            queues: queues.to_vec(),
+            extent,
+            render_pass,
+            framebuffer,
+            pipeline,
+            layout,
+            targets: vec![],
+        })
+    }
+
+    /// Adds the feedback buffers of a sparse texture with `page_count` pages along x and y and
+    /// returns the target to track its materials and read the flags back with.
+    pub fn add_target(
+        &mut self,
+        page_count: (u32, u32),
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<usize> {
+        let len = FEEDBACK_HEADER_LEN + (page_count.0 * page_count.1) as usize;
+        let size = (len * size_of::<u32>()) as u64;
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            self.device.clone(),
+            MAX_FRAMES as u32,
+            vec![PoolSizeRatio::new(DescriptorType::STORAGE_BUFFER, 1.0)],
+        );
+        let mut writer = DescriptorWriter::new();
+        let mut descriptor_sets = vec![];
+        let mut buffers = vec![];
+        for _ in 0..MAX_FRAMES {
+            let buffer = self.memory_allocator.allocate_single_buffer(
+                size,
+                &self.queues,
+                BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::TRANSFER_DST,
+                MemoryUsage::Unknown,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped = buffer.mapped_data() as *mut u32;
+            // the header never changes, the record only clears the page flags behind it
+            unsafe {
+                mapped.write_bytes(0, len);
+                mapped.write(page_count.0);
+                mapped.add(1).write(page_count.1);
+            }
+            deletion_queue.enqueue(FType::DROP(Box::new(buffer.clone())));
+
+            let descriptor_set = descriptor_allocator.allocate(self.device.clone(), &[self.layout]);
+            writer.clear();
+            writer.write_buffer(0, &buffer, size, 0, DescriptorType::STORAGE_BUFFER);
+            writer.update_set(self.device.clone(), descriptor_set[0]);
+            descriptor_sets.push(descriptor_set);
+            buffers.push((buffer, mapped));
+        }
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| {
+            descriptor_allocator.destroy_pools(device);
+        })));
+        self.targets.push(FeedbackTarget {
+            materials: HashSet::new(),
+            descriptor_sets,
+            buffers,
+            len,
+        });
+        Ok(self.targets.len() - 1)
+    }
+
+    /// Draws the surfaces using the bindless material `material_index` into `target`.
+    pub fn track_material(&mut self, target: usize, material_index: u32) {
+        self.targets[target].materials.insert(material_index);
+    }
+
+    /// Page count header and page flags `target` got in the frame `frame_idx` was last recorded
+    /// for, must only be called once the frame's fence has been waited on.
+    pub fn read_back(&self, target: usize, frame_idx: usize) -> &[u32] {
+        let target = &self.targets[target];
+        unsafe { std::slice::from_raw_parts(target.buffers[frame_idx].1, target.len) }
+    }
+
+    /// Records one pass per target over the first view, the one analysis and picking see too.
+    pub fn record(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        frame_idx: usize,
+        draw_ctx: &DrawContext,
+        view: &ViewTarget,
+        scene_data: &SceneDataBinding,
+        bindless_set: DescriptorSet,
+        render_area: &Rect2D,
+    ) {
+        let scissor = self.scaled_rect(&view.scissor);
+        let viewport = Viewport {
+            x: view.viewport.x / FEEDBACK_SCALE as f32,
+            y: view.viewport.y / FEEDBACK_SCALE as f32,
+            width: view.viewport.width / FEEDBACK_SCALE as f32,
+            height: view.viewport.height / FEEDBACK_SCALE as f32,
+            ..view.viewport
+        };
+        let clear_value = [ClearValue {
+            depth_stencil: ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        unsafe {
+            for target in self.targets.iter().filter(|target| !target.materials.is_empty()) {
+                let buffer = *target.buffers[frame_idx].0;
+                device.cmd_fill_buffer(
+                    cmd,
+                    buffer,
+                    (FEEDBACK_HEADER_LEN * size_of::<u32>()) as u64,
+                    WHOLE_SIZE,
+                    0,
+                );
+                Self::barrier(
+                    cmd,
+                    device,
+                    PipelineStageFlags2::TRANSFER,
+                    AccessFlags2::TRANSFER_WRITE,
+                    PipelineStageFlags2::FRAGMENT_SHADER,
+                    AccessFlags2::SHADER_READ | AccessFlags2::SHADER_WRITE,
+                );
+                device.cmd_begin_render_pass(
+                    cmd,
+                    &RenderPassBeginInfo::default()
+                        .render_pass(**self.render_pass)
+                        .framebuffer(*self.framebuffer)
+                        .render_area(self.scaled_rect(render_area))
+                        .clear_values(&clear_value),
+                    SubpassContents::INLINE,
+                );
+                device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, *self.pipeline);
+                device.cmd_set_scissor(cmd, 0, &[scissor]);
+                device.cmd_set_viewport(cmd, 0, &[viewport]);
+                scene_data.bind(
+                    device,
+                    cmd,
+                    self.pipeline.pipeline_layout,
+                    &[bindless_set, target.descriptor_sets[frame_idx][0]],
+                );
+                let tracked = draw_ctx.opaque_surfaces.iter().filter(|render_obj| {
+                    target.materials.contains(&render_obj.material.material_index)
+                        && !render_obj.material.pipeline.skinned
+                });
+                // transparent surfaces go last so they don't hide the opaque ones behind them
+                let (transparent, opaque): (Vec<_>, Vec<_>) = tracked.partition(|render_obj| {
+                    render_obj.material.pass == MaterialPass::GLTF_PBR_TRANSPARENT
+                });
+                let mut bound_index_buffer = None;
+                for render_obj in opaque.into_iter().chain(transparent) {
+                    if bound_index_buffer != Some(*render_obj.index_buffer) {
+                        device.cmd_bind_index_buffer(cmd, *render_obj.index_buffer, 0, IndexType::UINT32);
+                        bound_index_buffer = Some(*render_obj.index_buffer);
+                    }
+                    device.cmd_push_constants(
+                        cmd,
+                        self.pipeline.pipeline_layout,
+                        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                        0,
+                        &gpu_scene_push_constant(
+                            render_obj.transform,
+                            render_obj.vertex_buffer_address,
+                            render_obj.material.material_index,
+                        ),
+                    );
+                    device.cmd_draw_indexed(
+                        cmd,
+                        render_obj.index_count,
+                        1,
+                        render_obj.first_index,
+                        0,
+                        0,
+                    );
+                }
+                device.cmd_end_render_pass(cmd);
+                Self::barrier(
+                    cmd,
+                    device,
+                    PipelineStageFlags2::FRAGMENT_SHADER,
+                    AccessFlags2::SHADER_WRITE,
+                    PipelineStageFlags2::HOST,
+                    AccessFlags2::HOST_READ,
+                );
+            }
+        }
+    }
+
+    /// `rect` of the render targets in the feedback extent.
+    fn scaled_rect(&self, rect: &Rect2D) -> Rect2D {
+        let offset = Offset2D {
+            x: rect.offset.x / FEEDBACK_SCALE as i32,
+            y: rect.offset.y / FEEDBACK_SCALE as i32,
+        };
+        Rect2D {
+            offset,
+            extent: Extent2D {
+                width: rect
+                    .extent
+                    .width
+                    .div_ceil(FEEDBACK_SCALE)
+                    .min(self.extent.width - offset.x as u32),
+                height: rect
+                    .extent
+                    .height
+                    .div_ceil(FEEDBACK_SCALE)
+                    .min(self.extent.height - offset.y as u32),
+            },
+        }
+    }
+
+    unsafe fn barrier(
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        src_stage: PipelineStageFlags2,
+        src_access: AccessFlags2,
+        dst_stage: PipelineStageFlags2,
+        dst_access: AccessFlags2,
+    ) {
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &[MemoryBarrier2::default()
+                    .src_stage_mask(src_stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(dst_stage)
+                    .dst_access_mask(dst_access)],
+                &[],
+                &[],
+            );
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk::{
+    AccessFlags2, BufferUsageFlags, CommandBuffer, DescriptorSet,
+    DescriptorSetLayoutCreateFlags, DescriptorType, Extent2D, Extent3D, Filter, Format, Image,
+    ImageAspectFlags, ImageLayout, ImageMemoryBarrier2, ImageUsageFlags, MemoryPropertyFlags,
+    PipelineBindPoint, PipelineStageFlags2, ShaderStageFlags, QUEUE_FAMILY_IGNORED,
+};
+use nalgebra::{Matrix4, Vector3, Vector4};
+use vk_mem::MemoryUsage;
+
+use crate::{
+    components::{
+        allocation_types::AllocatedImage,
+        bindless::BindlessDescriptors,
+        command_buffers::VkCommandPool,
+        deletion_queue::{DeletionQueue, FType},
+        descriptors::{
+            DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio,
+        },
+        device::VkDevice,
+        image_util::image_subresource_range,
+        memory_allocator::MemoryAllocator,
+        pipeline::VkPipeline,
+        queue::VkQueue,
+        sampler::{SamplerCache, VkSampler},
+    },
+    renderer::MAX_FRAMES,
+};
+
+/// Hemisphere samples per pixel, must match `KERNEL_SIZE` in `shaders/ssao.comp`.
+pub const SSAO_KERNEL_SIZE: usize = 16;
+/// Side of the tiled rotation noise, the blur averages the same square to hide it.
+const NOISE_SIZE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    /// View space radius of the sampled hemisphere.
+    pub radius: f32,
+    /// Depth difference below which a sample does not count as occluding, against acne.
+    pub bias: f32,
+    /// Exponent applied to the ambient occlusion, above 1 darkens it.
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Must match the `SsaoData` block of `shaders/ssao.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SsaoUniforms {
+    proj: Matrix4<f32>,
+    inverse_proj: Matrix4<f32>,
+    kernel: [Vector4<f32>; SSAO_KERNEL_SIZE],
+    /// x radius, y bias, z intensity
+    params: Vector4<f32>,
+}
+
+/// Screen space ambient occlusion from the depth a `DepthPrepass` wrote, so it is ready before
+/// the scene is shaded. Normals are reconstructed from the depth, the result is blurred and
+/// sampled by the mesh shaders through its bindless texture slot to darken the ambient term.
+pub struct Ssao {
+    pub settings: SsaoSettings,
+    /// Skips the passes and leaves the ambient term unoccluded, e.g. when the frame runs over
+    /// its GPU budget.
+    pub suspended: bool,
+    ao_image: AllocatedImage,
+    blurred_image: AllocatedImage,
+    ssao_pipeline: VkPipeline,
+    blur_pipeline: VkPipeline,
+    /// Bound in the descriptor sets, lives as long as they do.
+    _sampler: VkSampler,
+    ssao_sets: Vec<DescriptorSet>,
+    blur_set: DescriptorSet,
+    uniform_buffers: Vec<*mut SsaoUniforms>,
+    kernel: [Vector4<f32>; SSAO_KERNEL_SIZE],
+    texture_index: u32,
+}
+
+impl Ssao {
+    pub fn new(
+        device: Arc<VkDevice>,
+        memory_allocator: &MemoryAllocator,
+        queues: &[Arc<VkQueue>],
+        command_pool: &VkCommandPool,
+        extent: Extent2D,
+        depth_image: &AllocatedImage,
+        bindless: &mut BindlessDescriptors,
+        samplers: &SamplerCache,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<Self> {
+        let create_ao_image = |deletion_queue: &mut DeletionQueue| -> Result<AllocatedImage> {
+            let image = memory_allocator.create_image(
+                Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                },
+                Format::R16_SFLOAT,
+                None,
+                ImageUsageFlags::STORAGE | ImageUsageFlags::SAMPLED,
+                ImageAspectFlags::COLOR,
+                false,
+            )?;
+            deletion_queue.enqueue(FType::DROP(Box::new(image.clone())));
+            Ok(image)
+        };
+        let ao_image = create_ao_image(deletion_queue)?;
+        let blurred_image = create_ao_image(deletion_queue)?;
+
+        let noise: Vec<u32> = (0..NOISE_SIZE * NOISE_SIZE)
+            .map(|idx| {
+                let [x, y] = [random(idx * 2 + 1000), random(idx * 2 + 1001)];
+                (x * 255.0) as u32 | ((y * 255.0) as u32) << 8
+            })
+            .collect();
+        let noise_image = memory_allocator.create_image_with_data(
+            &noise,
+            Extent3D {
+                width: NOISE_SIZE,
+                height: NOISE_SIZE,
+                depth: 1,
+            },
+            Format::R8G8B8A8_UNORM,
+            ImageUsageFlags::SAMPLED,
+            ImageAspectFlags::COLOR,
+            command_pool,
+            false,
+        )?;
+        deletion_queue.enqueue(FType::DROP(Box::new(noise_image.clone())));
+
+        let ssao_layout = DescriptorLayoutBuilder::new()
+            .add_binding(
+                0,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                1,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+                ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(2, DescriptorType::UNIFORM_BUFFER, ShaderStageFlags::COMPUTE)
+            .add_binding(3, DescriptorType::STORAGE_IMAGE, ShaderStageFlags::COMPUTE)
+            .build(
+                device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            );
+        let blur_layout = DescriptorLayoutBuilder::new()
+            .add_binding(0, DescriptorType::STORAGE_IMAGE, ShaderStageFlags::COMPUTE)
+            .add_binding(1, DescriptorType::STORAGE_IMAGE, ShaderStageFlags::COMPUTE)
+            .build(
+                device.clone(),
+                ShaderStageFlags::empty(),
+                DescriptorSetLayoutCreateFlags::empty(),
+            );
+        let ssao_pipeline = VkPipeline::compute_pipelines(
+            device.clone(),
+            &[ssao_layout],
+            "/Users/zapzap/Projects/piplup/shaders/ssao.comp.spv",
+        )?;
+        let blur_pipeline = VkPipeline::compute_pipelines(
+            device.clone(),
+            &[blur_layout],
+            "/Users/zapzap/Projects/piplup/shaders/ssao_blur.comp.spv",
+        )?;
+
+        // the shaders only use texelFetch, the sampler never filters
+        let sampler = samplers.with_filter(Filter::NEAREST, Filter::NEAREST);
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            device.clone(),
+            MAX_FRAMES as u32 + 1,
+            vec![
+                PoolSizeRatio::new(DescriptorType::COMBINED_IMAGE_SAMPLER, 2.0),
+                PoolSizeRatio::new(DescriptorType::UNIFORM_BUFFER, 1.0),
+                PoolSizeRatio::new(DescriptorType::STORAGE_IMAGE, 2.0),
+            ],
+        );
+        let mut writer = DescriptorWriter::new();
+        let mut ssao_sets = vec![];
+        let mut uniform_buffers = vec![];
+        for _ in 0..MAX_FRAMES {
+            let buffer = memory_allocator.allocate_single_buffer(
+                size_of::<SsaoUniforms>() as u64,
+                queues,
+                BufferUsageFlags::UNIFORM_BUFFER,
+                MemoryUsage::Unknown,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped = buffer.mapped_data() as *mut SsaoUniforms;
+            deletion_queue.enqueue(FType::DROP(Box::new(buffer.clone())));
+
+            let set = descriptor_allocator.allocate(device.clone(), &[ssao_layout])[0];
+            writer.clear();
+            writer.write_image(
+                0,
+                depth_image.image_details.image_view,
+                Some(sampler.clone()),
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.write_image(
+                1,
+                noise_image.image_details.image_view,
+                Some(sampler.clone()),
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.write_buffer(
+                2,
+                &buffer,
+                size_of::<SsaoUniforms>() as u64,
+                0,
+                DescriptorType::UNIFORM_BUFFER,
+            );
+            writer.write_image(
+                3,
+                ao_image.image_details.image_view,
+                None,
+                ImageLayout::GENERAL,
+                DescriptorType::STORAGE_IMAGE,
+            );
+            writer.update_set(device.clone(), set);
+            ssao_sets.push(set);
+            uniform_buffers.push(mapped);
+        }
+        let blur_set = descriptor_allocator.allocate(device.clone(), &[blur_layout])[0];
+        writer.clear();
+        writer.write_image(
+            0,
+            ao_image.image_details.image_view,
+            None,
+            ImageLayout::GENERAL,
+            DescriptorType::STORAGE_IMAGE,
+        );
+        writer.write_image(
+            1,
+            blurred_image.image_details.image_view,
+            None,
+            ImageLayout::GENERAL,
+            DescriptorType::STORAGE_IMAGE,
+        );
+        writer.update_set(device.clone(), blur_set);
+
+        let texture_index =
+            bindless.texture_index(blurred_image.image_details.image_view, &sampler)?;
+
+        deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
+            device.destroy_descriptor_set_layout(ssao_layout, None);
+            device.destroy_descriptor_set_layout(blur_layout, None);
+            descriptor_allocator.destroy_pools(device);
+        })));
+
+        Ok(Self {
+            settings: SsaoSettings::default(),
+            suspended: false,
+            ao_image,
+            blurred_image,
+            ssao_pipeline,
+            blur_pipeline,
+            _sampler: sampler,
+            ssao_sets,
+            blur_set,
+            uniform_buffers,
+            kernel: hemisphere_kernel(),
+            texture_index,
+        })
+    }
+
+    /// Slot of the blurred result in the bindless texture array.
+    pub fn texture_index(&self) -> u32 {
+        self.texture_index
+    }
+
+    /// Expects `depth_image` in DEPTH_ATTACHMENT_OPTIMAL after the depth pre-pass and leaves it
+    /// there for the scene pass to load. The result is in SHADER_READ_ONLY_OPTIMAL afterwards.
+    pub fn record(
+        &self,
+        cmd: CommandBuffer,
+        device: &Arc<VkDevice>,
+        frame_idx: usize,
+        proj: Matrix4<f32>,
+        depth_image: &AllocatedImage,
+    ) {
+        let uniforms = SsaoUniforms {
+            proj,
+            inverse_proj: proj.try_inverse().unwrap_or_else(Matrix4::identity),
+            kernel: self.kernel,
+            params: Vector4::new(
+                self.settings.radius,
+                self.settings.bias,
+                self.settings.intensity,
+                0.0,
+            ),
+        };
+        let extent = self.ao_image.extent;
+        let depth = depth_image.image_details.image;
+        let ao = self.ao_image.image_details.image;
+        let blurred = self.blurred_image.image_details.image;
+        unsafe {
+            self.uniform_buffers[frame_idx].write(uniforms);
+            image_barrier(
+                cmd,
+                device,
+                &[
+                    (
+                        depth,
+                        ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                        ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                    ),
+                    (ao, ImageLayout::UNDEFINED, ImageLayout::GENERAL),
+                    // the last frame's result may still be sampled by the scene pass
+                    (blurred, ImageLayout::UNDEFINED, ImageLayout::GENERAL),
+                ],
+                PipelineStageFlags2::LATE_FRAGMENT_TESTS | PipelineStageFlags2::FRAGMENT_SHADER,
+                AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_READ | AccessFlags2::SHADER_WRITE,
+            );
+            let dispatch = |pipeline: &VkPipeline, set: DescriptorSet| {
+                device.cmd_bind_pipeline(cmd, PipelineBindPoint::COMPUTE, **pipeline);
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    PipelineBindPoint::COMPUTE,
+                    pipeline.pipeline_layout,
+                    0,
+                    &[set],
+                    &[],
+                );
+                device.cmd_dispatch(
+                    cmd,
+                    extent.width.div_ceil(16),
+                    extent.height.div_ceil(16),
+                    1,
+                );
+            };
+            dispatch(&self.ssao_pipeline, self.ssao_sets[frame_idx]);
+            image_barrier(
+                cmd,
+                device,
+                &[(ao, ImageLayout::GENERAL, ImageLayout::GENERAL)],
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_WRITE,
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_READ,
+            );
+            dispatch(&self.blur_pipeline, self.blur_set);
+            image_barrier(
+                cmd,
+                device,
+                &[
+                    (
+                        blurred,
+                        ImageLayout::GENERAL,
+                        ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    (
+                        depth,
+                        ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                    ),
+                ],
+                PipelineStageFlags2::COMPUTE_SHADER,
+                AccessFlags2::SHADER_WRITE,
+                PipelineStageFlags2::FRAGMENT_SHADER
+                    | PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                AccessFlags2::SHADER_READ
+                    | AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            );
+        }
+    }
+}
+
+/// One barrier per `(image, old, new)` with the same masks, depth images get the depth aspect.
+unsafe fn image_barrier(
+    cmd: CommandBuffer,
+    device: &Arc<VkDevice>,
+    images: &[(Image, ImageLayout, ImageLayout)],
+    src_stage: PipelineStageFlags2,
+    src_access: AccessFlags2,
+    dst_stage: PipelineStageFlags2,
+    dst_access: AccessFlags2,
+) {
+    let barriers: Vec<_> = images
+        .iter()
+        .map(|(image, old_layout, new_layout)| {
+            let depth = [*old_layout, *new_layout].iter().any(|layout| {
+                matches!(
+                    *layout,
+                    ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+                        | ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                )
+            });
+            ImageMemoryBarrier2::default()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access)
+                .old_layout(*old_layout)
+                .new_layout(*new_layout)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(*image)
+                .subresource_range(image_subresource_range(if depth {
+                    ImageAspectFlags::DEPTH
+                } else {
+                    ImageAspectFlags::COLOR
+                }))
+        })
+        .collect();
+    unsafe {
+        device.cmd_pipeline_barrier2(cmd, &[], &[], &barriers);
+    }
+}
+
+/// Points in the +z hemisphere, more of them close to the center so nearby geometry weighs more.
+fn hemisphere_kernel() -> [Vector4<f32>; SSAO_KERNEL_SIZE] {
+    std::array::from_fn(|idx| {
+        let seed = idx as u32 * 3;
+        let direction = Vector3::new(
+            random(seed) * 2.0 - 1.0,
+            random(seed + 1) * 2.0 - 1.0,
+            random(seed + 2),
+        )
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(Vector3::z);
+        let t = idx as f32 / SSAO_KERNEL_SIZE as f32;
+        let scale = 0.1 + 0.9 * t * t;
+        (direction * scale * random(seed + 500).max(0.1)).push(0.0)
+    })
+}
+
+/// Hash of `seed` mapped to [0, 1), the kernel and noise only need to look random and stay the
+/// same between runs.
+fn random(seed: u32) -> f32 {
+    let mut x = seed.wrapping_mul(0x9E37_79B9) ^ 0x85EB_CA6B;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1 << 24) as f32
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use nalgebra::Vector3;
+
+use crate::geom::scene::{InstanceId, PrefabInstance};
+
+use super::camera::Camera;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the linear progress `t` in [0, 1] onto the eased progress.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// What a tween moves, instances are looked up in the scene the tween was added to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweenTarget {
+    Instance(InstanceId),
+    Camera,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TweenId(pub usize);
+
+/// Interpolates the position of a `TweenTarget` over `duration`.
+#[derive(Debug, Clone)]
+pub struct Tween {
+    target: TweenTarget,
+    from: Vector3<f32>,
+    to: Vector3<f32>,
+    duration: Duration,
+    elapsed: Duration,
+    easing: Easing,
+}
+
+impl Tween {
+    pub fn position(
+        target: TweenTarget,
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+        duration: Duration,
+        easing: Easing,
+    ) -> Self {
+        Self {
+            target,
+            from,
+            to,
+            duration,
+            elapsed: Duration::ZERO,
+            easing,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    fn current_position(&self) -> Vector3<f32> {
+        let progress = if self.duration.is_zero() {
+            1.0
+        } else {
+            self.elapsed.as_secs_f32() / self.duration.as_secs_f32()
+        };
+        self.from.lerp(&self.to, self.easing.apply(progress))
+    }
+}
+
+/// Running tweens of a scene, advanced once per frame before the scene is drawn.
+#[derive(Debug, Default)]
+pub struct Tweens {
+    active: Vec<(TweenId, Tween)>,
+    next_id: usize,
+}
+
+impl Tweens {
+    pub fn add(&mut self, tween: Tween) -> TweenId {
+        let id = TweenId(self.next_id);
+        self.next_id += 1;
+        self.active.push((id, tween));
+        id
+    }
+
+    /// Stops a tween where it currently is.
+    pub fn cancel(&mut self, id: TweenId) {
+        self.active.retain(|(tween_id, _)| *tween_id != id);
+    }
+
+    pub fn is_running(&self, id: TweenId) -> bool {
+        self.active.iter().any(|(tween_id, _)| *tween_id == id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    /// Advances every tween by `delta`, writes the positions and drops finished tweens. Tweens
+    /// whose instance no longer exists are dropped as well.
+    pub fn update(
+        &mut self,
+        delta: Duration,
+        camera: &mut Camera,
+        instances: &mut [PrefabInstance],
+    ) {
+        self.active.retain_mut(|(_, tween)| {
+            tween.elapsed = (tween.elapsed + delta).min(tween.duration);
+            let position = tween.current_position();
+            match tween.target {
+                TweenTarget::Camera => camera.set_position(position),
+                TweenTarget::Instance(instance_id) => match instances.get_mut(instance_id.0) {
+                    Some(instance) => instance
+                        .transform
+                        .fixed_view_mut::<3, 1>(0, 3)
+                        .copy_from(&position),
+                    None => return false,
+                },
+            }
+            !tween.is_finished()
+        });
+    }
+}
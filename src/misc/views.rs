@@ -0,0 +1,127 @@
+use ash::vk::{Extent2D, Offset2D, Rect2D, Viewport};
+
+use crate::geom::scene::SceneData;
+
+use super::camera::Camera;
+
+/// Views a frame can be split into, each one has a scene data slot per frame.
+pub const MAX_VIEWS: usize = 4;
+
+/// Part of the render area a view is drawn into, in fractions of its width and height from
+/// the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewRect {
+    pub const FULL: ViewRect = ViewRect::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The rect in pixels of `area`, clamped to it and at least a pixel wide and high. Views
+    /// next to each other share their edges without gaps.
+    pub fn to_pixels(&self, area: &Rect2D) -> Rect2D {
+        let edge = |fraction: f32, size: u32| (fraction.clamp(0.0, 1.0) * size as f32).round() as u32;
+        let (width, height) = (area.extent.width, area.extent.height);
+        let left = edge(self.x, width).min(width.saturating_sub(1));
+        let top = edge(self.y, height).min(height.saturating_sub(1));
+        let right = edge(self.x + self.width, width).max(left + 1);
+        let bottom = edge(self.y + self.height, height).max(top + 1);
+        Rect2D::default()
+            .offset(
+                Offset2D::default()
+                    .x(area.offset.x + left as i32)
+                    .y(area.offset.y + top as i32),
+            )
+            .extent(Extent2D::default().width(right - left).height(bottom - top))
+    }
+}
+
+/// Which camera a view is seen through.
+pub enum ViewCamera {
+    /// The camera of the active scene, the one moved by the input handling.
+    Scene,
+    Custom(Camera),
+}
+
+/// A camera drawn into its own part of the frame, e.g. for split-screen or the top, front and
+/// side views of an editor.
+pub struct View {
+    pub camera: ViewCamera,
+    pub rect: ViewRect,
+}
+
+impl View {
+    pub fn new(camera: ViewCamera, rect: ViewRect) -> Self {
+        Self { camera, rect }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ViewId(pub usize);
+
+/// A view resolved for one frame, what the passes draw it with.
+#[derive(Debug, Clone)]
+pub struct ViewTarget {
+    pub scene_data: SceneData,
+    pub viewport: Viewport,
+    pub scissor: Rect2D,
+}
+
+impl ViewTarget {
+    pub fn new(scene_data: SceneData, scissor: Rect2D) -> Self {
+        let viewport = Viewport::default()
+            .x(scissor.offset.x as f32)
+            .y(scissor.offset.y as f32)
+            .width(scissor.extent.width as f32)
+            .height(scissor.extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+        Self {
+            scene_data,
+            viewport,
+            scissor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Extent2D, Offset2D, Rect2D};
+
+    use super::ViewRect;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rect2D {
+        Rect2D::default()
+            .offset(Offset2D::default().x(x).y(y))
+            .extent(Extent2D::default().width(width).height(height))
+    }
+
+    #[test]
+    fn split_screen_halves_share_their_edge() {
+        let area = rect(0, 0, 1001, 600);
+        let left = ViewRect::new(0.0, 0.0, 0.5, 1.0).to_pixels(&area);
+        let right = ViewRect::new(0.5, 0.0, 0.5, 1.0).to_pixels(&area);
+        assert_eq!(left, rect(0, 0, 501, 600));
+        assert_eq!(right, rect(501, 0, 500, 600));
+        assert_eq!(ViewRect::FULL.to_pixels(&area), area);
+    }
+
+    #[test]
+    fn degenerate_rects_keep_a_pixel_inside_the_area() {
+        let area = rect(10, 20, 100, 50);
+        assert_eq!(ViewRect::new(1.0, 1.0, 0.0, 0.0).to_pixels(&area), rect(109, 69, 1, 1));
+        assert_eq!(ViewRect::new(-1.0, 0.5, 3.0, 2.0).to_pixels(&area), rect(10, 45, 100, 25));
+    }
+}
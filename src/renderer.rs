@@ -1,31 +1,51 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Debug,
     ops::{Add, Deref},
+    path::Path,
     rc::Weak,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use ash::{
     ext::debug_utils,
     vk::{
         AttachmentLoadOp, Buffer, BufferUsageFlags, ClearDepthStencilValue, ClearValue,
         ColorComponentFlags, CommandBuffer, CommandBufferBeginInfo, CommandBufferResetFlags,
-        CommandBufferUsageFlags, CullModeFlags, DebugUtilsMessengerEXT,
+        CommandBufferSubmitInfo, CommandBufferUsageFlags, ColorSpaceKHR, CompareOp, CullModeFlags,
+        DebugUtilsMessengerEXT, DescriptorSet, DeviceAddress,
         DescriptorSetLayoutCreateFlags, DescriptorType, DynamicState, Extent2D, Extent3D, Fence,
         Filter, Format, FrontFace, ImageAspectFlags, ImageLayout, ImageUsageFlags, IndexType,
-        MemoryPropertyFlags, Offset2D, PipelineBindPoint, PipelineStageFlags, PolygonMode,
-        PresentInfoKHR, PrimitiveTopology, Queue, Rect2D, RenderPassBeginInfo, SampleCountFlags,
-        Semaphore, ShaderStageFlags, SubmitInfo, SubpassContents, Viewport, WHOLE_SIZE,
+        Offset2D, PhysicalDevice, PipelineBindPoint, PipelineStageFlags2,
+        PolygonMode, PresentInfoKHR, PresentModeKHR, PrimitiveTopology, Queue, Rect2D,
+        RenderPassBeginInfo, SampleCountFlags, Semaphore, SemaphoreSubmitInfo,
+        ShaderStageFlags, SubmitInfo2, SubpassContents, SwapchainKHR, Viewport, WHOLE_SIZE,
     },
 };
-use log::debug;
-use nalgebra::{Matrix4, Perspective3, Scale3, Scale4, Vector3, Vector4};
-use vk_mem::{AllocatorCreateFlags, AllocatorCreateInfo, MemoryUsage};
-use winit::window::Window;
+use log::{debug, error, info, warn};
+use tracing::info_span;
+use egui::Color32;
+use nalgebra::{Matrix4, Scale3, Scale4, Vector3, Vector4};
+use vk_mem::{AllocatorCreateFlags, AllocatorCreateInfo};
+use winit::{
+    event::{ElementState, MouseButton, WindowEvent},
+    window::{Window, WindowId},
+};
 
-const MAX_FRAMES: usize = 2;
+pub const MAX_FRAMES: usize = 2;
+/// Size of the buffers the vertices and indices of loaded meshes are suballocated from.
+const MESH_ARENA_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+/// Color of the command buffer labels around the passes of a frame, shown by debuggers.
+const PASS_LABEL_COLOR: [f32; 4] = [0.4, 0.6, 1.0, 1.0];
+/// Fewest scene draws worth a secondary command buffer recorded on a thread of its own.
+const MIN_DRAWS_PER_CHUNK: usize = 512;
+/// Registered for the surfaces of OBJ files that select no material of their MTL files.
+const OBJ_DEFAULT_MATERIAL: &str = "obj default";
+/// Range `RendererConfig::render_scale` and `Renderer::set_render_scale` are clamped to.
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
 
 pub trait PackUnorm {
     fn pack_unorm4x8(&self) -> u32;
@@ -44,81 +64,309 @@ impl PackUnorm for Vector4<f32> {
 
 use crate::{
     components::{
-        allocation_types::{AllocatedImage, VkBuffer, VkFrameBuffer, IDENTIFIER},
-        command_buffers::VkCommandPool,
-        deletion_queue::{DeletionQueue, DestroyBufferTask, DestroyImageTask, FType},
+        allocation_types::{AllocatedImage, VkFrameBuffer, IDENTIFIER},
+        bindless::BindlessDescriptors,
+        command_buffers::{ImmediateSubmit, VkCommandPool},
+        command_log::CommandLog,
+        deletion_queue::{DeletionQueue, FType},
         descriptors::{
             DescriptorAllocator, DescriptorLayoutBuilder, DescriptorSetDetails, DescriptorWriter,
             PoolSizeRatio,
         },
         device::{self, VkDevice},
-        frame_data::{FrameData, FrameResources},
-        image_util::{copy_image_to_image, image_transition},
-        instance::{self, VkInstance},
-        memory_allocator::MemoryAllocator,
+        frame_data::{FrameData, FrameResources, SceneDataBinding},
+        image_util::{
+            copy_image_to_image, image_subresource_range, image_transition, PresentationPolicy,
+        },
+        instance::{self, Validation, VkInstance},
+        memory_allocator::{MemoryAllocator, MemoryStatistics, ReadbackImage},
         pipeline::{
             create_color_blending_attachment_state, create_multisampling_state,
-            create_rasterizer_state, ShaderInformation, VkPipeline,
+            create_rasterizer_state, ShaderInformation, VertexInput, VkPipeline,
         },
         queue::{QueueType, VkQueue},
+        raw::{RawFrameContext, RawFrameFn, RawVulkan},
         render_pass::VkRenderPass,
-        sampler::VkSampler,
+        sampler::SamplerCache,
+        secondary_commands::chunk_ranges,
         surface,
         swapchain::{ImageDetails, KHRSwapchain},
+        window_target::WindowTarget,
+        sync_pool::SyncPool,
+        buffer_arena::BufferArena,
+        upload_context::{UploadContext, UPLOAD_DST_STAGES},
+        async_compute::{AsyncCompute, COMPUTE_DST_STAGES},
+        swapchain_support_details::SwapchainSupportDetails,
+    },
+    egui::{
+        labels::ScreenLabel,
+        notifications::NotificationLevel,
+        scene_editor::{InstanceOutline, NodeOutline, SceneEdit, SceneOutline, CUSTOM_MATERIAL},
+        EguiRenderer,
     },
-    egui::EguiRenderer,
     geom::{
-        assets::{self, GLTFMaterial, MeshAsset},
+        assets::{
+            self, read_gltf_file, read_obj_meshes, GLTFMaterial, GltfMeshes, LoadedGLTF, MeshAsset,
+            MeshData, ObjMeshes,
+        },
+        bounds::Ray,
         gpu_scene_push_constant,
-        push_constants::PushConstant,
-        scene::{self, SceneData},
+        lod::LodSettings,
+        skinned_scene_push_constant,
+        push_constants::{PushConstant, PushConstantLayout},
+        scene::{self, NodeHandle, Scene, SceneData, SceneId},
         triangle_push_constant,
         vertex_3d::Vertex3D,
         VertexAttributes,
     },
     misc::{
-        camera::Camera, material::{MaterialConstants, MaterialMetallicRoughness, MaterialPass, MaterialResources}, render_object::{MeshNode, Node, RenderObject}, DrawContext, RenderNode, Renderable
+        analysis::{AnalysisResults, GpuAnalysis}, asset_reload::AssetReloader, asset_registry::{AssetKey, AssetRegistry, MaterialHandle, MeshHandle, TextureHandle}, asset_server::{AssetHandle, AssetServer, AssetStatus}, auto_quality::{AutoQuality, AutoQualitySettings, QualityChange, QualityStep, RENDER_SCALE_STEP}, camera::{Camera, CameraPose}, debug_draw::DebugDraw, deferred::DeferredShading, depth_pick::{DepthPick, DepthPicker}, depth_prepass::DepthPrepass, display_mode::DisplayMode, display_transform::{DisplayTransform, DisplayTransformPass}, gizmo::{Gizmo, GizmoMode}, gpu_timer::GpuTimer, jobs::JobSystem, post_process::{FullscreenBinding, FullscreenPassId, InsertionPoint, PostProcess, Tonemapper}, material::{MaterialConstants, MaterialMetallicRoughness, MaterialPass, MaterialResources, DEFAULT_VERTEX_SHADER, ERROR_FRAGMENT_SHADER}, material_library::{MaterialDefaults, MaterialDefinition, MaterialLibrary, Registration}, measurement::{project_to_viewport, Measurement, MeasurementId}, render_object::{MeshNode, Node, RenderObject}, skinning::JointBuffer, skybox::Skybox, snapping::GridSnap, sparse_feedback::SparseFeedback, ssao::{Ssao, SsaoSettings}, views::{View, ViewCamera, ViewId, ViewTarget, MAX_VIEWS}, DrawContext, DrawStats, FrameStats, RenderNode, Renderable
     },
 };
 
+#[cfg(feature = "sparse-textures")]
+use crate::components::sparse_image::{SparseImage, SparsePage};
+
+/// Options fixed for the lifetime of the renderer.
+#[derive(Debug, Clone, Copy)]
+pub struct RendererConfig {
+    /// Samples per pixel of the scene color and depth attachments, clamped to what the device
+    /// supports. Anything above `TYPE_1` renders into a multisampled image that is resolved
+    /// into the draw image at the end of the main render pass.
+    pub msaa_samples: SampleCountFlags,
+    /// Encoding of the final image, `None` picks one from the color spaces the surface supports.
+    /// Also decides the color space the swapchain is created with.
+    pub display_transform: Option<DisplayTransform>,
+    /// Initial clear color of the scene pass, can be changed later with `set_clear_color`.
+    pub clear_color: [f32; 4],
+    /// Load op of the scene color attachment, `CLEAR` or `DONT_CARE` when a skybox covers every
+    /// pixel anyway. `LOAD` is not supported since the draw image is re-encoded every frame.
+    pub scene_load_op: AttachmentLoadOp,
+    /// Load op of the egui pass on the swapchain image, `LOAD` draws the UI over the scene and
+    /// `CLEAR` gives a UI only frame.
+    pub ui_load_op: AttachmentLoadOp,
+    /// Writes the depth of the opaque surfaces before shading them, the scene pass then only
+    /// shades the closest fragment of each pixel (EQUAL depth test). Cuts overdraw in heavy
+    /// scenes at the cost of transforming the geometry twice.
+    pub depth_prepass: bool,
+    /// Screen space ambient occlusion darkening the ambient light, implies `depth_prepass`. Not
+    /// available with MSAA.
+    pub ssao: bool,
+    /// Shades the surfaces of the default PBR material once per pixel from a G-buffer (albedo,
+    /// normal, metal-rough, emissive and the scene depth) instead of once per draw. Transparent
+    /// and custom shader materials are still drawn forward on top. Not available with MSAA.
+    pub deferred: bool,
+    /// Lowers the quality while the GPU frame time stays over budget and restores it once there
+    /// is headroom again, see `Renderer::set_auto_quality`.
+    pub auto_quality: Option<AutoQualitySettings>,
+    /// FIFO waits for VSync, IMMEDIATE and MAILBOX do not, e.g. for benchmarking. Falls back to
+    /// the closest mode the surface supports, can be changed later with `set_present_mode`.
+    pub present_mode: PresentModeKHR,
+    /// Validation layer and debug messenger, by default `Full` in debug and `Off` in release
+    /// builds. Renders without validation if the layer is not installed.
+    pub validation: Validation,
+    /// Pushes the scene data descriptor with VK_KHR_push_descriptor whenever the mesh
+    /// pipelines bind their sets instead of keeping a descriptor set per frame. Ignored if the
+    /// device lacks the extension.
+    pub push_descriptors: bool,
+    /// Size of the draw and depth images relative to the swapchain extent, below 1 to render
+    /// fewer pixels on slow GPUs and above 1 for supersampling. The final image is scaled onto
+    /// the swapchain. Ignored by headless renderers, their draw image has the requested extent.
+    pub render_scale: f32,
+    /// How the final image is placed on a window of another aspect ratio, can be changed later
+    /// with `set_presentation_policy`.
+    pub presentation: PresentationPolicy,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            msaa_samples: SampleCountFlags::TYPE_1,
+            display_transform: None,
+            clear_color: [0.0, 0.0, 0.0, 0.0],
+            scene_load_op: AttachmentLoadOp::CLEAR,
+            ui_load_op: AttachmentLoadOp::LOAD,
+            depth_prepass: false,
+            ssao: false,
+            deferred: false,
+            auto_quality: None,
+            present_mode: PresentModeKHR::FIFO,
+            validation: Validation::default(),
+            push_descriptors: false,
+            render_scale: 1.0,
+            presentation: PresentationPolicy::default(),
+        }
+    }
+}
+
 #[allow(unused)]
 pub struct Renderer {
     pub instance: Arc<VkInstance>,
-    debug_instance: debug_utils::Instance,
-    debugger: DebugUtilsMessengerEXT,
+    /// `None` with `Validation::Off` or without VK_EXT_debug_utils.
+    debugger: Option<(debug_utils::Instance, DebugUtilsMessengerEXT)>,
     device: Arc<VkDevice>,
     graphics_queue: Arc<VkQueue>,
-    presentation_queue: Arc<VkQueue>,
-    swapchain: Arc<KHRSwapchain>,
+    /// `None` for headless renderers, like `window_target` and `egui_renderer`.
+    presentation_queue: Option<Arc<VkQueue>>,
+    /// Swapchain of the window the renderer was created for, the UI is drawn on it.
+    window_target: Option<WindowTarget>,
+    /// Windows opened with `add_window`, they show the same frames without the UI.
+    secondary_windows: Vec<SecondaryWindow>,
     render_pass: Arc<VkRenderPass>,
     memory_allocator: Arc<MemoryAllocator>,
     draw_image: AllocatedImage,
     depth_image: AllocatedImage,
     descriptor_allocator: DescriptorAllocator,
+    samplers: SamplerCache,
     descriptor_layout_builder: DescriptorLayoutBuilder<'static>,
     descriptor_writer: DescriptorWriter,
     single_image_descriptor: DescriptorSetDetails,
     gltf_pipeline: VkPipeline,
     gltf_buffers: Vec<Arc<Mutex<MeshAsset<Vertex3D>>>>,
+    /// Holds the vertex and index buffers of every loaded mesh.
+    mesh_arena: BufferArena,
+    /// Re-reads loaded glTF files that changed on disk, see `reload_changed_assets`.
+    asset_reloader: AssetReloader,
+    /// Loads glTF files and textures on the job system, see `load_gltf_async`.
+    asset_server: AssetServer,
+    /// Meshes, textures and materials shared through handles, see `load_meshes`.
+    assets: AssetRegistry,
     viewports: Vec<Viewport>,
     scissors: Vec<Rect2D>,
-    swapchain_image_details: Vec<ImageDetails>,
     framebuffers: HashMap<IDENTIFIER, Vec<VkFrameBuffer>>,
     frame_data: Vec<FrameData>,
     frame_idx: usize,
+    /// Frames begun so far, deferred deletions wait for the frames they were queued after.
+    frame_number: u64,
+    /// Scene data of the first view.
     scene_data: SceneData,
+    /// Split the frame, see `add_view`. Empty draws the scene camera over the whole frame.
+    views: Vec<(ViewId, View)>,
+    next_view: usize,
+    /// `views` resolved by `update_scene` for the next frame.
+    view_targets: Vec<ViewTarget>,
+    /// Part of the render targets the frames are drawn into, smaller than `extent` while
+    /// `set_render_scale` lowers the resolution.
     render_area: Rect2D,
+    /// Size of the render targets.
     extent: Extent2D,
+    /// Scale of `render_area` relative to the swapchain extent.
+    render_scale: f32,
     command_pool: VkCommandPool,
     main_deletion_queue: DeletionQueue,
-    loaded_nodes: HashMap<String, Box<dyn Renderable>>,
-    camera: Camera,
+    scenes: Vec<Scene>,
+    active_scene: SceneId,
     draw_ctx: DrawContext,
+    /// Commands of the scene draws in the frame recorded last.
+    draw_stats: DrawStats,
+    /// Errors already logged and shown, each one is reported only once.
+    reported_errors: HashSet<String>,
+    debug_draw: DebugDraw,
+    joint_buffer: JointBuffer,
+    analysis: GpuAnalysis,
+    depth_prepass: Option<DepthPrepass>,
+    /// Flags the pages of the sparse textures that get sampled, created with the first one.
+    sparse_feedback: Option<SparseFeedback>,
+    #[cfg(feature = "sparse-textures")]
+    sparse_textures: Vec<SparseTexture>,
+    ssao: Option<Ssao>,
+    deferred: Option<DeferredShading>,
+    depth_picker: DepthPicker,
+    gpu_timer: GpuTimer,
+    auto_quality: Option<AutoQuality>,
+    /// Render scales before each `QualityStep::ReduceRenderScale` taken, latest last.
+    lowered_render_scales: Vec<f32>,
+    post_process: PostProcess,
+    display_transform: DisplayTransformPass,
+    sync_pool: SyncPool,
+    /// Streams buffers and images through the transfer queue, batches are submitted per frame.
+    upload_context: UploadContext,
+    /// Compute passes on the compute queue, each frame waits for the batches submitted before.
+    async_compute: AsyncCompute,
+    skybox: Option<Skybox>,
+    /// Destroys the resources of `skybox`, kept apart so a replaced skybox can be deferred.
+    skybox_deletion_queue: Option<DeletionQueue>,
+    materials: MaterialLibrary,
+    /// Textures and constants of every material, bound once per frame as set 1.
+    bindless: BindlessDescriptors,
+    grid_snap: GridSnap,
+    /// View projection culling is checked against while the culling camera is frozen.
+    frozen_view_proj: Option<Matrix4<f32>>,
+    measurements: HashMap<MeasurementId, Measurement>,
+    next_measurement: usize,
+    skip_idle_frames: bool,
+    command_logging: bool,
+    /// Shows the allocator statistics in the debug window.
+    memory_overlay: bool,
+    /// Shows the scene editor panel for the active scene.
+    scene_editor: bool,
+    /// Node manipulated with the gizmo, picked with the mouse or set with `select`.
+    selection: Option<NodeHandle>,
+    gizmo: Gizmo,
+    /// Last cursor position over the window in physical pixels, `None` while it is outside.
+    cursor_position: Option<[f32; 2]>,
+    /// Commands of the last frame drawn while `command_logging` was on.
+    last_command_log: Option<CommandLog>,
+    /// Queued by `with_raw_frame`, run in the next drawn frame.
+    raw_frame_callbacks: Vec<RawFrameFn>,
+    /// View projection of every view in the last drawn frame.
+    last_view_projs: Vec<Matrix4<f32>>,
+    last_frame: Instant,
+    /// Set while the simulation is stepped from outside with `update`, how far the frames are
+    /// drawn between the last two steps. Otherwise every frame steps by its own duration.
+    interpolation: Option<f32>,
+    /// The active scene before the last `update`, what frames interpolate from.
+    previous_step: Option<SimulationSnapshot>,
+    invalidated: bool,
+    config: RendererConfig,
     pub checkboard_image: AllocatedImage,
-    pub egui_renderer: EguiRenderer,
+    pub egui_renderer: Option<EguiRenderer>,
+}
+
+/// A window opened with `Renderer::add_window`. The target goes first, its surface has to be
+/// destroyed before the window.
+struct SecondaryWindow {
+    target: WindowTarget,
+    window: Arc<Window>,
+}
+
+#[cfg(feature = "sparse-textures")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SparseTextureId(pub usize);
+
+/// A texture added with `Renderer::add_sparse_texture`.
+#[cfg(feature = "sparse-textures")]
+struct SparseTexture {
+    image: SparseImage,
+    /// Texels of a page once it gets bound, tightly packed over the extent of the page.
+    fill_page: Box<dyn FnMut(SparsePage, Extent3D) -> Vec<u8>>,
+    /// Slot of the image in the bindless texture array.
+    texture_index: u32,
+    /// Target of the texture in `Renderer::sparse_feedback`.
+    feedback_target: usize,
+}
+
+/// What `update` moves, as it was before a step.
+struct SimulationSnapshot {
+    scene: SceneId,
+    camera: CameraPose,
+    /// Poses of the views with their own camera.
+    views: Vec<(ViewId, CameraPose)>,
+    /// Translation of every instance of the scene.
+    instances: Vec<Vector3<f32>>,
+    animation_time: f32,
+}
+
+/// Which window an image of a frame was acquired from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WindowSlot {
+    Main,
+    /// Index into `Renderer::secondary_windows`.
+    Secondary(usize),
 }
 
 #[allow(unused)]
+#[derive(Debug, Clone, Copy)]
 pub struct ImageIndex {
     pub index: u32,
     pub recreate_swapchain: bool,
@@ -141,35 +389,144 @@ impl ImageIndex {
     }
 }
 
+/// Everything `Renderer::record_command_buffer` records a frame from, split off the renderer so
+/// the passes borrowed mutably don't lock the rest of it.
+struct FrameContext<'a> {
+    frame_resources: &'a mut FrameResources,
+    device: &'a Arc<VkDevice>,
+    /// The final image is copied to each of them, which end up in the layout given with them.
+    swapchain_images: &'a [(ImageDetails, Extent2D, ImageLayout)],
+    presentation: PresentationPolicy,
+    draw_image: &'a AllocatedImage,
+    graphics_queue: &'a VkQueue,
+    render_area: &'a Rect2D,
+    views: &'a [ViewTarget],
+    bindless_set: DescriptorSet,
+    extent: &'a Extent2D,
+    render_pass: &'a Arc<VkRenderPass>,
+    depth_image: &'a AllocatedImage,
+    framebuffers: &'a HashMap<IDENTIFIER, Vec<VkFrameBuffer>>,
+    draw_ctx: &'a DrawContext,
+    debug_draw: &'a mut DebugDraw,
+    joint_buffer: &'a mut JointBuffer,
+    skybox: Option<&'a Skybox>,
+    analysis: &'a GpuAnalysis,
+    depth_prepass: Option<&'a DepthPrepass>,
+    sparse_feedback: Option<&'a SparseFeedback>,
+    ssao: Option<&'a Ssao>,
+    deferred: Option<&'a DeferredShading>,
+    depth_picker: &'a mut DepthPicker,
+    gpu_timer: &'a mut GpuTimer,
+    raw_frame_callbacks: Vec<RawFrameFn>,
+    post_process: &'a PostProcess,
+    display_transform: &'a DisplayTransformPass,
+    clear_color: [f32; 4],
+    upload_context: &'a mut UploadContext,
+    async_compute: &'a mut AsyncCompute,
+    frame_idx: usize,
+}
+
+/// What the scene surfaces of a frame are drawn with, the same for every chunk and view.
+#[derive(Clone, Copy)]
+struct SurfacePass<'a> {
+    device: &'a Arc<VkDevice>,
+    bindless_set: DescriptorSet,
+    joint_address: DeviceAddress,
+    /// Shades only the fragments matching the depth the pre-pass wrote.
+    after_depth_prepass: bool,
+    /// Leaves out the surfaces the deferred path draws.
+    skip_deferred: bool,
+}
+
 impl Renderer {
     pub fn init(window: &Window) -> Result<Renderer, Error> {
-        let vk_instance = Arc::new(instance::VkInstance::new(window)?);
-        let (debug_instance, debugger) = instance::VkInstance::create_debugger(vk_instance.clone());
-        let surface = Arc::new(surface::KHRSurface::new(vk_instance.clone(), window)?);
+        Self::init_with_config(window, RendererConfig::default())
+    }
+
+    pub fn init_with_config(window: &Window, config: RendererConfig) -> Result<Renderer, Error> {
+        Self::create(Some(window), Extent2D::default(), config)
+    }
+
+    /// Creates a renderer without a window, surface, swapchain or UI that renders `extent`
+    /// sized frames into the draw image only. Frames are drawn with `render_offscreen` and
+    /// copied back with `read_draw_image`, for tests and batch rendering without a display.
+    pub fn init_headless(extent: Extent2D, config: RendererConfig) -> Result<Renderer, Error> {
+        Self::create(None, extent, config)
+    }
+
+    /// `headless_extent` is the size of the draw image when there is no `window`.
+    fn create(
+        window: Option<&Window>,
+        headless_extent: Extent2D,
+        config: RendererConfig,
+    ) -> Result<Renderer, Error> {
+        let vk_instance = Arc::new(instance::VkInstance::new(window, config.validation)?);
+        let debugger = instance::VkInstance::create_debugger(vk_instance.clone());
+        let surface = window
+            .map(|window| surface::KHRSurface::new(vk_instance.clone(), window).map(Arc::new))
+            .transpose()?;
         let vk_device = Arc::new(device::VkDevice::new(
             vk_instance.clone(),
-            surface.clone(),
-            window,
+            surface.clone().zip(window),
         )?);
         let graphics_queue = Arc::new(VkQueue::new(
             vk_device.clone(),
             surface.clone(),
             QueueType::GRAPHICS_QUEUE,
         )?);
-        let presentation_queue = Arc::new(VkQueue::new(
+        let transfer_queue = Arc::new(VkQueue::new(
             vk_device.clone(),
             surface.clone(),
-            QueueType::PRESENT_QUEUE,
+            QueueType::TRANSFER_QUEUE,
         )?);
-        let swapchain = Arc::new(KHRSwapchain::new(
-            vk_instance.clone(),
+        let compute_queue = Arc::new(VkQueue::new(
             vk_device.clone(),
             surface.clone(),
-            window,
-            [graphics_queue.clone(), presentation_queue.clone()],
+            QueueType::COMPUTE_QUEUE,
         )?);
+        let (presentation_queue, swapchain, display_transform) = match surface.zip(window) {
+            Some((surface, window)) => {
+                let presentation_queue = Arc::new(VkQueue::new(
+                    vk_device.clone(),
+                    Some(surface.clone()),
+                    QueueType::PRESENT_QUEUE,
+                )?);
+                let display_transform = match config.display_transform {
+                    Some(display_transform) => display_transform,
+                    None => {
+                        let details = SwapchainSupportDetails::get_swapchain_support_details(
+                            vk_device.physical_device,
+                            surface.clone(),
+                            window,
+                        )?;
+                        DisplayTransform::detect(&details)
+                    }
+                };
+                let swapchain = Arc::new(KHRSwapchain::new(
+                    vk_instance.clone(),
+                    vk_device.clone(),
+                    surface,
+                    window,
+                    [graphics_queue.clone(), presentation_queue.clone()],
+                    display_transform.color_space(),
+                    config.present_mode,
+                )?);
+                (Some(presentation_queue), Some(swapchain), display_transform)
+            }
+            None => (None, None, config.display_transform.unwrap_or_default()),
+        };
         let command_pool = VkCommandPool::new(graphics_queue.clone());
-        let extent = swapchain.details.clone().choose_swapchain_extent(window);
+        let render_scale = match swapchain {
+            Some(_) => clamp_render_scale(config.render_scale),
+            None => 1.0,
+        };
+        let extent = match swapchain.as_ref().zip(window) {
+            Some((swapchain, window)) => scale_extent(
+                swapchain.details.clone().choose_swapchain_extent(window),
+                render_scale,
+            ),
+            None => headless_extent,
+        };
         let mut alloc_info =
             AllocatorCreateInfo::new(&vk_instance, &vk_device, vk_device.physical_device);
         alloc_info.flags = AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS;
@@ -189,41 +546,127 @@ impl Renderer {
             },
             Format::R16G16B16A16_SFLOAT,
             None,
-            ImageUsageFlags::STORAGE | ImageUsageFlags::COLOR_ATTACHMENT,
+            ImageUsageFlags::STORAGE
+                | ImageUsageFlags::COLOR_ATTACHMENT
+                | ImageUsageFlags::SAMPLED
+                | ImageUsageFlags::TRANSFER_SRC,
             ImageAspectFlags::COLOR,
             false,
         )?;
-        let allocation = draw_image.allocation;
-        let draw_image = draw_image.unit.get_cloned::<AllocatedImage>();
-        main_deletion_queue.enqueue(FType::TASK(Box::new(DestroyImageTask {
-            image: draw_image.image_details.image,
-            allocation,
-        })));
-        main_deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
-            device.destroy_image_view(draw_image.image_details.image_view, None)
-        })));
+        vk_device
+            .debug_utils
+            .name(draw_image.image_details.image, "draw image");
+        main_deletion_queue.enqueue(FType::DROP(Box::new(draw_image.clone())));
         let mut framebuffers: HashMap<IDENTIFIER, Vec<VkFrameBuffer>> = HashMap::new();
-        let depth_image = memory_allocator.create_image(
-            Extent3D {
-                width: extent.width,
-                height: extent.height,
-                depth: 1,
+        // shown once the UI exists
+        let mut init_notifications = vec![];
+        if config.scene_load_op == AttachmentLoadOp::LOAD {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                "LOAD is not supported for the scene pass, clearing instead".to_owned(),
+            ));
+        }
+        if swapchain.is_some() && render_scale != config.render_scale {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                format!(
+                    "Render scale {} is out of range, using {render_scale}",
+                    config.render_scale
+                ),
+            ));
+        }
+        if let Some(swapchain) = &swapchain
+            && swapchain.present_mode != config.present_mode
+        {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                format!(
+                    "{:?} presentation is not supported by the surface, using {:?}",
+                    config.present_mode, swapchain.present_mode
+                ),
+            ));
+        }
+        let msaa_samples = vk_device.max_usable_sample_count(config.msaa_samples);
+        if msaa_samples != config.msaa_samples {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                format!(
+                    "{:?} MSAA is not supported by the device, using {:?}",
+                    config.msaa_samples, msaa_samples
+                ),
+            ));
+        }
+        if config.ssao && msaa_samples != SampleCountFlags::TYPE_1 {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                "SSAO is not available with MSAA, it is disabled".to_owned(),
+            ));
+        }
+        if config.deferred && msaa_samples != SampleCountFlags::TYPE_1 {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                "Deferred shading is not available with MSAA, rendering forward".to_owned(),
+            ));
+        }
+        if config.push_descriptors && vk_device.push_descriptor.is_none() {
+            init_notifications.push((
+                NotificationLevel::Warn,
+                "VK_KHR_push_descriptor is not supported, binding descriptor sets instead"
+                    .to_owned(),
+            ));
+        }
+        let ssao = config.ssao && msaa_samples == SampleCountFlags::TYPE_1;
+        let config = RendererConfig {
+            msaa_samples,
+            depth_prepass: config.depth_prepass || ssao,
+            ssao,
+            deferred: config.deferred && msaa_samples == SampleCountFlags::TYPE_1,
+            display_transform: Some(display_transform),
+            present_mode: swapchain
+                .as_ref()
+                .map_or(config.present_mode, |swapchain| swapchain.present_mode),
+            scene_load_op: match config.scene_load_op {
+                AttachmentLoadOp::LOAD => AttachmentLoadOp::CLEAR,
+                load_op => load_op,
             },
+            push_descriptors: config.push_descriptors && vk_device.push_descriptor.is_some(),
+            render_scale,
+            ..config
+        };
+        let draw_extent = Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+        let msaa_image = if config.msaa_samples != SampleCountFlags::TYPE_1 {
+            let msaa_image = memory_allocator.create_multisampled_image(
+                draw_extent,
+                draw_image.image_format,
+                ImageUsageFlags::COLOR_ATTACHMENT,
+                ImageAspectFlags::COLOR,
+                config.msaa_samples,
+            )?;
+            vk_device
+                .debug_utils
+                .name(msaa_image.image_details.image, "multisampled draw image");
+            main_deletion_queue.enqueue(FType::DROP(Box::new(msaa_image.clone())));
+            Some(msaa_image)
+        } else {
+            None
+        };
+        let depth_image = memory_allocator.create_multisampled_image(
+            draw_extent,
             Format::D32_SFLOAT,
-            None,
-            ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                | ImageUsageFlags::TRANSFER_SRC
+                | ImageUsageFlags::SAMPLED,
             ImageAspectFlags::DEPTH,
-            false,
+            config.msaa_samples,
         )?;
-        let depth_allocation = depth_image.allocation;
-        let depth_image = depth_image.unit.get_copied::<AllocatedImage>();
-        main_deletion_queue.enqueue(FType::TASK(Box::new(DestroyImageTask {
-            image: depth_image.image_details.image,
-            allocation: depth_allocation,
-        })));
-        main_deletion_queue.enqueue(FType::DEVICE(Box::new(move |device| unsafe {
-            device.destroy_image_view(depth_image.image_details.image_view, None)
-        })));
+        vk_device
+            .debug_utils
+            .name(depth_image.image_details.image, "depth image");
+        main_deletion_queue.enqueue(FType::DROP(Box::new(depth_image.clone())));
 
         let white = Vector4::<f32>::new(1.0, 1.0, 1.0, 1.0).pack_unorm4x8();
         let white_image = memory_allocator
@@ -259,6 +702,23 @@ impl Renderer {
             )
             .unwrap();
 
+        let flat_normal = Vector4::new(0.5, 0.5, 1.0, 1.0).pack_unorm4x8();
+        let flat_normal_image = memory_allocator
+            .create_image_with_data(
+                &[flat_normal],
+                Extent3D {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+                Format::R8G8B8A8_UNORM,
+                ImageUsageFlags::SAMPLED,
+                ImageAspectFlags::COLOR,
+                &command_pool,
+                false,
+            )
+            .unwrap();
+
         let black = Vector4::new(0.0, 0.0, 0.0, 0.0).pack_unorm4x8();
         let black_image = memory_allocator
             .create_image_with_data(
@@ -317,35 +777,52 @@ impl Renderer {
             false,
         )?;
 
-        let default_nearest_sampler =
-            VkSampler::with_filter(vk_device.clone(), Filter::NEAREST, Filter::NEAREST);
-
-        let default_linear_sampler =
-            VkSampler::with_filter(vk_device.clone(), Filter::LINEAR, Filter::LINEAR);
+        let samplers = SamplerCache::new(vk_device.clone());
+        let default_nearest_sampler = samplers.with_filter(Filter::NEAREST, Filter::NEAREST);
+        let default_linear_sampler = samplers.with_filter(Filter::LINEAR, Filter::LINEAR);
 
-        let render_pass = Arc::new(VkRenderPass::new(
-            vk_device.clone(),
-            swapchain.details.clone().choose_swapchain_format().format,
-            ImageLayout::UNDEFINED,
-            ImageLayout::TRANSFER_SRC_OPTIMAL,
-            AttachmentLoadOp::CLEAR,
-            true,
-        )?);
+        // the scene is drawn into the draw image, only the UI goes straight to the swapchain
+        let surface_format = swapchain
+            .as_ref()
+            .map_or(draw_image.image_format, |swapchain| swapchain.format);
+        // the deferred path writes the depth in its G-buffer pass
+        let render_pass = Arc::new(if config.depth_prepass || config.deferred {
+            VkRenderPass::new_multisampled_after_prepass(
+                vk_device.clone(),
+                draw_image.image_format,
+                config.msaa_samples,
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                config.scene_load_op,
+            )?
+        } else {
+            VkRenderPass::new_multisampled(
+                vk_device.clone(),
+                draw_image.image_format,
+                config.msaa_samples,
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                config.scene_load_op,
+                true,
+            )?
+        });
+        let draw_attachments = match msaa_image {
+            Some(msaa_image) => vec![
+                msaa_image.image_details,
+                depth_image.image_details,
+                draw_image.image_details,
+            ],
+            None => vec![draw_image.image_details, depth_image.image_details],
+        };
         let draw_framebuffers = VkFrameBuffer::create_framebuffer(
             IDENTIFIER::DRAW,
             vk_device.clone(),
             render_pass.clone(),
             extent,
-            &[draw_image.image_details, depth_image.image_details],
-        );
-        let mut descriptor_allocator = DescriptorAllocator::new(
-            vk_device.clone(),
-            16,
-            vec![
-                PoolSizeRatio::new(DescriptorType::COMBINED_IMAGE_SAMPLER, 1.0),
-                PoolSizeRatio::new(DescriptorType::UNIFORM_BUFFER, 2.0),
-            ],
+            &draw_attachments,
         );
+        let mut descriptor_allocator =
+            DescriptorAllocator::new(vk_device.clone(), 16, PoolSizeRatio::defaults());
         let scene_data = SceneData::default();
         /* let scene_descriptor = descriptor_allocator.write_image_descriptors(
             &draw_image.image_details.image_view,
@@ -360,15 +837,39 @@ impl Renderer {
             extent,
             &[draw_image.image_details],
         ) */
-        let swapchain_image_details = swapchain.create_image_details()?;
+        let window_target = match swapchain.clone().zip(window) {
+            Some((swapchain, window)) => {
+                Some(WindowTarget::new(vk_device.clone(), window.id(), swapchain)?)
+            }
+            None => None,
+        };
         framebuffers.insert(IDENTIFIER::DRAW, vec![draw_framebuffers]);
+        // set 0 of every mesh pipeline
+        let scene_data_layout = DescriptorLayoutBuilder::new()
+            .add_binding(
+                0,
+                DescriptorType::UNIFORM_BUFFER,
+                ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            )
+            .build_cached(
+                descriptor_allocator.layout_cache(),
+                ShaderStageFlags::empty(),
+                if config.push_descriptors {
+                    DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR
+                } else {
+                    DescriptorSetLayoutCreateFlags::empty()
+                },
+            );
         let mut frame_data: Vec<FrameData> = Vec::new();
         for _i in 0..MAX_FRAMES {
             frame_data.push(FrameData::new(
                 vk_device.clone(),
                 memory_allocator.clone(),
                 graphics_queue.clone(),
-            ));
+                &mut descriptor_allocator,
+                scene_data_layout,
+                config.push_descriptors,
+            )?);
         }
         let render_area = Rect2D::default()
             .offset(Offset2D::default().y(0).x(0))
@@ -390,8 +891,8 @@ impl Renderer {
             DescriptorType::COMBINED_IMAGE_SAMPLER,
             ShaderStageFlags::empty(),
         );
-        let single_image_layout = descriptor_layout_builder.build(
-            vk_device.clone(),
+        let single_image_layout = descriptor_layout_builder.build_cached(
+            descriptor_allocator.layout_cache(),
             ShaderStageFlags::FRAGMENT,
             DescriptorSetLayoutCreateFlags::empty(),
         );
@@ -400,12 +901,8 @@ impl Renderer {
             descriptor_allocator.allocate(vk_device.clone(), &[single_image_layout]);
         writer.write_image(
             0,
-            error_checkboard
-                .unit
-                .get_copied::<AllocatedImage>()
-                .image_details
-                .image_view,
-            Some(default_nearest_sampler),
+            error_checkboard.image_details.image_view,
+            Some(default_nearest_sampler.clone()),
             ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             DescriptorType::COMBINED_IMAGE_SAMPLER,
         );
@@ -416,7 +913,6 @@ impl Renderer {
             vk_device.clone(),
             &[DynamicState::SCISSOR, DynamicState::VIEWPORT],
             PrimitiveTopology::TRIANGLE_LIST,
-            ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
             &[
                 ShaderInformation::new(
                     "/Users/zapzap/Projects/piplup/shaders/3_pos_vertex.spv".to_owned(),
@@ -431,9 +927,10 @@ impl Renderer {
             ],
             Some(&single_image_descriptor.layout),
             &extent,
-            Some(PushConstant::<Matrix4<f32>>::default()),
-            [].to_vec(),
-            [].to_vec(),
+            Some(PushConstantLayout::of::<PushConstant<Matrix4<f32>>>(
+                ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            )),
+            VertexInput::Pulling,
             &[create_color_blending_attachment_state(
                 ColorComponentFlags::R
                     | ColorComponentFlags::G
@@ -448,56 +945,167 @@ impl Renderer {
                 None,
             )],
             create_rasterizer_state(PolygonMode::FILL, CullModeFlags::NONE, FrontFace::CLOCKWISE),
-            create_multisampling_state(false, SampleCountFlags::TYPE_1, 1.0, false, false),
+            create_multisampling_state(false, render_pass.samples(), 1.0, false, false),
             render_pass.clone(),
-            true,
+            true)?;
+
+        let mut bindless = BindlessDescriptors::new(
+            vk_device.clone(),
+            &memory_allocator,
+            &[graphics_queue.clone()],
+            &mut main_deletion_queue,
         )?;
+        let depth_prepass = if config.depth_prepass {
+            Some(DepthPrepass::new(
+                vk_device.clone(),
+                extent,
+                config.msaa_samples,
+                &depth_image,
+                scene_data_layout,
+                bindless.layout,
+            )?)
+        } else {
+            None
+        };
+        let ssao = if config.ssao {
+            Some(Ssao::new(
+                vk_device.clone(),
+                &memory_allocator,
+                &[graphics_queue.clone()],
+                &command_pool,
+                extent,
+                &depth_image,
+                &mut bindless,
+                &samplers,
+                &mut main_deletion_queue,
+            )?)
+        } else {
+            None
+        };
+        let deferred = if config.deferred {
+            Some(DeferredShading::new(
+                vk_device.clone(),
+                &memory_allocator,
+                extent,
+                &depth_image,
+                config.depth_prepass,
+                render_pass.clone(),
+                scene_data_layout,
+                bindless.layout,
+                &samplers,
+                &mut main_deletion_queue,
+            )?)
+        } else {
+            None
+        };
+        let default_constants = MaterialConstants::new(
+            Vector4::<f32>::new(1.0, 1.0, 1.0, 1.0),
+            Vector4::<f32>::new(1.0, 0.5, 0.0, 0.0),
+        );
 
-        let material_metallic_roughness_pipelines = MaterialMetallicRoughness::build_pipelines(
+        let error_material = MaterialMetallicRoughness::build_pipelines_with_shaders(
+            "error material",
             vk_device.clone(),
             &extent,
             render_pass.clone(),
-        )
-        .unwrap();
-
-        let material_constants = memory_allocator.create_buffer_with_mapped_memory(
-            &[MaterialConstants::new(
-                Vector4::<f32>::new(1.0, 1.0, 1.0, 1.0),
-                Vector4::<f32>::new(1.0, 0.5, 0.0, 0.0),
-            )],
-            &[graphics_queue.clone()],
-            BufferUsageFlags::UNIFORM_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            MemoryUsage::Auto,
-            MemoryPropertyFlags::HOST_COHERENT | MemoryPropertyFlags::HOST_VISIBLE,
-            &command_pool.clone(),
+            scene_data_layout,
+            bindless.layout,
+            DEFAULT_VERTEX_SHADER,
+            ERROR_FRAGMENT_SHADER,
+        )?
+        .write_material(
+            MaterialPass::GLTF_PBR_OPAQUE,
+            MaterialResources {
+                color_image: error_checkboard.clone(),
+                color_sampler: default_nearest_sampler,
+                metal_rough_image: white_image.clone(),
+                metal_rough_sampler: default_linear_sampler.clone(),
+                normal_image: flat_normal_image.clone(),
+                normal_sampler: default_linear_sampler.clone(),
+                emissive_image: black_image.clone(),
+                emissive_sampler: default_linear_sampler.clone(),
+                occlusion_image: white_image.clone(),
+                occlusion_sampler: default_linear_sampler.clone(),
+                constants: default_constants,
+            },
+            &mut bindless,
         )?;
 
         let material_resources = MaterialResources {
-            color_image: white_image.unit.get_copied::<AllocatedImage>(),
+            color_image: white_image.clone(),
             color_sampler: default_linear_sampler.clone(),
-            metal_rough_image: white_image.unit.get_copied::<AllocatedImage>(),
-            metal_rough_sampler: default_linear_sampler,
-            data_buffer: material_constants.unit.get_copied::<VkBuffer>(),
-            buffer_offset: 0,
+            metal_rough_image: white_image.clone(),
+            metal_rough_sampler: default_linear_sampler.clone(),
+            normal_image: flat_normal_image.clone(),
+            normal_sampler: default_linear_sampler.clone(),
+            emissive_image: black_image.clone(),
+            emissive_sampler: default_linear_sampler.clone(),
+            occlusion_image: white_image.clone(),
+            occlusion_sampler: default_linear_sampler.clone(),
+            constants: default_constants,
         };
 
-        let material_instance = material_metallic_roughness_pipelines
-            .write_material(
-                vk_device.clone(),
+        let material_instance = MaterialMetallicRoughness::build_pipelines(
+            "default material",
+            vk_device.clone(),
+            &extent,
+            render_pass.clone(),
+            scene_data_layout,
+            bindless.layout,
+        )
+        .and_then(|pipelines| {
+            pipelines.write_material(
                 MaterialPass::GLTF_PBR_MAIN_COLOR,
                 material_resources,
-                &mut descriptor_allocator,
+                &mut bindless,
             )
-            .unwrap();
+        })
+        .unwrap_or_else(|err| {
+            init_notifications.push((NotificationLevel::Error, format!("Default material: {err}")));
+            error_material.clone()
+        });
         debug!("{:?}", material_instance);
+        let materials = MaterialLibrary::new(
+            vk_device.clone(),
+            memory_allocator.clone(),
+            command_pool.clone(),
+            render_pass.clone(),
+            extent,
+            scene_data_layout,
+            MaterialDefaults {
+                white_image: white_image.clone(),
+                black_image: black_image.clone(),
+                flat_normal_image: flat_normal_image.clone(),
+                sampler: default_linear_sampler,
+            },
+            Arc::new(GLTFMaterial {
+                data: error_material.clone(),
+            }),
+        );
+        let mesh_arena = BufferArena::new(
+            "mesh arena",
+            memory_allocator.clone(),
+            &[graphics_queue.clone()],
+            // vertices are pulled through their address, never bound as vertex buffers
+            BufferUsageFlags::INDEX_BUFFER | BufferUsageFlags::STORAGE_BUFFER,
+            MESH_ARENA_BLOCK_SIZE,
+        );
+        let basic_mesh_path = "/Users/zapzap/Projects/piplup/assets/basicmesh.glb";
         let gltf_buffers = assets::MeshAsset::<Vertex3D>::load_gltf_meshes(
-            "/Users/zapzap/Projects/piplup/assets/basicmesh.glb",
+            basic_mesh_path,
             scissors[0],
             viewports[0],
-            memory_allocator.clone(),
             &[graphics_queue.clone()],
             command_pool.clone(),
+            &mesh_arena,
         )?;
+        init_notifications.push((
+            NotificationLevel::Info,
+            format!("Loaded {} meshes from basicmesh.glb", gltf_buffers.len()),
+        ));
+        let mut asset_reloader = AssetReloader::new(scissors[0], viewports[0]);
+        asset_reloader.watch(basic_mesh_path, &gltf_buffers);
+        let asset_server = AssetServer::new(scissors[0], viewports[0]);
         let mut loaded_nodes: HashMap<String, Box<dyn Renderable>> = HashMap::new();
         for asset in &gltf_buffers {
             let node = Arc::new(Node::new(
@@ -515,24 +1123,97 @@ impl Renderer {
             loaded_nodes.insert(asset.lock().unwrap().name.clone(), Box::new(mesh_node));
         }
         debug!("{loaded_nodes:?}");
-        let egui_renderer = EguiRenderer::new(
+        let debug_draw = DebugDraw::new(
             vk_device.clone(),
-            window,
             memory_allocator.clone(),
-            graphics_queue.clone(),
+            &[graphics_queue.clone()],
+            &extent,
+            render_pass.clone(),
+            &mut main_deletion_queue,
+        )?;
+        let joint_buffer = JointBuffer::new(
+            memory_allocator.clone(),
+            &[graphics_queue.clone()],
+            &mut main_deletion_queue,
+        )?;
+        let analysis = GpuAnalysis::new(
+            vk_device.clone(),
+            memory_allocator.clone(),
+            &[graphics_queue.clone()],
+            extent,
+            &draw_image,
+            &mut main_deletion_queue,
+        )?;
+        let depth_picker = DepthPicker::new(
+            &memory_allocator,
+            &[graphics_queue.clone()],
+            config.msaa_samples != SampleCountFlags::TYPE_1,
+            &mut main_deletion_queue,
+        )?;
+        let post_process = PostProcess::new(
+            vk_device.clone(),
+            &memory_allocator,
             extent,
-            swapchain.details.clone().choose_swapchain_format().format,
-            swapchain_image_details.clone(),
+            &draw_image,
+            &samplers,
+            &mut main_deletion_queue,
+        )?;
+        let display_transform = DisplayTransformPass::new(
+            vk_device.clone(),
+            post_process.output(),
+            display_transform,
+            swapchain
+                .as_ref()
+                .map_or(ColorSpaceKHR::SRGB_NONLINEAR, |swapchain| swapchain.color_space),
+            &mut main_deletion_queue,
+        )?;
+        let sync_pool = SyncPool::new(vk_device.clone(), &mut main_deletion_queue);
+        let upload_context = UploadContext::new(
+            vk_device.clone(),
+            memory_allocator.clone(),
+            transfer_queue,
+            graphics_queue.clone(),
         )?;
+        let async_compute =
+            AsyncCompute::new(vk_device.clone(), compute_queue, graphics_queue.clone())?;
+        let gpu_timer = GpuTimer::new(vk_device.clone(), &mut main_deletion_queue)?;
+        let checkboard_image = error_checkboard.clone();
+        for default_image in [
+            white_image,
+            grey_image,
+            flat_normal_image,
+            black_image,
+            magenta_image,
+            error_checkboard,
+        ] {
+            main_deletion_queue.enqueue(FType::DROP(Box::new(default_image)));
+        }
+        let egui_renderer = window
+            .zip(window_target.as_ref())
+            .map(|(window, window_target)| {
+                EguiRenderer::new(
+                    vk_device.clone(),
+                    window,
+                    memory_allocator.clone(),
+                    graphics_queue.clone(),
+                    extent,
+                    surface_format,
+                    window_target.image_details.clone(),
+                    config.ui_load_op,
+                    config.clear_color,
+                    &samplers,
+                )
+            })
+            .transpose()?;
 
-        Ok(Self {
+        let mut renderer = Self {
             instance: vk_instance,
-            debug_instance,
             debugger,
             device: vk_device,
             graphics_queue,
             presentation_queue,
-            swapchain,
+            window_target,
+            secondary_windows: vec![],
             main_deletion_queue,
             //    compute_pipelines,
             //   compute_descriptor_set_details,
@@ -542,59 +1223,446 @@ impl Renderer {
             draw_image,
             depth_image,
             descriptor_allocator,
+            samplers,
             descriptor_layout_builder,
             descriptor_writer: writer,
             single_image_descriptor,
-            swapchain_image_details,
             framebuffers,
             memory_allocator,
             gltf_buffers,
+            mesh_arena,
+            asset_reloader,
+            asset_server,
+            assets: AssetRegistry::default(),
             scene_data,
             frame_data,
             frame_idx: 0,
+            frame_number: 0,
             render_area,
             command_pool,
-            loaded_nodes,
+            scenes: vec![Scene::new("main", loaded_nodes)],
+            active_scene: SceneId(0),
+            draw_stats: DrawStats::default(),
             draw_ctx: DrawContext {
                 opaque_surfaces: vec![],
+                error_material,
+                camera_position: Vector3::zeros(),
+                lod_settings: LodSettings::default(),
+                joint_matrices: vec![],
+                animation_time: 0.0,
+                animating: false,
             },
+            reported_errors: HashSet::new(),
+            debug_draw,
+            joint_buffer,
+            analysis,
+            depth_prepass,
+            sparse_feedback: None,
+            #[cfg(feature = "sparse-textures")]
+            sparse_textures: vec![],
+            ssao,
+            deferred,
+            depth_picker,
+            gpu_timer,
+            auto_quality: None,
+            lowered_render_scales: vec![],
+            post_process,
+            display_transform,
+            sync_pool,
+            upload_context,
+            async_compute,
+            skybox: None,
+            skybox_deletion_queue: None,
+            materials,
+            bindless,
+            grid_snap: GridSnap::default(),
+            frozen_view_proj: None,
+            measurements: HashMap::new(),
+            next_measurement: 0,
+            views: vec![],
+            next_view: 0,
+            view_targets: vec![],
+            skip_idle_frames: false,
+            command_logging: false,
+            memory_overlay: false,
+            scene_editor: false,
+            selection: None,
+            gizmo: Gizmo::default(),
+            cursor_position: None,
+            last_command_log: None,
+            raw_frame_callbacks: vec![],
+            last_view_projs: vec![],
+            last_frame: Instant::now(),
+            interpolation: None,
+            previous_step: None,
+            invalidated: true,
+            config,
             viewports,
             scissors,
             extent,
-            checkboard_image: error_checkboard.unit.get_copied::<AllocatedImage>(),
+            render_scale,
+            checkboard_image,
             egui_renderer,
-        })
+        };
+        renderer.set_auto_quality(renderer.config.auto_quality);
+        for (level, message) in init_notifications {
+            match level {
+                NotificationLevel::Error => renderer.report_error(message),
+                level => renderer.notify(level, message),
+            }
+        }
+        Ok(renderer)
+    }
+
+    /// When enabled, `display` returns without rendering as long as the camera did not move,
+    /// no debug shapes were queued and the UI does not need a repaint.
+    pub fn set_skip_idle_frames(&mut self, skip_idle_frames: bool) {
+        self.skip_idle_frames = skip_idle_frames;
+    }
+
+    /// Records the passes, pipeline binds, draws, dispatches, barriers and copies of every frame
+    /// drawn from now on into a `CommandLog`, the log of the last one is kept for
+    /// `last_command_log`. Meant for tests and diffing frames without a GPU capture.
+    pub fn set_command_logging(&mut self, command_logging: bool) {
+        self.command_logging = command_logging;
+        if !command_logging {
+            self.last_command_log = None;
+        }
+    }
+
+    /// `None` until a frame was drawn with command logging on.
+    pub fn last_command_log(&self) -> Option<&CommandLog> {
+        self.last_command_log.as_ref()
+    }
+
+    /// Copies the HDR scene colors of the last drawn frame, before post processing, back to the
+    /// host. Waits for the GPU to go idle, so meant for screenshots and tests rather than every
+    /// frame.
+    pub fn read_draw_image(&self) -> Result<ReadbackImage> {
+        if self.frame_number == 0 {
+            return Err(anyhow!("No frame has been drawn yet"));
+        }
+        unsafe { self.device.device_wait_idle()? };
+        // post processing is the last pass reading the draw image
+        self.memory_allocator.read_image(
+            &self.draw_image,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &self.command_pool,
+        )
+    }
+
+    /// Current budgets and allocation counts of every memory heap, sample it across frames to
+    /// spot resources that are never freed.
+    pub fn memory_statistics(&self) -> Result<MemoryStatistics> {
+        Ok(self.memory_allocator.statistics()?)
+    }
+
+    /// Shows `memory_statistics` in the debug window, refreshed every frame while enabled.
+    pub fn set_memory_overlay(&mut self, memory_overlay: bool) {
+        self.memory_overlay = memory_overlay;
+        if !memory_overlay {
+            if let Some(egui_renderer) = &mut self.egui_renderer {
+                egui_renderer.set_memory_statistics(None);
+            }
+        }
+    }
+
+    /// Shows a side panel listing the prefab instances of the active scene and their nodes,
+    /// with an inspector editing the transform and material override of the selected one and
+    /// the scene lights. Edits are applied after the frame they were made in.
+    pub fn set_scene_editor(&mut self, scene_editor: bool) {
+        self.scene_editor = scene_editor;
+        if !scene_editor {
+            if let Some(egui_renderer) = &mut self.egui_renderer {
+                egui_renderer.set_scene_outline(None);
+            }
+        }
+    }
+
+    /// What the scene editor shows of the active scene, instance nodes are sorted by name.
+    fn scene_outline(&self) -> SceneOutline {
+        let scene = self.active_scene();
+        let mut materials: Vec<String> = self.materials.names().map(str::to_owned).collect();
+        materials.sort();
+        let material_name = |material: &Arc<GLTFMaterial>| {
+            materials
+                .iter()
+                .find(|name| {
+                    self.materials
+                        .get(name)
+                        .is_some_and(|library| Arc::ptr_eq(&library, material))
+                })
+                .cloned()
+                .unwrap_or_else(|| CUSTOM_MATERIAL.to_owned())
+        };
+        let instances = scene
+            .instances
+            .iter()
+            .map(|instance| {
+                let mut nodes: Vec<NodeOutline> = instance
+                    .nodes
+                    .iter()
+                    .map(|(name, node)| NodeOutline {
+                        name: name.clone(),
+                        local_transform: node.node.local_transform(),
+                        material_override: node.material_override.as_ref().map(material_name),
+                    })
+                    .collect();
+                nodes.sort_by(|a, b| a.name.cmp(&b.name));
+                InstanceOutline {
+                    transform: instance.transform,
+                    nodes,
+                }
+            })
+            .collect();
+        let mut nodes: Vec<String> = scene.nodes.keys().cloned().collect();
+        nodes.sort();
+        SceneOutline {
+            name: scene.name.clone(),
+            nodes,
+            instances,
+            lights: scene.lights.clone(),
+            materials,
+        }
+    }
+
+    /// Applies the edits of the scene editor to the active scene.
+    fn apply_scene_edits(&mut self, edits: Vec<SceneEdit>) {
+        if edits.is_empty() {
+            return;
+        }
+        for edit in edits {
+            let material = match &edit {
+                SceneEdit::NodeMaterial(_, _, Some(name)) => match self.materials.get(name) {
+                    Some(material) => Some(material),
+                    None => {
+                        self.report_error(format!("Material {name} is not in the library"));
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+            let scene = self.active_scene_mut();
+            match edit {
+                SceneEdit::InstanceTransform(instance_id, transform) => {
+                    if let Some(instance) = scene.instance_mut(instance_id) {
+                        instance.transform = transform;
+                    }
+                }
+                SceneEdit::NodeTransform(instance_id, name, transform) => {
+                    if let Some(node) = scene
+                        .instance_mut(instance_id)
+                        .and_then(|instance| instance.nodes.get(&name))
+                    {
+                        node.node.set_local_transform(transform);
+                        // instance nodes have no parent, the instance transform is applied when
+                        // drawn
+                        node.node.refresh_transform(Matrix4::identity());
+                    }
+                }
+                SceneEdit::NodeMaterial(instance_id, name, _) => {
+                    if let Some(node) = scene
+                        .instance_mut(instance_id)
+                        .and_then(|instance| instance.nodes.get_mut(&name))
+                    {
+                        node.material_override = material;
+                    }
+                }
+                SceneEdit::Lights(lights) => scene.lights = lights,
+            }
+        }
+        self.invalidate();
+    }
+
+    /// Runs `callback` once while the next frame is recorded, right after the scene render
+    /// pass, to record raw Vulkan commands into the frame. See `RawFrameContext` for the state
+    /// the images are in and have to be left in. Queue it again every frame to keep drawing.
+    pub fn with_raw_frame(&mut self, callback: impl FnOnce(&RawFrameContext) + 'static) {
+        self.raw_frame_callbacks.push(Box::new(callback));
+        self.invalidate();
+    }
+
+    /// Marks the current frame as outdated, used by reactive rendering to decide whether a
+    /// redraw is needed.
+    pub fn invalidate(&mut self) {
+        self.invalidated = true;
+    }
+
+    /// Whether something changed since the last presented frame (explicit invalidation, queued
+    /// debug shapes, running tweens or animations or a pending UI repaint).
+    pub fn needs_redraw(&self) -> bool {
+        self.invalidated
+            || !self.debug_draw.is_empty()
+            || !self.active_scene().tweens.is_empty()
+            || self.active_scene().is_animating()
+            || self.draw_ctx.animating
+            || self.depth_picker.is_waiting()
+            || !self.raw_frame_callbacks.is_empty()
+            || self.asset_server.is_loading()
+            || self.ui_needs_repaint()
+    }
+
+    fn ui_needs_repaint(&self) -> bool {
+        self.egui_renderer
+            .as_ref()
+            .is_some_and(|egui_renderer| egui_renderer.needs_repaint())
+    }
+
+    /// Next point in time a redraw is due without further input, e.g. for UI animations.
+    pub fn next_redraw_deadline(&self) -> Option<Instant> {
+        self.egui_renderer
+            .as_ref()
+            .and_then(|egui_renderer| egui_renderer.repaint_deadline())
+    }
+
+    /// Draws and presents a frame, returns what it cost or `None` if no frame was drawn, e.g.
+    /// while minimized or when idle frames are skipped.
+    pub fn display(&mut self, window: &Window) -> Result<Option<FrameStats>> {
+        let Some(window_target) = &mut self.window_target else {
+            return Err(anyhow!("Headless renderers draw with render_offscreen"));
+        };
+        if is_zero_sized(window) {
+            window_target.minimized = true;
+            return Ok(None);
+        }
+        if window_target.minimized || window_target.outdated {
+            self.recreate_swapchain(window, self.config.present_mode)?;
+        }
+        self.render_frame(Some(window))
+    }
+
+    /// Draws a frame of a renderer created with `init_headless` into the draw image, read it
+    /// back with `read_draw_image`.
+    pub fn render_offscreen(&mut self) -> Result<Option<FrameStats>> {
+        if self.window_target.is_some() {
+            return Err(anyhow!("Renderers with a window draw with display"));
+        }
+        self.render_frame(None)
     }
 
-    pub fn display(&mut self, window: &Window) -> Result<()> {
-        self.draw(self.frame_idx, window)?;
+    fn render_frame(&mut self, window: Option<&Window>) -> Result<Option<FrameStats>> {
+        let _span = info_span!("frame", number = self.frame_number).entered();
+        self.invalidated = false;
+        let allocations_before = self.memory_allocator.allocation_counts();
+        let now = Instant::now();
+        let delta = now - self.last_frame;
+        self.last_frame = now;
+        self.reload_changed_material_files();
+        self.reload_changed_assets();
+        self.upload_loaded_assets();
+        let tweening = !self.active_scene().tweens.is_empty();
+        // like tweens, the frame after the last update still has to show its pose
+        let playing = self.active_scene().is_animating();
+        let alpha = match self.interpolation {
+            Some(alpha) => alpha,
+            None => {
+                self.update(delta);
+                1.0
+            }
+        };
+        self.update_scene(alpha);
+        let view_projs: Vec<Matrix4<f32>> = self
+            .view_targets
+            .iter()
+            .map(|view| view.scene_data.view_proj)
+            .collect();
+        let scene_changed = view_projs != self.last_view_projs
+            || self.draw_ctx.animating
+            || !self.debug_draw.is_empty()
+            || self.depth_picker.is_waiting()
+            || !self.raw_frame_callbacks.is_empty()
+            || tweening
+            || playing
+            || self.gizmo.is_dragging();
+        if self.skip_idle_frames && !scene_changed && !self.ui_needs_repaint() {
+            return Ok(None);
+        }
+        self.last_view_projs = view_projs;
+        self.draw_measurements();
+        if let Some(transform) = self.selection_transform() {
+            let camera_position = self.active_scene().camera.position();
+            self.gizmo.draw(&transform, camera_position, &mut self.debug_draw);
+        }
+        if let Some(frozen_view_proj) = self.frozen_view_proj {
+            self.debug_draw.frustum(frozen_view_proj, Vector4::new(1.0, 1.0, 0.0, 1.0));
+        }
+        let Some(mut frame_stats) = self.draw(self.frame_idx, window)? else {
+            return Ok(None);
+        };
+        let allocations = self.memory_allocator.allocation_counts();
+        frame_stats.frame_number = self.frame_number;
+        frame_stats.buffer_allocations = allocations.buffers - allocations_before.buffers;
+        frame_stats.texture_allocations = allocations.images - allocations_before.images;
+        frame_stats.cpu_ms = now.elapsed().as_secs_f32() * 1000.0;
         self.frame_idx = self.frame_idx.add(1_usize) % MAX_FRAMES;
-        Ok(())
+        self.frame_number += 1;
+        #[cfg(feature = "tracy")]
+        if let Some(client) = tracy_client::Client::running() {
+            client.frame_mark();
+        }
+        Ok(Some(frame_stats))
     }
 
-    fn draw(&mut self, frame_idx: usize, window: &Window) -> Result<()> {
+    /// Acquires, draws the UI on and presents a swapchain image when there is a `window`.
+    /// Returns `None` when the swapchain was out of date and nothing got drawn.
+    fn draw(&mut self, frame_idx: usize, window: Option<&Window>) -> Result<Option<FrameStats>> {
         unsafe {
-            self.update_scene();
-            self.device.wait_for_fences(
-                &self.frame_data[frame_idx].render_fence,
-                true,
-                u64::MAX,
-            )?;
+            info_span!("wait for frame").in_scope(|| {
+                self.device
+                    .wait_for_fences(&self.frame_data[frame_idx].render_fence, true, u64::MAX)
+            })?;
+            self.sync_pool.recycle();
+            // the fence guarded the frame MAX_FRAMES back, every frame up to it has completed
+            self.main_deletion_queue
+                .collect((self.frame_number + 1).saturating_sub(MAX_FRAMES as u64));
+            self.upload_context.collect()?;
+            self.async_compute.collect()?;
+            self.analysis.read_back(frame_idx);
+            #[cfg(feature = "sparse-textures")]
+            if let Err(err) = self.update_sparse_residency(frame_idx) {
+                self.report_error(format!("Sparse texture pages: {err:#}"));
+            }
+            self.depth_picker.read_back(frame_idx);
+            if let Some(gpu_ms) = self.gpu_timer.read_back(&self.device, frame_idx) {
+                self.update_auto_quality(gpu_ms);
+            }
+            let scene_outline = self.scene_editor.then(|| self.scene_outline());
+            if let Some(egui_renderer) = &mut self.egui_renderer {
+                egui_renderer.set_analysis_results(self.analysis.results().cloned());
+                if scene_outline.is_some() {
+                    egui_renderer.set_scene_outline(scene_outline);
+                }
+                if self.memory_overlay {
+                    egui_renderer.set_memory_statistics(Some(self.memory_allocator.statistics()?));
+                }
+            }
+
+            let acquire_span = info_span!("acquire").entered();
+            let render_fence = self.frame_data[frame_idx].render_fence[0];
+            let presentation = match self.window_target.as_mut().zip(window) {
+                Some((window_target, window)) => {
+                    match window_target.acquire(frame_idx, render_fence)? {
+                        Some(image_index) => Some((window, image_index)),
+                        // Nothing gets submitted, so the render fence is left signaled.
+                        None => {
+                            return self
+                                .recreate_swapchain(window, self.config.present_mode)
+                                .map(|_| None);
+                        }
+                    }
+                }
+                None => None,
+            };
+            // the secondary windows only show the frames the main window gets
+            let mut acquired: Vec<(WindowSlot, ImageIndex)> = vec![];
+            if let Some((_, image_index)) = presentation {
+                acquired.push((WindowSlot::Main, image_index));
+                acquired.extend(self.acquire_secondary_windows(frame_idx, render_fence));
+            }
+            acquire_span.exit();
             self.device
                 .reset_fences(&self.frame_data[frame_idx].render_fence)?;
 
-            let image_index = ImageIndex::new(
-                self.swapchain
-                    .s_device
-                    .acquire_next_image(
-                        **self.swapchain,
-                        u64::MAX,
-                        self.frame_data[frame_idx].swapchain_semaphore[0],
-                        Fence::null(),
-                    )
-                    .unwrap(),
-            );
-
             self.device.reset_command_buffer(
                 self.frame_data[frame_idx].command_buffer,
                 CommandBufferResetFlags::empty(),
@@ -603,59 +1671,147 @@ impl Renderer {
                 .frame_resources
                 .per_frame_deletion_queue
                 .flush();
-            let stage_masks = vec![
-                PipelineStageFlags::VERTEX_SHADER,
-                PipelineStageFlags::FRAGMENT_SHADER,
-            ];
-
-            {
-                Self::record_command_buffer(
+            let upload_wait = info_span!("uploads").in_scope(|| self.upload_context.submit())?;
+            let compute_wait = self.async_compute.take_wait();
+            let clear_color = self.clear_color();
+            if self.command_logging {
+                self.device.begin_command_log(&[
                     self.frame_data[frame_idx].command_buffer,
-                    &self.command_pool,
-                    &image_index,
-                    window,
-                    &mut self.frame_data[frame_idx].frame_resources,
-                    &self.device.clone(),
-                    &self.swapchain_image_details,
-                    &self.draw_image,
-                    &self.graphics_queue.clone(),
-                    &self.render_area,
-                    &self.viewports,
-                    &self.single_image_descriptor,
-                    &self.gltf_pipeline,
-                    &self.gltf_buffers,
-                    &self.memory_allocator,
-                    &self.extent,
-                    &self.render_pass,
-                    &self.depth_image,
-                    &self.framebuffers,
-                    self.scene_data.clone(),
-                    &self.draw_ctx,
-                )
-                .unwrap();
+                    self.frame_data[frame_idx].egui_command_buffer,
+                ]);
             }
-            self.egui_renderer.draw(
-                self.frame_data[frame_idx].egui_command_buffer,
-                &image_index,
-                window,
-                self.viewports.clone(),
-                self.render_area,
+
+            let swapchain_images: Vec<(ImageDetails, Extent2D, ImageLayout)> = acquired
+                .iter()
+                .map(|(slot, image_index)| {
+                    let window_target = self.slot_target(*slot);
+                    (
+                        window_target.image_details[**image_index as usize],
+                        window_target.swapchain.extent,
+                        // the UI is drawn on the main window before it is presented
+                        match slot {
+                            WindowSlot::Main => ImageLayout::GENERAL,
+                            WindowSlot::Secondary(_) => ImageLayout::PRESENT_SRC_KHR,
+                        },
+                    )
+                })
+                .collect();
+            let record_span = info_span!("record").entered();
+            let mut frame_stats = Self::record_command_buffer(
+                self.frame_data[frame_idx].command_buffer,
+                FrameContext {
+                    frame_resources: &mut self.frame_data[frame_idx].frame_resources,
+                    device: &self.device,
+                    swapchain_images: &swapchain_images,
+                    presentation: self.config.presentation,
+                    draw_image: &self.draw_image,
+                    graphics_queue: &self.graphics_queue,
+                    render_area: &self.render_area,
+                    views: &self.view_targets,
+                    bindless_set: self.bindless.descriptor_set(),
+                    extent: &self.extent,
+                    render_pass: &self.render_pass,
+                    depth_image: &self.depth_image,
+                    framebuffers: &self.framebuffers,
+                    draw_ctx: &self.draw_ctx,
+                    debug_draw: &mut self.debug_draw,
+                    joint_buffer: &mut self.joint_buffer,
+                    skybox: self.skybox.as_ref(),
+                    analysis: &self.analysis,
+                    depth_prepass: self.depth_prepass.as_ref(),
+                    sparse_feedback: self.sparse_feedback.as_ref(),
+                    ssao: self.ssao.as_ref(),
+                    deferred: self.deferred.as_ref(),
+                    depth_picker: &mut self.depth_picker,
+                    gpu_timer: &mut self.gpu_timer,
+                    raw_frame_callbacks: std::mem::take(&mut self.raw_frame_callbacks),
+                    post_process: &self.post_process,
+                    display_transform: &self.display_transform,
+                    clear_color,
+                    upload_context: &mut self.upload_context,
+                    async_compute: &mut self.async_compute,
+                    frame_idx,
+                },
             )?;
+            record_span.exit();
+            self.draw_stats = frame_stats.scene;
+            frame_stats.gpu_ms = self.gpu_timer.last_frame_ms();
+            frame_stats.pass_timings = self.gpu_timer.last_pass_timings().to_vec();
+            let other_frame_fences: Vec<Fence> = self
+                .frame_data
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx != frame_idx)
+                .flat_map(|(_, frame_data)| frame_data.render_fence.iter().copied())
+                .collect();
+            let mut scene_edits = vec![];
+            if let (Some((window, image_index)), Some(egui_renderer), Some(window_target)) =
+                (&presentation, &mut self.egui_renderer, &self.window_target)
+            {
+                let _span = info_span!("ui").entered();
+                let swapchain_extent = window_target.swapchain.extent;
+                egui_renderer.draw(
+                    self.frame_data[frame_idx].egui_command_buffer,
+                    image_index,
+                    window,
+                    vec![Viewport::default()
+                        .width(swapchain_extent.width as f32)
+                        .height(swapchain_extent.height as f32)
+                        .min_depth(0.0)
+                        .max_depth(1.0)],
+                    Rect2D::default().extent(swapchain_extent),
+                    &other_frame_fences,
+                )?;
+                scene_edits = egui_renderer.take_scene_edits();
+            }
+            self.apply_scene_edits(scene_edits);
+            if self.command_logging {
+                self.last_command_log = self.device.end_command_log();
+            }
+            let command_buffers = [
+                self.frame_data[frame_idx].command_buffer,
+                self.frame_data[frame_idx].egui_command_buffer,
+            ];
+            // the egui pass only exists with a swapchain image to draw on
+            let command_buffers = match presentation {
+                Some(_) => &command_buffers[..],
+                None => &command_buffers[..1],
+            };
+            let submit_span = info_span!("submit").entered();
+            let presented: Vec<(Semaphore, Semaphore)> = acquired
+                .iter()
+                .map(|(slot, image_index)| {
+                    let window_target = self.slot_target(*slot);
+                    (
+                        window_target.acquire_semaphore(frame_idx),
+                        window_target.sync.render_semaphore(**image_index),
+                    )
+                })
+                .collect();
             self.submit_queue(
                 **self.graphics_queue,
                 frame_idx,
-                &[
-                    self.frame_data[frame_idx].command_buffer,
-                    self.frame_data[frame_idx].egui_command_buffer,
-                ],
-                &stage_masks,
-            );
-            let image_indices = vec![image_index.index];
-            self.present_queue(
-                **self.graphics_queue,
-                &self.frame_data[frame_idx].render_semaphore,
-                &image_indices,
+                command_buffers,
+                upload_wait,
+                compute_wait,
+                &presented,
             );
+            submit_span.exit();
+            let mut outdated = vec![];
+            if !acquired.is_empty() {
+                let _span = info_span!("present").entered();
+                let render_semaphores: Vec<Semaphore> =
+                    presented.iter().map(|(_, render_semaphore)| *render_semaphore).collect();
+                let presented_outdated = self.present_queue(&acquired, &render_semaphores)?;
+                outdated = acquired
+                    .iter()
+                    .zip(presented_outdated)
+                    .filter(|((_, image_index), outdated)| {
+                        *outdated || image_index.recreate_swapchain
+                    })
+                    .map(|((slot, _), _)| *slot)
+                    .collect();
+            }
             let frame_data = &mut self.frame_data[frame_idx];
 
             frame_data.frame_resources.descriptor_layout_builder.clear();
@@ -665,44 +1821,131 @@ impl Renderer {
                 .borrow_mut()
                 .reset_descriptors(self.device.clone());
             frame_data.frame_resources.descriptor_writer.clear();
+            for slot in outdated {
+                match (slot, presentation.as_ref()) {
+                    (WindowSlot::Main, Some((window, _))) => {
+                        self.recreate_swapchain(window, self.config.present_mode)?
+                    }
+                    (WindowSlot::Secondary(idx), _) => self.recreate_secondary_window(idx)?,
+                    (WindowSlot::Main, None) => {}
+                }
+            }
+            Ok(Some(frame_stats))
         }
-        Ok(())
     }
 
-    fn record_command_buffer(
-        cmd: CommandBuffer,
-        command_pool: &VkCommandPool,
-        image_index: &ImageIndex,
-        window: &Window,
-        frame_resources: &mut FrameResources,
-        device: &Arc<VkDevice>,
-        swapchain_image_details: &[ImageDetails],
-        draw_image: &AllocatedImage,
-        graphics_queue: &Arc<VkQueue>,
-        render_area: &Rect2D,
-        viewports: &[Viewport],
-        descriptor_set: &DescriptorSetDetails,
-        gltf_pipeline: &VkPipeline,
-        gltf_buffers: &[Arc<Mutex<MeshAsset<Vertex3D>>>],
-        memory_allocator: &Arc<MemoryAllocator>,
-        extent: &Extent2D,
-        render_pass: &Arc<VkRenderPass>,
-        depth_image: &AllocatedImage,
-        framebuffers: &HashMap<IDENTIFIER, Vec<VkFrameBuffer>>,
-        scene_data: SceneData,
-        draw_ctx: &DrawContext,
-    ) -> Result<()> {
+    /// The final image is copied to each of the swapchain images of `frame`. Headless frames
+    /// end with it in the post processing output.
+    fn record_command_buffer(cmd: CommandBuffer, frame: FrameContext) -> Result<FrameStats> {
+        let FrameContext {
+            frame_resources,
+            device,
+            swapchain_images,
+            presentation,
+            draw_image,
+            graphics_queue,
+            render_area,
+            views,
+            bindless_set,
+            extent,
+            render_pass,
+            depth_image,
+            framebuffers,
+            draw_ctx,
+            debug_draw,
+            joint_buffer,
+            skybox,
+            analysis,
+            depth_prepass,
+            sparse_feedback,
+            ssao,
+            deferred,
+            depth_picker,
+            gpu_timer,
+            raw_frame_callbacks,
+            post_process,
+            display_transform,
+            clear_color,
+            upload_context,
+            async_compute,
+            frame_idx,
+        } = frame;
         unsafe {
-            let current_image = swapchain_image_details[**image_index as usize];
             device.begin_command_buffer(
                 cmd,
                 &CommandBufferBeginInfo::default().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
             )?;
+            frame_resources.secondary_commands.reset()?;
+            upload_context.record_acquires(cmd);
+            async_compute.record_acquires(cmd);
+            gpu_timer.begin(cmd, device, frame_idx);
+
+            // the first view is the one picking, analysis and raw frame callbacks see
+            let scene_data = &views[0].scene_data;
+            let scene_data_bindings: Vec<SceneDataBinding> = views
+                .iter()
+                .enumerate()
+                .map(|(view_idx, view)| frame_resources.write_scene_data(view_idx, &view.scene_data))
+                .collect();
+            let joint_address = joint_buffer.upload(frame_idx, &draw_ctx.joint_matrices)?;
+            let labels = &device.debug_utils;
+            let mut frame_stats = FrameStats::default();
+            if let Some(depth_prepass) = depth_prepass {
+                frame_stats.draws += labels.label(cmd, "depth prepass", PASS_LABEL_COLOR, || {
+                    depth_prepass.record(
+                        cmd,
+                        device,
+                        draw_ctx,
+                        views,
+                        &scene_data_bindings,
+                        bindless_set,
+                        render_area,
+                    )
+                });
+                gpu_timer.mark(cmd, device, frame_idx, "depth prepass");
+            }
+            if let Some(sparse_feedback) = sparse_feedback {
+                labels.label(cmd, "sparse feedback", PASS_LABEL_COLOR, || {
+                    sparse_feedback.record(
+                        cmd,
+                        device,
+                        frame_idx,
+                        draw_ctx,
+                        &views[0],
+                        &scene_data_bindings[0],
+                        bindless_set,
+                        render_area,
+                    )
+                });
+                gpu_timer.mark(cmd, device, frame_idx, "sparse feedback");
+            }
+            if let Some(ssao) = ssao.filter(|ssao| !ssao.suspended && views.len() == 1) {
+                labels.label(cmd, "ssao", PASS_LABEL_COLOR, || {
+                    let proj = viewport_projection(&views[0].scissor, extent) * scene_data.proj;
+                    ssao.record(cmd, device, frame_idx, proj, depth_image)
+                });
+                gpu_timer.mark(cmd, device, frame_idx, "ssao");
+            }
+            if let Some(deferred) = deferred {
+                frame_stats.draws += labels.label(cmd, "g-buffer", PASS_LABEL_COLOR, || {
+                    deferred.record_gbuffer(
+                        cmd,
+                        device,
+                        draw_ctx,
+                        views,
+                        &scene_data_bindings,
+                        bindless_set,
+                        render_area,
+                        depth_prepass.is_some(),
+                    )
+                });
+                gpu_timer.mark(cmd, device, frame_idx, "g-buffer");
+            }
 
             let clear_value = vec![
                 ClearValue {
                     color: ash::vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 0.0],
+                        float32: clear_color,
                     },
                 },
                 ClearValue {
@@ -712,168 +1955,342 @@ impl Renderer {
                     },
                 },
             ];
+            labels.begin_label(cmd, "scene", PASS_LABEL_COLOR);
+            let framebuffer = *framebuffers.get(&IDENTIFIER::DRAW).unwrap()[0];
+            let secondary_commands = &frame_resources.secondary_commands;
+            let chunks = chunk_ranges(
+                draw_ctx.opaque_surfaces.len(),
+                secondary_commands.max_chunks(),
+                MIN_DRAWS_PER_CHUNK,
+            );
+            // a running command log needs the commands in recording order, it stays inline, and
+            // the chunks draw a single view
+            let parallel = chunks.len() > 1 && !device.command_log_running() && views.len() == 1;
             device.cmd_begin_render_pass(
                 cmd,
                 &RenderPassBeginInfo::default()
                     .render_pass(***render_pass) // Dereference VkRenderPass
-                    .framebuffer(*framebuffers.get(&IDENTIFIER::DRAW).unwrap()[0])
+                    .framebuffer(framebuffer)
                     .render_area(*render_area)
                     .clear_values(&clear_value),
-                SubpassContents::INLINE,
+                if parallel {
+                    SubpassContents::SECONDARY_COMMAND_BUFFERS
+                } else {
+                    SubpassContents::INLINE
+                },
             );
-            Self::draw_geom::<Vertex3D>(
-                cmd,
-                window,
-                frame_resources,
-                gltf_buffers,
-                memory_allocator,
-                descriptor_set,
+            let (view_proj, primary_viewport) = (scene_data.view_proj, views[0].viewport);
+            let surface_pass = SurfacePass {
                 device,
-                scene_data,
-                extent,
-                viewports,
-                gltf_pipeline,
-                render_area,
-                draw_image,
-                draw_ctx,
-                graphics_queue,
-                command_pool,
-            )?;
+                bindless_set,
+                joint_address,
+                after_depth_prepass: depth_prepass.is_some(),
+                skip_deferred: deferred.is_some(),
+            };
+            let mut draw_stats = DrawStats::default();
+            if parallel {
+                let (view, scene_data_binding) = (&views[0], scene_data_bindings[0]);
+                let prologue = secondary_commands.prologue();
+                secondary_commands.begin(prologue, ***render_pass, framebuffer)?;
+                Self::record_scene_background(
+                    prologue,
+                    device,
+                    skybox,
+                    deferred,
+                    scene_data,
+                    scene_data_binding,
+                    bindless_set,
+                    &[view.viewport],
+                    &view.scissor,
+                );
+                device.end_command_buffer(prologue)?;
+                let chunks: Vec<_> = chunks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, range)| (secondary_commands.chunk(idx), range))
+                    .collect();
+                let chunk_stats = JobSystem::global().map(
+                    "record_draws",
+                    &chunks,
+                    |(chunk, range)| -> Result<DrawStats> {
+                        secondary_commands.begin(*chunk, ***render_pass, framebuffer)?;
+                        let stats = Self::draw_geom(
+                            *chunk,
+                            surface_pass,
+                            scene_data_binding,
+                            &[view.viewport],
+                            &view.scissor,
+                            &draw_ctx.opaque_surfaces[range.clone()],
+                        );
+                        device.end_command_buffer(*chunk)?;
+                        Ok(stats)
+                    },
+                );
+                for stats in chunk_stats {
+                    draw_stats += stats?;
+                }
+                let epilogue = secondary_commands.epilogue();
+                secondary_commands.begin(epilogue, ***render_pass, framebuffer)?;
+                debug_draw.record(epilogue, device, frame_idx, views);
+                device.end_command_buffer(epilogue)?;
+                let secondary_buffers: Vec<CommandBuffer> = std::iter::once(prologue)
+                    .chain(chunks.iter().map(|(chunk, _)| *chunk))
+                    .chain(std::iter::once(epilogue))
+                    .collect();
+                device.cmd_execute_commands(cmd, &secondary_buffers);
+            } else {
+                for (view, &scene_data_binding) in views.iter().zip(&scene_data_bindings) {
+                    Self::record_scene_background(
+                        cmd,
+                        device,
+                        skybox,
+                        deferred,
+                        &view.scene_data,
+                        scene_data_binding,
+                        bindless_set,
+                        &[view.viewport],
+                        &view.scissor,
+                    );
+                    draw_stats += Self::draw_geom(
+                        cmd,
+                        surface_pass,
+                        scene_data_binding,
+                        &[view.viewport],
+                        &view.scissor,
+                        &draw_ctx.opaque_surfaces,
+                    );
+                }
+                debug_draw.record(cmd, device, frame_idx, views);
+            }
             device.cmd_end_render_pass(cmd);
-            image_transition(
-                device.clone(),
-                cmd,
-                graphics_queue.queue_family_index,
-                current_image.image,
-                ImageLayout::UNDEFINED,
-                ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
-            let extent = Extent2D::default()
-                .width(draw_image.extent.width)
-                .height(draw_image.extent.height);
-            copy_image_to_image(
-                &device,
-                cmd,
-                draw_image.image_details.image,
-                current_image.image,
-                extent,
-                extent, // Pass extent directly
-            );
-            image_transition(
-                device.clone(),
-                cmd,
-                graphics_queue.queue_family_index,
-                current_image.image,
-                ImageLayout::TRANSFER_DST_OPTIMAL,
-                ImageLayout::GENERAL,
-            );
+            labels.end_label(cmd);
+            gpu_timer.mark(cmd, device, frame_idx, "scene");
+            frame_stats.scene = draw_stats;
+            frame_stats.draws += draw_stats;
+            if !raw_frame_callbacks.is_empty() {
+                let raw_frame = RawFrameContext {
+                    device,
+                    command_buffer: cmd,
+                    frame_idx,
+                    graphics_queue_family_index: graphics_queue.queue_family_index,
+                    draw_image,
+                    depth_image,
+                    view: scene_data.view,
+                    proj: scene_data.proj,
+                };
+                labels.label(cmd, "raw frame callbacks", PASS_LABEL_COLOR, || {
+                    for callback in raw_frame_callbacks {
+                        callback(&raw_frame);
+                    }
+                });
+                gpu_timer.mark(cmd, device, frame_idx, "raw frame callbacks");
+            }
+            labels.label(cmd, "depth picking", PASS_LABEL_COLOR, || {
+                depth_picker.record(cmd, device, frame_idx, depth_image, view_proj, primary_viewport)
+            });
+            gpu_timer.mark(cmd, device, frame_idx, "depth picking");
+            labels.label(cmd, "analysis", PASS_LABEL_COLOR, || {
+                analysis.record(
+                    cmd,
+                    device,
+                    frame_idx,
+                    draw_ctx,
+                    view_proj,
+                    &[primary_viewport],
+                    &views[0].scissor,
+                    draw_image,
+                    graphics_queue.queue_family_index,
+                )
+            });
+            gpu_timer.mark(cmd, device, frame_idx, "analysis");
+            labels.label(cmd, "post-process", PASS_LABEL_COLOR, || {
+                post_process.record(cmd, device, graphics_queue.queue_family_index)
+            });
+            gpu_timer.mark(cmd, device, frame_idx, "post-process");
+            let output_image = post_process.output();
+            labels.label(cmd, "display transform", PASS_LABEL_COLOR, || {
+                display_transform.record(cmd, device, output_image, graphics_queue.queue_family_index)
+            });
+            gpu_timer.mark(cmd, device, frame_idx, "display transform");
+            if !swapchain_images.is_empty() {
+                labels.begin_label(cmd, "copy to swapchain", PASS_LABEL_COLOR);
+                for &(current_image, swapchain_extent, final_layout) in swapchain_images {
+                    image_transition(
+                        device.clone(),
+                        cmd,
+                        graphics_queue.queue_family_index,
+                        current_image.image,
+                        ImageLayout::UNDEFINED,
+                        ImageLayout::TRANSFER_DST_OPTIMAL,
+                    );
+                    // Scaled to the swapchain from the drawn part of the output, which differs
+                    // with a render scale or after the window was resized.
+                    let (src_region, dst_region) =
+                        presentation.blit_regions(render_area.extent, swapchain_extent);
+                    if dst_region.extent != swapchain_extent {
+                        device.cmd_clear_color_image(
+                            cmd,
+                            current_image.image,
+                            ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &ash::vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
+                            &[image_subresource_range(ImageAspectFlags::COLOR)],
+                        );
+                        // the blit overwrites the middle of the cleared image
+                        image_transition(
+                            device.clone(),
+                            cmd,
+                            graphics_queue.queue_family_index,
+                            current_image.image,
+                            ImageLayout::TRANSFER_DST_OPTIMAL,
+                            ImageLayout::TRANSFER_DST_OPTIMAL,
+                        );
+                    }
+                    copy_image_to_image(
+                        &device,
+                        cmd,
+                        output_image.image_details.image,
+                        current_image.image,
+                        src_region,
+                        dst_region,
+                    );
+                    image_transition(
+                        device.clone(),
+                        cmd,
+                        graphics_queue.queue_family_index,
+                        current_image.image,
+                        ImageLayout::TRANSFER_DST_OPTIMAL,
+                        final_layout,
+                    );
+                }
+                labels.end_label(cmd);
+                gpu_timer.mark(cmd, device, frame_idx, "copy to swapchain");
+            }
 
+            gpu_timer.end(cmd, device, frame_idx);
             device.end_command_buffer(cmd)?;
+            Ok(frame_stats)
         }
-
-        Ok(())
     }
 
-    fn draw_geom<T: VertexAttributes + Debug>(
+    /// The skybox and the lighting of the deferred path, drawn before the forward surfaces.
+    fn record_scene_background(
         cmd: CommandBuffer,
-        window: &Window,
-        frame_resources: &mut FrameResources,
-        gltf_buffers: &[Arc<Mutex<MeshAsset<Vertex3D>>>],
-        memory_allocator: &Arc<MemoryAllocator>,
-        descriptor_set: &DescriptorSetDetails,
         device: &Arc<VkDevice>,
-        scene_data: SceneData,
-        extent: &Extent2D,
+        skybox: Option<&Skybox>,
+        deferred: Option<&DeferredShading>,
+        scene_data: &SceneData,
+        scene_data_binding: SceneDataBinding,
+        bindless_set: DescriptorSet,
         viewports: &[Viewport],
-        gltf_pipeline: &VkPipeline,
         render_area: &Rect2D,
-        draw_image: &AllocatedImage,
-        draw_ctx: &DrawContext,
-        graphics_queue: &Arc<VkQueue>,
-        command_pool: &VkCommandPool,
-    ) -> Result<()> {
-        unsafe {
-            let gpu_scene_data_buffer = memory_allocator
-                .create_buffer_with_mapped_memory::<SceneData>(
-                    &[scene_data],
-                    &[graphics_queue.clone()],
-                    BufferUsageFlags::UNIFORM_BUFFER | BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-                    vk_mem::MemoryUsage::Auto,
-                    MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-                    command_pool,
-                )?;
-
-            let scene_data_allocation = gpu_scene_data_buffer.allocation;
-            let gpu_scene_data_buffer = gpu_scene_data_buffer.unit.get_copied::<VkBuffer>();
-            frame_resources
-                .per_frame_deletion_queue
-                .enqueue(FType::TASK(Box::new(DestroyBufferTask {
-                    buffer: *gpu_scene_data_buffer,
-                    allocation: scene_data_allocation,
-                })));
-
-            let scene_data_descriptor_layout = frame_resources
-                .descriptor_layout_builder
-                .add_binding(
-                    0,
-                    DescriptorType::UNIFORM_BUFFER,
-                    ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
-                )
-                .build(
-                    device.clone(),
-                    ShaderStageFlags::empty(),
-                    DescriptorSetLayoutCreateFlags::empty(),
-                );
-            let scene_data_set = frame_resources
-                .descriptor_allocator
-                .borrow_mut()
-                .allocate(device.clone(), &[scene_data_descriptor_layout]);
-            frame_resources.descriptor_writer.write_buffer(
-                0,
-                gpu_scene_data_buffer,
-                size_of::<SceneData>() as u64,
-                0,
-                DescriptorType::UNIFORM_BUFFER,
+    ) {
+        if let Some(skybox) = skybox {
+            skybox.record(cmd, device, scene_data, viewports, render_area);
+        }
+        if let Some(deferred) = deferred {
+            deferred.record_lighting(
+                cmd,
+                device,
+                scene_data_binding,
+                bindless_set,
+                viewports,
+                render_area,
             );
-            frame_resources
-                .descriptor_writer
-                .update_set(device.clone(), scene_data_set[0]);
+        }
+    }
+
+    fn draw_geom(
+        cmd: CommandBuffer,
+        pass: SurfacePass,
+        scene_data_binding: SceneDataBinding,
+        viewports: &[Viewport],
+        render_area: &Rect2D,
+        surfaces: &[RenderObject],
+    ) -> DrawStats {
+        let SurfacePass {
+            device,
+            bindless_set,
+            joint_address,
+            after_depth_prepass,
+            skip_deferred,
+        } = pass;
+        // the pre-pass already wrote the closest depth, only the fragment matching it is shaded
+        let depth_compare_op = if after_depth_prepass {
+            CompareOp::EQUAL
+        } else {
+            CompareOp::LESS_OR_EQUAL
+        };
+        let mut stats = DrawStats::default();
+        unsafe {
             device.cmd_set_scissor(cmd, 0, &[*render_area]);
             device.cmd_set_viewport(cmd, 0, viewports);
 
-            for render_obj in &draw_ctx.opaque_surfaces {
-                device.cmd_bind_pipeline(
+            // all material pipelines share their layout, the sets stay bound across pipelines
+            if let Some(first) = surfaces.first() {
+                scene_data_binding.bind(
+                    device,
                     cmd,
-                    PipelineBindPoint::GRAPHICS,
-                    *render_obj.material.pipeline.pipeline,
-                );
-                device.cmd_bind_descriptor_sets(
-                    cmd,
-                    PipelineBindPoint::GRAPHICS,
-                    render_obj.material.pipeline.pipeline_layout,
-                    0,
-                    &scene_data_set,
-                    &[],
-                );
-                device.cmd_bind_descriptor_sets(
-                    cmd,
-                    PipelineBindPoint::GRAPHICS,
-                    render_obj.material.pipeline.pipeline_layout,
-                    1,
-                    &render_obj.material.material_set,
-                    &[],
+                    first.material.pipeline.pipeline_layout,
+                    &[bindless_set],
                 );
+                stats.descriptor_set_binds += 1;
+            }
+            let mut bound_pipeline = None;
+            // meshes share the buffers of the mesh arena
+            let mut bound_index_buffer = None;
+            // surfaces of one mesh with the same material push the same constants, which stay
+            // valid across the pipelines sharing the layout
+            let mut pushed_constant = None;
+            for render_obj in surfaces
+                .iter()
+                .filter(|render_obj| !(skip_deferred && render_obj.material.deferred()))
+            {
+                let pipeline = *render_obj.material.pipeline.pipeline;
+                if bound_pipeline != Some(pipeline) {
+                    device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, pipeline);
+                    // skinned surfaces are not in the pre-pass, their depth is tested here
+                    device.cmd_set_depth_compare_op(
+                        cmd,
+                        if render_obj.material.pipeline.skinned {
+                            CompareOp::LESS_OR_EQUAL
+                        } else {
+                            depth_compare_op
+                        },
+                    );
+                    bound_pipeline = Some(pipeline);
+                    stats.pipeline_binds += 1;
+                }
 
-                device.cmd_bind_index_buffer(cmd, *render_obj.index_buffer, 0, IndexType::UINT32);
-                let gpu_push_constant =
-                    gpu_scene_push_constant(render_obj.transform, render_obj.vertex_buffer_address);
-                device.cmd_push_constants(
-                    cmd,
-                    render_obj.material.pipeline.pipeline_layout,
-                    ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
-                    0,
-                    &gpu_push_constant,
-                );
+                if bound_index_buffer != Some(*render_obj.index_buffer) {
+                    device.cmd_bind_index_buffer(cmd, *render_obj.index_buffer, 0, IndexType::UINT32);
+                    bound_index_buffer = Some(*render_obj.index_buffer);
+                    stats.index_buffer_binds += 1;
+                }
+                let gpu_push_constant = match render_obj.joint_offset {
+                    Some(joint_offset) => skinned_scene_push_constant(
+                        render_obj.transform,
+                        render_obj.vertex_buffer_address,
+                        render_obj.material.material_index,
+                        joint_address + joint_offset as u64 * size_of::<Matrix4<f32>>() as u64,
+                    ),
+                    None => gpu_scene_push_constant(
+                        render_obj.transform,
+                        render_obj.vertex_buffer_address,
+                        render_obj.material.material_index,
+                    ),
+                };
+                if pushed_constant.as_ref() != Some(&gpu_push_constant) {
+                    device.cmd_push_constants(
+                        cmd,
+                        render_obj.material.pipeline.pipeline_layout,
+                        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                        0,
+                        &gpu_push_constant,
+                    );
+                    pushed_constant = Some(gpu_push_constant);
+                    stats.push_constant_updates += 1;
+                }
+                stats.record_draw(render_obj.index_count, 1);
                 device.cmd_draw_indexed(
                     cmd,
                     render_obj.index_count,
@@ -884,7 +2301,7 @@ impl Renderer {
                 );
             }
         };
-        Ok(())
+        stats
     }
 
     #[allow(dead_code)]
@@ -896,71 +2313,1729 @@ impl Renderer {
     }
 
     fn submit_queue(
-        &self,
+        &mut self,
         queue: Queue,
         frame_idx: usize, // Added frame_idx
         submit_cmd_buffers: &[CommandBuffer],
-        stage_masks: &[PipelineStageFlags],
+        upload_wait: Option<u64>,
+        compute_wait: Option<u64>,
+        presented: &[(Semaphore, Semaphore)],
     ) {
-        let frame_data = &self.frame_data[frame_idx]; // Access frame_data using index
-        let submit_info = vec![SubmitInfo::default()
-            .command_buffers(submit_cmd_buffers)
-            .wait_dst_stage_mask(stage_masks)
-            .signal_semaphores(&frame_data.render_semaphore)
-            .wait_semaphores(&frame_data.swapchain_semaphore)];
+        let mut wait_infos = vec![];
+        let mut signal_infos = vec![];
+        // the acquire and render semaphores of every presented image, none for headless frames
+        for &(acquire_semaphore, render_semaphore) in presented {
+            // the swapchain image is first written by the copy of the finished frame
+            wait_infos.push(
+                SemaphoreSubmitInfo::default()
+                    .semaphore(acquire_semaphore)
+                    .stage_mask(PipelineStageFlags2::TRANSFER),
+            );
+            signal_infos.push(
+                SemaphoreSubmitInfo::default()
+                    .semaphore(render_semaphore)
+                    .stage_mask(PipelineStageFlags2::ALL_COMMANDS),
+            );
+        }
+        if let Some(value) = upload_wait {
+            wait_infos.push(
+                SemaphoreSubmitInfo::default()
+                    .semaphore(self.upload_context.semaphore())
+                    .value(value)
+                    .stage_mask(UPLOAD_DST_STAGES),
+            );
+        }
+        if let Some(value) = compute_wait {
+            wait_infos.push(
+                SemaphoreSubmitInfo::default()
+                    .semaphore(self.async_compute.semaphore())
+                    .value(value)
+                    .stage_mask(COMPUTE_DST_STAGES),
+            );
+        }
+        // lets compute batches wait for the frames submitted before them
+        signal_infos.push(
+            SemaphoreSubmitInfo::default()
+                .semaphore(self.async_compute.frame_semaphore())
+                .value(self.async_compute.next_frame_value())
+                .stage_mask(PipelineStageFlags2::ALL_COMMANDS),
+        );
+        let command_buffer_infos = submit_cmd_buffers
+            .iter()
+            .map(|&command_buffer| CommandBufferSubmitInfo::default().command_buffer(command_buffer))
+            .collect::<Vec<_>>();
+        let submit_info = vec![SubmitInfo2::default()
+            .wait_semaphore_infos(&wait_infos)
+            .command_buffer_infos(&command_buffer_infos)
+            .signal_semaphore_infos(&signal_infos)];
         unsafe {
             self.device
-                .queue_submit(queue, &submit_info, frame_data.render_fence[0])
+                .queue_submit2(queue, &submit_info, self.frame_data[frame_idx].render_fence[0])
                 .unwrap()
         };
     }
 
-    fn present_queue(&self, queue: Queue, wait_semaphores: &[Semaphore], image_indices: &[u32]) {
-        let swapchains = vec![**self.swapchain];
+    /// Presents the `acquired` images of their windows at once, returns for each whether its
+    /// swapchain no longer matches the surface and has to be recreated. Presents from the
+    /// presentation queue, which is the graphics queue unless the graphics family can't present.
+    fn present_queue(
+        &self,
+        acquired: &[(WindowSlot, ImageIndex)],
+        wait_semaphores: &[Semaphore],
+    ) -> Result<Vec<bool>> {
+        let swapchains: Vec<SwapchainKHR> = acquired
+            .iter()
+            .map(|(slot, _)| **self.slot_target(*slot).swapchain)
+            .collect();
+        let image_indices: Vec<u32> =
+            acquired.iter().map(|(_, image_index)| **image_index).collect();
+        let mut results = vec![ash::vk::Result::SUCCESS; acquired.len()];
         let present_info = PresentInfoKHR::default()
             .wait_semaphores(wait_semaphores)
             .swapchains(&swapchains)
-            .image_indices(image_indices);
-        unsafe {
-            self.swapchain
-                .s_device
-                .queue_present(queue, &present_info)
-                .unwrap()
+            .image_indices(&image_indices)
+            .results(&mut results);
+        let queue = self.presentation_queue.as_ref().unwrap_or(&self.graphics_queue);
+        // every swapchain of the device presents through the same functions
+        let s_device = &self.slot_target(WindowSlot::Main).swapchain.s_device;
+        match unsafe { s_device.queue_present(***queue, &present_info) } {
+            Ok(_) | Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                matches!(
+                    result,
+                    ash::vk::Result::SUBOPTIMAL_KHR | ash::vk::Result::ERROR_OUT_OF_DATE_KHR
+                )
+            })
+            .collect())
+    }
+
+    /// Draws the following frames at `scale` times the swapchain extent, e.g. to lower the
+    /// resolution while the GPU can't keep up. The render targets keep the size of the
+    /// `render_scale` the renderer was created with, so the scale is clamped to it and to
+    /// `MIN_RENDER_SCALE`. Headless renderers always draw the whole draw image. Returns the
+    /// scale that is used from now on.
+    pub fn set_render_scale(&mut self, scale: f32) -> f32 {
+        if self.window_target.is_none() {
+            return self.render_scale;
+        }
+        let scale = clamp_render_scale(scale).min(self.config.render_scale);
+        if scale != self.render_scale {
+            let render_extent = scale_extent(self.extent, scale / self.config.render_scale);
+            self.render_area = Rect2D::default().extent(render_extent);
+            self.viewports[0] = self.viewports[0]
+                .width(render_extent.width as f32)
+                .height(render_extent.height as f32);
+            self.render_scale = scale;
+            self.invalidate();
+        }
+        scale
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    pub fn set_presentation_policy(&mut self, presentation: PresentationPolicy) {
+        self.config.presentation = presentation;
+        self.invalidate();
+    }
+
+    pub fn presentation_policy(&self) -> PresentationPolicy {
+        self.config.presentation
+    }
+
+    /// Draws the frames split into `view` and the views added before, e.g. side by side for
+    /// split-screen. Until the first view is added the scene camera covers the whole frame. The
+    /// first view is the one picking, the analysis and the raw frame callbacks use, screen
+    /// space ambient occlusion is only applied while there is at most one view.
+    pub fn add_view(&mut self, view: View) -> Result<ViewId> {
+        if self.views.len() >= MAX_VIEWS {
+            return Err(anyhow!("A frame is split into at most {MAX_VIEWS} views"));
+        }
+        let id = ViewId(self.next_view);
+        self.next_view += 1;
+        self.views.push((id, view));
+        self.invalidate();
+        Ok(id)
+    }
+
+    pub fn remove_view(&mut self, id: ViewId) -> Option<View> {
+        let idx = self.views.iter().position(|(view_id, _)| *view_id == id)?;
+        self.invalidate();
+        Some(self.views.remove(idx).1)
+    }
+
+    /// The view `id`, to move its camera or rect.
+    pub fn view_mut(&mut self, id: ViewId) -> Option<&mut View> {
+        self.invalidate();
+        self.views
+            .iter_mut()
+            .find(|(view_id, _)| *view_id == id)
+            .map(|(_, view)| view)
+    }
+
+    /// The views in the order they were added.
+    pub fn views(&self) -> impl Iterator<Item = (ViewId, &View)> {
+        self.views.iter().map(|(id, view)| (*id, view))
+    }
+
+    /// Viewport of the first view in the render targets.
+    fn primary_viewport(&self) -> Viewport {
+        self.view_targets
+            .first()
+            .map_or(self.viewports[0], |view| view.viewport)
+    }
+
+    /// The viewport in physical pixels of the window the first view is scaled onto, the render
+    /// viewport of the view when headless.
+    fn window_viewport(&self) -> Viewport {
+        let primary = self.primary_viewport();
+        match &self.window_target {
+            Some(window_target) => {
+                let window = self
+                    .config
+                    .presentation
+                    .image_viewport(self.render_area.extent, window_target.swapchain.extent);
+                let x = window.width / self.render_area.extent.width as f32;
+                let y = window.height / self.render_area.extent.height as f32;
+                window
+                    .x(window.x + primary.x * x)
+                    .y(window.y + primary.y * y)
+                    .width(primary.width * x)
+                    .height(primary.height * y)
+            }
+            None => primary,
+        }
+    }
+
+    /// Presentation mode the swapchain was created with, the configured one when headless.
+    pub fn present_mode(&self) -> PresentModeKHR {
+        self.config.present_mode
+    }
+
+    /// Shows `window`, the one the renderer was created for, in `mode`. The swapchain follows
+    /// the new size of the window before the next frame.
+    pub fn set_display_mode(&mut self, window: &Window, mode: DisplayMode) -> Result<()> {
+        let Some(window_target) = &mut self.window_target else {
+            return Err(anyhow!("Headless renderers have no window"));
         };
+        window.set_fullscreen(mode.fullscreen(window));
+        window_target.outdated = true;
+        self.invalidate();
+        Ok(())
     }
 
-    pub fn update_scene(&mut self) {
-        let (width, height) = (self.extent.width, self.extent.height);
-        self.draw_ctx.opaque_surfaces.clear();
-        self.loaded_nodes
-        .get("Suzanne")
-        .unwrap()
-        .draw(Matrix4::identity(), &mut self.draw_ctx);
-        for x in -3..3 {
-            let scale = nalgebra::Scale3::new(0.2, 0.2, 0.2).to_homogeneous();
-            let translation = Matrix4::new_translation(&Vector3::new(x as f32, 1.0, 0.0));            
-            self.loaded_nodes
-                .get("Cube")
-                .unwrap()
-                .draw(translation * scale, &mut self.draw_ctx);
-        }
-        self.scene_data.view = Matrix4::new_translation(&Vector3::new(0.0, 0.0, -2.0));
-        self.scene_data.proj = Perspective3::new(
-            90.0_f32.to_radians(),
-            width as f32 / height as f32,
-            0.1,
-            1000.0,
-        )
-        .to_homogeneous();
-        self.scene_data.view_proj = self.scene_data.proj * self.scene_data.view;
-        self.scene_data.sunlight_color = Vector4::from_element(1.0);
-        self.scene_data.ambient_color = Vector4::from_element(0.1);
-        self.scene_data.sunlight_direction = Vector4::new(0.0, 1.0, 0.5, 1.0);
+    /// Recreates the swapchains of all windows with `present_mode`, or the closest mode the
+    /// surface of the main window supports. Waits for the GPU to finish all frames in flight.
+    pub fn set_present_mode(
+        &mut self,
+        window: &Window,
+        present_mode: PresentModeKHR,
+    ) -> Result<()> {
+        if self.window_target.is_none() {
+            return Err(anyhow!("Headless renderers don't present"));
+        }
+        if present_mode == self.config.present_mode {
+            return Ok(());
+        }
+        self.recreate_swapchain(window, present_mode)?;
+        for idx in 0..self.secondary_windows.len() {
+            self.recreate_secondary_window(idx)?;
+        }
+        let minimized = self.window_target.as_ref().is_some_and(|target| target.minimized);
+        if !minimized && self.config.present_mode != present_mode {
+            self.notify(
+                NotificationLevel::Warn,
+                format!(
+                    "{present_mode:?} presentation is not supported by the surface, using {:?}",
+                    self.config.present_mode
+                ),
+            );
+        }
+        self.invalidate();
+        Ok(())
+    }
 
-        /*       for x in -3..3 {
-            let scale: Matrix4<f32> = Matrix4::default().scale(0.2);
-            let translation = Matrix4::new_translation(&Vector3::new(x as f32, 1.0, 0.0));
-            self.loaded_nodes.get("Cube").unwrap().draw(translation * scale, &mut self.draw_ctx);
-        } */
+    /// Replaces the swapchain of the main window, its image views and the egui framebuffers on
+    /// them once the device is idle. The scene keeps rendering at the size the renderer was
+    /// created with and is scaled to the new swapchain size.
+    fn recreate_swapchain(&mut self, window: &Window, present_mode: PresentModeKHR) -> Result<()> {
+        let Some(window_target) = &mut self.window_target else {
+            return Ok(());
+        };
+        unsafe { self.device.device_wait_idle()? };
+        if is_zero_sized(window) {
+            // No swapchain can be created without an extent, `display` retries once there is one.
+            self.config.present_mode = present_mode;
+            window_target.minimized = true;
+            return Ok(());
+        }
+        window_target.recreate(window, present_mode)?;
+        if let Some(egui_renderer) = &mut self.egui_renderer {
+            egui_renderer.recreate_framebuffers(
+                &window_target.image_details,
+                window_target.swapchain.extent,
+            );
+        }
+        self.config.present_mode = window_target.swapchain.present_mode;
+        Ok(())
+    }
+
+    /// Like `recreate_swapchain` for the secondary window `idx`.
+    fn recreate_secondary_window(&mut self, idx: usize) -> Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        let secondary = &mut self.secondary_windows[idx];
+        if is_zero_sized(&secondary.window) {
+            secondary.target.minimized = true;
+            return Ok(());
+        }
+        secondary.target.recreate(&secondary.window, self.config.present_mode)
+    }
+
+    /// Acquires an image of every secondary window that has an area for the frame `frame_idx`.
+    /// Windows whose swapchain is out of date are recreated and skip the frame, so do windows
+    /// failing to acquire or recreate, which is reported without holding up the others.
+    fn acquire_secondary_windows(
+        &mut self,
+        frame_idx: usize,
+        render_fence: Fence,
+    ) -> Vec<(WindowSlot, ImageIndex)> {
+        let mut acquired = vec![];
+        for idx in 0..self.secondary_windows.len() {
+            match self.acquire_secondary_window(idx, frame_idx, render_fence) {
+                Ok(Some(image_index)) => acquired.push((WindowSlot::Secondary(idx), image_index)),
+                Ok(None) => {}
+                Err(err) => {
+                    let window_id = self.secondary_windows[idx].window.id();
+                    self.report_error(format!("Window {window_id:?} skips the frame: {err}"));
+                }
+            }
+        }
+        acquired
+    }
+
+    fn acquire_secondary_window(
+        &mut self,
+        idx: usize,
+        frame_idx: usize,
+        render_fence: Fence,
+    ) -> Result<Option<ImageIndex>> {
+        let secondary = &mut self.secondary_windows[idx];
+        if is_zero_sized(&secondary.window) {
+            secondary.target.minimized = true;
+            return Ok(None);
+        }
+        if secondary.target.minimized {
+            self.recreate_secondary_window(idx)?;
+        }
+        match self.secondary_windows[idx].target.acquire(frame_idx, render_fence)? {
+            Some(image_index) => Ok(Some(image_index)),
+            None => self.recreate_secondary_window(idx).map(|_| None),
+        }
+    }
+
+    fn slot_target(&self, slot: WindowSlot) -> &WindowTarget {
+        match slot {
+            WindowSlot::Main => self.window_target.as_ref().unwrap(),
+            WindowSlot::Secondary(idx) => &self.secondary_windows[idx].target,
+        }
+    }
+
+    /// Opens a swapchain on `window`, which from the next frame on shows the frames presented
+    /// to the main window, scaled with the same presentation policy but without the UI. Its
+    /// events don't have to be forwarded, the swapchain follows its size. Only renderers with
+    /// a window can draw to more.
+    pub fn add_window(&mut self, window: Arc<Window>) -> Result<()> {
+        let Some(main_target) = &self.window_target else {
+            return Err(anyhow!("Headless renderers can't draw to windows"));
+        };
+        let surface = Arc::new(surface::KHRSurface::new(self.instance.clone(), &window)?);
+        let presentation_queue = self.presentation_queue.as_ref().unwrap_or(&self.graphics_queue);
+        let supported = unsafe {
+            surface.instance.get_physical_device_surface_support(
+                self.device.physical_device,
+                presentation_queue.queue_family_index,
+                **surface,
+            )?
+        };
+        if !supported {
+            return Err(anyhow!("The presentation queue can't present to the window"));
+        }
+        let swapchain = KHRSwapchain::new(
+            self.instance.clone(),
+            self.device.clone(),
+            surface,
+            &window,
+            [self.graphics_queue.clone(), presentation_queue.clone()],
+            main_target.swapchain.color_space,
+            self.config.present_mode,
+        )?;
+        let target = WindowTarget::new(self.device.clone(), window.id(), Arc::new(swapchain))?;
+        self.secondary_windows.push(SecondaryWindow { target, window });
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Stops drawing to the window opened with `add_window` and destroys its swapchain and
+    /// surface, returns `false` if there was none for `window_id`. Waits for the GPU to finish
+    /// all frames in flight.
+    pub fn remove_window(&mut self, window_id: WindowId) -> Result<bool> {
+        let Some(idx) = self
+            .secondary_windows
+            .iter()
+            .position(|secondary| secondary.target.window_id == window_id)
+        else {
+            return Ok(false);
+        };
+        unsafe { self.device.device_wait_idle()? };
+        let mut secondary = self.secondary_windows.remove(idx);
+        secondary.target.destroy();
+        Ok(true)
+    }
+
+    /// Loads the cube faces `px`, `nx`, `py`, `ny`, `pz`, `nz` from `directory` and renders
+    /// them as the background behind all opaque geometry from now on.
+    pub fn set_environment_map<P: AsRef<Path>>(&mut self, directory: P) -> Result<()> {
+        let directory = directory.as_ref();
+        let mut deletion_queue =
+            DeletionQueue::new(self.device.clone(), self.memory_allocator.clone());
+        let skybox = match Skybox::load(
+            directory,
+            self.device.clone(),
+            self.memory_allocator.clone(),
+            &self.command_pool,
+            &mut self.descriptor_allocator,
+            &self.samplers,
+            &self.extent,
+            self.render_pass.clone(),
+            &mut deletion_queue,
+        ) {
+            Ok(skybox) => skybox,
+            Err(err) => {
+                // nothing was submitted with what got created so far
+                deletion_queue.flush();
+                self.report_error(format!("Environment map {}: {err}", directory.display()));
+                return Err(err);
+            }
+        };
+        if let Some(previous) = self.skybox.replace(skybox) {
+            self.main_deletion_queue
+                .enqueue_after(self.frame_number, FType::DEVICE(Box::new(move |_| drop(previous))));
+        }
+        if let Some(previous) = self.skybox_deletion_queue.replace(deletion_queue) {
+            // frames in flight may still draw the previous skybox
+            self.main_deletion_queue.defer_queue(self.frame_number, previous);
+        }
+        self.notify(
+            NotificationLevel::Info,
+            format!("Environment map loaded from {}", directory.display()),
+        );
+        Ok(())
+    }
+
+    /// Makes `definition` available by name through `material`. Registering a name again
+    /// replaces the material everywhere it was used as a mesh or instance material.
+    pub fn register_material(&mut self, definition: &MaterialDefinition) -> Arc<GLTFMaterial> {
+        let Registration {
+            material,
+            replaced,
+            error,
+        } = self.materials.register(
+            definition,
+            &mut self.bindless,
+            &mut self.main_deletion_queue,
+        );
+        if let Some(err) = error {
+            self.report_error(format!("Material {}: {err:#}", definition.name));
+        }
+        if let Some(replaced) = replaced {
+            for asset in &self.gltf_buffers {
+                for surface in asset.lock().unwrap().surfaces.iter_mut() {
+                    if surface
+                        .material
+                        .as_ref()
+                        .is_some_and(|current| Arc::ptr_eq(current, &replaced))
+                    {
+                        surface.material = Some(material.clone());
+                    }
+                }
+            }
+            for scene in &mut self.scenes {
+                scene.replace_material(&replaced, &material);
+            }
+            self.assets.replace_material(&replaced, &material);
+            // frames in flight may still draw with the pipelines of the replaced material
+            self.main_deletion_queue
+                .enqueue_after(self.frame_number, FType::DEVICE(Box::new(move |_| drop(replaced))));
+            self.invalidate();
+        }
+        material
+    }
+
+    /// Registers every material of a TOML material file and reloads them whenever the file
+    /// changes, see `MaterialDefinition` for the format.
+    pub fn load_material_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Arc<GLTFMaterial>>> {
+        let materials: Vec<_> = MaterialDefinition::load_file(&path)
+            .inspect_err(|err| {
+                self.report_error(format!("Material file {}: {err}", path.as_ref().display()))
+            })?
+            .iter()
+            .map(|definition| self.register_material(definition))
+            .collect();
+        self.notify(
+            NotificationLevel::Info,
+            format!(
+                "Loaded {} materials from {}",
+                materials.len(),
+                path.as_ref().display()
+            ),
+        );
+        self.materials.watch(path);
+        Ok(materials)
+    }
+
+    pub fn material(&self, name: &str) -> Option<Arc<GLTFMaterial>> {
+        self.materials.get(name)
+    }
+
+    /// Re-registers the materials of watched material files that changed on disk, a file that
+    /// can't be parsed keeps the previously loaded materials.
+    fn reload_changed_material_files(&mut self) {
+        for path in self.materials.changed_files() {
+            match MaterialDefinition::load_file(&path) {
+                Ok(definitions) => {
+                    for definition in &definitions {
+                        self.register_material(definition);
+                    }
+                    self.notify(
+                        NotificationLevel::Info,
+                        format!("Reloaded materials from {}", path.display()),
+                    );
+                }
+                Err(err) => {
+                    self.report_error(format!("Material file {}: {err}", path.display()))
+                }
+            }
+        }
+    }
+
+    /// Handles of the meshes of the glTF file at `path`, loading it unless its meshes are
+    /// registered already. Every handle holds a reference that `release_mesh` gives back. The
+    /// materials of the file are registered in the material library under their names, see
+    /// `read_gltf_materials`.
+    pub fn load_meshes<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<MeshHandle>> {
+        let path = path.as_ref();
+        if let Some(handles) = self.assets.find_meshes(path) {
+            return Ok(handles);
+        }
+        let GltfMeshes { meshes, materials } =
+            read_gltf_file(path, self.scissors[0], self.viewports[0])?;
+        let registered: HashMap<String, Arc<GLTFMaterial>> = materials
+            .iter()
+            .map(|definition| (definition.name.clone(), self.register_material(definition)))
+            .collect();
+        // every buffer of the file is uploaded with a single submission
+        let submit = ImmediateSubmit::begin(&self.command_pool, self.graphics_queue.clone())?;
+        let mut assets = vec![];
+        for data in meshes {
+            let surface_materials = data.surface_materials.clone();
+            let mut asset = MeshAsset::<Vertex3D>::upload(data, &submit, &self.mesh_arena)?;
+            for (surface, name) in asset.surfaces.iter_mut().zip(surface_materials) {
+                surface.material = name.and_then(|name| registered.get(&name).cloned());
+            }
+            assets.push(Arc::new(Mutex::new(asset)));
+        }
+        submit.submit()?;
+        Ok(self.assets.insert_meshes(path, assets))
+    }
+
+    /// Loads the objects of the OBJ file at `path` as meshes. The materials of its MTL files
+    /// are registered in the material library under their names, surfaces without one are
+    /// drawn with a plain white material.
+    pub fn load_obj<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<Arc<Mutex<MeshAsset<Vertex3D>>>>> {
+        let ObjMeshes { meshes, materials } =
+            read_obj_meshes(&path, self.scissors[0], self.viewports[0])?;
+        let mut registered: HashMap<String, Arc<GLTFMaterial>> = materials
+            .iter()
+            .map(|definition| (definition.name.clone(), self.register_material(definition)))
+            .collect();
+        let submit = ImmediateSubmit::begin(&self.command_pool, self.graphics_queue.clone())?;
+        let mut assets = vec![];
+        for data in meshes {
+            let surface_materials = data.surface_materials.clone();
+            let mut asset = MeshAsset::<Vertex3D>::upload(data, &submit, &self.mesh_arena)?;
+            for (surface, name) in asset.surfaces.iter_mut().zip(surface_materials) {
+                let material = match name.and_then(|name| registered.get(&name).cloned()) {
+                    Some(material) => material,
+                    None => self.obj_default_material(&mut registered),
+                };
+                surface.material = Some(material);
+            }
+            assets.push(Arc::new(Mutex::new(asset)));
+        }
+        submit.submit()?;
+        Ok(assets)
+    }
+
+    fn obj_default_material(
+        &mut self,
+        registered: &mut HashMap<String, Arc<GLTFMaterial>>,
+    ) -> Arc<GLTFMaterial> {
+        if let Some(material) = registered.get(OBJ_DEFAULT_MATERIAL) {
+            return material.clone();
+        }
+        let material = self.materials.get(OBJ_DEFAULT_MATERIAL).unwrap_or_else(|| {
+            self.register_material(&MaterialDefinition {
+                metal_rough_factors: [0.0, 0.5, 0.0, 0.0],
+                ..MaterialDefinition::new(OBJ_DEFAULT_MATERIAL)
+            })
+        });
+        registered.insert(OBJ_DEFAULT_MATERIAL.to_owned(), material.clone());
+        material
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> Option<Arc<Mutex<MeshAsset<Vertex3D>>>> {
+        self.assets.meshes.get(handle).cloned()
+    }
+
+    /// Gives back the reference of `handle`, the buffers of the mesh return to the mesh arena
+    /// with the last one once the frames in flight are done with them.
+    pub fn release_mesh(&mut self, handle: MeshHandle) {
+        if let Some(mesh) = self.assets.meshes.release(handle) {
+            let slices = mesh.lock().unwrap().buffer_slices();
+            let mesh_arena = self.mesh_arena.clone();
+            self.main_deletion_queue.enqueue_after(
+                self.frame_number,
+                FType::DEVICE(Box::new(move |_| {
+                    slices.into_iter().for_each(|slice| mesh_arena.free(slice))
+                })),
+            );
+        }
+    }
+
+    /// Handle of the mipmapped texture of the PNG or JPEG file at `path`, loading it unless
+    /// it is registered already. A file is loaded once, with the `srgb` of the first load.
+    pub fn load_texture<P: AsRef<Path>>(&mut self, path: P, srgb: bool) -> Result<TextureHandle> {
+        let path = path.as_ref();
+        let (memory_allocator, command_pool) = (&self.memory_allocator, &self.command_pool);
+        self.assets
+            .textures
+            .get_or_insert_with(AssetKey::Path(path.to_path_buf()), || {
+                memory_allocator.create_image_from_file(
+                    path,
+                    srgb,
+                    true,
+                    ImageUsageFlags::empty(),
+                    command_pool,
+                )
+            })
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<AllocatedImage> {
+        self.assets.textures.get(handle).cloned()
+    }
+
+    /// Gives back the reference of `handle`, the image is dropped with the last one once the
+    /// frames in flight are done with it.
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        if let Some(texture) = self.assets.textures.release(handle) {
+            self.main_deletion_queue
+                .enqueue_after(self.frame_number, FType::DROP(Box::new(texture)));
+        }
+    }
+
+    /// Handle of the material registered as `name` in the material library, it keeps
+    /// resolving to the material registered last under that name.
+    pub fn material_handle(&mut self, name: &str) -> Option<MaterialHandle> {
+        let material = self.materials.get(name)?;
+        let handle = self
+            .assets
+            .materials
+            .get_or_insert_with(AssetKey::Name(name.to_owned()), || Ok::<_, Error>(material));
+        handle.ok()
+    }
+
+    pub fn material_by_handle(&self, handle: MaterialHandle) -> Option<Arc<GLTFMaterial>> {
+        self.assets.materials.get(handle).cloned()
+    }
+
+    pub fn release_material(&mut self, handle: MaterialHandle) {
+        self.assets.materials.release(handle);
+    }
+
+    /// Starts loading the meshes of the glTF file at `path` in the background, the prefab is
+    /// available from `loaded_gltf` once `asset_status` is `Ready`.
+    pub fn load_gltf_async<P: AsRef<Path>>(&mut self, path: P) -> AssetHandle {
+        self.asset_server.load_gltf(path)
+    }
+
+    /// Starts loading the PNG or JPEG file at `path` in the background, see `load_gltf_async`.
+    pub fn load_texture_async<P: AsRef<Path>>(&mut self, path: P, srgb: bool) -> AssetHandle {
+        self.asset_server.load_texture(path, srgb)
+    }
+
+    pub fn asset_status(&self, handle: AssetHandle) -> Option<&AssetStatus> {
+        self.asset_server.status(handle)
+    }
+
+    pub fn loaded_gltf(&self, handle: AssetHandle) -> Option<&LoadedGLTF> {
+        self.asset_server.gltf(handle)
+    }
+
+    pub fn loaded_texture(&self, handle: AssetHandle) -> Option<AllocatedImage> {
+        self.asset_server.texture(handle)
+    }
+
+    /// Streams the assets decoded by background loads to the GPU, they are drawable in the
+    /// frame about to be recorded.
+    fn upload_loaded_assets(&mut self) {
+        let _span = info_span!("asset uploads").entered();
+        let finished = self.asset_server.upload_decoded(
+            &mut self.upload_context,
+            &self.mesh_arena,
+            &mut self.main_deletion_queue,
+        );
+        for handle in finished {
+            let path = self.asset_server.path(handle).unwrap().display().to_string();
+            match self.asset_server.status(handle) {
+                Some(AssetStatus::Failed(err)) => {
+                    let message = format!("Loading {path}: {err}");
+                    self.report_error(message);
+                }
+                _ => self.notify(NotificationLevel::Info, format!("Loaded {path}")),
+            }
+            self.invalidate();
+        }
+    }
+
+    /// Starts reading the loaded glTF files that changed on disk and swaps in the meshes of
+    /// those read meanwhile. The buffers they replace go back to the mesh arena once the frames
+    /// in flight are done with them, a file that can't be read keeps the previous meshes.
+    fn reload_changed_assets(&mut self) {
+        self.asset_reloader.poll_changes();
+        for file in self.asset_reloader.finished() {
+            let swapped = file
+                .meshes
+                .and_then(|meshes| self.swap_reloaded_meshes(&file.path, meshes));
+            if let Err(err) = swapped {
+                self.report_error(format!("Reloading {}: {err}", file.path.display()));
+                continue;
+            }
+            self.notify(
+                NotificationLevel::Info,
+                format!("Reloaded meshes from {}", file.path.display()),
+            );
+            self.invalidate();
+        }
+    }
+
+    fn swap_reloaded_meshes(&mut self, path: &Path, meshes: Vec<MeshData>) -> Result<()> {
+        let submit = ImmediateSubmit::begin(&self.command_pool, self.graphics_queue.clone())?;
+        let mut reloaded = vec![];
+        for data in meshes {
+            // meshes the file didn't have when it was loaded have no nodes drawing them
+            let Some(asset) = self.asset_reloader.asset(path, &data.name) else {
+                continue;
+            };
+            let mesh = MeshAsset::<Vertex3D>::upload(data, &submit, &self.mesh_arena)?;
+            reloaded.push((asset, mesh));
+        }
+        submit.submit()?;
+        for (asset, mesh) in reloaded {
+            let previous = asset.lock().unwrap().replace(mesh);
+            let mesh_arena = self.mesh_arena.clone();
+            self.main_deletion_queue.enqueue_after(
+                self.frame_number,
+                FType::DEVICE(Box::new(move |_| {
+                    previous.into_iter().for_each(|slice| mesh_arena.free(slice))
+                })),
+            );
+        }
+        Ok(())
+    }
+
+    /// Logs `message` at `level` and shows it as a toast in the UI.
+    pub fn notify(&mut self, level: NotificationLevel, message: impl Into<String>) {
+        let message = message.into();
+        match level {
+            NotificationLevel::Info => info!("{message}"),
+            NotificationLevel::Warn => warn!("{message}"),
+            NotificationLevel::Error => error!("{message}"),
+        }
+        if let Some(egui_renderer) = &mut self.egui_renderer {
+            egui_renderer.notify(level, message);
+        }
+    }
+
+    /// Notifies about an error, messages that were already reported are ignored.
+    pub fn report_error(&mut self, message: String) {
+        if self.reported_errors.insert(message.clone()) {
+            self.notify(NotificationLevel::Error, message);
+        }
+    }
+
+    /// Increments the editor tools snap translation, rotation and scale to.
+    pub fn grid_snap(&self) -> GridSnap {
+        self.grid_snap
+    }
+
+    pub fn set_grid_snap(&mut self, grid_snap: GridSnap) {
+        self.grid_snap = grid_snap;
+    }
+
+    /// Shows the distance between `from` and `to` until `remove_measurement` is called.
+    pub fn add_measurement(
+        &mut self,
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+        color: Vector4<f32>,
+    ) -> MeasurementId {
+        let id = MeasurementId(self.next_measurement);
+        self.next_measurement += 1;
+        self.measurements.insert(id, Measurement::new(from, to, color));
+        self.invalidate();
+        id
+    }
+
+    pub fn remove_measurement(&mut self, id: MeasurementId) -> Option<Measurement> {
+        self.invalidate();
+        self.measurements.remove(&id)
+    }
+
+    pub fn measurements(&self) -> impl Iterator<Item = (&MeasurementId, &Measurement)> {
+        self.measurements.iter()
+    }
+
+    /// Queues the measurement lines for this frame and labels them with their distance at the
+    /// projected midpoint.
+    fn draw_measurements(&mut self) {
+        let mut labels = vec![];
+        for measurement in self.measurements.values() {
+            measurement.draw(&mut self.debug_draw);
+            let Some(position) = project_to_viewport(
+                self.scene_data.view_proj,
+                measurement.midpoint(),
+                &self.window_viewport(),
+            ) else {
+                continue;
+            };
+            let [r, g, b, a] = measurement
+                .color
+                .map(|channel| (channel.clamp(0.0, 1.0) * 255.0) as u8)
+                .into();
+            labels.push(ScreenLabel::new(
+                position,
+                measurement.label(),
+                Color32::from_rgba_unmultiplied(r, g, b, a),
+            ));
+        }
+        if let Some(egui_renderer) = &mut self.egui_renderer {
+            egui_renderer.set_screen_labels(labels);
+        }
+    }
+
+    /// Reads the depth under `pixel` (physical pixels of the render target) in the next frame,
+    /// the result shows up in `depth_pick` once that frame has finished. Returns false if the
+    /// depth buffer can not be read back, which is the case with MSAA.
+    pub fn request_depth_pick(&mut self, pixel: [u32; 2]) -> bool {
+        self.depth_picker.request(pixel);
+        self.invalidate();
+        self.depth_picker.is_supported()
+    }
+
+    /// The latest finished depth pick with the world position under its pixel, e.g. to place
+    /// an object or a measurement end point where the user clicked.
+    pub fn depth_pick(&self) -> Option<DepthPick> {
+        self.depth_picker.result()
+    }
+
+    /// The prefab instance node under `cursor_pos` (physical pixels of the render target) in
+    /// the last drawn frame, for selecting objects in an editor. Unlike `request_depth_pick`
+    /// the answer is immediate, it is found by casting a ray against the bounding boxes of the
+    /// meshes, so the nearest box wins even where the mesh inside doesn't cover the pixel.
+    pub fn pick(&self, cursor_pos: [f32; 2]) -> Option<NodeHandle> {
+        let ray =
+            Ray::from_viewport(self.scene_data.view_proj, cursor_pos, &self.primary_viewport())?;
+        self.active_scene().ray_cast(&ray).map(|(handle, _)| handle)
+    }
+
+    /// Selects the node the gizmo is drawn on and manipulates, `None` hides the gizmo.
+    pub fn select(&mut self, selection: Option<NodeHandle>) {
+        if selection != self.selection {
+            self.gizmo.end_drag();
+            self.selection = selection;
+            self.invalidate();
+        }
+    }
+
+    pub fn selection(&self) -> Option<&NodeHandle> {
+        self.selection.as_ref()
+    }
+
+    pub fn set_gizmo_mode(&mut self, mode: GizmoMode) {
+        self.gizmo.set_mode(mode);
+        self.invalidate();
+    }
+
+    pub fn gizmo_mode(&self) -> GizmoMode {
+        self.gizmo.mode()
+    }
+
+    /// Picks and manipulates nodes with the left mouse button: a click grabs a handle of the
+    /// gizmo of the selected node to drag it, or selects the node under the cursor. Call it
+    /// with every window event after egui has seen it, clicks on the UI are left alone.
+    pub fn on_window_event(&mut self, event: &WindowEvent) {
+        let ui_wants_pointer = self
+            .egui_renderer
+            .as_ref()
+            .is_some_and(|egui_renderer| egui_renderer.wants_pointer_input());
+        let camera_position = self.active_scene().camera.position();
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some([position.x as f32, position.y as f32]);
+                let (Some(transform), Some(ray)) = (self.selection_transform(), self.cursor_ray())
+                else {
+                    return;
+                };
+                if self.gizmo.is_dragging() {
+                    if let Some(transform) = self.gizmo.drag(&ray) {
+                        self.set_selection_transform(transform);
+                    }
+                } else if self.gizmo.hover(
+                    &transform,
+                    camera_position,
+                    (!ui_wants_pointer).then_some(&ray),
+                ) {
+                    self.invalidate();
+                }
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+            WindowEvent::Resized(size) => {
+                if let Some(window_target) = &mut self.window_target {
+                    let extent = window_target.swapchain.extent;
+                    window_target.outdated |=
+                        extent.width != size.width || extent.height != size.height;
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !ui_wants_pointer => {
+                let Some(ray) = self.cursor_ray() else {
+                    return;
+                };
+                let grabbed = match self.selection_transform() {
+                    Some(transform) => self.gizmo.begin_drag(&transform, camera_position, &ray),
+                    None => false,
+                };
+                if !grabbed {
+                    let picked = self.active_scene().ray_cast(&ray).map(|(handle, _)| handle);
+                    self.select(picked);
+                }
+                self.invalidate();
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } if self.gizmo.is_dragging() => {
+                self.gizmo.end_drag();
+                self.invalidate();
+            }
+            _ => {}
+        }
+    }
+
+    fn cursor_ray(&self) -> Option<Ray> {
+        Ray::from_viewport(self.scene_data.view_proj, self.cursor_position?, &self.window_viewport())
+    }
+
+    /// World transform of the selected node, `None` once it was removed from the scene.
+    fn selection_transform(&self) -> Option<Matrix4<f32>> {
+        let handle = self.selection.as_ref()?;
+        let instance = self.active_scene().instances.get(handle.instance.0)?;
+        let node = instance.nodes.get(&handle.node)?;
+        Some(instance.transform * node.node.world_transform())
+    }
+
+    /// Moves the selected node to the world transform `transform`.
+    fn set_selection_transform(&mut self, transform: Matrix4<f32>) {
+        let Some(handle) = &self.selection else {
+            return;
+        };
+        let scene = self.active_scene();
+        let Some(node) = scene.node(handle) else {
+            return;
+        };
+        let Some(inverse) = scene.instances[handle.instance.0].transform.try_inverse() else {
+            return;
+        };
+        node.node.set_local_transform(inverse * transform);
+        // instance nodes have no parent, the instance transform is applied when drawn
+        node.node.refresh_transform(Matrix4::identity());
+        self.invalidate();
+    }
+
+    /// Draws a line for the current frame only.
+    pub fn debug_line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: Vector4<f32>) {
+        self.debug_draw.line(from, to, color);
+    }
+
+    /// Draws the outline of an axis aligned bounding box for the current frame only.
+    pub fn debug_aabb(&mut self, min: Vector3<f32>, max: Vector3<f32>, color: Vector4<f32>) {
+        self.debug_draw.aabb(min, max, color);
+    }
+
+    /// Toggles the luminance histogram, overdraw statistics and the overdraw heatmap overlay.
+    pub fn analysis_mut(&mut self) -> &mut GpuAnalysis {
+        &mut self.analysis
+    }
+
+    pub fn analysis_results(&self) -> Option<&AnalysisResults> {
+        self.analysis.results()
+    }
+
+    /// Lives in the UI so the debug window can edit it, in the config when headless.
+    pub fn clear_color(&self) -> [f32; 4] {
+        self.egui_renderer
+            .as_ref()
+            .map_or(self.config.clear_color, |egui_renderer| egui_renderer.clear_color())
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.config.clear_color = clear_color;
+        if let Some(egui_renderer) = &mut self.egui_renderer {
+            egui_renderer.set_clear_color(clear_color);
+        }
+        self.invalidate();
+    }
+
+    /// Transient fences and semaphores, for work submitted outside of the per frame ones.
+    pub fn sync_pool(&self) -> &SyncPool {
+        &self.sync_pool
+    }
+
+    /// Uploads recorded here become usable in the next frame without stalling the current
+    /// one, the returned buffers and images are owned by the caller.
+    pub fn upload_context(&mut self) -> &mut UploadContext {
+        &mut self.upload_context
+    }
+
+    /// Compute batches recorded here run on the compute queue alongside the graphics work, the
+    /// next frame drawn waits for the ones submitted.
+    pub fn async_compute(&mut self) -> &mut AsyncCompute {
+        &mut self.async_compute
+    }
+
+    pub fn display_transform(&self) -> DisplayTransform {
+        self.display_transform.transform()
+    }
+
+    /// Changes how the final image is encoded. The swapchain keeps the color space picked at
+    /// init, see `DisplayTransformPass::matches_surface`.
+    pub fn set_display_transform(&mut self, transform: DisplayTransform) {
+        self.display_transform.set_transform(transform);
+        self.invalidate();
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.post_process.tonemapper
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.post_process.tonemapper = tonemapper;
+        self.invalidate();
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.post_process.exposure
+    }
+
+    /// Linear scale applied to the scene colors before tonemapping.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.post_process.exposure = exposure.max(0.0);
+        self.invalidate();
+    }
+
+    /// `None` unless the renderer was created with `RendererConfig::ssao`.
+    pub fn ssao_settings(&self) -> Option<SsaoSettings> {
+        self.ssao.as_ref().map(|ssao| ssao.settings)
+    }
+
+    /// Ignored when SSAO is disabled.
+    pub fn set_ssao_settings(&mut self, settings: SsaoSettings) {
+        if let Some(ssao) = &mut self.ssao {
+            ssao.settings = settings;
+            self.invalidate();
+        }
+    }
+
+    /// `ambientOcclusion` of the scene data, x the bindless slot of the SSAO result and y
+    /// whether to sample it.
+    fn ambient_occlusion(&self) -> Vector4<u32> {
+        match &self.ssao {
+            // computed over the whole depth image with the projection of a single view
+            Some(ssao) if !ssao.suspended && self.views.len() <= 1 => {
+                Vector4::new(ssao.texture_index(), 1, 0, 0)
+            }
+            _ => Vector4::zeros(),
+        }
+    }
+
+    /// GPU milliseconds of the scene command buffer, `MAX_FRAMES` frames behind the current
+    /// one. The UI pass is not included.
+    pub fn gpu_frame_ms(&self) -> Option<f32> {
+        self.gpu_timer.last_frame_ms()
+    }
+
+    /// Enables the auto quality controller with `settings` or disables it with `None`, which
+    /// restores full quality. Steps only exist for what the renderer was created with:
+    /// suspending SSAO, then twice lowering the render scale of windowed renderers. The
+    /// thresholds can also be edited in the debug window.
+    pub fn set_auto_quality(&mut self, settings: Option<AutoQualitySettings>) {
+        if let Some(auto_quality) = self.auto_quality.take() {
+            for step in auto_quality.taken_steps().iter().rev() {
+                self.apply_quality_change(QualityChange::Restore(*step));
+            }
+        }
+        self.auto_quality = settings.map(|settings| {
+            let mut steps = vec![];
+            if self.ssao.is_some() {
+                steps.push(QualityStep::DisableSsao);
+            }
+            if self.window_target.is_some() {
+                steps.extend([QualityStep::ReduceRenderScale; 2]);
+            }
+            AutoQuality::new(settings, steps)
+        });
+        if let Some(egui_renderer) = &mut self.egui_renderer {
+            egui_renderer.set_auto_quality_settings(settings);
+        }
+    }
+
+    /// Quality steps the auto quality controller currently has taken, lowest quality last.
+    pub fn quality_steps(&self) -> &[QualityStep] {
+        self.auto_quality
+            .as_ref()
+            .map_or(&[], |auto_quality| auto_quality.taken_steps())
+    }
+
+    fn update_auto_quality(&mut self, gpu_ms: f32) {
+        // the thresholds edited in the debug window
+        if let Some(auto_quality) = &mut self.auto_quality
+            && let Some(settings) = self
+                .egui_renderer
+                .as_ref()
+                .and_then(|egui_renderer| egui_renderer.auto_quality_settings())
+        {
+            auto_quality.settings = settings;
+        }
+        let Some(change) = self
+            .auto_quality
+            .as_mut()
+            .and_then(|auto_quality| auto_quality.update(gpu_ms))
+        else {
+            return;
+        };
+        info!("GPU frame took {gpu_ms:.2}ms, auto quality: {change:?}");
+        self.apply_quality_change(change);
+    }
+
+    fn apply_quality_change(&mut self, change: QualityChange) {
+        match change {
+            QualityChange::Lower(QualityStep::DisableSsao) => {
+                if let Some(ssao) = &mut self.ssao {
+                    ssao.suspended = true;
+                }
+            }
+            QualityChange::Restore(QualityStep::DisableSsao) => {
+                if let Some(ssao) = &mut self.ssao {
+                    ssao.suspended = false;
+                }
+            }
+            QualityChange::Lower(QualityStep::ReduceRenderScale) => {
+                self.lowered_render_scales.push(self.render_scale);
+                self.set_render_scale(self.render_scale * RENDER_SCALE_STEP);
+            }
+            QualityChange::Restore(QualityStep::ReduceRenderScale) => {
+                if let Some(render_scale) = self.lowered_render_scales.pop() {
+                    self.set_render_scale(render_scale);
+                }
+            }
+        }
+        // The scene data of the frame about to be recorded was already updated.
+        self.scene_data.ambient_occlusion = self.ambient_occlusion();
+        self.invalidate();
+    }
+
+    /// Runs the SPIR-V fragment shader at `shader` over the whole frame at `insertion_point`,
+    /// see `PostProcess::add_pass` for what the shader gets bound.
+    pub fn add_fullscreen_pass(
+        &mut self,
+        shader: &str,
+        bindings: Vec<FullscreenBinding>,
+        insertion_point: InsertionPoint,
+    ) -> Result<FullscreenPassId> {
+        let id = self.post_process.add_pass(
+            &self.memory_allocator,
+            shader,
+            bindings,
+            insertion_point,
+            &mut self.main_deletion_queue,
+        )?;
+        self.invalidate();
+        Ok(id)
+    }
+
+    pub fn remove_fullscreen_pass(&mut self, id: FullscreenPassId) -> bool {
+        self.invalidate();
+        self.post_process
+            .remove_pass(id, &mut self.main_deletion_queue, self.frame_number)
+    }
+
+    /// Push constants of a fullscreen pass, e.g. the time for an animated effect.
+    pub fn set_fullscreen_pass_constants(
+        &mut self,
+        id: FullscreenPassId,
+        constants: &[u8],
+    ) -> Result<()> {
+        self.post_process.set_pass_constants(id, constants)?;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Draws the outline of the volume seen through `view_proj` for the current frame only.
+    pub fn debug_frustum(&mut self, view_proj: Matrix4<f32>, color: Vector4<f32>) {
+        self.debug_draw.frustum(view_proj, color);
+    }
+
+    /// Freezes the culling camera where the active camera is right now and draws its frustum
+    /// every frame, the active camera keeps moving freely to inspect it from outside.
+    pub fn set_culling_camera_frozen(&mut self, frozen: bool) {
+        self.frozen_view_proj = frozen.then_some(self.scene_data.view_proj);
+        self.invalidate();
+    }
+
+    pub fn is_culling_camera_frozen(&self) -> bool {
+        self.frozen_view_proj.is_some()
+    }
+
+    /// The view projection visibility is decided with, the frozen one while frozen.
+    pub fn culling_view_proj(&self) -> Matrix4<f32> {
+        self.frozen_view_proj.unwrap_or(self.scene_data.view_proj)
+    }
+
+    /// Draws a wireframe sphere for the current frame only.
+    pub fn debug_sphere(&mut self, center: Vector3<f32>, radius: f32, color: Vector4<f32>) {
+        self.debug_draw.sphere(center, radius, color);
+    }
+
+    /// Keeps `scene` loaded next to the existing ones, it is only drawn once activated.
+    pub fn add_scene(&mut self, scene: Scene) -> SceneId {
+        self.scenes.push(scene);
+        SceneId(self.scenes.len() - 1)
+    }
+
+    /// Switches rendering to another loaded scene, its nodes, camera and lights are used as
+    /// they were left.
+    pub fn set_active_scene(&mut self, scene_id: SceneId) -> Result<()> {
+        if scene_id.0 >= self.scenes.len() {
+            return Err(Error::msg(format!("Unknown scene {scene_id:?}")));
+        }
+        self.active_scene = scene_id;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Bias and forced level the level of detail of the scene meshes is picked with from the
+    /// next `update_scene` on.
+    pub fn set_lod_settings(&mut self, settings: LodSettings) {
+        if self.draw_ctx.lod_settings != settings {
+            self.draw_ctx.lod_settings = settings;
+            self.invalidate();
+        }
+    }
+
+    pub fn lod_settings(&self) -> LodSettings {
+        self.draw_ctx.lod_settings
+    }
+
+    /// Seconds on the clock skeletal animations are played back by, start clips at it with
+    /// `PrefabInstance::play_animation`.
+    pub fn animation_time(&self) -> f32 {
+        self.draw_ctx.animation_time
+    }
+
+    /// How many draws, binds and push constant updates the scene draws of the last frame
+    /// took, lower after `update_scene` sorted them by pipeline and material.
+    pub fn draw_stats(&self) -> DrawStats {
+        self.draw_stats
+    }
+
+    pub fn active_scene(&self) -> &Scene {
+        &self.scenes[self.active_scene.0]
+    }
+
+    pub fn active_scene_mut(&mut self) -> &mut Scene {
+        &mut self.scenes[self.active_scene.0]
+    }
+
+    pub fn scene_mut(&mut self, scene_id: SceneId) -> Option<&mut Scene> {
+        self.scenes.get_mut(scene_id.0)
+    }
+
+    /// Steps the simulation of the active scene by `dt`: moves the cameras and plays the tweens
+    /// and animations. Called by every frame unless `set_interpolation` hands it to the caller,
+    /// e.g. for updates at a fixed rate.
+    pub fn update(&mut self, dt: Duration) {
+        self.previous_step = Some(self.simulation_snapshot());
+        let scene = &mut self.scenes[self.active_scene.0];
+        scene.camera.update();
+        for (_, view) in &mut self.views {
+            if let ViewCamera::Custom(camera) = &mut view.camera {
+                camera.update();
+            }
+        }
+        scene.update_tweens(dt);
+        scene.update_animations(dt);
+        self.draw_ctx.animation_time += dt.as_secs_f32();
+    }
+
+    /// `Some(alpha)` stops the frames from stepping the simulation, they draw the scene `alpha`
+    /// of the way from before the last `update` to after it instead. `None` steps every frame
+    /// again.
+    pub fn set_interpolation(&mut self, alpha: Option<f32>) {
+        self.interpolation = alpha.map(|alpha| alpha.clamp(0.0, 1.0));
+    }
+
+    pub fn interpolation(&self) -> Option<f32> {
+        self.interpolation
+    }
+
+    fn simulation_snapshot(&self) -> SimulationSnapshot {
+        let scene = self.active_scene();
+        SimulationSnapshot {
+            scene: self.active_scene,
+            camera: scene.camera.pose(),
+            views: self
+                .views
+                .iter()
+                .filter_map(|(view_id, view)| match &view.camera {
+                    ViewCamera::Scene => None,
+                    ViewCamera::Custom(camera) => Some((*view_id, camera.pose())),
+                })
+                .collect(),
+            instances: scene
+                .instances
+                .iter()
+                .map(|instance| instance.transform.fixed_view::<3, 1>(0, 3).into_owned())
+                .collect(),
+            animation_time: self.draw_ctx.animation_time,
+        }
+    }
+
+    /// Collects the draws and views of the active scene `alpha` of the way from before the last
+    /// `update` to after it.
+    pub fn update_scene(&mut self, alpha: f32) {
+        self.draw_ctx.opaque_surfaces.clear();
+        self.draw_ctx.joint_matrices.clear();
+        self.draw_ctx.animating = false;
+        let scene = &self.scenes[self.active_scene.0];
+        // nothing to interpolate from after switching scenes
+        let previous = self
+            .previous_step
+            .as_ref()
+            .filter(|previous| previous.scene == self.active_scene && alpha < 1.0);
+        let interpolate = |previous: Option<&CameraPose>, camera: &Camera| match previous {
+            Some(previous) => previous.lerp(&camera.pose(), alpha),
+            None => camera.pose(),
+        };
+        let camera_pose = interpolate(previous.map(|previous| &previous.camera), &scene.camera);
+        self.draw_ctx.camera_position = camera_pose.position;
+        // skinned poses are sampled at the interpolated time, the clock goes back after drawing
+        let animation_time = self.draw_ctx.animation_time;
+        if let Some(previous) = previous {
+            self.draw_ctx.animation_time = previous.animation_time
+                + (animation_time - previous.animation_time) * alpha;
+        }
+        if let Some(suzanne) = scene.nodes.get("Suzanne") {
+            suzanne.draw(Matrix4::identity(), &mut self.draw_ctx);
+        }
+        if let Some(cube) = scene.nodes.get("Cube") {
+            for x in -3..3 {
+                let scale = nalgebra::Scale3::new(0.2, 0.2, 0.2).to_homogeneous();
+                let translation = Matrix4::new_translation(&Vector3::new(x as f32, 1.0, 0.0));
+                cube.draw(translation * scale, &mut self.draw_ctx);
+            }
+        }
+        for (idx, instance) in scene.instances.iter().enumerate() {
+            // moves the instance back from where the last step put it towards where it was
+            let offset = previous
+                .and_then(|previous| previous.instances.get(idx))
+                .map_or(Vector3::zeros(), |translation| {
+                    (translation - instance.transform.fixed_view::<3, 1>(0, 3)) * (1.0 - alpha)
+                });
+            instance.draw(Matrix4::new_translation(&offset), &mut self.draw_ctx);
+        }
+        self.draw_ctx.animation_time = animation_time;
+        self.draw_ctx.sort_for_submission();
+        self.scene_data.sunlight_color = scene.lights.sunlight_color;
+        self.scene_data.ambient_color = scene.lights.ambient_color;
+        self.scene_data.sunlight_direction = scene.lights.sunlight_direction;
+        self.scene_data.ambient_occlusion = self.ambient_occlusion();
+        let scene_camera = &scene.camera;
+        let view_target = |pose: CameraPose, camera: &Camera, scissor: Rect2D| {
+            let mut scene_data = self.scene_data.clone();
+            scene_data.view = pose.view_matrix();
+            scene_data.proj = camera.get_projection_matrix(
+                scissor.extent.width as f32 / scissor.extent.height as f32,
+            );
+            scene_data.view_proj = scene_data.proj * scene_data.view;
+            ViewTarget::new(scene_data, scissor)
+        };
+        let view_targets = if self.views.is_empty() {
+            vec![view_target(camera_pose, scene_camera, self.render_area)]
+        } else {
+            self.views
+                .iter()
+                .map(|(view_id, view)| {
+                    let (pose, camera) = match &view.camera {
+                        ViewCamera::Scene => (camera_pose, scene_camera),
+                        ViewCamera::Custom(camera) => {
+                            let previous = previous.and_then(|previous| {
+                                previous.views.iter().find(|(id, _)| id == view_id)
+                            });
+                            (interpolate(previous.map(|(_, pose)| pose), camera), camera)
+                        }
+                    };
+                    view_target(pose, camera, view.rect.to_pixels(&self.render_area))
+                })
+                .collect()
+        };
+        self.scene_data = view_targets[0].scene_data.clone();
+        self.view_targets = view_targets;
+
+        /*       for x in -3..3 {
+            let scale: Matrix4<f32> = Matrix4::default().scale(0.2);
+            let translation = Matrix4::new_translation(&Vector3::new(x as f32, 1.0, 0.0));
+            self.active_scene().nodes.get("Cube").unwrap().draw(translation * scale, &mut self.draw_ctx);
+        } */
+    }
+}
+
+#[cfg(feature = "sparse-textures")]
+impl Renderer {
+    /// Adds a sparse resident texture of `extent` that starts out without any memory. Pages
+    /// drawn with a material from `sparse_texture_material` are bound a few frames after they
+    /// first show up and filled with what `fill_page` returns for them, the tightly packed
+    /// texels of the page's extent.
+    pub fn add_sparse_texture(
+        &mut self,
+        extent: Extent2D,
+        format: Format,
+        fill_page: impl FnMut(SparsePage, Extent3D) -> Vec<u8> + 'static,
+    ) -> Result<SparseTextureId> {
+        let image = self.memory_allocator.create_sparse_image(
+            extent,
+            format,
+            ImageUsageFlags::SAMPLED,
+            &self.graphics_queue,
+        )?;
+        let prepared = self.prepare_sparse_texture(&image);
+        let (texture_index, feedback_target) = match prepared {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                self.memory_allocator.destroy_sparse_image(image);
+                return Err(err);
+            }
+        };
+        self.sparse_textures.push(SparseTexture {
+            image,
+            fill_page: Box::new(fill_page),
+            texture_index,
+            feedback_target,
+        });
+        Ok(SparseTextureId(self.sparse_textures.len() - 1))
+    }
+
+    /// Copy of `material` sampling the sparse texture `id` as its color texture. Surfaces drawn
+    /// with it request the pages of the texture they show.
+    pub fn sparse_texture_material(
+        &mut self,
+        id: SparseTextureId,
+        material: &GLTFMaterial,
+    ) -> Result<Arc<GLTFMaterial>> {
+        let texture = self
+            .sparse_textures
+            .get(id.0)
+            .ok_or_else(|| anyhow!("There is no sparse texture {id:?}"))?;
+        let mut bindless_material = self
+            .bindless
+            .material(material.data.material_index)
+            .ok_or_else(|| anyhow!("Material {} is not bindless", material.data.material_index))?;
+        bindless_material.textures.color = texture.texture_index;
+        let material_index = self.bindless.add_material(bindless_material)?;
+        // created with the first sparse texture
+        if let Some(sparse_feedback) = &mut self.sparse_feedback {
+            sparse_feedback.track_material(texture.feedback_target, material_index);
+        }
+        let mut data = material.data.clone();
+        data.material_index = material_index;
+        Ok(Arc::new(GLTFMaterial { data }))
+    }
+
+    /// Moves a new sparse image to SHADER_READ_ONLY_OPTIMAL and gives it a bindless slot and a
+    /// feedback target.
+    fn prepare_sparse_texture(&mut self, image: &SparseImage) -> Result<(u32, usize)> {
+        let submit = ImmediateSubmit::begin(&self.command_pool, self.graphics_queue.clone())?;
+        image_transition(
+            self.device.clone(),
+            submit.command_buffer(),
+            self.graphics_queue.queue_family_index,
+            image.image_details.image,
+            ImageLayout::UNDEFINED,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        submit.submit()?;
+        let sampler = self.samplers.with_filter(Filter::LINEAR, Filter::LINEAR);
+        let texture_index = self
+            .bindless
+            .texture_index(image.image_details.image_view, &sampler)?;
+        let sparse_feedback = match &mut self.sparse_feedback {
+            Some(sparse_feedback) => sparse_feedback,
+            None => self.sparse_feedback.insert(SparseFeedback::new(
+                self.device.clone(),
+                self.memory_allocator.clone(),
+                &[self.graphics_queue.clone()],
+                self.extent,
+                self.materials.scene_data_layout(),
+                self.bindless.layout,
+                &mut self.main_deletion_queue,
+            )?),
+        };
+        let feedback_target =
+            sparse_feedback.add_target(image.page_count(), &mut self.main_deletion_queue)?;
+        Ok((texture_index, feedback_target))
+    }
+
+    /// Binds and fills the pages the feedback pass flagged in the frame `frame_idx` was last
+    /// recorded for, must only be called once the frame's fence has been waited on.
+    fn update_sparse_residency(&mut self, frame_idx: usize) -> Result<()> {
+        let Some(sparse_feedback) = &self.sparse_feedback else {
+            return Ok(());
+        };
+        let mut filled = false;
+        for texture in &mut self.sparse_textures {
+            let feedback = sparse_feedback.read_back(texture.feedback_target, frame_idx);
+            let pages = texture.image.requested_pages(feedback);
+            if pages.is_empty() {
+                continue;
+            }
+            self.memory_allocator
+                .bind_sparse_pages(&mut texture.image, &pages, &self.graphics_queue)?;
+            let mut texels = vec![];
+            for page in pages {
+                let (_, extent) = texture.image.page_region(page)?;
+                texels.push((page, (texture.fill_page)(page, extent)));
+            }
+            self.memory_allocator
+                .fill_sparse_pages(&self.command_pool, &texture.image, &texels)?;
+            filled = true;
+        }
+        if filled {
+            self.invalidate();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Renderer {
+    /// Waits for the GPU and destroys everything the renderer created. The device, surface and
+    /// instance go last, when the fields holding them are dropped.
+    fn drop(&mut self) {
+        if let Err(err) = unsafe { self.device.device_wait_idle() } {
+            error!("Waiting for the device before shutdown failed: {err}");
+        }
+        if let Some(egui_renderer) = &mut self.egui_renderer {
+            egui_renderer.destroy();
+        }
+        self.upload_context.destroy();
+        self.async_compute.destroy();
+        self.assets.textures.drain().for_each(drop);
+        #[cfg(feature = "sparse-textures")]
+        for texture in self.sparse_textures.drain(..) {
+            self.memory_allocator.destroy_sparse_image(texture.image);
+        }
+        for frame_data in &mut self.frame_data {
+            frame_data.destroy(self.device.clone());
+        }
+        if let Some(mut skybox_deletion_queue) = self.skybox_deletion_queue.take() {
+            skybox_deletion_queue.flush();
+        }
+        self.main_deletion_queue.flush();
+        // the meshes give their slices back through the queues flushed above, the blocks
+        // holding every mesh go now
+        self.mesh_arena.destroy();
+        self.descriptor_allocator.destroy_pools(self.device.clone());
+        unsafe {
+            self.device.destroy_command_pool(*self.command_pool, None);
+            if let Some((debug_instance, debugger)) = &self.debugger {
+                debug_instance.destroy_debug_utils_messenger(*debugger, None);
+            }
+        }
+        if let Some(window_target) = &mut self.window_target {
+            window_target.destroy();
+        }
+        for secondary in &mut self.secondary_windows {
+            secondary.target.destroy();
+        }
+        debug!("Renderer resources have been destroyed");
+    }
+}
+
+fn clamp_render_scale(scale: f32) -> f32 {
+    if scale.is_finite() {
+        scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE)
+    } else {
+        1.0
+    }
+}
+
+/// `extent` scaled by `scale`, at least a pixel wide and high.
+fn scale_extent(extent: Extent2D, scale: f32) -> Extent2D {
+    let scale = |size: u32| ((size as f32 * scale).round() as u32).max(1);
+    Extent2D::default()
+        .width(scale(extent.width))
+        .height(scale(extent.height))
+}
+
+/// Maps the clip space of a projection onto `scissor` instead of the whole `extent` of the
+/// render targets, for passes that address them by normalized coordinates.
+fn viewport_projection(scissor: &Rect2D, extent: &Extent2D) -> Matrix4<f32> {
+    let (width, height) = (extent.width as f32, extent.height as f32);
+    let x = scissor.extent.width as f32 / width;
+    let y = scissor.extent.height as f32 / height;
+    let mut matrix = Matrix4::new_nonuniform_scaling(&Vector3::new(x, y, 1.0));
+    matrix[(0, 3)] = 2.0 * scissor.offset.x as f32 / width + x - 1.0;
+    matrix[(1, 3)] = 2.0 * scissor.offset.y as f32 / height + y - 1.0;
+    matrix
+}
+
+/// Whether `window` has no area to present to, like while it is minimized.
+pub fn is_zero_sized(window: &Window) -> bool {
+    let size = window.inner_size();
+    size.width == 0 || size.height == 0
+}
+
+impl RawVulkan for Renderer {
+    fn raw_instance(&self) -> &ash::Instance {
+        &self.instance.instance
+    }
+
+    fn raw_device(&self) -> &ash::Device {
+        &self.device.device
+    }
+
+    fn raw_physical_device(&self) -> PhysicalDevice {
+        self.device.physical_device
+    }
+
+    fn raw_graphics_queue(&self) -> (Queue, u32) {
+        (**self.graphics_queue, self.graphics_queue.queue_family_index)
+    }
+
+    fn raw_present_queue(&self) -> (Queue, u32) {
+        // headless renderers have no presentation queue
+        let queue = self.presentation_queue.as_ref().unwrap_or(&self.graphics_queue);
+        (***queue, queue.queue_family_index)
+    }
+
+    fn raw_allocator(&self) -> &vk_mem::Allocator {
+        &self.memory_allocator
+    }
+
+    fn raw_frame_command_buffer(&self) -> CommandBuffer {
+        self.frame_data[self.frame_idx].command_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Extent2D, Offset2D, Rect2D};
+    use nalgebra::{Vector4, Vector3};
+
+    use super::{scale_extent, viewport_projection, PackUnorm};
+
+    #[test]
+    fn scaled_extents_keep_at_least_a_pixel() {
+        let extent = Extent2D::default().width(1920).height(1080);
+        assert_eq!(scale_extent(extent, 0.5), Extent2D::default().width(960).height(540));
+        assert_eq!(scale_extent(extent, 1.5), Extent2D::default().width(2880).height(1620));
+        assert_eq!(
+            scale_extent(Extent2D::default().width(1).height(3), 0.25),
+            Extent2D::default().width(1).height(1)
+        );
+    }
+
+    #[test]
+    fn viewport_projection_maps_clip_space_onto_the_drawn_rect() {
+        let extent = Extent2D::default().width(100).height(100);
+        let corner = Rect2D::default().extent(Extent2D::default().width(50).height(25));
+        let matrix = viewport_projection(&corner, &extent);
+        let map = |x: f32, y: f32| matrix.transform_point(&Vector3::new(x, y, 0.5).into());
+        assert_eq!(map(-1.0, -1.0), Vector3::new(-1.0, -1.0, 0.5).into());
+        assert_eq!(map(1.0, 1.0), Vector3::new(0.0, -0.5, 0.5).into());
+        let right_half = Rect2D::default()
+            .offset(Offset2D::default().x(50))
+            .extent(Extent2D::default().width(50).height(100));
+        let matrix = viewport_projection(&right_half, &extent);
+        let map = |x: f32, y: f32| matrix.transform_point(&Vector3::new(x, y, 0.5).into());
+        assert_eq!(map(-1.0, -1.0), Vector3::new(0.0, -1.0, 0.5).into());
+        assert_eq!(map(1.0, 1.0), Vector3::new(1.0, 1.0, 0.5).into());
+    }
+
+    #[test]
+    fn pack_unorm4x8_puts_x_in_the_lowest_byte() {
+        assert_eq!(
+            Vector4::new(1.0, 0.0, 0.0, 0.0).pack_unorm4x8(),
+            0x0000_00FF
+        );
+        assert_eq!(
+            Vector4::new(0.0, 1.0, 0.0, 0.0).pack_unorm4x8(),
+            0x0000_FF00
+        );
+        assert_eq!(
+            Vector4::new(0.0, 0.0, 1.0, 0.0).pack_unorm4x8(),
+            0x00FF_0000
+        );
+        assert_eq!(
+            Vector4::new(0.0, 0.0, 0.0, 1.0).pack_unorm4x8(),
+            0xFF00_0000
+        );
+    }
+
+    #[test]
+    fn pack_unorm4x8_rounds_and_clamps() {
+        assert_eq!(
+            Vector4::new(1.0, 1.0, 1.0, 1.0).pack_unorm4x8(),
+            0xFFFF_FFFF
+        );
+        // the default flat normal texel
+        assert_eq!(
+            Vector4::new(0.5, 0.5, 1.0, 1.0).pack_unorm4x8(),
+            0xFFFF_8080
+        );
+        assert_eq!(
+            Vector4::new(-0.5, 2.0, 0.66, 0.0).pack_unorm4x8(),
+            0x00A8_FF00
+        );
     }
 }